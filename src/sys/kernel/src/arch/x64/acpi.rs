@@ -0,0 +1,146 @@
+// Minimal ACPI table parsing: the kernel only needs SRAT (the System
+// Resource Affinity Table), to learn which CPUs and memory ranges belong to
+// which NUMA proximity domain. Called during bootup, before physical memory
+// is initialized, so this does not allocate.
+//
+// See ACPI Specification, sections 5.2.5 (RSDP), 5.2.7/5.2.8 (RSDT/XSDT)
+// and 5.2.16 (SRAT).
+
+use crate::mm::{MemorySegment, PAGING_DIRECT_MAP_OFFSET};
+
+const SDT_HEADER_SIZE: usize = 36;
+const SRAT_HEADER_SIZE: usize = SDT_HEADER_SIZE + 12;
+
+// SRAT entry types.
+const SRAT_LOCAL_APIC: u8 = 0;
+const SRAT_MEMORY: u8 = 1;
+const SRAT_X2APIC: u8 = 2;
+
+const SRAT_FLAG_ENABLED: u32 = 1;
+
+fn read<T: Copy>(phys_addr: u64) -> T {
+    unsafe {
+        core::ptr::read_unaligned((phys_addr + PAGING_DIRECT_MAP_OFFSET) as usize as *const T)
+    }
+}
+
+fn checksum_ok(phys_addr: u64, len: usize) -> bool {
+    let mut sum = 0_u8;
+    for offset in 0..len {
+        sum = sum.wrapping_add(read::<u8>(phys_addr + offset as u64));
+    }
+    sum == 0
+}
+
+// Returns the length of the table at @phys_addr if it has @signature and
+// a valid checksum.
+fn validate_sdt(phys_addr: u64, signature: &[u8; 4]) -> Option<usize> {
+    if phys_addr == 0 || read::<[u8; 4]>(phys_addr) != *signature {
+        return None;
+    }
+    let len = read::<u32>(phys_addr + 4) as usize;
+    if len < SDT_HEADER_SIZE || !checksum_ok(phys_addr, len) {
+        log::warn!(
+            "ACPI: bad {} table at 0x{:x}.",
+            core::str::from_utf8(signature).unwrap_or("?"),
+            phys_addr
+        );
+        return None;
+    }
+    Some(len)
+}
+
+fn find_table(rsdp_paddr: u64, signature: &[u8; 4]) -> Option<u64> {
+    if rsdp_paddr == 0 || read::<[u8; 8]>(rsdp_paddr) != *b"RSD PTR " {
+        return None;
+    }
+    if !checksum_ok(rsdp_paddr, 20) {
+        log::warn!("ACPI: bad RSDP checksum.");
+        return None;
+    }
+
+    // ACPI 2.0+ has the XSDT, with 64-bit entries; ACPI 1.0 only has the RSDT.
+    let revision = read::<u8>(rsdp_paddr + 15);
+    let (root, entry_size) = if revision >= 2 && checksum_ok(rsdp_paddr, 36) {
+        (read::<u64>(rsdp_paddr + 24), 8)
+    } else {
+        (read::<u32>(rsdp_paddr + 16) as u64, 4)
+    };
+    let root_signature = if entry_size == 8 { b"XSDT" } else { b"RSDT" };
+    let root_len = validate_sdt(root, root_signature)?;
+
+    let mut entry = root + SDT_HEADER_SIZE as u64;
+    while entry + entry_size <= root + root_len as u64 {
+        let table = if entry_size == 8 {
+            read::<u64>(entry)
+        } else {
+            read::<u32>(entry) as u64
+        };
+        if read::<[u8; 4]>(table) == *signature {
+            return Some(table);
+        }
+        entry += entry_size;
+    }
+
+    None
+}
+
+// Calls @on_cpu(APIC ID, proximity domain) and @on_memory(range, proximity domain)
+// for each enabled SRAT entry. Returns false if there is no (valid) SRAT.
+pub fn parse_srat<C: FnMut(u32, u32), M: FnMut(MemorySegment, u32)>(
+    rsdp_paddr: u64,
+    mut on_cpu: C,
+    mut on_memory: M,
+) -> bool {
+    let Some(srat) = find_table(rsdp_paddr, b"SRAT") else {
+        return false;
+    };
+    let Some(len) = validate_sdt(srat, b"SRAT") else {
+        return false;
+    };
+
+    let end = srat + len as u64;
+    let mut entry = srat + SRAT_HEADER_SIZE as u64;
+    while entry + 2 <= end {
+        let entry_type = read::<u8>(entry);
+        let entry_len = read::<u8>(entry + 1) as u64;
+        if entry_len < 2 || entry + entry_len > end {
+            log::warn!("ACPI: bad SRAT entry at 0x{:x}.", entry);
+            return false;
+        }
+
+        match entry_type {
+            SRAT_LOCAL_APIC if entry_len >= 16 => {
+                if read::<u32>(entry + 4) & SRAT_FLAG_ENABLED != 0 {
+                    let domain_hi = read::<[u8; 3]>(entry + 9);
+                    let domain = (read::<u8>(entry + 2) as u32)
+                        | ((domain_hi[0] as u32) << 8)
+                        | ((domain_hi[1] as u32) << 16)
+                        | ((domain_hi[2] as u32) << 24);
+                    on_cpu(read::<u8>(entry + 3) as u32, domain);
+                }
+            }
+            SRAT_MEMORY if entry_len >= 40 => {
+                if read::<u32>(entry + 28) & SRAT_FLAG_ENABLED != 0 {
+                    let segment = MemorySegment {
+                        start: read::<u64>(entry + 8),
+                        size: read::<u64>(entry + 16),
+                    };
+                    if segment.size > 0 {
+                        on_memory(segment, read::<u32>(entry + 2));
+                    }
+                }
+            }
+            SRAT_X2APIC if entry_len >= 24 => {
+                if read::<u32>(entry + 12) & SRAT_FLAG_ENABLED != 0 {
+                    on_cpu(read::<u32>(entry + 8), read::<u32>(entry + 4));
+                }
+            }
+            _ => {}
+        }
+
+        entry += entry_len;
+    }
+
+    true
+}
@@ -542,6 +542,7 @@ pub extern "C" fn irq_handler_inner(rsp: u64, irq_num: u64) {
                 // If the timer fires when the CPU is running a userspace thread,
                 // it is preempted and ends up in super::syscall::thread_off_cpu_reason(),
                 // which then calls on_timer_irq().
+                crate::xray::sampler::sample_kernel();
                 crate::sched::on_timer_irq();
                 eoi();
             }
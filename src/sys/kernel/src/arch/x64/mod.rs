@@ -1,5 +1,6 @@
 pub(self) mod gdt;
 
+pub mod acpi;
 pub mod irq;
 pub mod paging;
 pub mod serial;
@@ -98,6 +99,25 @@ pub fn kernel_exit() -> ! {
     loop {} // The above did not work, so just loop
 }
 
+pub fn kernel_reboot() -> ! {
+    use x86_64::instructions::port::Port;
+
+    crate::raw_log!("\n\r\n\rvm_reboot: bye.\n\r");
+    unsafe {
+        // Pulse the CPU reset line via the keyboard controller,
+        // which both Qemu and cloud-hypervisor emulate.
+        let mut port = Port::new(0x64);
+        port.write(0xfe as u8);
+
+        // Then, try the PCI reset control register.
+        let mut port = Port::new(0xcf9);
+        port.write(0x06 as u8);
+    }
+
+    // The above did not work: at least stop the VM.
+    kernel_exit()
+}
+
 #[cfg(debug_assertions)]
 fn get_backtrace() -> [u64; 256] {
     let mut backtrace: [u64; 256] = [0; 256];
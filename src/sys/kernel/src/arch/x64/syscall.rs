@@ -271,7 +271,7 @@ impl ThreadControlBlock {
 
     #[inline(never)]
     pub fn exit(&self) -> ! {
-        self.owner().process_stats.stop_cpu_usage_kernel();
+        self.owner().stop_cpu_usage_kernel();
         self.owner().process_stats.start_cpu_usage_uspace();
         #[cfg(debug_assertions)]
         debug_assert!(super::is_kernel_rsp());
@@ -281,7 +281,7 @@ impl ThreadControlBlock {
 
     #[inline(never)]
     pub fn die(&self, tocr: u64, addr: u64) -> ! {
-        self.owner().process_stats.stop_cpu_usage_kernel();
+        self.owner().stop_cpu_usage_kernel();
         self.owner().process_stats.start_cpu_usage_uspace();
         debug_assert!(self.in_syscall.load(Ordering::Relaxed));
         kill_current_thread(tocr, addr)
@@ -334,7 +334,7 @@ impl ThreadControlBlock {
 
     #[inline(never)]
     pub fn pause(&self) {
-        self.owner().process_stats.stop_cpu_usage_kernel();
+        self.owner().stop_cpu_usage_kernel();
         self.owner().process_stats.start_cpu_usage_uspace(); // see tocr re: why
         debug_assert!(self.in_syscall.load(Ordering::Relaxed));
         self.owner().trace("pause", self.syscall_rsp, 0);
@@ -478,13 +478,18 @@ impl ThreadControlBlock {
     }
 
     fn thread_off_cpu_reason(&self, tocr: u64, addr: u64) -> ThreadOffCpuReason {
-        self.owner().process_stats.stop_cpu_usage_uspace();
+        self.owner().stop_cpu_usage_uspace();
 
         match tocr {
             TOCR_PAUSED => ThreadOffCpuReason::Paused,
             TOCR_PREEMPTED => {
                 // If the timer fires when the CPU is running a userspace thread,
                 // it is preempted and ends up here.
+                let thread = self.owner();
+                crate::xray::sampler::sample(
+                    thread.process_stats.pid().as_u64(),
+                    thread.tid().as_u64(),
+                );
                 crate::sched::on_timer_irq();
                 ThreadOffCpuReason::Preempted
             }
@@ -535,11 +540,11 @@ extern "C" fn syscall_handler_rust(
         .is_ok());
     let thread = tcb.owner();
 
-    thread.process_stats.stop_cpu_usage_uspace();
+    thread.stop_cpu_usage_uspace();
     thread.process_stats.start_cpu_usage_kernel();
     // This may block (call TCB::pause()).
     let result = do_syscall(thread, &mut args);
-    thread.process_stats.stop_cpu_usage_kernel();
+    thread.stop_cpu_usage_kernel();
     thread.process_stats.start_cpu_usage_uspace();
 
     tcb.validate_gs();
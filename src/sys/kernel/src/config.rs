@@ -8,6 +8,8 @@ pub const MAX_CPUS: uCpus = 16;
 pub const KERNEL_STACK_PAGES: u64 = 64;
 
 pub const TRACE_BUFFER_SIZE: usize = 128;
+pub const CPU_SAMPLE_BUFFER_SIZE: usize = 1024; // Per CPU; see xray/sampler.rs.
+pub const LOCK_STATS_SITES: usize = 512; // See xray/lock_stats.rs.
 
 static NUM_CPUS: AtomicUCpus = AtomicUCpus::new(0);
 
@@ -326,6 +326,7 @@ fn cpu_main(this_cpu: u64) -> ! {
 
         crate::mm::init_mm_bsp_stage2();
         crate::xray::stats::init();
+        crate::util::entropy::init();
         crate::uspace::init();
 
         // If we print the boot logo before init_clock(), KVM in the host misbehaves and
@@ -389,6 +390,6 @@ pub fn start_userspace_processes() {
     // crate::util::tracing::start();
     log::debug!("starting sys-io");
     process.start();
-    log::set_max_level(log::LevelFilter::Info);
+    crate::xray::logger::set_level(None, log::LevelFilter::Info).unwrap();
     let _ = alloc::sync::Arc::into_raw(process);
 }
@@ -5,6 +5,7 @@
 mod cache;
 pub mod kheap;
 pub mod mmio;
+pub mod numa;
 pub mod phys;
 mod slab;
 pub mod user;
@@ -390,7 +391,8 @@ pub fn init_mm_bsp_stage1(boot_info: &crate::init::KernelBootupInfo) -> u64 {
         assert!(initrd_seg.end() < KERNEL_PHYS_START as u64);
     }
 
-    phys::init(&available_memory[0..], &in_use[0..]);
+    let topology = numa::Topology::from_acpi(boot_info.pvh().rsdp_paddr);
+    phys::init(&available_memory[0..], &in_use[0..], topology);
     virt::init();
 
     // Do the INIT_STATUS dance so that we can initialize CPUs (allocates pages for per-cpu GS)
@@ -419,6 +421,7 @@ pub fn init_mm_bsp_stage2() {
     }
 
     INIT_STATUS.store(INIT_STATUS_CPU, Ordering::Release);
+    phys::enable_frame_caches();
 }
 
 // Returns the new stack.
@@ -446,11 +449,18 @@ fn allocate_kernel_stack() -> u64 {
     stack.end() - PAGE_SIZE_SMALL
 }
 
+// Returns true if allocating @would_allocate bytes for userspace would eat
+// into the pages reserved for the system; this triggers the OOM killer.
 pub fn oom_for_user(would_allocate: u64) -> bool {
     let available = phys::available_small_pages() << 12;
-    if available <= would_allocate {
+    let oom = if available <= would_allocate {
         true
     } else {
         (available - would_allocate) <= (SMALL_PAGES_RESERVED_FOR_SYSTEM << 12)
+    };
+
+    if oom && would_allocate > 0 {
+        crate::uspace::on_oom();
     }
+    oom
 }
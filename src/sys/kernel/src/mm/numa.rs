@@ -0,0 +1,131 @@
+// NUMA topology: which node each CPU and each physical memory range belongs
+// to, from ACPI SRAT (see arch::acpi). Nodes are numbered densely, in the order
+// their proximity domains first appear in SRAT. Without SRAT (or with a single
+// proximity domain), there is one node 0 with all CPUs and memory.
+//
+// The physical memory allocator (see phys.rs) uses this to prefer memory local
+// to the CPU (or to the node a thread asked for via SysMem::set_numa_node()).
+
+use super::MemorySegment;
+use crate::config::uCpus;
+use core::cell::RefCell;
+
+pub const MAX_NODES: usize = moto_sys::stats::NumaNodeStats::MAX_NODES;
+const MAX_RANGES: usize = 32;
+
+pub struct Topology {
+    num_nodes: u8,
+    domains: [u32; MAX_NODES], // The proximity domain of each node.
+    cpu_nodes: [u8; crate::config::MAX_CPUS as usize],
+    ranges: [(MemorySegment, u8); MAX_RANGES], // Sorted by start.
+    num_ranges: usize,
+}
+
+impl Topology {
+    const fn single_node() -> Self {
+        Self {
+            num_nodes: 1,
+            domains: [0; MAX_NODES],
+            cpu_nodes: [0; crate::config::MAX_CPUS as usize],
+            ranges: [(MemorySegment::empty_segment(), 0); MAX_RANGES],
+            num_ranges: 0,
+        }
+    }
+
+    pub fn from_acpi(rsdp_paddr: u64) -> Self {
+        let topology = RefCell::new(Self::single_node());
+        topology.borrow_mut().num_nodes = 0;
+        // Set if SRAT has more domains or ranges than we can track.
+        let overflow = RefCell::new(false);
+
+        let found = crate::arch::acpi::parse_srat(
+            rsdp_paddr,
+            |apic_id, domain| {
+                let mut topology = topology.borrow_mut();
+                let Some(node) = topology.node_for_domain(domain) else {
+                    *overflow.borrow_mut() = true;
+                    return;
+                };
+                // CPU numbers are APIC IDs (see arch::x64::GS::init()).
+                if let Some(cpu_node) = topology.cpu_nodes.get_mut(apic_id as usize) {
+                    *cpu_node = node;
+                }
+            },
+            |segment, domain| {
+                let mut topology = topology.borrow_mut();
+                let Some(node) = topology.node_for_domain(domain) else {
+                    *overflow.borrow_mut() = true;
+                    return;
+                };
+                if topology.num_ranges == MAX_RANGES {
+                    *overflow.borrow_mut() = true;
+                    return;
+                }
+                let idx = topology.num_ranges;
+                topology.ranges[idx] = (segment, node);
+                topology.num_ranges += 1;
+            },
+        );
+
+        let mut topology = topology.into_inner();
+        if !found || topology.num_nodes < 2 {
+            return Self::single_node();
+        }
+        if overflow.into_inner() {
+            log::warn!("NUMA: SRAT is too large; ignoring it.");
+            return Self::single_node();
+        }
+
+        let num_ranges = topology.num_ranges;
+        topology.ranges[..num_ranges].sort_unstable_by_key(|(segment, _)| segment.start);
+        log::info!(
+            "NUMA: {} nodes, {} memory ranges.",
+            topology.num_nodes,
+            num_ranges
+        );
+        topology
+    }
+
+    fn node_for_domain(&mut self, domain: u32) -> Option<u8> {
+        let num_nodes = self.num_nodes as usize;
+        if let Some(node) = self.domains[..num_nodes].iter().position(|d| *d == domain) {
+            return Some(node as u8);
+        }
+        if num_nodes == MAX_NODES {
+            return None;
+        }
+        self.domains[num_nodes] = domain;
+        self.num_nodes += 1;
+        Some(num_nodes as u8)
+    }
+
+    pub fn num_nodes(&self) -> u8 {
+        self.num_nodes
+    }
+
+    pub fn cpu_node(&self, cpu: uCpus) -> u8 {
+        self.cpu_nodes[cpu as usize]
+    }
+
+    // Memory not described in SRAT is in node 0.
+    pub fn addr_node(&self, addr: u64) -> u8 {
+        self.ranges[..self.num_ranges]
+            .iter()
+            .find(|(segment, _)| segment.contains(addr))
+            .map(|(_, node)| *node)
+            .unwrap_or(0)
+    }
+
+    // The first address above @addr that may be in a different node than @addr.
+    pub fn node_boundary(&self, addr: u64) -> u64 {
+        for (segment, _) in &self.ranges[..self.num_ranges] {
+            if segment.contains(addr) {
+                return segment.end();
+            }
+            if segment.start > addr {
+                return segment.start;
+            }
+        }
+        u64::MAX
+    }
+}
@@ -1,12 +1,21 @@
+use super::numa::Topology;
 use super::slab::*;
 use super::*;
 use alloc::vec;
+use core::cell::UnsafeCell;
 use core::marker::PhantomData;
 use core::sync::atomic::*;
 use moto_sys::ErrorCode;
 
-pub fn init(available: &[MemorySegment], in_use: &[MemorySegment]) {
-    PhysicalMemory::init(available, in_use);
+pub fn init(available: &[MemorySegment], in_use: &[MemorySegment], topology: Topology) {
+    PhysicalMemory::init(available, in_use, topology);
+}
+
+// Called once all CPUs are up (FrameCache uses crate::arch::current_cpu()).
+pub fn enable_frame_caches() {
+    PhysicalMemory::inst()
+        .frame_caches_enabled
+        .store(true, Ordering::Release);
 }
 
 // Physical frame.
@@ -86,6 +95,19 @@ pub fn mark_unused(seg: &MemorySegment) {
     PhysicalMemory::inst().small_pages.mark_unused(seg)
 }
 
+pub fn num_numa_nodes() -> u8 {
+    PhysicalMemory::inst().topology.num_nodes()
+}
+
+// Small pages allocated on the current CPU come from @node, if it has free
+// memory, rather than from the CPU's node. Called when a thread that asked
+// for @node (see SysMem::set_numa_node()) is about to run on the current CPU.
+pub fn set_numa_node_hint(node: Option<u8>) {
+    let inst = PhysicalMemory::inst();
+    let hint = &inst.numa_node_hints[crate::arch::current_cpu() as usize];
+    hint.store(node.unwrap_or(PhysicalMemory::NO_NODE), Ordering::Relaxed);
+}
+
 #[cfg(debug_assertions)]
 pub fn dump_serial() {
     PhysicalMemory::inst().dump_serial();
@@ -97,6 +119,7 @@ struct DesignatedSegment<S: PageSize> {
     segment: MemorySegment,
     used_bitmap: AtomicU64, // bit per page, so at most 64 pages.
     num_pages: u8,
+    node: u8, // The NUMA node (see numa.rs); segments never span nodes.
     _unused: PhantomData<S>,
 }
 
@@ -106,6 +129,7 @@ impl<S: PageSize> DesignatedSegment<S> {
             segment: segment.clone(),
             used_bitmap: AtomicU64::new(0),
             num_pages: (segment.size >> S::SIZE_LOG2) as u8,
+            node: 0,
             _unused: PhantomData {},
         };
 
@@ -200,6 +224,24 @@ impl<S: PageSize> DesignatedSegment<S> {
         }
     }
 
+    // Calls @f with the length of each run of free pages.
+    fn for_each_free_run<F: FnMut(u64)>(&self, mut f: F) {
+        let used = self.used_bitmap.load(Ordering::Relaxed);
+
+        let mut run = 0;
+        for idx in 0..self.num_pages {
+            if (used & (1u64 << idx)) == 0 {
+                run += 1;
+            } else if run > 0 {
+                f(run);
+                run = 0;
+            }
+        }
+        if run > 0 {
+            f(run);
+        }
+    }
+
     fn allocate_contiguous(&self, num: u64) -> Result<u64, ErrorCode> {
         assert!(num > 1);
         let prev = self.used_bitmap.load(Ordering::Relaxed);
@@ -327,6 +369,11 @@ struct MemoryArea<S: PageSize> {
 
     // A free-list consisting of a single frame. Note that the free_frame is still counted as used.
     free_frame: AtomicU64, // Zero means empty.
+
+    // Indices into segments, by NUMA node; empty if there is a single node.
+    node_segments: [Vec<u32>; numa::MAX_NODES],
+    // Pages allocated from other nodes because the preferred node had none free.
+    remote_fallbacks: [AtomicU64; numa::MAX_NODES],
 }
 
 impl<S: PageSize> MemoryArea<S> {
@@ -336,15 +383,19 @@ impl<S: PageSize> MemoryArea<S> {
             total_pages: 0,
             used_pages: AtomicU64::new(0),
             free_frame: AtomicU64::new(0),
+            node_segments: core::array::from_fn(|_| vec![]),
+            remote_fallbacks: core::array::from_fn(|_| AtomicU64::new(0)),
         }
     }
 
-    fn add_segment(&mut self, segment: &MemorySegment) {
+    fn add_segment(&mut self, segment: &MemorySegment, node: u8) {
         debug_assert_eq!(0, segment.start & (S::SIZE - 1));
         debug_assert_eq!(0, segment.size & (S::SIZE - 1));
         debug_assert!(self.segments.len() < self.segments.capacity());
 
-        self.segments.push(DesignatedSegment::new(segment));
+        let mut segment = DesignatedSegment::new(segment);
+        segment.node = node;
+        self.segments.push(segment);
 
         self.total_pages += segment.size >> S::SIZE_LOG2;
     }
@@ -371,6 +422,26 @@ impl<S: PageSize> MemoryArea<S> {
             .sort_unstable_by(|a, b| a.segment.start.cmp(&b.segment.start));
     }
 
+    // Called after sort(), as node_segments index segments.
+    fn index_nodes(&mut self, num_nodes: u8) {
+        if num_nodes < 2 {
+            return;
+        }
+
+        // As in PhysicalMemory::assign_pages_to_area(), reserve first so that
+        // the bootup heap does not have to reallocate.
+        let mut counts = [0_usize; numa::MAX_NODES];
+        for seg in &self.segments {
+            counts[seg.node as usize] += 1;
+        }
+        for (node, count) in counts.iter().enumerate() {
+            self.node_segments[node].reserve_exact(*count);
+        }
+        for (idx, seg) in self.segments.iter().enumerate() {
+            self.node_segments[seg.node as usize].push(idx as u32);
+        }
+    }
+
     #[cfg(debug_assertions)]
     fn dump_serial(&self, prefix: &str) {
         crate::raw_log!(
@@ -425,18 +496,48 @@ impl<S: PageSize> MemoryArea<S> {
         // Err(moto_rt::E_OUT_OF_MEMORY)
     }
 
-    fn do_allocate_frame(&self) -> Result<u64, ErrorCode> {
-        let start = self.free_frame.swap(0u64, Ordering::Relaxed);
-        if start != 0 {
-            // Found a cached frame.
-            self.used_pages.fetch_add(1, Ordering::Relaxed);
-            return Ok(start);
+    // Tries the segments of @node, starting at a random one.
+    fn allocate_from_node(&self, node: u8) -> Result<u64, ErrorCode> {
+        let indices = &self.node_segments[node as usize];
+        if indices.is_empty() {
+            return Err(moto_rt::E_OUT_OF_MEMORY);
+        }
+
+        let first = crate::util::prng(false) as usize % indices.len();
+        for idx in 0..indices.len() {
+            let segment = &self.segments[indices[(first + idx) % indices.len()] as usize];
+            if let Ok(frame) = segment.allocate_frame() {
+                self.used_pages.fetch_add(1, Ordering::Relaxed);
+                return Ok(frame);
+            }
+        }
+
+        Err(moto_rt::E_OUT_OF_MEMORY)
+    }
+
+    // @node is the preferred NUMA node, if there are several; frames from other
+    // nodes are allocated only when it has none free.
+    fn do_allocate_frame(&self, node: Option<u8>) -> Result<u64, ErrorCode> {
+        if node.is_none() {
+            let start = self.free_frame.swap(0u64, Ordering::Relaxed);
+            if start != 0 {
+                // Found a cached frame.
+                self.used_pages.fetch_add(1, Ordering::Relaxed);
+                return Ok(start);
+            }
         }
 
         if self.total_pages == self.used_pages.load(Ordering::Relaxed) {
             return Err(moto_rt::E_OUT_OF_MEMORY);
         }
 
+        if let Some(node) = node {
+            if let Ok(frame) = self.allocate_from_node(node) {
+                return Ok(frame);
+            }
+            self.remote_fallbacks[node as usize].fetch_add(1, Ordering::Relaxed);
+        }
+
         // Try allocating from a random segment several times.
         {
             const RANDOM_TRIES: u8 = 3;
@@ -465,8 +566,8 @@ impl<S: PageSize> MemoryArea<S> {
         Err(moto_rt::E_OUT_OF_MEMORY)
     }
 
-    fn allocate_frame(&self) -> Result<u64, ErrorCode> {
-        match self.do_allocate_frame() {
+    fn allocate_frame(&self, node: Option<u8>) -> Result<u64, ErrorCode> {
+        match self.do_allocate_frame(node) {
             Ok(f) => Ok(f),
             Err(err) => {
                 log::error!(
@@ -489,7 +590,7 @@ impl<S: PageSize> MemoryArea<S> {
             );
             Err(moto_rt::E_OUT_OF_MEMORY)
         } else if num_frames == 1 {
-            self.allocate_frame()
+            self.allocate_frame(None)
         } else if num_frames <= 64 {
             // Linear search is fine as this is used during bootup in VirtIO setup.
             for segment in &self.segments {
@@ -512,6 +613,58 @@ impl<S: PageSize> MemoryArea<S> {
         }
     }
 
+    // Fills @frames with free frames (for a FrameCache), in a single update
+    // of used_pages. Returns the number of frames allocated. If @node is set,
+    // all frames are from @node (the FrameCache's), so there may be none.
+    fn allocate_batch(&self, frames: &mut [u64], node: Option<u8>) -> usize {
+        if self.total_pages == self.used_pages.load(Ordering::Relaxed) {
+            return 0;
+        }
+
+        let indices = node.map(|node| self.node_segments[node as usize].as_slice());
+        let num_segments = match indices {
+            Some(indices) => indices.len(),
+            None => self.segments.len(),
+        };
+        if num_segments == 0 {
+            return 0;
+        }
+
+        let first = crate::util::prng(false) as usize % num_segments;
+        let mut count = 0;
+        for idx in 0..num_segments {
+            let idx = (first + idx) % num_segments;
+            let segment = match indices {
+                Some(indices) => &self.segments[indices[idx] as usize],
+                None => &self.segments[idx],
+            };
+            while count < frames.len() {
+                match segment.allocate_frame() {
+                    Ok(frame) => {
+                        frames[count] = frame;
+                        count += 1;
+                    }
+                    Err(_) => break,
+                }
+            }
+            if count == frames.len() {
+                break;
+            }
+        }
+
+        self.used_pages.fetch_add(count as u64, Ordering::Relaxed);
+        count
+    }
+
+    // Frees @frames (drained from a FrameCache), in a single update of used_pages.
+    fn deallocate_batch(&self, frames: &[u64]) {
+        self.used_pages
+            .fetch_sub(frames.len() as u64, Ordering::Relaxed);
+        for frame in frames {
+            self.release_to_segment(*frame);
+        }
+    }
+
     fn deallocate_frame(&self, addr: u64) {
         assert!(self.total_pages != 0);
         self.used_pages.fetch_sub(1, Ordering::Relaxed);
@@ -529,6 +682,10 @@ impl<S: PageSize> MemoryArea<S> {
             prev_cached
         };
 
+        self.release_to_segment(to_free);
+    }
+
+    fn release_to_segment(&self, to_free: u64) {
         let owner = self.segments.as_slice().binary_search_by(|seg| {
             use core::cmp::Ordering;
 
@@ -551,7 +708,72 @@ impl<S: PageSize> MemoryArea<S> {
     }
 }
 
+// A per-CPU cache ("magazine") of free small pages, so that most page
+// allocations and deallocations don't touch MemoryArea atomics shared by all
+// CPUs. Caches are refilled and drained FRAME_CACHE_BATCH pages at a time.
+//
+// Pages in caches are counted in MemoryArea::used_pages (so that it is
+// updated once per batch) and are subtracted from it in stats.
+const FRAME_CACHE_SIZE: usize = 64;
+const FRAME_CACHE_BATCH: usize = FRAME_CACHE_SIZE / 2;
+
+struct FrameCache {
+    // Normally taken by the CPU owning the cache; if an IRQ handler on that
+    // CPU finds it taken, it goes to MemoryArea directly. Other CPUs take
+    // it to steal pages when there are no free pages left in MemoryArea.
+    busy: AtomicBool,
+    len: AtomicUsize, // Updated only while busy.
+    frames: UnsafeCell<[u64; FRAME_CACHE_SIZE]>,
+
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+unsafe impl Sync for FrameCache {}
+
+impl FrameCache {
+    fn new() -> Self {
+        Self {
+            busy: AtomicBool::new(false),
+            len: AtomicUsize::new(0),
+            frames: UnsafeCell::new([0; FRAME_CACHE_SIZE]),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    // Calls @f with the frames and their number, unless the cache is busy.
+    fn with<R, F: FnOnce(&mut [u64; FRAME_CACHE_SIZE], &mut usize) -> R>(&self, f: F) -> Option<R> {
+        if self.busy.swap(true, Ordering::Acquire) {
+            return None;
+        }
+
+        let mut len = self.len.load(Ordering::Relaxed);
+        let result = f(unsafe { &mut *self.frames.get() }, &mut len);
+        self.len.store(len, Ordering::Relaxed);
+
+        self.busy.store(false, Ordering::Release);
+        Some(result)
+    }
+
+    fn pop(&self) -> Option<u64> {
+        self.with(|frames, len| {
+            if *len == 0 {
+                return None;
+            }
+            *len -= 1;
+            Some(frames[*len])
+        })
+        .flatten()
+    }
+}
+
 // Contains everything. Has a single instantiation.
+//
+// With several NUMA nodes, small pages are allocated from the node of the
+// current CPU or, if the thread running on it asked for one, from that node
+// (see set_numa_node_hint()); from other nodes only when that node has no free
+// pages. Frame caches then hold only pages of their CPU's node.
 struct PhysicalMemory {
     total_size: u64, // does not change once initialized
 
@@ -559,6 +781,12 @@ struct PhysicalMemory {
 
     small_pages: MemoryArea<PageSizeSmall>,
     mid_pages: DesignatedSegment<PageSizeMid>,
+
+    topology: Topology,
+    numa_node_hints: [AtomicU8; crate::config::MAX_CPUS as usize], // Per CPU.
+
+    frame_caches: [FrameCache; crate::config::MAX_CPUS as usize],
+    frame_caches_enabled: AtomicBool,
 }
 
 // A pointer to the one and only instance of struct PhysicalMemory.
@@ -573,6 +801,8 @@ impl PhysicalMemory {
         size: (Self::MID_PAGES << PAGE_SIZE_MID_LOG2) as u64,
     };
 
+    const NO_NODE: u8 = u8::MAX;
+
     fn inst() -> &'static Self {
         let addr =
             unsafe { core::ptr::read_volatile(core::ptr::addr_of!(PHYS_MEM) as *const usize) };
@@ -581,7 +811,133 @@ impl PhysicalMemory {
     }
 
     fn available_small_pages(&'static self) -> u64 {
-        self.small_pages.total_pages - self.small_pages.used_pages.load(Ordering::Relaxed)
+        self.small_pages.total_pages - self.small_pages_used()
+    }
+
+    fn small_pages_used(&self) -> u64 {
+        let cached: usize = self
+            .frame_caches
+            .iter()
+            .map(|cache| cache.len.load(Ordering::Relaxed))
+            .sum();
+        // Caches are not updated atomically with used_pages, hence "saturating".
+        self.small_pages
+            .used_pages
+            .load(Ordering::Relaxed)
+            .saturating_sub(cached as u64)
+    }
+
+    fn frame_cache(&self) -> Option<&FrameCache> {
+        if self.frame_caches_enabled.load(Ordering::Acquire) {
+            Some(&self.frame_caches[crate::arch::current_cpu() as usize])
+        } else {
+            None
+        }
+    }
+
+    // The node of the current CPU, or the node hinted by the running thread.
+    // None if there is a single node.
+    fn preferred_node(&self) -> Option<u8> {
+        if self.topology.num_nodes() < 2 {
+            return None;
+        }
+        let cpu = crate::arch::current_cpu();
+        match self.numa_node_hints[cpu as usize].load(Ordering::Relaxed) {
+            Self::NO_NODE => Some(self.topology.cpu_node(cpu)),
+            node => Some(node),
+        }
+    }
+
+    fn is_local(&self, addr: u64) -> bool {
+        self.topology.num_nodes() < 2
+            || self.topology.addr_node(addr) == self.topology.cpu_node(crate::arch::current_cpu())
+    }
+
+    fn allocate_small_page(&self) -> Result<u64, ErrorCode> {
+        let Some(cache) = self.frame_cache() else {
+            return self.small_pages.allocate_frame(None);
+        };
+
+        let small_pages = &self.small_pages;
+        let node = self.preferred_node();
+        if node.is_some() && node != Some(self.topology.cpu_node(crate::arch::current_cpu())) {
+            // The cache has only local pages.
+            return small_pages.allocate_frame(node);
+        }
+
+        let frame = cache.with(|frames, len| {
+            if *len > 0 {
+                cache.hits.fetch_add(1, Ordering::Relaxed);
+            } else {
+                cache.misses.fetch_add(1, Ordering::Relaxed);
+                *len = small_pages.allocate_batch(&mut frames[..FRAME_CACHE_BATCH], node);
+                if *len == 0 {
+                    return None;
+                }
+            }
+            *len -= 1;
+            Some(frames[*len])
+        });
+
+        match frame {
+            Some(Some(frame)) => Ok(frame),
+            Some(None) => {
+                // The node has no free pages left: try other nodes first.
+                if node.is_some() {
+                    if let Ok(frame) = small_pages.do_allocate_frame(node) {
+                        return Ok(frame);
+                    }
+                }
+                // MemoryArea is out of free pages, but other CPUs may have some.
+                for other in &self.frame_caches {
+                    if let Some(frame) = other.pop() {
+                        return Ok(frame);
+                    }
+                }
+                small_pages.allocate_frame(node) // Will likely fail and log OOM.
+            }
+            None => {
+                cache.misses.fetch_add(1, Ordering::Relaxed);
+                small_pages.allocate_frame(node)
+            }
+        }
+    }
+
+    fn deallocate_small_page(&self, addr: u64) {
+        if let Some(cache) = self.frame_cache().filter(|_| self.is_local(addr)) {
+            let small_pages = &self.small_pages;
+            let cached = cache.with(|frames, len| {
+                if *len == FRAME_CACHE_SIZE {
+                    // Drain the older half: the newer pages are more likely to be hot.
+                    small_pages.deallocate_batch(&frames[..FRAME_CACHE_BATCH]);
+                    frames.copy_within(FRAME_CACHE_BATCH.., 0);
+                    *len -= FRAME_CACHE_BATCH;
+                }
+                frames[*len] = addr;
+                *len += 1;
+            });
+            if cached.is_some() {
+                return;
+            }
+        }
+
+        self.small_pages.deallocate_frame(addr)
+    }
+
+    // Removes @addr from the frame cache it is in, if any.
+    fn take_from_frame_caches(&self, addr: u64) -> bool {
+        for cache in &self.frame_caches {
+            let found = cache.with(|frames, len| {
+                let pos = frames[..*len].iter().position(|frame| *frame == addr)?;
+                *len -= 1;
+                frames[pos] = frames[*len];
+                Some(())
+            });
+            if let Some(Some(())) = found {
+                return true;
+            }
+        }
+        false
     }
 
     fn allocate_frame(&'static self, kind: PageType) -> Result<SlabArc<Frame>, ErrorCode> {
@@ -601,7 +957,7 @@ impl PhysicalMemory {
 
     fn allocate_frameless(&'static self, kind: PageType) -> Result<u64, ErrorCode> {
         let result = match kind {
-            PageType::SmallPage => self.small_pages.allocate_frame(),
+            PageType::SmallPage => self.allocate_small_page(),
             PageType::MidPage => self.mid_pages.allocate_frame(),
             _ => panic!(),
         };
@@ -617,7 +973,12 @@ impl PhysicalMemory {
             return Ok(());
         }
         match kind {
-            PageType::SmallPage => self.small_pages.fixed_addr_reserve(phys_addr),
+            PageType::SmallPage => {
+                if self.take_from_frame_caches(phys_addr) {
+                    return Ok(()); // Cached pages are already counted as used.
+                }
+                self.small_pages.fixed_addr_reserve(phys_addr)
+            }
             // PageType::MidPage => self.mid_pages.fixed_addr_reserve(phys_addr),
             _ => panic!(),
         }
@@ -625,7 +986,7 @@ impl PhysicalMemory {
 
     fn deallocate_frameless(&'static self, phys_addr: u64, kind: PageType) {
         match kind {
-            PageType::SmallPage => self.small_pages.deallocate_frame(phys_addr),
+            PageType::SmallPage => self.deallocate_small_page(phys_addr),
             /*
             PageType::MidPage => {
                 if self.mid_pages.segment.contains(phys_addr) {
@@ -652,7 +1013,7 @@ impl PhysicalMemory {
 
     fn deallocate_frame(&self, frame: &Frame) {
         match frame.kind {
-            PageType::SmallPage => self.small_pages.deallocate_frame(frame.start),
+            PageType::SmallPage => self.deallocate_small_page(frame.start),
             // PageType::MidPage => self.mid_pages.deallocate_frame(frame.start),
             _ => panic!(),
         };
@@ -709,7 +1070,7 @@ impl PhysicalMemory {
         }
     }
 
-    fn init(available: &[MemorySegment], in_use: &[MemorySegment]) {
+    fn init(available: &[MemorySegment], in_use: &[MemorySegment], topology: Topology) {
         assert_eq!(0, unsafe {
             core::ptr::read_volatile(core::ptr::addr_of!(PHYS_MEM) as *const usize)
         });
@@ -737,6 +1098,10 @@ impl PhysicalMemory {
             slab: MMSlab::<Frame>::new(true),
             small_pages: MemoryArea::new(),
             mid_pages: DesignatedSegment::new(&Self::MID_PAGES_SEGMENT),
+            frame_caches: core::array::from_fn(|_| FrameCache::new()),
+            frame_caches_enabled: AtomicBool::new(false),
+            topology,
+            numa_node_hints: core::array::from_fn(|_| AtomicU8::new(Self::NO_NODE)),
         }));
 
         let ptr = self_ as *mut PhysicalMemory;
@@ -745,12 +1110,17 @@ impl PhysicalMemory {
             core::ptr::write_volatile(core::ptr::addr_of_mut!(PHYS_MEM) as *mut usize, ptr);
         }
 
-        PhysicalMemory::assign_pages_to_area(available, &mut self_.small_pages);
+        PhysicalMemory::assign_pages_to_area(available, &self_.topology, &mut self_.small_pages);
         self_.small_pages.sort();
+        self_.small_pages.index_nodes(self_.topology.num_nodes());
         self_.mark_used(in_use);
     }
 
-    fn assign_pages_to_area<S: PageSize>(available: &[MemorySegment], area: &mut MemoryArea<S>) {
+    fn assign_pages_to_area<S: PageSize>(
+        available: &[MemorySegment],
+        topology: &Topology,
+        area: &mut MemoryArea<S>,
+    ) {
         // This happens during bootup memory setup, so heap is just a bump allocator.
         // Because of that we don't want area.segments to reallocate, during area.add_segment().
         // So we first count the number of segments we will add, then add them.
@@ -786,6 +1156,18 @@ impl PhysicalMemory {
                         pages = 64;
                     }
 
+                    // Segments must not span NUMA nodes.
+                    let boundary = topology.node_boundary(start);
+                    if boundary < start + (pages << S::SIZE_LOG2) {
+                        pages = (boundary - start) >> S::SIZE_LOG2;
+                        if pages == 0 {
+                            // The boundary is not page-aligned: skip to it.
+                            segment.size = segment.end().saturating_sub(boundary);
+                            segment.start = boundary;
+                            continue;
+                        }
+                    }
+
                     let size = pages << S::SIZE_LOG2;
                     let mut seg = MemorySegment { start, size };
 
@@ -808,7 +1190,7 @@ impl PhysicalMemory {
                     if count {
                         segment_count += 1;
                     } else {
-                        area.add_segment(&seg);
+                        area.add_segment(&seg, topology.addr_node(seg.start));
                     }
 
                     segment.size -= seg.size;
@@ -866,7 +1248,7 @@ impl PhysStats {
             small_pages: inst.small_pages.total_pages,
             mid_pages: inst.mid_pages.num_pages as u64,
 
-            small_pages_used: inst.small_pages.used_pages.load(Ordering::Relaxed),
+            small_pages_used: inst.small_pages_used(),
             mid_pages_used: inst
                 .mid_pages
                 .used_bitmap
@@ -887,6 +1269,98 @@ impl PhysStats {
     }
 }
 
+pub fn fragmentation_stats() -> moto_sys::stats::MemFragmentationStats {
+    use moto_sys::stats::MemFragmentationStats;
+
+    let mut stats = MemFragmentationStats::default();
+    let mut add_run = |run: u64| {
+        let order = (run.ilog2() as usize).min(MemFragmentationStats::NUM_ORDERS - 1);
+        stats.free_runs[order] += 1;
+        stats.largest_free_run = stats.largest_free_run.max(run);
+        stats.free_pages += run;
+    };
+
+    let small_pages = &PhysicalMemory::inst().small_pages;
+    for seg in &small_pages.segments {
+        seg.for_each_free_run(&mut add_run);
+    }
+
+    // Cached free frames are marked used in their segments.
+    if small_pages.free_frame.load(Ordering::Relaxed) != 0 {
+        add_run(1);
+    }
+    for cache in &PhysicalMemory::inst().frame_caches {
+        for _ in 0..cache.len.load(Ordering::Relaxed) {
+            add_run(1);
+        }
+    }
+
+    stats
+}
+
+// Copies the stats of per-CPU frame caches into @dest; returns the number
+// of entries copied.
+pub fn frame_cache_stats(dest: &mut [moto_sys::stats::FrameCacheStats]) -> usize {
+    let caches = &PhysicalMemory::inst().frame_caches[..crate::arch::num_cpus() as usize];
+    for (cache, entry) in caches.iter().zip(dest.iter_mut()) {
+        entry.hits = cache.hits.load(Ordering::Relaxed);
+        entry.misses = cache.misses.load(Ordering::Relaxed);
+        entry.cached_pages = cache.len.load(Ordering::Relaxed) as u64;
+    }
+
+    caches.len().min(dest.len())
+}
+
+// Copies the stats of each NUMA node into @dest; returns the number of
+// entries copied.
+pub fn numa_stats(dest: &mut [moto_sys::stats::NumaNodeStats]) -> usize {
+    use moto_sys::stats::NumaNodeStats;
+
+    let inst = PhysicalMemory::inst();
+    let topology = &inst.topology;
+    let count = (topology.num_nodes() as usize).min(dest.len());
+    let dest = &mut dest[..count];
+    dest.fill(NumaNodeStats::default());
+
+    let mut add_free = |node: u8, pages: u64| {
+        if let Some(entry) = dest.get_mut(node as usize) {
+            entry.free_pages += pages;
+        }
+    };
+
+    for seg in &inst.small_pages.segments {
+        let used = seg.used_bitmap.load(Ordering::Relaxed).count_ones() as u64;
+        add_free(seg.node, (seg.num_pages as u64).saturating_sub(used));
+    }
+
+    // Cached free frames are marked used in their segments; frame caches
+    // have pages of their CPU's node only.
+    let free_frame = inst.small_pages.free_frame.load(Ordering::Relaxed);
+    if free_frame != 0 {
+        add_free(topology.addr_node(free_frame), 1);
+    }
+    for cpu in 0..crate::arch::num_cpus() {
+        let cached = inst.frame_caches[cpu as usize].len.load(Ordering::Relaxed);
+        add_free(topology.cpu_node(cpu), cached as u64);
+    }
+
+    for cpu in 0..crate::arch::num_cpus() {
+        if let Some(entry) = dest.get_mut(topology.cpu_node(cpu) as usize) {
+            entry.cpus |= 1_u64 << cpu;
+        }
+    }
+    for seg in &inst.small_pages.segments {
+        if let Some(entry) = dest.get_mut(seg.node as usize) {
+            entry.total_pages += seg.num_pages as u64;
+        }
+    }
+    for (node, entry) in dest.iter_mut().enumerate() {
+        entry.remote_fallbacks = inst.small_pages.remote_fallbacks[node].load(Ordering::Relaxed);
+    }
+
+    count
+}
+
 #[cfg(debug_assertions)]
 pub fn dump_stats() {
     log::debug!("phys mem stats:\n{:#?}", PhysStats::get());
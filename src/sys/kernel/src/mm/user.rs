@@ -1,5 +1,6 @@
 use core::sync::atomic::*;
 
+use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 
 use super::{align_up, virt::*, PAGE_SIZE_SMALL, PAGE_SIZE_SMALL_LOG2};
@@ -34,6 +35,10 @@ pub struct UserAddressSpace {
     max_memory: AtomicU64,
     total_usage: AtomicU64,
 
+    // Reserved (see alloc_user_reserved()) segments: start => size. Their
+    // pages are charged to total_usage as they get populated.
+    reserved: crate::util::SpinLock<BTreeMap<u64, u64>>,
+
     // User mem stats are tracked via @inner.
     // Kernel mem stats (kernel stacks) are tracked here.
     kernel_mem_stats: Arc<MemStats>,
@@ -77,6 +82,7 @@ impl UserAddressSpace {
                     .load(Ordering::Relaxed),
             ),
             total_usage: AtomicU64::new(0),
+            reserved: crate::util::SpinLock::new(BTreeMap::new()),
             kernel_mem_stats: Arc::new(MemStats::new_kernel()),
 
             kernel_stacks: super::cache::SegmentCache::new(),
@@ -144,12 +150,16 @@ impl UserAddressSpace {
             self.total_usage.fetch_sub(bytes, Ordering::Relaxed);
             Err(moto_rt::E_OUT_OF_MEMORY)
         } else {
+            self.user_mem_stats()
+                .add_committed(bytes >> PAGE_SIZE_SMALL_LOG2);
             Ok(())
         }
     }
 
     fn stats_user_sub(&self, bytes: u64) {
         self.total_usage.fetch_sub(bytes, Ordering::Relaxed);
+        self.user_mem_stats()
+            .sub_committed(bytes >> PAGE_SIZE_SMALL_LOG2);
     }
 
     fn stats_kernel_add(&self, num_pages: u64) -> Result<(), ErrorCode> {
@@ -432,6 +442,34 @@ impl UserAddressSpace {
             })
     }
 
+    /// Like alloc_user_lazy(), but only reserves address space: pages are charged
+    /// against max_memory as they get populated, so a fault may fail with OOM.
+    pub fn alloc_user_reserved(&self, num_pages: u64) -> Result<super::MemorySegment, ErrorCode> {
+        let segment = self.inner.vmem_allocate_pages(
+            VmemKind::User,
+            num_pages,
+            Some(
+                MappingOptions::READABLE
+                    | MappingOptions::WRITABLE
+                    | MappingOptions::USER_ACCESSIBLE
+                    | MappingOptions::LAZY,
+            ),
+        )?;
+
+        self.reserved
+            .lock(line!())
+            .insert(segment.start, segment.size);
+        Ok(segment)
+    }
+
+    fn is_reserved(&self, addr: u64) -> bool {
+        let reserved = self.reserved.lock(line!());
+        match reserved.range(..=addr).next_back() {
+            Some((start, size)) => addr < (*start + *size),
+            None => false,
+        }
+    }
+
     pub fn alloc_user_unmapped(&self, num_pages: u64) -> Result<super::MemorySegment, ErrorCode> {
         // Stats have to be increased, otherwise:
         // - process A gets unmapped, no stats change
@@ -463,6 +501,12 @@ impl UserAddressSpace {
     }
 
     pub fn unmap(&self, addr: u64) -> Result<(), ErrorCode> {
+        if self.reserved.lock(line!()).remove(&addr).is_some() {
+            let (_, charged_pages) = self.inner.normal_memory.free_charged(addr)?;
+            self.stats_user_sub(charged_pages << PAGE_SIZE_SMALL_LOG2);
+            return Ok(());
+        }
+
         self.inner.normal_memory.free(addr).map_or_else(
             |_| {
                 self.inner.custom_memory.free(addr).map(|sz| {
@@ -477,6 +521,38 @@ impl UserAddressSpace {
         )
     }
 
+    /// Populates the pages in [addr, addr + num_pages) of a lazy (or reserved)
+    /// mapping ahead of access. Returns the number of pages populated.
+    pub fn populate(&self, addr: u64, num_pages: u64) -> Result<u64, ErrorCode> {
+        if super::oom_for_user(num_pages << PAGE_SIZE_SMALL_LOG2) {
+            return Err(moto_rt::E_OUT_OF_MEMORY);
+        }
+
+        // Pages of reserved mappings are charged as they get populated: charge
+        // the whole range, and refund pages that were already populated.
+        let reserved = self.is_reserved(addr);
+        if reserved {
+            self.stats_user_add(num_pages << PAGE_SIZE_SMALL_LOG2)?;
+        }
+        let (populated, result) = self.inner.normal_memory.populate(addr, num_pages);
+        if reserved {
+            self.stats_user_sub((num_pages - populated) << PAGE_SIZE_SMALL_LOG2);
+        }
+
+        result.map(|_| populated)
+    }
+
+    /// Releases the populated pages in [addr, addr + num_pages) of a lazy
+    /// (or reserved) mapping. Returns the number of pages released.
+    pub fn release(&self, addr: u64, num_pages: u64) -> Result<u64, ErrorCode> {
+        let released = self.inner.normal_memory.release(addr, num_pages)?;
+        if self.is_reserved(addr) {
+            self.stats_user_sub(released << PAGE_SIZE_SMALL_LOG2);
+        }
+
+        Ok(released)
+    }
+
     pub fn mmio_map(&self, phys_addr: u64, num_pages: u64) -> Result<u64, ErrorCode> {
         assert_eq!(0, phys_addr & (PAGE_SIZE_SMALL - 1));
 
@@ -503,10 +579,21 @@ impl UserAddressSpace {
 
     pub fn fix_pagefault(&self, pf_addr: u64, error_code: u64) -> Result<(), ErrorCode> {
         if super::phys::available_small_pages() < super::SMALL_PAGES_RESERVED_FOR_SYSTEM {
-            Err(moto_rt::E_OUT_OF_MEMORY)
-        } else {
-            self.inner.fix_pagefault(pf_addr, error_code)
+            crate::uspace::on_oom();
+            return Err(moto_rt::E_OUT_OF_MEMORY);
+        }
+
+        if !self.is_reserved(pf_addr) {
+            return self.inner.fix_pagefault(pf_addr, error_code);
         }
+
+        self.stats_user_add(PAGE_SIZE_SMALL)?;
+        self.inner
+            .fix_pagefault(pf_addr, error_code)
+            .or_else(|err| {
+                self.stats_user_sub(PAGE_SIZE_SMALL);
+                Err(err)
+            })
     }
 
     pub fn copy_to_user(&self, bytes: &[u8], user_vaddr_start: u64) -> Result<(), ErrorCode> {
@@ -224,6 +224,12 @@ impl VmemRegion {
 
     #[allow(unused)]
     pub(super) fn free(&self, addr: u64) -> Result<u64, ErrorCode> {
+        self.free_charged(addr).map(|(sz, _)| sz)
+    }
+
+    /// Frees the segment at @addr. Returns its size and the number of its
+    /// pages that were counted in MemStats (fewer than size for LAZY segments).
+    pub(super) fn free_charged(&self, addr: u64) -> Result<(u64, u64), ErrorCode> {
         if !self.segment.contains(addr) {
             return Err(moto_rt::E_INVALID_ARGUMENT);
         }
@@ -231,16 +237,15 @@ impl VmemRegion {
         let mut segments = self.used_segments.lock(line!());
 
         if let Some(deleted) = segments.remove(addr) {
+            let charged_pages = deleted.charged_pages();
             let sz = VmemSegment::unmap(deleted); // Consumes deleted.
 
             self.bytes_used.fetch_sub(sz, Ordering::Relaxed);
-            {
-                unsafe { self.address_space.get() }
-                    .mem_stats
-                    .sub(sz >> PAGE_SIZE_SMALL_LOG2);
-            }
+            unsafe { self.address_space.get() }
+                .mem_stats
+                .sub(charged_pages);
 
-            Ok(sz)
+            Ok((sz, charged_pages))
         } else {
             Err(moto_rt::E_INVALID_ARGUMENT)
         }
@@ -250,14 +255,13 @@ impl VmemRegion {
         let mut segments = self.used_segments.lock(line!());
 
         while let Some(deleted) = segments.pop_first() {
+            let charged_pages = deleted.charged_pages();
             let sz = VmemSegment::unmap(deleted); // Consumes deleted.
 
             self.bytes_used.fetch_sub(sz, Ordering::Relaxed);
-            {
-                unsafe { self.address_space.get() }
-                    .mem_stats
-                    .sub(sz >> PAGE_SIZE_SMALL_LOG2);
-            }
+            unsafe { self.address_space.get() }
+                .mem_stats
+                .sub(charged_pages);
         }
 
         segments.clear();
@@ -327,9 +331,13 @@ impl VmemRegion {
         seg.allocate_pages()?;
         self.bytes_used.fetch_add(size, Ordering::Relaxed);
 
+        let charged_pages = seg.charged_pages();
         segments.insert(seg);
 
-        unsafe { self.address_space.get() }.mem_stats.add(num_pages);
+        // Pages of LAZY segments are counted as they get populated (see fix_pagefault()).
+        unsafe { self.address_space.get() }
+            .mem_stats
+            .add(charged_pages);
 
         Ok(MemorySegment { start, size })
     }
@@ -432,13 +440,55 @@ impl VmemRegion {
         self.bytes_used.fetch_add(size, Ordering::Relaxed);
 
         seg.allocate_pages()?;
+        let charged_pages = seg.charged_pages();
         segments.insert(seg);
 
-        unsafe { self.address_space.get() }.mem_stats.add(num_pages);
+        unsafe { self.address_space.get() }
+            .mem_stats
+            .add(charged_pages);
 
         Ok(memory_segment)
     }
 
+    /// Populates the pages in [start, start + num_pages), which must be within
+    /// a LAZY segment (see VmemSegment::populate()). Returns the number of
+    /// pages populated, which are counted in MemStats even on errors.
+    pub(super) fn populate(&self, start: u64, num_pages: u64) -> (u64, Result<(), ErrorCode>) {
+        let end = start + (num_pages << PAGE_SIZE_SMALL_LOG2);
+        let mut segments = self.used_segments.lock(line!());
+        let Some(seg) = segments.find_mut(start) else {
+            return (0, Err(moto_rt::E_INVALID_ARGUMENT));
+        };
+        if !seg.is_lazy() || end > seg.segment().end() {
+            return (0, Err(moto_rt::E_INVALID_ARGUMENT));
+        }
+
+        let mut populated = 0;
+        let result = seg.populate(start, end, &mut populated);
+        unsafe { self.address_space.get() }.mem_stats.add(populated);
+
+        (populated, result)
+    }
+
+    /// Releases the pages in [start, start + num_pages), which must be within
+    /// a LAZY segment (see VmemSegment::release()). Returns the number of
+    /// pages released.
+    pub(super) fn release(&self, start: u64, num_pages: u64) -> Result<u64, ErrorCode> {
+        let end = start + (num_pages << PAGE_SIZE_SMALL_LOG2);
+        let mut segments = self.used_segments.lock(line!());
+        let Some(seg) = segments.find_mut(start) else {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        };
+        if !seg.is_lazy() || end > seg.segment().end() {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        let released = seg.release(start, end);
+        unsafe { self.address_space.get() }.mem_stats.sub(released);
+
+        Ok(released)
+    }
+
     fn fix_pagefault(&self, pf_addr: u64, error_code: u64) -> Result<(), ErrorCode> {
         if !self.segment.contains(pf_addr) {
             return Err(moto_rt::E_INVALID_ARGUMENT);
@@ -447,7 +497,11 @@ impl VmemRegion {
         let mut segments = self.used_segments.lock(line!());
         if let Some(seg) = segments.find_mut(pf_addr) {
             debug_assert!(seg.segment().contains(pf_addr));
-            return seg.fix_pagefault(pf_addr, error_code);
+            seg.fix_pagefault(pf_addr, error_code)?;
+            unsafe { self.address_space.get() }
+                .mem_stats
+                .add_page_fault();
+            return Ok(());
         }
 
         Err(moto_rt::E_INVALID_ARGUMENT)
@@ -223,6 +223,7 @@ pub(super) struct VmemSegment {
     pages: RBTree<PageTreeAdapter>,
     owner: crate::util::UnsafeRef<super::virt::VmemRegion>,
     mapping_options: MappingOptions,
+    faulted_pages: u64, // Pages of a LAZY segment populated in fix_pagefault().
 }
 
 impl Drop for VmemSegment {
@@ -242,6 +243,17 @@ impl VmemSegment {
             owner: crate::util::UnsafeRef::from(owner),
             mapping_options,
             pages: RBTree::new(PageTreeAdapter::new()),
+            faulted_pages: 0,
+        }
+    }
+
+    /// The number of pages counted in MemStats: LAZY segments are counted
+    /// as their pages are populated, other segments are counted in full.
+    pub(super) fn charged_pages(&self) -> u64 {
+        if self.mapping_options.contains(MappingOptions::LAZY) {
+            self.faulted_pages
+        } else {
+            self.segment.size >> PAGE_SIZE_SMALL_LOG2
         }
     }
 
@@ -486,6 +498,12 @@ impl VmemSegment {
             return Err(moto_rt::E_INVALID_ARGUMENT);
         }
 
+        self.populate_page(pf_addr)
+    }
+
+    // Allocates and maps a frame for the page at @addr, which must not have one.
+    fn populate_page(&mut self, addr: u64) -> Result<(), ErrorCode> {
+        let page = self.find_page_mut(addr).unwrap();
         page.frame = super::phys::allocate_frame(PageType::SmallPage)?;
         let mut mapping_options = page.mapping_options;
         mapping_options.remove(MappingOptions::LAZY);
@@ -497,10 +515,73 @@ impl VmemSegment {
         self.address_space()
             .page_table
             .map_page(phys_addr, virt_addr, page_type, mapping_options);
+        self.faulted_pages += 1;
+
+        Ok(())
+    }
+
+    pub(super) fn is_lazy(&self) -> bool {
+        self.mapping_options.contains(MappingOptions::LAZY)
+    }
+
+    /// Populates the pages of a LAZY segment in [start, end) that have not been
+    /// populated yet (guard pages excepted), as if they were written to.
+    /// @populated is incremented for each page populated, even on errors.
+    pub(super) fn populate(
+        &mut self,
+        start: u64,
+        end: u64,
+        populated: &mut u64,
+    ) -> Result<(), ErrorCode> {
+        debug_assert!(self.is_lazy());
+        debug_assert!(self.segment.start <= start && end <= self.segment.end());
+
+        let mut addr = start;
+        while addr < end {
+            let page = self.find_page(addr).unwrap();
+            if page.frame.is_null()
+                && page
+                    .mapping_options
+                    .contains(MappingOptions::USER_ACCESSIBLE)
+            {
+                self.populate_page(addr)?;
+                *populated += 1;
+            }
+            addr += PAGE_SIZE_SMALL;
+        }
 
         Ok(())
     }
 
+    /// Unmaps and frees the populated private pages of a LAZY segment in
+    /// [start, end): they are populated (zeroed) again on next access.
+    /// Shared pages are kept. Returns the number of pages released.
+    pub(super) fn release(&mut self, start: u64, end: u64) -> u64 {
+        debug_assert!(self.is_lazy());
+        debug_assert!(self.segment.start <= start && end <= self.segment.end());
+
+        let mut released = 0;
+        let mut addr = start;
+        while addr < end {
+            let page = self.find_page_mut(addr).unwrap();
+            if !page.frame.is_null() && page.frame.refs() == 1 {
+                let frame = page.frame.take();
+                let virt_addr = page.start;
+                self.address_space().page_table.unmap_page(
+                    frame.get().unwrap().start(),
+                    virt_addr,
+                    PageType::SmallPage,
+                );
+                core::mem::drop(frame); // Frees the frame: must be after unmapping.
+                released += 1;
+            }
+            addr += PAGE_SIZE_SMALL;
+        }
+
+        self.faulted_pages -= released;
+        released
+    }
+
     pub(super) fn share_with(
         &self,
         other: &mut Self,
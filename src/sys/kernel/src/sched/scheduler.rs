@@ -31,6 +31,7 @@ use crate::arch::time::Instant;
 use crate::config::uCpus;
 use crate::util::StaticRef;
 use crate::util::{SpinLock, StaticPerCpu};
+use moto_sys::stats::CpuUsageDetail;
 use moto_sys::ErrorCode;
 
 use super::timers::Timers;
@@ -65,6 +66,7 @@ pub struct Job {
     pub arg: u64,
     pub prio: Priority,
     pub cpu: uCpus, // uCpus::MAX => not set.
+    posted_at: u64, // TSC; set by post(), zero if not posted.
 }
 
 unsafe impl Send for Job {}
@@ -78,6 +80,7 @@ impl Default for Job {
             arg: 0,
             prio: Priority::Idle,
             cpu: uCpus::MAX,
+            posted_at: 0,
         }
     }
 }
@@ -90,6 +93,7 @@ impl Job {
             arg: 0,
             prio: Priority::Normal,
             cpu,
+            posted_at: 0,
         }
     }
 
@@ -100,6 +104,7 @@ impl Job {
             arg,
             prio: Priority::Normal,
             cpu: uCpus::MAX,
+            posted_at: 0,
         }
     }
 
@@ -110,6 +115,7 @@ impl Job {
             arg: 0,
             prio: Priority::Normal,
             cpu: thread.get_cpu_affinity(),
+            posted_at: 0,
         }
     }
 
@@ -124,6 +130,7 @@ impl Job {
                 arg: 0,
                 prio: Priority::Normal,
                 cpu: crate::arch::current_cpu(),
+                posted_at: 0,
             }
         }
     }
@@ -131,6 +138,20 @@ impl Job {
     fn run(&self) {
         (self.job_fn)(&self.thread, self.arg);
     }
+
+    // Charges the time the job has spent in a ready queue (the scheduling latency)
+    // to the process of its thread, if any.
+    fn record_sched_latency(&self) {
+        if self.posted_at == 0 {
+            return;
+        }
+        if let Some(thread) = self.thread.upgrade() {
+            let now = Instant::now().as_u64();
+            thread
+                .process_stats
+                .add_sched_latency(now.saturating_sub(self.posted_at));
+        }
+    }
 }
 
 // LocalAgent manages cooperative execution of jobs on a CPU.
@@ -152,8 +173,21 @@ struct Scheduler {
     load_tick: Instant,
     load_prev: AtomicU64,
     load_curr: u64,
+    // The part of load_prev/load_curr spent in userspace.
+    load_prev_uspace: AtomicU64,
+    load_curr_uspace: u64,
+    // Userspace time on this CPU, in TSC: all of it, and as of the last update_load().
+    uspace_total: AtomicU64,
+    uspace_seen: u64,
 
     timer_irq_tick: AtomicBool,
+    // Set on every timer IRQ, including one-shot timer deadlines between ticks.
+    timer_irq_fired: AtomicBool,
+    next_tick: AtomicU64, // TSC.
+
+    // How late timer jobs run after their deadlines: a moving average, in TSC.
+    timer_lateness: AtomicU64,
+    timers_fired: AtomicU64,
 
     #[cfg(debug_assertions)]
     die_on_next_wake: AtomicBool,
@@ -178,7 +212,15 @@ impl Scheduler {
 
             load_prev: AtomicU64::new(0),
             load_curr: 0,
+            load_prev_uspace: AtomicU64::new(0),
+            load_curr_uspace: 0,
+            uspace_total: AtomicU64::new(0),
+            uspace_seen: 0,
             timer_irq_tick: AtomicBool::new(false),
+            timer_irq_fired: AtomicBool::new(false),
+            next_tick: AtomicU64::new(0),
+            timer_lateness: AtomicU64::new(0),
+            timers_fired: AtomicU64::new(0),
 
             #[cfg(debug_assertions)]
             die_on_next_wake: AtomicBool::new(false),
@@ -195,14 +237,35 @@ impl Scheduler {
             return;
         }
 
+        // Userspace time is only known in total, so it is spread over the
+        // running interval proportionally.
+        let uspace_total = self.uspace_total.load(Ordering::Relaxed);
+        let running = if was_running {
+            now.as_u64() - self.load_tick.as_u64()
+        } else {
+            0
+        };
+        let uspace = (uspace_total - self.uspace_seen).min(running);
+        self.uspace_seen = uspace_total;
+        let uspace_share = |interval: u64| -> u64 {
+            if running == 0 {
+                0
+            } else {
+                ((interval as u128) * (uspace as u128) / (running as u128)) as u64
+            }
+        };
+
         if (now.as_u64() - self.load_tick.as_u64()) > 24 * Self::LOAD_PERIOD {
             // The VM was preempted or sleeping.
             self.load_tick = now;
             self.load_prev.store(0, Ordering::Relaxed);
             self.load_curr = 0;
+            self.load_prev_uspace.store(0, Ordering::Relaxed);
+            self.load_curr_uspace = 0;
             return;
         }
         let mut load_prev = self.load_prev.load(Ordering::Relaxed);
+        let mut load_prev_uspace = self.load_prev_uspace.load(Ordering::Relaxed);
 
         let mut iter = 0_u64;
         loop {
@@ -216,28 +279,36 @@ impl Scheduler {
                 (self.load_tick.as_u64() + Self::LOAD_PERIOD) & !(Self::LOAD_PERIOD - 1);
             if period_end > now.as_u64() {
                 if was_running {
-                    self.load_curr += now.as_u64() - self.load_tick.as_u64();
+                    let interval = now.as_u64() - self.load_tick.as_u64();
+                    self.load_curr += interval;
+                    self.load_curr_uspace += uspace_share(interval);
                 }
                 break;
             }
 
-            let interval = self.load_curr
-                + if was_running {
-                    period_end - self.load_tick.as_u64()
-                } else {
-                    0
-                };
+            let running_part = if was_running {
+                period_end - self.load_tick.as_u64()
+            } else {
+                0
+            };
+            let interval = self.load_curr + running_part;
+            let interval_uspace = self.load_curr_uspace + uspace_share(running_part);
             self.load_tick = Instant::from_u64(period_end);
             self.load_curr = 0;
+            self.load_curr_uspace = 0;
 
             load_prev += interval;
+            load_prev_uspace += interval_uspace;
 
             if self.load_tick == now {
                 break;
             }
             load_prev >>= 1;
+            load_prev_uspace >>= 1;
         }
 
+        self.load_prev_uspace
+            .store(load_prev_uspace, Ordering::Relaxed);
         self.load_prev.store(load_prev, Ordering::Release);
         self.load_tick = now;
     }
@@ -266,6 +337,56 @@ impl Scheduler {
         }
     }
 
+    // The load split into (uspace, kernel, idle) fractions; see load_pct().
+    pub fn load_detailed(&self) -> CpuUsageDetail {
+        let total = self.load_pct();
+        let uspace = ((self.load_prev_uspace.load(Ordering::Relaxed) as f32)
+            / (Self::LOAD_PERIOD as f32))
+            .min(total);
+        CpuUsageDetail {
+            uspace,
+            kernel: total - uspace,
+            idle: 1.0 - total,
+        }
+    }
+
+    // Runs timer jobs that are due and programs the timer for the next one.
+    // Returns true if any ran.
+    fn run_due_timers(&self) -> bool {
+        let now = Instant::now();
+        let mut ran = false;
+        let mut timers_iter = 0_u64;
+        loop {
+            timers_iter += 1;
+            if timers_iter > 1_000_000 {
+                panic!("timers_iter looping.");
+            }
+            match self.timers.pop(now) {
+                Ok(timer) => {
+                    self.record_timer_lateness(timer.when());
+                    timer.job().run();
+                    ran = true;
+                }
+                Err(next) => {
+                    maybe_program_timer(next);
+                    return ran;
+                }
+            }
+        }
+    }
+
+    fn record_timer_lateness(&self, deadline: Instant) {
+        let late = Instant::now().as_u64().saturating_sub(deadline.as_u64());
+        let fired = self.timers_fired.fetch_add(1, Ordering::Relaxed);
+        let avg = if fired == 0 {
+            late
+        } else {
+            let prev = self.timer_lateness.load(Ordering::Relaxed);
+            prev - prev / 16 + late / 16
+        };
+        self.timer_lateness.store(avg, Ordering::Relaxed);
+    }
+
     fn wake(&self) {
         if self.cpu == crate::arch::current_cpu() {
             self.local_wake();
@@ -345,8 +466,17 @@ impl Scheduler {
 
             curr_iteration += 1;
 
+            // Sleeps and timeouts should not wait for their turn in the round robin below.
+            if self.timer_irq_fired.swap(false, Ordering::Relaxed) && self.run_due_timers() {
+                last_job_iter = curr_iteration;
+            }
+
             if self.timer_irq_tick.swap(false, Ordering::Relaxed) {
                 self.update_load(true);
+                if self.cpu == 0 {
+                    crate::uspace::check_watchdogs();
+                    crate::uspace::check_log_drops();
+                }
             }
 
             // Round robit between queues.
@@ -360,6 +490,7 @@ impl Scheduler {
                 // to a deadlock.
                 let maybe_job = self.normal_queue.lock(line!()).pop_front();
                 if let Some(job) = maybe_job {
+                    job.record_sched_latency();
                     job.run();
                     self.queue_length.fetch_sub(1, Ordering::Relaxed);
                     last_job_iter = curr_iteration;
@@ -370,32 +501,15 @@ impl Scheduler {
             if curr_iteration % 3 == 1 {
                 let maybe_job = { GLOBAL_READY_QUEUE_NORMAL.lock(102).pop_front() };
                 if let Some(job) = maybe_job {
+                    job.record_sched_latency();
                     job.run();
                     last_job_iter = curr_iteration;
                     continue;
                 }
             }
 
-            if curr_iteration % 3 == 2 {
-                let now = Instant::now();
-                let mut timers_iter = 0_u64;
-                loop {
-                    timers_iter += 1;
-                    if timers_iter > 1_000_000 {
-                        panic!("timers_iter looping.");
-                    }
-                    match self.timers.pop(now) {
-                        Ok(timer) => {
-                            timer.job().run();
-                            last_job_iter = curr_iteration;
-                            continue;
-                        }
-                        Err(next) => {
-                            maybe_program_timer(next);
-                            break;
-                        }
-                    }
-                }
+            if curr_iteration % 3 == 2 && self.run_due_timers() {
+                last_job_iter = curr_iteration;
             }
 
             // If HALT_POLLING_ITERS is very small (e.g. two), there are noticeable delays.
@@ -417,9 +531,13 @@ impl Scheduler {
                     crate::xray::tracing::trace("scheduler hlt", 0, 0, 0);
                     crate::xray::stats::system_stats_ref().start_cpu_usage_kernel();
                     self.idle.store(true, Ordering::Release);
+                    // Any IRQ, including the timer and wakeup IPIs, will wake the CPU.
                     interrupts::enable_and_hlt();
                     self.idle.store(false, Ordering::Release);
-                    crate::xray::stats::system_stats_ref().stop_cpu_usage_kernel();
+                    // The whole interval charged to SYSTEM_STATS is idle, so that
+                    // idle and busy time partition the CPU's time.
+                    let idle = crate::xray::stats::system_stats_ref().stop_cpu_usage_kernel();
+                    crate::xray::stats::system_stats_ref().add_cpu_idle(idle);
                     crate::xray::tracing::trace("scheduler hlt wake", 0, 0, 0);
                 }
             }
@@ -498,7 +616,8 @@ fn update_system_time() {
     crate::arch::time::populate_kernel_static_page(shared_page);
 }
 
-pub fn post(job: Job) {
+pub fn post(mut job: Job) {
+    job.posted_at = Instant::now().as_u64();
     if job.cpu == uCpus::MAX {
         {
             GLOBAL_READY_QUEUE_NORMAL.lock(line!()).push_back(job)
@@ -573,28 +692,81 @@ pub fn get_irq_wait_handle(
     Ok(USER_IRQ_WAITERS[idx as usize].clone())
 }
 
+// The time slice (the scheduler tick) in microseconds: how often the timer
+// fires to preempt userspace threads. See SysCpu::OP_TIME_SLICE.
+// CPU sampling (xray/sampler.rs) does not depend on it.
+static TIME_SLICE_MICROS: AtomicU64 = AtomicU64::new(20_000);
+
+pub fn time_slice() -> core::time::Duration {
+    core::time::Duration::from_micros(TIME_SLICE_MICROS.load(Ordering::Relaxed))
+}
+
+// Takes effect on each CPU at its next tick.
+pub fn set_time_slice(slice: core::time::Duration) {
+    TIME_SLICE_MICROS.store(slice.as_micros() as u64, Ordering::Relaxed);
+}
+
 // Note: may be called from IRQ. Always called, exactly once, when a timer IRQ happens:
 // if the timer IRQ happens in the kernel context, on_timer_irq() is called
 // from the IRQ handler directly; if the timer IRQ happens in the user context,
 // the user thread is preempted and then on_timer_irq() is called when the
 // context switches to the kernel (in syscall.rs).
+//
+// The timer is one-shot (TSC deadline), and is programmed for the next tick or
+// the next timer deadline (see maybe_program_timer()), whichever comes first,
+// so sleeps and timeouts are not rounded up to ticks. Only the IRQs at tick
+// deadlines count as scheduler ticks.
 pub fn on_timer_irq() {
     let scheduler = PERCPU_SCHEDULERS.get_per_cpu();
-    scheduler.timer_irq_tick.store(true, Ordering::Relaxed);
+    scheduler.timer_irq_fired.store(true, Ordering::Relaxed);
+
+    let now = crate::arch::time::Instant::now();
+    let mut next_tick = Instant::from_u64(scheduler.next_tick.load(Ordering::Relaxed));
+    if now >= next_tick {
+        scheduler.timer_irq_tick.store(true, Ordering::Relaxed);
+        next_tick = now + time_slice();
+        scheduler
+            .next_tick
+            .store(next_tick.as_u64(), Ordering::Relaxed);
+    }
 
-    const SCHED_TICK_MILLIS: u64 = 20;
-    let when =
-        crate::arch::time::Instant::now() + core::time::Duration::from_millis(SCHED_TICK_MILLIS);
+    // CPU sampling has its own period, independent of the time slice.
+    let next_timer = match crate::xray::sampler::next_sample() {
+        Some(next_sample) if next_sample < next_tick => next_sample,
+        _ => next_tick,
+    };
 
     // Unlike the conditional vs curr_timer in maybe_program_timer() below, we set the timer
     // unconditionally here, because on_timer_irq() is called from the irq, that is the current timer
-    // has fired.
-    crate::arch::irq::set_timer(when);
-    *PERCPU_TIMERS.get_per_cpu() = when;
+    // has fired. If a timer deadline comes before the next tick, the timer will be re-programmed
+    // when the scheduler loop runs due timers.
+    crate::arch::irq::set_timer(next_timer);
+    *PERCPU_TIMERS.get_per_cpu() = next_timer;
 
     scheduler.local_wake();
 }
 
+// The effective timer resolution: how late, on average, timers fire after their
+// deadlines (over all CPUs, a moving average), including the time to run the jobs
+// of earlier timers. Zero if no timers have fired yet.
+pub fn timer_resolution() -> core::time::Duration {
+    let mut total = 0_u64;
+    let mut cpus = 0_u64;
+    let mut add = |_: uCpus, scheduler: &Scheduler| -> bool {
+        if scheduler.timers_fired.load(Ordering::Relaxed) > 0 {
+            total += scheduler.timer_lateness.load(Ordering::Relaxed);
+            cpus += 1;
+        }
+        false
+    };
+    PERCPU_SCHEDULERS.for_each_cpu(&mut add);
+
+    if cpus == 0 {
+        return core::time::Duration::ZERO;
+    }
+    Instant::from_u64(total / cpus).duration_since(Instant::from_u64(0))
+}
+
 fn maybe_program_timer(when: Instant) {
     if when.is_nan() {
         return;
@@ -621,6 +793,24 @@ pub fn get_usage(buf: &mut [f32]) {
     }
 }
 
+pub fn get_usage_detailed(buf: &mut [CpuUsageDetail]) {
+    let num_cpus = crate::arch::num_cpus() as usize;
+    assert_eq!(buf.len(), num_cpus);
+
+    for cpu in 0..num_cpus {
+        let scheduler = PERCPU_SCHEDULERS.get_for_cpu(cpu as uCpus);
+        buf[cpu] = scheduler.load_detailed();
+    }
+}
+
+// Called with the time the current thread has spent in userspace on this CPU.
+pub fn add_uspace_time(elapsed: u64) {
+    PERCPU_SCHEDULERS
+        .get_per_cpu()
+        .uspace_total
+        .fetch_add(elapsed, Ordering::Relaxed);
+}
+
 #[cfg(debug_assertions)]
 pub fn print_stack_trace_and_die(cpu: uCpus) {
     PERCPU_SCHEDULERS.get_for_cpu(cpu).die()
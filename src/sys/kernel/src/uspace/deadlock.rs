@@ -0,0 +1,122 @@
+// Deadlock detection for SysCpu::wait(); see SysRay::OP_DEADLOCK_DETECTION.
+//
+// A thread blocked in a wait without a timeout is woken by one of the objects
+// it waits on: an IPC object by its peer process, a thread or a process object
+// by that thread's owner or that process. Starting with the thread about to
+// block, we collect the processes that could wake it, then the processes that
+// could wake any of their threads, and so on. If every thread of every process
+// collected is blocked this way, nothing will ever wake any of them, and the
+// wait fails with E_DEADLOCK instead of blocking.
+//
+// Anything we can't account for ends the search with "no deadlock": a thread
+// (including the waiter itself) that is running, waits with a timeout, has
+// pending wakes (these end its wait right away), or waits on no
+// objects at all (it can be woken directly, by handle); an object of another
+// kind (e.g. an IRQ); a process that is not running. Wakes by thread handle
+// (SysCpu::wake_thread()) through IPC objects the thread does not wait on are
+// not accounted for, so the detection may report a deadlock that isn't; this
+// is why it is off by default and meant for debugging.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::collections::BTreeSet;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::process::{Process, ProcessId, ProcessStatus, Thread};
+use super::sysobject::{object_from_sysobject, SysObject};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// The search runs with a thread about to block, so it is bounded.
+const MAX_PROCESSES: usize = 64;
+
+pub(super) fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub(super) fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// Enabled globally, or for @thread's process (see SysRay::F_DEADLOCK_DETECTION_SET_PROCESS).
+pub(super) fn enabled_for(thread: &Thread) -> bool {
+    enabled() || thread.owner().deadlock_detection()
+}
+
+// The process that can wake a thread of @pid waiting on @obj, if known.
+fn waker_of(pid: ProcessId, obj: &Arc<SysObject>) -> Option<Arc<Process>> {
+    if let Some(peer) = super::shared::peer_owner(pid, obj) {
+        return Some(peer);
+    }
+    // A thread or a process object is woken when the thread or the process exits.
+    if object_from_sysobject::<Thread>(obj).is_some()
+        || object_from_sysobject::<Process>(obj).is_some()
+    {
+        return obj.process_owner().upgrade();
+    }
+    None
+}
+
+struct Search {
+    visited: BTreeSet<ProcessId>,
+    pending: Vec<Arc<Process>>,
+}
+
+impl Search {
+    // Queues the wakers of a thread of @pid waiting on @objects.
+    // Returns false if any of them is unknown.
+    fn add_wakers(&mut self, pid: ProcessId, objects: &[Arc<SysObject>]) -> bool {
+        for obj in objects {
+            let Some(waker) = waker_of(pid, obj) else {
+                return false;
+            };
+            if self.visited.insert(waker.pid()) {
+                self.pending.push(waker);
+            }
+        }
+        true
+    }
+}
+
+// Called with @curr's wait objects in place, just before it blocks without a timeout.
+pub(super) fn would_deadlock(curr: &Thread) -> bool {
+    let mut search = Search {
+        visited: BTreeSet::new(),
+        pending: Vec::new(),
+    };
+
+    if curr.has_pending_wakes() {
+        return false; // The wait won't block.
+    }
+
+    let owner = curr.owner();
+    let objects = curr.wait_object_list();
+    if objects.is_empty() || !search.add_wakers(owner.pid(), &objects) {
+        return false;
+    }
+
+    while let Some(process) = search.pending.pop() {
+        if search.visited.len() > MAX_PROCESSES {
+            return false;
+        }
+        if process.status() != ProcessStatus::Running {
+            return false;
+        }
+
+        for thread in process.threads() {
+            if process.pid() == owner.pid() && thread.tid() == curr.tid() {
+                continue; // Accounted for above.
+            }
+            let Some(objects) = thread.blocked_on() else {
+                return false;
+            };
+            if !search.add_wakers(process.pid(), &objects) {
+                return false;
+            }
+        }
+    }
+
+    log::info!("{}: deadlock detected in SysCpu::wait()", curr.debug_name());
+    true
+}
@@ -0,0 +1,175 @@
+// Rate limiting of SysRay::OP_LOG (see SysRay::LOG_RATE_DEFAULT), so that
+// a process logging in a tight loop does not flood the kernel log.
+//
+// Each process has a token bucket that holds up to `burst` messages and
+// refills at `rate` messages per second. Messages logged while the bucket
+// is empty are dropped and counted; the count is logged, as one line, once
+// the bucket has refilled: just before the next message that gets through,
+// or, if the process logs nothing more, on a CPU 0 scheduler tick (see check()).
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use moto_sys::{ErrorCode, SysRay};
+
+use super::process::{Process, Thread};
+use crate::arch::time::Instant;
+use crate::util::SpinLock;
+
+static RATE: AtomicU64 = AtomicU64::new(SysRay::LOG_RATE_DEFAULT);
+static BURST: AtomicU64 = AtomicU64::new(SysRay::LOG_BURST_DEFAULT);
+
+// A message costs this many tokens, so that refills don't need rounding.
+const TOKENS_PER_MESSAGE: u64 = 1_000_000_000;
+
+pub(super) fn set(rate: u64, burst: u64) -> Result<(), ErrorCode> {
+    if rate > 0 && (burst == 0 || burst > SysRay::LOG_BURST_MAX) {
+        return Err(moto_rt::E_INVALID_ARGUMENT);
+    }
+
+    BURST.store(burst, Ordering::Relaxed);
+    RATE.store(rate, Ordering::Relaxed);
+    Ok(())
+}
+
+pub(super) fn get() -> (u64, u64) {
+    (RATE.load(Ordering::Relaxed), BURST.load(Ordering::Relaxed))
+}
+
+pub struct LogBucket {
+    tokens: u64,
+    last_refill: Instant, // NaN: never used, so full.
+    suppressed: u64,
+}
+
+impl LogBucket {
+    pub const fn new() -> Self {
+        Self {
+            tokens: 0,
+            last_refill: Instant::nan(),
+            suppressed: 0,
+        }
+    }
+
+    pub fn suppressed(&self) -> u64 {
+        self.suppressed
+    }
+
+    // Returns false if the rate is unlimited.
+    fn refill(&mut self) -> bool {
+        let rate = RATE.load(Ordering::Relaxed);
+        if rate == 0 {
+            return false;
+        }
+
+        let capacity = BURST.load(Ordering::Relaxed).max(1) * TOKENS_PER_MESSAGE;
+        let now = Instant::now();
+        if self.last_refill.is_nan() {
+            self.tokens = capacity;
+        } else {
+            // A second refills `rate` messages.
+            let elapsed = now.duration_since(self.last_refill).as_nanos();
+            let refill = elapsed.saturating_mul(rate as u128);
+            self.tokens =
+                ((self.tokens as u128).saturating_add(refill)).min(capacity as u128) as u64;
+        }
+        self.last_refill = now;
+        // The burst may have been lowered since the last refill.
+        self.tokens = self.tokens.min(capacity);
+        true
+    }
+
+    /// Takes a message from the bucket. Returns the number of messages dropped
+    /// since the last one logged, or, if the message must be dropped, Err with
+    /// the number of messages dropped so far (including this one).
+    pub fn admit(&mut self) -> Result<u64, u64> {
+        if self.refill() {
+            if self.tokens < TOKENS_PER_MESSAGE {
+                self.suppressed += 1;
+                return Err(self.suppressed);
+            }
+            self.tokens -= TOKENS_PER_MESSAGE;
+        }
+
+        Ok(core::mem::take(&mut self.suppressed))
+    }
+
+    /// The number of messages dropped since the last one logged, if the next
+    /// message would get through; None if it would be dropped.
+    pub fn take_recovered(&mut self) -> Option<u64> {
+        if self.suppressed > 0 && self.refill() && self.tokens < TOKENS_PER_MESSAGE {
+            return None;
+        }
+
+        Some(core::mem::take(&mut self.suppressed))
+    }
+}
+
+struct Dropping {
+    process: Weak<Process>,
+    thread_name: String, // Of the thread whose message was dropped first.
+}
+
+// pid -> a process with dropped messages not yet reported.
+static DROPPING: SpinLock<BTreeMap<u64, Dropping>> = SpinLock::new(BTreeMap::new());
+// So that check() does not take the lock on every tick when there's nothing to check.
+static NUM_DROPPING: AtomicUsize = AtomicUsize::new(0);
+
+// Called when a message of @thread is dropped, and no earlier dropped message
+// is pending a report.
+pub(super) fn on_first_drop(thread: &Thread) {
+    let process = thread.owner();
+    let thread_name = thread.debug_name();
+
+    let mut dropping = DROPPING.lock(line!());
+    dropping.insert(
+        process.pid().as_u64(),
+        Dropping {
+            process: Arc::downgrade(&process),
+            thread_name,
+        },
+    );
+    NUM_DROPPING.store(dropping.len(), Ordering::Relaxed);
+}
+
+// Called from the scheduler on timer ticks: reports messages dropped by
+// processes that have not logged anything since their bucket refilled.
+pub fn check() {
+    if NUM_DROPPING.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+
+    let mut recovered: Vec<(String, u64)> = Vec::new();
+    // Dropped only after the lock is released.
+    let mut processes: Vec<Arc<Process>> = Vec::new();
+    {
+        let mut dropping = DROPPING.lock(line!());
+        dropping.retain(|_, entry| {
+            let Some(process) = entry.process.upgrade() else {
+                return false; // The process is gone.
+            };
+            let Some(suppressed) = process.take_recovered_log_drops() else {
+                processes.push(process);
+                return true;
+            };
+            processes.push(process);
+
+            // Zero if already reported with a message that got through.
+            if suppressed > 0 {
+                recovered.push((core::mem::take(&mut entry.thread_name), suppressed));
+            }
+            false
+        });
+        NUM_DROPPING.store(dropping.len(), Ordering::Relaxed);
+    }
+
+    for (thread_name, suppressed) in recovered {
+        crate::xray::logger::log_user_as(
+            thread_name.as_str(),
+            alloc::format!("{} messages suppressed (log rate limit)", suppressed).as_str(),
+        );
+    }
+}
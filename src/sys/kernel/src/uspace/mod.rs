@@ -18,7 +18,15 @@ mod sys_obj;
 mod sys_ray;
 mod sys_ray_dbg;
 
+mod deadlock;
+mod log_limit;
+mod oom;
+mod watchdog;
+
+pub use log_limit::check as check_log_drops;
+pub use oom::on_oom;
 pub use sysobject::process_wake_events;
+pub use watchdog::check as check_watchdogs;
 
 pub fn init() {
     shared::init();
@@ -0,0 +1,103 @@
+// The OOM killer: when an allocation fails because the system is out of
+// physical memory, kill the process that frees the most memory for the least
+// harm, as decided by its resident memory and OOM priority (see
+// SysRay::OP_OOM_PRIORITY), rather than let the whole system wedge.
+//
+// Running out of a per-process memory limit does not trigger the OOM killer:
+// the process just fails its allocation.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use alloc::sync::Weak;
+use moto_sys::SysRay;
+
+use super::process::{Process, ProcessId, Thread};
+use crate::arch::time::Instant;
+use crate::util::SpinLock;
+use crate::xray::stats::KProcessStats;
+
+// While the last victim is dying, its memory is not free yet: don't kill
+// another process for this long, unless the victim is gone.
+const KILL_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+static JOB_POSTED: AtomicBool = AtomicBool::new(false);
+
+// (pid, when killed).
+static LAST_VICTIM: SpinLock<Option<(u64, Instant)>> = SpinLock::new(None);
+
+/// Called when an allocation fails because the system is out of memory.
+/// The victim is selected and killed asynchronously, as the caller may
+/// hold locks.
+pub fn on_oom() {
+    if JOB_POSTED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+
+    crate::sched::post(crate::sched::Job::new_with_arg(job_fn_oom, 0));
+}
+
+fn oom_score(stats: &KProcessStats, process: &Process) -> u64 {
+    let priority = process.oom_priority();
+    if priority <= SysRay::OOM_PRIORITY_MIN {
+        return 0;
+    }
+
+    stats.resident_pages() * ((1000 + priority) as u64) / 1000
+}
+
+fn job_fn_oom(_: &Weak<Thread>, _: u64) {
+    JOB_POSTED.store(false, Ordering::Release);
+
+    // Memory may have been freed since the allocation failed.
+    if !crate::mm::oom_for_user(0) {
+        return;
+    }
+
+    {
+        let last_victim = LAST_VICTIM.lock(line!());
+        if let Some((pid, killed_at)) = *last_victim {
+            let alive = crate::xray::stats::stats_from_pid(pid).is_some();
+            if alive && Instant::now() < (killed_at + KILL_GRACE_PERIOD) {
+                return;
+            }
+        }
+    }
+
+    let mut victim: Option<(u64, u64)> = None; // (pid, score).
+    for stats in KProcessStats::snapshot(ProcessId::from_u64(moto_sys::stats::PID_SYSTEM)) {
+        if !stats.is_active() {
+            continue;
+        }
+        let Some(process) = stats.owner.upgrade() else {
+            continue;
+        };
+        if (process.capabilities() & moto_sys::caps::CAP_SYS) != 0 {
+            continue;
+        }
+
+        let score = oom_score(&stats, &process);
+        if score > victim.map_or(0, |(_, best)| best) {
+            victim = Some((stats.pid().as_u64(), score));
+        }
+    }
+
+    let Some((pid, score)) = victim else {
+        log::error!("OOM: no process to kill.");
+        return;
+    };
+
+    if let Some(stats) = crate::xray::stats::stats_from_pid(pid) {
+        log::error!(
+            "OOM: killing process {} '{}': {} resident pages, score {}; {} pages free.",
+            pid,
+            stats.debug_name(),
+            stats.resident_pages(),
+            score,
+            crate::mm::phys::available_small_pages()
+        );
+    }
+
+    *LAST_VICTIM.lock(line!()) = Some((pid, Instant::now()));
+    super::process::post_kill_by_pid(pid);
+}
@@ -13,6 +13,7 @@ use crate::mm::user::UserAddressSpace;
 use crate::util::LockGuard;
 use crate::util::SpinLock;
 use crate::xray::stats::KProcessStats;
+use crate::xray::stats::ThreadCpuStats;
 use alloc::borrow::ToOwned;
 use alloc::collections::BTreeMap;
 use alloc::string::String;
@@ -21,6 +22,7 @@ use alloc::sync::Weak;
 use alloc::vec::Vec;
 use core::assert_matches::assert_matches;
 use core::sync::atomic::*;
+use moto_sys::syscalls::SyscallFilter;
 use moto_sys::ErrorCode;
 use moto_sys::SysHandle;
 use moto_sys::UserThreadControlBlock;
@@ -142,6 +144,28 @@ pub struct Process {
     //     Thread::status is locked is dangerous, as the normal pattern
     //     is to order the two locks from the larger (process) to the smaller (thread).
     paused_debuggee: AtomicBool,
+
+    // One of SysRay::PANIC_ACTION_*. See SysRay::OP_PANIC_ACTION.
+    panic_action: AtomicU64,
+
+    // See SysObj::OP_SYSCALL_FILTER. syscall_filtered is false while the
+    // filter allows everything, so that unfiltered syscalls don't lock.
+    syscall_filter: SpinLock<SyscallFilter>,
+    syscall_filtered: AtomicBool,
+
+    // See SysRay::OP_OOM_PRIORITY.
+    oom_priority: AtomicI32,
+
+    // See SysRay::F_DEADLOCK_DETECTION_SET_PROCESS.
+    deadlock_detection: AtomicBool,
+
+    // See SysRay::OP_LOG_RATE_LIMIT.
+    log_bucket: SpinLock<super::log_limit::LogBucket>,
+
+    // See SysCpu::OP_PROCESS_AFFINITY. The initial affinity of new threads
+    // (uCpus::MAX => none), and whether child processes inherit it.
+    cpu_affinity: AtomicU32,
+    inherit_affinity: AtomicBool,
 }
 
 unsafe impl Send for Process {}
@@ -203,6 +227,14 @@ impl Process {
             ),
             debug_session: SpinLock::new(None),
             paused_debuggee: AtomicBool::new(false),
+            panic_action: AtomicU64::new(moto_sys::SysRay::PANIC_ACTION_EXIT),
+            syscall_filter: SpinLock::new(SyscallFilter::allow_all()),
+            syscall_filtered: AtomicBool::new(false),
+            oom_priority: AtomicI32::new(moto_sys::SysRay::OOM_PRIORITY_DEFAULT),
+            deadlock_detection: AtomicBool::new(false),
+            log_bucket: SpinLock::new(super::log_limit::LogBucket::new()),
+            cpu_affinity: AtomicU32::new(uCpus::MAX as u32),
+            inherit_affinity: AtomicBool::new(false),
         });
 
         // Safe because this is the "constructor" and no other references exit.
@@ -289,6 +321,23 @@ impl Process {
         )
         .map_err(|_| moto_rt::E_INTERNAL_ERROR)?;
 
+        crate::util::entropy::on_new_process();
+
+        // Inherited before the child can run.
+        if parent.syscall_filtered.load(Ordering::Acquire) {
+            process.set_syscall_filter(&parent.syscall_filter());
+        }
+        process.set_oom_priority(parent.oom_priority());
+        let (cpu, inherit) = parent.cpu_affinity();
+        if inherit {
+            // Only IO_MANAGER can be affined to CPU 0 (see SysCpu::affine_to_cpu()).
+            let cpu = match cpu {
+                Some(0) if (capabilities & moto_sys::caps::CAP_IO_MANAGER) == 0 => None,
+                cpu => cpu,
+            };
+            process.set_cpu_affinity(cpu, inherit);
+        }
+
         Ok(process)
     }
 
@@ -296,10 +345,97 @@ impl Process {
         self.stats.pid()
     }
 
+    pub fn stats(&self) -> &Arc<KProcessStats> {
+        &self.stats
+    }
+
     pub fn capabilities(&self) -> u64 {
         self.capabilities.load(Ordering::Relaxed)
     }
 
+    pub fn panic_action(&self) -> u64 {
+        self.panic_action.load(Ordering::Relaxed)
+    }
+
+    pub fn set_panic_action(&self, action: u64) {
+        self.panic_action.store(action, Ordering::Relaxed)
+    }
+
+    pub fn oom_priority(&self) -> i32 {
+        self.oom_priority.load(Ordering::Relaxed)
+    }
+
+    pub fn set_oom_priority(&self, priority: i32) {
+        self.oom_priority.store(priority, Ordering::Relaxed)
+    }
+
+    pub(super) fn deadlock_detection(&self) -> bool {
+        self.deadlock_detection.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn set_deadlock_detection(&self, enabled: bool) {
+        self.deadlock_detection.store(enabled, Ordering::Relaxed)
+    }
+
+    /// Returns the initial affinity of new threads, and whether children inherit it.
+    pub fn cpu_affinity(&self) -> (Option<uCpus>, bool) {
+        let cpu = self.cpu_affinity.load(Ordering::Relaxed) as uCpus;
+        (
+            if cpu == uCpus::MAX { None } else { Some(cpu) },
+            self.inherit_affinity.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Threads created from now on start affined to @cpu. So does the main
+    /// thread, if the process has not started yet.
+    pub fn set_cpu_affinity(&self, cpu: Option<uCpus>, inherit: bool) {
+        let status = self.status.lock(line!());
+        self.cpu_affinity
+            .store(cpu.unwrap_or(uCpus::MAX) as u32, Ordering::Relaxed);
+        self.inherit_affinity.store(inherit, Ordering::Relaxed);
+        if *status == ProcessStatus::Created {
+            if let Some(thread) = self.main_thread.as_ref() {
+                thread.set_cpu_affinity(cpu);
+            }
+        }
+    }
+
+    /// See log_limit::LogBucket::admit().
+    pub fn admit_log_message(&self) -> Result<u64, u64> {
+        self.log_bucket.lock(line!()).admit()
+    }
+
+    /// Messages dropped by the log rate limit, not yet reported.
+    pub fn log_messages_dropped(&self) -> u64 {
+        self.log_bucket.lock(line!()).suppressed()
+    }
+
+    /// See log_limit::LogBucket::take_recovered().
+    pub fn take_recovered_log_drops(&self) -> Option<u64> {
+        self.log_bucket.lock(line!()).take_recovered()
+    }
+
+    pub fn syscall_filter(&self) -> SyscallFilter {
+        *self.syscall_filter.lock(line!())
+    }
+
+    // Intersects the current filter with @filter.
+    pub fn set_syscall_filter(&self, filter: &SyscallFilter) {
+        let mut curr = self.syscall_filter.lock(line!());
+        *curr = curr.intersect(filter);
+        self.syscall_filtered
+            .store(*curr != SyscallFilter::allow_all(), Ordering::Release);
+    }
+
+    pub fn syscall_allowed(&self, syscall_nr: u8, operation: u8) -> bool {
+        if !self.syscall_filtered.load(Ordering::Acquire) {
+            return true;
+        }
+        self.syscall_filter
+            .lock(line!())
+            .is_allowed(syscall_nr, operation)
+    }
+
     pub(super) fn add_object(&self, object: Arc<SysObject>) -> SysHandle {
         let wait_object = WaitObject::new(object);
         let object_id = self
@@ -421,6 +557,11 @@ impl Process {
         Some(thread.get_thread_data())
     }
 
+    pub(super) fn threads(&self) -> Vec<Arc<Thread>> {
+        let _lock = self.status.lock(line!());
+        self.threads.values().cloned().collect()
+    }
+
     pub(super) fn self_object(&self) -> Option<Arc<SysObject>> {
         self.status.lock(line!()); // Must lock status because self.self_object is mutated on exit.
         compiler_fence(Ordering::AcqRel);
@@ -579,7 +720,7 @@ impl Process {
     }
 
     fn on_thread_exited(&self, tid: ThreadId, thread_status: ThreadStatus) {
-        self.stats.on_thread_exited();
+        self.stats.on_thread_exited(tid.as_u64());
         self.address_space
             .process_static_page_mut()
             .active_threads
@@ -733,6 +874,29 @@ impl Process {
             }
         }
     }
+
+    fn job_fn_cpu_limit_exceeded(_: &Weak<Thread>, pid: u64) {
+        let Some(target) = Self::from_pid(pid) else {
+            return;
+        };
+
+        match target.stats.cpu_limit_action() {
+            moto_sys::SysRay::CPU_LIMIT_ACTION_KILL => {
+                // Not set for CAP_SYS processes: see sys_cpu_limit_set().
+                if target.capabilities() & moto_sys::caps::CAP_SYS == 0 {
+                    target.die();
+                }
+            }
+            moto_sys::SysRay::CPU_LIMIT_ACTION_NOTIFY => {
+                // This does not break std programs: the runtime's own waits
+                // keep the interrupt pending (see SysCpu::wait_uninterruptible()).
+                if let Some(main_thread) = target.main_thread() {
+                    main_thread.post_interrupt();
+                }
+            }
+            _ => panic!("unexpected CPU limit action"),
+        }
+    }
 }
 
 // Allowed live thread status transitions:
@@ -816,6 +980,9 @@ pub struct Thread {
     wakes_queued: AtomicU64, // Counts wakes for self wakes.
     wakes_taken: AtomicU64,
 
+    // Set while the thread checks for a deadlock before blocking; see blocked_on().
+    blocking: AtomicBool,
+
     kernel_stack_segment: Option<mm::MemorySegment>,
 
     // Fields that change over the thread runtime.
@@ -835,8 +1002,16 @@ pub struct Thread {
     timed_out: AtomicBool,
     wakers: SpinLock<Vec<SysHandle>>,
 
+    // Set by post_interrupt(), cleared by take_interrupt().
+    interrupt_pending: AtomicBool,
+
     last_cpu: AtomicU32,
     affined_to: AtomicU32,
+    numa_node: AtomicU32, // See SysMem::set_numa_node(); u32::MAX if not set.
+
+    // Cumulative CPU time of this thread; also accounted in process_stats,
+    // which lists it by TID while the thread is alive.
+    cpu_stats: Arc<ThreadCpuStats>,
 
     pub process_stats: Arc<KProcessStats>,
 }
@@ -879,6 +1054,7 @@ impl Thread {
             join_handle: SysHandle::NONE,
             wakes_queued: AtomicU64::new(0),
             wakes_taken: AtomicU64::new(0),
+            blocking: AtomicBool::new(false),
             owner: Arc::downgrade(&owner),
             thread_entry_point,
             user_stack,
@@ -890,8 +1066,11 @@ impl Thread {
             sys_wait_objects: SpinLock::new(Vec::new()),
             timed_out: AtomicBool::new(false),
             wakers: SpinLock::new(alloc::vec![]),
+            interrupt_pending: AtomicBool::new(false),
             last_cpu: AtomicU32::new(u32::MAX),
-            affined_to: AtomicU32::new(uCpus::MAX as u32),
+            affined_to: AtomicU32::new(owner.cpu_affinity.load(Ordering::Relaxed)),
+            numa_node: AtomicU32::new(u32::MAX),
+            cpu_stats: Arc::new(ThreadCpuStats::default()),
             process_stats: owner.stats.clone(),
         });
         unsafe {
@@ -930,7 +1109,9 @@ impl Thread {
             .active_threads
             .fetch_add(1, Ordering::Relaxed);
 
-        owner.stats.on_thread_added();
+        owner
+            .stats
+            .on_thread_added(self_.tid.as_u64(), self_.cpu_stats.clone());
         self_
     }
 
@@ -1019,6 +1200,63 @@ impl Thread {
         *list = objects;
     }
 
+    // The objects this thread waits on, if it is in a SysCpu::wait() that
+    // only they can end: no timeout, no queued wakes. See deadlock.rs.
+    //
+    // A thread that is about to block (see set_blocking()) counts as blocked:
+    // of two threads of different processes blocking on each other at the
+    // same time, at least one then sees the other, and fails its wait.
+    pub(super) fn blocked_on(&self) -> Option<Vec<Arc<SysObject>>> {
+        {
+            let status = self.status.lock(line!());
+            match *status {
+                ThreadStatus::Live(LiveThreadStatus::InWait(
+                    moto_sys::syscalls::SYS_CPU,
+                    moto_sys::SysCpu::OP_WAIT,
+                )) => {}
+                _ if self.blocking.load(Ordering::SeqCst) => {}
+                _ => return None,
+            }
+            if self.timer_id.load(Ordering::Relaxed) != 0
+                || self.interrupt_pending()
+                || self.has_pending_wakes()
+            {
+                return None;
+            }
+        }
+
+        let objects = self.wait_object_list();
+        if objects.is_empty() {
+            None
+        } else {
+            Some(objects)
+        }
+    }
+
+    // True if a wait would return right away: the thread has been woken
+    // by a waker handle, or directly, since its last wait.
+    pub(super) fn has_pending_wakes(&self) -> bool {
+        !self.wakers.lock(line!()).is_empty()
+            || self.wakes_taken.load(Ordering::Relaxed) != self.wakes_queued.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn set_blocking(&self, blocking: bool) {
+        self.blocking.store(blocking, Ordering::SeqCst);
+    }
+
+    // Ends a wait that has not blocked, keeping its wakes for the next one.
+    pub(super) fn cancel_wait(&self) {
+        self.clear_wait_objects_on_wake();
+    }
+
+    pub(super) fn wait_object_list(&self) -> Vec<Arc<SysObject>> {
+        self.sys_wait_objects
+            .lock(line!())
+            .iter()
+            .map(|obj| obj.sys_object.clone())
+            .collect()
+    }
+
     pub(super) fn add_waker(&self, waker: SysHandle) {
         if waker != SysHandle::NONE {
             self.wakers.lock(line!()).push(waker);
@@ -1057,6 +1295,17 @@ impl Thread {
         }
     }
 
+    // Called right before the thread runs on the current CPU.
+    fn update_last_cpu(&self) {
+        self.process_stats.on_context_switch();
+        crate::mm::phys::set_numa_node_hint(self.numa_node());
+        let cpu = current_cpu();
+        let prev = self.last_cpu.swap(cpu as u32, Ordering::Relaxed);
+        if prev != u32::MAX && prev != (cpu as u32) {
+            self.process_stats.on_thread_migrated(prev as uCpus, cpu);
+        }
+    }
+
     pub fn trace(&self, event: &'static str, arg1: u64, arg2: u64) {
         crate::xray::tracing::trace(event, self.tid.as_u64(), arg1, arg2);
     }
@@ -1177,6 +1426,23 @@ impl Thread {
         self.affined_to.load(Ordering::Relaxed) as uCpus
     }
 
+    // Takes effect right away, as the thread is running (this is called from
+    // its syscall), and then every time it is scheduled (see update_last_cpu()).
+    pub fn set_numa_node(&self, node: Option<u8>) {
+        self.numa_node.store(
+            node.map(|n| n as u32).unwrap_or(u32::MAX),
+            Ordering::Relaxed,
+        );
+        crate::mm::phys::set_numa_node_hint(node);
+    }
+
+    pub fn numa_node(&self) -> Option<u8> {
+        match self.numa_node.load(Ordering::Relaxed) {
+            u32::MAX => None,
+            node => Some(node as u8),
+        }
+    }
+
     fn init_user_tcb(&mut self) {
         self.user_tcb_user_addr = (self.user_stack.stack_top()
             - (core::mem::size_of::<UserThreadControlBlock>() as u64))
@@ -1365,6 +1631,7 @@ impl Thread {
                 return;
             }
             core::mem::drop(cpu_usage_scope);
+            self.update_last_cpu();
             self.on_thread_descheduled(tcb.spawn_usermode_thread(arg));
         }
     }
@@ -1500,6 +1767,7 @@ impl Thread {
         // be checked when it is resumed in Self::wait().
         self.cancel_timeout();
         self.clear_wait_objects_on_wake();
+        self.update_last_cpu();
 
         self.on_thread_descheduled(
             /*
@@ -1531,6 +1799,7 @@ impl Thread {
 
         if resume {
             log::debug!("resume_in_userspace: {}", self.debug_name());
+            self.update_last_cpu();
             self.on_thread_descheduled(self.tcb.resume_preempted_thread());
         }
     }
@@ -1645,6 +1914,53 @@ impl Thread {
         thread_data
     }
 
+    // Marks the thread as interrupted and wakes it if it is in wait().
+    // Interrupts are "sticky": wait() will not block while the interrupt
+    // is pending, i.e. until the thread calls take_interrupt().
+    pub fn post_interrupt(&self) {
+        self.trace("thread::post_interrupt", 0, 0);
+        self.interrupt_pending.store(true, Ordering::Release);
+        // Bump wakes_queued so that a concurrent on_thread_paused() does not go to sleep.
+        self.wakes_queued.fetch_add(1, Ordering::Relaxed);
+        let mut status = self.status.lock(line!());
+        match *status {
+            ThreadStatus::Live(LiveThreadStatus::InWait(nr, op)) => {
+                *status = ThreadStatus::Live(LiveThreadStatus::Runnable(nr, op));
+                self.post_wake_locked(false);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn interrupt_pending(&self) -> bool {
+        self.interrupt_pending.load(Ordering::Acquire)
+    }
+
+    // Returns true if there was a pending interrupt.
+    pub fn take_interrupt(&self) -> bool {
+        self.interrupt_pending.swap(false, Ordering::AcqRel)
+    }
+
+    #[inline]
+    pub fn stop_cpu_usage_uspace(&self) {
+        let elapsed = self.process_stats.stop_cpu_usage_uspace();
+        self.cpu_stats.add_uspace(elapsed);
+        crate::sched::add_uspace_time(elapsed);
+        self.process_stats.charge_cpu_limit(elapsed);
+    }
+
+    #[inline]
+    pub fn stop_cpu_usage_kernel(&self) {
+        let elapsed = self.process_stats.stop_cpu_usage_kernel();
+        self.cpu_stats.add_kernel(elapsed);
+        self.process_stats.charge_cpu_limit(elapsed);
+    }
+
+    // Returns (uspace, kernel) CPU time of this thread, in TSC.
+    pub fn cpu_usage(&self) -> (u64, u64) {
+        self.cpu_stats.get()
+    }
+
     pub fn wake_by_timeout(&self) {
         let mut status = self.status.lock(line!());
         match *status {
@@ -2047,3 +2363,11 @@ pub fn post_kill_by_pid(pid: u64) {
         pid,
     ));
 }
+
+// Called (once) when the process exceeds its CPU time limit: see SysRay::OP_CPU_LIMIT.
+pub fn post_cpu_limit_exceeded(pid: u64) {
+    crate::sched::post(crate::sched::Job::new_with_arg(
+        Process::job_fn_cpu_limit_exceeded,
+        pid,
+    ));
+}
@@ -124,6 +124,7 @@ fn process_wake_handles(
     next_arg: usize,
     wakers: Vec<SysHandle>,
     timed_out: bool,
+    interrupted: bool,
 ) -> SyscallResult {
     if wakers.len() < 6 {
         let mut data = [0_u64; 6];
@@ -136,7 +137,12 @@ fn process_wake_handles(
             }
         }
 
-        if timed_out {
+        if interrupted {
+            return SyscallResult {
+                result: moto_rt::E_INTERRUPTED as u64,
+                data,
+            };
+        } else if timed_out {
             return SyscallResult {
                 result: moto_rt::E_TIMED_OUT as u64,
                 data,
@@ -179,7 +185,9 @@ fn process_wake_handles(
 
     let mut result = ResultBuilder::ok();
     result.result |= SyscallResult::F_HANDLE_ARRAY;
-    if timed_out {
+    if interrupted {
+        result.result |= moto_rt::E_INTERRUPTED as u64;
+    } else if timed_out {
         result.result |= moto_rt::E_TIMED_OUT as u64;
     }
 
@@ -251,6 +259,27 @@ pub(super) fn sys_wait_impl(curr: &super::process::Thread, args: &SyscallArgs) -
         return result;
     }
 
+    if curr.interrupt_pending() {
+        // Don't block until the interrupt is taken.
+        let wakers = curr.take_wakers();
+        return process_wake_handles(curr, args, next_arg, wakers, false, true);
+    }
+
+    let deadlock_check = timeout == u64::MAX && super::deadlock::enabled_for(curr);
+    if deadlock_check {
+        curr.set_blocking(true);
+        if super::deadlock::would_deadlock(curr) {
+            curr.set_blocking(false);
+            curr.cancel_wait();
+            // A wake that came in after the check ends the wait as usual.
+            if !curr.has_pending_wakes() {
+                return ResultBuilder::result(moto_rt::E_DEADLOCK);
+            }
+            let wakers = curr.take_wakers();
+            return process_wake_handles(curr, args, next_arg, wakers, false, false);
+        }
+    }
+
     if timeout != u64::MAX {
         curr.new_timeout(crate::arch::time::Instant::from_u64(timeout));
     }
@@ -264,8 +293,12 @@ pub(super) fn sys_wait_impl(curr: &super::process::Thread, args: &SyscallArgs) -
     } else {
         curr.wait()
     };
+    if deadlock_check {
+        curr.set_blocking(false);
+    }
 
-    process_wake_handles(curr, args, next_arg, wakers, timed_out)
+    let interrupted = curr.interrupt_pending();
+    process_wake_handles(curr, args, next_arg, wakers, timed_out, interrupted)
 }
 
 pub(super) fn do_wake(
@@ -333,6 +366,71 @@ fn sys_wake_impl(waker: &super::process::Thread, args: &SyscallArgs) -> SyscallR
     }
 }
 
+fn sys_interrupt_impl(curr: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    if args.version > 0 {
+        return ResultBuilder::version_too_high();
+    }
+
+    match args.flags {
+        SysCpu::F_INTERRUPT_TAKE => {
+            if curr.take_interrupt() {
+                ResultBuilder::ok_1(1)
+            } else {
+                ResultBuilder::ok_1(0)
+            }
+        }
+        0 => {
+            // Only threads in the same process can be interrupted.
+            let target = SysHandle::from_u64(args.args[0]);
+            if let Some(thread) = super::sysobject::object_from_handle::<super::process::Thread>(
+                &curr.owner(),
+                target,
+            ) {
+                thread.post_interrupt();
+                ResultBuilder::ok()
+            } else {
+                ResultBuilder::bad_handle(target)
+            }
+        }
+        _ => ResultBuilder::invalid_argument(),
+    }
+}
+
+fn sys_time_slice_impl(curr: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    if args.version > 0 {
+        return ResultBuilder::version_too_high();
+    }
+
+    match args.flags {
+        0 => ResultBuilder::ok_1(crate::sched::time_slice().as_micros() as u64),
+        SysCpu::F_TIME_SLICE_SET => {
+            if (curr.owner().capabilities() & moto_sys::caps::CAP_SYS) == 0 {
+                return ResultBuilder::result(moto_rt::E_NOT_ALLOWED);
+            }
+
+            let slice = core::time::Duration::from_micros(args.args[0]);
+            if slice < SysCpu::MIN_TIME_SLICE || slice > SysCpu::MAX_TIME_SLICE {
+                return ResultBuilder::invalid_argument();
+            }
+
+            crate::sched::set_time_slice(slice);
+            ResultBuilder::ok()
+        }
+        _ => ResultBuilder::invalid_argument(),
+    }
+}
+
+fn sys_timer_resolution_impl(_curr: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    if args.version > 0 {
+        return ResultBuilder::version_too_high();
+    }
+    if args.flags != 0 {
+        return ResultBuilder::invalid_argument();
+    }
+
+    ResultBuilder::ok_1(crate::sched::timer_resolution().as_nanos() as u64)
+}
+
 fn sys_kill_impl(killer: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
     if args.version > 0 {
         return ResultBuilder::version_too_high();
@@ -381,6 +479,37 @@ fn sys_kill_impl(killer: &super::process::Thread, args: &SyscallArgs) -> Syscall
         }
     }
 
+    if args.flags == SysCpu::F_KILL_PID_COMPLETION {
+        let target_pid = args.args[0];
+        let target_stats = match crate::xray::stats::stats_from_pid(target_pid) {
+            Some(stats) => stats,
+            None => return ResultBuilder::result(moto_rt::E_INVALID_ARGUMENT),
+        };
+
+        let target = target_stats.owner.upgrade();
+        if let Some(target) = target.as_ref() {
+            if target.capabilities() & moto_sys::caps::CAP_SYS != 0 {
+                return ResultBuilder::result(moto_rt::E_NOT_ALLOWED);
+            }
+        }
+
+        // The process may already be dead, with its stats kept alive
+        // by somebody else; the completion will fire when they are dropped.
+        let completion = target_stats.drop_completion();
+        let handle = killer.owner().add_object(completion);
+        core::mem::drop(target_stats);
+
+        if let Some(target) = target {
+            log::debug!(
+                "process {} killed by {}",
+                target.debug_name(),
+                killer.owner().debug_name()
+            );
+            target.die();
+        }
+        return ResultBuilder::ok_1(handle.as_u64());
+    }
+
     if args.flags != 0 {
         log::info!("bad flags: 0x{:x}", args.flags);
         return ResultBuilder::invalid_argument();
@@ -446,7 +575,15 @@ fn sys_cpu_usage_impl(curr: &super::process::Thread, args: &mut SyscallArgs) ->
     if args.version > 0 {
         return ResultBuilder::version_too_high();
     }
-    if args.flags != 0 {
+    if args.flags == SysCpu::F_USAGE_THREAD {
+        // Note: the current syscall is not yet accounted for.
+        let (uspace, kernel) = curr.cpu_usage();
+        return ResultBuilder::ok_2(uspace, kernel);
+    }
+    if args.flags != 0
+        && args.flags != SysCpu::F_USAGE_IDLE
+        && args.flags != SysCpu::F_USAGE_DETAILED
+    {
         return ResultBuilder::invalid_argument();
     }
 
@@ -465,6 +602,51 @@ fn sys_cpu_usage_impl(curr: &super::process::Thread, args: &mut SyscallArgs) ->
         return ResultBuilder::invalid_argument();
     }
 
+    if args.flags == SysCpu::F_USAGE_IDLE {
+        let mut idle: Vec<u64> = Vec::with_capacity(crate::arch::num_cpus() as usize);
+        idle.resize(crate::arch::num_cpus() as usize, 0);
+        crate::xray::stats::system_stats_ref().per_cpu_idle(idle.as_mut());
+
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                idle.as_ptr() as *const u8,
+                idle.len() * core::mem::size_of::<u64>(),
+            )
+        };
+        if curr
+            .owner()
+            .address_space()
+            .copy_to_user(bytes, addr)
+            .is_err()
+        {
+            return ResultBuilder::invalid_argument();
+        }
+        return ResultBuilder::ok();
+    }
+
+    if args.flags == SysCpu::F_USAGE_DETAILED {
+        let mut usage: Vec<moto_sys::stats::CpuUsageDetail> =
+            Vec::with_capacity(crate::arch::num_cpus() as usize);
+        usage.resize(crate::arch::num_cpus() as usize, Default::default());
+        crate::sched::get_usage_detailed(usage.as_mut());
+
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                usage.as_ptr() as *const u8,
+                usage.len() * core::mem::size_of::<moto_sys::stats::CpuUsageDetail>(),
+            )
+        };
+        if curr
+            .owner()
+            .address_space()
+            .copy_to_user(bytes, addr)
+            .is_err()
+        {
+            return ResultBuilder::invalid_argument();
+        }
+        return ResultBuilder::ok();
+    }
+
     let mut usage: Vec<f32> = Vec::with_capacity(crate::arch::num_cpus() as usize);
     for _ in 0..crate::arch::num_cpus() {
         usage.push(0.0);
@@ -520,6 +702,57 @@ fn sys_affine_cpu(curr: &super::process::Thread, args: &mut SyscallArgs) -> Sysc
     ResultBuilder::ok()
 }
 
+fn sys_process_affinity(curr: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    if args.version > 0 {
+        return ResultBuilder::version_too_high();
+    }
+
+    let handle = SysHandle::from_u64(args.args[0]);
+    let process = if handle == SysHandle::SELF {
+        curr.owner()
+    } else {
+        match super::sysobject::object_from_handle::<super::process::Process>(&curr.owner(), handle)
+        {
+            Some(process) => process,
+            None => return ResultBuilder::bad_handle(handle),
+        }
+    };
+
+    if args.flags == 0 {
+        let (cpu, inherit) = process.cpu_affinity();
+        return ResultBuilder::ok_2(
+            cpu.map(|cpu| cpu as u64).unwrap_or(u64::MAX),
+            inherit as u64,
+        );
+    }
+
+    if (args.flags & SysCpu::F_PROCESS_AFFINITY_SET) == 0
+        || (args.flags & !(SysCpu::F_PROCESS_AFFINITY_SET | SysCpu::F_PROCESS_AFFINITY_INHERIT))
+            != 0
+    {
+        return ResultBuilder::invalid_argument();
+    }
+
+    let arg1 = args.args[1];
+    let cpu = if arg1 == u64::MAX {
+        None
+    } else if arg1 >= (crate::arch::num_cpus() as u64) {
+        return ResultBuilder::invalid_argument();
+    } else {
+        Some(arg1 as uCpus)
+    };
+
+    // The threads affined are the target's, so check its caps (see sys_affine_cpu()).
+    if let Some(0) = cpu {
+        if (process.capabilities() & CAP_IO_MANAGER) == 0 {
+            return ResultBuilder::result(moto_rt::E_NOT_ALLOWED);
+        }
+    }
+
+    process.set_cpu_affinity(cpu, (args.flags & SysCpu::F_PROCESS_AFFINITY_INHERIT) != 0);
+    ResultBuilder::ok()
+}
+
 fn sys_query_percpu_stats(curr: &super::process::Thread, args: &mut SyscallArgs) -> SyscallResult {
     if args.version > 0 {
         return ResultBuilder::version_too_high();
@@ -552,6 +785,10 @@ pub(super) fn sys_cpu_impl(curr: &super::process::Thread, args: &mut SyscallArgs
         SysCpu::OP_USAGE => sys_cpu_usage_impl(curr, args),
         SysCpu::OP_AFFINE_CPU => sys_affine_cpu(curr, args),
         SysCpu::OP_QUERY_PERCPU_STATS => sys_query_percpu_stats(curr, args),
+        SysCpu::OP_INTERRUPT => sys_interrupt_impl(curr, args),
+        SysCpu::OP_TIME_SLICE => sys_time_slice_impl(curr, args),
+        SysCpu::OP_TIMER_RESOLUTION => sys_timer_resolution_impl(curr, args),
+        SysCpu::OP_PROCESS_AFFINITY => sys_process_affinity(curr, args),
         // Note: curr.exit() below does not return, and the compiler puts ud2 after call,
         //       so if we get INVALID_OPCODE interrupt, we screwed up in syscall_exit_asm().
         SysCpu::OP_EXIT => curr.exit(args.args[0]),
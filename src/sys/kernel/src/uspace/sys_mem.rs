@@ -186,6 +186,18 @@ fn sys_map(
         };
     }
 
+    if flags == (SysMem::F_READABLE | SysMem::F_WRITABLE | SysMem::F_RESERVE) {
+        if phys_addr != u64::MAX || virt_addr != u64::MAX {
+            log::debug!("sys_mem_impl: bad map addresses");
+            return ResultBuilder::invalid_argument();
+        }
+
+        return match address_space.alloc_user_reserved(num_pages) {
+            Ok(segment) => ResultBuilder::ok_2(segment.start, segment.size),
+            Err(_) => ResultBuilder::result(moto_rt::E_OUT_OF_MEMORY),
+        };
+    }
+
     if flags == 0 {
         // This is used to reserve virt memory without physical mapping, for
         // shared memory operations.
@@ -269,7 +281,61 @@ fn sys_mem_global_stats(
     thread: &super::process::Thread,
     flags: u32,
     user_ptr: u64,
+    num_entries: u64,
 ) -> SyscallResult {
+    if flags == SysMem::F_QUERY_FRAME_CACHES {
+        use moto_sys::stats::FrameCacheStats;
+
+        let num_entries = (num_entries as usize).min(crate::config::MAX_CPUS as usize);
+        let mut stats = alloc::vec![FrameCacheStats::default(); num_entries];
+        let count = crate::mm::phys::frame_cache_stats(stats.as_mut_slice());
+        unsafe {
+            let src: &[u8] = core::slice::from_raw_parts(
+                stats.as_ptr() as *const u8,
+                count * core::mem::size_of::<FrameCacheStats>(),
+            );
+            if let Err(err) = thread.owner().address_space().copy_to_user(src, user_ptr) {
+                return ResultBuilder::result(err);
+            }
+        }
+
+        return ResultBuilder::ok_1(count as u64);
+    }
+
+    if flags == SysMem::F_QUERY_NUMA_NODES {
+        use moto_sys::stats::NumaNodeStats;
+
+        let num_entries = (num_entries as usize).min(NumaNodeStats::MAX_NODES);
+        let mut stats = [NumaNodeStats::default(); NumaNodeStats::MAX_NODES];
+        let count = crate::mm::phys::numa_stats(&mut stats[..num_entries]);
+        unsafe {
+            let src: &[u8] = core::slice::from_raw_parts(
+                stats.as_ptr() as *const u8,
+                count * core::mem::size_of::<NumaNodeStats>(),
+            );
+            if let Err(err) = thread.owner().address_space().copy_to_user(src, user_ptr) {
+                return ResultBuilder::result(err);
+            }
+        }
+
+        return ResultBuilder::ok_1(count as u64);
+    }
+
+    if flags == SysMem::F_QUERY_FRAGMENTATION {
+        let stats = crate::mm::phys::fragmentation_stats();
+        unsafe {
+            let src: &[u8] = core::slice::from_raw_parts(
+                &stats as *const _ as *const u8,
+                core::mem::size_of::<moto_sys::stats::MemFragmentationStats>(),
+            );
+            if let Err(err) = thread.owner().address_space().copy_to_user(src, user_ptr) {
+                return ResultBuilder::result(err);
+            }
+        }
+
+        return ResultBuilder::ok();
+    }
+
     if flags != SysMem::F_QUERY_STATS {
         return ResultBuilder::invalid_argument();
     }
@@ -295,6 +361,33 @@ fn sys_mem_global_stats(
     ResultBuilder::ok()
 }
 
+fn sys_mem_advise(
+    address_space: &UserAddressSpace,
+    advice: u32,
+    addr: u64,
+    num_pages: u64,
+) -> SyscallResult {
+    use sys_mem::{MAX_ADDRESS_SPACE_SIZE_LOG2, PAGE_SIZE_SMALL, PAGE_SIZE_SMALL_LOG2};
+
+    if (addr & (PAGE_SIZE_SMALL - 1)) != 0
+        || num_pages == 0
+        || num_pages > (1_u64 << (MAX_ADDRESS_SPACE_SIZE_LOG2 - PAGE_SIZE_SMALL_LOG2))
+    {
+        return ResultBuilder::invalid_argument();
+    }
+
+    let result = match advice {
+        SysMem::F_ADVISE_WILLNEED => address_space.populate(addr, num_pages),
+        SysMem::F_ADVISE_DONTNEED => address_space.release(addr, num_pages),
+        _ => return ResultBuilder::invalid_argument(),
+    };
+
+    match result {
+        Ok(pages) => ResultBuilder::ok_1(pages),
+        Err(err) => ResultBuilder::result(err),
+    }
+}
+
 fn sys_mem_query(
     _curr_thread: &super::process::Thread,
     address_space: &UserAddressSpace,
@@ -327,6 +420,102 @@ fn sys_mem_query(
     }
 }
 
+fn sys_random(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    let addr = args.args[1];
+    let len = args.args[2] as usize;
+    if len == 0 || len > SysMem::MAX_RANDOM_BYTES {
+        return ResultBuilder::invalid_argument();
+    }
+
+    let process = thread.owner();
+    let mut bytes = [0_u8; SysMem::MAX_RANDOM_BYTES];
+    let bytes = &mut bytes[..len];
+
+    match args.flags {
+        SysMem::F_RANDOM_GET => {
+            crate::util::entropy::fill(bytes);
+            let result = process.address_space().copy_to_user(bytes, addr);
+            bytes.fill(0);
+            match result {
+                Ok(()) => ResultBuilder::ok(),
+                Err(err) => ResultBuilder::result(err),
+            }
+        }
+        SysMem::F_RANDOM_ADD_ENTROPY => {
+            if process.capabilities() & moto_sys::caps::CAP_SYS == 0 {
+                return ResultBuilder::result(moto_rt::E_NOT_ALLOWED);
+            }
+            if let Err(err) = process.address_space().read_from_user_into(addr, bytes) {
+                return ResultBuilder::result(err);
+            }
+            crate::util::entropy::add_entropy(bytes);
+            bytes.fill(0);
+            ResultBuilder::ok()
+        }
+        _ => ResultBuilder::invalid_argument(),
+    }
+}
+
+fn sys_memory_pressure(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    if args.args[3] != 0 || args.args[4] != 0 || args.args[5] != 0 {
+        return ResultBuilder::invalid_argument();
+    }
+
+    match args.flags {
+        SysMem::F_PRESSURE_QUERY => {
+            if args.args[1] != 0 || args.args[2] != 0 {
+                return ResultBuilder::invalid_argument();
+            }
+            let (under_pressure, high, low) = crate::xray::stats::memory_pressure();
+            ResultBuilder::ok_3(under_pressure as u64, high, low)
+        }
+        SysMem::F_PRESSURE_SET => {
+            if thread.owner().capabilities() & moto_sys::caps::CAP_SYS == 0 {
+                return ResultBuilder::result(moto_rt::E_NOT_ALLOWED);
+            }
+            match crate::xray::stats::set_memory_pressure_thresholds(args.args[1], args.args[2]) {
+                Ok(()) => ResultBuilder::ok(),
+                Err(err) => ResultBuilder::result(err),
+            }
+        }
+        SysMem::F_PRESSURE_HANDLE => {
+            if args.args[1] != 0 || args.args[2] != 0 {
+                return ResultBuilder::invalid_argument();
+            }
+            let handle = thread
+                .owner()
+                .add_object(crate::xray::stats::memory_pressure_event());
+            ResultBuilder::ok_1(handle.as_u64())
+        }
+        _ => ResultBuilder::invalid_argument(),
+    }
+}
+
+fn sys_numa_node(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    if args.args[2] != 0 || args.args[3] != 0 || args.args[4] != 0 || args.args[5] != 0 {
+        return ResultBuilder::invalid_argument();
+    }
+
+    match args.flags {
+        SysMem::F_NUMA_NODE_SET => {
+            let node = match args.args[1] {
+                u64::MAX => None,
+                node if node < (crate::mm::phys::num_numa_nodes() as u64) => Some(node as u8),
+                _ => return ResultBuilder::invalid_argument(),
+            };
+            thread.set_numa_node(node);
+            ResultBuilder::ok()
+        }
+        SysMem::F_NUMA_NODE_GET => {
+            if args.args[1] != 0 {
+                return ResultBuilder::invalid_argument();
+            }
+            ResultBuilder::ok_1(thread.numa_node().map(|n| n as u64).unwrap_or(u64::MAX))
+        }
+        _ => ResultBuilder::invalid_argument(),
+    }
+}
+
 fn sys_reclaim() -> SyscallResult {
     log::warn!("SysMem::reclaim(): do CAPs check.");
 
@@ -343,13 +532,34 @@ pub fn sys_mem_impl(thread: &super::process::Thread, args: &SyscallArgs) -> Sysc
 
     let address_space_handle = SysHandle::from_u64(args.args[0]);
 
+    if args.operation == SysMem::OP_RANDOM {
+        if address_space_handle != SysHandle::NONE {
+            return ResultBuilder::invalid_argument();
+        }
+        return sys_random(thread, args);
+    }
+
+    if args.operation == SysMem::OP_PRESSURE {
+        if address_space_handle != SysHandle::NONE {
+            return ResultBuilder::invalid_argument();
+        }
+        return sys_memory_pressure(thread, args);
+    }
+
+    if args.operation == SysMem::OP_NUMA_NODE {
+        if address_space_handle != SysHandle::NONE {
+            return ResultBuilder::invalid_argument();
+        }
+        return sys_numa_node(thread, args);
+    }
+
     if address_space_handle == SysHandle::NONE {
         if args.operation != SysMem::OP_QUERY {
             log::debug!("sys_mem_impl: NONE handle and not OP_QUERY.");
             return ResultBuilder::invalid_argument();
         }
 
-        return sys_mem_global_stats(thread, args.flags, args.args[1]);
+        return sys_mem_global_stats(thread, args.flags, args.args[1], args.args[2]);
     }
 
     if address_space_handle == SysHandle::KERNEL {
@@ -409,6 +619,12 @@ pub fn sys_mem_impl(thread: &super::process::Thread, args: &SyscallArgs) -> Sysc
                 args.args[2],
             );
         }
+        SysMem::OP_ADVISE => {
+            if args.args[3] != 0 || args.args[4] != 0 || args.args[5] != 0 {
+                return ResultBuilder::invalid_argument();
+            }
+            return sys_mem_advise(&address_space, args.flags, args.args[1], args.args[2]);
+        }
         SysMem::OP_QUERY => {
             if args.args[5] != 0 {
                 return ResultBuilder::invalid_argument();
@@ -89,6 +89,54 @@ fn sys_handle_create(
     Err(moto_rt::E_INVALID_ARGUMENT)
 }
 
+fn sys_syscall_filter(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    use moto_sys::syscalls::SyscallFilter;
+
+    if args.version > 0 {
+        return ResultBuilder::version_too_high();
+    }
+    if args.args[1] != (core::mem::size_of::<SyscallFilter>() as u64) {
+        return ResultBuilder::invalid_argument();
+    }
+
+    let process = thread.owner();
+    match args.flags {
+        SysObj::F_SYSCALL_FILTER_SET => {
+            let mut filter = SyscallFilter::deny_all();
+            let bytes = unsafe {
+                core::slice::from_raw_parts_mut(
+                    &mut filter as *mut SyscallFilter as usize as *mut u8,
+                    core::mem::size_of::<SyscallFilter>(),
+                )
+            };
+            if let Err(err) = process
+                .address_space()
+                .read_from_user_into(args.args[0], bytes)
+            {
+                return ResultBuilder::result(err);
+            }
+
+            process.set_syscall_filter(&filter);
+            log::debug!("process {} set a syscall filter", process.debug_name());
+            ResultBuilder::ok()
+        }
+        SysObj::F_SYSCALL_FILTER_GET => {
+            let filter = process.syscall_filter();
+            let bytes = unsafe {
+                core::slice::from_raw_parts(
+                    &filter as *const SyscallFilter as usize as *const u8,
+                    core::mem::size_of::<SyscallFilter>(),
+                )
+            };
+            match process.address_space().copy_to_user(bytes, args.args[0]) {
+                Ok(()) => ResultBuilder::ok(),
+                Err(err) => ResultBuilder::result(err),
+            }
+        }
+        _ => ResultBuilder::invalid_argument(),
+    }
+}
+
 fn sys_handle_shared(
     op: u8,
     thread: &super::process::Thread,
@@ -287,9 +335,10 @@ fn sys_query_handle(thread: &super::process::Thread, args: &SyscallArgs) -> Sysc
     }
 
     let handle = SysHandle::from_u64(args.args[0]);
-    let return_pid = match args.flags {
-        0 => false,
-        SysObj::F_QUERY_PID => true,
+    let (return_pid, return_caps) = match args.flags {
+        0 => (false, false),
+        SysObj::F_QUERY_PID => (true, false),
+        SysObj::F_QUERY_PEER_CREDS => (true, true),
         _ => return ResultBuilder::invalid_argument(),
     };
 
@@ -308,6 +357,9 @@ fn sys_query_handle(thread: &super::process::Thread, args: &SyscallArgs) -> Sysc
 
         if return_pid {
             if let Some(proc) = super::shared::peer_owner(thread.owner().pid(), &obj.sys_object) {
+                if return_caps {
+                    return ResultBuilder::ok_2(proc.pid().as_u64(), proc.capabilities());
+                }
                 return ResultBuilder::ok_1(proc.pid().as_u64());
             } else {
                 return ResultBuilder::result(moto_rt::E_NOT_FOUND);
@@ -430,6 +482,7 @@ pub(super) fn sys_ctl_impl(thread: &super::process::Thread, args: &SyscallArgs)
             }
         }
         SysObj::OP_QUERY_HANDLE => sys_query_handle(thread, args),
+        SysObj::OP_SYSCALL_FILTER => sys_syscall_filter(thread, args),
 
         SysObj::OP_SET_LOG_LEVEL => {
             if args.version > 0 {
@@ -444,7 +497,6 @@ pub(super) fn sys_ctl_impl(thread: &super::process::Thread, args: &SyscallArgs)
                 return ResultBuilder::result(moto_rt::E_NOT_ALLOWED);
             }
 
-            let curr_log_level = log::max_level() as usize as u64;
             let next_log_level = args.args[0] as usize;
 
             let level = match next_log_level {
@@ -458,7 +510,10 @@ pub(super) fn sys_ctl_impl(thread: &super::process::Thread, args: &SyscallArgs)
                 }
             };
 
-            log::set_max_level(level);
+            let curr_log_level = match crate::xray::logger::set_level(None, level) {
+                Ok(prev) => prev as usize as u64,
+                Err(err) => return ResultBuilder::result(err),
+            };
             log::info!(
                 "Thread {} set log level to {:?}",
                 thread.debug_name(),
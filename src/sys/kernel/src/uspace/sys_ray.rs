@@ -1,4 +1,8 @@
-use moto_sys::{stats::ProcessStatsV1, syscalls::SyscallResult, SysHandle, SysRay};
+use moto_sys::{
+    stats::{ProcessStatsV1, ProcessStatsV2},
+    syscalls::SyscallResult,
+    SysHandle, SysRay,
+};
 
 use crate::xray::stats::KProcessStats;
 
@@ -32,18 +36,49 @@ fn sys_query_process_status(thread: &super::process::Thread, args: &SyscallArgs)
     }
 }
 
+// Appends @stats as ProcessStatsV1 (@version 1) or ProcessStatsV2 (@version 2) to @dest.
+fn append_process_stats(
+    stats: &KProcessStats,
+    version: u16,
+    now: u64,
+    dest: &mut alloc::vec::Vec<u8>,
+) {
+    fn append<T>(val: &T, dest: &mut alloc::vec::Vec<u8>) {
+        dest.extend_from_slice(unsafe {
+            core::slice::from_raw_parts(val as *const T as *const u8, core::mem::size_of::<T>())
+        });
+    }
+
+    if version == 1 {
+        let mut entry = ProcessStatsV1::default();
+        stats.into_v1(&mut entry, now);
+        append(&entry, dest);
+    } else {
+        let mut entry = ProcessStatsV2::default();
+        stats.into_v2(&mut entry, now);
+        append(&entry, dest);
+    }
+}
+
+fn process_stats_size(version: u16) -> usize {
+    if version == 1 {
+        core::mem::size_of::<ProcessStatsV1>()
+    } else {
+        core::mem::size_of::<ProcessStatsV2>()
+    }
+}
+
 fn sys_query_process_list(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
-    if args.version > 1 {
+    if args.version > 2 {
         return ResultBuilder::version_too_high();
     } else if args.version == 0 {
         return ResultBuilder::invalid_argument();
     }
 
-    let flat_list = match args.flags {
-        SysRay::F_QUERY_LIST => true,
-        SysRay::F_QUERY_LIST_CHILDREN => false,
+    match args.flags {
+        SysRay::F_QUERY_LIST | SysRay::F_QUERY_LIST_CHILDREN | SysRay::F_QUERY_DESCENDANTS => {}
         _ => return ResultBuilder::invalid_argument(),
-    };
+    }
 
     let pid = super::process::ProcessId::from_u64(args.args[0]);
     let dest_addr = args.args[1] as usize;
@@ -61,27 +96,38 @@ fn sys_query_process_list(thread: &super::process::Thread, args: &SyscallArgs) -
     let error_ref = &mut error;
 
     let now = crate::arch::time::Instant::now().as_u64();
+    let version = args.version;
+    let mut buf = alloc::vec::Vec::with_capacity(process_stats_size(version));
 
     let func = |val: &KProcessStats| -> bool {
-        let mut stats = ProcessStatsV1::default();
-        val.into_v1(&mut stats, now);
+        buf.clear();
+        append_process_stats(val, version, now, &mut buf);
 
-        unsafe {
-            let dest_ptr = dest_addr + *counter_ref * core::mem::size_of::<ProcessStatsV1>();
-            let buf: &[u8] = core::slice::from_raw_parts(
-                &stats as *const _ as *const u8,
-                core::mem::size_of::<ProcessStatsV1>(),
-            );
-            if let Err(err) = address_space.copy_to_user(buf, dest_ptr as u64) {
-                *error_ref = err;
-                return false;
-            }
+        let dest_ptr = dest_addr + *counter_ref * process_stats_size(version);
+        if let Err(err) = address_space.copy_to_user(buf.as_slice(), dest_ptr as u64) {
+            *error_ref = err;
+            return false;
         }
         *counter_ref += 1;
         *counter_ref < dest_num
     };
 
-    KProcessStats::iterate(pid, flat_list, func);
+    match args.flags {
+        SysRay::F_QUERY_LIST => KProcessStats::iterate(pid, true, func),
+        SysRay::F_QUERY_LIST_CHILDREN => KProcessStats::iterate(pid, false, func),
+        _ => {
+            let max_depth = args.args[3].min(u32::MAX as u64) as u32;
+            let caps = args.args[4];
+            let predicate = |val: &KProcessStats| -> bool {
+                caps == 0
+                    || val
+                        .owner
+                        .upgrade()
+                        .is_some_and(|process| process.capabilities() & caps == caps)
+            };
+            KProcessStats::iterate_filtered(pid, max_depth, predicate, func)
+        }
+    }
 
     if error != moto_rt::E_OK {
         return ResultBuilder::result(error);
@@ -90,6 +136,728 @@ fn sys_query_process_list(thread: &super::process::Thread, args: &SyscallArgs) -
     ResultBuilder::ok_1(counter as u64)
 }
 
+fn sys_query_process_snapshot(
+    thread: &super::process::Thread,
+    args: &SyscallArgs,
+) -> SyscallResult {
+    if args.version > 2 {
+        return ResultBuilder::version_too_high();
+    } else if args.version == 0 {
+        return ResultBuilder::invalid_argument();
+    }
+
+    let pid = super::process::ProcessId::from_u64(args.args[0]);
+    let dest_addr = args.args[1];
+    let dest_num = args.args[2] as usize; // Number of structs, not number of bytes.
+    if dest_num < 1 {
+        return ResultBuilder::invalid_argument();
+    }
+
+    let snapshot = KProcessStats::snapshot(pid);
+    if snapshot.is_empty() {
+        return ResultBuilder::result(moto_rt::E_NOT_FOUND);
+    }
+
+    let now = crate::arch::time::Instant::now().as_u64();
+    let num_entries = dest_num.min(snapshot.len());
+    let mut entries =
+        alloc::vec::Vec::with_capacity(num_entries * process_stats_size(args.version));
+    for stats in snapshot.iter().take(dest_num) {
+        append_process_stats(stats, args.version, now, &mut entries);
+    }
+
+    if let Err(err) = thread
+        .owner()
+        .address_space()
+        .copy_to_user(entries.as_slice(), dest_addr)
+    {
+        return ResultBuilder::result(err);
+    }
+
+    ResultBuilder::ok_2(num_entries as u64, snapshot.len() as u64)
+}
+
+fn sys_query_process_percpu_usage(
+    thread: &super::process::Thread,
+    args: &SyscallArgs,
+) -> SyscallResult {
+    if args.version > 0 {
+        return ResultBuilder::version_too_high();
+    }
+
+    let pid = args.args[0];
+    let dest_addr = args.args[1];
+    let dest_num = args.args[2] as usize; // Number of u64 values, not number of bytes.
+
+    let num_cpus = crate::arch::num_cpus() as usize;
+    if dest_num < num_cpus {
+        return ResultBuilder::invalid_argument();
+    }
+
+    let stats = match crate::xray::stats::any_stats_from_pid(pid) {
+        Some(stats) => stats,
+        None => return ResultBuilder::result(moto_rt::E_NOT_FOUND),
+    };
+
+    let mut usage = alloc::vec::Vec::new();
+    usage.resize(num_cpus, 0_u64);
+    let count = stats.per_cpu_usage(usage.as_mut_slice());
+
+    unsafe {
+        let buf: &[u8] = core::slice::from_raw_parts(
+            usage.as_ptr() as *const u8,
+            count * core::mem::size_of::<u64>(),
+        );
+        if let Err(err) = thread.owner().address_space().copy_to_user(buf, dest_addr) {
+            return ResultBuilder::result(err);
+        }
+    }
+
+    ResultBuilder::ok_1(count as u64)
+}
+
+fn sys_query_process_page_faults(args: &SyscallArgs) -> SyscallResult {
+    if args.version > 0 {
+        return ResultBuilder::version_too_high();
+    }
+
+    match crate::xray::stats::any_stats_from_pid(args.args[0]) {
+        Some(stats) => ResultBuilder::ok_1(stats.page_faults()),
+        None => ResultBuilder::result(moto_rt::E_NOT_FOUND),
+    }
+}
+
+fn sys_query_process_sched_latency(
+    thread: &super::process::Thread,
+    args: &SyscallArgs,
+) -> SyscallResult {
+    if args.version > 0 {
+        return ResultBuilder::version_too_high();
+    }
+
+    let stats = match crate::xray::stats::any_stats_from_pid(args.args[0]) {
+        Some(stats) => stats.sched_latency(),
+        None => return ResultBuilder::result(moto_rt::E_NOT_FOUND),
+    };
+
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            &stats as *const _ as usize as *const u8,
+            core::mem::size_of::<moto_sys::stats::SchedLatencyStats>(),
+        )
+    };
+    match thread
+        .owner()
+        .address_space()
+        .copy_to_user(bytes, args.args[1])
+    {
+        Ok(()) => ResultBuilder::ok(),
+        Err(err) => ResultBuilder::result(err),
+    }
+}
+
+fn sys_query_process_rusage(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    if args.version > 0 {
+        return ResultBuilder::version_too_high();
+    }
+
+    let usage = match super::sysobject::object_from_handle::<super::process::Process>(
+        &thread.owner(),
+        SysHandle::from_u64(args.args[0]),
+    ) {
+        Some(proc) => proc.stats().rusage(),
+        None => return ResultBuilder::result(moto_rt::E_INVALID_ARGUMENT),
+    };
+
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            &usage as *const _ as usize as *const u8,
+            core::mem::size_of::<moto_sys::stats::ResourceUsage>(),
+        )
+    };
+    match thread
+        .owner()
+        .address_space()
+        .copy_to_user(bytes, args.args[1])
+    {
+        Ok(()) => ResultBuilder::ok(),
+        Err(err) => ResultBuilder::result(err),
+    }
+}
+
+fn sys_query_process_percpu_migrations(
+    thread: &super::process::Thread,
+    args: &SyscallArgs,
+) -> SyscallResult {
+    if args.version > 0 {
+        return ResultBuilder::version_too_high();
+    }
+
+    let pid = args.args[0];
+    let dest_addr = args.args[1];
+    let dest_num = args.args[2] as usize; // Number of CpuMigrations.
+
+    let num_cpus = crate::arch::num_cpus() as usize;
+    if dest_num < num_cpus {
+        return ResultBuilder::invalid_argument();
+    }
+
+    let stats = match crate::xray::stats::any_stats_from_pid(pid) {
+        Some(stats) => stats,
+        None => return ResultBuilder::result(moto_rt::E_NOT_FOUND),
+    };
+
+    let mut migrations = alloc::vec![moto_sys::stats::CpuMigrations::default(); num_cpus];
+    let count = stats.per_cpu_migrations(migrations.as_mut_slice());
+
+    unsafe {
+        let buf: &[u8] = core::slice::from_raw_parts(
+            migrations.as_ptr() as *const u8,
+            count * core::mem::size_of::<moto_sys::stats::CpuMigrations>(),
+        );
+        if let Err(err) = thread.owner().address_space().copy_to_user(buf, dest_addr) {
+            return ResultBuilder::result(err);
+        }
+    }
+
+    ResultBuilder::ok_1(count as u64)
+}
+
+fn sys_query_process_threads(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    use moto_sys::stats::ThreadCpuUsage;
+
+    if args.version > 0 {
+        return ResultBuilder::version_too_high();
+    }
+
+    let pid = args.args[0];
+    let dest_addr = args.args[1];
+    let dest_num = args.args[2] as usize; // Number of entries, not number of bytes.
+
+    if dest_num == 0 {
+        return ResultBuilder::invalid_argument();
+    }
+
+    let stats = match crate::xray::stats::any_stats_from_pid(pid) {
+        Some(stats) => stats,
+        None => return ResultBuilder::result(moto_rt::E_NOT_FOUND),
+    };
+
+    let (cpu_uspace, cpu_kernel) = stats.reaped_threads_cpu_usage();
+    let mut usage = alloc::vec![ThreadCpuUsage {
+        tid: 0,
+        cpu_uspace,
+        cpu_kernel,
+    }];
+    let mut available = 1;
+    stats.iterate_threads(|tid, cpu_uspace, cpu_kernel| {
+        available += 1;
+        if usage.len() < dest_num {
+            usage.push(ThreadCpuUsage {
+                tid,
+                cpu_uspace,
+                cpu_kernel,
+            });
+        }
+        true
+    });
+
+    unsafe {
+        let buf: &[u8] = core::slice::from_raw_parts(
+            usage.as_ptr() as *const u8,
+            usage.len() * core::mem::size_of::<ThreadCpuUsage>(),
+        );
+        if let Err(err) = thread.owner().address_space().copy_to_user(buf, dest_addr) {
+            return ResultBuilder::result(err);
+        }
+    }
+
+    ResultBuilder::ok_2(usage.len() as u64, available)
+}
+
+fn sys_cmdline_set(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    let target = SysHandle::from_u64(args.args[0]);
+    let addr = args.args[1];
+    let len = args.args[2];
+
+    if len == 0 || len > SysRay::MAX_CMDLINE_SIZE {
+        return ResultBuilder::invalid_argument();
+    }
+
+    // Only the holder of the process handle (normally the spawner) can set the cmdline.
+    let process = match super::sysobject::object_from_handle::<super::process::Process>(
+        &thread.owner(),
+        target,
+    ) {
+        Some(process) => process,
+        None => return ResultBuilder::bad_handle(target),
+    };
+
+    let bytes = match thread.owner().address_space().read_from_user(addr, len) {
+        Ok(bytes) => bytes,
+        Err(err) => return ResultBuilder::result(err),
+    };
+
+    match process.stats().set_cmdline(bytes) {
+        Ok(()) => ResultBuilder::ok(),
+        Err(err) => ResultBuilder::result(err),
+    }
+}
+
+fn sys_cmdline_get(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    let pid = args.args[0];
+    let dest_addr = args.args[1];
+    let dest_len = args.args[2] as usize;
+
+    // Only privileged processes can look at other processes' cmdlines.
+    if pid != thread.owner().pid().as_u64()
+        && (thread.owner().capabilities() & moto_sys::caps::CAP_SYS) == 0
+    {
+        return ResultBuilder::result(moto_rt::E_NOT_ALLOWED);
+    }
+
+    let stats = match crate::xray::stats::any_stats_from_pid(pid) {
+        Some(stats) => stats,
+        None => return ResultBuilder::result(moto_rt::E_NOT_FOUND),
+    };
+
+    let cmdline = match stats.cmdline() {
+        Some(cmdline) => cmdline,
+        None => return ResultBuilder::ok_1(0),
+    };
+
+    let sz = dest_len.min(cmdline.len());
+    if sz > 0 {
+        if let Err(err) = thread
+            .owner()
+            .address_space()
+            .copy_to_user(&cmdline[0..sz], dest_addr)
+        {
+            return ResultBuilder::result(err);
+        }
+    }
+
+    ResultBuilder::ok_1(cmdline.len() as u64)
+}
+
+fn sys_cpu_limit_set(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    let target = SysHandle::from_u64(args.args[0]);
+    let limit = args.args[1];
+    let action = args.args[2];
+
+    if limit == 0 {
+        return ResultBuilder::invalid_argument();
+    }
+    match action {
+        SysRay::CPU_LIMIT_ACTION_KILL | SysRay::CPU_LIMIT_ACTION_NOTIFY => {}
+        _ => return ResultBuilder::invalid_argument(),
+    }
+
+    // Only the holder of the process handle (normally the spawner) can set the limit.
+    let process = match super::sysobject::object_from_handle::<super::process::Process>(
+        &thread.owner(),
+        target,
+    ) {
+        Some(process) => process,
+        None => return ResultBuilder::bad_handle(target),
+    };
+
+    // CAP_SYS processes cannot be killed (see job_fn_kill_by_pid()), so
+    // a limit that would kill one would never be enforced.
+    if action == SysRay::CPU_LIMIT_ACTION_KILL
+        && (process.capabilities() & moto_sys::caps::CAP_SYS) != 0
+    {
+        return ResultBuilder::result(moto_rt::E_NOT_ALLOWED);
+    }
+
+    match process.stats().set_cpu_limit(limit, action) {
+        Ok(()) => ResultBuilder::ok(),
+        Err(err) => ResultBuilder::result(err),
+    }
+}
+
+fn sys_cpu_limit_get(args: &SyscallArgs) -> SyscallResult {
+    let stats = match crate::xray::stats::any_stats_from_pid(args.args[0]) {
+        Some(stats) => stats,
+        None => return ResultBuilder::result(moto_rt::E_NOT_FOUND),
+    };
+
+    let (limit, remaining) = stats.cpu_limit();
+    ResultBuilder::ok_2(limit, remaining)
+}
+
+fn sys_stats_reset(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    if (thread.owner().capabilities() & moto_sys::caps::CAP_SYS) == 0 {
+        return ResultBuilder::result(moto_rt::E_NOT_ALLOWED);
+    }
+
+    let stats = match crate::xray::stats::any_stats_from_pid(args.args[0]) {
+        Some(stats) => stats,
+        None => return ResultBuilder::result(moto_rt::E_NOT_FOUND),
+    };
+
+    stats.reset_counters();
+    ResultBuilder::ok()
+}
+
+fn level_filter_from_u64(level: u64) -> Option<log::LevelFilter> {
+    match level {
+        0 => Some(log::LevelFilter::Off),
+        1 => Some(log::LevelFilter::Error),
+        2 => Some(log::LevelFilter::Warn),
+        3 => Some(log::LevelFilter::Info),
+        4 => Some(log::LevelFilter::Debug),
+        5 => Some(log::LevelFilter::Trace),
+        _ => None,
+    }
+}
+
+fn sys_klog_read(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    let from = args.args[0];
+    let dest_addr = args.args[1];
+    let dest_len = args.args[2] as usize;
+
+    if dest_len == 0 {
+        return ResultBuilder::invalid_argument();
+    }
+
+    let mut bytes = alloc::vec::Vec::new();
+    bytes.resize(dest_len.min(SysRay::KLOG_MAX_READ), 0_u8);
+    let (len, next) = crate::xray::logger::read_log(from, bytes.as_mut_slice());
+
+    if len > 0 {
+        if let Err(err) = thread
+            .owner()
+            .address_space()
+            .copy_to_user(&bytes[0..len], dest_addr)
+        {
+            return ResultBuilder::result(err);
+        }
+    }
+
+    ResultBuilder::ok_2(len as u64, next)
+}
+
+fn sys_klog_set_level(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    let addr = args.args[0];
+    let len = args.args[1];
+    let level = match level_filter_from_u64(args.args[2]) {
+        Some(level) => level,
+        None => return ResultBuilder::invalid_argument(),
+    };
+
+    if len == 0 || len > (crate::xray::logger::MAX_SUBSYSTEM_LEN as u64) {
+        return ResultBuilder::invalid_argument();
+    }
+
+    let bytes = match thread.owner().address_space().read_from_user(addr, len) {
+        Ok(bytes) => bytes,
+        Err(err) => return ResultBuilder::result(err),
+    };
+    let subsystem = match core::str::from_utf8(bytes.as_slice()) {
+        Ok(subsystem) => subsystem,
+        Err(_) => return ResultBuilder::invalid_argument(),
+    };
+
+    match crate::xray::logger::set_level(Some(subsystem), level) {
+        Ok(prev) => {
+            log::info!(
+                "Thread {} set log level of '{}' to {:?}",
+                thread.debug_name(),
+                subsystem,
+                level
+            );
+            ResultBuilder::ok_1(prev as usize as u64)
+        }
+        Err(err) => ResultBuilder::result(err),
+    }
+}
+
+fn sys_panic_action_set(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    let action = args.args[0];
+    let process = thread.owner();
+
+    match action {
+        SysRay::PANIC_ACTION_EXIT => {}
+        SysRay::PANIC_ACTION_HALT | SysRay::PANIC_ACTION_REBOOT => {
+            if (process.capabilities() & moto_sys::caps::CAP_SYS) == 0 {
+                return ResultBuilder::result(moto_rt::E_NOT_ALLOWED);
+            }
+        }
+        _ => return ResultBuilder::invalid_argument(),
+    }
+
+    process.set_panic_action(action);
+    ResultBuilder::ok()
+}
+
+fn sys_panic_action_panicked(thread: &super::process::Thread) -> SyscallResult {
+    let process = thread.owner();
+
+    match process.panic_action() {
+        SysRay::PANIC_ACTION_HALT => {
+            log::error!(
+                "Process {} '{}' panicked: shutting down.",
+                process.pid().as_u64(),
+                process.debug_name()
+            );
+            crate::arch::kernel_exit()
+        }
+        SysRay::PANIC_ACTION_REBOOT => {
+            log::error!(
+                "Process {} '{}' panicked: rebooting.",
+                process.pid().as_u64(),
+                process.debug_name()
+            );
+            crate::arch::kernel_reboot()
+        }
+        _ => ResultBuilder::ok(),
+    }
+}
+
+fn sys_oom_priority(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    let process = thread.owner();
+    let Some(target) = super::process::Process::from_pid(args.args[0]) else {
+        return ResultBuilder::result(moto_rt::E_NOT_FOUND);
+    };
+
+    match args.flags {
+        SysRay::F_OOM_PRIORITY_GET => ResultBuilder::ok_1(target.oom_priority() as i64 as u64),
+        SysRay::F_OOM_PRIORITY_SET => {
+            let priority = args.args[1] as i64;
+            if priority < (SysRay::OOM_PRIORITY_MIN as i64)
+                || priority > (SysRay::OOM_PRIORITY_MAX as i64)
+            {
+                return ResultBuilder::invalid_argument();
+            }
+            let priority = priority as i32;
+
+            if (process.capabilities() & moto_sys::caps::CAP_SYS) == 0
+                && (target.pid() != process.pid() || priority < target.oom_priority())
+            {
+                return ResultBuilder::result(moto_rt::E_NOT_ALLOWED);
+            }
+
+            target.set_oom_priority(priority);
+            ResultBuilder::ok()
+        }
+        _ => ResultBuilder::invalid_argument(),
+    }
+}
+
+fn sys_log_rate_limit(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    match args.flags {
+        SysRay::F_LOG_RATE_LIMIT_GET => {
+            let (rate, burst) = super::log_limit::get();
+            ResultBuilder::ok_2(rate, burst)
+        }
+        SysRay::F_LOG_RATE_LIMIT_DROPPED => {
+            ResultBuilder::ok_1(thread.owner().log_messages_dropped())
+        }
+        SysRay::F_LOG_RATE_LIMIT_SET => {
+            if (thread.owner().capabilities() & moto_sys::caps::CAP_SYS) == 0 {
+                return ResultBuilder::result(moto_rt::E_NOT_ALLOWED);
+            }
+            match super::log_limit::set(args.args[0], args.args[1]) {
+                Ok(()) => ResultBuilder::ok(),
+                Err(err) => ResultBuilder::result(err),
+            }
+        }
+        _ => ResultBuilder::invalid_argument(),
+    }
+}
+
+fn sys_deadlock_detection(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    match args.flags {
+        SysRay::F_DEADLOCK_DETECTION_GET => ResultBuilder::ok_1(super::deadlock::enabled() as u64),
+        SysRay::F_DEADLOCK_DETECTION_SET => {
+            if (thread.owner().capabilities() & moto_sys::caps::CAP_SYS) == 0 {
+                return ResultBuilder::result(moto_rt::E_NOT_ALLOWED);
+            }
+            super::deadlock::set_enabled(args.args[0] != 0);
+            ResultBuilder::ok()
+        }
+        SysRay::F_DEADLOCK_DETECTION_SET_PROCESS => {
+            // Affects only the waits of the caller's own threads.
+            thread.owner().set_deadlock_detection(args.args[0] != 0);
+            ResultBuilder::ok()
+        }
+        _ => ResultBuilder::invalid_argument(),
+    }
+}
+
+fn sys_watchdog(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    let process = thread.owner();
+    if (process.capabilities() & moto_sys::caps::CAP_SYS) == 0 {
+        return ResultBuilder::result(moto_rt::E_NOT_ALLOWED);
+    }
+
+    let result = match args.flags {
+        SysRay::F_WATCHDOG_REGISTER => super::watchdog::register(
+            &process,
+            core::time::Duration::from_nanos(args.args[0]),
+            args.args[1],
+        ),
+        SysRay::F_WATCHDOG_PET => super::watchdog::pet(&process),
+        SysRay::F_WATCHDOG_UNREGISTER => super::watchdog::unregister(&process),
+        _ => return ResultBuilder::invalid_argument(),
+    };
+
+    match result {
+        Ok(()) => ResultBuilder::ok(),
+        Err(err) => ResultBuilder::result(err),
+    }
+}
+
+// The system hostname; see SysRay::OP_HOSTNAME.
+struct Hostname {
+    bytes: [u8; SysRay::MAX_HOSTNAME_LEN],
+    len: usize,
+}
+
+const DEFAULT_HOSTNAME: &str = "localhost";
+
+impl Hostname {
+    const fn new(hostname: &str) -> Self {
+        let src = hostname.as_bytes();
+        let mut bytes = [0_u8; SysRay::MAX_HOSTNAME_LEN];
+        let mut idx = 0;
+        while idx < src.len() {
+            bytes[idx] = src[idx];
+            idx += 1;
+        }
+        Self {
+            bytes,
+            len: src.len(),
+        }
+    }
+}
+
+static HOSTNAME: crate::util::SpinLock<Hostname> =
+    crate::util::SpinLock::new(Hostname::new(DEFAULT_HOSTNAME));
+
+fn sys_hostname_get(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    let dest_addr = args.args[0];
+    let dest_len = args.args[1] as usize;
+
+    let (bytes, len) = {
+        let hostname = HOSTNAME.lock(line!());
+        (hostname.bytes, hostname.len)
+    };
+
+    let sz = dest_len.min(len);
+    if sz > 0 {
+        if let Err(err) = thread
+            .owner()
+            .address_space()
+            .copy_to_user(&bytes[0..sz], dest_addr)
+        {
+            return ResultBuilder::result(err);
+        }
+    }
+
+    ResultBuilder::ok_1(len as u64)
+}
+
+fn sys_hostname_set(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    let addr = args.args[0];
+    let len = args.args[1];
+
+    if (thread.owner().capabilities() & moto_sys::caps::CAP_SYS) == 0 {
+        return ResultBuilder::result(moto_rt::E_NOT_ALLOWED);
+    }
+    if len == 0 || len > (SysRay::MAX_HOSTNAME_LEN as u64) {
+        return ResultBuilder::invalid_argument();
+    }
+
+    let bytes = match thread.owner().address_space().read_from_user(addr, len) {
+        Ok(bytes) => bytes,
+        Err(err) => return ResultBuilder::result(err),
+    };
+    let hostname = match core::str::from_utf8(bytes.as_slice()) {
+        Ok(hostname) => hostname,
+        Err(_) => return ResultBuilder::invalid_argument(),
+    };
+    if hostname.chars().any(|c| c.is_control()) {
+        return ResultBuilder::invalid_argument();
+    }
+
+    *HOSTNAME.lock(line!()) = Hostname::new(hostname);
+    log::info!(
+        "Thread {} set hostname to '{}'",
+        thread.debug_name(),
+        hostname
+    );
+    ResultBuilder::ok()
+}
+
+fn sys_cpu_samples_read(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    let cpu = args.args[0];
+    let from = args.args[1];
+    let dest_addr = args.args[2];
+    let dest_num = args.args[3] as usize; // Number of samples, not number of bytes.
+
+    if dest_num == 0 || cpu >= (crate::arch::num_cpus() as u64) {
+        return ResultBuilder::invalid_argument();
+    }
+
+    let mut samples = alloc::vec::Vec::new();
+    samples.resize(
+        dest_num.min(crate::config::CPU_SAMPLE_BUFFER_SIZE),
+        moto_sys::stats::CpuSample::default(),
+    );
+    let (count, next) =
+        match crate::xray::sampler::read(cpu as crate::config::uCpus, from, samples.as_mut_slice())
+        {
+            Ok(res) => res,
+            Err(err) => return ResultBuilder::result(err),
+        };
+
+    if count > 0 {
+        unsafe {
+            let buf: &[u8] = core::slice::from_raw_parts(
+                samples.as_ptr() as *const u8,
+                count * core::mem::size_of::<moto_sys::stats::CpuSample>(),
+            );
+            if let Err(err) = thread.owner().address_space().copy_to_user(buf, dest_addr) {
+                return ResultBuilder::result(err);
+            }
+        }
+    }
+
+    ResultBuilder::ok_2(count as u64, next)
+}
+
+fn sys_lock_stats_read(thread: &super::process::Thread, args: &SyscallArgs) -> SyscallResult {
+    let dest_addr = args.args[0];
+    let dest_num = args.args[1] as usize; // Number of sites, not number of bytes.
+
+    if dest_num == 0 {
+        return ResultBuilder::invalid_argument();
+    }
+
+    let mut sites = alloc::vec::Vec::new();
+    sites.resize(
+        dest_num.min(crate::config::LOCK_STATS_SITES),
+        moto_sys::stats::LockSiteStats::default(),
+    );
+    let (count, dropped) = crate::xray::lock_stats::read(sites.as_mut_slice());
+
+    if count > 0 {
+        unsafe {
+            let buf: &[u8] = core::slice::from_raw_parts(
+                sites.as_ptr() as *const u8,
+                count * core::mem::size_of::<moto_sys::stats::LockSiteStats>(),
+            );
+            if let Err(err) = thread.owner().address_space().copy_to_user(buf, dest_addr) {
+                return ResultBuilder::result(err);
+            }
+        }
+    }
+
+    ResultBuilder::ok_2(count as u64, dropped)
+}
+
 fn sys_log(
     curr_thread: &super::process::Thread,
     flags: u32,
@@ -104,6 +872,17 @@ fn sys_log(
         return ResultBuilder::invalid_argument();
     }
 
+    // Dropped messages are not an error for the caller.
+    let suppressed = match curr_thread.owner().admit_log_message() {
+        Ok(suppressed) => suppressed,
+        Err(dropped) => {
+            if dropped == 1 {
+                super::log_limit::on_first_drop(curr_thread);
+            }
+            return ResultBuilder::ok();
+        }
+    };
+
     let sz = u64::min(256, sz);
     let address_space = curr_thread.owner().address_space().clone();
     let bytes = match address_space.read_from_user(virt_addr, sz) {
@@ -117,6 +896,12 @@ fn sys_log(
     use core::str;
     match str::from_utf8(bytes.as_slice()) {
         Ok(str) => {
+            if suppressed > 0 {
+                crate::xray::logger::log_user(
+                    curr_thread,
+                    alloc::format!("{} messages suppressed (log rate limit)", suppressed).as_str(),
+                );
+            }
             crate::xray::logger::log_user(curr_thread, str);
         }
         Err(_) => return ResultBuilder::result(moto_rt::E_INVALID_ARGUMENT),
@@ -131,11 +916,148 @@ pub(super) fn sys_ray_impl(thread: &super::process::Thread, args: &SyscallArgs)
 
         SysRay::OP_QUERY_PROCESS => match args.flags {
             SysRay::F_QUERY_STATUS => sys_query_process_status(thread, args),
-            SysRay::F_QUERY_LIST | SysRay::F_QUERY_LIST_CHILDREN => {
+            SysRay::F_QUERY_LIST | SysRay::F_QUERY_LIST_CHILDREN | SysRay::F_QUERY_DESCENDANTS => {
                 sys_query_process_list(thread, args)
             }
+            SysRay::F_QUERY_PERCPU_USAGE => sys_query_process_percpu_usage(thread, args),
+            SysRay::F_QUERY_PERCPU_MIGRATIONS => sys_query_process_percpu_migrations(thread, args),
+            SysRay::F_QUERY_SNAPSHOT => sys_query_process_snapshot(thread, args),
+            SysRay::F_QUERY_PAGE_FAULTS => sys_query_process_page_faults(args),
+            SysRay::F_QUERY_SCHED_LATENCY => sys_query_process_sched_latency(thread, args),
+            SysRay::F_QUERY_RUSAGE => sys_query_process_rusage(thread, args),
+            SysRay::F_QUERY_THREADS => sys_query_process_threads(thread, args),
             _ => ResultBuilder::invalid_argument(),
         },
+        SysRay::OP_CMDLINE => {
+            if args.version > 0 {
+                return ResultBuilder::version_too_high();
+            }
+            match args.flags {
+                SysRay::F_CMDLINE_SET => sys_cmdline_set(thread, args),
+                SysRay::F_CMDLINE_GET => sys_cmdline_get(thread, args),
+                _ => ResultBuilder::invalid_argument(),
+            }
+        }
+        SysRay::OP_CPU_LIMIT => {
+            if args.version > 0 {
+                return ResultBuilder::version_too_high();
+            }
+            match args.flags {
+                SysRay::F_CPU_LIMIT_SET => sys_cpu_limit_set(thread, args),
+                SysRay::F_CPU_LIMIT_GET => sys_cpu_limit_get(args),
+                _ => ResultBuilder::invalid_argument(),
+            }
+        }
+        SysRay::OP_STATS_RESET => {
+            if args.version > 0 {
+                return ResultBuilder::version_too_high();
+            }
+            if args.flags != 0 {
+                return ResultBuilder::invalid_argument();
+            }
+            sys_stats_reset(thread, args)
+        }
+        SysRay::OP_KLOG => {
+            if args.version > 0 {
+                return ResultBuilder::version_too_high();
+            }
+            if (thread.owner().capabilities() & moto_sys::caps::CAP_LOG) == 0 {
+                return ResultBuilder::result(moto_rt::E_NOT_ALLOWED);
+            }
+            match args.flags {
+                SysRay::F_KLOG_READ => sys_klog_read(thread, args),
+                SysRay::F_KLOG_SET_LEVEL => sys_klog_set_level(thread, args),
+                _ => ResultBuilder::invalid_argument(),
+            }
+        }
+        SysRay::OP_CPU_SAMPLING => {
+            if args.version > 0 {
+                return ResultBuilder::version_too_high();
+            }
+            if (thread.owner().capabilities() & moto_sys::caps::CAP_SYS) == 0 {
+                return ResultBuilder::result(moto_rt::E_NOT_ALLOWED);
+            }
+            match args.flags {
+                SysRay::F_CPU_SAMPLING_START => {
+                    crate::xray::sampler::start();
+                    ResultBuilder::ok()
+                }
+                SysRay::F_CPU_SAMPLING_STOP => {
+                    crate::xray::sampler::stop();
+                    ResultBuilder::ok()
+                }
+                SysRay::F_CPU_SAMPLING_READ => sys_cpu_samples_read(thread, args),
+                _ => ResultBuilder::invalid_argument(),
+            }
+        }
+        SysRay::OP_LOCK_STATS => {
+            if args.version > 0 {
+                return ResultBuilder::version_too_high();
+            }
+            if (thread.owner().capabilities() & moto_sys::caps::CAP_SYS) == 0 {
+                return ResultBuilder::result(moto_rt::E_NOT_ALLOWED);
+            }
+            match args.flags {
+                SysRay::F_LOCK_STATS_START => {
+                    crate::xray::lock_stats::set_enabled(true);
+                    ResultBuilder::ok()
+                }
+                SysRay::F_LOCK_STATS_STOP => {
+                    crate::xray::lock_stats::set_enabled(false);
+                    ResultBuilder::ok()
+                }
+                SysRay::F_LOCK_STATS_READ => sys_lock_stats_read(thread, args),
+                SysRay::F_LOCK_STATS_RESET => {
+                    crate::xray::lock_stats::reset();
+                    ResultBuilder::ok()
+                }
+                _ => ResultBuilder::invalid_argument(),
+            }
+        }
+        SysRay::OP_PANIC_ACTION => {
+            if args.version > 0 {
+                return ResultBuilder::version_too_high();
+            }
+            match args.flags {
+                SysRay::F_PANIC_ACTION_SET => sys_panic_action_set(thread, args),
+                SysRay::F_PANIC_ACTION_PANICKED => sys_panic_action_panicked(thread),
+                _ => ResultBuilder::invalid_argument(),
+            }
+        }
+        SysRay::OP_HOSTNAME => {
+            if args.version > 0 {
+                return ResultBuilder::version_too_high();
+            }
+            match args.flags {
+                SysRay::F_HOSTNAME_GET => sys_hostname_get(thread, args),
+                SysRay::F_HOSTNAME_SET => sys_hostname_set(thread, args),
+                _ => ResultBuilder::invalid_argument(),
+            }
+        }
+        SysRay::OP_WATCHDOG => {
+            if args.version > 0 {
+                return ResultBuilder::version_too_high();
+            }
+            sys_watchdog(thread, args)
+        }
+        SysRay::OP_OOM_PRIORITY => {
+            if args.version > 0 {
+                return ResultBuilder::version_too_high();
+            }
+            sys_oom_priority(thread, args)
+        }
+        SysRay::OP_LOG_RATE_LIMIT => {
+            if args.version > 0 {
+                return ResultBuilder::version_too_high();
+            }
+            sys_log_rate_limit(thread, args)
+        }
+        SysRay::OP_DEADLOCK_DETECTION => {
+            if args.version > 0 {
+                return ResultBuilder::version_too_high();
+            }
+            sys_deadlock_detection(thread, args)
+        }
         SysRay::OP_LOG => {
             if args.args[2] != 0 || args.args[3] != 0 || args.args[4] != 0 || args.args[5] != 0 {
                 return ResultBuilder::invalid_argument();
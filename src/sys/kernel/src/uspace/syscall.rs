@@ -57,6 +57,15 @@ impl ResultBuilder {
         SyscallResult { result: 0, data }
     }
 
+    #[inline(always)]
+    pub fn ok_3(val0: u64, val1: u64, val2: u64) -> SyscallResult {
+        let mut data = [0_u64; 6];
+        data[0] = val0;
+        data[1] = val1;
+        data[2] = val2;
+        SyscallResult { result: 0, data }
+    }
+
     #[inline(always)]
     pub fn result(result: ErrorCode) -> SyscallResult {
         SyscallResult {
@@ -106,12 +115,20 @@ pub fn do_syscall(curr: &super::process::Thread, args: &mut SyscallArgs) -> Sysc
 
     curr.on_syscall_enter(args.syscall_nr, args.operation);
 
-    let result = match args.syscall_nr {
-        syscalls::SYS_CPU => super::sys_cpu::sys_cpu_impl(curr, args),
-        syscalls::SYS_MEM => super::sys_mem::sys_mem_impl(curr, args),
-        syscalls::SYS_OBJ => super::sys_obj::sys_ctl_impl(curr, args),
-        syscalls::SYS_RAY => super::sys_ray::sys_ray_impl(curr, args),
-        _ => ResultBuilder::not_implemented(),
+    let result = if !curr
+        .owner()
+        .syscall_allowed(args.syscall_nr, args.operation)
+    {
+        // Blocked by the process's syscall filter (SysObj::OP_SYSCALL_FILTER).
+        ResultBuilder::result(moto_rt::E_NOT_ALLOWED)
+    } else {
+        match args.syscall_nr {
+            syscalls::SYS_CPU => super::sys_cpu::sys_cpu_impl(curr, args),
+            syscalls::SYS_MEM => super::sys_mem::sys_mem_impl(curr, args),
+            syscalls::SYS_OBJ => super::sys_obj::sys_ctl_impl(curr, args),
+            syscalls::SYS_RAY => super::sys_ray::sys_ray_impl(curr, args),
+            _ => ResultBuilder::not_implemented(),
+        }
     };
 
     curr.on_syscall_exit();
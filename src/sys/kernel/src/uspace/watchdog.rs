@@ -0,0 +1,146 @@
+// Watchdog for critical system services (see SysRay::OP_WATCHDOG).
+//
+// A registered process must pet its watchdog at least once per timeout;
+// deadlines are checked on CPU 0's scheduler ticks, so a missed deadline
+// is noticed up to a time slice late.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+use moto_sys::{ErrorCode, SysRay};
+
+use super::process::Process;
+use crate::arch::time::Instant;
+use crate::util::SpinLock;
+
+struct Watchdog {
+    process: Weak<Process>,
+    timeout: Duration,
+    action: u64,
+    last_pet: Instant, // TSC.
+    // The deadline has been missed; reset on the next pet.
+    fired: bool,
+}
+
+// pid -> watchdog.
+static WATCHDOGS: SpinLock<BTreeMap<u64, Watchdog>> = SpinLock::new(BTreeMap::new());
+// So that check() does not take the lock on every tick when there's nothing to check.
+static NUM_WATCHDOGS: AtomicUsize = AtomicUsize::new(0);
+
+pub(super) fn register(
+    process: &Arc<Process>,
+    timeout: Duration,
+    action: u64,
+) -> Result<(), ErrorCode> {
+    match action {
+        SysRay::WATCHDOG_ACTION_LOG
+        | SysRay::WATCHDOG_ACTION_KILL
+        | SysRay::WATCHDOG_ACTION_REBOOT => {}
+        _ => return Err(moto_rt::E_INVALID_ARGUMENT),
+    }
+    // Bounded so that last_pet + timeout below does not overflow.
+    if timeout.is_zero() || timeout > Duration::from_secs(SysRay::MAX_WATCHDOG_TIMEOUT_SECS) {
+        return Err(moto_rt::E_INVALID_ARGUMENT);
+    }
+
+    let mut watchdogs = WATCHDOGS.lock(line!());
+    watchdogs.insert(
+        process.pid().as_u64(),
+        Watchdog {
+            process: Arc::downgrade(process),
+            timeout,
+            action,
+            last_pet: Instant::now(),
+            fired: false,
+        },
+    );
+    NUM_WATCHDOGS.store(watchdogs.len(), Ordering::Relaxed);
+
+    log::info!(
+        "Process {} '{}' registered a watchdog: {:?}, action {}.",
+        process.pid().as_u64(),
+        process.debug_name(),
+        timeout,
+        action
+    );
+    Ok(())
+}
+
+pub(super) fn pet(process: &Process) -> Result<(), ErrorCode> {
+    let mut watchdogs = WATCHDOGS.lock(line!());
+    let watchdog = watchdogs
+        .get_mut(&process.pid().as_u64())
+        .ok_or(moto_rt::E_NOT_FOUND)?;
+    watchdog.last_pet = Instant::now();
+    watchdog.fired = false;
+    Ok(())
+}
+
+pub(super) fn unregister(process: &Process) -> Result<(), ErrorCode> {
+    let mut watchdogs = WATCHDOGS.lock(line!());
+    if watchdogs.remove(&process.pid().as_u64()).is_none() {
+        return Err(moto_rt::E_NOT_FOUND);
+    }
+    NUM_WATCHDOGS.store(watchdogs.len(), Ordering::Relaxed);
+    Ok(())
+}
+
+// Called from the scheduler on timer ticks.
+pub fn check() {
+    if NUM_WATCHDOGS.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+
+    let now = Instant::now();
+    let mut missed: Vec<(Arc<Process>, Duration, u64)> = Vec::new();
+    {
+        let mut watchdogs = WATCHDOGS.lock(line!());
+        watchdogs.retain(|_, watchdog| {
+            if watchdog.process.strong_count() == 0 {
+                return false; // The process is gone.
+            }
+            if watchdog.fired || (watchdog.last_pet + watchdog.timeout) > now {
+                return true;
+            }
+            // Upgraded only here so that the last Arc<Process> is not dropped under the lock.
+            let Some(process) = watchdog.process.upgrade() else {
+                return false;
+            };
+
+            watchdog.fired = true;
+            missed.push((
+                process,
+                now.duration_since(watchdog.last_pet),
+                watchdog.action,
+            ));
+            // Killed processes don't need watching anymore.
+            watchdog.action != SysRay::WATCHDOG_ACTION_KILL
+        });
+        NUM_WATCHDOGS.store(watchdogs.len(), Ordering::Relaxed);
+    }
+
+    // Act without holding the lock.
+    for (process, since_pet, action) in missed {
+        log::error!(
+            "Watchdog: process {} '{}' has not pet its watchdog for {:?}.",
+            process.pid().as_u64(),
+            process.debug_name(),
+            since_pet
+        );
+
+        match action {
+            SysRay::WATCHDOG_ACTION_KILL => {
+                log::error!("Watchdog: killing process {}.", process.pid().as_u64());
+                process.die();
+            }
+            SysRay::WATCHDOG_ACTION_REBOOT => {
+                log::error!("Watchdog: rebooting.");
+                crate::arch::kernel_reboot()
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,219 @@
+// The kernel entropy pool and CSPRNG behind SysMem::OP_RANDOM.
+//
+// Random bytes come from ChaCha20 with "fast key erasure": the key is replaced
+// from the generator's own output after every request, so earlier outputs
+// cannot be recovered from the current state. New entropy (RDSEED/RDRAND,
+// TSC jitter, and whatever sys-io feeds in from virtio-rng via
+// F_RANDOM_ADD_ENTROPY) is folded into a pending pool and mixed into the key
+// on reseed: periodically, and on the first read after a new process is created.
+//
+// The generator is seeded during boot, before userspace runs, so reads never
+// block; a read racing with boot seeds the generator itself first.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use super::SpinLock;
+use crate::arch::time::Instant;
+
+const RESEED_INTERVAL: Duration = Duration::from_secs(60);
+
+// How many TSC samples to take when seeding from jitter.
+const JITTER_SAMPLES: usize = 512;
+
+struct Csprng {
+    key: [u32; 8],
+    counter: u64,
+
+    // Entropy collected since the last reseed.
+    pool: [u32; 16],
+    pool_pos: usize,
+
+    last_reseed: Instant,
+}
+
+static CSPRNG: SpinLock<Csprng> = SpinLock::new(Csprng {
+    key: [0; 8],
+    counter: 0,
+    pool: [0; 16],
+    pool_pos: 0,
+    last_reseed: Instant::nan(),
+});
+
+static SEEDED: AtomicBool = AtomicBool::new(false);
+static RESEED_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[inline]
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+// ChaCha20 block function (RFC 8439), with a 64-bit counter and a 64-bit nonce.
+fn chacha20_block(key: &[u32; 8], counter: u64, nonce: u64) -> [u32; 16] {
+    let mut input = [0_u32; 16];
+    input[0..4].copy_from_slice(&[0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574]);
+    input[4..12].copy_from_slice(key);
+    input[12] = counter as u32;
+    input[13] = (counter >> 32) as u32;
+    input[14] = nonce as u32;
+    input[15] = (nonce >> 32) as u32;
+
+    let mut state = input;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    for (word, input) in state.iter_mut().zip(input.iter()) {
+        *word = word.wrapping_add(*input);
+    }
+    state
+}
+
+impl Csprng {
+    fn mix_u64(&mut self, val: u64) {
+        self.mix_u32(val as u32);
+        self.mix_u32((val >> 32) as u32);
+    }
+
+    fn mix_u32(&mut self, val: u32) {
+        let pos = self.pool_pos;
+        self.pool[pos] = self.pool[pos].rotate_left(7) ^ val;
+        self.pool_pos = (pos + 1) % self.pool.len();
+    }
+
+    fn mix_bytes(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(4) {
+            let mut word = [0_u8; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.mix_u32(u32::from_ne_bytes(word));
+        }
+    }
+
+    // Fresh entropy from the CPU: RDSEED (or RDRAND) if available, and TSC jitter.
+    fn collect(&mut self, jitter_samples: usize) {
+        let (has_rdrand, has_rdseed) = cpu_rng_support();
+        for _ in 0..8 {
+            let val = if has_rdseed {
+                moto_sys::rdseed()
+            } else if has_rdrand {
+                moto_sys::rdrand()
+            } else {
+                break;
+            };
+            if let Ok(val) = val {
+                self.mix_u64(val);
+            }
+        }
+
+        // Timing of a short busy loop varies with caches, interrupts, and
+        // the hypervisor; only the low bits of each delta are worth anything.
+        let mut prev = Instant::now().as_u64();
+        let mut acc = 0_u32;
+        for idx in 0..jitter_samples {
+            for _ in 0..(prev & 0x1f) {
+                core::hint::spin_loop();
+            }
+            let now = Instant::now().as_u64();
+            acc = acc.rotate_left(5) ^ ((now.wrapping_sub(prev)) as u32);
+            prev = now;
+            if idx % 4 == 3 {
+                self.mix_u32(acc);
+            }
+        }
+        self.mix_u64(prev);
+    }
+
+    fn reseed(&mut self) {
+        // Each half of the pool is mixed in by one ChaCha20 block.
+        for half in 0..2 {
+            let mut key = self.key;
+            for (word, pool) in key.iter_mut().zip(self.pool[(half * 8)..].iter()) {
+                *word ^= *pool;
+            }
+            let block = chacha20_block(&key, self.counter, u64::MAX);
+            self.key.copy_from_slice(&block[0..8]);
+            self.counter = self.counter.wrapping_add(1);
+        }
+
+        self.pool = [0; 16];
+        self.pool_pos = 0;
+        self.last_reseed = Instant::now();
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(64) {
+            let block = chacha20_block(&self.key, self.counter, 0);
+            self.counter = self.counter.wrapping_add(1);
+            for (idx, byte) in chunk.iter_mut().enumerate() {
+                *byte = (block[idx / 4] >> ((idx % 4) * 8)) as u8;
+            }
+        }
+
+        // Fast key erasure.
+        let block = chacha20_block(&self.key, self.counter, 0);
+        self.counter = self.counter.wrapping_add(1);
+        self.key.copy_from_slice(&block[0..8]);
+    }
+}
+
+// (RDRAND, RDSEED): executing either without CPU support faults.
+fn cpu_rng_support() -> (bool, bool) {
+    let leaf1 = unsafe { core::arch::x86_64::__cpuid(1) };
+    let leaf7 = unsafe { core::arch::x86_64::__cpuid_count(7, 0) };
+    ((leaf1.ecx & (1 << 30)) != 0, (leaf7.ebx & (1 << 18)) != 0)
+}
+
+fn seed(csprng: &mut Csprng) {
+    csprng.collect(JITTER_SAMPLES);
+    csprng.reseed();
+    SEEDED.store(true, Ordering::Release);
+}
+
+// Called once during boot.
+pub fn init() {
+    let mut csprng = CSPRNG.lock(line!());
+    if !SEEDED.load(Ordering::Acquire) {
+        seed(&mut csprng);
+    }
+}
+
+/// Fills @buf with random bytes.
+pub fn fill(buf: &mut [u8]) {
+    let mut csprng = CSPRNG.lock(line!());
+    if !SEEDED.load(Ordering::Acquire) {
+        seed(&mut csprng);
+    } else if RESEED_REQUESTED.swap(false, Ordering::Relaxed)
+        || Instant::now() > (csprng.last_reseed + RESEED_INTERVAL)
+    {
+        csprng.collect(JITTER_SAMPLES / 16);
+        csprng.reseed();
+    }
+
+    csprng.fill(buf);
+}
+
+/// Mixes @bytes, from an external entropy source, into the pool.
+pub fn add_entropy(bytes: &[u8]) {
+    let mut csprng = CSPRNG.lock(line!());
+    csprng.mix_bytes(bytes);
+    csprng.mix_u64(Instant::now().as_u64());
+}
+
+/// Called when a new process is created, so that the next read reseeds.
+pub fn on_new_process() {
+    RESEED_REQUESTED.store(true, Ordering::Relaxed);
+}
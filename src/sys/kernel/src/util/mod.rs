@@ -1,3 +1,4 @@
+pub mod entropy;
 pub mod loader;
 pub mod percpu;
 pub mod pin_weak;
@@ -34,9 +34,11 @@ impl<T> SpinLock<T> {
 }
 
 impl<T: ?Sized> SpinLock<T> {
-    fn obtain_lock(&self, lockword: u32) {
+    // Returns the number of spins (zero if the lock was not contended).
+    fn obtain_lock(&self, lockword: u32) -> u64 {
         assert_ne!(0, lockword);
         let mut outer_iter = 0_u64;
+        let mut spins = 0_u64;
         while self
             .lock_word
             .compare_exchange(0, lockword, Ordering::SeqCst, Ordering::Relaxed)
@@ -79,15 +81,21 @@ impl<T: ?Sized> SpinLock<T> {
                 }
                 core::hint::spin_loop();
             }
+            spins += inner_iter + 1;
         }
 
         #[cfg(debug_assertions)]
         self.lock_cpu
             .store(crate::arch::current_cpu() as u32, Ordering::Release);
+
+        spins
     }
 
     pub fn lock(&self, lockword: u32) -> LockGuard<'_, T> {
-        self.obtain_lock(lockword);
+        let spins = self.obtain_lock(lockword);
+        if crate::xray::lock_stats::enabled() {
+            crate::xray::lock_stats::record(lockword, spins);
+        }
         LockGuard {
             lock_word: &self.lock_word,
             data: unsafe { &mut *self.data.get() },
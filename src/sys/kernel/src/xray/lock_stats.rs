@@ -0,0 +1,108 @@
+// SpinLock contention stats: when enabled (CAP_SYS, via SysRay), every
+// SpinLock::lock() records an acquisition, and contended ones also the
+// number of spins, keyed by the lock word (the caller's line!()).
+// Disabled, the cost on the lock path is a single relaxed load.
+//
+// Sites live in a fixed open-addressed table that is never cleared: if it
+// fills up, acquisitions at new sites are only counted as dropped.
+use core::sync::atomic::*;
+
+use moto_sys::stats::LockSiteStats;
+
+use crate::config::LOCK_STATS_SITES;
+
+const _: () = assert!(LOCK_STATS_SITES.is_power_of_two());
+
+struct Site {
+    line: AtomicU32, // Zero: unused.
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
+    spins: AtomicU64,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SITES: [Site; LOCK_STATS_SITES] = [const {
+    Site {
+        line: AtomicU32::new(0),
+        acquisitions: AtomicU64::new(0),
+        contended: AtomicU64::new(0),
+        spins: AtomicU64::new(0),
+    }
+}; LOCK_STATS_SITES];
+static DROPPED_SITES: AtomicU64 = AtomicU64::new(0);
+
+#[inline(always)]
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Release);
+}
+
+fn find_or_insert(line: u32) -> Option<&'static Site> {
+    let mut slot = (line as usize).wrapping_mul(0x9E37_79B9) & (LOCK_STATS_SITES - 1);
+    for _ in 0..LOCK_STATS_SITES {
+        let site = &SITES[slot];
+        match site
+            .line
+            .compare_exchange(0, line, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => return Some(site),
+            Err(prev) if prev == line => return Some(site),
+            Err(_) => slot = (slot + 1) & (LOCK_STATS_SITES - 1),
+        }
+    }
+
+    None
+}
+
+// Called by SpinLock after the lock has been obtained. Must not take locks.
+pub fn record(line: u32, spins: u64) {
+    let Some(site) = find_or_insert(line) else {
+        DROPPED_SITES.fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+
+    site.acquisitions.fetch_add(1, Ordering::Relaxed);
+    if spins > 0 {
+        site.contended.fetch_add(1, Ordering::Relaxed);
+        site.spins.fetch_add(spins, Ordering::Relaxed);
+    }
+}
+
+/// Zeroes the counters of all sites.
+pub fn reset() {
+    for site in &SITES {
+        site.acquisitions.store(0, Ordering::Relaxed);
+        site.contended.store(0, Ordering::Relaxed);
+        site.spins.store(0, Ordering::Relaxed);
+    }
+    DROPPED_SITES.store(0, Ordering::Relaxed);
+}
+
+/// Copies stats of sites seen so far into @dst, most contended (by spins) first.
+/// Returns the number of sites copied and the number of acquisitions not
+/// recorded because the table was full.
+pub fn read(dst: &mut [LockSiteStats]) -> (usize, u64) {
+    let mut sites = alloc::vec::Vec::new();
+    for site in &SITES {
+        let line = site.line.load(Ordering::Acquire);
+        if line == 0 {
+            continue;
+        }
+        sites.push(LockSiteStats {
+            line,
+            _reserved: 0,
+            acquisitions: site.acquisitions.load(Ordering::Relaxed),
+            contended: site.contended.load(Ordering::Relaxed),
+            spins: site.spins.load(Ordering::Relaxed),
+        });
+    }
+
+    sites.sort_unstable_by(|a, b| b.spins.cmp(&a.spins));
+    let count = sites.len().min(dst.len());
+    dst[..count].copy_from_slice(&sites[..count]);
+
+    (count, DROPPED_SITES.load(Ordering::Relaxed))
+}
@@ -1,3 +1,7 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use log::LevelFilter;
+
 struct Logger {
     lock: crate::util::SpinLock<()>, // To unscramble concurrent log messages.
 }
@@ -6,9 +10,98 @@ static LOGGER: Logger = Logger {
     lock: crate::util::SpinLock::new(()),
 };
 
+// Recent log lines, for dmesg-like retrieval from the userspace.
+// A fixed-size array, so that logging never allocates.
+const LOG_RING_SIZE: usize = 64 * 1024;
+
+struct LogRing {
+    buf: [u8; LOG_RING_SIZE],
+    // Total number of bytes ever written; the ring holds the last
+    // LOG_RING_SIZE of them.
+    written: u64,
+}
+
+impl LogRing {
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.buf[(self.written % (LOG_RING_SIZE as u64)) as usize] = *b;
+            self.written += 1;
+        }
+    }
+
+    fn oldest(&self) -> u64 {
+        self.written.saturating_sub(LOG_RING_SIZE as u64)
+    }
+
+    fn byte_at(&self, pos: u64) -> u8 {
+        self.buf[(pos % (LOG_RING_SIZE as u64)) as usize]
+    }
+}
+
+impl core::fmt::Write for LogRing {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.push_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+static LOG_RING: crate::util::SpinLock<LogRing> = crate::util::SpinLock::new(LogRing {
+    buf: [0; LOG_RING_SIZE],
+    written: 0,
+});
+
+// Per-subsystem level filters: a subsystem is a log target prefix
+// (e.g. "kernel::mm"); the longest matching prefix wins.
+pub const MAX_LEVEL_FILTERS: usize = 32;
+pub const MAX_SUBSYSTEM_LEN: usize = 64;
+
+struct LevelFilters {
+    default_level: LevelFilter,
+    filters: Vec<(String, LevelFilter)>,
+}
+
+impl LevelFilters {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        let mut result = self.default_level;
+        let mut matched_len = 0;
+        for (prefix, level) in &self.filters {
+            if prefix.len() >= matched_len && target.starts_with(prefix.as_str()) {
+                matched_len = prefix.len();
+                result = *level;
+            }
+        }
+        result
+    }
+
+    // The log crate discards anything above max_level before calling
+    // into the logger, so it must cover the most verbose filter.
+    fn update_max_level(&self) {
+        let mut max_level = self.default_level;
+        for (_, level) in &self.filters {
+            max_level = max_level.max(*level);
+        }
+        log::set_max_level(max_level);
+    }
+}
+
+static LEVEL_FILTERS: crate::util::SpinLock<LevelFilters> =
+    crate::util::SpinLock::new(LevelFilters {
+        default_level: LevelFilter::Info,
+        filters: Vec::new(),
+    });
+
+fn write_line(args: core::fmt::Arguments) {
+    crate::arch::arch_write_serial!("{}\n\r", args);
+
+    use core::fmt::Write;
+    let mut ring = LOG_RING.lock(line!());
+    let _ = ring.write_fmt(args);
+    ring.push_bytes(b"\n");
+}
+
 impl log::Log for Logger {
-    fn enabled(&self, _metadata: &log::Metadata) -> bool {
-        true
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= LEVEL_FILTERS.lock(line!()).level_for(metadata.target())
     }
 
     fn log(&self, record: &log::Record) {
@@ -26,8 +119,8 @@ impl log::Log for Logger {
 
             let cpu = crate::arch::current_cpu();
 
-            crate::arch::arch_write_serial!(
-                "{:3}:{:03} {:2}: {:6} {}:{} - {}\n\r",
+            write_line(format_args!(
+                "{:3}:{:03} {:2}: {:6} {}:{} - {}",
                 secs,
                 millis,
                 cpu,
@@ -35,7 +128,7 @@ impl log::Log for Logger {
                 target,
                 line,
                 record.args()
-            );
+            ));
         }
     }
 
@@ -43,9 +136,13 @@ impl log::Log for Logger {
 }
 
 pub fn log_user(thread: &crate::uspace::process::Thread, msg: &str) {
+    log_user_as(thread.debug_name().as_str(), msg)
+}
+
+// As log_user(), for when the thread may be gone: @thr is its debug_name().
+pub fn log_user_as(thr: &str, msg: &str) {
     let _lock = LOGGER.lock.lock(line!());
 
-    let thr = thread.debug_name();
     let tm = crate::arch::time::system_start_time().elapsed();
     let millis = tm.as_millis();
     let secs = millis / 1000;
@@ -53,15 +150,92 @@ pub fn log_user(thread: &crate::uspace::process::Thread, msg: &str) {
 
     let cpu = crate::arch::current_cpu();
 
-    crate::arch::arch_write_serial!(
-        "{:3}:{:03} {:2}: {:6} {}: {}\n\r",
-        secs,
-        millis,
-        cpu,
-        "USER",
-        thr,
-        msg
-    );
+    write_line(format_args!(
+        "{:3}:{:03} {:2}: {:6} {}: {}",
+        secs, millis, cpu, "USER", thr, msg
+    ));
+}
+
+/// Sets the log level of targets starting with @subsystem, or the default
+/// level if @subsystem is None. LevelFilter::Off for a subsystem removes
+/// its filter. Returns the previous level.
+pub fn set_level(
+    subsystem: Option<&str>,
+    level: LevelFilter,
+) -> Result<LevelFilter, moto_rt::ErrorCode> {
+    let mut filters = LEVEL_FILTERS.lock(line!());
+
+    let prev = match subsystem {
+        None => core::mem::replace(&mut filters.default_level, level),
+        Some(subsystem) => {
+            if subsystem.is_empty() || subsystem.len() > MAX_SUBSYSTEM_LEN {
+                return Err(moto_rt::E_INVALID_ARGUMENT);
+            }
+
+            let idx = filters
+                .filters
+                .iter()
+                .position(|(prefix, _)| prefix.as_str() == subsystem);
+            match (idx, level) {
+                (Some(idx), LevelFilter::Off) => filters.filters.swap_remove(idx).1,
+                (Some(idx), level) => core::mem::replace(&mut filters.filters[idx].1, level),
+                (None, LevelFilter::Off) => LevelFilter::Off,
+                (None, level) => {
+                    if filters.filters.len() >= MAX_LEVEL_FILTERS {
+                        return Err(moto_rt::E_OUT_OF_MEMORY);
+                    }
+                    filters.filters.push((String::from(subsystem), level));
+                    LevelFilter::Off
+                }
+            }
+        }
+    };
+
+    filters.update_max_level();
+    Ok(prev)
+}
+
+pub fn default_level() -> LevelFilter {
+    LEVEL_FILTERS.lock(line!()).default_level
+}
+
+/// Copies whole log lines starting at byte offset @from (in the stream of
+/// all bytes ever logged) into @dst. If @from is older than what the ring
+/// still holds, copying starts at the oldest complete line. Returns the
+/// number of bytes copied and the offset to continue from.
+pub fn read_log(from: u64, dst: &mut [u8]) -> (usize, u64) {
+    let ring = LOG_RING.lock(line!());
+
+    let mut start = from.min(ring.written);
+    if start < ring.oldest() {
+        // Skip the partially overwritten line.
+        start = ring.oldest();
+        while start < ring.written && ring.byte_at(start) != b'\n' {
+            start += 1;
+        }
+        if start < ring.written {
+            start += 1;
+        }
+    }
+
+    let available = (ring.written - start) as usize;
+    let mut len = available.min(dst.len());
+    if len < available {
+        // Only whole lines, unless a single line does not fit.
+        let mut end = len;
+        while end > 0 && ring.byte_at(start + (end as u64) - 1) != b'\n' {
+            end -= 1;
+        }
+        if end > 0 {
+            len = end;
+        }
+    }
+
+    for idx in 0..len {
+        dst[idx] = ring.byte_at(start + (idx as u64));
+    }
+
+    (len, start + (len as u64))
 }
 
 // Initializes the logger from crate log.
@@ -70,9 +244,9 @@ pub fn init_logging() {
     assert!(log::set_logger(&LOGGER).is_ok());
 
     #[cfg(debug_assertions)]
-    log::set_max_level(log::LevelFilter::Debug);
+    set_level(None, LevelFilter::Debug).unwrap();
     #[cfg(not(debug_assertions))]
-    log::set_max_level(log::LevelFilter::Info);
+    set_level(None, LevelFilter::Info).unwrap();
 
     // NOTE: init.rs sets max log level to INFO before starting
     // the userspace, as otherwise the kernel spams the console too much.
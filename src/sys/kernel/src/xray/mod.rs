@@ -1,3 +1,5 @@
+pub mod lock_stats;
 pub mod logger;
+pub mod sampler;
 pub mod stats;
 pub mod tracing;
@@ -0,0 +1,169 @@
+// CPU sampling: every SysRay::CPU_SAMPLE_PERIOD, every CPU records what it is
+// running (pid + tid) into its ring buffer, for statistical whole-system profiles.
+// Samples are taken in timer IRQs; while sampling, the scheduler programs the
+// timer for the next sample if it comes before the next tick (see
+// sched::on_timer_irq()), so the sampling rate does not depend on the time slice.
+// Samples in the kernel context (idle, IRQs, syscalls) are recorded as
+// (PID_KERNEL, 0). Samplers (CAP_SYS) read the rings via SysRay.
+use core::sync::atomic::*;
+
+use alloc::boxed::Box;
+use moto_sys::stats::CpuSample;
+
+use crate::arch::time::Instant;
+use crate::config::{uCpus, CPU_SAMPLE_BUFFER_SIZE};
+use crate::util::{StaticPerCpu, StaticRef};
+
+const _: () = assert!(CPU_SAMPLE_BUFFER_SIZE.is_power_of_two());
+
+struct SampleBuffer {
+    // Only the owning CPU writes to the buffer, so this is not a fetch_add.
+    next_sample: AtomicU64,
+    next_sample_at: AtomicU64, // Instant. Also only used by the owning CPU.
+    pids: [AtomicU64; CPU_SAMPLE_BUFFER_SIZE],
+    tids: [AtomicU64; CPU_SAMPLE_BUFFER_SIZE],
+}
+
+impl SampleBuffer {
+    fn new() -> &'static mut Self {
+        Box::leak(Box::new(SampleBuffer {
+            next_sample: AtomicU64::new(0),
+            next_sample_at: AtomicU64::new(0),
+            pids: [const { AtomicU64::new(0) }; CPU_SAMPLE_BUFFER_SIZE],
+            tids: [const { AtomicU64::new(0) }; CPU_SAMPLE_BUFFER_SIZE],
+        }))
+    }
+
+    fn add_sample(&self, pid: u64, tid: u64) {
+        let idx = self.next_sample.load(Ordering::Relaxed);
+        let slot = (idx as usize) & (CPU_SAMPLE_BUFFER_SIZE - 1);
+        self.pids[slot].store(pid, Ordering::Relaxed);
+        self.tids[slot].store(tid, Ordering::Relaxed);
+        self.next_sample.store(idx + 1, Ordering::Release);
+    }
+
+    // Samples being overwritten while read are skipped.
+    fn read(&self, from: u64, dst: &mut [CpuSample]) -> (usize, u64) {
+        let next = self.next_sample.load(Ordering::Acquire);
+        let oldest = next.saturating_sub(CPU_SAMPLE_BUFFER_SIZE as u64);
+        let start = from.max(oldest).min(next);
+
+        let count = ((next - start) as usize).min(dst.len());
+        for idx in 0..count {
+            let slot = ((start + idx as u64) as usize) & (CPU_SAMPLE_BUFFER_SIZE - 1);
+            dst[idx] = CpuSample {
+                pid: self.pids[slot].load(Ordering::Relaxed),
+                tid: self.tids[slot].load(Ordering::Relaxed),
+            };
+        }
+
+        // A sample read above may have been overwritten by the owning CPU.
+        let overwritten = self
+            .next_sample
+            .load(Ordering::Acquire)
+            .saturating_sub(CPU_SAMPLE_BUFFER_SIZE as u64)
+            .saturating_sub(start) as usize;
+        if overwritten >= count {
+            return (0, start + count as u64);
+        }
+        if overwritten > 0 {
+            dst.copy_within(overwritten..count, 0);
+        }
+
+        (count - overwritten, start + count as u64)
+    }
+}
+
+struct Sampler {
+    sampling: AtomicBool,
+    buffers: StaticPerCpu<SampleBuffer>,
+}
+
+static SAMPLER: StaticRef<Sampler> = StaticRef::default_const();
+
+// Called on every timer IRQ; takes a sample if one is due.
+// Note: may be called from IRQ.
+#[inline]
+pub fn sample(pid: u64, tid: u64) {
+    let Some(sampler) = SAMPLER.get() else {
+        return;
+    };
+    if !sampler.sampling.load(Ordering::Relaxed) {
+        return;
+    }
+
+    // Buffers are allocated in start(), not here, as we may be in an IRQ.
+    if let Some(buffer) = sampler.buffers.get() {
+        let now = Instant::now();
+        if now < Instant::from_u64(buffer.next_sample_at.load(Ordering::Relaxed)) {
+            return;
+        }
+        buffer.add_sample(pid, tid);
+        buffer.next_sample_at.store(
+            (now + moto_sys::SysRay::CPU_SAMPLE_PERIOD).as_u64(),
+            Ordering::Relaxed,
+        );
+    }
+}
+
+// When the current CPU takes its next sample, if sampling.
+pub fn next_sample() -> Option<Instant> {
+    let sampler = SAMPLER.get()?;
+    if !sampler.sampling.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    sampler
+        .buffers
+        .get()
+        .map(|buffer| Instant::from_u64(buffer.next_sample_at.load(Ordering::Relaxed)))
+}
+
+pub fn sample_kernel() {
+    sample(moto_sys::stats::PID_KERNEL, 0);
+}
+
+pub fn start() {
+    static STARTING: crate::util::SpinLock<()> = crate::util::SpinLock::new(());
+    let _lock = STARTING.lock(line!());
+
+    if !SAMPLER.is_set() {
+        let sampler = Box::leak(Box::new(Sampler {
+            sampling: AtomicBool::new(false),
+            buffers: StaticPerCpu::new(),
+        }));
+        for cpu in 0..crate::arch::num_cpus() {
+            sampler.buffers.set_for_cpu(cpu, SampleBuffer::new());
+        }
+        SAMPLER.set(sampler);
+    }
+
+    SAMPLER
+        .get()
+        .unwrap()
+        .sampling
+        .store(true, Ordering::Release);
+}
+
+pub fn stop() {
+    if let Some(sampler) = SAMPLER.get() {
+        sampler.sampling.store(false, Ordering::Release);
+    }
+}
+
+/// Copies samples of @cpu, starting at sample number @from, into @dst.
+/// Returns the number of samples copied and the sample number to read from next.
+pub fn read(
+    cpu: uCpus,
+    from: u64,
+    dst: &mut [CpuSample],
+) -> Result<(usize, u64), moto_rt::ErrorCode> {
+    if cpu >= crate::arch::num_cpus() {
+        return Err(moto_rt::E_INVALID_ARGUMENT);
+    }
+
+    match SAMPLER.get() {
+        Some(sampler) => Ok(sampler.buffers.get_for_cpu(cpu).read(from, dst)),
+        None => Err(moto_rt::E_NOT_READY),
+    }
+}
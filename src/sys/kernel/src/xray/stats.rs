@@ -22,7 +22,13 @@ use moto_sys::stats::*;
 
 #[derive(Debug)]
 pub struct MemStats {
-    pages_used: AtomicU64,
+    pages_used: AtomicU64,  // Resident: backed by physical pages.
+    peak_pages: AtomicU64,  // The high-water mark of pages_used.
+    page_faults: AtomicU64, // Pages of lazy mappings populated on first access.
+    // User only: pages charged against the process's memory limit, including
+    // pages of lazy mappings that have not been touched yet, so usually more
+    // than pages_used.
+    pages_committed: AtomicU64,
     user_stats: bool,
 }
 
@@ -39,6 +45,9 @@ impl MemStats {
     const fn new(user_stats: bool) -> Self {
         Self {
             pages_used: AtomicU64::new(0),
+            peak_pages: AtomicU64::new(0),
+            page_faults: AtomicU64::new(0),
+            pages_committed: AtomicU64::new(0),
             user_stats,
         }
     }
@@ -60,16 +69,86 @@ impl MemStats {
 
         if self.user_stats {
             SYSTEM_STATS.mem_stats_user.add_simple(num_pages);
+            check_memory_pressure_up();
         } else {
             if core::intrinsics::likely(SYSTEM_STATS.is_set()) {
                 SYSTEM_STATS.mem_stats_kernel.add_simple(num_pages);
+                check_memory_pressure_up();
             }
         }
     }
 
+    // A page of a lazy (demand-zero) mapping was populated on first access.
+    // All such faults are minor: there is no swap and no file-backed memory.
+    pub fn add_page_fault(&self) {
+        self.page_faults.fetch_add(1, Ordering::Relaxed);
+        if self.user_stats {
+            SYSTEM_STATS
+                .mem_stats_user
+                .page_faults
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        self.add(1);
+    }
+
+    pub fn page_faults(&self) -> u64 {
+        self.page_faults.load(Ordering::Relaxed)
+    }
+
+    // See KProcessStats::reset_counters().
+    fn reset_page_faults(&self) {
+        self.page_faults.store(0, Ordering::Relaxed);
+    }
+
+    // Called when memory is charged against the process's limit: lazy mappings
+    // are committed in full when created, and become resident page by page as
+    // they are touched (see add_page_fault()).
+    pub fn add_committed(&self, num_pages: u64) {
+        self.pages_committed.fetch_add(num_pages, Ordering::Relaxed);
+        if self.user_stats {
+            SYSTEM_STATS
+                .mem_stats_user
+                .pages_committed
+                .fetch_add(num_pages, Ordering::Relaxed);
+        }
+    }
+
+    pub fn sub_committed(&self, num_pages: u64) {
+        self.pages_committed.fetch_sub(num_pages, Ordering::Relaxed);
+        if self.user_stats {
+            SYSTEM_STATS
+                .mem_stats_user
+                .pages_committed
+                .fetch_sub(num_pages, Ordering::Relaxed);
+        }
+    }
+
+    /// The most memory ever used, in bytes.
+    pub fn peak(&self) -> u64 {
+        self.peak_pages() << PAGE_SIZE_SMALL_LOG2
+    }
+
+    pub fn peak_pages(&self) -> u64 {
+        self.peak_pages.load(Ordering::Relaxed)
+    }
+
     #[inline]
     fn add_simple(&self, num_pages: u64) {
-        self.pages_used.fetch_add(num_pages, Ordering::Relaxed);
+        let used = self.pages_used.fetch_add(num_pages, Ordering::Relaxed) + num_pages;
+
+        // Racy vs concurrent add/sub, but the peak is always a value pages_used had.
+        let mut peak = self.peak_pages.load(Ordering::Relaxed);
+        while used > peak {
+            match self.peak_pages.compare_exchange_weak(
+                peak,
+                used,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(prev) => peak = prev,
+            }
+        }
     }
 
     pub fn sub(&self, num_pages: u64) {
@@ -77,9 +156,11 @@ impl MemStats {
 
         if self.user_stats {
             SYSTEM_STATS.mem_stats_user.sub_simple(num_pages);
+            check_memory_pressure_down();
         } else {
             if core::intrinsics::likely(SYSTEM_STATS.is_set()) {
                 SYSTEM_STATS.mem_stats_kernel.sub_simple(num_pages);
+                check_memory_pressure_down();
             }
         }
     }
@@ -90,13 +171,130 @@ impl MemStats {
     }
 }
 
+// Memory pressure (see SysMem::OP_PRESSURE): the system comes under pressure
+// when its used memory (user + kernel) grows to the high watermark, and stays
+// under pressure until it shrinks to the low watermark, so that usage hovering
+// around one watermark does not flip the state back and forth. Each flip wakes
+// MEMORY_PRESSURE_EVENT. The watermarks are in pages; disabled by default.
+static PRESSURE_HIGH_PAGES: AtomicU64 = AtomicU64::new(u64::MAX);
+static PRESSURE_LOW_PAGES: AtomicU64 = AtomicU64::new(0);
+static UNDER_PRESSURE: AtomicBool = AtomicBool::new(false);
+static MEMORY_PRESSURE_EVENT: StaticRef<Arc<crate::uspace::SysObject>> = StaticRef::default_const();
+
+fn system_pages_used() -> u64 {
+    SYSTEM_STATS
+        .mem_stats_user
+        .pages_used
+        .load(Ordering::Relaxed)
+        + SYSTEM_STATS
+            .mem_stats_kernel
+            .pages_used
+            .load(Ordering::Relaxed)
+}
+
+// Called on every page accounted, so the common case is a couple of loads.
+#[inline]
+fn check_memory_pressure_up() {
+    if core::intrinsics::likely(system_pages_used() < PRESSURE_HIGH_PAGES.load(Ordering::Relaxed)) {
+        return;
+    }
+    if !UNDER_PRESSURE.load(Ordering::Relaxed) {
+        set_memory_pressure(true);
+    }
+}
+
+#[inline]
+fn check_memory_pressure_down() {
+    if core::intrinsics::likely(!UNDER_PRESSURE.load(Ordering::Relaxed)) {
+        return;
+    }
+    if system_pages_used() <= PRESSURE_LOW_PAGES.load(Ordering::Relaxed) {
+        set_memory_pressure(false);
+    }
+}
+
+// May be called with any locks held, including the allocator's, and so
+// must not lock or allocate: the waiters are woken asynchronously.
+#[cold]
+fn set_memory_pressure(under_pressure: bool) {
+    if UNDER_PRESSURE
+        .compare_exchange(
+            !under_pressure,
+            under_pressure,
+            Ordering::AcqRel,
+            Ordering::Relaxed,
+        )
+        .is_ok()
+        && MEMORY_PRESSURE_EVENT.is_set()
+    {
+        crate::uspace::SysObject::wake_irq(&MEMORY_PRESSURE_EVENT);
+    }
+}
+
+/// Sets the memory pressure watermarks, in bytes; u64::MAX as @high disables
+/// the notifications (and clears the pressure state). Requires low < high
+/// (in pages), so that there is hysteresis.
+pub fn set_memory_pressure_thresholds(high: u64, low: u64) -> Result<(), moto_rt::ErrorCode> {
+    if high == u64::MAX {
+        PRESSURE_HIGH_PAGES.store(u64::MAX, Ordering::Relaxed);
+        PRESSURE_LOW_PAGES.store(0, Ordering::Relaxed);
+        set_memory_pressure(false);
+        return Ok(());
+    }
+
+    let high = high >> PAGE_SIZE_SMALL_LOG2;
+    let low = low >> PAGE_SIZE_SMALL_LOG2;
+    if low >= high {
+        return Err(moto_rt::E_INVALID_ARGUMENT);
+    }
+
+    // So that a concurrent check never sees low at or above high: when the
+    // watermarks go up, the new low may be above the old high, so high is
+    // raised first; when they go down, low is lowered first.
+    if high >= PRESSURE_HIGH_PAGES.load(Ordering::Relaxed) {
+        PRESSURE_HIGH_PAGES.store(high, Ordering::Relaxed);
+        PRESSURE_LOW_PAGES.store(low, Ordering::Relaxed);
+    } else {
+        PRESSURE_LOW_PAGES.store(low, Ordering::Relaxed);
+        PRESSURE_HIGH_PAGES.store(high, Ordering::Relaxed);
+    }
+
+    // Re-evaluate the state against the new watermarks.
+    check_memory_pressure_up();
+    check_memory_pressure_down();
+    Ok(())
+}
+
+/// Returns (under pressure, high, low), the watermarks in bytes (u64::MAX, 0 if disabled).
+pub fn memory_pressure() -> (bool, u64, u64) {
+    let high = PRESSURE_HIGH_PAGES.load(Ordering::Relaxed);
+    let low = PRESSURE_LOW_PAGES.load(Ordering::Relaxed);
+    (
+        UNDER_PRESSURE.load(Ordering::Relaxed),
+        if high == u64::MAX {
+            u64::MAX
+        } else {
+            high << PAGE_SIZE_SMALL_LOG2
+        },
+        low << PAGE_SIZE_SMALL_LOG2,
+    )
+}
+
+/// The object woken whenever the system comes under, or gets out of, memory pressure.
+pub fn memory_pressure_event() -> Arc<crate::uspace::SysObject> {
+    MEMORY_PRESSURE_EVENT.clone()
+}
+
 #[repr(C, align(64))]
 pub struct PerCpuStatsEntry {
-    pub cpu_kernel: AtomicU64, // as TSC
-    pub cpu_uspace: AtomicU64, // as TSC
-    pub started_k: AtomicU64,  // if running, indicates when cpu_kernel started, otherwise zero
-    pub started_u: AtomicU64,  // if running, indicates when cpu_uspace started, otherwise zero
-    _pad: [u64; 4],
+    pub cpu_kernel: AtomicU64,     // as TSC
+    pub cpu_uspace: AtomicU64,     // as TSC
+    pub started_k: AtomicU64,      // if running, indicates when cpu_kernel started, otherwise zero
+    pub started_u: AtomicU64,      // if running, indicates when cpu_uspace started, otherwise zero
+    pub cpu_idle: AtomicU64,       // as TSC; see add_cpu_idle(); only tracked in SYSTEM_STATS
+    pub migrations_in: AtomicU64,  // threads that moved onto this CPU from another one
+    pub migrations_out: AtomicU64, // threads that moved from this CPU to another one
+    _pad: [u64; 1],
 }
 
 const _: () = assert!(64 == core::mem::size_of::<PerCpuStatsEntry>());
@@ -108,7 +306,10 @@ impl PerCpuStatsEntry {
             cpu_kernel: AtomicU64::new(0),
             started_k: AtomicU64::new(0),
             started_u: AtomicU64::new(0),
-            _pad: [0; 4],
+            cpu_idle: AtomicU64::new(0),
+            migrations_in: AtomicU64::new(0),
+            migrations_out: AtomicU64::new(0),
+            _pad: [0; 1],
         }
     }
 
@@ -149,13 +350,40 @@ impl PerCpuStats {
     }
 }
 
+// The CPU usage of a thread, in TSC (see KProcessStats::iterate_threads()).
+// Updated when a uspace or kernel interval of the thread stops, so an
+// interval in progress is not included.
+#[derive(Default)]
+pub struct ThreadCpuStats {
+    cpu_uspace: AtomicU64,
+    cpu_kernel: AtomicU64,
+}
+
+impl ThreadCpuStats {
+    pub fn add_uspace(&self, elapsed: u64) {
+        self.cpu_uspace.fetch_add(elapsed, Ordering::Relaxed);
+    }
+
+    pub fn add_kernel(&self, elapsed: u64) {
+        self.cpu_kernel.fetch_add(elapsed, Ordering::Relaxed);
+    }
+
+    // (uspace, kernel).
+    pub fn get(&self) -> (u64, u64) {
+        (
+            self.cpu_uspace.load(Ordering::Relaxed),
+            self.cpu_kernel.load(Ordering::Relaxed),
+        )
+    }
+}
+
 pub struct CpuUsageScopeKernel {
     stats: Arc<KProcessStats>,
 }
 
 impl Drop for CpuUsageScopeKernel {
     fn drop(&mut self) {
-        self.stats.stop_cpu_usage_kernel()
+        self.stats.stop_cpu_usage_kernel();
     }
 }
 
@@ -176,6 +404,36 @@ pub struct KProcessStats {
     pub owner: Weak<crate::uspace::Process>,
 
     per_cpu_stats: PerCpuStats,
+
+    // Live threads, by TID. When a thread exits, its CPU usage is moved to
+    // reaped_cpu_*, so that the per-thread numbers still add up to (at most)
+    // the process total in per_cpu_stats, which also has kernel work not done
+    // on behalf of any thread.
+    threads: SpinLock<BTreeMap<u64, Arc<ThreadCpuStats>>>,
+    reaped_cpu_uspace: AtomicU64,
+    reaped_cpu_kernel: AtomicU64,
+
+    // Woken (and marked done) when self is dropped, i.e. when the process is fully gone.
+    drop_completion: SpinLock<Option<Arc<crate::uspace::SysObject>>>,
+
+    // Command line and environment, set by the spawner. See SysRay::OP_CMDLINE.
+    cmdline: SpinLock<Option<Arc<alloc::vec::Vec<u8>>>>,
+
+    // CPU time limit, in TSC; zero => no limit. See SysRay::OP_CPU_LIMIT.
+    cpu_limit: AtomicU64,
+    // CPU time charged against cpu_limit; starts at cpu_usage() when the limit is set.
+    cpu_limit_used: AtomicU64,
+    cpu_limit_action: AtomicU64,
+    cpu_limit_exceeded: AtomicBool,
+
+    // Scheduling latency: the time threads spend runnable but waiting
+    // in a ready queue, in TSC. See SysRay::F_QUERY_SCHED_LATENCY.
+    sched_latency_total: AtomicU64,
+    sched_latency_max: AtomicU64,
+    sched_latency_count: AtomicU64,
+
+    // The number of times a thread of the process was switched to.
+    context_switches: AtomicU64,
 }
 
 impl Drop for KProcessStats {
@@ -190,6 +448,11 @@ impl Drop for KProcessStats {
             .lock(line!())
             .remove(&self.pid)
             .is_some());
+
+        if let Some(completion) = self.drop_completion.lock(line!()).take() {
+            completion.mark_done();
+            completion.wake(false);
+        }
     }
 }
 
@@ -234,6 +497,19 @@ impl KProcessStats {
             mem_stats_kernel,
             owner,
             per_cpu_stats: PerCpuStats::new(),
+            threads: SpinLock::new(BTreeMap::new()),
+            reaped_cpu_uspace: AtomicU64::new(0),
+            reaped_cpu_kernel: AtomicU64::new(0),
+            drop_completion: SpinLock::new(None),
+            cmdline: SpinLock::new(None),
+            cpu_limit: AtomicU64::new(0),
+            cpu_limit_used: AtomicU64::new(0),
+            cpu_limit_action: AtomicU64::new(0),
+            cpu_limit_exceeded: AtomicBool::new(false),
+            sched_latency_total: AtomicU64::new(0),
+            sched_latency_max: AtomicU64::new(0),
+            sched_latency_count: AtomicU64::new(0),
+            context_switches: AtomicU64::new(0),
         });
 
         match self_.parent.as_ref() {
@@ -287,10 +563,120 @@ impl KProcessStats {
         }
     }
 
+    // Returns an object that will be woken when self is dropped.
+    pub fn drop_completion(&self) -> Arc<crate::uspace::SysObject> {
+        let mut lock = self.drop_completion.lock(line!());
+        if let Some(completion) = lock.as_ref() {
+            return completion.clone();
+        }
+
+        let completion = crate::uspace::SysObject::new(Arc::new(alloc::format!(
+            "process_stats_dropped:{}",
+            self.pid.as_u64()
+        )));
+        *lock = Some(completion.clone());
+        completion
+    }
+
+    // Can be set only once.
+    pub fn set_cmdline(&self, cmdline: alloc::vec::Vec<u8>) -> Result<(), moto_rt::ErrorCode> {
+        let mut lock = self.cmdline.lock(line!());
+        if lock.is_some() {
+            return Err(moto_rt::E_ALREADY_IN_USE);
+        }
+        *lock = Some(Arc::new(cmdline));
+        Ok(())
+    }
+
+    pub fn cmdline(&self) -> Option<Arc<alloc::vec::Vec<u8>>> {
+        self.cmdline.lock(line!()).clone()
+    }
+
+    // Can be set only once.
+    pub fn set_cpu_limit(&self, limit: u64, action: u64) -> Result<(), moto_rt::ErrorCode> {
+        assert_ne!(limit, 0);
+        assert_ne!(action, 0);
+
+        // A non-zero action claims the limit; the limit itself is published last,
+        // as charge_cpu_limit() starts using action and used once it is non-zero.
+        if self
+            .cpu_limit_action
+            .compare_exchange(0, action, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(moto_rt::E_ALREADY_IN_USE);
+        }
+
+        let now = crate::arch::time::Instant::now().as_u64();
+        self.cpu_limit_used
+            .store(self.cpu_usage(now), Ordering::Relaxed);
+        self.cpu_limit.store(limit, Ordering::Release);
+
+        // The process may have already used more than the limit.
+        self.charge_cpu_limit(0);
+        Ok(())
+    }
+
+    // Returns (limit, remaining), in TSC.
+    pub fn cpu_limit(&self) -> (u64, u64) {
+        let limit = self.cpu_limit.load(Ordering::Acquire);
+        if limit == 0 {
+            return (0, 0);
+        }
+
+        let used = self.cpu_limit_used.load(Ordering::Relaxed);
+        (limit, limit.saturating_sub(used))
+    }
+
+    pub fn cpu_limit_action(&self) -> u64 {
+        self.cpu_limit_action.load(Ordering::Relaxed)
+    }
+
+    // Called when a thread of this process stops using a CPU.
+    #[inline]
+    pub fn charge_cpu_limit(&self, elapsed: u64) {
+        let limit = self.cpu_limit.load(Ordering::Acquire);
+        if limit == 0 {
+            return;
+        }
+
+        let used = self.cpu_limit_used.fetch_add(elapsed, Ordering::Relaxed) + elapsed;
+        if used >= limit && !self.cpu_limit_exceeded.swap(true, Ordering::Relaxed) {
+            crate::uspace::process::post_cpu_limit_exceeded(self.pid.as_u64());
+        }
+    }
+
+    // Called by the scheduler when it picks a job of a thread of this process.
+    #[inline]
+    pub fn add_sched_latency(&self, latency: u64) {
+        self.sched_latency_total
+            .fetch_add(latency, Ordering::Relaxed);
+        self.sched_latency_max.fetch_max(latency, Ordering::Relaxed);
+        self.sched_latency_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn sched_latency(&self) -> moto_sys::stats::SchedLatencyStats {
+        moto_sys::stats::SchedLatencyStats {
+            total: self.sched_latency_total.load(Ordering::Relaxed),
+            max: self.sched_latency_max.load(Ordering::Relaxed),
+            count: self.sched_latency_count.load(Ordering::Relaxed),
+        }
+    }
+
     pub fn active_threads(&self) -> u64 {
         self.active_threads.load(Ordering::Relaxed)
     }
 
+    /// Physical pages used by the process, user and kernel.
+    pub fn resident_pages(&self) -> u64 {
+        self.mem_stats_user.pages_used.load(Ordering::Relaxed)
+            + self.mem_stats_kernel.pages_used.load(Ordering::Relaxed)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
     pub fn debug_name(&self) -> &str {
         &self.debug_name.as_str()
     }
@@ -303,18 +689,49 @@ impl KProcessStats {
         self.pid
     }
 
-    pub fn on_thread_added(&self) {
+    pub fn on_thread_added(&self, tid: u64, cpu_stats: Arc<ThreadCpuStats>) {
+        assert!(self.threads.lock(line!()).insert(tid, cpu_stats).is_none());
         self.active_threads.fetch_add(1, Ordering::Relaxed);
         self.total_threads.fetch_add(1, Ordering::Relaxed);
         SYSTEM_STATS.active_threads.fetch_add(1, Ordering::Relaxed);
         SYSTEM_STATS.total_threads.fetch_add(1, Ordering::Relaxed);
     }
 
-    pub fn on_thread_exited(&self) {
+    pub fn on_thread_exited(&self, tid: u64) {
+        // CPU time the thread uses after this point (e.g. cleanup in the kernel)
+        // is only in the process total.
+        if let Some(cpu_stats) = self.threads.lock(line!()).remove(&tid) {
+            let (uspace, kernel) = cpu_stats.get();
+            self.reaped_cpu_uspace.fetch_add(uspace, Ordering::Relaxed);
+            self.reaped_cpu_kernel.fetch_add(kernel, Ordering::Relaxed);
+        }
         self.active_threads.fetch_sub(1, Ordering::Relaxed);
         SYSTEM_STATS.active_threads.fetch_sub(1, Ordering::Relaxed);
     }
 
+    /// Calls @func with (tid, uspace, kernel) CPU usage, in TSC, for each live
+    /// thread, in TID order, until it returns false.
+    pub fn iterate_threads<F>(&self, mut func: F)
+    where
+        F: FnMut(u64, u64, u64) -> bool,
+    {
+        let threads = self.threads.lock(line!());
+        for (tid, cpu_stats) in threads.iter() {
+            let (uspace, kernel) = cpu_stats.get();
+            if !func(*tid, uspace, kernel) {
+                return;
+            }
+        }
+    }
+
+    /// The (uspace, kernel) CPU usage, in TSC, of the threads that have exited.
+    pub fn reaped_threads_cpu_usage(&self) -> (u64, u64) {
+        (
+            self.reaped_cpu_uspace.load(Ordering::Relaxed),
+            self.reaped_cpu_kernel.load(Ordering::Relaxed),
+        )
+    }
+
     pub fn into_v1(&self, dest: &mut ProcessStatsV1, now: u64) {
         dest.pid = self.pid.as_u64();
         dest.parent_pid = self.parent.as_ref().map_or(0, |p| p.pid.as_u64());
@@ -354,6 +771,23 @@ impl KProcessStats {
         };
     }
 
+    pub fn into_v2(&self, dest: &mut ProcessStatsV2, now: u64) {
+        self.into_v1(&mut dest.v1, now);
+        dest.pages_user_committed = self.mem_stats_user.pages_committed.load(Ordering::Relaxed);
+        dest.peak_pages_user = self.mem_stats_user.peak_pages();
+        dest.peak_pages_kernel = self.mem_stats_kernel.peak_pages();
+
+        // At the same @now as v1.cpu_usage above.
+        let per_cpu = &self.per_cpu_stats.data;
+        let num_cpus = per_cpu.len().min(moto_sys::stats::PROCESS_STATS_MAX_CPUS);
+        for (entry, val) in per_cpu.iter().zip(dest.per_cpu.iter_mut()) {
+            val.uspace = entry.usage_uspace(now);
+            val.kernel = entry.usage_kernel(now);
+        }
+        dest.num_cpus = num_cpus as u32;
+        dest.truncated = (per_cpu.len() > num_cpus) as u8;
+    }
+
     pub fn iterate<F>(start: ProcessId, flat: bool, mut func: F)
     where
         F: FnMut(&Self) -> bool,
@@ -395,6 +829,103 @@ impl KProcessStats {
         }
     }
 
+    /// Walks the process tree from @start down to @max_depth levels of
+    /// descendants (zero: @start only), calling @func, in pre-order, for the
+    /// entries that match @predicate. Stops when @func returns false.
+    ///
+    /// No lock is held while @predicate or @func run: the children of an
+    /// entry are collected (as Weak refs) under its lock, and upgraded after
+    /// the lock is released. Entries that are dropped mid-walk are skipped,
+    /// together with their subtree. The walk is iterative, so deep trees
+    /// don't overflow the kernel stack.
+    pub fn iterate_filtered<P, F>(start: ProcessId, max_depth: u32, mut predicate: P, mut func: F)
+    where
+        P: FnMut(&Self) -> bool,
+        F: FnMut(&Self) -> bool,
+    {
+        let start = if start.as_u64() == PID_SYSTEM {
+            Arc::downgrade(&*SYSTEM_STATS)
+        } else {
+            let child_lock = SYSTEM_STATS.children.lock(line!());
+            match child_lock.get(&start) {
+                Some(entry) => entry.clone(),
+                None => return,
+            }
+        };
+
+        // Entries still to visit: (entry, its parent's PID, depth left). The
+        // top of the stack is visited next.
+        let mut stack: alloc::vec::Vec<(Weak<Self>, Option<ProcessId>, u32)> =
+            alloc::vec![(start, None, max_depth)];
+        while let Some((entry, parent_pid, depth_left)) = stack.pop() {
+            // Dropping the last Arc to an entry locks its parent's children (see
+            // Drop above), so entries are upgraded (and dropped) with no lock held.
+            let Some(entry) = entry.upgrade() else {
+                continue;
+            };
+            // SYSTEM_STATS lists all processes as its children; only
+            // walk the actual ones.
+            if parent_pid.is_some() && entry.parent.as_ref().map(|p| p.pid) != parent_pid {
+                continue;
+            }
+
+            if predicate(entry.as_ref()) && !func(entry.as_ref()) {
+                return;
+            }
+            if depth_left == 0 {
+                continue;
+            }
+
+            let first_child = stack.len();
+            stack.extend(
+                entry
+                    .children
+                    .lock(line!())
+                    .values()
+                    .map(|child| (child.clone(), Some(entry.pid), depth_left - 1)),
+            );
+            // So that children are visited in PID order.
+            stack[first_child..].reverse();
+        }
+    }
+
+    /// Returns @root and all its live descendants, in PID order, collected
+    /// in one pass under the global process list lock, so that the list is
+    /// a consistent snapshot of the process tree. PID_SYSTEM lists everything.
+    pub fn snapshot(root: ProcessId) -> alloc::vec::Vec<Arc<Self>> {
+        let mut result = alloc::vec::Vec::new();
+        if root.as_u64() == PID_SYSTEM {
+            result.push(SYSTEM_STATS.clone());
+        }
+
+        let is_in_subtree = |stats: &Self| -> bool {
+            if root.as_u64() == PID_SYSTEM {
+                return true;
+            }
+            let mut curr = Some(stats);
+            while let Some(entry) = curr {
+                if entry.pid == root {
+                    return true;
+                }
+                curr = entry.parent.as_deref();
+            }
+            false
+        };
+
+        let all: alloc::vec::Vec<Arc<Self>> = {
+            let child_lock = SYSTEM_STATS.children.lock(line!());
+            child_lock
+                .values()
+                .filter_map(|entry| entry.upgrade())
+                .collect()
+        };
+
+        // Filter after the lock is released: dropping the last Arc to an entry
+        // locks SYSTEM_STATS.children (see Drop above).
+        result.extend(all.into_iter().filter(|e| is_in_subtree(e.as_ref())));
+        result
+    }
+
     fn cpu_usage(&self, now: u64) -> u64 {
         let mut res = 0;
         for entry in &self.per_cpu_stats.data {
@@ -404,6 +935,122 @@ impl KProcessStats {
         res
     }
 
+    /// The resources used so far; final once the process has exited
+    /// (memory stats outlive the address space).
+    pub fn rusage(&self) -> ResourceUsage {
+        let now = crate::arch::time::Instant::now().as_u64();
+        let mut usage = ResourceUsage::default();
+        for entry in &self.per_cpu_stats.data {
+            usage.cpu_uspace += entry.usage_uspace(now);
+            usage.cpu_kernel += entry.usage_kernel(now);
+        }
+        usage.page_faults = self.mem_stats_user.page_faults();
+        usage.peak_bytes_user = self.mem_stats_user.peak();
+        usage.peak_bytes_kernel = self.mem_stats_kernel.peak();
+        usage.total_threads = self.total_threads.load(Ordering::Relaxed);
+        usage.total_children = self.total_children.load(Ordering::Relaxed);
+        usage.context_switches = self.context_switches.load(Ordering::Relaxed);
+        usage.migrations = self
+            .per_cpu_stats
+            .data
+            .iter()
+            .map(|entry| entry.migrations_in.load(Ordering::Relaxed))
+            .sum();
+        usage.sched_latency = self.sched_latency();
+        usage.pid = self.pid.as_u64();
+
+        usage
+    }
+
+    /// The number of user pages populated on first access (see MemStats::add_page_fault()).
+    pub fn page_faults(&self) -> u64 {
+        self.mem_stats_user.page_faults()
+    }
+
+    /// Copies idle time, in TSC, for each CPU into dest (see add_cpu_idle()).
+    /// Returns the number of entries copied.
+    pub fn per_cpu_idle(&self, dest: &mut [u64]) -> usize {
+        let mut count = 0;
+        for (entry, val) in self.per_cpu_stats.data.iter().zip(dest.iter_mut()) {
+            *val = entry.cpu_idle.load(Ordering::Relaxed);
+            count += 1;
+        }
+
+        count
+    }
+
+    /// Copies combined (kernel + uspace) CPU usage, in TSC, for each CPU into dest.
+    /// Returns the number of entries copied.
+    pub fn per_cpu_usage(&self, dest: &mut [u64]) -> usize {
+        let now = crate::arch::time::Instant::now().as_u64();
+        let mut count = 0;
+        for (entry, val) in self.per_cpu_stats.data.iter().zip(dest.iter_mut()) {
+            *val = entry.usage_kernel(now) + entry.usage_uspace(now);
+            count += 1;
+        }
+
+        count
+    }
+
+    /// Copies (migrations_in, migrations_out) for each CPU into dest, as pairs.
+    /// Returns the number of CPUs (pairs) copied.
+    pub fn per_cpu_migrations(&self, dest: &mut [moto_sys::stats::CpuMigrations]) -> usize {
+        let mut count = 0;
+        for (entry, val) in self.per_cpu_stats.data.iter().zip(dest.iter_mut()) {
+            val.migrations_in = entry.migrations_in.load(Ordering::Relaxed);
+            val.migrations_out = entry.migrations_out.load(Ordering::Relaxed);
+            count += 1;
+        }
+
+        count
+    }
+
+    // Called when a thread of this process is about to run.
+    #[inline]
+    pub fn on_context_switch(&self) {
+        self.context_switches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Called when a thread of this process starts running on a CPU
+    // different from the one it last ran on.
+    pub fn on_thread_migrated(&self, from: uCpus, to: uCpus) {
+        for stats in [self, SYSTEM_STATS.as_ref()] {
+            stats.per_cpu_stats.data[from as usize]
+                .migrations_out
+                .fetch_add(1, Ordering::Relaxed);
+            stats.per_cpu_stats.data[to as usize]
+                .migrations_in
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Zeroes cumulative counters (CPU usage, idle time, migrations, context
+    // switches, scheduling latency, page faults). Gauges (memory usage, active
+    // threads/children) and structural fields are kept. The CPU limit budget
+    // is tracked separately and is not reset, so that a reset does not let
+    // a process escape its limit. Per-thread CPU usage is not reset either:
+    // it backs the thread CPU clock (F_USAGE_THREAD).
+    //
+    // Counters are zeroed one by one, without stopping the process, so a
+    // concurrent reader may see some zeroed and some not yet, and events
+    // counted during the reset may or may not be kept.
+    pub fn reset_counters(&self) {
+        for entry in &self.per_cpu_stats.data {
+            // started_k/started_u mark in-progress intervals; they will be added
+            // to the zeroed counters when the interval stops.
+            entry.cpu_kernel.store(0, Ordering::Relaxed);
+            entry.cpu_uspace.store(0, Ordering::Relaxed);
+            entry.cpu_idle.store(0, Ordering::Relaxed);
+            entry.migrations_in.store(0, Ordering::Relaxed);
+            entry.migrations_out.store(0, Ordering::Relaxed);
+        }
+        self.sched_latency_total.store(0, Ordering::Relaxed);
+        self.sched_latency_max.store(0, Ordering::Relaxed);
+        self.sched_latency_count.store(0, Ordering::Relaxed);
+        self.context_switches.store(0, Ordering::Relaxed);
+        self.mem_stats_user.reset_page_faults();
+    }
+
     pub fn get_percpu_stats_entry(&self, cpu: uCpus) -> &PerCpuStatsEntry {
         &self.per_cpu_stats.data[cpu as usize]
     }
@@ -428,8 +1075,9 @@ impl KProcessStats {
         assert_eq!(0, entry.started_k.swap(now, Ordering::Relaxed));
     }
 
+    // Returns the elapsed time (TSC) since the matching start_cpu_usage_kernel().
     #[inline]
-    pub fn stop_cpu_usage_kernel(&self) {
+    pub fn stop_cpu_usage_kernel(&self) -> u64 {
         let now = crate::arch::time::Instant::now().as_u64();
         let cpu = current_cpu() as usize;
 
@@ -437,13 +1085,14 @@ impl KProcessStats {
         let entry = &self.per_cpu_stats.data[cpu];
         let prev = entry.started_k.swap(0, Ordering::Relaxed);
         assert_ne!(prev, 0);
-        if now > prev {
-            entry.cpu_kernel.fetch_add(now - prev, Ordering::Relaxed);
-        }
+        let elapsed = if now > prev { now - prev } else { 0 };
+        entry.cpu_kernel.fetch_add(elapsed, Ordering::Relaxed);
 
         // Start kernel.
         let kernel_entry = &KERNEL_STATS.per_cpu_stats.data[cpu];
         assert_eq!(0, kernel_entry.started_k.swap(now, Ordering::Relaxed));
+
+        elapsed
     }
 
     #[inline]
@@ -466,8 +1115,9 @@ impl KProcessStats {
         assert_eq!(0, entry.started_u.swap(now, Ordering::Relaxed));
     }
 
+    // Returns the elapsed time (TSC) since the matching start_cpu_usage_uspace().
     #[inline]
-    pub fn stop_cpu_usage_uspace(&self) {
+    pub fn stop_cpu_usage_uspace(&self) -> u64 {
         let now = crate::arch::time::Instant::now().as_u64();
         let cpu = current_cpu() as usize;
 
@@ -475,13 +1125,25 @@ impl KProcessStats {
         let entry = &self.per_cpu_stats.data[cpu];
         let prev = entry.started_u.swap(0, Ordering::Relaxed);
         assert_ne!(prev, 0);
-        if now > prev {
-            entry.cpu_uspace.fetch_add(now - prev, Ordering::Relaxed);
-        }
+        let elapsed = if now > prev { now - prev } else { 0 };
+        entry.cpu_uspace.fetch_add(elapsed, Ordering::Relaxed);
 
         // Start kernel.
         let kernel_entry = &KERNEL_STATS.per_cpu_stats.data[cpu];
         assert_eq!(0, kernel_entry.started_k.swap(now, Ordering::Relaxed));
+
+        elapsed
+    }
+
+    // Called by the scheduler after the current CPU wakes from hlt, with the
+    // time returned by SYSTEM_STATS.stop_cpu_usage_kernel(): the scheduler
+    // charges its halts to SYSTEM_STATS, and nothing else runs as SYSTEM_STATS,
+    // so idle time is exactly its kernel time, and does not overlap with
+    // the time of any process, including KERNEL_STATS.
+    pub fn add_cpu_idle(&self, idle_tsc: u64) {
+        self.per_cpu_stats.data[current_cpu() as usize]
+            .cpu_idle
+            .fetch_add(idle_tsc, Ordering::Relaxed);
     }
 
     pub fn cpu_usage_scope_kernel(self: &Arc<Self>) -> CpuUsageScopeKernel {
@@ -537,6 +1199,10 @@ pub fn init() {
             .load(Ordering::Acquire),
         Ordering::Relaxed,
     );
+
+    MEMORY_PRESSURE_EVENT.set(Box::leak(Box::new(crate::uspace::SysObject::new(
+        Arc::new("memory_pressure".to_owned()),
+    ))));
 }
 
 fn system_stats() -> Arc<KProcessStats> {
@@ -555,6 +1221,15 @@ pub fn stats_from_pid(pid: u64) -> Option<Arc<KProcessStats>> {
         .map(|w| w.upgrade())?
 }
 
+// Same as stats_from_pid() above, but also covers PID_SYSTEM and PID_KERNEL.
+pub fn any_stats_from_pid(pid: u64) -> Option<Arc<KProcessStats>> {
+    match pid {
+        PID_SYSTEM => Some(system_stats()),
+        PID_KERNEL => Some(kernel_stats()),
+        _ => stats_from_pid(pid),
+    }
+}
+
 pub fn system_stats_ref() -> &'static KProcessStats {
     &SYSTEM_STATS
 }
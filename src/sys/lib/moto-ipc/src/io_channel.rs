@@ -31,6 +31,12 @@ pub const CMD_NOOP_OK: u16 = 1;
 // payload::args_64()[2].
 pub const FLAG_CMD_NOOP_OK_TIMESTAMP: u32 = 1;
 
+// Set on a completion (CQE) by ServerConnection::attach_page(): the message carries
+// pages allocated from the server's pool, and their ownership is transferred to the client,
+// which must get them via ClientConnection::take_server_page() (and free them by dropping).
+// Reserved for all commands: protocols must not use this bit in Msg::flags.
+pub const FLAG_CQE_SERVER_PAGES: u32 = 1 << 30;
+
 pub const PAGE_SIZE: usize = 4096;
 
 #[repr(C, align(4096))]
@@ -90,8 +96,55 @@ impl Payload {
     pub fn args_64(&self) -> &[u64; 3] {
         unsafe { &self.args_64 }
     }
+
+    /// The maximum number of bytes that can be passed inline, without allocating an IoPage.
+    pub const INLINE_DATA_MAX: usize = 23;
+
+    /// Puts a small amount of data directly into the payload: the first byte
+    /// holds the length, the rest holds the data. Useful for control messages
+    /// that don't need a full IoPage.
+    pub fn set_inline_data(&mut self, data: &[u8]) -> Result<(), ErrorCode> {
+        if data.len() > Self::INLINE_DATA_MAX {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        let args = self.args_8_mut();
+        args[0] = data.len() as u8;
+        args[1..(data.len() + 1)].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Returns the data put via [`Payload::set_inline_data`].
+    pub fn inline_data(&self) -> Result<&[u8], ErrorCode> {
+        let args = self.args_8();
+        let len = args[0] as usize;
+        if len > Self::INLINE_DATA_MAX {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        Ok(&args[1..(len + 1)])
+    }
+
+    /// The number of pages in a message that carries up to @max_slots pages
+    /// (scatter/gather): the pages are in shared_pages()[0..n], in order, and
+    /// the remaining slots up to @max_slots are NO_PAGE. Returns
+    /// E_INVALID_ARGUMENT if a page follows a NO_PAGE slot.
+    pub fn shared_page_count(&self, max_slots: usize) -> Result<usize, ErrorCode> {
+        let slots = &self.shared_pages()[0..max_slots];
+        let count = slots
+            .iter()
+            .position(|page_idx| *page_idx == NO_PAGE)
+            .unwrap_or(max_slots);
+        if slots[count..].iter().any(|page_idx| *page_idx != NO_PAGE) {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+        Ok(count)
+    }
 }
 
+/// An unused page slot in Payload::shared_pages() (see Payload::shared_page_count()).
+pub const NO_PAGE: u16 = u16::MAX;
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct Msg {
@@ -139,8 +192,45 @@ impl Msg {
 
 pub const QUEUE_SIZE: u64 = 64;
 const QUEUE_MASK: u64 = QUEUE_SIZE - 1;
+// Set in a queue head once the queue is shut down; see RawChannel::close_queue().
+const QUEUE_CLOSED: u64 = 1 << 63;
 pub const CHANNEL_PAGE_COUNT: usize = 64;
 
+/// The maximum number of pages that can be in use at the same time in a subchannel,
+/// i.e. the most `alloc_pages()` can ever return for `subchannel_mask`
+/// (at most CHANNEL_PAGE_COUNT), unless the pool is limited (see [`PoolLimits`]).
+pub const fn max_pages(subchannel_mask: u64) -> usize {
+    subchannel_mask.count_ones() as usize
+}
+
+/// The alignment of every IoPage: pages are whole, PAGE_SIZE-aligned pages of the
+/// shared mapping, so they can be used for direct (O_DIRECT-like) block transfers
+/// as is. The overhead is that every buffer takes a whole page, however small
+/// the data. Larger alignments are not supported: the channel is backed by
+/// small pages, which are not physically contiguous.
+pub const PAGE_ALIGNMENT: usize = PAGE_SIZE;
+
+const _PAGES_ALIGNED: () = assert!(core::mem::align_of::<Page>() == PAGE_ALIGNMENT);
+
+fn check_alignment(alignment: usize) -> Result<(), ErrorCode> {
+    if alignment.is_power_of_two() && alignment <= PAGE_ALIGNMENT {
+        Ok(())
+    } else {
+        Err(moto_rt::E_INVALID_ARGUMENT)
+    }
+}
+
+/// How [`ClientConnection::submit_sqe_blocking`] waits for room in the queue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetryPolicy {
+    /// Wait on the server handle as soon as the queue is found full.
+    Wait,
+    /// Retry up to this many times with a spin loop hint before waiting.
+    /// Saves a context switch when the server runs on another CPU
+    /// and drains the queue quickly.
+    SpinThenWait(u32),
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum SubChannelType {
     Client,
@@ -162,6 +252,264 @@ impl Into<SubChannelType> for SubChannel {
     }
 }
 
+/// Page pool usage of one side of a channel, in pages.
+#[derive(Clone, Copy, Debug)]
+pub struct PoolUsage {
+    pub in_use: usize,
+    /// The most pages in use at the same time since the channel was created
+    /// (or since the last `reset_pool_high_water()`).
+    pub high_water: usize,
+    /// The pages the pool has grown to so far (see [`PoolLimits`]).
+    pub capacity: usize,
+    /// The most pages the pool can grow to.
+    pub max_capacity: usize,
+}
+
+/// How the page pool of one side of a channel grows, see
+/// [`ClientConnection::set_pool_limits`].
+///
+/// The pool starts with `initial` pages; when they are all in use, `alloc_page()`
+/// doubles the capacity, up to `max`, instead of failing with E_NOT_READY.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolLimits {
+    pub initial: usize,
+    /// At most CHANNEL_PAGE_COUNT.
+    pub max: usize,
+    /// Go back to `initial` pages once no pages are in use.
+    pub shrink_when_idle: bool,
+}
+
+impl PoolLimits {
+    /// The default: all CHANNEL_PAGE_COUNT pages from the start.
+    pub const FIXED: Self = Self {
+        initial: CHANNEL_PAGE_COUNT,
+        max: CHANNEL_PAGE_COUNT,
+        shrink_when_idle: false,
+    };
+
+    const SHRINK_WHEN_IDLE: u64 = 1 << 16;
+
+    fn validate(&self) -> Result<(), ErrorCode> {
+        if self.max == 0 || self.max > CHANNEL_PAGE_COUNT || self.initial > self.max {
+            Err(moto_rt::E_INVALID_ARGUMENT)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Packed into RawChannel::*_pages_limits.
+    fn to_u64(self) -> u64 {
+        let shrink = if self.shrink_when_idle {
+            Self::SHRINK_WHEN_IDLE
+        } else {
+            0
+        };
+        (self.initial as u64) | ((self.max as u64) << 8) | shrink
+    }
+
+    fn from_u64(val: u64) -> Self {
+        Self {
+            initial: (val & 0xff) as usize,
+            max: ((val >> 8) & 0xff) as usize,
+            shrink_when_idle: val & Self::SHRINK_WHEN_IDLE != 0,
+        }
+    }
+}
+
+// The pages with indices below @capacity.
+const fn capacity_mask(capacity: u64) -> u64 {
+    if capacity >= 64 {
+        u64::MAX
+    } else {
+        (1_u64 << capacity) - 1
+    }
+}
+
+/// The number of buckets in a [`LatencyHistogram`].
+pub const LATENCY_BUCKETS: usize = 32;
+
+// The most requests in flight whose start time is tracked; requests beyond that
+// are not recorded (see LatencyHistogram::untracked).
+const LATENCY_SLOTS: usize = 256;
+const LATENCY_SLOT_FREE: u64 = u64::MAX;
+
+/// A snapshot of request latencies recorded by a connection, see
+/// [`ServerConnection::enable_latency_histogram`].
+///
+/// `buckets[0]` counts latencies below 2ns, `buckets[N]` counts latencies
+/// in [2^N ns, 2^(N+1) ns), and the last bucket counts everything above that.
+#[derive(Clone, Copy, Debug)]
+pub struct LatencyHistogram {
+    pub buckets: [u64; LATENCY_BUCKETS],
+    /// Requests not recorded because too many were in flight.
+    pub untracked: u64,
+}
+
+impl LatencyHistogram {
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// An upper bound of the `p`-th percentile (0.0..=100.0) of recorded latencies,
+    /// precise to a factor of two; None if nothing has been recorded.
+    pub fn percentile(&self, p: f64) -> Option<core::time::Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+
+        let rank = (((count as f64) * p / 100.0) as u64).clamp(1, count);
+        let mut seen = 0;
+        for (idx, bucket) in self.buckets.iter().enumerate() {
+            seen += *bucket;
+            if seen >= rank {
+                return Some(core::time::Duration::from_nanos(1_u64 << (idx + 1)));
+            }
+        }
+        unreachable!()
+    }
+}
+
+// Records the time between the start of a request (keyed by Msg::id) and its
+// completion. Lock-free, as connections can be used from several threads.
+struct LatencyRecorder {
+    slot_ids: [AtomicU64; LATENCY_SLOTS],
+    slot_starts: [AtomicU64; LATENCY_SLOTS],
+    buckets: [AtomicU64; LATENCY_BUCKETS],
+    untracked: AtomicU64,
+}
+
+impl LatencyRecorder {
+    fn new() -> alloc::boxed::Box<Self> {
+        alloc::boxed::Box::new(Self {
+            slot_ids: [const { AtomicU64::new(LATENCY_SLOT_FREE) }; LATENCY_SLOTS],
+            slot_starts: [const { AtomicU64::new(0) }; LATENCY_SLOTS],
+            buckets: [const { AtomicU64::new(0) }; LATENCY_BUCKETS],
+            untracked: AtomicU64::new(0),
+        })
+    }
+
+    fn start(&self, id: u64) {
+        if id == LATENCY_SLOT_FREE {
+            self.untracked.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        let now = moto_rt::time::Instant::now().as_u64();
+        let first = (id as usize) % LATENCY_SLOTS;
+        for step in 0..LATENCY_SLOTS {
+            let idx = (first + step) % LATENCY_SLOTS;
+            if self.slot_ids[idx]
+                .compare_exchange(LATENCY_SLOT_FREE, id, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+            {
+                self.slot_starts[idx].store(now, Ordering::Release);
+                return;
+            }
+        }
+        self.untracked.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn complete(&self, id: u64) {
+        if id == LATENCY_SLOT_FREE {
+            return;
+        }
+
+        let first = (id as usize) % LATENCY_SLOTS;
+        for step in 0..LATENCY_SLOTS {
+            let idx = (first + step) % LATENCY_SLOTS;
+            if self.slot_ids[idx].load(Ordering::Relaxed) != id {
+                continue;
+            }
+
+            let start = self.slot_starts[idx].swap(0, Ordering::Acquire);
+            self.slot_ids[idx].store(LATENCY_SLOT_FREE, Ordering::Release);
+            if start == 0 {
+                return; // Raced with start(); not recorded.
+            }
+
+            let nanos = moto_rt::time::Instant::now()
+                .duration_since(moto_rt::time::Instant::from_u64(start))
+                .as_nanos() as u64;
+            let bucket = (nanos.max(1).ilog2() as usize).min(LATENCY_BUCKETS - 1);
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        // Not started (e.g. untracked, or a CQE that does not complete an SQE).
+    }
+
+    fn snapshot(&self) -> LatencyHistogram {
+        let mut buckets = [0; LATENCY_BUCKETS];
+        for (dst, src) in buckets.iter_mut().zip(self.buckets.iter()) {
+            *dst = src.load(Ordering::Relaxed);
+        }
+        LatencyHistogram {
+            buckets,
+            untracked: self.untracked.load(Ordering::Relaxed),
+        }
+    }
+
+    fn reset(&self) {
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.untracked.store(0, Ordering::Relaxed);
+    }
+}
+
+// See dequeue() in mpmc.cc. Dequeues up to msgs.len() messages with a single
+// update of @tail: the messages ready at the tail are counted first, and then
+// claimed all at once, so a batch is a contiguous run of the queue, in order,
+// just as if dequeued one by one.
+fn dequeue_batch(
+    queue: &mut [MsgSlot; QUEUE_SIZE as usize],
+    tail: &AtomicU64,
+    msgs: &mut [Msg],
+) -> usize {
+    let max = (msgs.len() as u64).min(QUEUE_SIZE);
+    if max == 0 {
+        return 0;
+    }
+
+    let mut pos = tail.load(Ordering::Relaxed);
+    let count = loop {
+        let mut count = 0;
+        while count < max {
+            let stamp = queue[((pos + count) & QUEUE_MASK) as usize]
+                .stamp
+                .load(Ordering::Acquire);
+            if stamp != (pos + count + 1) {
+                break;
+            }
+            count += 1;
+        }
+
+        if count == 0 {
+            let stamp = queue[(pos & QUEUE_MASK) as usize]
+                .stamp
+                .load(Ordering::Acquire);
+            if stamp < (pos + 1) {
+                return 0; // The queue is empty.
+            }
+            // We lost the race - continue.
+            pos = tail.load(Ordering::Relaxed);
+            continue;
+        }
+
+        match tail.compare_exchange_weak(pos, pos + count, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => break count,
+            Err(current) => pos = current, // continue
+        }
+    };
+
+    for idx in 0..count {
+        let slot = &mut queue[((pos + idx) & QUEUE_MASK) as usize];
+        msgs[idx as usize] = slot.msg;
+        slot.stamp.store(pos + idx + QUEUE_SIZE, Ordering::Release);
+    }
+    count as usize
+}
+
 #[derive(Clone, Copy, Debug)]
 struct RawIoPage {
     page_idx: u16,
@@ -180,16 +528,32 @@ struct RawChannel {
     server_queue_tail: AtomicU64,
     _pad4: [u64; 7],
 
+    // Page pools. Only pages with indices below the capacity are allocated;
+    // alloc_page() grows the capacity up to the max in *_pages_limits (see
+    // PoolLimits). Pages are referred to by their index in client_pages/server_pages
+    // below, and the whole channel is shared memory mapped at connect time, so
+    // the pages above the capacity are there, but are not handed out.
     client_pages_in_use: AtomicU64,
-    _pad5: [u64; 7],
+    client_pages_high_water: AtomicU64,
+    client_pages_capacity: AtomicU64,
+    client_pages_limits: AtomicU64,
+    _pad5: [u64; 4],
     server_pages_in_use: AtomicU64,
-    _pad6: [u64; 7],
+    server_pages_high_water: AtomicU64,
+    server_pages_capacity: AtomicU64,
+    server_pages_limits: AtomicU64,
+    _pad6: [u64; 4],
+
+    // Non-zero if page checksums are enabled. See ClientConnection::enable_checksums().
+    checksums_enabled: AtomicU64,
+    _pad7: [u64; 15], // Pad to BLOCK_SIZE (512 bytes).
 
-    // Pad to BLOCK_SIZE (512 bytes).
-    _pad7: [u8; 128],
+    // Page checksums; only used if checksums are enabled.
+    client_page_checksums: [AtomicU32; CHANNEL_PAGE_COUNT], // 256 bytes.
+    server_page_checksums: [AtomicU32; CHANNEL_PAGE_COUNT], // 256 bytes.
 
     // Pad to PAGE_SIZE
-    _pad8: [u8; 3584],
+    _pad8: [u8; 3072],
 
     // offset: 4096 = 1 page; client=>server queue.
     client_queue: [MsgSlot; QUEUE_SIZE as usize], // 4096 bytes = 8 blocks
@@ -205,7 +569,7 @@ pub const _RAW_CHANNEL_SIZE: () = assert!(core::mem::size_of::<RawChannel>() ==
 
 impl RawChannel {
     fn is_empty(&self) -> bool {
-        self.client_queue_head.load(Ordering::Acquire)
+        (self.client_queue_head.load(Ordering::Acquire) & !QUEUE_CLOSED)
             == self.server_queue_tail.load(Ordering::Acquire)
     }
 
@@ -227,18 +591,76 @@ impl RawChannel {
         }
     }
 
-    fn alloc_page(&self, subchannel: SubChannel) -> Result<RawIoPage, ErrorCode> {
-        let (bitmap_ref, subchannel_mask) = match subchannel {
-            SubChannel::Client(mask) => (&self.client_pages_in_use, mask),
-            SubChannel::Server(mask) => (&self.server_pages_in_use, mask),
+    // (in use bitmap, high water, capacity, limits).
+    fn pool(&self, s_type: SubChannelType) -> (&AtomicU64, &AtomicU64, &AtomicU64, &AtomicU64) {
+        match s_type {
+            SubChannelType::Client => (
+                &self.client_pages_in_use,
+                &self.client_pages_high_water,
+                &self.client_pages_capacity,
+                &self.client_pages_limits,
+            ),
+            SubChannelType::Server => (
+                &self.server_pages_in_use,
+                &self.server_pages_high_water,
+                &self.server_pages_capacity,
+                &self.server_pages_limits,
+            ),
+        }
+    }
+
+    fn pool_limits(&self, s_type: SubChannelType) -> PoolLimits {
+        PoolLimits::from_u64(self.pool(s_type).3.load(Ordering::Relaxed))
+    }
+
+    fn set_pool_limits(&self, s_type: SubChannelType, limits: PoolLimits) -> Result<(), ErrorCode> {
+        limits.validate()?;
+        let (_, _, capacity, limits_ref) = self.pool(s_type);
+        limits_ref.store(limits.to_u64(), Ordering::Relaxed);
+        // Pages in use above the new capacity stay valid until freed.
+        capacity.store(limits.initial as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // The pages in @subchannel the pool can grow to.
+    fn max_subchannel_mask(&self, subchannel: SubChannel) -> u64 {
+        let (s_type, subchannel_mask) = match subchannel {
+            SubChannel::Client(mask) => (SubChannelType::Client, mask),
+            SubChannel::Server(mask) => (SubChannelType::Server, mask),
         };
+        subchannel_mask & capacity_mask(self.pool_limits(s_type).max as u64)
+    }
+
+    fn alloc_page(&self, subchannel: SubChannel) -> Result<RawIoPage, ErrorCode> {
+        let (bitmap_ref, high_water, capacity_ref, _) = self.pool(subchannel.into());
+        let subchannel_mask = self.max_subchannel_mask(subchannel);
+
+        if subchannel_mask == 0 {
+            // Would never succeed: don't let the caller spin on E_NOT_READY.
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
 
         loop {
             let bitmap = bitmap_ref.load(Ordering::Relaxed);
-            let ones = (bitmap | !subchannel_mask).trailing_ones();
+            let capacity = capacity_ref.load(Ordering::Relaxed);
+            let ones = (bitmap | !(subchannel_mask & capacity_mask(capacity))).trailing_ones();
             if ones == 64 {
-                // Nothing left.
-                return Err(moto_rt::E_NOT_READY);
+                if (!bitmap & subchannel_mask) == 0 {
+                    // Nothing left, even if the pool grows.
+                    return Err(moto_rt::E_NOT_READY);
+                }
+
+                // Grow. If another thread has grown (or shrunk) the pool
+                // concurrently, just try again.
+                let max = self.pool_limits(subchannel.into()).max as u64;
+                let grown = (capacity * 2).clamp(1, max);
+                let _ = capacity_ref.compare_exchange(
+                    capacity,
+                    grown,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                );
+                continue;
             }
 
             let bit = 1u64 << ones;
@@ -254,6 +676,11 @@ impl RawChannel {
                 continue;
             }
 
+            let in_use = (bitmap | bit).count_ones() as u64;
+            if in_use > high_water.load(Ordering::Relaxed) {
+                high_water.fetch_max(in_use, Ordering::Relaxed);
+            }
+
             return Ok(RawIoPage {
                 page_idx: ones as u16,
                 s_type: subchannel.into(),
@@ -266,24 +693,135 @@ impl RawChannel {
             return Err(moto_rt::E_INVALID_ARGUMENT);
         }
 
-        let bitmap = match raw_page.s_type {
-            SubChannelType::Client => &self.client_pages_in_use,
-            SubChannelType::Server => &self.server_pages_in_use,
-        };
+        let (bitmap, _, capacity, _) = self.pool(raw_page.s_type);
 
         let bit = 1u64 << raw_page.page_idx;
-        if (bitmap.fetch_xor(bit, Ordering::AcqRel) & bit) == 0 {
+        let prev = bitmap.fetch_xor(bit, Ordering::AcqRel);
+        if (prev & bit) == 0 {
             // The page was not actually used.
             panic!(
                 "io_channel: freeing unused page 0x{:x?}; used pages: 0x{:x}",
                 raw_page,
                 bitmap.load(Ordering::Relaxed)
             )
+        }
+
+        if prev == bit {
+            // The pool is idle. A page allocated concurrently above the
+            // shrunk capacity is fine: it is freed as usual.
+            let limits = self.pool_limits(raw_page.s_type);
+            if limits.shrink_when_idle {
+                capacity.store(limits.initial as u64, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+
+    fn pool_usage(&self, s_type: SubChannelType) -> PoolUsage {
+        let (bitmap, high_water, capacity, _) = self.pool(s_type);
+
+        PoolUsage {
+            in_use: bitmap.load(Ordering::Relaxed).count_ones() as usize,
+            high_water: high_water.load(Ordering::Relaxed) as usize,
+            capacity: capacity.load(Ordering::Relaxed) as usize,
+            max_capacity: self.pool_limits(s_type).max,
+        }
+    }
+
+    // Counts the pages the pool can grow to.
+    fn free_pages(&self, subchannel: SubChannel) -> usize {
+        let bitmap = self.pool(subchannel.into()).0;
+        (!bitmap.load(Ordering::Acquire) & self.max_subchannel_mask(subchannel)).count_ones()
+            as usize
+    }
+
+    // The number of consecutive free slots at the queue head. A slot is free
+    // once its stamp is updated, which is after the consumer moves the tail,
+    // so QUEUE_SIZE - (head - tail) could count slots that are not free yet.
+    fn queue_space(queue: &[MsgSlot; QUEUE_SIZE as usize], head: &AtomicU64) -> usize {
+        let head = head.load(Ordering::Relaxed);
+        if head & QUEUE_CLOSED != 0 {
+            return 0;
+        }
+        (0..QUEUE_SIZE)
+            .take_while(|idx| {
+                let pos = head + idx;
+                queue[(pos & QUEUE_MASK) as usize]
+                    .stamp
+                    .load(Ordering::Acquire)
+                    == pos
+            })
+            .count()
+    }
+
+    fn reset_high_water(&self, s_type: SubChannelType) {
+        let (bitmap, high_water, _, _) = self.pool(s_type);
+        high_water.store(
+            bitmap.load(Ordering::Relaxed).count_ones() as u64,
+            Ordering::Relaxed,
+        );
+    }
+
+    fn checksums_enabled(&self) -> bool {
+        self.checksums_enabled.load(Ordering::Relaxed) != 0
+    }
+
+    fn page_checksum_slot(&self, raw_page: RawIoPage) -> &AtomicU32 {
+        match raw_page.s_type {
+            SubChannelType::Client => &self.client_page_checksums[raw_page.page_idx as usize],
+            SubChannelType::Server => &self.server_page_checksums[raw_page.page_idx as usize],
+        }
+    }
+
+    // Called when the page is handed over to the peer.
+    fn seal_page(&self, raw_page: RawIoPage) {
+        if !self.checksums_enabled() {
+            return;
+        }
+
+        let crc = crc32(self.page_bytes(raw_page).unwrap());
+        self.page_checksum_slot(raw_page)
+            .store(crc, Ordering::Release);
+    }
+
+    // Called when the page is received from the peer.
+    fn validate_page(&self, raw_page: RawIoPage) -> Result<(), ErrorCode> {
+        if !self.checksums_enabled() {
+            return Ok(());
+        }
+
+        let expected = self.page_checksum_slot(raw_page).load(Ordering::Acquire);
+        let actual = crc32(self.page_bytes(raw_page).unwrap());
+        if expected != actual {
+            crate::moto_log!(
+                "io_channel: checksum mismatch for page {:?}: expected 0x{:x} got 0x{:x}",
+                raw_page,
+                expected,
+                actual
+            );
+            return Err(moto_rt::E_BAD_DATA);
+        }
+
+        Ok(())
+    }
+
+    // Sets QUEUE_CLOSED in @queue_head. As enqueue CASes the head, a message
+    // is either enqueued before the queue is closed, or not at all. Returns
+    // E_NOT_CONNECTED if already closed.
+    fn close_queue(queue_head: &AtomicU64) -> Result<(), ErrorCode> {
+        if queue_head.fetch_or(QUEUE_CLOSED, Ordering::AcqRel) & QUEUE_CLOSED != 0 {
+            Err(moto_rt::E_NOT_CONNECTED)
         } else {
             Ok(())
         }
     }
 
+    // True if the queue is closed and everything enqueued before was received.
+    fn is_drained(queue_head: &AtomicU64, queue_tail: &AtomicU64) -> bool {
+        let head = queue_head.load(Ordering::Acquire);
+        head & QUEUE_CLOSED != 0 && queue_tail.load(Ordering::Acquire) >= (head & !QUEUE_CLOSED)
+    }
+
     fn dump_state(&self) {
         crate::moto_log!(
             "RawChannel: sqh: {} sqt: {} cqh: {} cqt: {} client pages: 0x{:x} server pages: 0x{:x}",
@@ -310,6 +848,19 @@ impl Drop for IoPage {
     }
 }
 
+// CRC-32 (IEEE). Slow, but only used for debugging.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = u32::MAX;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
 impl IoPage {
     const SERVER_FLAG: u16 = 1 << 15;
     const _FOO: () = assert!((CHANNEL_PAGE_COUNT as u16) < Self::SERVER_FLAG);
@@ -318,6 +869,12 @@ impl IoPage {
         self.raw_channel.page_bytes(self.raw_page).unwrap()
     }
 
+    /// Returns true if the opaque number returned by [`IoPage::into_u16`]
+    /// refers to a page from the server's pool.
+    pub fn is_server_page(val: u16) -> bool {
+        (val & Self::SERVER_FLAG) != 0
+    }
+
     pub fn bytes_mut(&self) -> &mut [u8] {
         self.raw_channel.page_bytes(self.raw_page).unwrap()
     }
@@ -327,7 +884,11 @@ impl IoPage {
     /// To avoid a memory leak the number must be converted back to an `IoPage` using
     /// [`IoPage::from_u16`].
     ///
+    /// If checksums are enabled on the channel, the page checksum is computed here
+    /// and validated when the peer gets the page.
+    ///
     pub fn into_u16(mut val: Self) -> u16 {
+        val.raw_channel.seal_page(val.raw_page);
         let res = match val.raw_page.s_type {
             SubChannelType::Client => val.raw_page.page_idx,
             SubChannelType::Server => val.raw_page.page_idx | Self::SERVER_FLAG,
@@ -366,6 +927,7 @@ impl IoPage {
 pub struct ClientConnection {
     raw_channel: AtomicPtr<RawChannel>,
     server_handle: SysHandle,
+    latency: Option<alloc::boxed::Box<LatencyRecorder>>,
 }
 
 impl Drop for ClientConnection {
@@ -375,6 +937,13 @@ impl Drop for ClientConnection {
 }
 
 impl ClientConnection {
+    /// Same as [`Self::connect`], but fails with E_INVALID_ARGUMENT unless every
+    /// page of the channel is aligned to `alignment` (see [`PAGE_ALIGNMENT`]).
+    pub fn connect_aligned(url: &str, alignment: usize) -> Result<Self, ErrorCode> {
+        check_alignment(alignment)?;
+        Self::connect(url)
+    }
+
     pub fn connect(url: &str) -> Result<Self, ErrorCode> {
         let addr = SysMem::map(
             SysHandle::SELF,
@@ -400,6 +969,7 @@ impl ClientConnection {
         let self_ = Self {
             raw_channel: AtomicPtr::new(addr as usize as *mut RawChannel),
             server_handle,
+            latency: None,
         };
 
         fence(Ordering::Acquire);
@@ -419,6 +989,26 @@ impl ClientConnection {
             .raw_channel()
             .client_queue_tail
             .store(0, Ordering::Relaxed);
+        self_
+            .raw_channel()
+            .checksums_enabled
+            .store(0, Ordering::Relaxed);
+        self_
+            .raw_channel()
+            .client_pages_high_water
+            .store(0, Ordering::Relaxed);
+        self_
+            .raw_channel()
+            .server_pages_high_water
+            .store(0, Ordering::Relaxed);
+        self_
+            .raw_channel()
+            .set_pool_limits(SubChannelType::Client, PoolLimits::FIXED)
+            .unwrap();
+        self_
+            .raw_channel()
+            .set_pool_limits(SubChannelType::Server, PoolLimits::FIXED)
+            .unwrap();
 
         for idx in 0..(QUEUE_SIZE) {
             self_.raw_channel().client_queue[idx as usize]
@@ -437,6 +1027,11 @@ impl ClientConnection {
         self.server_handle
     }
 
+    /// (PID, capabilities) of the server process, as known to the kernel.
+    pub fn peer_credentials(&self) -> Result<(u64, u64), ErrorCode> {
+        SysObj::get_peer_credentials(self.server_handle)
+    }
+
     fn clear(&mut self) {
         let addr = self.raw_channel.load(Ordering::Acquire) as usize;
         SysMem::free(addr as u64).unwrap();
@@ -449,10 +1044,13 @@ impl ClientConnection {
     // See enqueue() in mpmc.cc.
     pub fn send(&self, msg: Msg) -> Result<(), ErrorCode> {
         let raw_channel = self.raw_channel();
-
         let mut slot: &mut MsgSlot;
         let mut pos = raw_channel.client_queue_head.load(Ordering::Relaxed);
         loop {
+            if pos & QUEUE_CLOSED != 0 {
+                // Shut down by either side.
+                return Err(moto_rt::E_NOT_CONNECTED);
+            }
             slot = &mut raw_channel.client_queue[(pos & QUEUE_MASK) as usize];
             let stamp = slot.stamp.load(Ordering::Acquire);
 
@@ -474,67 +1072,215 @@ impl ClientConnection {
             }
         }
 
+        if let Some(latency) = &self.latency {
+            latency.start(msg.id);
+        }
         slot.msg = msg;
         slot.stamp.store(pos + 1, Ordering::Release);
         Ok(())
     }
 
-    // See dequeue() in mpmc.cc.
-    pub fn recv(&self) -> Result<Msg, ErrorCode> {
-        let raw_channel = self.raw_channel();
+    /// Sends @msgs, in order, and then wakes the server once (if anything was sent).
+    /// Stops at the first message that does not fit into the queue; returns the
+    /// number of messages sent. See ServerConnection::send_batch() re: ordering.
+    pub fn send_batch(&self, msgs: &[Msg]) -> Result<usize, ErrorCode> {
+        let mut sent = 0;
+        for msg in msgs {
+            match self.send(*msg) {
+                Ok(()) => sent += 1,
+                Err(moto_rt::E_NOT_CONNECTED) if sent == 0 => return Err(moto_rt::E_NOT_CONNECTED),
+                Err(_) => break,
+            }
+        }
 
-        let mut slot: &mut MsgSlot;
-        let mut pos = raw_channel.server_queue_tail.load(Ordering::Relaxed);
+        if sent > 0 {
+            SysCpu::wake(self.server_handle)?;
+        }
+        Ok(sent)
+    }
+
+    /// Sends @sqe and wakes the server, retrying while the queue is full,
+    /// until @timeout. Returns E_TIMED_OUT if the queue is still full by then.
+    ///
+    /// A server may stop accepting SQEs while its completions are not received,
+    /// so a caller that keeps submitting without processing completions
+    /// (here or in another thread) may time out.
+    ///
+    /// The timeout covers only waiting for queue space: once sent, an SQE
+    /// cannot be withdrawn, as the server may already be working on it (and
+    /// own its pages). A client that gives up on a request must still receive
+    /// its CQE and free the pages it carries, e.g. by remembering the msg id
+    /// and treating the CQE as an orphan (see on_orphan_message() in rt.vdso's
+    /// rt_net.rs). For FS requests, moto_sys_io::io_executor does this: see its
+    /// submit_with_timeout() and submit_cancellable().
+    pub fn submit_sqe_blocking(
+        &self,
+        sqe: Msg,
+        timeout: Option<moto_rt::time::Instant>,
+        policy: RetryPolicy,
+    ) -> Result<(), ErrorCode> {
+        let mut spins = match policy {
+            RetryPolicy::Wait => 0,
+            RetryPolicy::SpinThenWait(spins) => spins,
+        };
 
         loop {
-            slot = &mut raw_channel.server_queue[(pos & QUEUE_MASK) as usize];
-            let stamp = slot.stamp.load(Ordering::Acquire);
+            match self.send(sqe) {
+                Ok(()) => return SysCpu::wake(self.server_handle),
+                Err(moto_rt::E_NOT_READY) => {}
+                Err(err) => return Err(err),
+            }
 
-            if stamp == (pos + 1) {
-                match raw_channel.server_queue_tail.compare_exchange_weak(
-                    pos,
-                    pos + 1,
-                    Ordering::Relaxed,
-                    Ordering::Relaxed,
-                ) {
-                    Ok(_) => break,
-                    Err(tail) => pos = tail, // continue
+            if spins > 0 {
+                spins -= 1;
+                core::hint::spin_loop();
+                continue;
+            }
+
+            if let Some(timeout) = timeout {
+                if moto_rt::time::Instant::now() >= timeout {
+                    return Err(moto_rt::E_TIMED_OUT);
                 }
-            } else if stamp < (pos + 1) {
-                return Err(moto_rt::E_NOT_READY); // The queue is empty.
-            } else {
-                // We lost the race - continue.
-                pos = raw_channel.server_queue_tail.load(Ordering::Relaxed);
+            }
+
+            // Wake the server so that it drains the queue, and wait for it to respond.
+            match SysCpu::wait_uninterruptible(
+                &mut [self.server_handle],
+                SysHandle::NONE,
+                self.server_handle,
+                timeout,
+            ) {
+                Ok(()) | Err(moto_rt::E_TIMED_OUT) => {} // Retry once more after a timeout.
+                Err(err) => return Err(err),
             }
         }
+    }
 
-        let cqe = slot.msg;
-        slot.stamp.store(pos + QUEUE_SIZE, Ordering::Release);
-        Ok(cqe)
+    /// Returns E_NOT_READY if there are no CQEs, and E_NOT_CONNECTED once
+    /// the server has sent its last CQE (see ServerConnection::shutdown_completions())
+    /// and all its CQEs have been received.
+    pub fn recv(&self) -> Result<Msg, ErrorCode> {
+        let mut cqe = [Msg::new()];
+        match self.recv_batch(&mut cqe)? {
+            0 => Err(moto_rt::E_NOT_READY), // The queue is empty.
+            _ => Ok(cqe[0]),
+        }
     }
 
+    /// Receives up to msgs.len() CQEs into @msgs, in queue order, and returns
+    /// how many were received. Does not block: returns Ok(0) if the queue is
+    /// empty, and fewer than msgs.len() if fewer CQEs are ready. Once the
+    /// server has sent its last CQE and the queue is drained, returns E_NOT_CONNECTED.
+    pub fn recv_batch(&self, msgs: &mut [Msg]) -> Result<usize, ErrorCode> {
+        let raw_channel = self.raw_channel();
+        let received = dequeue_batch(
+            &mut raw_channel.server_queue,
+            &raw_channel.server_queue_tail,
+            msgs,
+        );
+        if received == 0
+            && !msgs.is_empty()
+            && RawChannel::is_drained(
+                &raw_channel.server_queue_head,
+                &raw_channel.server_queue_tail,
+            )
+        {
+            return Err(moto_rt::E_NOT_CONNECTED);
+        }
+
+        if let Some(latency) = &self.latency {
+            for cqe in &msgs[0..received] {
+                latency.complete(cqe.id);
+            }
+        }
+        Ok(received)
+    }
+
+    /// Tells the server that no more SQEs are coming: send() fails with
+    /// E_NOT_CONNECTED from now on, while SQEs already sent are still
+    /// received, and completed, by the server, whose recv() returns
+    /// E_NOT_CONNECTED after the last of them. CQEs keep coming until the
+    /// server has completed them all (see recv()), so a client drains them
+    /// after shutting down, and then drops the connection.
+    ///
+    /// The client and the server may shut down concurrently. An SQE sent
+    /// by another thread concurrently with shutdown() is either received
+    /// (and completed) by the server, or fails with E_NOT_CONNECTED.
+    /// Returns E_NOT_CONNECTED if already shut down by either side.
+    pub fn shutdown(&self) -> Result<(), ErrorCode> {
+        RawChannel::close_queue(&self.raw_channel().client_queue_head)?;
+        SysCpu::wake(self.server_handle)
+    }
+
+    /// Allocates a page in the subchannel, growing the pool if needed (see
+    /// [`PoolLimits`]). Returns E_NOT_READY if all pages in the subchannel the pool
+    /// can grow to are in use, and E_INVALID_ARGUMENT if there are no such pages.
     pub fn alloc_page(&self, subchannel_mask: u64) -> Result<IoPage, ErrorCode> {
-        if let Ok(raw_page) = self
+        let raw_page = self
             .raw_channel()
-            .alloc_page(SubChannel::Client(subchannel_mask))
-        {
-            Ok(IoPage {
-                raw_page,
-                raw_channel: self.raw_channel(),
-            })
-        } else {
-            Err(moto_rt::E_NOT_READY)
+            .alloc_page(SubChannel::Client(subchannel_mask))?;
+        Ok(IoPage {
+            raw_page,
+            raw_channel: self.raw_channel(),
+        })
+    }
+
+    /// Allocates `num_pages` pages in the subchannel, all or nothing.
+    ///
+    /// Returns E_INVALID_ARGUMENT if `num_pages` is zero or exceeds
+    /// [`max_pages`]`(subchannel_mask)` (or the pages in the subchannel below
+    /// `PoolLimits::max`), as such a request can never succeed,
+    /// and E_NOT_READY if there are not enough free pages at the moment.
+    pub fn alloc_pages(
+        &self,
+        subchannel_mask: u64,
+        num_pages: usize,
+    ) -> Result<alloc::vec::Vec<IoPage>, ErrorCode> {
+        let max_mask = self
+            .raw_channel()
+            .max_subchannel_mask(SubChannel::Client(subchannel_mask));
+        if num_pages == 0 || num_pages > max_pages(max_mask) {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        let mut pages = alloc::vec::Vec::with_capacity(num_pages);
+        for _ in 0..num_pages {
+            // On error, the pages allocated so far are freed when dropped.
+            pages.push(self.alloc_page(subchannel_mask)?);
         }
+        Ok(pages)
     }
 
+    /// Gets a page passed by the peer via [`IoPage::into_u16`].
+    ///
+    /// Returns E_BAD_DATA if checksums are enabled and the page content
+    /// does not match its checksum; the page is freed in this case.
     pub fn get_page(&self, page_idx: u16) -> Result<IoPage, ErrorCode> {
-        if page_idx & !IoPage::SERVER_FLAG > (CHANNEL_PAGE_COUNT as u16) {
+        if page_idx & !IoPage::SERVER_FLAG >= (CHANNEL_PAGE_COUNT as u16) {
             Err(moto_rt::E_INVALID_ARGUMENT)
         } else {
-            Ok(IoPage::from_u16(page_idx, self.raw_channel()))
+            let page = IoPage::from_u16(page_idx, self.raw_channel());
+            self.raw_channel().validate_page(page.raw_page)?;
+            Ok(page)
         }
     }
 
+    /// Takes ownership of a server-allocated page attached to `cqe` via
+    /// [`ServerConnection::attach_page`] at `slot` in `payload.shared_pages()`.
+    /// The page is returned to the server's pool when dropped.
+    pub fn take_server_page(&self, cqe: &Msg, slot: usize) -> Result<IoPage, ErrorCode> {
+        if cqe.flags & FLAG_CQE_SERVER_PAGES == 0 || slot >= cqe.payload.shared_pages().len() {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        let page_idx = cqe.payload.shared_pages()[slot];
+        if !IoPage::is_server_page(page_idx) {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        self.get_page(page_idx)
+    }
+
     fn raw_channel(&self) -> &'static mut RawChannel {
         unsafe {
             let ptr = self.raw_channel.load(Ordering::Relaxed);
@@ -546,6 +1292,94 @@ impl ClientConnection {
         self.raw_channel().is_empty()
     }
 
+    /// Enables page checksums on this channel (both directions).
+    ///
+    /// This is a debugging aid to catch shared memory corruption: each page
+    /// is checksummed when passed to the peer via [`IoPage::into_u16`] and
+    /// validated in `get_page()`. Slow, so disabled by default.
+    pub fn enable_checksums(&self) {
+        self.raw_channel()
+            .checksums_enabled
+            .store(1, Ordering::Release);
+    }
+
+    pub fn checksums_enabled(&self) -> bool {
+        self.raw_channel().checksums_enabled()
+    }
+
+    /// Usage of the client's page pool (the one `alloc_page()` allocates from).
+    pub fn pool_usage(&self) -> PoolUsage {
+        self.raw_channel().pool_usage(SubChannelType::Client)
+    }
+
+    /// The number of pages `alloc_pages(subchannel_mask, n)` can allocate
+    /// right now. Does not block or make syscalls.
+    ///
+    /// This is a lower bound only while no other thread allocates pages in
+    /// subchannels that overlap `subchannel_mask`: pages freed concurrently
+    /// (by the server or by other threads) only add to it, so allocating that
+    /// many pages then succeeds. Threads that need this at the same time must
+    /// allocate in disjoint subchannels.
+    pub fn free_pages(&self, subchannel_mask: u64) -> usize {
+        self.raw_channel()
+            .free_pages(SubChannel::Client(subchannel_mask))
+    }
+
+    /// The number of messages `send()` can enqueue right now without returning
+    /// E_NOT_READY. Does not block or make syscalls.
+    ///
+    /// This is a lower bound only while no other thread sends on the connection:
+    /// the server taking messages concurrently only adds to it. There is one
+    /// queue per connection, so there is no bound for concurrent senders.
+    pub fn send_queue_space(&self) -> usize {
+        let raw_channel = self.raw_channel();
+        RawChannel::queue_space(&raw_channel.client_queue, &raw_channel.client_queue_head)
+    }
+
+    /// Resets the high-water mark reported by [`Self::pool_usage`] to the current usage.
+    pub fn reset_pool_high_water(&self) {
+        self.raw_channel().reset_high_water(SubChannelType::Client)
+    }
+
+    /// Starts recording the round-trip latency of requests, from `send()`
+    /// of an SQE to `recv()` of the CQE with the same `id`, as seen by the client
+    /// (i.e. including queueing and wakeups). See [`Self::latency_histogram`].
+    ///
+    /// Requests are matched by `Msg::id`, so ids of requests in flight must be unique.
+    pub fn enable_latency_histogram(&mut self) {
+        if self.latency.is_none() {
+            self.latency = Some(LatencyRecorder::new());
+        }
+    }
+
+    /// Latencies recorded since [`Self::enable_latency_histogram`] (or the last
+    /// [`Self::reset_latency_histogram`]); None if not enabled.
+    pub fn latency_histogram(&self) -> Option<LatencyHistogram> {
+        self.latency.as_ref().map(|latency| latency.snapshot())
+    }
+
+    pub fn reset_latency_histogram(&self) {
+        if let Some(latency) = &self.latency {
+            latency.reset();
+        }
+    }
+
+    /// Sets how the client's page pool grows; the default is [`PoolLimits::FIXED`].
+    /// The capacity is set to `limits.initial`; pages in use above it stay valid.
+    /// Returns E_INVALID_ARGUMENT if `limits` are not valid.
+    ///
+    /// The channel is always mapped whole, so limiting the pool does not save
+    /// memory: it bounds the pages the client has in flight, and
+    /// [`Self::pool_usage`] shows how much of the pool a workload needs.
+    pub fn set_pool_limits(&self, limits: PoolLimits) -> Result<(), ErrorCode> {
+        self.raw_channel()
+            .set_pool_limits(SubChannelType::Client, limits)
+    }
+
+    pub fn pool_limits(&self) -> PoolLimits {
+        self.raw_channel().pool_limits(SubChannelType::Client)
+    }
+
     pub fn dump_state(&self) {
         self.raw_channel().dump_state()
     }
@@ -562,6 +1396,7 @@ pub struct ServerConnection {
     raw_channel: *mut RawChannel,
     wait_handle: SysHandle,
     status: ServerStatus,
+    latency: Option<alloc::boxed::Box<LatencyRecorder>>,
 }
 
 impl Drop for ServerConnection {
@@ -573,6 +1408,13 @@ impl Drop for ServerConnection {
 }
 
 impl ServerConnection {
+    /// Same as [`Self::create`], but fails with E_INVALID_ARGUMENT unless every
+    /// page of the channel is aligned to `alignment` (see [`PAGE_ALIGNMENT`]).
+    pub fn create_aligned(url: &str, alignment: usize) -> Result<Self, ErrorCode> {
+        check_alignment(alignment)?;
+        Self::create(url)
+    }
+
     pub fn create(url: &str) -> Result<Self, ErrorCode> {
         let addr = SysMem::map(
             SysHandle::SELF,
@@ -598,6 +1440,7 @@ impl ServerConnection {
             raw_channel: addr as usize as *mut RawChannel,
             wait_handle,
             status: ServerStatus::Created,
+            latency: None,
         })
     }
 
@@ -605,41 +1448,44 @@ impl ServerConnection {
         self.status
     }
 
-    // See dequeue() in mpmc.cc.
+    /// Returns E_NOT_READY if there are no SQEs, and E_NOT_CONNECTED once
+    /// the client has shut down and all its SQEs have been received.
     pub fn recv(&self) -> Result<Msg, ErrorCode> {
+        let mut sqe = [Msg::new()];
+        match self.recv_batch(&mut sqe)? {
+            0 => Err(moto_rt::E_NOT_READY), // The queue is empty.
+            _ => Ok(sqe[0]),
+        }
+    }
+
+    /// Receives up to msgs.len() SQEs into @msgs; see ClientConnection::recv_batch().
+    pub fn recv_batch(&self, msgs: &mut [Msg]) -> Result<usize, ErrorCode> {
         if self.status != ServerStatus::Connected {
             return Err(moto_rt::E_INVALID_ARGUMENT);
         }
 
         let raw_channel = self.raw_channel();
+        let received = dequeue_batch(
+            &mut raw_channel.client_queue,
+            &raw_channel.client_queue_tail,
+            msgs,
+        );
+        if received == 0
+            && !msgs.is_empty()
+            && RawChannel::is_drained(
+                &raw_channel.client_queue_head,
+                &raw_channel.client_queue_tail,
+            )
+        {
+            return Err(moto_rt::E_NOT_CONNECTED);
+        }
 
-        let mut slot: &mut MsgSlot;
-        let mut pos = raw_channel.client_queue_tail.load(Ordering::Relaxed);
-        loop {
-            slot = &mut raw_channel.client_queue[(pos & QUEUE_MASK) as usize];
-            let stamp = slot.stamp.load(Ordering::Acquire);
-
-            if stamp == (pos + 1) {
-                match raw_channel.client_queue_tail.compare_exchange_weak(
-                    pos,
-                    pos + 1,
-                    Ordering::Relaxed,
-                    Ordering::Relaxed,
-                ) {
-                    Ok(_) => break,
-                    Err(tail) => pos = tail, // continue
-                }
-            } else if stamp < (pos + 1) {
-                return Err(moto_rt::E_NOT_READY); // The queue is empty.
-            } else {
-                // We lost the race - continue.
-                pos = raw_channel.client_queue_tail.load(Ordering::Relaxed);
+        if let Some(latency) = &self.latency {
+            for sqe in &msgs[0..received] {
+                latency.start(sqe.id);
             }
         }
-
-        let sqe = slot.msg;
-        slot.stamp.store(pos + QUEUE_SIZE, Ordering::Release);
-        Ok(sqe)
+        Ok(received)
     }
 
     // See enqueue() in mpmc.cc.
@@ -649,11 +1495,13 @@ impl ServerConnection {
         }
 
         let raw_channel = self.raw_channel();
-
         let mut slot: &mut MsgSlot;
         let mut pos = raw_channel.server_queue_head.load(Ordering::Relaxed);
 
         loop {
+            if pos & QUEUE_CLOSED != 0 {
+                return Err(moto_rt::E_NOT_CONNECTED);
+            }
             slot = &mut raw_channel.server_queue[(pos & QUEUE_MASK) as usize];
             let stamp = slot.stamp.load(Ordering::Acquire);
 
@@ -677,13 +1525,93 @@ impl ServerConnection {
 
         slot.msg = sqe;
         slot.stamp.store(pos + 1, Ordering::Release);
+        if let Some(latency) = &self.latency {
+            latency.complete(sqe.id);
+        }
         Ok(())
     }
 
+    /// Completes @cqes, in order, and then wakes the client once (if anything
+    /// was sent), instead of once per completion. Stops at the first CQE that
+    /// does not fit into the queue; returns the number of CQEs sent, so that
+    /// the caller can retry the rest later.
+    ///
+    /// Ordering: CQEs are enqueued in slice order, and, as the queue is FIFO,
+    /// a client thread calling recv() sees them in that order. The client
+    /// may see (and process) a prefix of the batch before the wakeup.
+    pub fn send_batch(&self, cqes: &[Msg]) -> Result<usize, ErrorCode> {
+        if self.status != ServerStatus::Connected {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        let mut sent = 0;
+        for cqe in cqes {
+            match self.send(*cqe) {
+                Ok(()) => sent += 1,
+                Err(moto_rt::E_NOT_CONNECTED) if sent == 0 => return Err(moto_rt::E_NOT_CONNECTED),
+                Err(_) => break,
+            }
+        }
+
+        if sent > 0 {
+            SysCpu::wake(self.wait_handle)?;
+        }
+        Ok(sent)
+    }
+
     pub fn wait_handle(&self) -> SysHandle {
         self.wait_handle
     }
 
+    /// Stops accepting SQEs: the client's send() fails with E_NOT_CONNECTED
+    /// from now on, as if the client had called ClientConnection::shutdown().
+    /// SQEs the client has already sent are still received here, until
+    /// recv() returns E_NOT_CONNECTED, and should be completed as usual;
+    /// then shutdown_completions() tells the client that it has got all
+    /// its CQEs. Returns E_NOT_CONNECTED if already shut down by either side.
+    pub fn shutdown(&self) -> Result<(), ErrorCode> {
+        if self.status != ServerStatus::Connected {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        RawChannel::close_queue(&self.raw_channel().client_queue_head)?;
+        // Wake a client blocked in submit_sqe_blocking().
+        SysCpu::wake(self.wait_handle)
+    }
+
+    /// Tells the client that no more CQEs are coming, after the last CQE
+    /// sent: the client's recv() returns E_NOT_CONNECTED once it has received
+    /// them all, and send() here fails with E_NOT_CONNECTED from now on.
+    ///
+    /// Shuts down SQEs first (see shutdown()), if not done yet. Returns
+    /// E_NOT_READY if there are SQEs that have not been received yet: they
+    /// must be received and completed before the CQE queue is closed, so
+    /// the client does not wait for CQEs that never come. Returns
+    /// E_NOT_CONNECTED if already called.
+    pub fn shutdown_completions(&self) -> Result<(), ErrorCode> {
+        if self.status != ServerStatus::Connected {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        let raw_channel = self.raw_channel();
+        let _ = RawChannel::close_queue(&raw_channel.client_queue_head);
+        if !RawChannel::is_drained(
+            &raw_channel.client_queue_head,
+            &raw_channel.client_queue_tail,
+        ) {
+            return Err(moto_rt::E_NOT_READY);
+        }
+
+        RawChannel::close_queue(&raw_channel.server_queue_head)?;
+        SysCpu::wake(self.wait_handle)
+    }
+
+    /// (PID, capabilities) of the connected client process, as known to the kernel.
+    /// Can be used to authorize clients without trusting a handshake.
+    pub fn peer_credentials(&self) -> Result<(u64, u64), ErrorCode> {
+        SysObj::get_peer_credentials(self.wait_handle)
+    }
+
     // Unsafe because it assumes wait on wait_handle succeeded. Otherwise
     // raw_buf pointer could still be unmapped.
     pub unsafe fn accept(&mut self) -> Result<(), ErrorCode> {
@@ -718,26 +1646,100 @@ impl ServerConnection {
         self.wait_handle = SysHandle::NONE;
     }
 
+    /// Allocates a page in the subchannel, growing the pool if needed (see
+    /// [`PoolLimits`]). Returns E_NOT_READY if all pages in the subchannel the pool
+    /// can grow to are in use, and E_INVALID_ARGUMENT if there are no such pages.
     pub fn alloc_page(&self, subchannel_mask: u64) -> Result<IoPage, ErrorCode> {
-        if let Ok(raw_page) = self
+        let raw_page = self
             .raw_channel()
-            .alloc_page(SubChannel::Server(subchannel_mask))
-        {
-            Ok(IoPage {
-                raw_page,
-                raw_channel: self.raw_channel(),
-            })
-        } else {
-            Err(moto_rt::E_NOT_READY)
+            .alloc_page(SubChannel::Server(subchannel_mask))?;
+        Ok(IoPage {
+            raw_page,
+            raw_channel: self.raw_channel(),
+        })
+    }
+
+    /// Allocates `num_pages` pages in the subchannel, all or nothing.
+    ///
+    /// Returns E_INVALID_ARGUMENT if `num_pages` is zero or exceeds
+    /// [`max_pages`]`(subchannel_mask)` (or the pages in the subchannel below
+    /// `PoolLimits::max`), as such a request can never succeed,
+    /// and E_NOT_READY if there are not enough free pages at the moment.
+    pub fn alloc_pages(
+        &self,
+        subchannel_mask: u64,
+        num_pages: usize,
+    ) -> Result<alloc::vec::Vec<IoPage>, ErrorCode> {
+        let max_mask = self
+            .raw_channel()
+            .max_subchannel_mask(SubChannel::Server(subchannel_mask));
+        if num_pages == 0 || num_pages > max_pages(max_mask) {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
         }
+
+        let mut pages = alloc::vec::Vec::with_capacity(num_pages);
+        for _ in 0..num_pages {
+            // On error, the pages allocated so far are freed when dropped.
+            pages.push(self.alloc_page(subchannel_mask)?);
+        }
+        Ok(pages)
     }
 
+    /// Gets a page passed by the peer via [`IoPage::into_u16`].
+    ///
+    /// Returns E_BAD_DATA if checksums are enabled and the page content
+    /// does not match its checksum; the page is freed in this case.
     pub fn get_page(&self, page_idx: u16) -> Result<IoPage, ErrorCode> {
-        if page_idx & !IoPage::SERVER_FLAG > (CHANNEL_PAGE_COUNT as u16) {
+        if page_idx & !IoPage::SERVER_FLAG >= (CHANNEL_PAGE_COUNT as u16) {
             Err(moto_rt::E_INVALID_ARGUMENT)
         } else {
-            Ok(IoPage::from_u16(page_idx, self.raw_channel()))
+            let page = IoPage::from_u16(page_idx, self.raw_channel());
+            self.raw_channel().validate_page(page.raw_page)?;
+            Ok(page)
+        }
+    }
+
+    /// Takes the pages of a scatter/gather message (see Payload::shared_page_count()),
+    /// in slot order. If the slots are not valid, the pages found are freed
+    /// and E_INVALID_ARGUMENT is returned.
+    pub fn get_pages(
+        &self,
+        msg: &Msg,
+        max_slots: usize,
+    ) -> Result<alloc::vec::Vec<IoPage>, ErrorCode> {
+        let slots = &msg.payload.shared_pages()[0..max_slots];
+        let count = msg.payload.shared_page_count(max_slots);
+
+        let mut pages = alloc::vec::Vec::with_capacity(max_slots);
+        let mut result = Ok(());
+        for page_idx in slots.iter().filter(|page_idx| **page_idx != NO_PAGE) {
+            match self.get_page(*page_idx) {
+                Ok(page) => pages.push(page),
+                Err(err) => result = Err(err),
+            }
         }
+
+        count?;
+        result?;
+        Ok(pages)
+    }
+
+    /// Attaches a page allocated via [`Self::alloc_page`] to a completion at
+    /// `slot` in `payload.shared_pages()`, transferring its ownership to the client.
+    /// Useful when the size of the response is not known when the request is submitted,
+    /// so the response does not have to reuse the request's pages.
+    ///
+    /// Returns E_INVALID_ARGUMENT if `page` is not from the server's pool (e.g. it
+    /// is a page of the request), or if `slot` is out of range; `page` is freed then.
+    pub fn attach_page(cqe: &mut Msg, slot: usize, page: IoPage) -> Result<(), ErrorCode> {
+        if !matches!(page.raw_page.s_type, SubChannelType::Server)
+            || slot >= cqe.payload.shared_pages().len()
+        {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+        cqe.payload.shared_pages_mut()[slot] = IoPage::into_u16(page);
+        cqe.flags |= FLAG_CQE_SERVER_PAGES;
+        Ok(())
     }
 
     fn raw_channel(&self) -> &'static mut RawChannel {
@@ -752,6 +1754,62 @@ impl ServerConnection {
         }
     }
 
+    /// See [`ClientConnection::enable_checksums`].
+    pub fn enable_checksums(&self) {
+        self.raw_channel()
+            .checksums_enabled
+            .store(1, Ordering::Release);
+    }
+
+    pub fn checksums_enabled(&self) -> bool {
+        self.raw_channel().checksums_enabled()
+    }
+
+    /// Usage of the server's page pool (the one `alloc_page()` allocates from).
+    pub fn pool_usage(&self) -> PoolUsage {
+        self.raw_channel().pool_usage(SubChannelType::Server)
+    }
+
+    /// Resets the high-water mark reported by [`Self::pool_usage`] to the current usage.
+    pub fn reset_pool_high_water(&self) {
+        self.raw_channel().reset_high_water(SubChannelType::Server)
+    }
+
+    /// Starts recording the service latency of requests, from `recv()` of an SQE
+    /// to `send()` (or `send_batch()`) of the CQE with the same `id`.
+    /// See [`Self::latency_histogram`].
+    ///
+    /// The SQE carries no submit timestamp, so the time the request spent
+    /// in the queue before `recv()` is not included; use
+    /// [`ClientConnection::enable_latency_histogram`] to measure round trips.
+    pub fn enable_latency_histogram(&mut self) {
+        if self.latency.is_none() {
+            self.latency = Some(LatencyRecorder::new());
+        }
+    }
+
+    /// Latencies recorded since [`Self::enable_latency_histogram`] (or the last
+    /// [`Self::reset_latency_histogram`]); None if not enabled.
+    pub fn latency_histogram(&self) -> Option<LatencyHistogram> {
+        self.latency.as_ref().map(|latency| latency.snapshot())
+    }
+
+    pub fn reset_latency_histogram(&self) {
+        if let Some(latency) = &self.latency {
+            latency.reset();
+        }
+    }
+
+    /// See [`ClientConnection::set_pool_limits`]; applies to the server's page pool.
+    pub fn set_pool_limits(&self, limits: PoolLimits) -> Result<(), ErrorCode> {
+        self.raw_channel()
+            .set_pool_limits(SubChannelType::Server, limits)
+    }
+
+    pub fn pool_limits(&self) -> PoolLimits {
+        self.raw_channel().pool_limits(SubChannelType::Server)
+    }
+
     pub fn dump_state(&self) {
         self.raw_channel().dump_state()
     }
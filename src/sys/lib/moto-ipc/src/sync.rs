@@ -257,7 +257,12 @@ impl ClientConnection {
 
             loop {
                 let mut handles = [self.handle];
-                let res = SysCpu::wait(&mut handles, self.handle, SysHandle::NONE, timeout);
+                let res = SysCpu::wait_uninterruptible(
+                    &mut handles,
+                    self.handle,
+                    SysHandle::NONE,
+                    timeout,
+                );
 
                 if res.is_ok() {
                     let seq = self.resp::<ResponseHeader>().seq.load(Ordering::SeqCst);
@@ -548,6 +553,16 @@ impl LocalServer {
         &mut self,
         swap_target: SysHandle,
         extra_waiters: &[SysHandle],
+    ) -> Result<Vec<SysHandle>, Vec<SysHandle>> {
+        self.wait_timeout(swap_target, extra_waiters, None)
+    }
+
+    /// Same as wait(), but returns no wakers if nothing happens until @timeout.
+    pub fn wait_timeout(
+        &mut self,
+        swap_target: SysHandle,
+        extra_waiters: &[SysHandle],
+        timeout: Option<moto_rt::time::Instant>,
     ) -> Result<Vec<SysHandle>, Vec<SysHandle>> {
         while self.listeners.len() < (self.max_listeners as usize)
             && (self.listeners.len() + self.active_conns.len() < (self.max_connections as usize))
@@ -581,7 +596,11 @@ impl LocalServer {
         }
 
         core::sync::atomic::fence(core::sync::atomic::Ordering::Release);
-        SysCpu::wait(&mut waiters[..], swap_target, SysHandle::NONE, None).map_err(|err| {
+        let result = SysCpu::wait(&mut waiters[..], swap_target, SysHandle::NONE, timeout);
+        if result == Err(moto_rt::E_TIMED_OUT) {
+            return Ok(Vec::new());
+        }
+        result.map_err(|err| {
             assert_eq!(err, moto_rt::E_BAD_HANDLE);
             let mut bad_extras = Vec::new();
             for waiter in &waiters {
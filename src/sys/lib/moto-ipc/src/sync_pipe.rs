@@ -173,16 +173,27 @@ impl PipeBuffer {
 
 pub struct Reader {
     buffer: PipeBuffer,
+    nonblocking: bool,
 }
 
 pub struct Writer {
     buffer: PipeBuffer,
+    nonblocking: bool,
 }
 
 const fn is_power_of_two(val: usize) -> bool {
     (val & (val - 1)) == 0
 }
 
+// Waits and wakes on the pipe's IPC handle fail if the other end is gone.
+fn pipe_error(err: ErrorCode) -> ErrorCode {
+    if err == moto_rt::E_BAD_HANDLE {
+        moto_rt::E_BROKEN_PIPE
+    } else {
+        err
+    }
+}
+
 impl Reader {
     pub unsafe fn new(pipe_data: RawPipeData) -> Reader {
         Reader {
@@ -191,9 +202,15 @@ impl Reader {
                 pipe_data.buf_size,
                 SysHandle::from_u64(pipe_data.ipc_handle),
             ),
+            nonblocking: false,
         }
     }
 
+    /// If set, reads return E_NOT_READY instead of blocking.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
     pub fn handle(&self) -> SysHandle {
         self.buffer.ipc_handle
     }
@@ -219,13 +236,29 @@ impl Reader {
                 if self.buffer.error_code != moto_rt::E_OK {
                     break 'outer;
                 }
-                if let Err(e) = SysCpu::wait(
+                if self.nonblocking {
+                    // Don't block, but find out if the writer is gone.
+                    if let Err(e) = SysCpu::wait_uninterruptible(
+                        &mut [self.buffer.ipc_handle],
+                        SysHandle::NONE,
+                        SysHandle::NONE,
+                        Some(moto_rt::time::Instant::nan()),
+                    ) {
+                        self.buffer.error_code = pipe_error(e);
+                        break 'outer;
+                    }
+                    if !self.buffer.can_read() {
+                        return Err(moto_rt::E_NOT_READY);
+                    }
+                    continue;
+                }
+                if let Err(e) = SysCpu::wait_uninterruptible(
                     &mut [self.buffer.ipc_handle],
                     self.buffer.ipc_handle,
                     SysHandle::NONE,
                     timeout,
                 ) {
-                    self.buffer.error_code = e;
+                    self.buffer.error_code = pipe_error(e);
                     break 'outer;
                 }
             }
@@ -236,7 +269,7 @@ impl Reader {
                 }
                 if let Err(e) = SysCpu::wake(self.buffer.ipc_handle) {
                     // Cache the error.
-                    self.buffer.error_code = e;
+                    self.buffer.error_code = pipe_error(e);
                 }
                 return Ok(read);
             }
@@ -273,9 +306,16 @@ impl Writer {
                 pipe_data.buf_size,
                 SysHandle::from_u64(pipe_data.ipc_handle),
             ),
+            nonblocking: false,
         }
     }
 
+    /// If set, writes return what fits into the pipe (or E_NOT_READY if nothing
+    /// does) instead of blocking.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
     pub fn handle(&self) -> SysHandle {
         self.buffer.ipc_handle
     }
@@ -300,13 +340,21 @@ impl Writer {
         let mut written = 0_usize;
 
         loop {
+            if self.nonblocking && !self.buffer.can_write() {
+                if written == 0 {
+                    return Err(moto_rt::E_NOT_READY);
+                }
+                return self.wake_reader(written);
+            }
+
             while !self.buffer.can_write() {
-                if let Err(err) = SysCpu::wait(
+                if let Err(err) = SysCpu::wait_uninterruptible(
                     &mut [self.buffer.ipc_handle],
                     self.buffer.ipc_handle,
                     SysHandle::NONE,
                     timeout,
                 ) {
+                    let err = pipe_error(err);
                     self.buffer.error_code = err;
                     written = written.checked_sub(self.buffer.unwrite()).unwrap_or(0);
                     if written > 0 {
@@ -319,19 +367,25 @@ impl Writer {
 
             written += self.buffer.write(&buf[written..]);
             if written == buf.len() {
-                if let Err(err) = SysCpu::wake(self.buffer.ipc_handle) {
-                    // Cache the error.
-                    self.buffer.error_code = err;
-                    written = written.checked_sub(self.buffer.unwrite()).unwrap_or(0);
-                    if written > 0 {
-                        return Ok(written);
-                    } else {
-                        return Err(err);
-                    }
-                }
+                return self.wake_reader(written);
+            }
+        }
+    }
+
+    // Wakes the reader after @written bytes have been written.
+    fn wake_reader(&mut self, mut written: usize) -> Result<usize, ErrorCode> {
+        if let Err(err) = SysCpu::wake(self.buffer.ipc_handle) {
+            // Cache the error.
+            let err = pipe_error(err);
+            self.buffer.error_code = err;
+            written = written.checked_sub(self.buffer.unwrite()).unwrap_or(0);
+            if written > 0 {
                 return Ok(written);
+            } else {
+                return Err(err);
             }
         }
+        Ok(written)
     }
 
     pub fn total_written(&self) -> usize {
@@ -412,6 +466,14 @@ impl Pipe {
         }
     }
 
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        match self {
+            Self::Reader(reader) => reader.set_nonblocking(nonblocking),
+            Self::Writer(writer) => writer.set_nonblocking(nonblocking),
+            _ => {}
+        }
+    }
+
     pub fn handle(&self) -> SysHandle {
         match self {
             Self::Reader(reader) => reader.buffer.ipc_handle,
@@ -21,6 +21,16 @@ pub const E_NOT_A_DIRECTORY: u16 = 17;
 pub const E_BAD_HANDLE: u16 = 18;
 pub const E_FILE_TOO_LARGE: u16 = 19;
 pub const E_BUFFER_FULL: u16 = 20;
+pub const E_BAD_DATA: u16 = 21;
+pub const E_INTERRUPTED: u16 = 22;
+pub const E_ADDR_NOT_AVAILABLE: u16 = 23;
+pub const E_QUOTA_EXCEEDED: u16 = 24;
+pub const E_IO_ERROR: u16 = 25; // The storage device failed the request.
+pub const E_BROKEN_PIPE: u16 = 26; // The other end of the pipe is closed.
+pub const E_DEADLOCK: u16 = 27; // The wait would never return; see SysRay::OP_DEADLOCK_DETECTION.
+pub const E_MSG_TOO_LARGE: u16 = 28; // The datagram does not fit in one message (EMSGSIZE).
+pub const E_NOT_CONNECTED: u16 = 29; // The connection (or this direction of it) is shut down.
+pub const E_CONNECTION_RESET: u16 = 30; // The peer reset the connection (ECONNRESET).
 
 pub const E_MAX: u16 = u16::MAX;
 
@@ -78,6 +88,17 @@ pub fn log_panic(info: &core::panic::PanicInfo<'_>) {
         let _ = crate::fs::write(crate::FD_STDERR, msg.as_str().as_bytes());
         log_backtrace(crate::FD_STDERR);
     }
+
+    // The kernel may shut down or reboot the system here, if the process
+    // has asked for it; see moto_sys::SysRay::set_panic_action().
+    let vdso_on_panic: extern "C" fn() = unsafe {
+        core::mem::transmute(
+            super::RtVdsoVtableV1::get()
+                .proc_on_panic
+                .load(core::sync::atomic::Ordering::Relaxed) as usize as *const (),
+        )
+    };
+    vdso_on_panic();
 }
 
 #[cfg(not(feature = "base"))]
@@ -46,6 +46,24 @@ pub const O_APPEND: u32 = 1 << 2;
 pub const O_TRUNCATE: u32 = 1 << 3;
 pub const O_CREATE: u32 = 1 << 4;
 pub const O_CREATE_NEW: u32 = 1 << 5;
+/// Writes through this fd are not flushed to the device one by one: they
+/// are written back in the background, per the WritebackPolicy, or by
+/// fsync(), sync_files(), or writeback(), whichever comes first.
+pub const O_WRITEBACK: u32 = 1 << 6;
+
+/// pipe() flag: reads and writes return E_NOT_READY instead of blocking.
+pub const PIPE_NONBLOCK: u32 = 1;
+
+/// The maximum number of segments in a vectored read or write.
+pub const MAX_IOV: usize = 16;
+
+/// A buffer segment of a vectored read or write, as struct iovec.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct IoVec {
+    pub base: usize,
+    pub len: usize,
+}
 
 // Seek option.
 pub const SEEK_SET: u8 = 0;
@@ -101,6 +119,96 @@ impl DirEntry {
     }
 }
 
+/// No limit, in DirQuota::max_bytes or DirQuota::max_inodes.
+pub const QUOTA_UNLIMITED: u64 = u64::MAX;
+
+/// A directory quota: limits, and the current usage of the directory's
+/// subtree (not counting the directory itself). Inodes are files and directories.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct DirQuota {
+    pub max_bytes: u64,
+    pub max_inodes: u64,
+    pub used_bytes: u64,
+    pub used_inodes: u64,
+}
+
+/// When data written via O_WRITEBACK fds, but not yet flushed to the device,
+/// is written back. See set_writeback_policy().
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WritebackPolicy {
+    /// Write back as soon as this many bytes are dirty. There is no cache
+    /// in sys-io to take a ratio of, so the limit is in bytes.
+    pub max_dirty_bytes: u64,
+    /// Write back once the oldest dirty write is this old, in milliseconds.
+    pub max_dirty_age_ms: u64,
+}
+
+impl Default for WritebackPolicy {
+    fn default() -> Self {
+        Self {
+            max_dirty_bytes: 16 << 20,
+            max_dirty_age_ms: 5_000,
+        }
+    }
+}
+
+/// See writeback_stats().
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct WritebackStats {
+    pub policy: WritebackPolicy,
+    /// Bytes written since the last writeback.
+    pub dirty_bytes: u64,
+    /// 4K pages written to since the last writeback (a page written to
+    /// twice is counted twice).
+    pub dirty_pages: u64,
+    /// Writebacks done by the policy (not counting fsync() etc.).
+    pub background_writebacks: u64,
+}
+
+// Ops of RtVdsoVtableV1::fs_writeback.
+#[doc(hidden)]
+pub const WRITEBACK_OP_STATS: u32 = 0;
+#[doc(hidden)]
+pub const WRITEBACK_OP_SET_POLICY: u32 = 1;
+#[doc(hidden)]
+pub const WRITEBACK_OP_FORCE: u32 = 2;
+
+/// The volume a file is on; see statfs().
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct StatFs {
+    /// The logical block size of the device.
+    pub block_size: u32,
+    /// Larger reads and writes are issued to the device in requests of this size.
+    pub optimal_io_size: u32,
+    pub total_bytes: u64,
+    /// How many requests of a read or write are in flight at once; see set_queue_depth().
+    pub queue_depth: u32,
+    /// The largest queue_depth the device allows.
+    pub max_queue_depth: u32,
+    /// The most requests in flight at once since the last set_queue_depth().
+    pub peak_in_flight: u32,
+    /// Requests completed by the device since start.
+    pub completed_requests: u64,
+}
+
+/// The layout of a file on the device; see file_extents().
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct FileExtents {
+    /// The number of FS blocks the file's data is in (zero for small files,
+    /// stored with their metadata).
+    pub blocks: u64,
+    /// The number of runs of blocks consecutive on the device: 1 for a
+    /// contiguous file.
+    pub extents: u64,
+    /// Blocks still to be moved by defragment().
+    pub pending_blocks: u64,
+}
+
 pub fn is_terminal(rt_fd: RtFd) -> bool {
     let vdso_is_terminal: extern "C" fn(i32) -> i32 = unsafe {
         core::mem::transmute(
@@ -125,6 +233,25 @@ pub fn duplicate(rt_fd: RtFd) -> Result<RtFd, ErrorCode> {
     to_result!(vdso_duplicate(rt_fd))
 }
 
+/// Creates an anonymous pipe, a byte stream: returns (read fd, write fd).
+///
+/// A read returns 0 (EOF) once the write end is closed and all data is read;
+/// a write fails with E_BROKEN_PIPE once the read end is closed. Either end
+/// can be passed to a child at spawn, but only if blocking (see SpawnArgs).
+pub fn pipe(flags: u32) -> Result<(RtFd, RtFd), ErrorCode> {
+    let vdso_pipe: extern "C" fn(u32, *mut RtFd) -> ErrorCode = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get().fs_pipe.load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    let mut fds = [0; 2];
+    match vdso_pipe(flags, fds.as_mut_ptr()) {
+        E_OK => Ok((fds[0], fds[1])),
+        err => Err(err),
+    }
+}
+
 /// Opens a file at `path` with options specified by `opts`.
 pub fn open(path: &str, opts: u32) -> Result<RtFd, ErrorCode> {
     let vdso_open: extern "C" fn(*const u8, usize, u32) -> i32 = unsafe {
@@ -163,6 +290,9 @@ pub fn get_file_attr(rt_fd: RtFd) -> Result<FileAttr, ErrorCode> {
     }
 }
 
+/// Makes all completed writes to the file durable, including writes done
+/// through other fds (see O_WRITEBACK). Returns only after the storage
+/// device has acknowledged the flush; fails with E_IO_ERROR if it did not.
 pub fn fsync(rt_fd: RtFd) -> Result<(), ErrorCode> {
     let vdso_fsync: extern "C" fn(i32) -> ErrorCode = unsafe {
         core::mem::transmute(
@@ -173,6 +303,7 @@ pub fn fsync(rt_fd: RtFd) -> Result<(), ErrorCode> {
     ok_or_error(vdso_fsync(rt_fd))
 }
 
+/// Same as fsync(): file metadata is always flushed with the data.
 pub fn datasync(rt_fd: RtFd) -> Result<(), ErrorCode> {
     let vdso_datasync: extern "C" fn(i32) -> ErrorCode = unsafe {
         core::mem::transmute(
@@ -183,6 +314,277 @@ pub fn datasync(rt_fd: RtFd) -> Result<(), ErrorCode> {
     ok_or_error(vdso_datasync(rt_fd))
 }
 
+/// Makes all completed writes to `files`, and to `parent_dir` (an fd
+/// returned by opendir(), if any), durable with a single device flush.
+/// Either all fds are valid and durable on return, or an error is
+/// returned; this is not a transaction: if the system crashes before
+/// this function returns, any subset of the writes may have persisted.
+pub fn sync_files(files: &[RtFd], parent_dir: Option<RtFd>) -> Result<(), ErrorCode> {
+    let vdso_sync_files: extern "C" fn(*const RtFd, usize, RtFd) -> ErrorCode = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get().fs_sync_files.load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    ok_or_error(vdso_sync_files(
+        files.as_ptr(),
+        files.len(),
+        parent_dir.unwrap_or(-1),
+    ))
+}
+
+/// Limits the total size of files, and the number of files and directories,
+/// under directory `path`. Writes, creates, and renames that would exceed
+/// the quota fail with E_QUOTA_EXCEEDED. QUOTA_UNLIMITED for both limits
+/// removes the quota. Requires CAP_SYS. Quotas are persisted on the volume,
+/// and their usage is recomputed when the FS driver restarts.
+pub fn set_dir_quota(path: &str, max_bytes: u64, max_inodes: u64) -> Result<(), ErrorCode> {
+    let vdso_set_dir_quota: extern "C" fn(*const u8, usize, u64, u64) -> ErrorCode = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get()
+                .fs_set_dir_quota
+                .load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    let bytes = path.as_bytes();
+    ok_or_error(vdso_set_dir_quota(
+        bytes.as_ptr(),
+        bytes.len(),
+        max_bytes,
+        max_inodes,
+    ))
+}
+
+/// Returns the quota set on directory `path` via set_dir_quota(), with its
+/// current usage, or E_NOT_FOUND if there is none.
+pub fn dir_quota(path: &str) -> Result<DirQuota, ErrorCode> {
+    let vdso_get_dir_quota: extern "C" fn(*const u8, usize, *mut DirQuota) -> ErrorCode = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get()
+                .fs_get_dir_quota
+                .load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    let bytes = path.as_bytes();
+    let mut quota = DirQuota::default();
+
+    match vdso_get_dir_quota(bytes.as_ptr(), bytes.len(), &mut quota) {
+        E_OK => Ok(quota),
+        err => Err(err),
+    }
+}
+
+/// The volume `path` is on.
+pub fn statfs(path: &str) -> Result<StatFs, ErrorCode> {
+    let vdso_statfs: extern "C" fn(*const u8, usize, *mut StatFs) -> ErrorCode = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get().fs_statfs.load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    let bytes = path.as_bytes();
+    let mut statfs = StatFs::default();
+
+    match vdso_statfs(bytes.as_ptr(), bytes.len(), &mut statfs) {
+        E_OK => Ok(statfs),
+        err => Err(err),
+    }
+}
+
+/// Sets how many requests of a read or write are issued to the device that
+/// `path` is on before waiting for the first of them to complete: deeper
+/// queues trade latency for throughput. Zero means `max_queue_depth` (see
+/// statfs()), which is the default and the upper bound. Not persisted across
+/// restarts. Requires CAP_SYS (E_NOT_ALLOWED otherwise).
+pub fn set_queue_depth(path: &str, depth: u32) -> Result<(), ErrorCode> {
+    let vdso_set_queue_depth: extern "C" fn(*const u8, usize, u32) -> ErrorCode = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get()
+                .fs_set_queue_depth
+                .load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    let bytes = path.as_bytes();
+    match vdso_set_queue_depth(bytes.as_ptr(), bytes.len(), depth) {
+        E_OK => Ok(()),
+        err => Err(err),
+    }
+}
+
+/// Snapshot `id` (see snapshot()) is read at `SNAPSHOT_DIR/<id>/<path>`,
+/// where `<path>` is relative to the snapshotted directory.
+pub const SNAPSHOT_DIR: &str = "/.snapshot";
+
+/// Takes a read-only, point-in-time snapshot of directory `path` ("/" for
+/// the whole volume) and returns its ID. Taking a snapshot is cheap, and
+/// writers are not paused: the state of a file or directory at snapshot time
+/// is copied aside the first time it changes. So while a snapshot is held,
+/// every file changed or removed under `path` uses its full size again
+/// (once per snapshot), and space of removed files is not freed until
+/// release_snapshot(); the copies are charged to directory quotas above the
+/// files they preserve. Requires CAP_SYS. Snapshots are not persisted across
+/// restarts.
+pub fn snapshot(path: &str) -> Result<u64, ErrorCode> {
+    let vdso_snapshot: extern "C" fn(*const u8, usize, *mut u64) -> ErrorCode = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get().fs_snapshot.load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    let bytes = path.as_bytes();
+    let mut id = 0;
+
+    match vdso_snapshot(bytes.as_ptr(), bytes.len(), &mut id) {
+        E_OK => Ok(id),
+        err => Err(err),
+    }
+}
+
+/// Releases snapshot `id`, freeing the space used by it. Requires CAP_SYS.
+pub fn release_snapshot(id: u64) -> Result<(), ErrorCode> {
+    let vdso_release_snapshot: extern "C" fn(u64) -> ErrorCode = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get()
+                .fs_release_snapshot
+                .load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    ok_or_error(vdso_release_snapshot(id))
+}
+
+fn writeback_op(op: u32, policy: &WritebackPolicy) -> Result<WritebackStats, ErrorCode> {
+    let vdso_writeback: extern "C" fn(
+        u32,
+        *const WritebackPolicy,
+        *mut WritebackStats,
+    ) -> ErrorCode = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get().fs_writeback.load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    let mut stats = WritebackStats::default();
+    match vdso_writeback(op, policy, &mut stats) {
+        E_OK => Ok(stats),
+        err => Err(err),
+    }
+}
+
+/// Sets the system-wide policy for O_WRITEBACK writes. Not persisted across restarts.
+/// Requires CAP_SYS (E_NOT_ALLOWED otherwise).
+pub fn set_writeback_policy(policy: &WritebackPolicy) -> Result<(), ErrorCode> {
+    writeback_op(WRITEBACK_OP_SET_POLICY, policy).map(|_| ())
+}
+
+/// The writeback policy, and how much data is waiting to be written back.
+pub fn writeback_stats() -> Result<WritebackStats, ErrorCode> {
+    writeback_op(WRITEBACK_OP_STATS, &WritebackPolicy::default())
+}
+
+/// A write barrier: when this returns, all writes completed before it was
+/// called, including O_WRITEBACK ones, are durable, and no write started
+/// after it returns is reordered before them. Cheaper than fsync() after
+/// every write, as writes between barriers are not flushed one by one.
+/// Fails with E_IO_ERROR if the storage device did not acknowledge the flush.
+pub fn barrier() -> Result<(), ErrorCode> {
+    let vdso_barrier: extern "C" fn() -> ErrorCode = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get().fs_barrier.load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    ok_or_error(vdso_barrier())
+}
+
+/// Writes back (makes durable) all data written via O_WRITEBACK fds so far.
+/// Flushes the storage device even if there is no such data, so this is
+/// also a whole-filesystem sync.
+pub fn writeback() -> Result<(), ErrorCode> {
+    writeback_op(WRITEBACK_OP_FORCE, &WritebackPolicy::default()).map(|_| ())
+}
+
+/// The version of file `rt_fd`: every write to the file (through any fd) changes it
+/// to a value not used before. See write_if_version(). Versions belong to the file,
+/// not to its path, so they survive renames. They are tracked in memory by the
+/// FS driver: files not written to since it started share a version, newer than
+/// any version before the start. Fails with E_NOT_IMPLEMENTED on filesystems
+/// without stable file IDs (e.g. flatfs), and on snapshot views.
+pub fn file_version(rt_fd: RtFd) -> Result<u64, ErrorCode> {
+    let vdso_file_version: extern "C" fn(i32, *mut u64) -> ErrorCode = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get()
+                .fs_file_version
+                .load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    let mut version = 0;
+    match vdso_file_version(rt_fd, &mut version) {
+        E_OK => Ok(version),
+        err => Err(err),
+    }
+}
+
+/// Writes all of `buf` at the current position of `rt_fd`, only if the version
+/// of the file is still `expected_version` (see file_version()); returns
+/// the new version. Fails with E_ALREADY_IN_USE, without writing, if the file
+/// has been written to since, and with E_INVALID_ARGUMENT if `buf` is too large
+/// to be written at once (a few kilobytes).
+pub fn write_if_version(rt_fd: RtFd, buf: &[u8], expected_version: u64) -> Result<u64, ErrorCode> {
+    let vdso_write_if_version: extern "C" fn(i32, *const u8, usize, u64, *mut u64) -> ErrorCode = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get()
+                .fs_write_if_version
+                .load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    let mut version = 0;
+    match vdso_write_if_version(
+        rt_fd,
+        buf.as_ptr(),
+        buf.len(),
+        expected_version,
+        &mut version,
+    ) {
+        E_OK => Ok(version),
+        err => Err(err),
+    }
+}
+
+fn defragment_op(rt_fd: RtFd, start: bool) -> Result<FileExtents, ErrorCode> {
+    let vdso_defragment: extern "C" fn(i32, u32, *mut FileExtents) -> ErrorCode = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get().fs_defragment.load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    let mut extents = FileExtents::default();
+    match vdso_defragment(rt_fd, start as u32, &mut extents) {
+        E_OK => Ok(extents),
+        err => Err(err),
+    }
+}
+
+/// How fragmented file `rt_fd` is on the device. Fails with E_NOT_IMPLEMENTED
+/// if the filesystem does not track blocks (e.g. on a read-only volume).
+pub fn file_extents(rt_fd: RtFd) -> Result<FileExtents, ErrorCode> {
+    defragment_op(rt_fd, false)
+}
+
+/// Starts moving the blocks of file `rt_fd` so that it becomes contiguous,
+/// and returns immediately, with the layout before the move. The file is
+/// moved in the background, a few blocks at a time, and can be used as
+/// usual meanwhile; file_extents() shows the progress. Does nothing if the
+/// file is contiguous already.
+pub fn defragment(rt_fd: RtFd) -> Result<FileExtents, ErrorCode> {
+    defragment_op(rt_fd, true)
+}
+
 pub fn truncate(rt_fd: RtFd, size: u64) -> Result<(), ErrorCode> {
     let vdso_truncate: extern "C" fn(i32, u64) -> ErrorCode = unsafe {
         core::mem::transmute(
@@ -213,6 +615,58 @@ pub fn write(rt_fd: RtFd, buf: &[u8]) -> Result<usize, ErrorCode> {
     to_result!(vdso_write(rt_fd, buf.as_ptr(), buf.len()))
 }
 
+/// Reads from `rt_fd` at `offset` into `bufs`, in order, as one request (preadv).
+/// The file position is not changed. Reads at most a few kilobytes (what fits
+/// into the FS channel); like read(), may read less than requested.
+///
+/// The data is copied through the FS channel, a few kilobytes at a time; to
+/// gather segments straight into io_channel pages, with requests in flight
+/// concurrently, see moto_sys_io::io_executor::File::read_vectored_at().
+pub fn preadv(rt_fd: RtFd, bufs: &mut [&mut [u8]], offset: u64) -> Result<usize, ErrorCode> {
+    let vdso_preadv: extern "C" fn(i32, *const IoVec, usize, u64) -> i64 = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get().fs_preadv.load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    if bufs.len() > MAX_IOV {
+        return Err(E_INVALID_ARGUMENT);
+    }
+    let mut iov = [IoVec::default(); MAX_IOV];
+    for (idx, buf) in bufs.iter_mut().enumerate() {
+        iov[idx] = IoVec {
+            base: buf.as_mut_ptr() as usize,
+            len: buf.len(),
+        };
+    }
+
+    to_result!(vdso_preadv(rt_fd, iov.as_ptr(), bufs.len(), offset))
+}
+
+/// Writes `bufs`, in order, to `rt_fd` at `offset` as one request (pwritev).
+/// The file position is not changed. Like write(), may write less than requested.
+/// See preadv() for the io_channel alternative.
+pub fn pwritev(rt_fd: RtFd, bufs: &[&[u8]], offset: u64) -> Result<usize, ErrorCode> {
+    let vdso_pwritev: extern "C" fn(i32, *const IoVec, usize, u64) -> i64 = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get().fs_pwritev.load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    if bufs.len() > MAX_IOV {
+        return Err(E_INVALID_ARGUMENT);
+    }
+    let mut iov = [IoVec::default(); MAX_IOV];
+    for (idx, buf) in bufs.iter().enumerate() {
+        iov[idx] = IoVec {
+            base: buf.as_ptr() as usize,
+            len: buf.len(),
+        };
+    }
+
+    to_result!(vdso_pwritev(rt_fd, iov.as_ptr(), bufs.len(), offset))
+}
+
 pub fn flush(rt_fd: RtFd) -> Result<(), ErrorCode> {
     let vdso_flush: extern "C" fn(i32) -> ErrorCode = unsafe {
         core::mem::transmute(
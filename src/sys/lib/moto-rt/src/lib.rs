@@ -210,6 +210,41 @@ pub struct RtVdsoVtableV1 {
     pub net_udp_recv_from: AtomicU64,
     pub net_udp_peek_from: AtomicU64,
     pub net_udp_send_to: AtomicU64,
+    pub net_poll_new: AtomicU64,
+    pub net_poll_ctl: AtomicU64,
+    pub net_poll_wait: AtomicU64,
+
+    // Filesystem (cont.).
+    pub fs_sync_files: AtomicU64,
+    pub fs_set_dir_quota: AtomicU64,
+    pub fs_get_dir_quota: AtomicU64,
+
+    // Process (cont.).
+    pub proc_on_panic: AtomicU64,
+
+    // Filesystem (cont.).
+    pub fs_file_version: AtomicU64,
+    pub fs_write_if_version: AtomicU64,
+
+    // Process (cont.).
+    pub proc_rusage: AtomicU64,
+
+    // Filesystem (cont.).
+    pub fs_statfs: AtomicU64,
+    pub fs_preadv: AtomicU64,
+    pub fs_pwritev: AtomicU64,
+    pub fs_snapshot: AtomicU64,
+    pub fs_release_snapshot: AtomicU64,
+    pub fs_writeback: AtomicU64,
+    pub fs_pipe: AtomicU64,
+    pub fs_defragment: AtomicU64,
+    pub fs_barrier: AtomicU64,
+
+    // Networking (cont.).
+    pub net_tcp_connect_from: AtomicU64,
+
+    // Filesystem (cont.).
+    pub fs_set_queue_depth: AtomicU64,
 }
 
 #[cfg(not(feature = "base"))]
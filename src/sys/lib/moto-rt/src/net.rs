@@ -15,12 +15,102 @@ pub const SHUTDOWN_WRITE: u8 = 2;
 
 pub const PROTO_TCP: u8 = 1;
 pub const PROTO_UDP: u8 = 2;
+/// Or-ed into the protocol passed to bind() (TCP only), similar to SO_REUSEPORT:
+/// several listeners of the same process, all bound with this flag, may bind
+/// to the same address, and incoming connections are distributed among them
+/// round-robin. See SO_LISTENER_ACCEPTED.
+pub const BIND_REUSEPORT: u8 = 0x80;
+/// Or-ed into the protocol passed to bind() (TCP only), similar to SO_EXCLUSIVEADDRUSE:
+/// the bind fails with E_ALREADY_IN_USE if the address is still used by connections
+/// accepted by a previous, now dropped, listener. Without it, such a bind succeeds
+/// (as with SO_REUSEADDR), so that e.g. a restarted server can listen right away.
+/// Two active listeners can't share an address either way (see BIND_REUSEPORT).
+pub const BIND_EXCLUSIVEADDR: u8 = 0x40;
 
 pub const SO_RCVTIMEO: u64 = 1;
 pub const SO_SNDTIMEO: u64 = 2;
 pub const SO_SHUTDOWN: u64 = 3;
 pub const SO_NODELAY: u64 = 4;
 pub const SO_TTL: u64 = 5;
+/// Listeners only: stop accepting new connections, but let the application
+/// accept() the connections already queued. Takes no value.
+pub const SO_LISTENER_DRAIN: u64 = 6;
+/// Listeners only (u8): if non-zero, closing the listener closes queued
+/// but not accepted connections with a FIN instead of a RST.
+pub const SO_LISTENER_FIN_ON_CLOSE: u64 = 7;
+/// u64, in nanoseconds; zero => no timeout. If set on a stream, sys-io closes
+/// it if there is no activity (bytes sent or received) for that long, even
+/// if the peer is alive; reads and writes then fail with E_TIMED_OUT.
+/// If set on a listener, applies to connections accepted afterwards.
+pub const SO_IDLE_TIMEOUT: u64 = 8;
+/// Streams only, read-only ([u64; 2]): the PID and capabilities of the process
+/// at the other end of a loopback connection, similar to SO_PEERCRED.
+/// Fails with E_NOT_IMPLEMENTED for non-local peers.
+pub const SO_PEER_CREDS: u64 = 9;
+/// Streams only (u8): if non-zero, sys-io stops delivering received bytes,
+/// and advertises a zero receive window to the peer, so the peer stops
+/// sending once it has seen it (bytes already in flight are still buffered
+/// by sys-io). Reads block (or time out, with SO_RCVTIMEO) once bytes
+/// delivered before the pause are consumed, until the stream is resumed.
+/// A paused stream is not closed by SO_IDLE_TIMEOUT: its idle time starts
+/// over when it is resumed.
+pub const SO_RX_PAUSED: u64 = 10;
+/// Streams only, write-only (u8): sends the byte as TCP urgent data (MSG_OOB).
+/// See SO_URGENT_MARK.
+pub const SO_URGENT_SEND: u64 = 11;
+/// Streams only, read-only (u64): how many bytes are left to read before
+/// the last urgent byte received (zero: the next byte read is the urgent
+/// byte, as with SIOCATMARK), or u64::MAX if there is none ahead.
+///
+/// There is no separate out-of-band channel: as if SO_OOBINLINE were always
+/// set, urgent bytes are delivered in order with the rest of the stream, and
+/// reads do not stop at the mark. Only the most recent mark is kept, so an
+/// urgent byte sent before the previous one is read replaces its mark.
+pub const SO_URGENT_MARK: u64 = 12;
+/// Streams only (u64, in nanoseconds; u64::MAX => off, the default): what
+/// closing the stream does with bytes not yet sent. If off, they are dropped
+/// and the connection is reset. If set to zero, the same, but explicitly:
+/// a RST for immediate teardown. Otherwise, closing the stream (the last fd
+/// referring to it) blocks until the queued bytes are sent and acknowledged,
+/// and the connection is closed with a FIN; if that takes longer than the
+/// linger timeout, the connection is reset and close fails with E_TIMED_OUT;
+/// if the peer resets the connection first, close fails with E_CONNECTION_RESET.
+pub const SO_LINGER: u64 = 13;
+/// Read-only (u64), like FIONREAD: for a stream, the number of bytes that
+/// can be read now, including bytes sys-io has received but not yet passed
+/// on (not counted while SO_RX_PAUSED is set); for a listener, the number
+/// of connections that can be accepted without waiting.
+pub const SO_RX_AVAILABLE: u64 = 14;
+/// Listeners only, read-only (u64): the number of connections accepted from
+/// the listener so far.
+pub const SO_LISTENER_ACCEPTED: u64 = 15;
+/// UDP sockets only (u8): if non-zero, datagrams are sent with the Don't
+/// Fragment bit set, and never fragmented: udp_send_to() fails with
+/// E_MSG_TOO_LARGE if the datagram does not fit in the MTU of the device
+/// it is routed through. Off by default (IPv4 datagrams are fragmented).
+pub const SO_DONT_FRAG: u64 = 16;
+
+/// Poll interest/readiness: the socket can be read from without blocking
+/// (this includes the case when the peer has closed its side); for a
+/// listener, accept() will not block.
+pub const POLL_READABLE: u64 = 1;
+/// Poll interest/readiness: the socket can be written to without blocking
+/// (this includes the case when the socket can no longer be written to).
+pub const POLL_WRITABLE: u64 = 2;
+
+pub const POLL_CTL_ADD: u32 = 1;
+pub const POLL_CTL_MOD: u32 = 2;
+pub const POLL_CTL_DEL: u32 = 3;
+
+/// A readiness notification returned by poll_wait().
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PollEvent {
+    /// The token the source was registered with.
+    pub token: u64,
+    /// POLL_READABLE | POLL_WRITABLE, masked by the registered interests.
+    pub events: u64,
+}
 
 fn setsockopt(rt_fd: RtFd, opt: u64, ptr: usize, len: usize) -> Result<(), ErrorCode> {
     let vdso_setsockopt: extern "C" fn(RtFd, u64, usize, usize) -> ErrorCode = unsafe {
@@ -81,6 +171,31 @@ pub fn tcp_connect(addr: &netc::sockaddr, timeout: Duration) -> Result<RtFd, Err
     to_result!(vdso_tcp_connect(addr, timeout))
 }
 
+/// Same as tcp_connect(), but from `local_addr`: an address of a configured
+/// interface (E_ADDR_NOT_AVAILABLE otherwise), or unspecified to let the route
+/// pick it. A zero port means an ephemeral one; a port used by another connection
+/// or by a listener on the address fails with E_ALREADY_IN_USE.
+pub fn tcp_connect_from(
+    local_addr: &netc::sockaddr,
+    addr: &netc::sockaddr,
+    timeout: Duration,
+) -> Result<RtFd, ErrorCode> {
+    let vdso_tcp_connect_from: extern "C" fn(
+        *const netc::sockaddr,
+        *const netc::sockaddr,
+        u64,
+    ) -> RtFd = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get()
+                .net_tcp_connect_from
+                .load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    let timeout = timeout.as_nanos().try_into().unwrap_or(u64::MAX);
+    to_result!(vdso_tcp_connect_from(local_addr, addr, timeout))
+}
+
 pub fn udp_connect(addr: &netc::sockaddr) -> Result<(), ErrorCode> {
     let vdso_udp_connect: extern "C" fn(*const netc::sockaddr) -> ErrorCode = unsafe {
         core::mem::transmute(
@@ -93,8 +208,18 @@ pub fn udp_connect(addr: &netc::sockaddr) -> Result<(), ErrorCode> {
     ok_or_error(vdso_udp_connect(addr))
 }
 
-pub fn socket_addr(_rt_fd: RtFd) -> Result<netc::sockaddr, ErrorCode> {
-    todo!()
+pub fn socket_addr(rt_fd: RtFd) -> Result<netc::sockaddr, ErrorCode> {
+    let vdso_socket_addr: extern "C" fn(RtFd, *mut netc::sockaddr) -> ErrorCode = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get()
+                .net_socket_addr
+                .load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    let mut addr: netc::sockaddr = unsafe { core::mem::zeroed() };
+    ok_or_error(vdso_socket_addr(rt_fd, &mut addr))?;
+    Ok(addr)
 }
 
 pub fn peer_addr(rt_fd: RtFd) -> Result<netc::sockaddr, ErrorCode> {
@@ -125,6 +250,81 @@ pub fn ttl(rt_fd: RtFd) -> Result<u32, ErrorCode> {
     Ok(ttl)
 }
 
+/// See SO_DONT_FRAG.
+pub fn set_dont_fragment(rt_fd: RtFd, dont_frag: bool) -> Result<(), ErrorCode> {
+    let dont_frag = dont_frag as u8;
+    setsockopt(rt_fd, SO_DONT_FRAG, &dont_frag as *const _ as usize, 1)
+}
+
+pub fn dont_fragment(rt_fd: RtFd) -> Result<bool, ErrorCode> {
+    let mut dont_frag = 0_u8;
+    getsockopt(rt_fd, SO_DONT_FRAG, &mut dont_frag as *mut _ as usize, 1)?;
+    Ok(dont_frag != 0)
+}
+
+/// Returns (PID, capabilities) of the local process at the other end of @rt_fd.
+/// See SO_PEER_CREDS.
+pub fn peer_credentials(rt_fd: RtFd) -> Result<(u64, u64), ErrorCode> {
+    let mut creds = [0_u64; 2];
+    getsockopt(
+        rt_fd,
+        SO_PEER_CREDS,
+        creds.as_mut_ptr() as usize,
+        core::mem::size_of::<[u64; 2]>(),
+    )?;
+    Ok((creds[0], creds[1]))
+}
+
+/// Stops delivering bytes received on @rt_fd, so that the peer eventually
+/// stops sending: application-level flow control. See SO_RX_PAUSED.
+pub fn pause_recv(rt_fd: RtFd) -> Result<(), ErrorCode> {
+    let paused = 1_u8;
+    setsockopt(rt_fd, SO_RX_PAUSED, &paused as *const _ as usize, 1)
+}
+
+/// Undoes pause_recv().
+pub fn resume_recv(rt_fd: RtFd) -> Result<(), ErrorCode> {
+    let paused = 0_u8;
+    setsockopt(rt_fd, SO_RX_PAUSED, &paused as *const _ as usize, 1)
+}
+
+pub fn recv_paused(rt_fd: RtFd) -> Result<bool, ErrorCode> {
+    let mut paused = 0_u8;
+    getsockopt(rt_fd, SO_RX_PAUSED, &mut paused as *mut _ as usize, 1)?;
+    Ok(paused != 0)
+}
+
+/// The number of bytes that can be read from stream @rt_fd now, or the number
+/// of connections that can be accepted from listener @rt_fd. See SO_RX_AVAILABLE.
+pub fn rx_available(rt_fd: RtFd) -> Result<u64, ErrorCode> {
+    let mut available = 0_u64;
+    getsockopt(
+        rt_fd,
+        SO_RX_AVAILABLE,
+        &mut available as *mut _ as usize,
+        core::mem::size_of::<u64>(),
+    )?;
+    Ok(available)
+}
+
+/// Sends @byte as urgent (out-of-band) data. See SO_URGENT_MARK.
+pub fn send_urgent(rt_fd: RtFd, byte: u8) -> Result<(), ErrorCode> {
+    setsockopt(rt_fd, SO_URGENT_SEND, &byte as *const _ as usize, 1)
+}
+
+/// The number of bytes to read before the urgent byte, if one is ahead.
+/// See SO_URGENT_MARK.
+pub fn urgent_mark(rt_fd: RtFd) -> Result<Option<u64>, ErrorCode> {
+    let mut mark = 0_u64;
+    getsockopt(
+        rt_fd,
+        SO_URGENT_MARK,
+        &mut mark as *mut _ as usize,
+        core::mem::size_of::<u64>(),
+    )?;
+    Ok(if mark == u64::MAX { None } else { Some(mark) })
+}
+
 pub fn set_only_v6(_rt_fd: RtFd, _only_v6: bool) -> Result<(), ErrorCode> {
     todo!()
 }
@@ -220,12 +420,82 @@ pub fn shutdown(rt_fd: RtFd, shutdown: u8) -> Result<(), ErrorCode> {
     setsockopt(rt_fd, SO_SHUTDOWN, &shutdown as *const _ as usize, 1)
 }
 
-pub fn set_linger(_rt_fd: RtFd, _timeout: Option<Duration>) -> Result<(), ErrorCode> {
-    todo!()
+/// Stops the listener from accepting new connections. Connections that
+/// are already queued can still be accepted; once the queue is empty,
+/// accept() fails with E_INVALID_ARGUMENT. For a graceful shutdown, drain
+/// the listener, accept (and serve) until accept() fails, then close it.
+pub fn drain_listener(rt_fd: RtFd) -> Result<(), ErrorCode> {
+    setsockopt(rt_fd, SO_LISTENER_DRAIN, 0, 0)
 }
 
-pub fn linger(_rt_fd: RtFd) -> Result<Option<Duration>, ErrorCode> {
-    todo!()
+/// The number of connections accepted from listener @rt_fd. See SO_LISTENER_ACCEPTED.
+pub fn listener_accepted(rt_fd: RtFd) -> Result<u64, ErrorCode> {
+    let mut accepted = 0_u64;
+    getsockopt(
+        rt_fd,
+        SO_LISTENER_ACCEPTED,
+        &mut accepted as *mut _ as usize,
+        core::mem::size_of::<u64>(),
+    )?;
+    Ok(accepted)
+}
+
+/// If `fin` is true, closing the listener closes connections that are
+/// queued, but not yet accepted, with a FIN instead of a RST.
+pub fn set_listener_fin_on_close(rt_fd: RtFd, fin: bool) -> Result<(), ErrorCode> {
+    let fin = if fin { 1_u8 } else { 0 };
+    setsockopt(
+        rt_fd,
+        SO_LISTENER_FIN_ON_CLOSE,
+        &fin as *const _ as usize,
+        1,
+    )
+}
+
+/// See SO_IDLE_TIMEOUT. `None` disables the idle timeout.
+pub fn set_idle_timeout(rt_fd: RtFd, timeout: Option<Duration>) -> Result<(), ErrorCode> {
+    let timeout: u64 = match timeout {
+        Some(dur) if dur.is_zero() => return Err(crate::E_INVALID_ARGUMENT),
+        Some(dur) => dur.as_nanos().try_into().unwrap_or(u64::MAX),
+        None => 0,
+    };
+
+    setsockopt(
+        rt_fd,
+        SO_IDLE_TIMEOUT,
+        &timeout as *const _ as usize,
+        core::mem::size_of::<u64>(),
+    )
+}
+
+/// See SO_LINGER. `None` turns lingering off.
+pub fn set_linger(rt_fd: RtFd, timeout: Option<Duration>) -> Result<(), ErrorCode> {
+    let timeout: u64 = match timeout {
+        Some(dur) => dur.as_nanos().try_into().unwrap_or(u64::MAX - 1),
+        None => u64::MAX,
+    };
+
+    setsockopt(
+        rt_fd,
+        SO_LINGER,
+        &timeout as *const _ as usize,
+        core::mem::size_of::<u64>(),
+    )
+}
+
+pub fn linger(rt_fd: RtFd) -> Result<Option<Duration>, ErrorCode> {
+    let mut timeout = 0_u64;
+    getsockopt(
+        rt_fd,
+        SO_LINGER,
+        &mut timeout as *mut _ as usize,
+        core::mem::size_of::<u64>(),
+    )?;
+    Ok(if timeout == u64::MAX {
+        None
+    } else {
+        Some(Duration::from_nanos(timeout))
+    })
 }
 
 pub fn set_nodelay(rt_fd: RtFd, nodelay: bool) -> Result<(), ErrorCode> {
@@ -251,16 +521,60 @@ pub fn udp_broadcast(_rt_fd: RtFd) -> Result<bool, ErrorCode> {
     todo!()
 }
 
-pub fn udp_recv_from(_rt_fd: RtFd, _buf: &mut [u8]) -> Result<(usize, netc::sockaddr), ErrorCode> {
-    todo!()
+type UdpRecvFn = extern "C" fn(RtFd, *mut u8, usize, *mut netc::sockaddr) -> i64;
+
+fn udp_recv(
+    vdso_recv: UdpRecvFn,
+    rt_fd: RtFd,
+    buf: &mut [u8],
+) -> Result<(usize, netc::sockaddr), ErrorCode> {
+    let mut addr: netc::sockaddr = unsafe { core::mem::zeroed() };
+    let sz = to_result!(vdso_recv(rt_fd, buf.as_mut_ptr(), buf.len(), &mut addr))?;
+    Ok((sz, addr))
 }
 
-pub fn udp_peek_from(_rt_fd: RtFd, _buf: &mut [u8]) -> Result<(usize, netc::sockaddr), ErrorCode> {
-    todo!()
+/// Receives one datagram on UDP socket @rt_fd (bound with PROTO_UDP), and
+/// returns its size and source. Datagrams are not truncated: if @buf is too
+/// small, this fails with E_MSG_TOO_LARGE and the datagram stays queued.
+/// Datagrams larger than moto_sys_io::api_net::UDP_MAX_DATAGRAM_SIZE (one
+/// page) are dropped by sys-io. Honors SO_RCVTIMEO.
+pub fn udp_recv_from(rt_fd: RtFd, buf: &mut [u8]) -> Result<(usize, netc::sockaddr), ErrorCode> {
+    let vdso_udp_recv_from: UdpRecvFn = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get()
+                .net_udp_recv_from
+                .load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    udp_recv(vdso_udp_recv_from, rt_fd, buf)
 }
 
-pub fn udp_send_to(_rt_fd: RtFd, _buf: &[u8], _addr: &netc::sockaddr) -> Result<usize, ErrorCode> {
-    todo!()
+/// Like udp_recv_from(), but the datagram stays queued.
+pub fn udp_peek_from(rt_fd: RtFd, buf: &mut [u8]) -> Result<(usize, netc::sockaddr), ErrorCode> {
+    let vdso_udp_peek_from: UdpRecvFn = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get()
+                .net_udp_peek_from
+                .load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    udp_recv(vdso_udp_peek_from, rt_fd, buf)
+}
+
+/// Sends @buf as one datagram to @addr. Fails with E_MSG_TOO_LARGE if @buf
+/// does not fit in one datagram (see udp_recv_from()).
+pub fn udp_send_to(rt_fd: RtFd, buf: &[u8], addr: &netc::sockaddr) -> Result<usize, ErrorCode> {
+    let vdso_udp_send_to: extern "C" fn(RtFd, *const u8, usize, *const netc::sockaddr) -> i64 = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get()
+                .net_udp_send_to
+                .load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    to_result!(vdso_udp_send_to(rt_fd, buf.as_ptr(), buf.len(), addr))
 }
 
 pub fn set_udp_multicast_loop_v4(_rt_fd: RtFd, _val: bool) -> Result<(), ErrorCode> {
@@ -319,6 +633,84 @@ pub fn leave_udp_multicast_v6(
     todo!()
 }
 
+/// Creates a new poll object. Close it with fs::close().
+pub fn poll_new() -> Result<RtFd, ErrorCode> {
+    let vdso_poll_new: extern "C" fn() -> RtFd = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get().net_poll_new.load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    to_result!(vdso_poll_new())
+}
+
+fn poll_ctl(
+    poll_fd: RtFd,
+    op: u32,
+    source_fd: RtFd,
+    token: u64,
+    interests: u64,
+) -> Result<(), ErrorCode> {
+    let vdso_poll_ctl: extern "C" fn(RtFd, u32, RtFd, u64, u64) -> ErrorCode = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get().net_poll_ctl.load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    ok_or_error(vdso_poll_ctl(poll_fd, op, source_fd, token, interests))
+}
+
+/// Registers a TCP stream or listener with the poll object. A poll object
+/// can be waited on by several threads at once; all of them are woken.
+pub fn poll_add(
+    poll_fd: RtFd,
+    source_fd: RtFd,
+    token: u64,
+    interests: u64,
+) -> Result<(), ErrorCode> {
+    poll_ctl(poll_fd, POLL_CTL_ADD, source_fd, token, interests)
+}
+
+/// Changes the token and/or interests of a registered source.
+pub fn poll_modify(
+    poll_fd: RtFd,
+    source_fd: RtFd,
+    token: u64,
+    interests: u64,
+) -> Result<(), ErrorCode> {
+    poll_ctl(poll_fd, POLL_CTL_MOD, source_fd, token, interests)
+}
+
+pub fn poll_delete(poll_fd: RtFd, source_fd: RtFd) -> Result<(), ErrorCode> {
+    poll_ctl(poll_fd, POLL_CTL_DEL, source_fd, 0, 0)
+}
+
+/// Waits until at least one registered source is ready, or the timeout expires
+/// (in which case Ok(0) is returned). Readiness is level-triggered: a source
+/// that is still ready will be reported again on the next call.
+pub fn poll_wait(
+    poll_fd: RtFd,
+    events: &mut [PollEvent],
+    timeout: Option<Duration>,
+) -> Result<usize, ErrorCode> {
+    let vdso_poll_wait: extern "C" fn(RtFd, *mut PollEvent, usize, u64) -> i32 = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get().net_poll_wait.load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    let timeout = match timeout {
+        Some(dur) => dur.as_nanos().try_into().unwrap_or(u64::MAX),
+        None => u64::MAX,
+    };
+    to_result!(vdso_poll_wait(
+        poll_fd,
+        events.as_mut_ptr(),
+        events.len(),
+        timeout
+    ))
+}
+
 pub fn lookup_host(
     host: &str,
     port: u16,
@@ -169,6 +169,13 @@ pub struct SpawnArgs {
     pub args: Vec<String>,
     pub env: Vec<(String, String)>,
     pub cwd: Option<String>,
+    /// STDIO_INHERIT, STDIO_NULL, STDIO_MAKE_PIPE, or an open fd (a file
+    /// or a pipe, see open_stdio_file(); other fds are rejected with
+    /// E_INVALID_ARGUMENT). On success, spawn() takes over an fd passed
+    /// here: it is closed when the child is gone (or, for stdin, after EOF),
+    /// and must not be used or closed by the caller. Once wait() returns,
+    /// all of the child's stdout/stderr has been written to their fds
+    /// (so if one is a pipe, drain it before calling wait()).
     pub stdin: RtFd,
     pub stdout: RtFd,
     pub stderr: RtFd,
@@ -265,6 +272,27 @@ pub fn spawn(args: SpawnArgs) -> Result<(u64, RtFd, RtFd, RtFd), crate::ErrorCod
     }
 }
 
+/// Opens (and creates, if needed) @path for SpawnArgs::stdout or SpawnArgs::stderr.
+/// The child's output is appended to the file if @append, otherwise the file
+/// is truncated first.
+pub fn open_stdio_file(path: &str, append: bool) -> Result<RtFd, crate::ErrorCode> {
+    use crate::fs::*;
+
+    if !append {
+        return open(path, O_CREATE | O_TRUNCATE | O_WRITE);
+    }
+
+    let fd = match open(path, O_WRITE) {
+        Err(crate::E_NOT_FOUND) => open(path, O_CREATE_NEW | O_WRITE)?,
+        res => res?,
+    };
+    if let Err(err) = seek(fd, 0, SEEK_END) {
+        let _ = close(fd);
+        return Err(err);
+    }
+    Ok(fd)
+}
+
 pub fn kill(handle: u64) -> crate::ErrorCode {
     let vdso_kill: extern "C" fn(u64) -> crate::ErrorCode = unsafe {
         core::mem::transmute(
@@ -318,6 +346,64 @@ pub fn try_wait(handle: u64) -> Result<i32, crate::ErrorCode> {
     }
 }
 
+/// Resources used by a process over its lifetime; see wait_rusage().
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct ResourceUsage {
+    pub cpu_user_ns: u64,
+    pub cpu_kernel_ns: u64,
+    /// Lazily mapped pages populated on first access (all faults are minor).
+    pub page_faults: u64,
+    pub peak_bytes_user: u64,
+    pub peak_bytes_kernel: u64,
+    pub total_threads: u64,
+    pub total_children: u64,
+    /// Times a thread of the process was switched to.
+    pub context_switches: u64,
+    /// Times a thread of the process moved to another CPU.
+    pub migrations: u64,
+    /// The time threads spent runnable, waiting for a CPU.
+    pub sched_latency_total_ns: u64,
+    pub sched_latency_max_ns: u64,
+    /// Bytes moved through sys-io: file data, and TCP/UDP payload.
+    pub fs_bytes_read: u64,
+    pub fs_bytes_written: u64,
+    pub net_bytes_rx: u64,
+    pub net_bytes_tx: u64,
+}
+
+impl ResourceUsage {
+    pub fn cpu_total(&self) -> core::time::Duration {
+        core::time::Duration::from_nanos(self.cpu_user_ns + self.cpu_kernel_ns)
+    }
+}
+
+/// The resources used by the process behind @handle so far. Once the process
+/// has exited, they are final. I/O is counted by sys-io, which keeps the
+/// counters of exited processes only for a while (see
+/// moto_sys_io::api_stats::ProcessIoStatsV1).
+pub fn rusage(handle: u64) -> Result<ResourceUsage, crate::ErrorCode> {
+    let vdso_rusage: extern "C" fn(u64, *mut ResourceUsage) -> crate::ErrorCode = unsafe {
+        core::mem::transmute(
+            RtVdsoVtableV1::get().proc_rusage.load(Ordering::Relaxed) as usize as *const (),
+        )
+    };
+
+    let mut usage = ResourceUsage::default();
+    let result = vdso_rusage(handle, &mut usage);
+    if result == crate::E_OK {
+        Ok(usage)
+    } else {
+        Err(result)
+    }
+}
+
+/// Like wait(), but also returns what the process has used.
+pub fn wait_rusage(handle: u64) -> Result<(i32, ResourceUsage), crate::ErrorCode> {
+    let status = wait(handle)?;
+    Ok((status, rusage(handle)?))
+}
+
 pub fn exit(code: i32) -> ! {
     let vdso_exit: extern "C" fn(i32) -> ! = unsafe {
         core::mem::transmute(
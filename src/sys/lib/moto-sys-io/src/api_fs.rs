@@ -20,6 +20,17 @@ pub const CMD_FILE_READ: u16 = 101;
 pub const CMD_FILE_WRITE: u16 = 102;
 pub const CMD_UNLINK: u16 = 103;
 pub const CMD_RENAME: u16 = 104;
+pub const CMD_BARRIER: u16 = 105;
+pub const CMD_SYNC_FILES: u16 = 106;
+pub const CMD_DEFRAGMENT: u16 = 107;
+pub const CMD_SET_DIR_QUOTA: u16 = 108;
+pub const CMD_GET_DIR_QUOTA: u16 = 109;
+pub const CMD_FILE_VERSION: u16 = 110;
+pub const CMD_FILE_WRITE_IF_VERSION: u16 = 111;
+pub const CMD_STATFS: u16 = 112;
+pub const CMD_SNAPSHOT: u16 = 113;
+pub const CMD_RELEASE_SNAPSHOT: u16 = 114;
+pub const CMD_WRITEBACK: u16 = 115;
 
 pub const F_UNLINK_FILE: u32 = 1;
 pub const F_UNLINK_DIR: u32 = 2;
@@ -148,12 +159,188 @@ pub struct FileWriteRequest {
     pub data: [u8; 0],
 }
 
+impl FileWriteRequest {
+    // Don't flush the device cache after the write: the write is ordered
+    // before subsequent writes, but is durable only after the next
+    // CMD_BARRIER (or a regular CMD_FILE_WRITE) completes, or the driver
+    // writes it back (see CMD_WRITEBACK).
+    pub const F_NO_FLUSH: u32 = 1;
+}
+
 #[repr(C, align(8))]
 pub struct FileWriteResponse {
     pub header: moto_ipc::sync::ResponseHeader,
     pub written: u32,
 }
 
+// CMD_FILE_VERSION: the current version of an open file. Versions are
+// tracked by the driver, in memory, per inode: every write to the file bumps
+// its version to a value never used before; files not written to since
+// sys-io started share a version newer than any before the start.
+#[repr(C, align(8))]
+pub struct FileVersionRequest {
+    pub header: moto_ipc::sync::RequestHeader,
+    pub fd: u64,
+}
+
+#[repr(C, align(8))]
+pub struct FileVersionResponse {
+    pub header: moto_ipc::sync::ResponseHeader,
+    pub version: u64,
+}
+
+// CMD_FILE_WRITE_IF_VERSION: writes all of data at offset, and bumps the version,
+// only if the file's version is expected_version; fails with E_ALREADY_IN_USE
+// otherwise, without writing. The check and the write are atomic with respect
+// to other requests (but not to crashes: a write is not a transaction).
+// Data must fit into the channel: partial writes are not done.
+// Responds with FileVersionResponse: the new version, or the current one
+// if the versions do not match.
+#[allow(unused)]
+#[repr(C, align(8))]
+pub struct FileWriteIfVersionRequest {
+    pub header: moto_ipc::sync::RequestHeader,
+    pub size: u32,
+    _reserved: u32,
+    pub offset: u64,
+    pub fd: u64,
+    pub expected_version: u64,
+    pub data: [u8; 0],
+}
+
+pub type FileWriteIfVersionResponse = FileVersionResponse;
+
+// CMD_BARRIER: when the response is received, all writes completed
+// before the request was sent are durable, and no write issued after
+// the response is received will be reordered before them. Cheaper
+// than flushing after every write when writes use F_NO_FLUSH.
+#[repr(C, align(8))]
+pub struct BarrierRequest {
+    pub header: moto_ipc::sync::RequestHeader,
+}
+
+pub type BarrierResponse = CloseFdResponse;
+
+// CMD_SYNC_FILES: validates that all fds (and parent_fd, if non-zero)
+// are open on this connection, then issues a single barrier. When the
+// response is received, all writes to these files (and to the parent
+// directory) completed before the request was sent are durable.
+// Note: this is "durable together", not a transaction: on a crash
+// before the response, any subset of the writes may have persisted.
+// The response is sent after the device acknowledges the flush; if the
+// flush fails, the result is E_IO_ERROR.
+#[repr(C, align(8))]
+pub struct SyncFilesRequest {
+    pub header: moto_ipc::sync::RequestHeader,
+    pub parent_fd: u64, // A CMD_READDIR fd, or zero.
+    pub num_fds: u32,
+    pub _reserved: u32,
+    pub fds: [u64; 0], // num_fds CMD_FILE_OPEN fds follow.
+}
+
+impl SyncFilesRequest {
+    pub fn max_fds(raw_channel: &moto_ipc::sync::RawChannel) -> usize {
+        (raw_channel.size() - core::mem::size_of::<Self>()) / core::mem::size_of::<u64>()
+    }
+}
+
+pub type SyncFilesResponse = CloseFdResponse;
+
+// CMD_SET_DIR_QUOTA sets max_bytes/max_inodes on directory fname (see
+// moto_rt::fs::set_dir_quota()); CMD_GET_DIR_QUOTA ignores them.
+// Both respond with the quota and its current usage.
+#[repr(C, align(8))]
+pub struct DirQuotaRequest {
+    pub header: moto_ipc::sync::RequestHeader,
+    pub max_bytes: u64,
+    pub max_inodes: u64,
+    pub fname_size: u16,
+    pub fname: [u8; moto_rt::fs::MAX_PATH_LEN], // Absolute.
+}
+
+#[repr(C, align(8))]
+pub struct DirQuotaResponse {
+    pub header: moto_ipc::sync::ResponseHeader,
+    pub quota: moto_rt::fs::DirQuota,
+}
+
+// CMD_STATFS: the volume that fname (which must exist) is on, after
+// setting its queue depth (see moto_rt::fs::set_queue_depth()) if
+// F_SET_QUEUE_DEPTH.
+#[repr(C, align(8))]
+pub struct StatFsRequest {
+    pub header: moto_ipc::sync::RequestHeader,
+    pub queue_depth: u32,
+    pub fname_size: u16,
+    pub fname: [u8; moto_rt::fs::MAX_PATH_LEN], // Absolute.
+}
+
+impl StatFsRequest {
+    pub const F_SET_QUEUE_DEPTH: u32 = 1;
+}
+
+#[repr(C, align(8))]
+pub struct StatFsResponse {
+    pub header: moto_ipc::sync::ResponseHeader,
+    pub statfs: moto_rt::fs::StatFs,
+}
+
+// CMD_SNAPSHOT snapshots directory fname (see moto_rt::fs::snapshot())
+// and responds with the snapshot ID; CMD_RELEASE_SNAPSHOT releases snapshot id,
+// and ignores fname.
+#[repr(C, align(8))]
+pub struct SnapshotRequest {
+    pub header: moto_ipc::sync::RequestHeader,
+    pub id: u64,
+    pub fname_size: u16,
+    pub fname: [u8; moto_rt::fs::MAX_PATH_LEN], // Absolute.
+}
+
+#[repr(C, align(8))]
+pub struct SnapshotResponse {
+    pub header: moto_ipc::sync::ResponseHeader,
+    pub id: u64,
+}
+
+// CMD_WRITEBACK: responds with the writeback policy and stats (see
+// moto_rt::fs::writeback_stats()), after setting the policy if F_SET_POLICY,
+// or flushing the device (whether or not there is dirty data) if F_FORCE.
+#[repr(C, align(8))]
+pub struct WritebackRequest {
+    pub header: moto_ipc::sync::RequestHeader,
+    pub policy: moto_rt::fs::WritebackPolicy,
+}
+
+impl WritebackRequest {
+    pub const F_SET_POLICY: u32 = 1;
+    pub const F_FORCE: u32 = 2;
+}
+
+#[repr(C, align(8))]
+pub struct WritebackResponse {
+    pub header: moto_ipc::sync::ResponseHeader,
+    pub stats: moto_rt::fs::WritebackStats,
+}
+
+// CMD_DEFRAGMENT: responds with the layout of file fd (see
+// moto_rt::fs::file_extents()), after queueing it for background
+// defragmentation if F_START.
+#[repr(C, align(8))]
+pub struct DefragmentRequest {
+    pub header: moto_ipc::sync::RequestHeader,
+    pub fd: u64,
+}
+
+impl DefragmentRequest {
+    pub const F_START: u32 = 1;
+}
+
+#[repr(C, align(8))]
+pub struct DefragmentResponse {
+    pub header: moto_ipc::sync::ResponseHeader,
+    pub extents: moto_rt::fs::FileExtents,
+}
+
 #[repr(C, align(8))]
 pub struct RenameRequest {
     pub header: moto_ipc::sync::RequestHeader, // CMD_RENAME
@@ -204,3 +391,40 @@ impl RenameRequest {
 }
 
 pub type RenameResponse = CloseFdResponse;
+
+// Requests over io_channel (see moto_sys_io::io_executor), served by the
+// FS driver at FS_IO_URL. Files opened over an io_channel connection have
+// their own fds, and are closed when the connection drops.
+pub const FS_IO_URL: &str = "motor-os-fs-io";
+
+pub const CMD_IO_MIN: u16 = moto_ipc::io_channel::CMD_RESERVED_MAX;
+
+// The path is in the page at shared_pages()[0], and its length in flags;
+// O_* flags are in args_32()[5]. CQE: the fd in handle, the file size in args_64()[0].
+pub const CMD_IO_OPEN: u16 = CMD_IO_MIN + 0;
+// handle: fd; flags: the number of bytes (at most IO_MAX_BYTES); args_64()[2]: offset.
+// CQE: the data in server pages at shared_pages()[0..], the number of bytes
+// read in args_64()[2] (short at the end of the file).
+pub const CMD_IO_READ: u16 = CMD_IO_MIN + 1;
+// As CMD_IO_READ, but the data is in client pages at shared_pages()[0..],
+// scatter/gather (see Payload::shared_page_count()), which the server frees,
+// whether or not the request succeeds. CQE: bytes written in args_64()[2].
+pub const CMD_IO_WRITE: u16 = CMD_IO_MIN + 2;
+// handle: fd. Makes the file's data, and all writes before, durable.
+pub const CMD_IO_FSYNC: u16 = CMD_IO_MIN + 3;
+// handle: fd.
+pub const CMD_IO_CLOSE: u16 = CMD_IO_MIN + 4;
+// The path as in CMD_IO_OPEN. CQE: the moto_rt::fs::FileAttr at the start
+// of the server page at shared_pages()[0].
+pub const CMD_IO_STAT: u16 = CMD_IO_MIN + 5;
+// The old path and then the new one are in the page at shared_pages()[0];
+// their lengths are in flags and args_32()[5].
+pub const CMD_IO_RENAME: u16 = CMD_IO_MIN + 6;
+pub const CMD_IO_MAX: u16 = CMD_IO_RENAME;
+
+// Pages a single CMD_IO_READ/CMD_IO_WRITE carries: shared_pages()[8..]
+// overlap args_64()[2].
+pub const IO_MAX_PAGES: usize = 8;
+pub const IO_MAX_BYTES: usize = IO_MAX_PAGES * moto_ipc::io_channel::PAGE_SIZE;
+// Connections to FS_IO_URL don't use subchannels.
+pub const IO_SUBCHANNEL_MASK: u64 = u64::MAX;
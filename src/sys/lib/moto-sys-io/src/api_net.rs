@@ -23,17 +23,102 @@ pub const CMD_TCP_STREAM_SET_OPTION: u16 = CMD_MIN + 9;
 pub const CMD_TCP_STREAM_GET_OPTION: u16 = CMD_MIN + 10;
 pub const CMD_TCP_STREAM_CLOSE: u16 = CMD_MIN + 11;
 
-pub const CMD_MAX: u16 = CMD_TCP_STREAM_CLOSE;
+// Packet capture: see capture_start_request().
+pub const CMD_CAPTURE_START: u16 = CMD_MIN + 12;
+pub const CMD_CAPTURE_STOP: u16 = CMD_MIN + 13;
+
+// UDP: see udp_socket_bind_request().
+pub const CMD_UDP_SOCKET_BIND: u16 = CMD_MIN + 14;
+pub const CMD_UDP_SOCKET_TX: u16 = CMD_MIN + 15;
+pub const CMD_UDP_SOCKET_RX: u16 = CMD_MIN + 16;
+pub const CMD_UDP_SOCKET_DROP: u16 = CMD_MIN + 17;
+
+pub const CMD_MAX: u16 = CMD_UDP_SOCKET_DROP;
 
 pub const EVT_TCP_STREAM_STATE_CHANGED: u16 = CMD_MIN;
+pub const EVT_CAPTURE_PACKET: u16 = CMD_MIN + 1;
+/// IO pages of a CMD_TCP_STREAM_TX with FLAG_TCP_STREAM_TX_NOTIFY were freed
+/// after sys-io had queued them: the stream may be written to without waiting.
+pub const EVT_TCP_STREAM_TX_SPACE: u16 = CMD_MIN + 2;
+/// A connection to listener msg.handle is waiting to be accepted.
+pub const EVT_TCP_LISTENER_READY: u16 = CMD_MIN + 3;
 
 pub const TCP_OPTION_SHUT_RD: u64 = 1 << 0;
 pub const TCP_OPTION_SHUT_WR: u64 = 1 << 1;
 pub const TCP_OPTION_NODELAY: u64 = 1 << 2;
 pub const TCP_OPTION_TTL: u64 = 1 << 3;
+/// Close the connection with E_TIMED_OUT if it sees no activity for the
+/// time specified in payload.args_64()[1], in nanoseconds (zero: never).
+pub const TCP_OPTION_IDLE_TIMEOUT: u64 = 1 << 4;
+/// CMD_TCP_STREAM_GET_OPTION only: PID and capabilities of the process owning
+/// the other end of a loopback connection, in payload.args_64()[0] and [1].
+/// Fails with E_NOT_IMPLEMENTED if the peer is not local.
+pub const TCP_OPTION_PEER_CREDS: u64 = 1 << 5;
+/// Stop (payload.args_64()[1] == 1) or resume (== 0) delivering received
+/// bytes to the application. While paused, sys-io does not read from the
+/// socket, advertises a zero receive window, and does not close the socket
+/// for being idle (see TCP_OPTION_IDLE_TIMEOUT).
+pub const TCP_OPTION_RX_PAUSED: u64 = 1 << 6;
+/// CMD_TCP_STREAM_GET_OPTION only: the offset in the inbound stream of the
+/// last urgent byte received (TCP urgent mark) in payload.args_64()[0], or
+/// u64::MAX if none. Urgent bytes are delivered inline, as regular bytes.
+pub const TCP_OPTION_URGENT_MARK: u64 = 1 << 7;
+/// What CMD_TCP_STREAM_CLOSE does with bytes not yet sent: payload.args_64()[1]
+/// is the linger timeout in nanoseconds, or u64::MAX for off (the default).
+/// Off or zero: the connection is reset. Otherwise, the close completes
+/// once the queued bytes are acknowledged (and a FIN is sent), or with
+/// E_TIMED_OUT (and a RST) if the timeout expires first.
+pub const TCP_OPTION_LINGER: u64 = 1 << 8;
+/// CMD_TCP_STREAM_GET_OPTION only: the number of bytes received on the stream
+/// so far, delivered to the application or buffered in sys-io, in
+/// payload.args_64()[0]. Bytes held back while TCP_OPTION_RX_PAUSED is set
+/// are not counted.
+pub const TCP_OPTION_RX_RECEIVED: u64 = 1 << 9;
 
 pub const TCP_RX_MAX_INFLIGHT: u64 = 8;
 
+/// If set in CMD_TCP_LISTENER_BIND flags, the listener may not bind to an address still
+/// in use by connections of a previous listener. See bind_tcp_listener_request().
+pub const FLAG_TCP_LISTENER_EXCLUSIVE_ADDR: u32 = 1 << 31;
+/// If set in CMD_TCP_LISTENER_BIND flags, the listener may share its address
+/// with other listeners of the same process. See bind_tcp_listener_request().
+pub const FLAG_TCP_LISTENER_REUSE_PORT: u32 = 1 << 30;
+/// The lower bits of CMD_TCP_LISTENER_BIND flags contain the number of listeners.
+pub const TCP_LISTENER_NUM_LISTENERS_MASK: u32 = 0xFF;
+
+/// If set in CMD_TCP_LISTENER_DROP flags, connected-but-not-yet-accepted
+/// connections are closed with a FIN instead of a RST.
+pub const FLAG_TCP_LISTENER_DROP_FIN: u32 = 1;
+
+/// CMD_TCP_LISTENER_SET_OPTION option (in payload.args_64()[0]): stop accepting
+/// new connections; already connected ones can still be accepted. Once they
+/// are all accepted, accept() fails with E_INVALID_ARGUMENT.
+pub const TCP_LISTENER_OPTION_DRAIN: u64 = 1;
+/// CMD_TCP_LISTENER_SET_OPTION option: TCP_OPTION_IDLE_TIMEOUT (payload.args_64()[1])
+/// for connections accepted from this listener.
+pub const TCP_LISTENER_OPTION_IDLE_TIMEOUT: u64 = 2;
+/// CMD_TCP_LISTENER_GET_OPTION option: the number of connections that can be
+/// accepted without waiting, in payload.args_64()[0].
+pub const TCP_LISTENER_OPTION_PENDING_ACCEPTS: u64 = 3;
+/// CMD_TCP_LISTENER_GET_OPTION option: the number of connections accepted
+/// from this listener so far, in payload.args_64()[0].
+pub const TCP_LISTENER_OPTION_ACCEPTED: u64 = 4;
+
+/// If set in CMD_TCP_STREAM_CONNECT flags, msg.handle contains the index
+/// of a shared page with the local address to bind to.
+pub const FLAG_TCP_STREAM_CONNECT_LOCAL_ADDR: u32 = 1;
+
+/// If set in CMD_TCP_STREAM_TX flags, the last byte of the message is sent
+/// as TCP urgent data (the urgent pointer is set to point past it). Unlike
+/// other writes, these get a response (msg.id must be set): on error
+/// (e.g. E_NOT_IMPLEMENTED if sys-io does not track the connection), the
+/// message is dropped.
+pub const FLAG_TCP_STREAM_TX_URGENT: u32 = 1;
+/// If set in CMD_TCP_STREAM_TX flags (the client has no free IO pages left
+/// on the subchannel), sys-io sends EVT_TCP_STREAM_TX_SPACE when it frees
+/// pages it could not free right away.
+pub const FLAG_TCP_STREAM_TX_NOTIFY: u32 = 2;
+
 /// Each IO Channel in moto_ipc::io_channel has 64 pages (for the server and for the client).
 /// Using the full channel per socket is wasteful, so channels are split into subchannels.
 /// A channel can be split into 2^0, 2^1, 2^2, ... 2^6 subchannels (technically, we
@@ -117,10 +202,31 @@ impl TcpState {
 /// reduces the chance that an incoming connection is rejected because there are no
 /// outstanding listeners due to a spike in incoming connections.
 /// `num_listeners` can be no more than 32.
-pub fn bind_tcp_listener_request(addr: &SocketAddr, num_listeners: Option<u8>) -> io_channel::Msg {
+///
+/// By default (similar to SO_REUSEADDR), the listener may bind to an address that
+/// is still used by connections accepted by a previous, now dropped, listener
+/// (e.g. when a server is restarted). If `exclusive_addr` is true (similar to
+/// SO_EXCLUSIVEADDRUSE), such a bind fails with E_ALREADY_IN_USE. Two active
+/// listeners may not share an address either way, unless `reuse_port` allows it.
+///
+/// If `reuse_port` is true (similar to SO_REUSEPORT), several listeners of the
+/// same process, all bound with `reuse_port`, may bind to the same address;
+/// incoming connections are distributed among them round-robin.
+pub fn bind_tcp_listener_request(
+    addr: &SocketAddr,
+    num_listeners: Option<u8>,
+    exclusive_addr: bool,
+    reuse_port: bool,
+) -> io_channel::Msg {
     let mut msg = io_channel::Msg::new();
     msg.command = CMD_TCP_LISTENER_BIND;
     msg.flags = num_listeners.unwrap_or(0) as u32;
+    if exclusive_addr {
+        msg.flags |= FLAG_TCP_LISTENER_EXCLUSIVE_ADDR;
+    }
+    if reuse_port {
+        msg.flags |= FLAG_TCP_LISTENER_REUSE_PORT;
+    }
     put_socket_addr(&mut msg.payload, addr);
 
     msg
@@ -163,6 +269,30 @@ pub fn tcp_stream_connect_timeout_request(
     msg
 }
 
+/// Bind the outgoing connection in `msg` (a CMD_TCP_STREAM_CONNECT request) to
+/// `local_addr`. The payload is fully used by the remote address, the subchannel
+/// mask and the timeout, so the local address is passed in a shared page.
+/// If the local port is zero, an ephemeral port is used.
+pub fn tcp_stream_connect_set_local_addr(
+    msg: &mut io_channel::Msg,
+    page: io_channel::IoPage,
+    local_addr: &SocketAddr,
+) {
+    debug_assert_eq!(msg.command, CMD_TCP_STREAM_CONNECT);
+    put_socket_addr_to_page(&page, local_addr);
+    msg.flags |= FLAG_TCP_STREAM_CONNECT_LOCAL_ADDR;
+    msg.handle = io_channel::IoPage::into_u16(page) as u64;
+}
+
+/// Returns the index of the page with the local address, if the connect request has one.
+pub fn tcp_stream_connect_local_addr_page(msg: &io_channel::Msg) -> Option<u16> {
+    if msg.flags & FLAG_TCP_STREAM_CONNECT_LOCAL_ADDR == 0 {
+        None
+    } else {
+        Some(msg.handle as u16)
+    }
+}
+
 pub fn tcp_stream_connect_timeout(msg: &io_channel::Msg) -> Option<moto_rt::time::Instant> {
     let mut timeout = msg.payload.args_32()[5];
     timeout &= u32::MAX - 1;
@@ -173,16 +303,50 @@ pub fn tcp_stream_connect_timeout(msg: &io_channel::Msg) -> Option<moto_rt::time
     Some(moto_rt::time::Instant::from_u64(timeout))
 }
 
+pub fn idle_timeout_from_nanos(nanos: u64) -> Option<core::time::Duration> {
+    if nanos == 0 {
+        None
+    } else {
+        Some(core::time::Duration::from_nanos(nanos))
+    }
+}
+
 pub fn tcp_stream_tx_msg(
     handle: u64,
     io_page: io_channel::IoPage,
     sz: usize,
     timestamp: u64,
+) -> io_channel::Msg {
+    tcp_stream_tx_sg_msg(handle, [io_page], sz, timestamp)
+}
+
+/// The max number of pages in a CMD_TCP_STREAM_TX message; args_64()[1..3]
+/// hold the size and the timestamp, so only shared_pages()[0..4] are free.
+pub const TCP_STREAM_TX_MAX_PAGES: usize = 4;
+
+/// A CMD_TCP_STREAM_TX message that sends @sz bytes from up to
+/// TCP_STREAM_TX_MAX_PAGES pages, in order; all but the last page must be
+/// full, and the last one not empty (sys-io fails the write otherwise).
+pub fn tcp_stream_tx_sg_msg(
+    handle: u64,
+    io_pages: impl IntoIterator<Item = io_channel::IoPage>,
+    sz: usize,
+    timestamp: u64,
 ) -> io_channel::Msg {
     let mut msg = io_channel::Msg::new();
     msg.command = CMD_TCP_STREAM_TX;
     msg.handle = handle;
-    msg.payload.shared_pages_mut()[0] = io_channel::IoPage::into_u16(io_page);
+
+    let slots = &mut msg.payload.shared_pages_mut()[0..TCP_STREAM_TX_MAX_PAGES];
+    slots.fill(io_channel::NO_PAGE);
+    let mut count = 0;
+    for io_page in io_pages {
+        assert!(count < TCP_STREAM_TX_MAX_PAGES);
+        slots[count] = io_channel::IoPage::into_u16(io_page);
+        count += 1;
+    }
+    assert!(sz > (count - 1) * io_channel::PAGE_SIZE && sz <= count * io_channel::PAGE_SIZE);
+
     msg.payload.args_64_mut()[1] = sz as u64;
     msg.payload.args_64_mut()[2] = timestamp;
 
@@ -236,3 +400,241 @@ pub fn put_socket_addr(payload: &mut io_channel::Payload, addr: &SocketAddr) {
         }
     }
 }
+
+// Page layout: [0]: 4 or 6; [2..4]: port (LE); [4..8] or [4..20]: ip octets.
+pub fn put_socket_addr_to_page(page: &io_channel::IoPage, addr: &SocketAddr) {
+    let bytes = page.bytes_mut();
+    bytes[2..4].copy_from_slice(&addr.port().to_le_bytes());
+    match addr.ip() {
+        IpAddr::V4(addr_v4) => {
+            bytes[0] = 4;
+            bytes[4..8].copy_from_slice(&addr_v4.octets());
+        }
+        IpAddr::V6(addr_v6) => {
+            bytes[0] = 6;
+            bytes[4..20].copy_from_slice(&addr_v6.octets());
+        }
+    }
+}
+
+pub fn get_socket_addr_from_page(page: &io_channel::IoPage) -> Result<SocketAddr, ErrorCode> {
+    let bytes = page.bytes();
+    let port = u16::from_le_bytes([bytes[2], bytes[3]]);
+    match bytes[0] {
+        4 => {
+            let octets: [u8; 4] = bytes[4..8].try_into().unwrap();
+            Ok(SocketAddr::new(IpAddr::V4(Ipv4Addr::from(octets)), port))
+        }
+        6 => {
+            let octets: [u8; 16] = bytes[4..20].try_into().unwrap();
+            Ok(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port))
+        }
+        _ => Err(moto_rt::E_INVALID_ARGUMENT),
+    }
+}
+
+/// Packet capture directions (CaptureFilter::directions).
+pub const CAPTURE_RX: u8 = 1;
+pub const CAPTURE_TX: u8 = 2;
+
+/// Each captured frame is delivered as a pcap record (a record header followed by
+/// the frame bytes) in a single page, so frames longer than this are truncated.
+pub const PCAP_RECORD_HEADER_SIZE: usize = 16;
+pub const CAPTURE_MAX_SNAPLEN: u32 = (io_channel::PAGE_SIZE - PCAP_RECORD_HEADER_SIZE) as u32;
+
+/// LINKTYPE_ETHERNET: all net devices, including the loopback, carry Ethernet frames.
+pub const PCAP_LINKTYPE_ETHERNET: u32 = 1;
+
+/// A pcap file is this header followed by the records of EVT_CAPTURE_PACKET, as is.
+pub fn pcap_file_header(snaplen: u32) -> [u8; 24] {
+    let mut header = [0_u8; 24];
+    header[0..4].copy_from_slice(&0xa1b2_c3d4_u32.to_le_bytes()); // Microsecond timestamps.
+    header[4..6].copy_from_slice(&2_u16.to_le_bytes()); // Version 2.4.
+    header[6..8].copy_from_slice(&4_u16.to_le_bytes());
+    // [8..16]: zero timezone offset and timestamp accuracy.
+    header[16..20].copy_from_slice(&snaplen.to_le_bytes());
+    header[20..24].copy_from_slice(&PCAP_LINKTYPE_ETHERNET.to_le_bytes());
+    header
+}
+
+/// Which frames to capture. A frame matches if all the set fields match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CaptureFilter {
+    /// The index of the device, in the order of stats::IoStatsService::get_net_dev_stats().
+    /// None: all devices.
+    pub device_idx: Option<u16>,
+    /// CAPTURE_RX and/or CAPTURE_TX. Note that on the loopback device, every
+    /// frame is both sent and received.
+    pub directions: u8,
+    /// The IP protocol number (e.g. 6 for TCP); zero: any.
+    pub protocol: u8,
+    /// TCP or UDP source or destination port; zero: any.
+    pub port: u16,
+    /// At most CAPTURE_MAX_SNAPLEN.
+    pub snaplen: u32,
+}
+
+impl Default for CaptureFilter {
+    fn default() -> Self {
+        Self {
+            device_idx: None,
+            directions: CAPTURE_RX | CAPTURE_TX,
+            protocol: 0,
+            port: 0,
+            snaplen: CAPTURE_MAX_SNAPLEN,
+        }
+    }
+}
+
+/// Prepare CMD_CAPTURE_START IO message. Requires moto_sys::caps::CAP_NET_CAPTURE.
+///
+/// Once started, copies of matching frames are sent to the connection as
+/// EVT_CAPTURE_PACKET messages: shared_pages()[0] is a server page (see
+/// io_channel::ClientConnection::take_server_page()) from `subchannel_mask` with
+/// a pcap record, args_64()[1] is the length of the record, and args_64()[2] is the
+/// number of frames dropped so far because the consumer did not keep up (i.e. all
+/// its pages were in use). There can be one capture per connection.
+pub fn capture_start_request(filter: &CaptureFilter, subchannel_mask: u64) -> io_channel::Msg {
+    let mut msg = io_channel::Msg::new();
+    msg.command = CMD_CAPTURE_START;
+    msg.payload.args_64_mut()[0] = subchannel_mask;
+    msg.payload.args_32_mut()[2] = filter.snaplen;
+    msg.payload.args_16_mut()[6] = filter.device_idx.unwrap_or(u16::MAX);
+    msg.payload.args_16_mut()[7] = filter.port;
+    msg.payload.args_8_mut()[16] = filter.directions;
+    msg.payload.args_8_mut()[17] = filter.protocol;
+
+    msg
+}
+
+pub fn capture_filter(msg: &io_channel::Msg) -> CaptureFilter {
+    debug_assert_eq!(msg.command, CMD_CAPTURE_START);
+    CaptureFilter {
+        device_idx: match msg.payload.args_16()[6] {
+            u16::MAX => None,
+            idx => Some(idx),
+        },
+        directions: msg.payload.args_8()[16],
+        protocol: msg.payload.args_8()[17],
+        port: msg.payload.args_16()[7],
+        snaplen: msg.payload.args_32()[2],
+    }
+}
+
+/// Prepare CMD_CAPTURE_STOP IO message. The response has the number of frames
+/// captured in args_64()[0], and the number of frames dropped in args_64()[1].
+pub fn capture_stop_request() -> io_channel::Msg {
+    let mut msg = io_channel::Msg::new();
+    msg.command = CMD_CAPTURE_STOP;
+
+    msg
+}
+
+/// A UDP datagram is carried in a single page, so it is at most this large.
+/// Datagrams are never truncated: sending a larger one fails with
+/// E_MSG_TOO_LARGE, and larger incoming ones are dropped.
+pub const UDP_MAX_DATAGRAM_SIZE: usize = io_channel::PAGE_SIZE;
+
+/// The address family of the peer address of a UDP datagram
+/// (see put_udp_peer_addr()).
+pub const UDP_AF_INET: u8 = 4;
+pub const UDP_AF_INET6: u8 = 6;
+/// Or-ed into the address family: the peer address is a broadcast (IPv4)
+/// or multicast group address. Reserved: not supported yet, so sys-io
+/// fails such datagrams with E_NOT_IMPLEMENTED.
+pub const UDP_AF_FLAG_GROUP: u8 = 0x80;
+
+/// Prepare a CMD_UDP_SOCKET_BIND IO message. The socket is not connected:
+/// each datagram sent (CMD_UDP_SOCKET_TX) carries its destination, and each
+/// datagram received (CMD_UDP_SOCKET_RX, sent by sys-io with msg.id == 0)
+/// carries its source. If the port is zero, an ephemeral port is used; if
+/// the IP is unspecified, the socket is bound on all devices.
+///
+/// The subchannel mask is passed in msg.handle, as the payload is fully used
+/// by the address. The response has the socket handle in msg.handle and the
+/// bound address (with the actual port) in the payload.
+pub fn udp_socket_bind_request(addr: &SocketAddr, subchannel_mask: u64) -> io_channel::Msg {
+    let mut msg = io_channel::Msg::new();
+    msg.command = CMD_UDP_SOCKET_BIND;
+    msg.handle = subchannel_mask;
+    put_socket_addr(&mut msg.payload, addr);
+
+    msg
+}
+
+// UDP payload layout: args_8()[0..16]: ip octets (IPv4: [0..4]); args_16()[8]: port;
+// args_8()[18]: address family; args_16()[10]: page; args_16()[11]: datagram size.
+pub fn put_udp_peer_addr(payload: &mut io_channel::Payload, addr: &SocketAddr) {
+    payload.args_8_mut()[0..16].fill(0);
+    match addr.ip() {
+        IpAddr::V4(addr_v4) => {
+            payload.args_8_mut()[0..4].copy_from_slice(&addr_v4.octets());
+            payload.args_8_mut()[18] = UDP_AF_INET;
+        }
+        IpAddr::V6(addr_v6) => {
+            payload.args_8_mut()[0..16].copy_from_slice(&addr_v6.octets());
+            payload.args_8_mut()[18] = UDP_AF_INET6;
+        }
+    }
+    payload.args_16_mut()[8] = addr.port();
+    payload.args_8_mut()[19] = 0;
+}
+
+pub fn get_udp_peer_addr(payload: &io_channel::Payload) -> Result<SocketAddr, ErrorCode> {
+    let port = payload.args_16()[8];
+    match payload.args_8()[18] {
+        UDP_AF_INET => {
+            let octets: [u8; 4] = payload.args_8()[0..4].try_into().unwrap();
+            Ok(SocketAddr::new(IpAddr::V4(Ipv4Addr::from(octets)), port))
+        }
+        UDP_AF_INET6 => {
+            let octets: [u8; 16] = payload.args_8()[0..16].try_into().unwrap();
+            Ok(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port))
+        }
+        family if family & UDP_AF_FLAG_GROUP != 0 => Err(moto_rt::E_NOT_IMPLEMENTED),
+        _ => Err(moto_rt::E_INVALID_ARGUMENT),
+    }
+}
+
+/// If set in CMD_UDP_SOCKET_TX flags, the datagram is not fragmented: it
+/// fails with E_MSG_TOO_LARGE if it does not fit in the device's MTU.
+pub const FLAG_UDP_TX_DONT_FRAG: u32 = 1;
+
+/// A CMD_UDP_SOCKET_TX message: sends @sz bytes in @io_page as one datagram to @dest.
+/// sys-io responds once the datagram is queued (or fails).
+pub fn udp_socket_tx_msg(
+    handle: u64,
+    io_page: io_channel::IoPage,
+    sz: usize,
+    dest: &SocketAddr,
+) -> io_channel::Msg {
+    assert!(sz <= UDP_MAX_DATAGRAM_SIZE);
+
+    let mut msg = io_channel::Msg::new();
+    msg.command = CMD_UDP_SOCKET_TX;
+    msg.handle = handle;
+    put_udp_peer_addr(&mut msg.payload, dest);
+    msg.payload.args_16_mut()[10] = io_channel::IoPage::into_u16(io_page);
+    msg.payload.args_16_mut()[11] = sz as u16;
+
+    msg
+}
+
+/// A CMD_UDP_SOCKET_RX message: a datagram of @sz bytes in @io_page, from @src.
+pub fn udp_socket_rx_msg(
+    handle: u64,
+    io_page: io_channel::IoPage,
+    sz: usize,
+    src: &SocketAddr,
+) -> io_channel::Msg {
+    let mut msg = udp_socket_tx_msg(handle, io_page, sz, src);
+    msg.command = CMD_UDP_SOCKET_RX;
+
+    msg
+}
+
+/// The page and the size of the datagram in a CMD_UDP_SOCKET_TX/RX message.
+pub fn udp_datagram(msg: &io_channel::Msg) -> (u16, usize) {
+    let args = msg.payload.args_16();
+    (args[10], args[11] as usize)
+}
@@ -0,0 +1,35 @@
+// The parts of the IO stats service (see stats.rs) that do not need std,
+// so that rt.vdso can use them.
+
+use moto_ipc::sync::RequestHeader;
+use moto_ipc::sync::ResponseHeader;
+
+pub const URL_IO_STATS: &str = "sys-io-stats-service";
+
+pub const CMD_PROCESS_IO: u16 = 1004;
+
+/// Bytes moved by a process through sys-io, counted since sys-io started.
+/// Counters of exited processes are kept for a while (the least recently
+/// updated ones are dropped first), so that they can be read after wait().
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProcessIoStatsV1 {
+    pub fs_bytes_read: u64,
+    pub fs_bytes_written: u64,
+    pub net_bytes_rx: u64, // TCP and UDP payload delivered to the process.
+    pub net_bytes_tx: u64, // TCP and UDP payload sent by the process.
+}
+
+// CMD_PROCESS_IO: the I/O of process pid. Allowed for the process itself,
+// its parent, and CAP_SYS.
+#[repr(C)]
+pub struct GetProcessIoRequest {
+    pub header: RequestHeader,
+    pub pid: u64,
+}
+
+#[repr(C)]
+pub struct GetProcessIoResponse {
+    pub header: ResponseHeader,
+    pub stats: ProcessIoStatsV1,
+}
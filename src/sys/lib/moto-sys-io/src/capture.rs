@@ -0,0 +1,110 @@
+//! Packet capture client (see api_net::capture_start_request()).
+
+extern crate std;
+
+use moto_ipc::io_channel;
+use moto_rt::ErrorCode;
+use moto_sys::{SysCpu, SysHandle};
+
+use crate::api_net::{self, CaptureFilter};
+
+/// A packet capture on its own connection to sys-io. Requires CAP_NET_CAPTURE.
+pub struct PacketCapture {
+    conn: io_channel::ClientConnection,
+    snaplen: u32,
+    dropped: u64,
+}
+
+impl PacketCapture {
+    pub fn start(filter: &CaptureFilter) -> Result<Self, ErrorCode> {
+        let mut self_ = Self {
+            conn: io_channel::ClientConnection::connect("sys-io")?,
+            snaplen: filter.snaplen,
+            dropped: 0,
+        };
+
+        // The connection is not shared, so all pages can hold records.
+        self_.call(api_net::capture_start_request(filter, u64::MAX))?;
+        Ok(self_)
+    }
+
+    /// The header to write before the records to get a pcap file.
+    pub fn file_header(&self) -> [u8; 24] {
+        api_net::pcap_file_header(self.snaplen)
+    }
+
+    /// The number of frames dropped so far, as of the last record received.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Waits for the next captured frame, and returns its pcap record
+    /// (the record header followed by the frame bytes). Returns None on timeout.
+    pub fn next_record(
+        &mut self,
+        timeout: Option<moto_rt::time::Instant>,
+    ) -> Result<Option<std::vec::Vec<u8>>, ErrorCode> {
+        loop {
+            let Some(msg) = self.recv(timeout)? else {
+                return Ok(None);
+            };
+            if msg.command != api_net::EVT_CAPTURE_PACKET {
+                continue;
+            }
+
+            let page = self.conn.take_server_page(&msg, 0)?;
+            let len = (msg.payload.args_64()[1] as usize).min(io_channel::PAGE_SIZE);
+            self.dropped = msg.payload.args_64()[2];
+            return Ok(Some(page.bytes()[0..len].to_vec()));
+        }
+    }
+
+    /// Stops the capture. Returns the total number of frames (captured, dropped).
+    pub fn stop(mut self) -> Result<(u64, u64), ErrorCode> {
+        let resp = self.call(api_net::capture_stop_request())?;
+        Ok((resp.payload.args_64()[0], resp.payload.args_64()[1]))
+    }
+
+    fn call(&mut self, msg: io_channel::Msg) -> Result<io_channel::Msg, ErrorCode> {
+        let command = msg.command;
+        self.conn
+            .submit_sqe_blocking(msg, None, io_channel::RetryPolicy::Wait)?;
+
+        loop {
+            let resp = self.recv(None)?.unwrap();
+            if resp.command == command {
+                return match resp.status() {
+                    moto_rt::E_OK => Ok(resp),
+                    err => Err(err),
+                };
+            }
+
+            // A record that arrived before the response: drop it.
+            if resp.command == api_net::EVT_CAPTURE_PACKET {
+                let _ = self.conn.take_server_page(&resp, 0);
+            }
+        }
+    }
+
+    fn recv(
+        &mut self,
+        timeout: Option<moto_rt::time::Instant>,
+    ) -> Result<Option<io_channel::Msg>, ErrorCode> {
+        loop {
+            if let Ok(msg) = self.conn.recv() {
+                return Ok(Some(msg));
+            }
+
+            match SysCpu::wait(
+                &mut [self.conn.server_handle()],
+                SysHandle::NONE,
+                SysHandle::NONE,
+                timeout,
+            ) {
+                Ok(()) => {}
+                Err(moto_rt::E_TIMED_OUT) => return Ok(None),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
@@ -0,0 +1,1013 @@
+//! Async file I/O, stat() and rename(), over an io_channel connection to the FS driver
+//! (see api_fs::FS_IO_URL).
+//!
+//! Requests are routed to their futures by a reactor thread, so the futures
+//! work with any executor; block_on() is the simplest one. Large reads and
+//! writes are split into IO_MAX_BYTES requests that are all in flight at the
+//! same time; vectored reads and writes gather their segments into the same
+//! requests. Dropping a future cancels it: its request still completes, but
+//! the completion, and any pages it carries, are discarded by the reactor.
+//!
+//! Other requests can be sent with submit(); submit_with_timeout() and
+//! submit_cancellable() give up on them after a timeout, or when cancelled
+//! via a CancelToken, the same way.
+
+extern crate std;
+
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use std::boxed::Box;
+use std::collections::{BTreeMap, HashMap};
+use std::string::String;
+use std::sync::{Arc, Mutex, Once, OnceLock};
+use std::vec::Vec;
+
+use moto_ipc::io_channel::{
+    ClientConnection, IoPage, Msg, CHANNEL_PAGE_COUNT, FLAG_CQE_SERVER_PAGES, NO_PAGE, PAGE_SIZE,
+};
+use moto_rt::time::Instant;
+use moto_rt::ErrorCode;
+use moto_sys::{SysCpu, SysHandle};
+
+use crate::api_fs::*;
+
+struct Reactor {
+    conn: ClientConnection,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    disconnected: bool,
+    // Server pages promised to CMD_IO_READ requests in flight, or not yet
+    // dropped by the client: never more than the server's pool, so the
+    // server does not run out of pages.
+    server_pages_reserved: usize,
+    completions: HashMap<u64, Msg>,
+    wakers: HashMap<u64, Waker>,
+    // Requests whose futures have been dropped, with their reservations.
+    orphans: HashMap<u64, usize>,
+    // CMD_IO_CLOSE requests from File::drop() that did not fit into the queue.
+    deferred: Vec<Msg>,
+    // Futures waiting for queue space or pages.
+    space_waiters: Vec<Waker>,
+    // Timers (see Timer) by deadline, woken by the reactor thread, which
+    // waits until the first one; it is woken when an earlier one is added.
+    timers: BTreeMap<(Instant, u64), Waker>,
+    next_timer_id: u64,
+    reactor_thread: u64, // SysHandle; zero until the thread has started.
+    reactor_deadline: Option<Instant>,
+}
+
+impl State {
+    fn wake_space_waiters(&mut self) {
+        for waker in self.space_waiters.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+static REACTOR: OnceLock<Result<Reactor, ErrorCode>> = OnceLock::new();
+
+fn reactor() -> Result<&'static Reactor, ErrorCode> {
+    static STARTED: Once = Once::new();
+
+    let reactor = REACTOR
+        .get_or_init(|| {
+            ClientConnection::connect(FS_IO_URL).map(|conn| Reactor {
+                conn,
+                state: Mutex::new(State::default()),
+            })
+        })
+        .as_ref()
+        .map_err(|err| *err)?;
+    STARTED.call_once(|| {
+        std::thread::spawn(move || reactor.run());
+    });
+    Ok(reactor)
+}
+
+impl Reactor {
+    fn run(&self) {
+        self.state.lock().unwrap().reactor_thread = SysHandle::this_thread().as_u64();
+        loop {
+            if self.poll_completions().is_err() {
+                break;
+            }
+            let deadline = self.fire_timers();
+            // The server wakes us after sending completions; a wake that
+            // comes before we wait is not lost.
+            match SysCpu::wait(
+                &mut [self.conn.server_handle()],
+                SysHandle::NONE,
+                SysHandle::NONE,
+                deadline,
+            ) {
+                Ok(()) | Err(moto_rt::E_TIMED_OUT) => {}
+                Err(_) => break,
+            }
+        }
+
+        // The server is gone: fail everything in flight, and everything after.
+        let mut state = self.state.lock().unwrap();
+        state.disconnected = true;
+        for (_, waker) in state.wakers.drain() {
+            waker.wake();
+        }
+        for (_, waker) in core::mem::take(&mut state.timers) {
+            waker.wake();
+        }
+        state.wake_space_waiters();
+    }
+
+    // Wakes expired timers; returns the deadline of the next one.
+    fn fire_timers(&self) -> Option<Instant> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        while let Some(timer) = state.timers.first_entry() {
+            if timer.key().0 > now {
+                break;
+            }
+            timer.remove().wake();
+        }
+        state.reactor_deadline = state.timers.first_key_value().map(|(key, _)| key.0);
+        state.reactor_deadline
+    }
+
+    fn poll_completions(&self) -> Result<(), ErrorCode> {
+        let mut cqes = [Msg::new(); 16];
+        loop {
+            let mut state = self.state.lock().unwrap();
+            while let Some(sqe) = state.deferred.last().copied() {
+                if self.conn.send(sqe).is_err() {
+                    break;
+                }
+                state.deferred.pop();
+                SysCpu::wake(self.conn.server_handle())?;
+            }
+
+            let received = self.conn.recv_batch(&mut cqes)?;
+            if received == 0 {
+                return Ok(());
+            }
+            for cqe in &cqes[0..received] {
+                if let Some(reserved) = state.orphans.remove(&cqe.id) {
+                    core::mem::drop(self.take_server_pages(cqe));
+                    state.server_pages_reserved -= reserved;
+                    continue;
+                }
+                if let Some(waker) = state.wakers.remove(&cqe.id) {
+                    waker.wake();
+                }
+                state.completions.insert(cqe.id, *cqe);
+            }
+            // Queue space, and maybe pages, have been freed.
+            state.wake_space_waiters();
+        }
+    }
+
+    // Server pages attached to @cqe; the ones that fail validation are freed
+    // by get_page(), and the rest when dropped.
+    fn take_server_pages(&self, cqe: &Msg) -> Vec<Result<IoPage, ErrorCode>> {
+        if cqe.flags & FLAG_CQE_SERVER_PAGES == 0 {
+            return Vec::new();
+        }
+        (0..IO_MAX_PAGES)
+            .take_while(|slot| cqe.payload.shared_pages()[*slot] != NO_PAGE)
+            .map(|slot| self.conn.take_server_page(cqe, slot))
+            .collect()
+    }
+
+    // Client pages in @sqe that the server will never see.
+    fn reclaim_client_pages(&self, sqe: &Msg, num_pages: usize) {
+        for slot in 0..num_pages {
+            let _ = self.conn.get_page(sqe.payload.shared_pages()[slot]);
+        }
+    }
+
+    async fn alloc_pages(&'static self, num_pages: usize) -> Result<Vec<IoPage>, ErrorCode> {
+        core::future::poll_fn(|cx| {
+            let mut state = self.state.lock().unwrap();
+            if state.disconnected {
+                return Poll::Ready(Err(moto_rt::E_NOT_CONNECTED));
+            }
+            match self.conn.alloc_pages(IO_SUBCHANNEL_MASK, num_pages) {
+                Err(moto_rt::E_NOT_READY) => {
+                    state.space_waiters.push(cx.waker().clone());
+                    Poll::Pending
+                }
+                result => Poll::Ready(result),
+            }
+        })
+        .await
+    }
+
+    async fn reserve_server_pages(
+        &'static self,
+        num_pages: usize,
+    ) -> Result<Reservation, ErrorCode> {
+        core::future::poll_fn(|cx| {
+            let mut state = self.state.lock().unwrap();
+            if state.disconnected {
+                return Poll::Ready(Err(moto_rt::E_NOT_CONNECTED));
+            }
+            if state.server_pages_reserved + num_pages > CHANNEL_PAGE_COUNT {
+                state.space_waiters.push(cx.waker().clone());
+                return Poll::Pending;
+            }
+            state.server_pages_reserved += num_pages;
+            Poll::Ready(Ok(Reservation {
+                reactor: self,
+                num_pages,
+            }))
+        })
+        .await
+    }
+
+    fn submit(
+        &'static self,
+        sqe: Msg,
+        client_pages: usize,
+        reservation: Option<Reservation>,
+    ) -> Submit {
+        Submit {
+            reactor: self,
+            sqe,
+            client_pages,
+            reservation,
+            id: None,
+        }
+    }
+}
+
+// Resolves at @deadline (never, if None), or once the server is gone.
+struct Timer {
+    reactor: &'static Reactor,
+    deadline: Option<Instant>,
+    key: Option<(Instant, u64)>, // In State::timers.
+}
+
+impl Future for Timer {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let Some(deadline) = this.deadline else {
+            return Poll::Pending;
+        };
+        let mut state = this.reactor.state.lock().unwrap();
+        if state.disconnected || Instant::now() >= deadline {
+            if let Some(key) = this.key.take() {
+                state.timers.remove(&key);
+            }
+            return Poll::Ready(());
+        }
+
+        let key = *this.key.get_or_insert_with(|| {
+            state.next_timer_id += 1;
+            (deadline, state.next_timer_id)
+        });
+        state.timers.insert(key, cx.waker().clone());
+        if state.reactor_thread != 0 && state.reactor_deadline.is_none_or(|d| deadline < d) {
+            state.reactor_deadline = Some(deadline);
+            let _ = SysCpu::wake(SysHandle::from(state.reactor_thread));
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.reactor.state.lock().unwrap().timers.remove(&key);
+        }
+    }
+}
+
+/// Cancels a request sent with submit_cancellable(), e.g. when the client it
+/// is made for disconnects. Can be cloned, and used from any thread.
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<CancelState>,
+}
+
+#[derive(Default)]
+struct CancelState {
+    cancelled: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl CancelToken {
+    /// The request's future fails with E_INTERRUPTED, unless it has completed.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::Release);
+        if let Some(waker) = self.inner.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+
+    async fn cancelled(&self) {
+        core::future::poll_fn(|cx| {
+            if self.is_cancelled() {
+                return Poll::Ready(());
+            }
+            *self.inner.waker.lock().unwrap() = Some(cx.waker().clone());
+            // cancel() may have run before the waker was stored.
+            if self.is_cancelled() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+// Polls @future until it completes, or until @stop does: then fails with
+// @err, and drops @future, which reaps its request (see Completion::drop()).
+async fn until<T, F, S>(future: F, stop: S, err: ErrorCode) -> Result<T, ErrorCode>
+where
+    F: Future<Output = Result<T, ErrorCode>>,
+    S: Future<Output = ()>,
+{
+    let mut future = core::pin::pin!(future);
+    let mut stop = core::pin::pin!(stop);
+    core::future::poll_fn(|cx| {
+        if let Poll::Ready(result) = future.as_mut().poll(cx) {
+            return Poll::Ready(result);
+        }
+        match stop.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    })
+    .await
+}
+
+// Server pages reserved for a CMD_IO_READ, released when dropped.
+struct Reservation {
+    reactor: &'static Reactor,
+    num_pages: usize,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let mut state = self.reactor.state.lock().unwrap();
+        state.server_pages_reserved -= self.num_pages;
+        state.wake_space_waiters();
+    }
+}
+
+// Sends an SQE, waiting for queue space if needed. If dropped before the SQE
+// is sent, frees the client pages in it.
+struct Submit {
+    reactor: &'static Reactor,
+    sqe: Msg,
+    client_pages: usize,
+    reservation: Option<Reservation>,
+    id: Option<u64>,
+}
+
+impl Future for Submit {
+    type Output = Result<Completion, ErrorCode>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let reactor = self.reactor;
+        let mut state = reactor.state.lock().unwrap();
+        if state.disconnected {
+            return Poll::Ready(Err(moto_rt::E_NOT_CONNECTED));
+        }
+
+        let id = match self.id {
+            Some(id) => id,
+            None => {
+                state.next_id += 1;
+                self.id = Some(state.next_id);
+                state.next_id
+            }
+        };
+        self.sqe.id = id;
+
+        match reactor.conn.send(self.sqe) {
+            Ok(()) => {}
+            Err(moto_rt::E_NOT_READY) => {
+                state.space_waiters.push(cx.waker().clone());
+                // Let the server drain the queue.
+                let _ = SysCpu::wake(reactor.conn.server_handle());
+                return Poll::Pending;
+            }
+            Err(err) => return Poll::Ready(Err(err)),
+        }
+        core::mem::drop(state);
+
+        // The server owns the client pages now. If it is gone, the reactor
+        // fails the completion.
+        self.client_pages = 0;
+        let _ = SysCpu::wake(reactor.conn.server_handle());
+        Poll::Ready(Ok(Completion {
+            reactor,
+            id,
+            reservation: self.reservation.take(),
+            done: false,
+        }))
+    }
+}
+
+impl Drop for Submit {
+    fn drop(&mut self) {
+        self.reactor
+            .reclaim_client_pages(&self.sqe, self.client_pages);
+    }
+}
+
+// A request in flight: resolves to its CQE. If dropped before that, the
+// reactor discards the CQE when it arrives.
+struct Completion {
+    reactor: &'static Reactor,
+    id: u64,
+    reservation: Option<Reservation>,
+    done: bool,
+}
+
+impl Future for Completion {
+    type Output = Msg;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.reactor.state.lock().unwrap();
+        if let Some(cqe) = state.completions.remove(&self.id) {
+            core::mem::drop(state);
+            self.done = true;
+            return Poll::Ready(cqe);
+        }
+        if state.disconnected {
+            core::mem::drop(state);
+            self.done = true;
+            let mut cqe = Msg::new();
+            cqe.id = self.id;
+            cqe.status = moto_rt::E_NOT_CONNECTED;
+            return Poll::Ready(cqe);
+        }
+        state.wakers.insert(self.id, cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for Completion {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+
+        let reservation = self.reservation.take();
+        let mut state = self.reactor.state.lock().unwrap();
+        state.wakers.remove(&self.id);
+        if let Some(cqe) = state.completions.remove(&self.id) {
+            core::mem::drop(state);
+            core::mem::drop(self.reactor.take_server_pages(&cqe));
+        } else if !state.disconnected {
+            // The reactor releases the reservation with the pages.
+            let reserved = reservation.as_ref().map(|r| r.num_pages).unwrap_or(0);
+            state.orphans.insert(self.id, reserved);
+            core::mem::forget(reservation);
+        }
+    }
+}
+
+// Polls all @futures until they all complete; the results are in order.
+async fn join_all<F: Future>(futures: Vec<F>) -> Vec<F::Output> {
+    let mut futures: Vec<Pin<Box<F>>> = futures.into_iter().map(Box::pin).collect();
+    let mut results: Vec<Option<F::Output>> = futures.iter().map(|_| None).collect();
+    core::future::poll_fn(|cx| {
+        let mut pending = false;
+        for (future, result) in futures.iter_mut().zip(results.iter_mut()) {
+            if result.is_none() {
+                match future.as_mut().poll(cx) {
+                    Poll::Ready(val) => *result = Some(val),
+                    Poll::Pending => pending = true,
+                }
+            }
+        }
+        if pending {
+            Poll::Pending
+        } else {
+            Poll::Ready(results.drain(..).map(|result| result.unwrap()).collect())
+        }
+    })
+    .await
+}
+
+// The number of contiguous bytes done by @results, of @chunk_size bytes
+// each (the last one may be shorter), up to the first short or failed chunk.
+// An error is returned only if the first chunk failed.
+fn sum_chunks(
+    results: Vec<Result<usize, ErrorCode>>,
+    len: usize,
+    chunk_size: usize,
+) -> Result<usize, ErrorCode> {
+    let mut done = 0;
+    for result in results {
+        let expected = chunk_size.min(len - done);
+        match result {
+            Ok(chunk_done) => {
+                done += chunk_done;
+                if chunk_done < expected {
+                    break;
+                }
+            }
+            Err(err) if done == 0 => return Err(err),
+            Err(_) => break,
+        }
+    }
+    Ok(done)
+}
+
+// Splits @bufs, as if they were one buffer, into chunks of @chunk_size bytes
+// (the last one may be shorter), each a list of (parts of) segments.
+fn split_segments<'a>(bufs: &'a [&[u8]], chunk_size: usize) -> Vec<Vec<&'a [u8]>> {
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut chunk_len = 0;
+    for buf in bufs {
+        let mut rest: &'a [u8] = buf;
+        while !rest.is_empty() {
+            let (head, tail) = rest.split_at((chunk_size - chunk_len).min(rest.len()));
+            chunk.push(head);
+            chunk_len += head.len();
+            rest = tail;
+            if chunk_len == chunk_size {
+                chunks.push(core::mem::take(&mut chunk));
+                chunk_len = 0;
+            }
+        }
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+// As split_segments(), for reads.
+fn split_segments_mut<'a>(bufs: &'a mut [&mut [u8]], chunk_size: usize) -> Vec<Vec<&'a mut [u8]>> {
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut chunk_len = 0;
+    for buf in bufs.iter_mut() {
+        let mut rest: &'a mut [u8] = buf;
+        while !rest.is_empty() {
+            let len = (chunk_size - chunk_len).min(rest.len());
+            let (head, tail) = core::mem::take(&mut rest).split_at_mut(len);
+            chunk.push(head);
+            chunk_len += len;
+            rest = tail;
+            if chunk_len == chunk_size {
+                chunks.push(core::mem::take(&mut chunk));
+                chunk_len = 0;
+            }
+        }
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+// Reads into @segments, as if they were one buffer, with one request.
+async fn read_chunk(
+    reactor: &'static Reactor,
+    fd: u64,
+    offset: u64,
+    mut segments: Vec<&mut [u8]>,
+) -> Result<usize, ErrorCode> {
+    let len: usize = segments.iter().map(|segment| segment.len()).sum();
+    let reservation = reactor
+        .reserve_server_pages(len.div_ceil(PAGE_SIZE))
+        .await?;
+
+    let mut sqe = Msg::new();
+    sqe.command = CMD_IO_READ;
+    sqe.handle = fd;
+    sqe.flags = len as u32;
+    sqe.payload.args_64_mut()[2] = offset;
+
+    let mut completion = reactor.submit(sqe, 0, Some(reservation)).await?;
+    let cqe = (&mut completion).await;
+    // Taken whatever the status, so that they are not leaked.
+    let pages = reactor.take_server_pages(&cqe);
+    if cqe.status() != moto_rt::E_OK {
+        return Err(cqe.status());
+    }
+
+    let done = cqe.payload.args_64()[2] as usize;
+    if done > len || pages.len() != done.div_ceil(PAGE_SIZE) {
+        return Err(moto_rt::E_INTERNAL_ERROR);
+    }
+    let pages = pages.into_iter().collect::<Result<Vec<_>, _>>()?;
+
+    // Scatter the pages into the segments.
+    let mut pos = 0;
+    for segment in segments.iter_mut() {
+        let mut filled = 0;
+        while filled < segment.len() && pos < done {
+            let page_offset = pos % PAGE_SIZE;
+            let sz = (PAGE_SIZE - page_offset)
+                .min(segment.len() - filled)
+                .min(done - pos);
+            segment[filled..(filled + sz)]
+                .copy_from_slice(&pages[pos / PAGE_SIZE].bytes()[page_offset..(page_offset + sz)]);
+            filled += sz;
+            pos += sz;
+        }
+    }
+    Ok(done)
+}
+
+// Writes @segments, as if they were one buffer, with one request.
+async fn write_chunk(
+    reactor: &'static Reactor,
+    fd: u64,
+    offset: u64,
+    segments: Vec<&[u8]>,
+) -> Result<usize, ErrorCode> {
+    let len: usize = segments.iter().map(|segment| segment.len()).sum();
+    let num_pages = len.div_ceil(PAGE_SIZE);
+    let pages = reactor.alloc_pages(num_pages).await?;
+
+    // Gather the segments into the pages.
+    let mut pos = 0;
+    for segment in segments {
+        let mut copied = 0;
+        while copied < segment.len() {
+            let page_offset = pos % PAGE_SIZE;
+            let sz = (PAGE_SIZE - page_offset).min(segment.len() - copied);
+            pages[pos / PAGE_SIZE].bytes_mut()[page_offset..(page_offset + sz)]
+                .copy_from_slice(&segment[copied..(copied + sz)]);
+            copied += sz;
+            pos += sz;
+        }
+    }
+
+    let mut sqe = Msg::new();
+    sqe.command = CMD_IO_WRITE;
+    sqe.handle = fd;
+    sqe.flags = len as u32;
+    sqe.payload.shared_pages_mut()[0..IO_MAX_PAGES].fill(NO_PAGE);
+    for (slot, page) in pages.into_iter().enumerate() {
+        sqe.payload.shared_pages_mut()[slot] = IoPage::into_u16(page);
+    }
+    sqe.payload.args_64_mut()[2] = offset;
+
+    let cqe = reactor.submit(sqe, num_pages, None).await?.await;
+    if cqe.status() != moto_rt::E_OK {
+        return Err(cqe.status());
+    }
+    Ok(cqe.payload.args_64()[2] as usize)
+}
+
+async fn simple_request(reactor: &'static Reactor, command: u16, fd: u64) -> Result<(), ErrorCode> {
+    let mut sqe = Msg::new();
+    sqe.command = command;
+    sqe.handle = fd;
+    let cqe = reactor.submit(sqe, 0, None).await?.await;
+    if cqe.status() != moto_rt::E_OK {
+        return Err(cqe.status());
+    }
+    Ok(())
+}
+
+/// Allocates @num_pages (at most IO_MAX_PAGES) client pages, e.g. for
+/// submit(), waiting until there are enough free ones.
+pub async fn alloc_pages(num_pages: usize) -> Result<Vec<IoPage>, ErrorCode> {
+    if num_pages > IO_MAX_PAGES {
+        return Err(moto_rt::E_INVALID_ARGUMENT);
+    }
+    reactor()?.alloc_pages(num_pages).await
+}
+
+async fn submit_request(
+    mut sqe: Msg,
+    pages: Vec<IoPage>,
+    server_pages: usize,
+) -> Result<(Msg, Vec<IoPage>), ErrorCode> {
+    if pages.len() > IO_MAX_PAGES || server_pages > IO_MAX_PAGES {
+        return Err(moto_rt::E_INVALID_ARGUMENT);
+    }
+    let reactor = reactor()?;
+    let reservation = if server_pages > 0 {
+        Some(reactor.reserve_server_pages(server_pages).await?)
+    } else {
+        None
+    };
+
+    let num_pages = pages.len();
+    for (slot, page) in pages.into_iter().enumerate() {
+        sqe.payload.shared_pages_mut()[slot] = IoPage::into_u16(page);
+    }
+
+    let cqe = reactor.submit(sqe, num_pages, reservation).await?.await;
+    // Taken whatever the status, so that they are not leaked.
+    let pages = reactor.take_server_pages(&cqe);
+    let pages = pages.into_iter().collect::<Result<Vec<_>, _>>()?;
+    Ok((cqe, pages))
+}
+
+/// Sends @sqe (e.g. CMD_IO_READ) to the FS driver, and resolves to its CQE,
+/// with the server pages attached to it; the CQE's status is not checked.
+/// @pages go into the SQE's shared pages, from slot 0, and belong to the
+/// server once sent. @server_pages is the most pages the CQE may carry.
+///
+/// Dropping the future cancels the request: it still completes (the server
+/// may already be working on it), but its CQE is discarded when it arrives,
+/// and its pages are freed.
+pub async fn submit(
+    sqe: Msg,
+    pages: Vec<IoPage>,
+    server_pages: usize,
+) -> Result<(Msg, Vec<IoPage>), ErrorCode> {
+    submit_request(sqe, pages, server_pages).await
+}
+
+/// As submit(), but fails with E_TIMED_OUT if the CQE has not arrived within
+/// @timeout (including waiting for queue space and pages). The request is
+/// then cancelled as if the future were dropped, so a stuck device does not
+/// hang the caller, and does not leak the request's pages.
+pub async fn submit_with_timeout(
+    sqe: Msg,
+    pages: Vec<IoPage>,
+    server_pages: usize,
+    timeout: Duration,
+) -> Result<(Msg, Vec<IoPage>), ErrorCode> {
+    let timer = Timer {
+        reactor: reactor()?,
+        deadline: Instant::now().checked_add_duration(&timeout),
+        key: None,
+    };
+    until(
+        submit_request(sqe, pages, server_pages),
+        timer,
+        moto_rt::E_TIMED_OUT,
+    )
+    .await
+}
+
+/// As submit(), but the request can be cancelled, from anywhere, with the
+/// returned token: its future then fails with E_INTERRUPTED, and the request
+/// is cancelled as if the future were dropped.
+pub fn submit_cancellable(
+    sqe: Msg,
+    pages: Vec<IoPage>,
+    server_pages: usize,
+) -> (
+    impl Future<Output = Result<(Msg, Vec<IoPage>), ErrorCode>>,
+    CancelToken,
+) {
+    let token = CancelToken::default();
+    let cancel = token.clone();
+    let future = async move {
+        until(
+            submit_request(sqe, pages, server_pages),
+            cancel.cancelled(),
+            moto_rt::E_INTERRUPTED,
+        )
+        .await
+    };
+    (future, token)
+}
+
+// The driver has no notion of our current directory.
+fn abs_path(path: &str) -> Result<String, ErrorCode> {
+    let path = if path.starts_with('/') {
+        String::from(path)
+    } else {
+        let cwd = std::env::current_dir().map_err(|_| moto_rt::E_INVALID_FILENAME)?;
+        let cwd = cwd.to_str().ok_or(moto_rt::E_INVALID_FILENAME)?;
+        std::format!("{}/{}", cwd.trim_end_matches('/'), path)
+    };
+    if path.len() > moto_rt::fs::MAX_PATH_LEN {
+        return Err(moto_rt::E_INVALID_FILENAME);
+    }
+    Ok(path)
+}
+
+/// The attributes of file or directory @path.
+pub async fn stat(path: &str) -> Result<moto_rt::fs::FileAttr, ErrorCode> {
+    let reactor = reactor()?;
+    let path = abs_path(path)?;
+
+    let page = reactor.alloc_pages(1).await?.pop().unwrap();
+    page.bytes_mut()[0..path.len()].copy_from_slice(path.as_bytes());
+    let reservation = reactor.reserve_server_pages(1).await?;
+
+    let mut sqe = Msg::new();
+    sqe.command = CMD_IO_STAT;
+    sqe.flags = path.len() as u32;
+    sqe.payload.shared_pages_mut()[0] = IoPage::into_u16(page);
+
+    let cqe = reactor.submit(sqe, 1, Some(reservation)).await?.await;
+    // Taken whatever the status, so that they are not leaked.
+    let pages = reactor.take_server_pages(&cqe);
+    if cqe.status() != moto_rt::E_OK {
+        return Err(cqe.status());
+    }
+    let Some(Ok(page)) = pages.into_iter().next() else {
+        return Err(moto_rt::E_INTERNAL_ERROR);
+    };
+    Ok(unsafe { (page.bytes().as_ptr() as *const moto_rt::fs::FileAttr).read() })
+}
+
+/// Renames (moves) file or directory @old to @new, replacing @new if it exists.
+pub async fn rename(old: &str, new: &str) -> Result<(), ErrorCode> {
+    let reactor = reactor()?;
+    let old = abs_path(old)?;
+    let new = abs_path(new)?;
+
+    // Both fit: PAGE_SIZE >= 2 * MAX_PATH_LEN.
+    let page = reactor.alloc_pages(1).await?.pop().unwrap();
+    page.bytes_mut()[0..old.len()].copy_from_slice(old.as_bytes());
+    page.bytes_mut()[old.len()..(old.len() + new.len())].copy_from_slice(new.as_bytes());
+
+    let mut sqe = Msg::new();
+    sqe.command = CMD_IO_RENAME;
+    sqe.flags = old.len() as u32;
+    sqe.payload.shared_pages_mut()[0] = IoPage::into_u16(page);
+    sqe.payload.args_32_mut()[5] = new.len() as u32;
+
+    let cqe = reactor.submit(sqe, 1, None).await?.await;
+    if cqe.status() != moto_rt::E_OK {
+        return Err(cqe.status());
+    }
+    Ok(())
+}
+
+/// A file opened via the FS driver's io_channel. Closed when dropped.
+pub struct File {
+    fd: u64,
+    size: u64,
+    path: String, // As opened: renames are not tracked.
+}
+
+impl File {
+    /// Opens file @path with moto_rt::fs::O_* @flags.
+    pub async fn open(path: &str, flags: u32) -> Result<File, ErrorCode> {
+        let reactor = reactor()?;
+        let path = abs_path(path)?;
+
+        let page = reactor.alloc_pages(1).await?.pop().unwrap();
+        page.bytes_mut()[0..path.len()].copy_from_slice(path.as_bytes());
+
+        let mut sqe = Msg::new();
+        sqe.command = CMD_IO_OPEN;
+        sqe.flags = path.len() as u32;
+        sqe.payload.shared_pages_mut()[0] = IoPage::into_u16(page);
+        sqe.payload.args_32_mut()[5] = flags;
+
+        let cqe = reactor.submit(sqe, 1, None).await?.await;
+        if cqe.status() != moto_rt::E_OK {
+            return Err(cqe.status());
+        }
+        Ok(File {
+            fd: cqe.handle,
+            size: cqe.payload.args_64()[0],
+            path,
+        })
+    }
+
+    /// The driver's handle of the file, for requests sent with submit().
+    pub fn fd(&self) -> u64 {
+        self.fd
+    }
+
+    /// The size of the file when it was opened.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The current attributes of the file (e.g. its size), looked up by
+    /// the path it was opened with, as moto_rt::fs::get_file_attr() does.
+    pub async fn metadata(&self) -> Result<moto_rt::fs::FileAttr, ErrorCode> {
+        stat(&self.path).await
+    }
+
+    /// Reads into @buf from @offset. Returns the number of bytes read, which
+    /// is less than buf.len() at the end of the file, or if a later chunk
+    /// failed. Either way, all requests have completed, and their pages have
+    /// been freed, by the time this returns.
+    pub async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, ErrorCode> {
+        self.read_vectored_at(offset, &mut [buf]).await
+    }
+
+    /// Writes @buf at @offset. Returns the number of bytes written: those
+    /// before the first chunk that failed, or was short. The pages of all
+    /// requests have been freed by the time this returns.
+    pub async fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, ErrorCode> {
+        self.write_vectored_at(offset, &[buf]).await
+    }
+
+    /// As read_at(), reading into @bufs in order (preadv). Segments are
+    /// scattered from the pages of the same request, so a number of small
+    /// buffers (e.g. a header and a body) cost one request, not one each.
+    pub async fn read_vectored_at(
+        &self,
+        offset: u64,
+        bufs: &mut [&mut [u8]],
+    ) -> Result<usize, ErrorCode> {
+        let reactor = reactor()?;
+        let len = bufs.iter().map(|buf| buf.len()).sum();
+        let chunks = split_segments_mut(bufs, IO_MAX_BYTES)
+            .into_iter()
+            .enumerate()
+            .map(|(idx, segments)| {
+                read_chunk(
+                    reactor,
+                    self.fd,
+                    offset + (idx * IO_MAX_BYTES) as u64,
+                    segments,
+                )
+            })
+            .collect();
+        sum_chunks(join_all(chunks).await, len, IO_MAX_BYTES)
+    }
+
+    /// As write_at(), writing @bufs in order (pwritev); see read_vectored_at().
+    pub async fn write_vectored_at(&self, offset: u64, bufs: &[&[u8]]) -> Result<usize, ErrorCode> {
+        let reactor = reactor()?;
+        let len = bufs.iter().map(|buf| buf.len()).sum();
+        let chunks = split_segments(bufs, IO_MAX_BYTES)
+            .into_iter()
+            .enumerate()
+            .map(|(idx, segments)| {
+                write_chunk(
+                    reactor,
+                    self.fd,
+                    offset + (idx * IO_MAX_BYTES) as u64,
+                    segments,
+                )
+            })
+            .collect();
+        sum_chunks(join_all(chunks).await, len, IO_MAX_BYTES)
+    }
+
+    /// Makes the file's data, and all writes completed before, durable.
+    pub async fn sync(&self) -> Result<(), ErrorCode> {
+        simple_request(reactor()?, CMD_IO_FSYNC, self.fd).await
+    }
+
+    /// Closes the file, reporting errors that dropping it would not.
+    pub async fn close(self) -> Result<(), ErrorCode> {
+        let fd = self.fd;
+        core::mem::forget(self);
+        simple_request(reactor()?, CMD_IO_CLOSE, fd).await
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        let Ok(reactor) = reactor() else {
+            return;
+        };
+        let mut state = reactor.state.lock().unwrap();
+        if state.disconnected {
+            return; // The driver has closed the file.
+        }
+        state.next_id += 1;
+        let id = state.next_id;
+        state.orphans.insert(id, 0);
+
+        let mut sqe = Msg::new();
+        sqe.id = id;
+        sqe.command = CMD_IO_CLOSE;
+        sqe.handle = self.fd;
+        if reactor.conn.send(sqe).is_err() {
+            state.deferred.push(sqe);
+        }
+        let _ = SysCpu::wake(reactor.conn.server_handle());
+    }
+}
+
+/// Runs @future to completion on the current thread.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    struct ThreadWaker(std::thread::Thread);
+
+    impl std::task::Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = core::pin::pin!(future);
+    loop {
+        if let Poll::Ready(val) = future.as_mut().poll(&mut cx) {
+            return val;
+        }
+        std::thread::park();
+    }
+}
@@ -4,6 +4,11 @@
 
 pub mod api_fs;
 pub mod api_net;
+pub mod api_stats;
 
+#[cfg(feature = "std")]
+pub mod capture;
+#[cfg(feature = "std")]
+pub mod io_executor;
 #[cfg(feature = "std")]
 pub mod stats;
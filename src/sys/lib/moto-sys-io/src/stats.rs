@@ -8,7 +8,7 @@ use moto_ipc::sync::RequestHeader;
 use moto_ipc::sync::ResponseHeader;
 use moto_rt::ErrorCode;
 
-pub const URL_IO_STATS: &str = "sys-io-stats-service";
+pub use crate::api_stats::*;
 
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -23,6 +23,10 @@ pub struct TcpSocketStatsV1 {
 
     pub tcp_state: crate::api_net::TcpState,
     pub smoltcp_state: smoltcp::socket::tcp::State,
+
+    // How many times the connection moved to another device after
+    // its device's link went down.
+    pub migrations: u64,
 }
 
 impl Default for TcpSocketStatsV1 {
@@ -37,6 +41,7 @@ impl Default for TcpSocketStatsV1 {
             remote_port: 0,
             tcp_state: crate::api_net::TcpState::Closed,
             smoltcp_state: smoltcp::socket::tcp::State::Closed,
+            migrations: 0,
         }
     }
 }
@@ -45,14 +50,15 @@ impl core::fmt::Debug for TcpSocketStatsV1 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "TCP: pid: {} dev: {} id: {} local_addr: {:?} remote_addr: {:?} state: {:?} ({:?})",
+            "TCP: pid: {} dev: {} id: {} local_addr: {:?} remote_addr: {:?} state: {:?} ({:?}) migrations: {}",
             self.pid,
             self.device_id,
             self.id,
             self.local_addr(),
             self.remote_addr(),
             self.tcp_state,
-            self.smoltcp_state
+            self.smoltcp_state,
+            self.migrations
         )
     }
 }
@@ -89,7 +95,118 @@ impl TcpSocketStatsV1 {
     }
 }
 
+/// System-wide network stats.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NetStatsV1 {
+    pub tcp_idle_reaped: u64, // TCP connections closed because of their idle timeout.
+    pub tcp_migrated: u64,    // TCP connections moved to another device on link loss.
+}
+
+/// Cumulative counters of a network interface, similar to a line of
+/// Linux's /proc/net/dev. Counted since sys-io started.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NetDevStatsV1 {
+    pub name_bytes: [u8; 32],
+    pub name_len: u8,
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    pub rx_errors: u64,         // Malformed (truncated) frames, dropped.
+    pub rx_drops_checksum: u64, // Packets with bad IP/TCP/UDP checksums, dropped.
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+    pub tx_drops_no_buffer: u64, // Packets dropped because the NIC had no TX buffers.
+}
+
+impl NetDevStatsV1 {
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name_bytes[0..(self.name_len as usize)]).unwrap_or("~")
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.rx_packets += other.rx_packets;
+        self.rx_bytes += other.rx_bytes;
+        self.rx_errors += other.rx_errors;
+        self.rx_drops_checksum += other.rx_drops_checksum;
+        self.tx_packets += other.tx_packets;
+        self.tx_bytes += other.tx_bytes;
+        self.tx_drops_no_buffer += other.tx_drops_no_buffer;
+    }
+}
+
+/// Counters of all network interfaces, taken at the same time.
+#[derive(Debug)]
+pub struct NetDevStats {
+    pub devices: std::vec::Vec<NetDevStatsV1>,
+    /// TCP connects that failed because no interface routes to the destination.
+    pub connect_no_route: u64,
+}
+
+impl NetDevStats {
+    /// System-wide counters: the sum over all interfaces.
+    pub fn total(&self) -> NetDevStatsV1 {
+        let mut total = NetDevStatsV1::default();
+        for dev in &self.devices {
+            total.add(dev);
+        }
+        total
+    }
+}
+
+/// A VirtIO device and what sys-io negotiated with it at initialization.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VirtioDeviceStatsV1 {
+    pub pci_device_id: u16, // E.g. 0x1041 for net, 0x1042 for block.
+    pub pci_bus: u8,
+    pub pci_slot: u8,
+    pub pci_func: u8,
+    pub device_status: u8, // See VirtIO spec, 2.1 "Device Status Field".
+    pub num_queues: u16,
+    pub queue_sizes: [u16; Self::MAX_QUEUES], // The first MAX_QUEUES.
+    pub features_offered: u64,
+    pub features_acked: u64,
+}
+
+impl VirtioDeviceStatsV1 {
+    pub const STATUS_DRIVER_OK: u8 = 4;
+    pub const STATUS_FAILED: u8 = 128;
+    pub const MAX_QUEUES: usize = 8;
+
+    pub fn driver_ok(&self) -> bool {
+        self.device_status & Self::STATUS_DRIVER_OK != 0
+    }
+
+    pub fn failed(&self) -> bool {
+        self.device_status & Self::STATUS_FAILED != 0
+    }
+}
+
 pub const CMD_TCP_STATS: u16 = 1000;
+pub const CMD_NET_STATS: u16 = 1001;
+pub const CMD_NET_DEV_STATS: u16 = 1002;
+pub const CMD_OPEN_FILES: u16 = 1003;
+pub const CMD_LOG_LEVEL: u16 = 1005;
+pub const CMD_VIRTIO_RNG: u16 = 1006;
+pub const CMD_VIRTIO_DEVICES: u16 = 1007;
+
+// With CMD_LOG_LEVEL: set the level (requires CAP_SYS); otherwise query it.
+pub const F_LOG_LEVEL_SET: u32 = 1;
+
+/// A file opened by a process, as known to sys-io.
+#[derive(Debug)]
+pub struct OpenFile {
+    pub fd: u64,
+    pub path: std::string::String, // As opened: renames are not tracked.
+}
+
+/// A handle a process has open in sys-io (see IoStatsService::get_open_handles()).
+#[derive(Debug)]
+pub enum OpenHandle {
+    File(OpenFile),
+    TcpSocket(TcpSocketStatsV1),
+}
 
 pub struct IoStatsService {
     conn: moto_ipc::sync::ClientConnection,
@@ -104,6 +221,7 @@ impl IoStatsService {
 
     /// Get existing TCP socket info for sockets with IDs >= start_id.
     /// Sockets are returned in order of their IDs, so start_id can be used for "paging".
+    /// Without CAP_SYS, only the caller's own sockets are returned.
     pub fn get_tcp_socket_stats(
         &mut self,
         start_id: u64,
@@ -120,6 +238,338 @@ impl IoStatsService {
             .resp::<GetTcpSocketStatsResponse<1>>()
             .socket_stats()
     }
+
+    pub fn get_net_stats(&mut self) -> Result<NetStatsV1, ErrorCode> {
+        let req = self.conn.req::<GetNetStatsRequest>();
+        req.header.cmd = CMD_NET_STATS;
+        req.header.ver = 0;
+        req.header.flags = 0;
+
+        self.conn.do_rpc(None)?;
+
+        let resp = self.conn.resp::<GetNetStatsResponse>();
+        if resp.header.result != moto_rt::E_OK {
+            return Err(resp.header.result);
+        }
+        Ok(resp.stats)
+    }
+
+    /// Get the bytes process @pid has read and written through sys-io
+    /// (see ProcessIoStatsV1).
+    pub fn get_process_io(&mut self, pid: u64) -> Result<ProcessIoStatsV1, ErrorCode> {
+        let req = self.conn.req::<GetProcessIoRequest>();
+        req.header.cmd = CMD_PROCESS_IO;
+        req.header.ver = 0;
+        req.header.flags = 0;
+        req.pid = pid;
+
+        self.conn.do_rpc(None)?;
+
+        let resp = self.conn.resp::<GetProcessIoResponse>();
+        if resp.header.result != moto_rt::E_OK {
+            return Err(resp.header.result);
+        }
+        Ok(resp.stats)
+    }
+
+    /// The level up to which sys-io logs records (one of SysRay::KLOG_LEVEL_*).
+    pub fn log_level(&mut self) -> Result<u8, ErrorCode> {
+        self.do_log_level(0, 0)
+    }
+
+    /// Changes the level up to which sys-io logs records (one of
+    /// SysRay::KLOG_LEVEL_*), until it exits. Returns the previous level.
+    /// Requires CAP_SYS.
+    pub fn set_log_level(&mut self, level: u8) -> Result<u8, ErrorCode> {
+        self.do_log_level(F_LOG_LEVEL_SET, level)
+    }
+
+    fn do_log_level(&mut self, flags: u32, level: u8) -> Result<u8, ErrorCode> {
+        let req = self.conn.req::<LogLevelRequest>();
+        req.header.cmd = CMD_LOG_LEVEL;
+        req.header.ver = 0;
+        req.header.flags = flags;
+        req.level = level as u64;
+
+        self.conn.do_rpc(None)?;
+
+        let resp = self.conn.resp::<LogLevelResponse>();
+        if resp.header.result != moto_rt::E_OK {
+            return Err(resp.header.result);
+        }
+        Ok(resp.level as u8)
+    }
+
+    /// Fills @buf with bytes read straight from the VirtIO RNG device,
+    /// bypassing the kernel entropy pool (use SysMem::get_random() for
+    /// randomness): this is for checking the device and its driver.
+    /// Returns E_NOT_FOUND if there is no (working) device.
+    pub fn get_virtio_rng_bytes(&mut self, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        if buf.len() > MAX_VIRTIO_RNG_BYTES {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        let req = self.conn.req::<GetVirtioRngRequest>();
+        req.header.cmd = CMD_VIRTIO_RNG;
+        req.header.ver = 0;
+        req.header.flags = 0;
+        req.len = buf.len() as u64;
+
+        self.conn.do_rpc(None)?;
+
+        let resp = self.conn.resp::<GetVirtioRngResponse>();
+        if resp.header.result != moto_rt::E_OK {
+            return Err(resp.header.result);
+        }
+        buf.copy_from_slice(&resp.bytes[0..buf.len()]);
+        Ok(())
+    }
+
+    /// Get the VirtIO devices sys-io has initialized (or failed to).
+    pub fn get_virtio_devices(&mut self) -> Result<std::vec::Vec<VirtioDeviceStatsV1>, ErrorCode> {
+        let req = self.conn.req::<GetNetStatsRequest>();
+        req.header.cmd = CMD_VIRTIO_DEVICES;
+        req.header.ver = 0;
+        req.header.flags = 0;
+
+        self.conn.do_rpc(None)?;
+
+        let resp = self.conn.resp::<GetVirtioDevicesResponse>();
+        if resp.header.result != moto_rt::E_OK {
+            return Err(resp.header.result);
+        }
+        let num_devices = (resp.num_devices as usize).min(MAX_VIRTIO_DEVICES);
+        Ok(resp.devices[0..num_devices].to_vec())
+    }
+
+    /// Get per-interface network counters (see NetDevStats::total() for
+    /// system-wide ones), all read in one pass.
+    pub fn get_net_dev_stats(&mut self) -> Result<NetDevStats, ErrorCode> {
+        let req = self.conn.req::<GetNetStatsRequest>();
+        req.header.cmd = CMD_NET_DEV_STATS;
+        req.header.ver = 0;
+        req.header.flags = 0;
+
+        self.conn.do_rpc(None)?;
+
+        let resp = self.conn.resp::<GetNetDevStatsResponse>();
+        if resp.header.result != moto_rt::E_OK {
+            return Err(resp.header.result);
+        }
+        let num_devices = (resp.num_devices as usize).min(MAX_NET_DEV_STATS);
+        Ok(NetDevStats {
+            devices: resp.devices[0..num_devices].to_vec(),
+            connect_no_route: resp.connect_no_route,
+        })
+    }
+
+    /// Get the files opened by process @pid, skipping the first @start ones
+    /// (ordered by fd). Requires CAP_SYS.
+    pub fn get_open_files(
+        &mut self,
+        pid: u64,
+        start: u64,
+    ) -> Result<std::vec::Vec<OpenFile>, ErrorCode> {
+        let req = self.conn.req::<GetOpenFilesRequest>();
+        req.header.cmd = CMD_OPEN_FILES;
+        req.header.ver = 0;
+        req.header.flags = 0;
+        req.pid = pid;
+        req.start = start;
+
+        self.conn.do_rpc(None)?;
+
+        let raw_channel = self.conn.raw_channel();
+        let resp = self.conn.resp::<GetOpenFilesResponse>();
+        if resp.header.result != moto_rt::E_OK {
+            return Err(resp.header.result);
+        }
+
+        let mut files = std::vec::Vec::new();
+        let mut offset = 0;
+        for _ in 0..resp.num_results {
+            let (file, next) = unsafe { resp.file_at(&raw_channel, offset)? };
+            files.push(file);
+            offset = next;
+        }
+        Ok(files)
+    }
+
+    /// Get all handles process @pid has open in sys-io: files and TCP
+    /// sockets. This is a snapshot: handles may be opened or closed while
+    /// it is being taken. Requires CAP_SYS.
+    pub fn get_open_handles(&mut self, pid: u64) -> Result<std::vec::Vec<OpenHandle>, ErrorCode> {
+        let mut handles = std::vec::Vec::new();
+
+        loop {
+            let files = self.get_open_files(pid, handles.len() as u64)?;
+            if files.is_empty() {
+                break;
+            }
+            handles.extend(files.into_iter().map(OpenHandle::File));
+        }
+
+        let mut start_id = 0;
+        loop {
+            let stats = self.get_tcp_socket_stats(start_id)?;
+            let Some(last) = stats.last() else {
+                break;
+            };
+            start_id = last.id + 1;
+            handles.extend(
+                stats
+                    .iter()
+                    .filter(|stat| stat.pid == pid)
+                    .map(|stat| OpenHandle::TcpSocket(*stat)),
+            );
+        }
+
+        Ok(handles)
+    }
+}
+
+#[repr(C)]
+pub struct GetNetStatsRequest {
+    pub header: RequestHeader,
+}
+
+#[repr(C)]
+pub struct GetNetStatsResponse {
+    pub header: ResponseHeader,
+    pub stats: NetStatsV1,
+}
+
+// The request is GetNetStatsRequest.
+#[repr(C)]
+pub struct GetNetDevStatsResponse {
+    pub header: ResponseHeader,
+    pub num_devices: u64,
+    pub connect_no_route: u64,
+    pub devices: [NetDevStatsV1; MAX_NET_DEV_STATS],
+}
+
+pub const MAX_NET_DEV_STATS: usize = 32;
+
+const _NET_DEV_SZ: () =
+    assert!(size_of::<GetNetDevStatsResponse>() <= moto_sys::sys_mem::PAGE_SIZE_SMALL as usize);
+
+#[repr(C)]
+pub struct LogLevelRequest {
+    pub header: RequestHeader,
+    pub level: u64, // With F_LOG_LEVEL_SET.
+}
+
+#[repr(C)]
+pub struct LogLevelResponse {
+    pub header: ResponseHeader,
+    pub level: u64, // The previous level with F_LOG_LEVEL_SET.
+}
+
+// The request is GetNetStatsRequest.
+#[repr(C)]
+pub struct GetVirtioDevicesResponse {
+    pub header: ResponseHeader,
+    pub num_devices: u64,
+    pub devices: [VirtioDeviceStatsV1; MAX_VIRTIO_DEVICES],
+}
+
+pub const MAX_VIRTIO_DEVICES: usize = 32;
+
+const _VIRTIO_DEVICES_SZ: () =
+    assert!(size_of::<GetVirtioDevicesResponse>() <= moto_sys::sys_mem::PAGE_SIZE_SMALL as usize);
+
+pub const MAX_VIRTIO_RNG_BYTES: usize = 256;
+
+#[repr(C)]
+pub struct GetVirtioRngRequest {
+    pub header: RequestHeader,
+    pub len: u64, // At most MAX_VIRTIO_RNG_BYTES.
+}
+
+#[repr(C)]
+pub struct GetVirtioRngResponse {
+    pub header: ResponseHeader,
+    pub bytes: [u8; MAX_VIRTIO_RNG_BYTES],
+}
+
+#[repr(C)]
+pub struct GetOpenFilesRequest {
+    pub header: RequestHeader,
+    pub pid: u64,
+    pub start: u64, // The number of files to skip.
+}
+
+// Followed by num_results (OpenFileV1, path bytes) records, each at an
+// 8-byte-aligned offset into data.
+#[repr(C)]
+pub struct GetOpenFilesResponse {
+    pub header: ResponseHeader,
+    pub num_results: u64,
+    pub data: [u8; 0],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct OpenFileV1 {
+    pub fd: u64,
+    pub path_len: u16,
+    pub _reserved: [u16; 3],
+}
+
+impl GetOpenFilesResponse {
+    /// Appends a record at @offset into data; returns the offset of
+    /// the next one, or None if the record does not fit.
+    pub unsafe fn put_file(
+        &mut self,
+        raw_channel: &moto_ipc::sync::RawChannel,
+        offset: usize,
+        fd: u64,
+        path: &str,
+    ) -> Option<usize> {
+        let path_offset = offset + size_of::<OpenFileV1>();
+        let next = (path_offset + path.len()).next_multiple_of(8);
+        if size_of::<Self>() + next > raw_channel.size() {
+            return None;
+        }
+
+        let record = self.data.as_mut_ptr().add(offset) as *mut OpenFileV1;
+        record.write(OpenFileV1 {
+            fd,
+            path_len: path.len() as u16,
+            _reserved: [0; 3],
+        });
+        raw_channel
+            .put_bytes(path.as_bytes(), self.data.as_mut_ptr().add(path_offset))
+            .ok()?;
+        Some(next)
+    }
+
+    /// Reads the record at @offset into data; returns it and the offset of
+    /// the next one.
+    pub unsafe fn file_at(
+        &self,
+        raw_channel: &moto_ipc::sync::RawChannel,
+        offset: usize,
+    ) -> Result<(OpenFile, usize), ErrorCode> {
+        if size_of::<Self>() + offset + size_of::<OpenFileV1>() > raw_channel.size() {
+            return Err(moto_rt::E_INTERNAL_ERROR);
+        }
+        let record = (self.data.as_ptr().add(offset) as *const OpenFileV1).read();
+        let path_offset = offset + size_of::<OpenFileV1>();
+        let bytes = raw_channel.get_bytes(
+            self.data.as_ptr().add(path_offset),
+            record.path_len as usize,
+        )?;
+        let path = core::str::from_utf8(bytes).map_err(|_| moto_rt::E_INTERNAL_ERROR)?;
+
+        Ok((
+            OpenFile {
+                fd: record.fd,
+                path: path.into(),
+            },
+            (path_offset + bytes.len()).next_multiple_of(8),
+        ))
+    }
 }
 
 #[repr(C)]
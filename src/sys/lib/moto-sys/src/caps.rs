@@ -13,6 +13,9 @@ pub const CAP_SPAWN: u64 = 1 << 2;
 // The process can use SysMem::OP_DEBUG and SysCtl::OP_SET_LOG_LEVEL.
 pub const CAP_LOG: u64 = 1 << 3;
 
+// The process can capture network packets (see CMD_CAPTURE_START in moto-sys-io).
+pub const CAP_NET_CAPTURE: u64 = 1 << 4;
+
 // This ENV key can be used to specify caps for the
 // process being created. The value must be formated in hex.
 // Currently works with Rust's std::process::Command.
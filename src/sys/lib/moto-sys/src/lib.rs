@@ -12,7 +12,7 @@ pub mod sys_obj;
 pub mod sys_ray;
 pub mod syscalls;
 pub use moto_rt::ErrorCode;
-pub use sys_cpu::SysCpu;
+pub use sys_cpu::{SysCpu, WakeCause};
 pub use sys_mem::SysMem;
 pub use sys_obj::SysObj;
 pub use sys_ray::SysRay;
@@ -18,7 +18,7 @@ pub const PID_SYS_IO: u64 = 2;
 pub struct ProcessStatsV1 {
     pub pid: u64, // PID_SYSTEM, PID_KERNEL, or actual process ID.
     pub parent_pid: u64,
-    pub pages_user: u64,
+    pub pages_user: u64, // Resident (RSS); see ProcessStatsV2 for committed pages.
     pub pages_kernel: u64,
     pub total_threads: u64,   // All threads created by this process.
     pub total_children: u64,  // All direct child processes spawned.
@@ -44,6 +44,13 @@ impl ProcessStatsV1 {
         crate::SysRay::list_processes_v1(parent, false, buf)
     }
 
+    // List @root and all its descendants (all processes if @root is PID_SYSTEM)
+    // as one consistent snapshot, in PID order. Returns (copied, total):
+    // copied < total means @buf was too small.
+    pub fn snapshot(root: u64, buf: &mut [ProcessStatsV1]) -> Result<(usize, usize), ErrorCode> {
+        crate::SysRay::snapshot_processes_v1(root, buf)
+    }
+
     pub fn debug_name(&self) -> &str {
         core::str::from_utf8(&self.debug_name_bytes[0..(self.debug_name_len as usize)])
             .unwrap_or("~")
@@ -55,6 +62,74 @@ impl ProcessStatsV1 {
 }
 
 #[repr(C)]
+#[derive(Default)]
+pub struct ProcessStatsV2 {
+    pub v1: ProcessStatsV1,
+    // User pages charged against the process's memory limit: lazy mappings
+    // count in full, whether or not they have been touched. At least v1.pages_user,
+    // unless the process has memory mapped by another process.
+    pub pages_user_committed: u64,
+    // The high-water marks of v1.pages_user and v1.pages_kernel.
+    pub peak_pages_user: u64,
+    pub peak_pages_kernel: u64,
+
+    // CPU usage on each CPU, in TSC; v1.cpu_usage is the sum of all entries.
+    pub num_cpus: u32, // The number of valid entries in per_cpu.
+    pub truncated: u8, // 1 => the system has more than PROCESS_STATS_MAX_CPUS CPUs.
+    _reserved: [u8; 3],
+    pub per_cpu: [CpuStatsPerCpuEntryV1; PROCESS_STATS_MAX_CPUS],
+}
+
+/// The number of CPUs ProcessStatsV2 has room for.
+pub const PROCESS_STATS_MAX_CPUS: usize = 16;
+
+#[cfg(feature = "userspace")]
+impl ProcessStatsV2 {
+    // See ProcessStatsV1::list().
+    pub fn list(start: u64, buf: &mut [ProcessStatsV2]) -> Result<usize, ErrorCode> {
+        crate::SysRay::list_processes_v2(start, true, buf)
+    }
+
+    // See ProcessStatsV1::list_children().
+    pub fn list_children(parent: u64, buf: &mut [ProcessStatsV2]) -> Result<usize, ErrorCode> {
+        crate::SysRay::list_processes_v2(parent, false, buf)
+    }
+
+    // See ProcessStatsV1::snapshot().
+    pub fn snapshot(root: u64, buf: &mut [ProcessStatsV2]) -> Result<(usize, usize), ErrorCode> {
+        crate::SysRay::snapshot_processes_v2(root, buf)
+    }
+
+    // List @root and its descendants down to @max_depth that have all of @caps.
+    // See SysRay::list_descendants_v2().
+    pub fn list_descendants(
+        root: u64,
+        max_depth: u32,
+        caps: u64,
+        buf: &mut [ProcessStatsV2],
+    ) -> Result<usize, ErrorCode> {
+        crate::SysRay::list_descendants_v2(root, max_depth, caps, buf)
+    }
+
+    pub fn resident_bytes(&self) -> u64 {
+        self.v1.pages_user << sys_mem::PAGE_SIZE_SMALL_LOG2
+    }
+
+    pub fn committed_bytes(&self) -> u64 {
+        self.pages_user_committed << sys_mem::PAGE_SIZE_SMALL_LOG2
+    }
+
+    pub fn peak_bytes_user(&self) -> u64 {
+        self.peak_pages_user << sys_mem::PAGE_SIZE_SMALL_LOG2
+    }
+
+    pub fn per_cpu(&self) -> &[CpuStatsPerCpuEntryV1] {
+        &self.per_cpu[0..(self.num_cpus as usize).min(PROCESS_STATS_MAX_CPUS)]
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
 pub struct CpuStatsPerCpuEntryV1 {
     pub kernel: u64,
     pub uspace: u64,
@@ -149,11 +224,192 @@ impl MemoryStats {
     }
 }
 
+// Fragmentation of free physical (small) pages. The physical allocator
+// manages memory in segments of at most 64 pages, and contiguous allocations
+// never span segments, so free runs are counted within segments.
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+pub struct MemFragmentationStats {
+    // free_runs[order]: the number of free runs of [2^order, 2^(order + 1)) pages.
+    pub free_runs: [u64; MemFragmentationStats::NUM_ORDERS],
+    pub largest_free_run: u64, // In pages: the largest contiguous allocation possible.
+    pub free_pages: u64,
+}
+
+impl MemFragmentationStats {
+    pub const NUM_ORDERS: usize = 7; // Runs of up to 64 pages.
+
+    #[cfg(feature = "userspace")]
+    pub fn get() -> Result<MemFragmentationStats, ErrorCode> {
+        SysMem::query_fragmentation()
+    }
+}
+
+// A per-CPU cache of free physical pages: most page allocations on a CPU are
+// served from its cache (hits); misses go to the allocator shared by all CPUs.
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+pub struct FrameCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub cached_pages: u64, // Free pages currently in the cache.
+}
+
+impl FrameCacheStats {
+    /// Stats of each CPU's cache, indexed by CPU.
+    #[cfg(feature = "userspace")]
+    pub fn get() -> Result<alloc::vec::Vec<FrameCacheStats>, ErrorCode> {
+        SysMem::query_frame_caches()
+    }
+
+    /// The share of allocations served from the cache, in [0.0, 1.0].
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+// A NUMA node: its CPUs and physical (small) pages. Node numbers come from
+// ACPI SRAT; without it, all CPUs and memory are in node 0.
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+pub struct NumaNodeStats {
+    pub cpus: u64, // A bit per CPU.
+    pub total_pages: u64,
+    pub free_pages: u64,
+    // Pages allocated from other nodes because this node, preferred by the
+    // allocating thread (or local to its CPU), had no free pages.
+    pub remote_fallbacks: u64,
+}
+
+impl NumaNodeStats {
+    /// The most nodes the kernel tracks.
+    pub const MAX_NODES: usize = 8;
+
+    /// Stats of each node, indexed by node.
+    #[cfg(feature = "userspace")]
+    pub fn get() -> Result<alloc::vec::Vec<NumaNodeStats>, ErrorCode> {
+        SysMem::query_numa_nodes()
+    }
+}
+
+// Scheduling latency of a process: how long its threads have waited, runnable,
+// for a CPU (see SysRay::query_sched_latency()). In TSC.
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+pub struct SchedLatencyStats {
+    pub total: u64,
+    pub max: u64,
+    pub count: u64, // The number of times a thread of the process was scheduled.
+}
+
+impl SchedLatencyStats {
+    #[cfg(feature = "userspace")]
+    pub fn get(pid: u64) -> Result<SchedLatencyStats, ErrorCode> {
+        crate::SysRay::query_sched_latency(pid)
+    }
+
+    /// The average latency, in TSC.
+    pub fn avg(&self) -> u64 {
+        if self.count == 0 {
+            0
+        } else {
+            self.total / self.count
+        }
+    }
+}
+
+// Resources used by a process over its lifetime (see SysRay::query_rusage()).
+// CPU time and latency are in TSC; memory peaks are in bytes. Once the process
+// has exited, these are final. I/O is done by sys-io, which counts it itself.
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+pub struct ResourceUsage {
+    pub cpu_uspace: u64,
+    pub cpu_kernel: u64,
+    pub page_faults: u64,
+    pub peak_bytes_user: u64,
+    pub peak_bytes_kernel: u64,
+    pub total_threads: u64,
+    pub total_children: u64,
+    pub context_switches: u64, // Times a thread of the process was switched to.
+    pub migrations: u64,       // Times a thread moved to another CPU.
+    pub sched_latency: SchedLatencyStats,
+    pub pid: u64,
+}
+
+// The CPU usage of a thread, in TSC (see SysRay::query_threads()).
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+pub struct ThreadCpuUsage {
+    pub tid: u64, // Zero: all threads that have exited.
+    pub cpu_uspace: u64,
+    pub cpu_kernel: u64,
+}
+
+// Thread migrations of a process to and from a CPU (see SysRay::query_percpu_migrations()).
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+pub struct CpuMigrations {
+    pub migrations_in: u64,
+    pub migrations_out: u64,
+}
+
+// A CPU sample: what a CPU was running at a sampling point (see SysRay::cpu_samples()).
+// Samples in the kernel (idle, IRQs, syscalls) are recorded as (PID_KERNEL, 0).
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+pub struct CpuSample {
+    pub pid: u64,
+    pub tid: u64,
+}
+
+// Contention stats of SpinLock::lock() calls with the same lock word, which
+// is the line!() of the call site (see SysRay::lock_stats()).
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+pub struct LockSiteStats {
+    pub line: u32,
+    pub _reserved: u32,
+    pub acquisitions: u64,
+    pub contended: u64, // Acquisitions that had to spin.
+    pub spins: u64,
+}
+
+// The recent load of a CPU, split by where the time went (see get_cpu_usage_detailed()).
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+pub struct CpuUsageDetail {
+    pub uspace: f32,
+    pub kernel: f32,
+    pub idle: f32,
+}
+
+// Each CPU's recent load, in [0.0, 1.0]: uspace + kernel of get_cpu_usage_detailed().
 #[cfg(feature = "userspace")]
 pub fn get_cpu_usage(buf: &mut [f32]) -> Result<(), ErrorCode> {
     crate::SysCpu::query_stats(buf)
 }
 
+// Same as get_cpu_usage(), but each CPU's time is split into the fractions spent
+// in userspace, in the kernel (syscalls, IRQs, the scheduler), and idle, which
+// add up to ~1.0. @buf must have at least num_cpus() entries; the rest are untouched.
+#[cfg(feature = "userspace")]
+pub fn get_cpu_usage_detailed(buf: &mut [CpuUsageDetail]) -> Result<(), ErrorCode> {
+    crate::SysCpu::query_stats_detailed(buf)
+}
+
+// Zero cumulative counters (e.g. cpu_usage) of the process, e.g. to measure
+// a fresh interval in a long-running benchmark. Requires CAP_SYS.
+#[cfg(feature = "userspace")]
+pub fn reset(pid: u64) -> Result<(), ErrorCode> {
+    crate::SysRay::reset_stats(pid)
+}
+
 #[repr(u16)]
 #[derive(Debug, Clone, Copy)]
 pub enum ThreadStatus {
@@ -5,9 +5,28 @@ use crate::syscalls::*;
 #[cfg(feature = "userspace")]
 use crate::ErrorCode;
 
+// This ENV key can be used to specify the CPU affinity of the process being
+// created (see SysCpu::set_process_affinity()): "$CPU" or "none", optionally
+// followed by ",inherit" to have its own children inherit it.
+// Currently works with Rust's std::process::Command.
+pub const MOTURUS_CPU_AFFINITY_ENV_KEY: &str = "MOTURUS_CPU_AFFINITY";
+
 /// SysCpu syscall: various scheduling-related operations.
 pub struct SysCpu;
 
+/// Why [`SysCpu::wait_ex`] returned. The count is the number of handles at the
+/// start of `wait_handles` that became ready (wakers); the rest are NONE.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WakeCause {
+    /// Woken by handles, or, if the count is zero, via SysCpu::wake()
+    /// (or swapped into).
+    Handles(usize),
+    /// The timeout elapsed.
+    TimedOut(usize),
+    /// The thread was interrupted (see SysCpu::interrupt()).
+    Interrupted(usize),
+}
+
 impl SysCpu {
     pub const OP_EXIT: u8 = 1;
     pub const OP_WAIT: u8 = 2;
@@ -17,6 +36,10 @@ impl SysCpu {
     pub const OP_USAGE: u8 = 6;
     pub const OP_AFFINE_CPU: u8 = 7;
     pub const OP_QUERY_PERCPU_STATS: u8 = 8;
+    pub const OP_INTERRUPT: u8 = 9;
+    pub const OP_TIME_SLICE: u8 = 10;
+    pub const OP_TIMER_RESOLUTION: u8 = 11;
+    pub const OP_PROCESS_AFFINITY: u8 = 12;
 
     // Controls whether hanles to wait for are passed via registers or as an array in memory.
     pub const F_HANDLE_ARRAY: u32 = 1;
@@ -37,6 +60,34 @@ impl SysCpu {
     // If present, OP_KILL's arg is the PID.
     pub const F_KILL_PID: u32 = 2;
 
+    // Same as F_KILL_PID, but returns a handle that will be woken when the process
+    // is fully gone, i.e. all its resources have been released.
+    pub const F_KILL_PID_COMPLETION: u32 = 4;
+
+    // If present, OP_USAGE returns the current thread's CPU time.
+    pub const F_USAGE_THREAD: u32 = 1;
+    // If present, OP_USAGE copies each CPU's idle time, in TSC, into a u64 array.
+    pub const F_USAGE_IDLE: u32 = 2;
+    // If present, OP_USAGE copies each CPU's stats::CpuUsageDetail.
+    pub const F_USAGE_DETAILED: u32 = 4;
+
+    // If present, OP_INTERRUPT takes (queries and clears) the current thread's pending interrupt.
+    pub const F_INTERRUPT_TAKE: u32 = 1;
+
+    // If present, OP_TIME_SLICE sets the time slice to args[0] microseconds
+    // (requires CAP_SYS); otherwise it queries the current one.
+    pub const F_TIME_SLICE_SET: u32 = 1;
+
+    // If present, OP_PROCESS_AFFINITY sets the affinity of process args[0]
+    // to CPU args[1] (u64::MAX => none); otherwise it queries it.
+    pub const F_PROCESS_AFFINITY_SET: u32 = 1;
+    // With F_PROCESS_AFFINITY_SET: children of the process inherit its affinity.
+    pub const F_PROCESS_AFFINITY_INHERIT: u32 = 2;
+
+    /// The bounds of the scheduler time slice (see [`Self::set_time_slice`]).
+    pub const MIN_TIME_SLICE: core::time::Duration = core::time::Duration::from_millis(1);
+    pub const MAX_TIME_SLICE: core::time::Duration = core::time::Duration::from_millis(200);
+
     #[cfg(feature = "userspace")]
     pub fn exit(code: u64) -> ! {
         do_syscall(
@@ -108,6 +159,36 @@ impl SysCpu {
         }
     }
 
+    /// Kill the process with @target PID; returns a handle that will be woken when
+    /// the process is fully reclaimed. The handle must be released with SysObj::put().
+    #[cfg(feature = "userspace")]
+    pub fn kill_pid_with_completion(target: u64) -> Result<SysHandle, ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_CPU, Self::OP_KILL, Self::F_KILL_PID_COMPLETION, 0),
+            target,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(SysHandle::from_u64(result.data[0]))
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Kill the process with @target PID and wait until it is fully reclaimed.
+    #[cfg(feature = "userspace")]
+    pub fn kill_pid_and_wait(target: u64) -> Result<(), ErrorCode> {
+        let completion = Self::kill_pid_with_completion(target)?;
+        let result = Self::wait(&mut [completion], SysHandle::NONE, SysHandle::NONE, None);
+        crate::SysObj::put(completion).unwrap();
+        result
+    }
+
     #[cfg(feature = "userspace")]
     pub fn wake(target: SysHandle) -> Result<(), ErrorCode> {
         let result = do_syscall(
@@ -150,8 +231,52 @@ impl SysCpu {
         }
     }
 
+    /// Interrupt a thread in the current process: its current or next wait
+    /// will return E_INTERRUPTED until the thread calls [`Self::take_interrupt`].
+    /// The runtime's own waits (e.g. in std's sleep, Mutex::lock, join) are not
+    /// interrupted (see [`Self::wait_uninterruptible`]).
+    #[cfg(feature = "userspace")]
+    pub fn interrupt(thread: SysHandle) -> Result<(), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_CPU, Self::OP_INTERRUPT, 0, 0),
+            thread.as_u64(),
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Clear the current thread's pending interrupt. Returns true if there was one.
+    #[cfg(feature = "userspace")]
+    pub fn take_interrupt() -> Result<bool, ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_CPU, Self::OP_INTERRUPT, Self::F_INTERRUPT_TAKE, 0),
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(result.data[0] != 0)
+        } else {
+            Err(result.error_code())
+        }
+    }
+
     // THE wait function:
     // - if timed out, will return Err(ErrorCode::TimedOut);
+    // - if interrupted, will return Err(E_INTERRUPTED), with wakers (if any) in @handles;
     // - if timeout is Some(Instant::nan()), then won't block (and won't return TimedOut);
     // - if Err(BadHandle), @handles will contain bad handles;
     // - if Ok(()), @handles will contain wakers;
@@ -221,6 +346,77 @@ impl SysCpu {
         Self::process_result(&result, wait_handles)
     }
 
+    /// Same as [`Self::wait`], but reports why the wait ended instead of mapping
+    /// timeouts and interrupts to errors. Errors other than E_TIMED_OUT and
+    /// E_INTERRUPTED (e.g. E_BAD_HANDLE) are returned as is.
+    #[cfg(feature = "userspace")]
+    pub fn wait_ex(
+        wait_handles: &mut [SysHandle],
+        swap_target: SysHandle,
+        wake_target: SysHandle,
+        timeout: Option<moto_rt::time::Instant>,
+    ) -> Result<WakeCause, ErrorCode> {
+        let result = Self::wait(wait_handles, swap_target, wake_target, timeout);
+
+        // The kernel puts wakers first.
+        let num_wakers = wait_handles
+            .iter()
+            .take_while(|handle| **handle != SysHandle::NONE)
+            .count();
+
+        match result {
+            Ok(()) => Ok(WakeCause::Handles(num_wakers)),
+            Err(moto_rt::E_TIMED_OUT) => Ok(WakeCause::TimedOut(num_wakers)),
+            Err(moto_rt::E_INTERRUPTED) => Ok(WakeCause::Interrupted(num_wakers)),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// The most handles [`Self::wait_uninterruptible`] can wait on.
+    pub const MAX_UNINTERRUPTIBLE_WAIT_HANDLES: usize = 8;
+
+    /// Same as [`Self::wait`], but an interrupt does not end the wait: it is taken
+    /// so that the wait blocks (interrupts are sticky), and is posted again when
+    /// the wait is over, so that the thread still sees it. This is for the waits
+    /// that are not for interrupts, e.g. those behind std's sleep, Mutex::lock,
+    /// join, and I/O.
+    #[cfg(feature = "userspace")]
+    pub fn wait_uninterruptible(
+        wait_handles: &mut [SysHandle],
+        mut swap_target: SysHandle,
+        mut wake_target: SysHandle,
+        timeout: Option<moto_rt::time::Instant>,
+    ) -> Result<(), ErrorCode> {
+        let num_handles = wait_handles.len();
+        assert!(num_handles <= Self::MAX_UNINTERRUPTIBLE_WAIT_HANDLES);
+
+        // The kernel overwrites wait handles with wakers.
+        let mut handles = [SysHandle::NONE; Self::MAX_UNINTERRUPTIBLE_WAIT_HANDLES];
+        handles[..num_handles].copy_from_slice(wait_handles);
+
+        let mut interrupted = false;
+        let result = loop {
+            match Self::wait(wait_handles, swap_target, wake_target, timeout) {
+                Err(moto_rt::E_INTERRUPTED) => {
+                    interrupted |= Self::take_interrupt()?;
+                    if num_handles > 0 && wait_handles[0] != SysHandle::NONE {
+                        break Ok(()); // Woken as well.
+                    }
+                    wait_handles.copy_from_slice(&handles[..num_handles]);
+                    // The targets have been woken by the first wait.
+                    swap_target = SysHandle::NONE;
+                    wake_target = SysHandle::NONE;
+                }
+                result => break result,
+            }
+        };
+
+        if interrupted {
+            Self::interrupt(crate::UserThreadControlBlock::this_thread_handle())?;
+        }
+        result
+    }
+
     #[cfg(feature = "userspace")]
     fn process_result(result: &SyscallResult, handles: &mut [SysHandle]) -> Result<(), ErrorCode> {
         // If the condition below is false, the kernel has properly put data in @handles.
@@ -296,6 +492,74 @@ impl SysCpu {
         }
     }
 
+    /// Copies the recent (uspace, kernel, idle) load of each CPU into @buf,
+    /// which must have room for num_cpus() entries; see stats::get_cpu_usage_detailed().
+    #[cfg(feature = "userspace")]
+    pub fn query_stats_detailed(buf: &mut [super::stats::CpuUsageDetail]) -> Result<(), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_CPU, Self::OP_USAGE, Self::F_USAGE_DETAILED, 0),
+            buf.as_ptr() as usize as u64,
+            buf.len() as u64,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Returns the (uspace, kernel) CPU time of the current thread, in TSC ticks
+    /// (see moto_rt::time::Instant), similar to CLOCK_THREAD_CPUTIME_ID.
+    #[cfg(feature = "userspace")]
+    pub fn thread_cpu_time() -> Result<(u64, u64), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_CPU, Self::OP_USAGE, Self::F_USAGE_THREAD, 0),
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok((result.data[0], result.data[1]))
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Copies the idle time of each CPU since boot (or since the last
+    /// stats::reset(PID_SYSTEM)), in TSC ticks, into @buf, which must have
+    /// room for num_cpus() entries. Idle time is the time the scheduler has
+    /// nothing to run, and is reported as the CPU usage of PID_SYSTEM; it does
+    /// not overlap with the usage of PID_KERNEL or of any process, so
+    /// utilization over an interval is busy / (busy + idle), with busy the
+    /// usage of PID_KERNEL plus that of all processes.
+    #[cfg(feature = "userspace")]
+    pub fn query_idle(buf: &mut [u64]) -> Result<(), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_CPU, Self::OP_USAGE, Self::F_USAGE_IDLE, 0),
+            buf.as_ptr() as usize as u64,
+            buf.len() as u64,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
     #[cfg(feature = "userspace")]
     pub fn get_percpu_stats_v1(page_addr: u64) -> Result<u32, ErrorCode> {
         let res = do_syscall(
@@ -315,6 +579,73 @@ impl SysCpu {
         }
     }
 
+    /// Set the scheduler time slice: how long a userspace thread runs before
+    /// it is preempted (the default is 20ms).
+    /// The slice is system-wide: all priorities share it. It does not change
+    /// the CPU sampling period (see SysRay::CPU_SAMPLE_PERIOD). Must be within
+    /// [MIN_TIME_SLICE, MAX_TIME_SLICE]. Requires CAP_SYS.
+    #[cfg(feature = "userspace")]
+    pub fn set_time_slice(slice: core::time::Duration) -> Result<(), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_CPU, Self::OP_TIME_SLICE, Self::F_TIME_SLICE_SET, 0),
+            slice.as_micros().min(u64::MAX as u128) as u64,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// The effective scheduler time slice.
+    #[cfg(feature = "userspace")]
+    pub fn time_slice() -> Result<core::time::Duration, ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_CPU, Self::OP_TIME_SLICE, 0, 0),
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(core::time::Duration::from_micros(result.data[0]))
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// The effective timer resolution: how late, on average, sleeps and
+    /// timeouts wake up after their deadlines. Timer deadlines are programmed
+    /// into each CPU's local APIC timer as one-shot deadlines, so they are
+    /// not rounded up to the time slice.
+    #[cfg(feature = "userspace")]
+    pub fn timer_resolution() -> Result<core::time::Duration, ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_CPU, Self::OP_TIMER_RESOLUTION, 0, 0),
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(core::time::Duration::from_nanos(result.data[0]))
+        } else {
+            Err(result.error_code())
+        }
+    }
+
     /// Affine the current thread to the specified CPU (or remove the affinity).
     /// Only the IO_MANAGER can affine to CPU 0.
     #[cfg(feature = "userspace")]
@@ -338,4 +669,70 @@ impl SysCpu {
             Err(result.error_code())
         }
     }
+
+    /// Set the affinity new threads of `process` start with, as if each called
+    /// affine_to_cpu(cpu) first. The affinity is a single CPU, as with
+    /// affine_to_cpu(). If the process has not started yet (e.g. it has just been
+    /// spawned), its main thread gets the affinity as well, before it first runs;
+    /// threads that are already running are not affected.
+    ///
+    /// If `inherit` is true, processes spawned by `process` from now on get the same
+    /// affinity (and inherit flag); otherwise they start without an affinity.
+    ///
+    /// `process` is SysHandle::SELF or a process handle (normally held by the spawner).
+    #[cfg(feature = "userspace")]
+    pub fn set_process_affinity(
+        process: SysHandle,
+        cpu: Option<u32>,
+        inherit: bool,
+    ) -> Result<(), ErrorCode> {
+        let flags = if inherit {
+            Self::F_PROCESS_AFFINITY_SET | Self::F_PROCESS_AFFINITY_INHERIT
+        } else {
+            Self::F_PROCESS_AFFINITY_SET
+        };
+        let result = do_syscall(
+            pack_nr_ver(SYS_CPU, Self::OP_PROCESS_AFFINITY, flags, 0),
+            process.as_u64(),
+            match cpu {
+                Some(cpu) => cpu as u64,
+                None => u64::MAX,
+            },
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Returns the affinity of `process` and whether its children inherit it.
+    /// See set_process_affinity().
+    #[cfg(feature = "userspace")]
+    pub fn process_affinity(process: SysHandle) -> Result<(Option<u32>, bool), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_CPU, Self::OP_PROCESS_AFFINITY, 0, 0),
+            process.as_u64(),
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            let cpu = match result.data[0] {
+                u64::MAX => None,
+                cpu => Some(cpu as u32),
+            };
+            Ok((cpu, result.data[1] != 0))
+        } else {
+            Err(result.error_code())
+        }
+    }
 }
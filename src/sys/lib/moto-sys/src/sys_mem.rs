@@ -33,6 +33,10 @@ impl SysMem {
     pub const OP_REMAP: u8 = 6;
     pub const OP_QUERY: u8 = 7;
     pub const OP_RECLAIM: u8 = 9;
+    pub const OP_ADVISE: u8 = 10;
+    pub const OP_RANDOM: u8 = 11;
+    pub const OP_NUMA_NODE: u8 = 12;
+    pub const OP_PRESSURE: u8 = 13;
 
     // Bit flags for create/map operations.
     pub const F_READABLE: u32 = 1;
@@ -40,11 +44,56 @@ impl SysMem {
     pub const F_MMIO: u32 = 4;
     pub const F_CONTIGUOUS: u32 = 8;
     pub const F_SHARE_SELF: u32 = 0x10;
-    pub const F_LAZY: u32 = 0x20; // Just a hint.
+    // Commit lazily: the whole mapping is charged against the process memory
+    // limit right away, but its pages are allocated (zeroed) and counted in
+    // memory stats only on first access.
+    pub const F_LAZY: u32 = 0x20;
     pub const F_CUSTOM_USER: u32 = 0x40;
+    // Reserve: like F_LAZY, but each page is charged against the process
+    // memory limit only on first access, so sparse large heaps are cheap.
+    // Touching a page beyond the limit is a fatal page fault.
+    pub const F_RESERVE: u32 = 0x80;
 
     // Bit flags for query.
     pub const F_QUERY_STATS: u32 = 1;
+    pub const F_QUERY_FRAGMENTATION: u32 = 2;
+    pub const F_QUERY_FRAME_CACHES: u32 = 3;
+    pub const F_QUERY_NUMA_NODES: u32 = 4;
+
+    // Advice (not bit flags) for OP_ADVISE; apply to F_LAZY and F_RESERVE mappings.
+    // Populate pages now, so that accessing them later does not fault; they are
+    // counted in memory stats (and charged, for F_RESERVE) right away.
+    pub const F_ADVISE_WILLNEED: u32 = 1;
+    // Release populated pages: they are unmapped and freed, and are populated
+    // with zeroes again on next access. Pages shared with others are kept.
+    pub const F_ADVISE_DONTNEED: u32 = 2;
+
+    // Flags (not bit flags) for OP_RANDOM.
+    // Fill a buffer with bytes from the kernel CSPRNG. Never blocks.
+    pub const F_RANDOM_GET: u32 = 1;
+    // Mix bytes from an entropy source (e.g. virtio-rng) into the kernel
+    // entropy pool. Requires CAP_SYS.
+    pub const F_RANDOM_ADD_ENTROPY: u32 = 2;
+
+    // Flags (not bit flags) for OP_NUMA_NODE.
+    // Set (or clear) the NUMA node the current thread prefers to allocate memory from.
+    pub const F_NUMA_NODE_SET: u32 = 1;
+    // Return the NUMA node the current thread prefers, or u64::MAX.
+    pub const F_NUMA_NODE_GET: u32 = 2;
+
+    // Flags (not bit flags) for OP_PRESSURE.
+    // Return (under pressure, high watermark, low watermark).
+    pub const F_PRESSURE_QUERY: u32 = 1;
+    // Set the watermarks. Requires CAP_SYS.
+    pub const F_PRESSURE_SET: u32 = 2;
+    // Return a handle that is woken whenever the pressure state changes.
+    pub const F_PRESSURE_HANDLE: u32 = 3;
+
+    /// The high watermark that disables memory pressure notifications (the default).
+    pub const PRESSURE_DISABLED: u64 = u64::MAX;
+
+    /// The most bytes a single OP_RANDOM syscall accepts.
+    pub const MAX_RANDOM_BYTES: usize = 256;
 
     #[cfg(feature = "userspace")]
     pub fn map(
@@ -121,6 +170,28 @@ impl SysMem {
         }
     }
 
+    /// Applies @advice (F_ADVISE_*) to @num_pages small pages at @addr, which
+    /// must be within a single F_LAZY or F_RESERVE mapping. Returns the number
+    /// of pages populated or released.
+    #[cfg(feature = "userspace")]
+    pub fn advise(addr: u64, num_pages: u64, advice: u32) -> Result<u64, ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_MEM, Self::OP_ADVISE, advice, 0),
+            SysHandle::SELF.as_u64(),
+            addr,
+            num_pages,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(result.data[0])
+        } else {
+            Err(result.error_code())
+        }
+    }
+
     #[cfg(feature = "userspace")]
     pub fn virt_to_phys(virt_addr: u64) -> Result<u64, ErrorCode> {
         let result = do_syscall(
@@ -223,6 +294,129 @@ impl SysMem {
         }
     }
 
+    #[cfg(feature = "userspace")]
+    pub fn query_fragmentation() -> Result<super::stats::MemFragmentationStats, ErrorCode> {
+        use crate::stats::MemFragmentationStats;
+
+        let mut stats = MemFragmentationStats::default();
+
+        let res = do_syscall(
+            pack_nr_ver(SYS_MEM, Self::OP_QUERY, Self::F_QUERY_FRAGMENTATION, 0),
+            SysHandle::NONE.as_u64(),
+            &mut stats as *mut _ as usize as u64,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if res.is_ok() {
+            Ok(stats)
+        } else {
+            Err(res.error_code())
+        }
+    }
+
+    #[cfg(feature = "userspace")]
+    pub fn query_frame_caches() -> Result<alloc::vec::Vec<super::stats::FrameCacheStats>, ErrorCode>
+    {
+        use crate::stats::FrameCacheStats;
+
+        let num_cpus = crate::shared_mem::KernelStaticPage::get().num_cpus as usize;
+        let mut stats = alloc::vec![FrameCacheStats::default(); num_cpus];
+
+        let res = do_syscall(
+            pack_nr_ver(SYS_MEM, Self::OP_QUERY, Self::F_QUERY_FRAME_CACHES, 0),
+            SysHandle::NONE.as_u64(),
+            stats.as_mut_ptr() as usize as u64,
+            num_cpus as u64,
+            0,
+            0,
+            0,
+        );
+
+        if res.is_ok() {
+            stats.truncate(res.data[0] as usize);
+            Ok(stats)
+        } else {
+            Err(res.error_code())
+        }
+    }
+
+    #[cfg(feature = "userspace")]
+    pub fn query_numa_nodes() -> Result<alloc::vec::Vec<super::stats::NumaNodeStats>, ErrorCode> {
+        use crate::stats::NumaNodeStats;
+
+        let mut stats = alloc::vec![NumaNodeStats::default(); NumaNodeStats::MAX_NODES];
+
+        let res = do_syscall(
+            pack_nr_ver(SYS_MEM, Self::OP_QUERY, Self::F_QUERY_NUMA_NODES, 0),
+            SysHandle::NONE.as_u64(),
+            stats.as_mut_ptr() as usize as u64,
+            NumaNodeStats::MAX_NODES as u64,
+            0,
+            0,
+            0,
+        );
+
+        if res.is_ok() {
+            stats.truncate(res.data[0] as usize);
+            Ok(stats)
+        } else {
+            Err(res.error_code())
+        }
+    }
+
+    /// Memory the current thread touches or allocates from now on comes from
+    /// `node` (see NumaNodeStats) if it has free pages, rather than from the
+    /// node of the CPU the thread runs on (the default, with `None`). Pages
+    /// come from other nodes only when `node` has none free. Combine with
+    /// SysCpu::affine_to_cpu() to keep a thread and its memory on one node.
+    #[cfg(feature = "userspace")]
+    pub fn set_numa_node(node: Option<u32>) -> Result<(), ErrorCode> {
+        let res = do_syscall(
+            pack_nr_ver(SYS_MEM, Self::OP_NUMA_NODE, Self::F_NUMA_NODE_SET, 0),
+            SysHandle::NONE.as_u64(),
+            match node {
+                Some(node) => node as u64,
+                None => u64::MAX,
+            },
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if res.is_ok() {
+            Ok(())
+        } else {
+            Err(res.error_code())
+        }
+    }
+
+    /// The NUMA node set by set_numa_node(), if any.
+    #[cfg(feature = "userspace")]
+    pub fn numa_node() -> Result<Option<u32>, ErrorCode> {
+        let res = do_syscall(
+            pack_nr_ver(SYS_MEM, Self::OP_NUMA_NODE, Self::F_NUMA_NODE_GET, 0),
+            SysHandle::NONE.as_u64(),
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if res.is_ok() {
+            Ok(match res.data[0] {
+                u64::MAX => None,
+                node => Some(node as u32),
+            })
+        } else {
+            Err(res.error_code())
+        }
+    }
+
     #[cfg(feature = "userspace")]
     pub fn reclaim(handle: SysHandle) -> Result<(), ErrorCode> {
         let res = do_syscall(
@@ -241,4 +435,115 @@ impl SysMem {
             Err(res.error_code())
         }
     }
+
+    /// Fills @buf with random bytes from the kernel CSPRNG, which is suitable
+    /// for keys and seeds.
+    #[cfg(feature = "userspace")]
+    pub fn get_random(buf: &mut [u8]) -> Result<(), ErrorCode> {
+        for chunk in buf.chunks_mut(Self::MAX_RANDOM_BYTES) {
+            let res = do_syscall(
+                pack_nr_ver(SYS_MEM, Self::OP_RANDOM, Self::F_RANDOM_GET, 0),
+                SysHandle::NONE.as_u64(),
+                chunk.as_mut_ptr() as usize as u64,
+                chunk.len() as u64,
+                0,
+                0,
+                0,
+            );
+
+            if res.is_err() {
+                return Err(res.error_code());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Mixes @bytes into the kernel entropy pool. Requires CAP_SYS.
+    #[cfg(feature = "userspace")]
+    pub fn add_entropy(bytes: &[u8]) -> Result<(), ErrorCode> {
+        for chunk in bytes.chunks(Self::MAX_RANDOM_BYTES) {
+            let res = do_syscall(
+                pack_nr_ver(SYS_MEM, Self::OP_RANDOM, Self::F_RANDOM_ADD_ENTROPY, 0),
+                SysHandle::NONE.as_u64(),
+                chunk.as_ptr() as usize as u64,
+                chunk.len() as u64,
+                0,
+                0,
+                0,
+            );
+
+            if res.is_err() {
+                return Err(res.error_code());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns (under pressure, high, low): the system comes under memory
+    /// pressure when its used memory (user + kernel) reaches the high watermark,
+    /// and stays under pressure until it drops to the low watermark. The
+    /// watermarks are in bytes; high is PRESSURE_DISABLED unless set.
+    #[cfg(feature = "userspace")]
+    pub fn memory_pressure() -> Result<(bool, u64, u64), ErrorCode> {
+        let res = do_syscall(
+            pack_nr_ver(SYS_MEM, Self::OP_PRESSURE, Self::F_PRESSURE_QUERY, 0),
+            SysHandle::NONE.as_u64(),
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if res.is_ok() {
+            Ok((res.data[0] != 0, res.data[1], res.data[2]))
+        } else {
+            Err(res.error_code())
+        }
+    }
+
+    /// Sets the memory pressure watermarks, in bytes; @low must be below @high
+    /// (by at least a page). PRESSURE_DISABLED as @high turns notifications off.
+    /// Requires CAP_SYS.
+    #[cfg(feature = "userspace")]
+    pub fn set_memory_pressure_thresholds(high: u64, low: u64) -> Result<(), ErrorCode> {
+        let res = do_syscall(
+            pack_nr_ver(SYS_MEM, Self::OP_PRESSURE, Self::F_PRESSURE_SET, 0),
+            SysHandle::NONE.as_u64(),
+            high,
+            low,
+            0,
+            0,
+            0,
+        );
+
+        if res.is_ok() {
+            Ok(())
+        } else {
+            Err(res.error_code())
+        }
+    }
+
+    /// Returns a handle that is woken (see SysCpu::wait()) whenever the system
+    /// comes under, or gets out of, memory pressure; memory_pressure() tells which.
+    #[cfg(feature = "userspace")]
+    pub fn memory_pressure_handle() -> Result<SysHandle, ErrorCode> {
+        let res = do_syscall(
+            pack_nr_ver(SYS_MEM, Self::OP_PRESSURE, Self::F_PRESSURE_HANDLE, 0),
+            SysHandle::NONE.as_u64(),
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if res.is_ok() {
+            Ok(SysHandle::from_u64(res.data[0]))
+        } else {
+            Err(res.error_code())
+        }
+    }
 }
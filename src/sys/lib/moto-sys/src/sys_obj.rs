@@ -14,8 +14,17 @@ impl SysObj {
     pub const OP_CREATE: u8 = 3;
     pub const OP_SET_LOG_LEVEL: u8 = 5;
     pub const OP_QUERY_HANDLE: u8 = 6;
+    /// The syscall filter of the calling process (see syscalls::SyscallFilter).
+    pub const OP_SYSCALL_FILTER: u8 = 7;
 
     pub const F_QUERY_PID: u32 = 4;
+    pub const F_QUERY_PEER_CREDS: u32 = 5;
+
+    /// Intersect the syscall filter of the calling process with the one in
+    /// args[0] (addr) and args[1] (size): filters can only be tightened.
+    /// Child processes start with the filter of their parent.
+    pub const F_SYSCALL_FILTER_SET: u32 = 1;
+    pub const F_SYSCALL_FILTER_GET: u32 = 2;
 
     // When connecting to ("getting") a shared URL, wake the counterpart.
     pub const F_WAKE_PEER: u32 = 1;
@@ -213,4 +222,78 @@ impl SysObj {
             Err(result.error_code().into())
         }
     }
+
+    /// Restricts the syscalls the calling process (and the processes it spawns
+    /// later) can make to those allowed by both `filter` and the current filter.
+    /// Blocked syscalls fail with E_NOT_ALLOWED.
+    #[cfg(feature = "userspace")]
+    pub fn set_syscall_filter(filter: &SyscallFilter) -> Result<(), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(
+                SYS_OBJ,
+                Self::OP_SYSCALL_FILTER,
+                Self::F_SYSCALL_FILTER_SET,
+                0,
+            ),
+            filter as *const SyscallFilter as usize as u64,
+            core::mem::size_of::<SyscallFilter>() as u64,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// The syscall filter of the calling process.
+    #[cfg(feature = "userspace")]
+    pub fn syscall_filter() -> Result<SyscallFilter, ErrorCode> {
+        let mut filter = SyscallFilter::deny_all();
+        let result = do_syscall(
+            pack_nr_ver(
+                SYS_OBJ,
+                Self::OP_SYSCALL_FILTER,
+                Self::F_SYSCALL_FILTER_GET,
+                0,
+            ),
+            &mut filter as *mut SyscallFilter as usize as u64,
+            core::mem::size_of::<SyscallFilter>() as u64,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(filter)
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Returns (PID, capabilities) of the process on the other side of a
+    /// shared handle (e.g. an io_channel), as known to the kernel.
+    #[cfg(feature = "userspace")]
+    pub fn get_peer_credentials(handle: SysHandle) -> Result<(u64, u64), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_OBJ, Self::OP_QUERY_HANDLE, Self::F_QUERY_PEER_CREDS, 0),
+            handle.as_u64(),
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok((result.data[0], result.data[1]))
+        } else {
+            Err(result.error_code().into())
+        }
+    }
 }
@@ -5,6 +5,13 @@ use crate::syscalls::*;
 #[cfg(feature = "userspace")]
 use crate::ErrorCode;
 
+// This ENV key can be used to specify a CPU time limit for the process
+// being created: "$MILLIS" kills the process once it has used $MILLIS of
+// CPU time; "$MILLIS,notify" interrupts its main thread instead. Spawning
+// a CAP_SYS process with a "kill" limit fails (see SysRay::set_cpu_limit()).
+// Currently works with Rust's std::process::Command.
+pub const MOTURUS_CPU_LIMIT_ENV_KEY: &str = "MOTURUS_CPU_LIMIT";
+
 /// SysRay syscall: various statistics/debugging operations.
 pub struct SysRay;
 
@@ -12,10 +19,182 @@ impl SysRay {
     pub const OP_QUERY_PROCESS: u8 = 1;
     pub const OP_DBG: u8 = 2;
     pub const OP_LOG: u8 = 3;
+    pub const OP_CMDLINE: u8 = 4;
+    pub const OP_CPU_LIMIT: u8 = 5;
+    /// Zero cumulative stats counters of a process. Requires CAP_SYS.
+    pub const OP_STATS_RESET: u8 = 6;
+    /// Read the kernel log ring buffer, or set per-subsystem log levels.
+    /// Requires CAP_LOG.
+    pub const OP_KLOG: u8 = 7;
+    /// Periodic sampling of what each CPU runs. Requires CAP_SYS.
+    pub const OP_CPU_SAMPLING: u8 = 8;
+    /// What the kernel does when the calling process panics.
+    pub const OP_PANIC_ACTION: u8 = 9;
+    /// Kernel SpinLock contention stats. Requires CAP_SYS.
+    pub const OP_LOCK_STATS: u8 = 10;
+    /// The system hostname. Readable by all processes; setting it requires CAP_SYS.
+    pub const OP_HOSTNAME: u8 = 11;
+    /// A per-process watchdog for critical services. Requires CAP_SYS.
+    pub const OP_WATCHDOG: u8 = 12;
+    /// How likely a process is to be killed when the system runs out of memory.
+    pub const OP_OOM_PRIORITY: u8 = 13;
+    /// How many OP_LOG messages a process may log. Setting it requires CAP_SYS.
+    pub const OP_LOG_RATE_LIMIT: u8 = 14;
+    /// Detection of deadlocked SysCpu::wait() calls. Setting it requires CAP_SYS.
+    pub const OP_DEADLOCK_DETECTION: u8 = 15;
 
     pub const F_QUERY_STATUS: u32 = 1;
     pub const F_QUERY_LIST: u32 = 2;
     pub const F_QUERY_LIST_CHILDREN: u32 = 3;
+    /// Per-CPU usage of a process, as TSC (kernel + uspace).
+    pub const F_QUERY_PERCPU_USAGE: u32 = 4;
+    /// Per-CPU thread migrations of a process, as (in, out) pairs.
+    /// PID_SYSTEM gives system-wide per-CPU counts.
+    pub const F_QUERY_PERCPU_MIGRATIONS: u32 = 5;
+    /// A process and all its descendants, as one consistent snapshot.
+    pub const F_QUERY_SNAPSHOT: u32 = 6;
+    /// The number of page faults that populated lazily mapped pages of a process.
+    pub const F_QUERY_PAGE_FAULTS: u32 = 7;
+    /// The scheduling latency of a process (see stats::SchedLatencyStats).
+    pub const F_QUERY_SCHED_LATENCY: u32 = 8;
+    /// The resources used by a process (requires the process handle);
+    /// see stats::ResourceUsage.
+    pub const F_QUERY_RUSAGE: u32 = 9;
+    /// Per-thread CPU usage of a process; see stats::ThreadCpuUsage.
+    pub const F_QUERY_THREADS: u32 = 10;
+    /// A process and its descendants down to a depth, filtered by capabilities.
+    pub const F_QUERY_DESCENDANTS: u32 = 11;
+
+    /// Set the command line of a newly spawned process (requires the process handle).
+    /// Can be done only once.
+    pub const F_CMDLINE_SET: u32 = 1;
+    /// Get the command line of a process by PID. Requires CAP_SYS unless
+    /// the process is the caller.
+    pub const F_CMDLINE_GET: u32 = 2;
+    /// The max size of a cmdline blob.
+    pub const MAX_CMDLINE_SIZE: u64 = 64 * 1024;
+
+    /// Set a CPU time limit on a process (requires the process handle).
+    /// Can be done only once.
+    pub const F_CPU_LIMIT_SET: u32 = 1;
+    /// Get the CPU time limit and the remaining budget of a process by PID.
+    pub const F_CPU_LIMIT_GET: u32 = 2;
+
+    /// Kill the process when its CPU time limit is exceeded. Not allowed
+    /// for CAP_SYS processes (see set_cpu_limit()).
+    pub const CPU_LIMIT_ACTION_KILL: u64 = 1;
+    /// Interrupt the main thread of the process (see SysCpu::interrupt())
+    /// when its CPU time limit is exceeded; the process keeps running.
+    pub const CPU_LIMIT_ACTION_NOTIFY: u64 = 2;
+
+    /// Copy recent kernel log lines into a userspace buffer.
+    pub const F_KLOG_READ: u32 = 1;
+    /// Set the log level of a kernel subsystem (a log target prefix, like "kernel::mm").
+    pub const F_KLOG_SET_LEVEL: u32 = 2;
+    /// The max number of bytes a single F_KLOG_READ returns.
+    pub const KLOG_MAX_READ: usize = 64 * 1024;
+
+    /// Log levels, as in log::LevelFilter; KLOG_LEVEL_OFF with F_KLOG_SET_LEVEL
+    /// removes the subsystem's own level, so the default one applies again.
+    pub const KLOG_LEVEL_OFF: u8 = 0;
+    pub const KLOG_LEVEL_ERROR: u8 = 1;
+    pub const KLOG_LEVEL_WARN: u8 = 2;
+    pub const KLOG_LEVEL_INFO: u8 = 3;
+    pub const KLOG_LEVEL_DEBUG: u8 = 4;
+    pub const KLOG_LEVEL_TRACE: u8 = 5;
+
+    pub const F_CPU_SAMPLING_START: u32 = 1;
+    pub const F_CPU_SAMPLING_STOP: u32 = 2;
+    pub const F_CPU_SAMPLING_READ: u32 = 3;
+
+    /// How often each CPU is sampled while CPU sampling is on (see set_cpu_sampling()).
+    pub const CPU_SAMPLE_PERIOD: core::time::Duration = core::time::Duration::from_millis(10);
+
+    pub const F_LOCK_STATS_START: u32 = 1;
+    pub const F_LOCK_STATS_STOP: u32 = 2;
+    pub const F_LOCK_STATS_READ: u32 = 3;
+    pub const F_LOCK_STATS_RESET: u32 = 4;
+
+    /// Set the panic action of the calling process.
+    pub const F_PANIC_ACTION_SET: u32 = 1;
+    /// Report that the calling process has panicked: the kernel applies its
+    /// panic action. Called by the runtime after the panic has been logged.
+    pub const F_PANIC_ACTION_PANICKED: u32 = 2;
+
+    /// The default: the panicking process exits, the system keeps running.
+    pub const PANIC_ACTION_EXIT: u64 = 0;
+    /// Shut down the system (the VM). Requires CAP_SYS.
+    pub const PANIC_ACTION_HALT: u64 = 1;
+    /// Reboot the system. Requires CAP_SYS.
+    pub const PANIC_ACTION_REBOOT: u64 = 2;
+
+    pub const F_HOSTNAME_GET: u32 = 1;
+    pub const F_HOSTNAME_SET: u32 = 2;
+    /// The max length of the hostname, in bytes (UTF-8).
+    pub const MAX_HOSTNAME_LEN: usize = 255;
+
+    /// Register (or re-configure) the watchdog of the calling process:
+    /// args[0] is the timeout in nanoseconds, args[1] one of WATCHDOG_ACTION_*.
+    /// Counts as a pet.
+    pub const F_WATCHDOG_REGISTER: u32 = 1;
+    /// Pet the watchdog of the calling process.
+    pub const F_WATCHDOG_PET: u32 = 2;
+    pub const F_WATCHDOG_UNREGISTER: u32 = 3;
+
+    /// Log an error (once per missed deadline); the process keeps running.
+    pub const WATCHDOG_ACTION_LOG: u64 = 0;
+    /// Kill the process; whoever supervises it (its parent) can restart it,
+    /// as sys-init does with the log server.
+    pub const WATCHDOG_ACTION_KILL: u64 = 1;
+    /// Reboot the system.
+    pub const WATCHDOG_ACTION_REBOOT: u64 = 2;
+
+    /// Longer watchdog timeouts are rejected with E_INVALID_ARGUMENT.
+    pub const MAX_WATCHDOG_TIMEOUT_SECS: u64 = 3600;
+
+    /// Set the OOM priority of a process: args[0] is the PID, args[1] the
+    /// priority (as i64). Processes can raise their own priority; lowering it,
+    /// or changing it for another process, requires CAP_SYS.
+    pub const F_OOM_PRIORITY_SET: u32 = 1;
+    /// Get the OOM priority of a process: args[0] is the PID.
+    pub const F_OOM_PRIORITY_GET: u32 = 2;
+
+    /// When the system runs out of memory, the kernel kills the process with
+    /// the highest score: its resident memory scaled by (1000 + priority) / 1000.
+    /// CAP_SYS processes are never killed; neither are processes with
+    /// OOM_PRIORITY_MIN. Children inherit the priority of their parent.
+    pub const OOM_PRIORITY_MIN: i32 = -1000;
+    pub const OOM_PRIORITY_DEFAULT: i32 = 0;
+    pub const OOM_PRIORITY_MAX: i32 = 1000;
+
+    /// Set the log rate limit: args[0] is the sustained rate, in messages per
+    /// second (zero: unlimited), args[1] the burst, in messages.
+    pub const F_LOG_RATE_LIMIT_SET: u32 = 1;
+    /// Get the log rate limit, as (rate, burst).
+    pub const F_LOG_RATE_LIMIT_GET: u32 = 2;
+    /// Get the number of the caller's messages dropped and not yet reported.
+    pub const F_LOG_RATE_LIMIT_DROPPED: u32 = 3;
+
+    /// Enable (args[0] != 0) or disable deadlock detection. While enabled, a
+    /// SysCpu::wait() without a timeout fails with E_DEADLOCK instead of
+    /// blocking if the processes that could wake the waiter are, transitively,
+    /// all blocked the same way. This is a debugging aid: the check walks the
+    /// threads of the processes involved on every such wait. Off by default.
+    pub const F_DEADLOCK_DETECTION_SET: u32 = 1;
+    /// Get whether deadlock detection is enabled (1) or not (0).
+    pub const F_DEADLOCK_DETECTION_GET: u32 = 2;
+    /// Enable (args[0] != 0) or disable deadlock detection for the waits of
+    /// the calling process only. Does not require CAP_SYS, and is not inherited.
+    pub const F_DEADLOCK_DETECTION_SET_PROCESS: u32 = 3;
+
+    /// Each process may log up to the burst at once, and the rate on average;
+    /// messages above the limit are dropped, and the kernel logs how many were
+    /// dropped once the process may log again: with its next message, or on a
+    /// scheduler tick if it logs nothing more. The limit is system-wide, and
+    /// applies to each process separately.
+    pub const LOG_RATE_DEFAULT: u64 = 200;
+    pub const LOG_BURST_DEFAULT: u64 = 1000;
+    pub const LOG_BURST_MAX: u64 = 1_000_000;
 
     /// Attach to a running process.
     pub const F_DBG_ATTACH: u32 = 1;
@@ -60,6 +239,36 @@ impl SysRay {
         }
     }
 
+    /// Fills @buf with the stats of @pid and all its descendants (everything,
+    /// if @pid is PID_SYSTEM), taken in one pass. Returns the number of entries
+    /// copied and the number of processes in the snapshot; if the latter is
+    /// larger, @buf was too small and the listing is truncated.
+    #[cfg(feature = "userspace")]
+    pub fn snapshot_processes_v1(
+        pid: u64,
+        buf: &mut [super::stats::ProcessStatsV1],
+    ) -> Result<(usize, usize), ErrorCode> {
+        if buf.len() < 1 {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_QUERY_PROCESS, Self::F_QUERY_SNAPSHOT, 1),
+            pid,
+            buf.as_mut_ptr() as usize as u64,
+            buf.len() as u64,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok((result.data[0] as usize, result.data[1] as usize))
+        } else {
+            Err(result.error_code())
+        }
+    }
+
     #[cfg(feature = "userspace")]
     pub fn list_processes_v1(
         pid: u64,
@@ -92,6 +301,265 @@ impl SysRay {
         }
     }
 
+    /// Fills @buf with the stats of @pid and its descendants down to
+    /// @max_depth levels (zero: @pid only), in pre-order, with the children
+    /// of each process in PID order. Only processes that have all of @caps
+    /// are listed (zero: all processes), but the descendants of those that
+    /// don't are still walked. Returns the number of entries copied.
+    #[cfg(feature = "userspace")]
+    pub fn list_descendants_v2(
+        pid: u64,
+        max_depth: u32,
+        caps: u64,
+        buf: &mut [super::stats::ProcessStatsV2],
+    ) -> Result<usize, ErrorCode> {
+        if buf.len() < 1 {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        let result = do_syscall(
+            pack_nr_ver(
+                SYS_RAY,
+                Self::OP_QUERY_PROCESS,
+                Self::F_QUERY_DESCENDANTS,
+                2,
+            ),
+            pid,
+            buf.as_mut_ptr() as usize as u64,
+            buf.len() as u64,
+            max_depth as u64,
+            caps,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(result.data[0] as usize)
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Like snapshot_processes_v1(), with ProcessStatsV2 entries.
+    #[cfg(feature = "userspace")]
+    pub fn snapshot_processes_v2(
+        pid: u64,
+        buf: &mut [super::stats::ProcessStatsV2],
+    ) -> Result<(usize, usize), ErrorCode> {
+        if buf.len() < 1 {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_QUERY_PROCESS, Self::F_QUERY_SNAPSHOT, 2),
+            pid,
+            buf.as_mut_ptr() as usize as u64,
+            buf.len() as u64,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok((result.data[0] as usize, result.data[1] as usize))
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Like list_processes_v1(), with ProcessStatsV2 entries.
+    #[cfg(feature = "userspace")]
+    pub fn list_processes_v2(
+        pid: u64,
+        flat_list: bool,
+        buf: &mut [super::stats::ProcessStatsV2],
+    ) -> Result<usize, ErrorCode> {
+        if buf.len() < 1 {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        let flags = if flat_list {
+            Self::F_QUERY_LIST
+        } else {
+            Self::F_QUERY_LIST_CHILDREN
+        };
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_QUERY_PROCESS, flags, 2),
+            pid,
+            buf.as_mut_ptr() as usize as u64,
+            buf.len() as u64,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(result.data[0] as usize)
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Fills @buf with the CPU usage (as TSC) of process @pid on each CPU.
+    /// @buf must have at least num_cpus entries. Returns the number of entries filled.
+    #[cfg(feature = "userspace")]
+    pub fn query_percpu_usage(pid: u64, buf: &mut [u64]) -> Result<usize, ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(
+                SYS_RAY,
+                Self::OP_QUERY_PROCESS,
+                Self::F_QUERY_PERCPU_USAGE,
+                0,
+            ),
+            pid,
+            buf.as_mut_ptr() as usize as u64,
+            buf.len() as u64,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(result.data[0] as usize)
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Fills @buf with the migrations of process @pid, one entry per CPU:
+    /// a migration is counted when a thread runs on a CPU different from
+    /// the one it last ran on. @buf must have at least num_cpus entries.
+    /// Returns the number of entries filled.
+    #[cfg(feature = "userspace")]
+    pub fn query_percpu_migrations(
+        pid: u64,
+        buf: &mut [super::stats::CpuMigrations],
+    ) -> Result<usize, ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(
+                SYS_RAY,
+                Self::OP_QUERY_PROCESS,
+                Self::F_QUERY_PERCPU_MIGRATIONS,
+                0,
+            ),
+            pid,
+            buf.as_mut_ptr() as usize as u64,
+            buf.len() as u64,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(result.data[0] as usize)
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// The number of pages of lazy (F_LAZY, F_RESERVE) mappings of @pid populated
+    /// on first access. These are minor faults: there are no major faults, as
+    /// nothing is paged in from storage. Zeroed by reset_stats() (which is
+    /// not atomic with other counters).
+    #[cfg(feature = "userspace")]
+    pub fn query_page_faults(pid: u64) -> Result<u64, ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(
+                SYS_RAY,
+                Self::OP_QUERY_PROCESS,
+                Self::F_QUERY_PAGE_FAULTS,
+                0,
+            ),
+            pid,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(result.data[0])
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// The time threads of @pid have spent runnable, waiting to be scheduled.
+    /// Reset by reset_stats().
+    #[cfg(feature = "userspace")]
+    pub fn query_sched_latency(pid: u64) -> Result<crate::stats::SchedLatencyStats, ErrorCode> {
+        let mut stats = crate::stats::SchedLatencyStats::default();
+        let result = do_syscall(
+            pack_nr_ver(
+                SYS_RAY,
+                Self::OP_QUERY_PROCESS,
+                Self::F_QUERY_SCHED_LATENCY,
+                0,
+            ),
+            pid,
+            &mut stats as *mut _ as usize as u64,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(stats)
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// The resources used by the process behind @handle, usually a child that
+    /// has exited. Stays available for as long as the handle is held.
+    #[cfg(feature = "userspace")]
+    pub fn query_rusage(handle: SysHandle) -> Result<crate::stats::ResourceUsage, ErrorCode> {
+        let mut usage = crate::stats::ResourceUsage::default();
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_QUERY_PROCESS, Self::F_QUERY_RUSAGE, 0),
+            handle.as_u64(),
+            &mut usage as *mut _ as usize as u64,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(usage)
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Fills @buf with the CPU usage of the threads of process @pid: the first
+    /// entry (tid zero) has the total of the threads that have exited, followed
+    /// by live threads in TID order. A thread's usage is updated when it leaves
+    /// the CPU or enters/exits the kernel, so the thread making this call does
+    /// not see its current time slice. Returns (entries filled, entries available).
+    #[cfg(feature = "userspace")]
+    pub fn query_threads(
+        pid: u64,
+        buf: &mut [crate::stats::ThreadCpuUsage],
+    ) -> Result<(usize, usize), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_QUERY_PROCESS, Self::F_QUERY_THREADS, 0),
+            pid,
+            buf.as_mut_ptr() as usize as u64,
+            buf.len() as u64,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok((result.data[0] as usize, result.data[1] as usize))
+        } else {
+            Err(result.error_code())
+        }
+    }
+
     #[cfg(feature = "userspace")]
     pub fn dbg_attach(pid: u64) -> Result<SysHandle, ErrorCode> {
         let result = do_syscall(
@@ -261,6 +729,624 @@ impl SysRay {
         }
     }
 
+    /// Set the command line of a newly spawned process. The format is opaque to the kernel;
+    /// by convention (see rt.vdso) it is the list of arguments, each followed by a NUL,
+    /// then an extra NUL, then the environment as KEY=VALUE strings, each followed by a NUL.
+    #[cfg(feature = "userspace")]
+    pub fn set_cmdline(process: SysHandle, cmdline: &[u8]) -> Result<(), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_CMDLINE, Self::F_CMDLINE_SET, 0),
+            process.as_u64(),
+            cmdline.as_ptr() as usize as u64,
+            cmdline.len() as u64,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Copy the command line of process `pid` into `buf`. Returns the full size
+    /// of the command line, which may be larger than `buf`, or zero if it was not set.
+    #[cfg(feature = "userspace")]
+    pub fn get_cmdline(pid: u64, buf: &mut [u8]) -> Result<usize, ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_CMDLINE, Self::F_CMDLINE_GET, 0),
+            pid,
+            buf.as_mut_ptr() as usize as u64,
+            buf.len() as u64,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(result.data[0] as usize)
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Set the CPU time limit of a process, in TSC (the same units as
+    /// ProcessStatsV1::cpu_usage). `action` is one of CPU_LIMIT_ACTION_*.
+    /// CAP_SYS processes cannot be killed, so CPU_LIMIT_ACTION_KILL fails
+    /// with E_NOT_ALLOWED for them; CPU_LIMIT_ACTION_NOTIFY works for any process.
+    #[cfg(feature = "userspace")]
+    pub fn set_cpu_limit(process: SysHandle, limit: u64, action: u64) -> Result<(), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_CPU_LIMIT, Self::F_CPU_LIMIT_SET, 0),
+            process.as_u64(),
+            limit,
+            action,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Returns (limit, remaining) CPU time of process `pid`, in TSC.
+    /// `limit` is zero if the process does not have a CPU time limit.
+    #[cfg(feature = "userspace")]
+    pub fn get_cpu_limit(pid: u64) -> Result<(u64, u64), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_CPU_LIMIT, Self::F_CPU_LIMIT_GET, 0),
+            pid,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok((result.data[0], result.data[1]))
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Zeroes cumulative counters (CPU usage, per-CPU idle time and migrations,
+    /// context switches, scheduling latency and page faults) of process `pid`
+    /// (PID_SYSTEM and PID_KERNEL included). Gauges, like memory usage or active
+    /// threads, are not affected. Requires CAP_SYS.
+    ///
+    /// The reset is not atomic: counters are zeroed one after another while
+    /// the process runs, so a concurrent query may see a mix of old and zeroed
+    /// counters, and events counted during the reset may be lost.
+    #[cfg(feature = "userspace")]
+    pub fn reset_stats(pid: u64) -> Result<(), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_STATS_RESET, 0, 0),
+            pid,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Copies whole kernel log lines, starting at byte offset @from of the
+    /// kernel log stream, into @buf. Lines that have been overwritten in the
+    /// ring buffer are skipped, so passing zero reads everything still there.
+    /// Returns the number of bytes copied and the offset to read from next.
+    #[cfg(feature = "userspace")]
+    pub fn klog_read(from: u64, buf: &mut [u8]) -> Result<(usize, u64), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_KLOG, Self::F_KLOG_READ, 0),
+            from,
+            buf.as_mut_ptr() as usize as u64,
+            buf.len() as u64,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok((result.data[0] as usize, result.data[1]))
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Sets the log level (one of KLOG_LEVEL_*) of kernel log targets starting
+    /// with @subsystem. Returns the previous level (KLOG_LEVEL_OFF if none was set).
+    #[cfg(feature = "userspace")]
+    pub fn klog_set_level(subsystem: &str, level: u8) -> Result<u8, ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_KLOG, Self::F_KLOG_SET_LEVEL, 0),
+            subsystem.as_ptr() as usize as u64,
+            subsystem.len() as u64,
+            level as u64,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(result.data[0] as u8)
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Sets what happens when the calling process panics; `action` is one of
+    /// PANIC_ACTION_*. Critical system services (e.g. sys-io) set this at startup
+    /// so that the system does not keep running half-dead without them.
+    #[cfg(feature = "userspace")]
+    pub fn set_panic_action(action: u64) -> Result<(), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_PANIC_ACTION, Self::F_PANIC_ACTION_SET, 0),
+            action,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Tells the kernel the calling process has panicked. Does not return
+    /// unless the panic action of the process is PANIC_ACTION_EXIT.
+    #[cfg(feature = "userspace")]
+    pub fn on_panic() {
+        let _ = do_syscall(
+            pack_nr_ver(
+                SYS_RAY,
+                Self::OP_PANIC_ACTION,
+                Self::F_PANIC_ACTION_PANICKED,
+                0,
+            ),
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+    }
+
+    /// Starts (or stops) recording, every CPU_SAMPLE_PERIOD, what each CPU is running.
+    /// Each CPU keeps its most recent samples in a ring buffer that cpu_samples() reads.
+    /// The sampling period does not depend on the time slice (see SysCpu::set_time_slice()).
+    #[cfg(feature = "userspace")]
+    pub fn set_cpu_sampling(enabled: bool) -> Result<(), ErrorCode> {
+        let flags = if enabled {
+            Self::F_CPU_SAMPLING_START
+        } else {
+            Self::F_CPU_SAMPLING_STOP
+        };
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_CPU_SAMPLING, flags, 0),
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Copies samples of @cpu, starting at sample number @from, into @buf.
+    /// Samples already overwritten in the ring buffer are skipped, so passing
+    /// zero reads all samples still there. Returns the number of samples
+    /// copied and the sample number to read from next.
+    #[cfg(feature = "userspace")]
+    pub fn cpu_samples(
+        cpu: u32,
+        from: u64,
+        buf: &mut [crate::stats::CpuSample],
+    ) -> Result<(usize, u64), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_CPU_SAMPLING, Self::F_CPU_SAMPLING_READ, 0),
+            cpu as u64,
+            from,
+            buf.as_mut_ptr() as usize as u64,
+            buf.len() as u64,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok((result.data[0] as usize, result.data[1]))
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Starts (or stops) counting kernel SpinLock acquisitions and spins per
+    /// lock site. Off by default, as it slows down every lock acquisition.
+    #[cfg(feature = "userspace")]
+    pub fn set_lock_stats(enabled: bool) -> Result<(), ErrorCode> {
+        let flags = if enabled {
+            Self::F_LOCK_STATS_START
+        } else {
+            Self::F_LOCK_STATS_STOP
+        };
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_LOCK_STATS, flags, 0),
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Zeroes kernel SpinLock stats counters.
+    #[cfg(feature = "userspace")]
+    pub fn reset_lock_stats() -> Result<(), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_LOCK_STATS, Self::F_LOCK_STATS_RESET, 0),
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Copies per-site kernel SpinLock stats into @buf, most contended first.
+    /// Returns the number of sites copied and the number of acquisitions
+    /// not recorded because the kernel ran out of site slots.
+    #[cfg(feature = "userspace")]
+    pub fn lock_stats(buf: &mut [crate::stats::LockSiteStats]) -> Result<(usize, u64), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_LOCK_STATS, Self::F_LOCK_STATS_READ, 0),
+            buf.as_mut_ptr() as usize as u64,
+            buf.len() as u64,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok((result.data[0] as usize, result.data[1]))
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Copy the system hostname into `buf`. Returns the full length of the hostname,
+    /// which may be larger than `buf` (at most MAX_HOSTNAME_LEN).
+    #[cfg(feature = "userspace")]
+    pub fn get_hostname(buf: &mut [u8]) -> Result<usize, ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_HOSTNAME, Self::F_HOSTNAME_GET, 0),
+            buf.as_mut_ptr() as usize as u64,
+            buf.len() as u64,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(result.data[0] as usize)
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Set the system hostname: a non-empty string of at most MAX_HOSTNAME_LEN bytes,
+    /// without control characters. Requires CAP_SYS.
+    #[cfg(feature = "userspace")]
+    pub fn set_hostname(hostname: &str) -> Result<(), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_HOSTNAME, Self::F_HOSTNAME_SET, 0),
+            hostname.as_ptr() as usize as u64,
+            hostname.len() as u64,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Registers the calling process with the kernel watchdog: if the process
+    /// does not call watchdog_pet() at least once every `timeout`, the kernel
+    /// takes `action` (one of WATCHDOG_ACTION_*). Deadlines are checked on
+    /// scheduler ticks, so timeouts are not precise (see SysCpu::OP_TIME_SLICE).
+    /// `timeout` must be non-zero and at most MAX_WATCHDOG_TIMEOUT_SECS.
+    #[cfg(feature = "userspace")]
+    pub fn watchdog_register(timeout: core::time::Duration, action: u64) -> Result<(), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_WATCHDOG, Self::F_WATCHDOG_REGISTER, 0),
+            timeout.as_nanos().try_into().unwrap_or(u64::MAX),
+            action,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    #[cfg(feature = "userspace")]
+    pub fn watchdog_pet() -> Result<(), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_WATCHDOG, Self::F_WATCHDOG_PET, 0),
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    #[cfg(feature = "userspace")]
+    pub fn watchdog_unregister() -> Result<(), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_WATCHDOG, Self::F_WATCHDOG_UNREGISTER, 0),
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Sets the OOM priority (OOM_PRIORITY_MIN..=OOM_PRIORITY_MAX) of @pid.
+    #[cfg(feature = "userspace")]
+    pub fn set_oom_priority(pid: u64, priority: i32) -> Result<(), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_OOM_PRIORITY, Self::F_OOM_PRIORITY_SET, 0),
+            pid,
+            priority as i64 as u64,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    #[cfg(feature = "userspace")]
+    pub fn oom_priority(pid: u64) -> Result<i32, ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(SYS_RAY, Self::OP_OOM_PRIORITY, Self::F_OOM_PRIORITY_GET, 0),
+            pid,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(result.data[0] as i64 as i32)
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Sets the log rate limit (see LOG_RATE_DEFAULT). Requires CAP_SYS.
+    #[cfg(feature = "userspace")]
+    pub fn set_log_rate_limit(rate: u64, burst: u64) -> Result<(), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(
+                SYS_RAY,
+                Self::OP_LOG_RATE_LIMIT,
+                Self::F_LOG_RATE_LIMIT_SET,
+                0,
+            ),
+            rate,
+            burst,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// The log rate limit, as (messages per second, burst).
+    #[cfg(feature = "userspace")]
+    pub fn log_rate_limit() -> Result<(u64, u64), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(
+                SYS_RAY,
+                Self::OP_LOG_RATE_LIMIT,
+                Self::F_LOG_RATE_LIMIT_GET,
+                0,
+            ),
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok((result.data[0], result.data[1]))
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// The number of messages of the calling process dropped by the log rate
+    /// limit that the kernel has not yet reported (see LOG_RATE_DEFAULT).
+    #[cfg(feature = "userspace")]
+    pub fn log_messages_dropped() -> Result<u64, ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(
+                SYS_RAY,
+                Self::OP_LOG_RATE_LIMIT,
+                Self::F_LOG_RATE_LIMIT_DROPPED,
+                0,
+            ),
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(result.data[0])
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Enables or disables deadlock detection (see F_DEADLOCK_DETECTION_SET).
+    /// Requires CAP_SYS.
+    #[cfg(feature = "userspace")]
+    pub fn set_deadlock_detection(enabled: bool) -> Result<(), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(
+                SYS_RAY,
+                Self::OP_DEADLOCK_DETECTION,
+                Self::F_DEADLOCK_DETECTION_SET,
+                0,
+            ),
+            enabled as u64,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Enables or disables deadlock detection for the calling process
+    /// (see F_DEADLOCK_DETECTION_SET_PROCESS).
+    #[cfg(feature = "userspace")]
+    pub fn set_process_deadlock_detection(enabled: bool) -> Result<(), ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(
+                SYS_RAY,
+                Self::OP_DEADLOCK_DETECTION,
+                Self::F_DEADLOCK_DETECTION_SET_PROCESS,
+                0,
+            ),
+            enabled as u64,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(())
+        } else {
+            Err(result.error_code())
+        }
+    }
+
+    /// Whether deadlock detection is enabled (globally).
+    #[cfg(feature = "userspace")]
+    pub fn deadlock_detection() -> Result<bool, ErrorCode> {
+        let result = do_syscall(
+            pack_nr_ver(
+                SYS_RAY,
+                Self::OP_DEADLOCK_DETECTION,
+                Self::F_DEADLOCK_DETECTION_GET,
+                0,
+            ),
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        );
+
+        if result.is_ok() {
+            Ok(result.data[0] != 0)
+        } else {
+            Err(result.error_code())
+        }
+    }
+
     #[cfg(feature = "userspace")]
     pub fn log(msg: &str) -> Result<(), ErrorCode> {
         let bytes = msg.as_bytes();
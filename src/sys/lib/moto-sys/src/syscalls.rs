@@ -41,6 +41,97 @@ impl SyscallResult {
     }
 }
 
+/// A per-process syscall filter (see SysObj::OP_SYSCALL_FILTER): one bit
+/// per (syscall number, operation); the operation is allowed if its bit is set.
+/// SysCpu::OP_EXIT and SysObj::OP_SYSCALL_FILTER are always allowed, so that
+/// a filtered process can still exit and tighten its filter further.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SyscallFilter {
+    bits: [u64; SyscallFilter::WORDS],
+}
+
+impl SyscallFilter {
+    /// Syscall numbers above this are not filterable (and not implemented).
+    pub const MAX_SYSCALL_NR: u8 = 7;
+    const WORDS: usize = ((Self::MAX_SYSCALL_NR as usize) + 1) * 256 / 64;
+
+    pub const fn allow_all() -> Self {
+        Self {
+            bits: [u64::MAX; Self::WORDS],
+        }
+    }
+
+    pub const fn deny_all() -> Self {
+        Self {
+            bits: [0; Self::WORDS],
+        }
+    }
+
+    fn bit(syscall_nr: u8, operation: u8) -> Option<(usize, u64)> {
+        if syscall_nr > Self::MAX_SYSCALL_NR {
+            return None;
+        }
+        let idx = (syscall_nr as usize) * 256 + (operation as usize);
+        Some((idx / 64, 1 << (idx % 64)))
+    }
+
+    pub fn allow(&mut self, syscall_nr: u8, operation: u8) {
+        if let Some((word, mask)) = Self::bit(syscall_nr, operation) {
+            self.bits[word] |= mask;
+        }
+    }
+
+    pub fn deny(&mut self, syscall_nr: u8, operation: u8) {
+        if let Some((word, mask)) = Self::bit(syscall_nr, operation) {
+            self.bits[word] &= !mask;
+        }
+    }
+
+    /// Allows all operations of the syscall.
+    pub fn allow_syscall(&mut self, syscall_nr: u8) {
+        for operation in 0..=u8::MAX {
+            self.allow(syscall_nr, operation);
+        }
+    }
+
+    /// Denies all operations of the syscall.
+    pub fn deny_syscall(&mut self, syscall_nr: u8) {
+        for operation in 0..=u8::MAX {
+            self.deny(syscall_nr, operation);
+        }
+    }
+
+    pub fn is_allowed(&self, syscall_nr: u8, operation: u8) -> bool {
+        if syscall_nr == SYS_CPU && operation == crate::SysCpu::OP_EXIT {
+            return true;
+        }
+        if syscall_nr == SYS_OBJ && operation == crate::SysObj::OP_SYSCALL_FILTER {
+            return true;
+        }
+
+        match Self::bit(syscall_nr, operation) {
+            Some((word, mask)) => (self.bits[word] & mask) != 0,
+            None => false,
+        }
+    }
+
+    /// What is allowed by both filters.
+    pub fn intersect(&self, other: &Self) -> Self {
+        let mut result = *self;
+        for idx in 0..Self::WORDS {
+            result.bits[idx] &= other.bits[idx];
+        }
+        result
+    }
+}
+
+impl Default for SyscallFilter {
+    fn default() -> Self {
+        Self::allow_all()
+    }
+}
+
 /// SysHandle represents a kernel object to the userspace.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[repr(C, align(8))]
@@ -28,7 +28,7 @@ pub use util::spin;
 
 extern crate alloc;
 
-use core::{ptr::copy_nonoverlapping, sync::atomic::Ordering};
+use core::sync::atomic::Ordering;
 use moto_rt::RtVdsoVtableV1;
 
 // The entry point.
@@ -307,6 +307,10 @@ pub extern "C" fn _rt_entry(version: u64) {
         vdso_unimplemented as *const () as usize as u64,
         Ordering::Relaxed,
     );
+    vtable.net_socket_addr.store(
+        rt_net::socket_addr as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
     vtable.net_peer_addr.store(
         rt_net::peer_addr as *const () as usize as u64,
         Ordering::Relaxed,
@@ -319,6 +323,101 @@ pub extern "C" fn _rt_entry(version: u64) {
         rt_net::getsockopt as *const () as usize as u64,
         Ordering::Relaxed,
     );
+    vtable.net_udp_recv_from.store(
+        rt_net::udp_recv_from as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.net_udp_peek_from.store(
+        rt_net::udp_peek_from as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.net_udp_send_to.store(
+        rt_net::udp_send_to as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.net_poll_new.store(
+        rt_net::poll_new as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.net_poll_ctl.store(
+        rt_net::poll_ctl as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.net_poll_wait.store(
+        rt_net::poll_wait as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.fs_sync_files.store(
+        rt_fs::sync_files as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.fs_set_dir_quota.store(
+        rt_fs::set_dir_quota as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.fs_get_dir_quota.store(
+        rt_fs::get_dir_quota as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.proc_on_panic.store(
+        rt_process::on_panic as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.fs_file_version.store(
+        rt_fs::file_version as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.fs_write_if_version.store(
+        rt_fs::write_if_version as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.proc_rusage.store(
+        rt_process::rusage as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.fs_statfs.store(
+        rt_fs::statfs as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.fs_preadv.store(
+        rt_fs::preadv as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.fs_pwritev.store(
+        rt_fs::pwritev as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.fs_snapshot.store(
+        rt_fs::snapshot as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.fs_release_snapshot.store(
+        rt_fs::release_snapshot as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.fs_writeback.store(
+        rt_fs::writeback as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable
+        .fs_pipe
+        .store(rt_fs::pipe as *const () as usize as u64, Ordering::Relaxed);
+    vtable.fs_defragment.store(
+        rt_fs::defragment as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.fs_barrier.store(
+        rt_fs::barrier as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.net_tcp_connect_from.store(
+        rt_net::tcp_connect_from as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
+    vtable.fs_set_queue_depth.store(
+        rt_fs::set_queue_depth as *const () as usize as u64,
+        Ordering::Relaxed,
+    );
 
     // The final fence.
     core::sync::atomic::fence(core::sync::atomic::Ordering::Release);
@@ -328,25 +427,11 @@ pub extern "C" fn _rt_entry(version: u64) {
 }
 
 pub extern "C" fn fill_random_bytes(ptr: *mut u8, size: usize) {
-    let mut curr_pos = 0_usize;
-    let mut remainder = size;
-    unsafe {
-        while remainder > 0 {
-            let mut val = 0_u64;
-            let _ = core::arch::x86_64::_rdrand64_step(&mut val);
-            if val == 0 {
-                panic!("rdrand64_step failed");
-            }
-            let to_copy = remainder.min(8);
-            copy_nonoverlapping(
-                &val as *const u64 as usize as *const u8,
-                ptr.add(curr_pos),
-                to_copy,
-            );
-            remainder -= to_copy;
-            curr_pos += to_copy;
-        }
+    if size == 0 {
+        return;
     }
+    let bytes = unsafe { core::slice::from_raw_parts_mut(ptr, size) };
+    moto_sys::SysMem::get_random(bytes).expect("SysMem::get_random() failed");
 }
 
 pub extern "C" fn vdso_unimplemented() {
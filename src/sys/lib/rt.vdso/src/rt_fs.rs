@@ -7,6 +7,7 @@ use alloc::borrow::ToOwned;
 use alloc::string::String;
 use alloc::string::ToString;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use moto_rt::error::*;
 use moto_rt::fs::*;
 use moto_rt::mutex::Mutex;
@@ -30,12 +31,36 @@ pub extern "C" fn duplicate(rt_fd: RtFd) -> RtFd {
 
     match fd.as_ref() {
         Fd::TcpListener(listener) => todo!(),
-        Fd::TcpStream(stream) =>
-            DESCRIPTORS.push(Arc::new(Fd::TcpStream(stream.clone()))),
+        Fd::TcpStream(stream) => stream.clone().new_fd(),
         _ => -(E_BAD_HANDLE as RtFd),
     }
 }
 
+pub extern "C" fn pipe(flags: u32, fds: *mut RtFd) -> ErrorCode {
+    use moto_ipc::sync_pipe::{make_pair, Pipe, Reader, Writer};
+    use moto_sys::SysHandle;
+
+    if flags & !PIPE_NONBLOCK != 0 {
+        return E_INVALID_ARGUMENT;
+    }
+
+    let (read_data, write_data) = match make_pair(SysHandle::SELF, SysHandle::SELF) {
+        Ok(pair) => pair,
+        Err(err) => return err,
+    };
+    let mut reader = Pipe::Reader(unsafe { Reader::new(read_data) });
+    let mut writer = Pipe::Writer(unsafe { Writer::new(write_data) });
+    if flags & PIPE_NONBLOCK != 0 {
+        reader.set_nonblocking(true);
+        writer.set_nonblocking(true);
+    }
+
+    let fds = unsafe { core::slice::from_raw_parts_mut(fds, 2) };
+    fds[0] = DESCRIPTORS.push(Arc::new(Fd::Pipe(crate::util::spin::Mutex::new(reader))));
+    fds[1] = DESCRIPTORS.push(Arc::new(Fd::Pipe(crate::util::spin::Mutex::new(writer))));
+    E_OK
+}
+
 pub extern "C" fn open(path_ptr: *const u8, path_size: usize, opts: u32) -> i32 {
     let path_bytes = unsafe { core::slice::from_raw_parts(path_ptr, path_size) };
     let path = unsafe { core::str::from_utf8_unchecked(path_bytes) };
@@ -59,7 +84,10 @@ pub extern "C" fn close(rt_fd: i32) -> ErrorCode {
             Ok(()) => E_OK,
             Err(err) => err,
         },
-        Fd::TcpListener(_) | Fd::TcpStream(_) =>
+        // The last fd referring to a stream closes it, and reports lingering
+        // errors (see moto_rt::net::SO_LINGER), whoever else holds the stream.
+        Fd::TcpStream(stream) => stream.fd_closed(),
+        Fd::TcpListener(_) | Fd::UdpSocket(_) | Fd::Poll(_) | Fd::Pipe(_) =>
             // drop will work
             E_OK,
         _ => panic!("fd {rt_fd} not a file"), // Can't just return an error, as we've popped the fd.
@@ -86,11 +114,224 @@ pub extern "C" fn get_file_attr(rt_fd: i32, attr: *mut FileAttr) -> ErrorCode {
 }
 
 pub extern "C" fn fsync(rt_fd: i32) -> ErrorCode {
-    E_OK
+    datasync(rt_fd)
 }
 
 pub extern "C" fn datasync(rt_fd: i32) -> ErrorCode {
-    E_OK
+    let Some(fd) = DESCRIPTORS.get(rt_fd) else {
+        return E_BAD_HANDLE;
+    };
+
+    // Writes through this fd may be flushed as they complete, but writes to the
+    // same file through O_WRITEBACK fds (maybe in other processes) are not.
+    match fd.as_ref() {
+        Fd::File(file) => match FsClient::sync_files(&[file.fd], 0) {
+            Ok(()) => E_OK,
+            Err(err) => err,
+        },
+        _ => E_OK,
+    }
+}
+
+pub unsafe extern "C" fn sync_files(
+    rt_fds: *const i32,
+    num_fds: usize,
+    parent_rt_fd: i32,
+) -> ErrorCode {
+    let rt_fds = core::slice::from_raw_parts(rt_fds, num_fds);
+
+    // Keep the descriptors alive until the request completes.
+    let mut descriptors = Vec::with_capacity(num_fds);
+    let mut fds = Vec::with_capacity(num_fds);
+    for rt_fd in rt_fds {
+        let Some(fd) = DESCRIPTORS.get(*rt_fd) else {
+            return E_BAD_HANDLE;
+        };
+        match fd.as_ref() {
+            Fd::File(file) => fds.push(file.fd),
+            _ => return E_BAD_HANDLE,
+        }
+        descriptors.push(fd);
+    }
+
+    let parent_fd = if parent_rt_fd < 0 {
+        0
+    } else {
+        let Some(fd) = DESCRIPTORS.get(parent_rt_fd) else {
+            return E_BAD_HANDLE;
+        };
+        let parent_fd = match fd.as_ref() {
+            Fd::ReadDir(rdir) => rdir.fd,
+            _ => return E_BAD_HANDLE,
+        };
+        descriptors.push(fd);
+        parent_fd
+    };
+
+    match FsClient::sync_files(fds.as_slice(), parent_fd) {
+        Ok(()) => E_OK,
+        Err(err) => err,
+    }
+}
+
+pub extern "C" fn set_dir_quota(
+    path_ptr: *const u8,
+    path_size: usize,
+    max_bytes: u64,
+    max_inodes: u64,
+) -> ErrorCode {
+    let path_bytes = unsafe { core::slice::from_raw_parts(path_ptr, path_size) };
+    let path = unsafe { core::str::from_utf8_unchecked(path_bytes) };
+    match FsClient::dir_quota(path, CMD_SET_DIR_QUOTA, max_bytes, max_inodes) {
+        Ok(_) => E_OK,
+        Err(err) => err,
+    }
+}
+
+pub extern "C" fn get_dir_quota(
+    path_ptr: *const u8,
+    path_size: usize,
+    quota: *mut DirQuota,
+) -> ErrorCode {
+    let path_bytes = unsafe { core::slice::from_raw_parts(path_ptr, path_size) };
+    let path = unsafe { core::str::from_utf8_unchecked(path_bytes) };
+    match FsClient::dir_quota(path, CMD_GET_DIR_QUOTA, 0, 0) {
+        Ok(q) => {
+            unsafe { *quota = q };
+            E_OK
+        }
+        Err(err) => err,
+    }
+}
+
+pub extern "C" fn statfs(path_ptr: *const u8, path_size: usize, statfs: *mut StatFs) -> ErrorCode {
+    let path_bytes = unsafe { core::slice::from_raw_parts(path_ptr, path_size) };
+    let path = unsafe { core::str::from_utf8_unchecked(path_bytes) };
+    match FsClient::statfs(path, 0, 0) {
+        Ok(s) => {
+            unsafe { *statfs = s };
+            E_OK
+        }
+        Err(err) => err,
+    }
+}
+
+pub extern "C" fn set_queue_depth(path_ptr: *const u8, path_size: usize, depth: u32) -> ErrorCode {
+    let path_bytes = unsafe { core::slice::from_raw_parts(path_ptr, path_size) };
+    let path = unsafe { core::str::from_utf8_unchecked(path_bytes) };
+    match FsClient::statfs(path, StatFsRequest::F_SET_QUEUE_DEPTH, depth) {
+        Ok(_) => E_OK,
+        Err(err) => err,
+    }
+}
+
+pub extern "C" fn snapshot(path_ptr: *const u8, path_size: usize, id: *mut u64) -> ErrorCode {
+    let path_bytes = unsafe { core::slice::from_raw_parts(path_ptr, path_size) };
+    let path = unsafe { core::str::from_utf8_unchecked(path_bytes) };
+    match FsClient::snapshot(path, CMD_SNAPSHOT, 0) {
+        Ok(snapshot_id) => {
+            unsafe { *id = snapshot_id };
+            E_OK
+        }
+        Err(err) => err,
+    }
+}
+
+pub extern "C" fn release_snapshot(id: u64) -> ErrorCode {
+    match FsClient::snapshot("/", CMD_RELEASE_SNAPSHOT, id) {
+        Ok(_) => E_OK,
+        Err(err) => err,
+    }
+}
+
+pub extern "C" fn writeback(
+    op: u32,
+    policy: *const WritebackPolicy,
+    stats: *mut WritebackStats,
+) -> ErrorCode {
+    let flags = match op {
+        WRITEBACK_OP_STATS => 0,
+        WRITEBACK_OP_SET_POLICY => WritebackRequest::F_SET_POLICY,
+        WRITEBACK_OP_FORCE => WritebackRequest::F_FORCE,
+        _ => return moto_rt::E_INVALID_ARGUMENT,
+    };
+    match FsClient::writeback(flags, unsafe { &*policy }) {
+        Ok(s) => {
+            unsafe { *stats = s };
+            E_OK
+        }
+        Err(err) => err,
+    }
+}
+
+pub extern "C" fn barrier() -> ErrorCode {
+    match FsClient::barrier() {
+        Ok(()) => E_OK,
+        Err(err) => err,
+    }
+}
+
+pub extern "C" fn file_version(rt_fd: i32, version: *mut u64) -> ErrorCode {
+    let Some(fd) = DESCRIPTORS.get(rt_fd) else {
+        return E_BAD_HANDLE;
+    };
+
+    match fd.as_ref() {
+        Fd::File(file) => match FsClient::file_version(file) {
+            Ok(v) => {
+                unsafe { *version = v };
+                E_OK
+            }
+            Err(err) => err,
+        },
+        _ => E_BAD_HANDLE,
+    }
+}
+
+pub extern "C" fn write_if_version(
+    rt_fd: i32,
+    buf: *const u8,
+    buf_sz: usize,
+    expected_version: u64,
+    version: *mut u64,
+) -> ErrorCode {
+    let Some(fd) = DESCRIPTORS.get(rt_fd) else {
+        return E_BAD_HANDLE;
+    };
+
+    let buf = unsafe { core::slice::from_raw_parts(buf, buf_sz) };
+    match fd.as_ref() {
+        Fd::File(file) => match FsClient::write_if_version(file, buf, expected_version) {
+            Ok(v) => {
+                unsafe { *version = v };
+                E_OK
+            }
+            Err(err) => err,
+        },
+        _ => E_BAD_HANDLE,
+    }
+}
+
+pub extern "C" fn defragment(rt_fd: i32, start: u32, extents: *mut FileExtents) -> ErrorCode {
+    let Some(fd) = DESCRIPTORS.get(rt_fd) else {
+        return E_BAD_HANDLE;
+    };
+
+    let flags = match start {
+        0 => 0,
+        1 => DefragmentRequest::F_START,
+        _ => return E_INVALID_ARGUMENT,
+    };
+    match fd.as_ref() {
+        Fd::File(file) => match FsClient::defragment(file, flags) {
+            Ok(e) => {
+                unsafe { *extents = e };
+                E_OK
+            }
+            Err(err) => err,
+        },
+        _ => E_BAD_HANDLE,
+    }
 }
 
 pub extern "C" fn truncate(rt_fd: i32, size: u64) -> ErrorCode {
@@ -116,6 +357,7 @@ pub extern "C" fn read(rt_fd: i32, buf: *mut u8, buf_sz: usize) -> i64 {
         },
         Fd::Pipe(pipe) => match pipe.lock().read(buf) {
             Ok(sz) => sz as i64,
+            Err(E_BROKEN_PIPE) => 0, // EOF: the writer is gone, and everything is read.
             Err(err) => -(err as i64),
         },
         Fd::ReadDir(read_dir) => -(E_BAD_HANDLE as i64),
@@ -124,6 +366,8 @@ pub extern "C" fn read(rt_fd: i32, buf: *mut u8, buf_sz: usize) -> i64 {
             Err(err) => -(err as i64),
         },
         Fd::TcpListener(_) => -(E_BAD_HANDLE as i64),
+        Fd::UdpSocket(_) => -(E_BAD_HANDLE as i64),
+        Fd::Poll(_) => -(E_BAD_HANDLE as i64),
     }
 }
 
@@ -154,6 +398,38 @@ pub extern "C" fn write(rt_fd: i32, buf: *const u8, buf_sz: usize) -> i64 {
             Err(err) => -(err as i64),
         },
         Fd::TcpListener(_) => -(E_BAD_HANDLE as i64),
+        Fd::UdpSocket(_) => -(E_BAD_HANDLE as i64),
+        Fd::Poll(_) => -(E_BAD_HANDLE as i64),
+    }
+}
+
+pub extern "C" fn preadv(rt_fd: i32, iov: *const IoVec, iov_cnt: usize, offset: u64) -> i64 {
+    let Some(fd) = DESCRIPTORS.get(rt_fd) else {
+        return -(E_BAD_HANDLE as i64);
+    };
+
+    let iov = unsafe { core::slice::from_raw_parts(iov, iov_cnt) };
+    match fd.as_ref() {
+        Fd::File(file) => match FsClient::read_at(file, iov, offset) {
+            Ok(sz) => sz as i64,
+            Err(err) => -(err as i64),
+        },
+        _ => -(E_BAD_HANDLE as i64),
+    }
+}
+
+pub extern "C" fn pwritev(rt_fd: i32, iov: *const IoVec, iov_cnt: usize, offset: u64) -> i64 {
+    let Some(fd) = DESCRIPTORS.get(rt_fd) else {
+        return -(E_BAD_HANDLE as i64);
+    };
+
+    let iov = unsafe { core::slice::from_raw_parts(iov, iov_cnt) };
+    match fd.as_ref() {
+        Fd::File(file) => match FsClient::write_at(file, iov, offset) {
+            Ok(sz) => sz as i64,
+            Err(err) => -(err as i64),
+        },
+        _ => -(E_BAD_HANDLE as i64),
     }
 }
 
@@ -376,6 +652,18 @@ pub struct File {
     abs_path: String,
     fd: u64,
     pos: AtomicU64, // Atomic because read operations take &File, but change pos.
+    // Opened with O_WRITEBACK.
+    writeback: bool,
+}
+
+impl File {
+    fn write_flags(&self) -> u32 {
+        if self.writeback {
+            FileWriteRequest::F_NO_FLUSH
+        } else {
+            0
+        }
+    }
 }
 
 // Given a path str from the user, figure out the absolute path, filename, etc.
@@ -637,7 +925,8 @@ impl FsClient {
             let req = raw_channel.get_mut::<FileOpenRequest>();
             req.header.cmd = CMD_FILE_OPEN;
             req.header.ver = 0;
-            req.header.flags = opts;
+            // O_WRITEBACK is per fd; the driver only sees its writes' flags.
+            req.header.flags = opts & !O_WRITEBACK;
             req.parent_fd = 0;
 
             req.fname_size = c_path.abs_path.as_bytes().len() as u16;
@@ -659,6 +948,7 @@ impl FsClient {
             abs_path: c_path.abs_path,
             fd: resp.fd,
             pos: AtomicU64::new(0),
+            writeback: (opts & O_WRITEBACK) != 0,
         })
     }
 
@@ -767,7 +1057,7 @@ impl FsClient {
             let req = raw_channel.get_mut::<FileWriteRequest>();
             req.header.cmd = CMD_FILE_WRITE;
             req.header.ver = 0;
-            req.header.flags = 0;
+            req.header.flags = file.write_flags();
             req.fd = file.fd;
             req.offset = file.pos.load(Ordering::Relaxed);
 
@@ -792,6 +1082,205 @@ impl FsClient {
         Ok(resp.written as usize)
     }
 
+    // Vectored reads and writes at an explicit offset (the file position
+    // is not used or changed): the segments are gathered into (scattered
+    // from) a single request, so that sys-io issues one device request.
+    fn read_at(file: &File, iov: &[IoVec], offset: u64) -> Result<usize, ErrorCode> {
+        let total: usize = iov.iter().map(|seg| seg.len).sum();
+        if total == 0 {
+            return Ok(0);
+        }
+
+        let mut conn = Self::get()?.conn.lock();
+        let raw_channel = conn.raw_channel();
+        unsafe {
+            let req = raw_channel.get_mut::<FileReadRequest>();
+            req.header.cmd = CMD_FILE_READ;
+            req.header.ver = 0;
+            req.fd = file.fd;
+            req.offset = offset;
+            req.max_bytes = total.min(raw_channel.size()) as u32;
+        }
+
+        conn.do_rpc(None)?;
+
+        let resp = unsafe { raw_channel.get::<FileReadResponse>() };
+        if resp.header.result != 0 {
+            return Err(resp.header.result);
+        }
+
+        let result_sz = total.min(resp.size as usize);
+        let bytes = unsafe { raw_channel.get_bytes(resp.data.as_ptr(), result_sz)? };
+        let mut copied = 0;
+        for seg in iov {
+            if copied == result_sz {
+                break;
+            }
+            let sz = seg.len.min(result_sz - copied);
+            unsafe {
+                core::intrinsics::copy_nonoverlapping(
+                    bytes[copied..].as_ptr(),
+                    seg.base as *mut u8,
+                    sz,
+                );
+            }
+            copied += sz;
+        }
+
+        Ok(result_sz)
+    }
+
+    fn write_at(file: &File, iov: &[IoVec], offset: u64) -> Result<usize, ErrorCode> {
+        let total: usize = iov.iter().map(|seg| seg.len).sum();
+        if total == 0 {
+            return Err(moto_rt::E_INVALID_ARGUMENT); // As in write().
+        }
+
+        let mut conn = Self::get()?.conn.lock();
+        let raw_channel = conn.raw_channel();
+        unsafe {
+            let req = raw_channel.get_mut::<FileWriteRequest>();
+            req.header.cmd = CMD_FILE_WRITE;
+            req.header.ver = 0;
+            req.header.flags = file.write_flags();
+            req.fd = file.fd;
+            req.offset = offset;
+
+            let size = (raw_channel.size() - core::mem::size_of::<FileWriteRequest>()).min(total);
+            req.size = size as u32;
+
+            let mut copied = 0;
+            for seg in iov {
+                if copied == size {
+                    break;
+                }
+                let sz = seg.len.min(size - copied);
+                let src = core::slice::from_raw_parts(seg.base as *const u8, sz);
+                raw_channel.put_bytes(src, req.data.as_mut_ptr().add(copied))?;
+                copied += sz;
+            }
+        }
+
+        conn.do_rpc(None)?;
+
+        let resp = unsafe { raw_channel.get::<FileWriteResponse>() };
+        if resp.header.result != 0 {
+            return Err(resp.header.result);
+        }
+
+        Ok(resp.written as usize)
+    }
+
+    fn file_version(file: &File) -> Result<u64, ErrorCode> {
+        let mut conn = Self::get()?.conn.lock();
+        let raw_channel = conn.raw_channel();
+        unsafe {
+            let req = raw_channel.get_mut::<FileVersionRequest>();
+            req.header.cmd = CMD_FILE_VERSION;
+            req.header.ver = 0;
+            req.header.flags = 0;
+            req.fd = file.fd;
+        }
+
+        conn.do_rpc(None)?;
+
+        let resp = unsafe { raw_channel.get::<FileVersionResponse>() };
+        if resp.header.result != 0 {
+            return Err(resp.header.result);
+        }
+
+        Ok(resp.version)
+    }
+
+    fn write_if_version(file: &File, buf: &[u8], expected_version: u64) -> Result<u64, ErrorCode> {
+        if buf.len() == 0 {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+        let mut conn = Self::get()?.conn.lock();
+        let raw_channel = conn.raw_channel();
+        unsafe {
+            // All or nothing: the write is not split.
+            if buf.len() > raw_channel.size() - core::mem::size_of::<FileWriteIfVersionRequest>() {
+                return Err(moto_rt::E_INVALID_ARGUMENT);
+            }
+
+            let req = raw_channel.get_mut::<FileWriteIfVersionRequest>();
+            req.header.cmd = CMD_FILE_WRITE_IF_VERSION;
+            req.header.ver = 0;
+            req.header.flags = 0;
+            req.fd = file.fd;
+            req.offset = file.pos.load(Ordering::Relaxed);
+            req.expected_version = expected_version;
+            req.size = buf.len() as u32;
+
+            raw_channel.put_bytes(buf, req.data.as_mut_ptr())?;
+        }
+
+        conn.do_rpc(None)?;
+
+        let resp = unsafe { raw_channel.get::<FileWriteIfVersionResponse>() };
+        if resp.header.result != 0 {
+            return Err(resp.header.result);
+        }
+
+        file.pos.fetch_add(buf.len() as u64, Ordering::Relaxed);
+        Ok(resp.version)
+    }
+
+    fn defragment(file: &File, flags: u32) -> Result<FileExtents, ErrorCode> {
+        let mut conn = Self::get()?.conn.lock();
+        let raw_channel = conn.raw_channel();
+        unsafe {
+            let req = raw_channel.get_mut::<DefragmentRequest>();
+            req.header.cmd = CMD_DEFRAGMENT;
+            req.header.ver = 0;
+            req.header.flags = flags;
+            req.fd = file.fd;
+        }
+
+        conn.do_rpc(None)?;
+
+        let resp = unsafe { raw_channel.get::<DefragmentResponse>() };
+        if resp.header.result != 0 {
+            return Err(resp.header.result);
+        }
+
+        Ok(resp.extents)
+    }
+
+    fn sync_files(fds: &[u64], parent_fd: u64) -> Result<(), ErrorCode> {
+        let mut conn = Self::get()?.conn.lock();
+        let raw_channel = conn.raw_channel();
+        unsafe {
+            if fds.len() > SyncFilesRequest::max_fds(&raw_channel) {
+                return Err(moto_rt::E_INVALID_ARGUMENT);
+            }
+
+            let req = raw_channel.get_mut::<SyncFilesRequest>();
+            req.header.cmd = CMD_SYNC_FILES;
+            req.header.ver = 0;
+            req.header.flags = 0;
+            req.parent_fd = parent_fd;
+            req.num_fds = fds.len() as u32;
+            req._reserved = 0;
+
+            let bytes = core::slice::from_raw_parts(
+                fds.as_ptr() as *const u8,
+                fds.len() * core::mem::size_of::<u64>(),
+            );
+            raw_channel.put_bytes(bytes, req.fds.as_mut_ptr() as *mut u8)?;
+        }
+
+        conn.do_rpc(None)?;
+
+        let resp = unsafe { raw_channel.get::<SyncFilesResponse>() };
+        if resp.header.result != 0 {
+            return Err(resp.header.result);
+        }
+
+        Ok(())
+    }
+
     fn readdir(path: &str) -> Result<ReadDir, ErrorCode> {
         let c_path = CanonicalPath::parse(path)?;
         let mut conn = Self::get()?.conn.lock();
@@ -885,6 +1374,133 @@ impl FsClient {
         Ok(resp.attr)
     }
 
+    fn dir_quota(
+        path: &str,
+        cmd: u16,
+        max_bytes: u64,
+        max_inodes: u64,
+    ) -> Result<DirQuota, ErrorCode> {
+        let c_path = CanonicalPath::parse(path)?;
+        let mut conn = Self::get()?.conn.lock();
+        let raw_channel = conn.raw_channel();
+
+        unsafe {
+            let req = raw_channel.get_mut::<DirQuotaRequest>();
+            req.header.cmd = cmd;
+            req.header.ver = 0;
+            req.header.flags = 0;
+            req.max_bytes = max_bytes;
+            req.max_inodes = max_inodes;
+
+            req.fname_size = c_path.abs_path.as_bytes().len() as u16;
+            raw_channel.put_bytes(c_path.abs_path.as_bytes(), req.fname.as_mut_ptr())?;
+        }
+
+        conn.do_rpc(None)?;
+
+        let resp = unsafe { raw_channel.get::<DirQuotaResponse>() };
+        if resp.header.result != 0 {
+            return Err(resp.header.result);
+        }
+
+        Ok(resp.quota)
+    }
+
+    fn statfs(path: &str, flags: u32, queue_depth: u32) -> Result<StatFs, ErrorCode> {
+        let c_path = CanonicalPath::parse(path)?;
+        let mut conn = Self::get()?.conn.lock();
+        let raw_channel = conn.raw_channel();
+
+        unsafe {
+            let req = raw_channel.get_mut::<StatFsRequest>();
+            req.header.cmd = CMD_STATFS;
+            req.header.ver = 0;
+            req.header.flags = flags;
+            req.queue_depth = queue_depth;
+
+            req.fname_size = c_path.abs_path.as_bytes().len() as u16;
+            raw_channel.put_bytes(c_path.abs_path.as_bytes(), req.fname.as_mut_ptr())?;
+        }
+
+        conn.do_rpc(None)?;
+
+        let resp = unsafe { raw_channel.get::<StatFsResponse>() };
+        if resp.header.result != 0 {
+            return Err(resp.header.result);
+        }
+
+        Ok(resp.statfs)
+    }
+
+    fn snapshot(path: &str, cmd: u16, id: u64) -> Result<u64, ErrorCode> {
+        let c_path = CanonicalPath::parse(path)?;
+        let mut conn = Self::get()?.conn.lock();
+        let raw_channel = conn.raw_channel();
+
+        unsafe {
+            let req = raw_channel.get_mut::<SnapshotRequest>();
+            req.header.cmd = cmd;
+            req.header.ver = 0;
+            req.header.flags = 0;
+            req.id = id;
+
+            req.fname_size = c_path.abs_path.as_bytes().len() as u16;
+            raw_channel.put_bytes(c_path.abs_path.as_bytes(), req.fname.as_mut_ptr())?;
+        }
+
+        conn.do_rpc(None)?;
+
+        let resp = unsafe { raw_channel.get::<SnapshotResponse>() };
+        if resp.header.result != 0 {
+            return Err(resp.header.result);
+        }
+
+        Ok(resp.id)
+    }
+
+    fn writeback(flags: u32, policy: &WritebackPolicy) -> Result<WritebackStats, ErrorCode> {
+        let mut conn = Self::get()?.conn.lock();
+        let raw_channel = conn.raw_channel();
+
+        unsafe {
+            let req = raw_channel.get_mut::<WritebackRequest>();
+            req.header.cmd = CMD_WRITEBACK;
+            req.header.ver = 0;
+            req.header.flags = flags;
+            req.policy = *policy;
+        }
+
+        conn.do_rpc(None)?;
+
+        let resp = unsafe { raw_channel.get::<WritebackResponse>() };
+        if resp.header.result != 0 {
+            return Err(resp.header.result);
+        }
+
+        Ok(resp.stats)
+    }
+
+    fn barrier() -> Result<(), ErrorCode> {
+        let mut conn = Self::get()?.conn.lock();
+        let raw_channel = conn.raw_channel();
+
+        unsafe {
+            let req = raw_channel.get_mut::<BarrierRequest>();
+            req.header.cmd = CMD_BARRIER;
+            req.header.ver = 0;
+            req.header.flags = 0;
+        }
+
+        conn.do_rpc(None)?;
+
+        let resp = unsafe { raw_channel.get::<BarrierResponse>() };
+        if resp.header.result != 0 {
+            return Err(resp.header.result);
+        }
+
+        Ok(())
+    }
+
     fn mkdir(path: &str) -> Result<(), ErrorCode> {
         let c_path = CanonicalPath::parse(path)?;
         let mut conn = Self::get()?.conn.lock();
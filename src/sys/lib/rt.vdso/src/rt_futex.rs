@@ -111,7 +111,9 @@ impl WaitQueue {
             }
         }
 
-        let timed_out = match SysCpu::wait(&mut [], SysHandle::NONE, SysHandle::NONE, *timeout) {
+        let result =
+            SysCpu::wait_uninterruptible(&mut [], SysHandle::NONE, SysHandle::NONE, *timeout);
+        let timed_out = match result {
             Ok(()) => false,
             Err(err) => {
                 assert_eq!(err, moto_rt::E_TIMED_OUT);
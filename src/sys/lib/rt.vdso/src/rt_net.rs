@@ -42,11 +42,23 @@ pub unsafe extern "C" fn dns_lookup(
 }
 
 pub extern "C" fn bind(proto: u8, addr: *const netc::sockaddr) -> RtFd {
-    if proto != moto_rt::net::PROTO_TCP {
+    if proto == moto_rt::net::PROTO_UDP {
+        let addr = unsafe { (*addr).into() };
+        return match UdpSocket::bind(&addr) {
+            Ok(socket) => DESCRIPTORS.push(alloc::sync::Arc::new(Fd::UdpSocket(socket))),
+            Err(err) => -(err as RtFd),
+        };
+    }
+
+    let exclusive_addr = (proto & moto_rt::net::BIND_EXCLUSIVEADDR) != 0;
+    let reuse_port = (proto & moto_rt::net::BIND_REUSEPORT) != 0;
+    if (proto & !(moto_rt::net::BIND_EXCLUSIVEADDR | moto_rt::net::BIND_REUSEPORT))
+        != moto_rt::net::PROTO_TCP
+    {
         return -(E_NOT_IMPLEMENTED as RtFd);
     }
     let addr = unsafe { (*addr).into() };
-    let listener = match TcpListener::bind(&addr) {
+    let listener = match TcpListener::bind(&addr, exclusive_addr, reuse_port) {
         Ok(x) => x,
         Err(err) => return -(err as RtFd),
     };
@@ -68,7 +80,7 @@ pub extern "C" fn accept(listener: RtFd, peer_addr: *mut netc::sockaddr) -> RtFd
         Ok(x) => x,
         Err(err) => return -(err as RtFd),
     };
-    let stream = DESCRIPTORS.push(alloc::sync::Arc::new(Fd::TcpStream(stream)));
+    let stream = stream.new_fd();
     unsafe {
         *peer_addr = addr.into();
     }
@@ -82,11 +94,30 @@ pub extern "C" fn tcp_connect(addr: *const netc::sockaddr, timeout_ns: u64) -> R
     } else {
         Some(Duration::from_nanos(timeout_ns))
     };
-    let stream = match TcpStream::connect(&addr, timeout) {
+    let stream = match TcpStream::connect(&addr, None, timeout) {
+        Ok(x) => x,
+        Err(err) => return -(err as RtFd),
+    };
+    stream.new_fd()
+}
+
+pub extern "C" fn tcp_connect_from(
+    local_addr: *const netc::sockaddr,
+    addr: *const netc::sockaddr,
+    timeout_ns: u64,
+) -> RtFd {
+    let local_addr = unsafe { (*local_addr).into() };
+    let addr = unsafe { (*addr).into() };
+    let timeout = if timeout_ns == u64::MAX {
+        None
+    } else {
+        Some(Duration::from_nanos(timeout_ns))
+    };
+    let stream = match TcpStream::connect(&addr, Some(&local_addr), timeout) {
         Ok(x) => x,
         Err(err) => return -(err as RtFd),
     };
-    DESCRIPTORS.push(alloc::sync::Arc::new(Fd::TcpStream(stream)))
+    stream.new_fd()
 }
 
 pub unsafe extern "C" fn setsockopt(rt_fd: RtFd, option: u64, ptr: usize, len: usize) -> ErrorCode {
@@ -96,6 +127,43 @@ pub unsafe extern "C" fn setsockopt(rt_fd: RtFd, option: u64, ptr: usize, len: u
         return E_BAD_HANDLE;
     };
 
+    if let Fd::TcpListener(listener) = fd.as_ref() {
+        return match option {
+            moto_rt::net::SO_LISTENER_DRAIN => listener.drain(),
+            moto_rt::net::SO_IDLE_TIMEOUT => {
+                assert_eq!(len, core::mem::size_of::<u64>());
+                listener.set_idle_timeout(*(ptr as *const u64))
+            }
+            moto_rt::net::SO_LISTENER_FIN_ON_CLOSE => {
+                assert_eq!(len, 1);
+                let fin = *(ptr as *const u8);
+                listener.fin_on_close.store(fin != 0, Ordering::Relaxed);
+                moto_rt::E_OK
+            }
+            _ => E_NOT_IMPLEMENTED,
+        };
+    }
+
+    if let Fd::UdpSocket(udp_socket) = fd.as_ref() {
+        return match option {
+            moto_rt::net::SO_RCVTIMEO => {
+                assert_eq!(len, core::mem::size_of::<u64>());
+                let timeout = *(ptr as *const u64);
+                udp_socket.rx_timeout_ns.store(timeout, Ordering::Relaxed);
+                moto_rt::E_OK
+            }
+            moto_rt::net::SO_DONT_FRAG => {
+                assert_eq!(len, 1);
+                let dont_frag = *(ptr as *const u8);
+                udp_socket
+                    .dont_frag
+                    .store(dont_frag != 0, Ordering::Relaxed);
+                moto_rt::E_OK
+            }
+            _ => E_NOT_IMPLEMENTED,
+        };
+    }
+
     let Fd::TcpStream(tcp_stream) = fd.as_ref() else {
         return E_BAD_HANDLE;
     };
@@ -130,6 +198,24 @@ pub unsafe extern "C" fn setsockopt(rt_fd: RtFd, option: u64, ptr: usize, len: u
             let ttl = *(ptr as *const u32);
             tcp_stream.set_ttl(ttl)
         }
+        moto_rt::net::SO_IDLE_TIMEOUT => {
+            assert_eq!(len, core::mem::size_of::<u64>());
+            tcp_stream.set_idle_timeout(*(ptr as *const u64))
+        }
+        moto_rt::net::SO_RX_PAUSED => {
+            assert_eq!(len, 1);
+            let paused = *(ptr as *const u8);
+            tcp_stream.set_rx_paused(paused != 0)
+        }
+        moto_rt::net::SO_URGENT_SEND => {
+            assert_eq!(len, 1);
+            let byte = *(ptr as *const u8);
+            tcp_stream.send_urgent(byte)
+        }
+        moto_rt::net::SO_LINGER => {
+            assert_eq!(len, core::mem::size_of::<u64>());
+            tcp_stream.set_linger(*(ptr as *const u64))
+        }
         _ => panic!("unrecognized option {option}"),
     }
 }
@@ -141,6 +227,48 @@ pub unsafe extern "C" fn getsockopt(rt_fd: RtFd, option: u64, ptr: usize, len: u
         return E_BAD_HANDLE;
     };
 
+    if let Fd::TcpListener(listener) = fd.as_ref() {
+        return match option {
+            moto_rt::net::SO_RX_AVAILABLE => {
+                assert_eq!(len, core::mem::size_of::<u64>());
+                match listener.pending_accepts() {
+                    Ok(pending) => {
+                        *(ptr as *mut u64) = pending;
+                        moto_rt::E_OK
+                    }
+                    Err(err) => err,
+                }
+            }
+            moto_rt::net::SO_LISTENER_ACCEPTED => {
+                assert_eq!(len, core::mem::size_of::<u64>());
+                match listener.accepted() {
+                    Ok(accepted) => {
+                        *(ptr as *mut u64) = accepted;
+                        moto_rt::E_OK
+                    }
+                    Err(err) => err,
+                }
+            }
+            _ => E_NOT_IMPLEMENTED,
+        };
+    }
+
+    if let Fd::UdpSocket(udp_socket) = fd.as_ref() {
+        return match option {
+            moto_rt::net::SO_RCVTIMEO => {
+                assert_eq!(len, core::mem::size_of::<u64>());
+                *(ptr as *mut u64) = udp_socket.rx_timeout_ns.load(Ordering::Relaxed);
+                moto_rt::E_OK
+            }
+            moto_rt::net::SO_DONT_FRAG => {
+                assert_eq!(len, 1);
+                *(ptr as *mut u8) = udp_socket.dont_frag.load(Ordering::Relaxed) as u8;
+                moto_rt::E_OK
+            }
+            _ => E_NOT_IMPLEMENTED,
+        };
+    }
+
     let Fd::TcpStream(tcp_stream) = fd.as_ref() else {
         return E_BAD_HANDLE;
     };
@@ -178,6 +306,51 @@ pub unsafe extern "C" fn getsockopt(rt_fd: RtFd, option: u64, ptr: usize, len: u
                 Err(err) => err,
             }
         }
+        moto_rt::net::SO_PEER_CREDS => {
+            assert_eq!(len, core::mem::size_of::<[u64; 2]>());
+            match tcp_stream.peer_credentials() {
+                Ok(creds) => {
+                    *(ptr as *mut [u64; 2]) = creds;
+                    moto_rt::E_OK
+                }
+                Err(err) => err,
+            }
+        }
+        moto_rt::net::SO_RX_PAUSED => {
+            assert_eq!(len, 1);
+            match tcp_stream.rx_paused() {
+                Ok(paused) => {
+                    *(ptr as *mut u8) = paused;
+                    moto_rt::E_OK
+                }
+                Err(err) => err,
+            }
+        }
+        moto_rt::net::SO_URGENT_MARK => {
+            assert_eq!(len, core::mem::size_of::<u64>());
+            match tcp_stream.urgent_mark() {
+                Ok(mark) => {
+                    *(ptr as *mut u64) = mark;
+                    moto_rt::E_OK
+                }
+                Err(err) => err,
+            }
+        }
+        moto_rt::net::SO_LINGER => {
+            assert_eq!(len, core::mem::size_of::<u64>());
+            *(ptr as *mut u64) = tcp_stream.linger();
+            moto_rt::E_OK
+        }
+        moto_rt::net::SO_RX_AVAILABLE => {
+            assert_eq!(len, core::mem::size_of::<u64>());
+            match tcp_stream.rx_available() {
+                Ok(available) => {
+                    *(ptr as *mut u64) = available;
+                    moto_rt::E_OK
+                }
+                Err(err) => err,
+            }
+        }
         _ => panic!("unrecognized option {option}"),
     }
 }
@@ -197,6 +370,165 @@ pub unsafe extern "C" fn peer_addr(rt_fd: RtFd, addr: *mut netc::sockaddr) -> Er
     E_OK
 }
 
+pub unsafe extern "C" fn socket_addr(rt_fd: RtFd, addr: *mut netc::sockaddr) -> ErrorCode {
+    let fd = if let Some(fd) = DESCRIPTORS.get(rt_fd) {
+        fd
+    } else {
+        return E_BAD_HANDLE;
+    };
+
+    let socket_addr = match fd.as_ref() {
+        Fd::TcpStream(tcp_stream) => tcp_stream.socket_addr(),
+        Fd::TcpListener(listener) => listener.socket_addr(),
+        Fd::UdpSocket(udp_socket) => Ok(udp_socket.local_addr),
+        _ => Err(E_BAD_HANDLE),
+    };
+    match socket_addr {
+        Ok(socket_addr) => {
+            *addr = socket_addr.into();
+            E_OK
+        }
+        Err(err) => err,
+    }
+}
+
+unsafe fn udp_recv(
+    rt_fd: RtFd,
+    buf: *mut u8,
+    buf_sz: usize,
+    addr: *mut netc::sockaddr,
+    peek: bool,
+) -> i64 {
+    let Some(fd) = DESCRIPTORS.get(rt_fd) else {
+        return -(E_BAD_HANDLE as i64);
+    };
+    let Fd::UdpSocket(udp_socket) = fd.as_ref() else {
+        return -(E_BAD_HANDLE as i64);
+    };
+
+    let buf = core::slice::from_raw_parts_mut(buf, buf_sz);
+    match udp_socket.recv_from(buf, peek) {
+        Ok((sz, from)) => {
+            *addr = from.into();
+            sz as i64
+        }
+        Err(err) => -(err as i64),
+    }
+}
+
+pub unsafe extern "C" fn udp_recv_from(
+    rt_fd: RtFd,
+    buf: *mut u8,
+    buf_sz: usize,
+    addr: *mut netc::sockaddr,
+) -> i64 {
+    udp_recv(rt_fd, buf, buf_sz, addr, false)
+}
+
+pub unsafe extern "C" fn udp_peek_from(
+    rt_fd: RtFd,
+    buf: *mut u8,
+    buf_sz: usize,
+    addr: *mut netc::sockaddr,
+) -> i64 {
+    udp_recv(rt_fd, buf, buf_sz, addr, true)
+}
+
+pub unsafe extern "C" fn udp_send_to(
+    rt_fd: RtFd,
+    buf: *const u8,
+    buf_sz: usize,
+    addr: *const netc::sockaddr,
+) -> i64 {
+    let Some(fd) = DESCRIPTORS.get(rt_fd) else {
+        return -(E_BAD_HANDLE as i64);
+    };
+    let Fd::UdpSocket(udp_socket) = fd.as_ref() else {
+        return -(E_BAD_HANDLE as i64);
+    };
+
+    let buf = core::slice::from_raw_parts(buf, buf_sz);
+    match udp_socket.send_to(buf, &(*addr).into()) {
+        Ok(sz) => sz as i64,
+        Err(err) => -(err as i64),
+    }
+}
+
+pub extern "C" fn poll_new() -> RtFd {
+    DESCRIPTORS.push(alloc::sync::Arc::new(Fd::Poll(Poll::new())))
+}
+
+pub extern "C" fn poll_ctl(
+    poll_fd: RtFd,
+    op: u32,
+    source_fd: RtFd,
+    token: u64,
+    interests: u64,
+) -> ErrorCode {
+    let fd = if let Some(fd) = DESCRIPTORS.get(poll_fd) {
+        fd
+    } else {
+        return E_BAD_HANDLE;
+    };
+
+    let Fd::Poll(poll) = fd.as_ref() else {
+        return E_BAD_HANDLE;
+    };
+
+    let source = if let Some(fd) = DESCRIPTORS.get(source_fd) {
+        fd
+    } else {
+        return E_BAD_HANDLE;
+    };
+
+    let source = match PollSource::from_fd(source.as_ref()) {
+        Ok(source) => source,
+        Err(err) => return err,
+    };
+
+    let res = match op {
+        moto_rt::net::POLL_CTL_ADD => poll.add(source_fd, source, token, interests),
+        moto_rt::net::POLL_CTL_MOD => poll.modify(source_fd, &source, token, interests),
+        moto_rt::net::POLL_CTL_DEL => poll.delete(source_fd, &source),
+        _ => Err(E_INVALID_ARGUMENT),
+    };
+
+    match res {
+        Ok(()) => E_OK,
+        Err(err) => err,
+    }
+}
+
+pub unsafe extern "C" fn poll_wait(
+    poll_fd: RtFd,
+    events: *mut moto_rt::net::PollEvent,
+    num_events: usize,
+    timeout_ns: u64,
+) -> i32 {
+    let fd = if let Some(fd) = DESCRIPTORS.get(poll_fd) {
+        fd
+    } else {
+        return -(E_BAD_HANDLE as i32);
+    };
+
+    let Fd::Poll(poll) = fd.as_ref() else {
+        return -(E_BAD_HANDLE as i32);
+    };
+
+    if num_events == 0 {
+        return -(E_INVALID_ARGUMENT as i32);
+    }
+    let events = core::slice::from_raw_parts_mut(events, num_events);
+
+    let timeout = if timeout_ns == u64::MAX {
+        None
+    } else {
+        Some(Instant::now() + Duration::from_nanos(timeout_ns))
+    };
+
+    poll.wait(events, timeout) as i32
+}
+
 // -------------------------------- implementation details ------------------------------ //
 
 // Note: we have an IO thread per net channel instead of a single IO thread:
@@ -219,6 +551,7 @@ pub unsafe extern "C" fn peer_addr(rt_fd: RtFd, addr: *mut netc::sockaddr) -> Er
 //       for performance and robustness.
 
 use alloc::collections::BTreeMap;
+use alloc::collections::BTreeSet;
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 use alloc::sync::Weak;
@@ -286,6 +619,7 @@ struct NetChannel {
     // owns tcp streams, and we want to clear things away when the user drops them.
     tcp_streams: Mutex<BTreeMap<u64, Weak<TcpStream>>>,
     tcp_listeners: Mutex<BTreeMap<u64, Weak<TcpListener>>>,
+    udp_sockets: Mutex<BTreeMap<u64, Weak<UdpSocket>>>,
 
     next_msg_id: CachePadded<AtomicU64>, // A counter.
 
@@ -351,7 +685,7 @@ impl NetChannel {
 
                 self.wake_driver(); // TODO: be smarter.
 
-                let _ = moto_sys::SysCpu::wait(
+                let _ = moto_sys::SysCpu::wait_uninterruptible(
                     &mut [self.conn.server_handle()],
                     SysHandle::NONE,
                     SysHandle::NONE,
@@ -394,6 +728,13 @@ impl NetChannel {
                     let mut rx_lock = stream.rx_waiter.lock();
                     stream.process_incoming_msg(msg);
                     rx_lock.take()
+                } else if let Some(socket) = self.udp_socket(msg.handle) {
+                    let mut rx_lock = socket.rx_waiter.lock();
+                    socket.process_incoming_msg(msg);
+                    rx_lock.take()
+                } else if let Some(listener) = self.tcp_listener(msg.handle) {
+                    listener.process_incoming_msg(msg);
+                    None
                 } else {
                     self.on_orphan_message(msg);
                     None
@@ -496,6 +837,7 @@ impl NetChannel {
             subchannels_in_use,
             tcp_streams: Mutex::new(BTreeMap::new()),
             tcp_listeners: Mutex::new(BTreeMap::new()),
+            udp_sockets: Mutex::new(BTreeMap::new()),
             reservations: AtomicUsize::new(0),
             next_msg_id: CachePadded::new(AtomicU64::new(1)),
             send_queue: crossbeam_queue::ArrayQueue::new(io_channel::CHANNEL_PAGE_COUNT),
@@ -578,6 +920,36 @@ impl NetChannel {
         NET.lock().release_channel(self.clone());
     }
 
+    fn udp_socket_created(self: &Arc<Self>, socket: &Arc<UdpSocket>) {
+        assert!(self
+            .udp_sockets
+            .lock()
+            .insert(socket.handle, Arc::downgrade(socket))
+            .is_none());
+    }
+
+    fn udp_socket_dropped(self: &Arc<Self>, handle: u64, subchannel_idx: usize) {
+        let socket = self.udp_sockets.lock().remove(&handle).unwrap();
+        assert_eq!(0, socket.strong_count());
+
+        self.release_subchannel(subchannel_idx);
+        NET.lock().release_channel(self.clone());
+    }
+
+    fn tcp_listener(&self, handle: u64) -> Option<Arc<TcpListener>> {
+        self.tcp_listeners
+            .lock()
+            .get(&handle)
+            .and_then(|listener| listener.upgrade())
+    }
+
+    fn udp_socket(&self, handle: u64) -> Option<Arc<UdpSocket>> {
+        self.udp_sockets
+            .lock()
+            .get(&handle)
+            .and_then(|socket| socket.upgrade())
+    }
+
     fn send_msg(self: &Arc<Self>, msg: io_channel::Msg) {
         loop {
             if self.send_queue.push(msg).is_ok() {
@@ -589,7 +961,12 @@ impl NetChannel {
                 .lock()
                 .push_back(moto_sys::UserThreadControlBlock::this_thread_handle().into());
             self.maybe_wake_io_thread();
-            let _ = moto_sys::SysCpu::wait(&mut [], SysHandle::NONE, SysHandle::NONE, None);
+            let _ = moto_sys::SysCpu::wait_uninterruptible(
+                &mut [],
+                SysHandle::NONE,
+                SysHandle::NONE,
+                None,
+            );
         }
     }
 
@@ -604,7 +981,12 @@ impl NetChannel {
             }
 
             // No need to wake the IO thread, as it will be woken by sys-io.
-            let _ = moto_sys::SysCpu::wait(&mut [], SysHandle::NONE, SysHandle::NONE, None);
+            let _ = moto_sys::SysCpu::wait_uninterruptible(
+                &mut [],
+                SysHandle::NONE,
+                SysHandle::NONE,
+                None,
+            );
         }
     }
 
@@ -638,7 +1020,13 @@ impl NetChannel {
                 }
             }
             api_net::EVT_TCP_STREAM_STATE_CHANGED => {}
+            api_net::EVT_TCP_STREAM_TX_SPACE => {}
+            api_net::EVT_TCP_LISTENER_READY => {}
             api_net::CMD_TCP_STREAM_CLOSE => {}
+            api_net::CMD_UDP_SOCKET_RX => {
+                // RX raced with the client dropping the socket.
+                let _ = self.conn.get_page(api_net::udp_datagram(&msg).0);
+            }
             _ => {
                 // #[cfg(debug_assertions)]
                 // This is logged always because if a new incoming message is added that
@@ -702,31 +1090,31 @@ pub struct TcpStream {
 
     tcp_state: AtomicU32, // rt_api::TcpState
     rx_done: AtomicBool,
+    // Why sys-io closed the stream, if not orderly (e.g. E_TIMED_OUT on idle timeout).
+    close_status: AtomicU16,
 
     rx_timeout_ns: AtomicU64,
     tx_timeout_ns: AtomicU64,
+    linger_ns: AtomicU64, // u64::MAX: off.
+
+    // CMD_TCP_STREAM_CLOSE has been sent.
+    closed: AtomicBool,
+    // Fds referring to the stream (see new_fd()): the stream is closed when
+    // the last one is, even if other threads still hold the stream.
+    fds: AtomicUsize,
 
     subchannel_idx: usize, // Never changes.
     subchannel_mask: u64,  // Never changes.
 
     stats_rx_bytes: AtomicU64,
     stats_tx_bytes: AtomicU64,
+
+    pollers: Pollers,
 }
 
 impl Drop for TcpStream {
     fn drop(&mut self) {
-        let mut req = io_channel::Msg::new();
-        req.command = api_net::CMD_TCP_STREAM_CLOSE;
-        req.handle = self.handle;
-
-        if moto_sys::UserThreadControlBlock::get().self_handle
-            == self.channel.io_thread_wake_handle.load(Ordering::Relaxed)
-        {
-            // We cannot do send_receive here because it will block the IO thread.
-            self.channel.send_queue.push(req).unwrap(); // TODO: don't panic on failure.
-        } else {
-            let _ = self.channel.send_receive(req);
-        }
+        let _ = self.close();
 
         // Clear RX queue: basically, free up server-allocated pages.
         {
@@ -746,6 +1134,43 @@ impl Drop for TcpStream {
 }
 
 impl TcpStream {
+    pub fn new_fd(self: Arc<Self>) -> RtFd {
+        self.fds.fetch_add(1, Ordering::Relaxed);
+        DESCRIPTORS.push(Arc::new(Fd::TcpStream(self)))
+    }
+
+    // Called when an fd returned by new_fd() is closed.
+    pub fn fd_closed(&self) -> ErrorCode {
+        if self.fds.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.close()
+        } else {
+            moto_rt::E_OK
+        }
+    }
+
+    // Closes the connection (once). With SO_LINGER set, this blocks until
+    // sys-io has sent the queued bytes, and returns E_TIMED_OUT (or
+    // E_CONNECTION_RESET) if it could not.
+    pub fn close(&self) -> ErrorCode {
+        if self.closed.swap(true, Ordering::AcqRel) {
+            return moto_rt::E_OK;
+        }
+
+        let mut req = io_channel::Msg::new();
+        req.command = api_net::CMD_TCP_STREAM_CLOSE;
+        req.handle = self.handle;
+
+        if moto_sys::UserThreadControlBlock::get().self_handle
+            == self.channel.io_thread_wake_handle.load(Ordering::Relaxed)
+        {
+            // We cannot do send_receive here because it will block the IO thread.
+            self.channel.send_queue.push(req).unwrap(); // TODO: don't panic on failure.
+            moto_rt::E_OK
+        } else {
+            self.channel.send_receive(req).status()
+        }
+    }
+
     fn ack_rx(&self) {
         let mut req = io_channel::Msg::new();
         req.command = api_net::CMD_TCP_STREAM_RX_ACK;
@@ -758,6 +1183,14 @@ impl TcpStream {
         api_net::TcpState::try_from(self.tcp_state.load(Ordering::Relaxed)).unwrap()
     }
 
+    // What read/write return when the stream is closed.
+    fn closed_result(&self) -> Result<usize, ErrorCode> {
+        match self.close_status.load(Ordering::Relaxed) {
+            moto_rt::E_OK => Ok(0),
+            err => Err(err),
+        }
+    }
+
     // Note: this is called from the IO thread, so must not sleep.
     fn process_incoming_msg(&self, msg: io_channel::Msg) {
         match msg.command {
@@ -765,9 +1198,13 @@ impl TcpStream {
                 self.recv_queue.lock().push_back(msg);
             }
             api_net::EVT_TCP_STREAM_STATE_CHANGED => {
+                if msg.status() != moto_rt::E_OK {
+                    self.close_status.store(msg.status(), Ordering::Relaxed);
+                }
                 self.tcp_state
                     .store(msg.payload.args_32()[0], Ordering::Relaxed);
             }
+            api_net::EVT_TCP_STREAM_TX_SPACE => {} // For pollers.
             _ => panic!(
                 "{}:{}: Unrecognized msg {} for stream 0x{:x}",
                 file!(),
@@ -776,17 +1213,40 @@ impl TcpStream {
                 msg.handle
             ),
         }
+
+        self.pollers.notify();
+    }
+
+    fn poll_readiness(&self) -> u64 {
+        // write() does not block on the TCP state (it returns right away if the
+        // stream is not writable), but it waits for a free IO page; sys-io frees
+        // pages as it hands the bytes over to the TCP socket, and sends
+        // EVT_TCP_STREAM_TX_SPACE if it frees them after write() returned.
+        let mut events = 0;
+        if !self.tcp_state().can_write() || self.channel.conn.free_pages(self.subchannel_mask) > 0 {
+            events |= moto_rt::net::POLL_WRITABLE;
+        }
+
+        if self.rx_done.load(Ordering::Relaxed)
+            || self.rx_buf.lock().is_some()
+            || !self.recv_queue.lock().is_empty()
+        {
+            events |= moto_rt::net::POLL_READABLE;
+        }
+
+        events
     }
 
     fn connect(
         socket_addr: &SocketAddr,
+        local_addr: Option<&SocketAddr>,
         timeout: Option<Duration>,
     ) -> Result<Arc<TcpStream>, ErrorCode> {
         let channel = NET.lock().reserve_channel();
         let subchannel_idx = channel.reserve_subchannel();
         let subchannel_mask = api_net::io_subchannel_mask(subchannel_idx);
 
-        let req = if let Some(timo) = timeout {
+        let mut req = if let Some(timo) = timeout {
             api_net::tcp_stream_connect_timeout_request(
                 socket_addr,
                 subchannel_mask,
@@ -795,6 +1255,18 @@ impl TcpStream {
         } else {
             api_net::tcp_stream_connect_request(socket_addr, subchannel_mask)
         };
+        if let Some(local_addr) = local_addr {
+            // The subchannel is new, so it has free pages.
+            let page = match channel.conn.alloc_page(subchannel_mask) {
+                Ok(page) => page,
+                Err(err) => {
+                    channel.release_subchannel(subchannel_idx);
+                    NET.lock().release_channel(channel.clone());
+                    return Err(err);
+                }
+            };
+            api_net::tcp_stream_connect_set_local_addr(&mut req, page, local_addr);
+        }
 
         let resp = channel.send_receive(req);
         if resp.status() != moto_rt::E_OK {
@@ -822,12 +1294,17 @@ impl TcpStream {
             rx_waiter: Mutex::new(None),
             tcp_state: AtomicU32::new(api_net::TcpState::ReadWrite.into()),
             rx_done: AtomicBool::new(false),
+            close_status: AtomicU16::new(moto_rt::E_OK),
             rx_timeout_ns: AtomicU64::new(u64::MAX),
             tx_timeout_ns: AtomicU64::new(u64::MAX),
+            linger_ns: AtomicU64::new(u64::MAX),
+            closed: AtomicBool::new(false),
+            fds: AtomicUsize::new(0),
             subchannel_idx,
             subchannel_mask,
             stats_rx_bytes: AtomicU64::new(0),
             stats_tx_bytes: AtomicU64::new(0),
+            pollers: Pollers::default(),
         });
 
         channel.tcp_stream_created(&inner);
@@ -880,6 +1357,9 @@ impl TcpStream {
 
         if sz_read == 0 {
             assert_eq!(msg.payload.shared_pages()[0], u16::MAX);
+            if msg.status() != moto_rt::E_OK {
+                self.close_status.store(msg.status(), Ordering::Relaxed);
+            }
             self.rx_done.store(true, Ordering::Release);
             #[cfg(debug_assertions)]
             moto_log!(
@@ -889,7 +1369,7 @@ impl TcpStream {
                 msg.handle,
                 rx_seq
             );
-            return Ok(0);
+            return self.closed_result();
         }
 
         let io_page = self
@@ -974,7 +1454,7 @@ impl TcpStream {
         }
 
         if self.rx_done.load(Ordering::Relaxed) {
-            return Ok(0);
+            return self.closed_result();
         }
 
         Err(moto_rt::E_NOT_READY)
@@ -1039,7 +1519,7 @@ impl TcpStream {
                 debug_assert = true;
                 debug_timeout
             };
-            if let Err(err) = moto_sys::SysCpu::wait(
+            if let Err(err) = moto_sys::SysCpu::wait_uninterruptible(
                 &mut [],
                 SysHandle::NONE,
                 SysHandle::NONE,
@@ -1065,16 +1545,26 @@ impl TcpStream {
     }
 
     pub fn write(&self, buf: &[u8]) -> Result<usize, ErrorCode> {
+        self.write_with_flags(buf, 0)
+    }
+
+    fn send_urgent(&self, byte: u8) -> ErrorCode {
+        match self.write_with_flags(&[byte], api_net::FLAG_TCP_STREAM_TX_URGENT) {
+            Ok(_) => moto_rt::E_OK,
+            Err(err) => err,
+        }
+    }
+
+    fn write_with_flags(&self, buf: &[u8], flags: u32) -> Result<usize, ErrorCode> {
         if buf.len() == 0 {
             return Ok(0);
         }
 
         if !self.tcp_state().can_write() {
-            return Ok(0);
+            return self.closed_result();
         }
 
         let timestamp = Instant::now();
-        let write_sz = buf.len().min(io_channel::PAGE_SIZE);
         let timo_ns = self.tx_timeout_ns.load(Ordering::Relaxed);
         let abs_timeout = if timo_ns == u64::MAX {
             None
@@ -1099,7 +1589,7 @@ impl TcpStream {
                 Ok(page) => break page,
                 Err(_) => {
                     if !self.tcp_state().can_write() {
-                        return Ok(0);
+                        return self.closed_result();
                     }
 
                     if spin_loop_counter < 100 {
@@ -1129,7 +1619,7 @@ impl TcpStream {
                         );
                     }
 
-                    let _ = moto_sys::SysCpu::wait(
+                    let _ = moto_sys::SysCpu::wait_uninterruptible(
                         &mut [],
                         SysHandle::NONE,
                         SysHandle::NONE,
@@ -1138,16 +1628,49 @@ impl TcpStream {
                 }
             }
         };
-        unsafe {
-            core::ptr::copy_nonoverlapping(
-                buf.as_ptr(),
-                io_page.bytes_mut().as_mut_ptr(),
-                write_sz,
-            );
+
+        // Send more of @buf in the same message if pages are available right away.
+        let mut io_pages = Vec::with_capacity(api_net::TCP_STREAM_TX_MAX_PAGES);
+        io_pages.push(io_page);
+        while io_pages.len() < api_net::TCP_STREAM_TX_MAX_PAGES
+            && buf.len() > io_pages.len() * io_channel::PAGE_SIZE
+        {
+            match self.channel.conn.alloc_page(self.subchannel_mask) {
+                Ok(page) => io_pages.push(page),
+                Err(_) => break,
+            }
         }
 
-        let msg = api_net::tcp_stream_tx_msg(self.handle, io_page, write_sz, timestamp.as_u64());
-        self.channel.send_msg(msg);
+        let write_sz = buf.len().min(io_pages.len() * io_channel::PAGE_SIZE);
+        for (chunk, io_page) in buf[0..write_sz]
+            .chunks(io_channel::PAGE_SIZE)
+            .zip(io_pages.iter())
+        {
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    chunk.as_ptr(),
+                    io_page.bytes_mut().as_mut_ptr(),
+                    chunk.len(),
+                );
+            }
+        }
+
+        let mut msg =
+            api_net::tcp_stream_tx_sg_msg(self.handle, io_pages, write_sz, timestamp.as_u64());
+        msg.flags |= flags;
+        if self.channel.conn.free_pages(self.subchannel_mask) == 0 {
+            // Pollers waiting for POLL_WRITABLE need to know when pages free up.
+            msg.flags |= api_net::FLAG_TCP_STREAM_TX_NOTIFY;
+        }
+        if flags & api_net::FLAG_TCP_STREAM_TX_URGENT != 0 {
+            // sys-io replies to urgent writes only; on error, nothing is sent.
+            let status = self.channel.send_receive(msg).status();
+            if status != moto_rt::E_OK {
+                return Err(status);
+            }
+        } else {
+            self.channel.send_msg(msg);
+        }
         self.stats_tx_bytes
             .fetch_add(write_sz as u64, Ordering::Relaxed);
         #[cfg(debug_assertions)]
@@ -1194,22 +1717,21 @@ impl TcpStream {
         }
     }
 
-    fn set_linger(&self, dur: Option<Duration>) -> Result<(), ErrorCode> {
-        if let Some(dur) = dur {
-            if dur == Duration::ZERO {
-                return Ok(());
-            }
+    fn set_linger(&self, linger_ns: u64) -> ErrorCode {
+        let mut req = io_channel::Msg::new();
+        req.command = api_net::CMD_TCP_STREAM_SET_OPTION;
+        req.handle = self.handle;
+        req.payload.args_64_mut()[0] = api_net::TCP_OPTION_LINGER;
+        req.payload.args_64_mut()[1] = linger_ns;
+        let status = self.channel.send_receive(req).status();
+        if status == moto_rt::E_OK {
+            self.linger_ns.store(linger_ns, Ordering::Relaxed);
         }
-
-        // At the moment, socket shutdown or drop drops all unsent bytes, which
-        // corresponds to SO_LINGER(0). This may or may not be what the user
-        // wants, but anything different requires changing sys-io code/logic,
-        // at there are higher-priority work to do.
-        Err(moto_rt::E_NOT_IMPLEMENTED)
+        status
     }
 
-    fn linger(&self) -> Result<Option<Duration>, ErrorCode> {
-        Ok(Some(Duration::ZERO)) // see set_linger() above.
+    fn linger(&self) -> u64 {
+        self.linger_ns.load(Ordering::Relaxed)
     }
 
     fn set_nodelay(&self, nodelay: u8) -> ErrorCode {
@@ -1236,18 +1758,100 @@ impl TcpStream {
         }
     }
 
-    fn set_ttl(&self, ttl: u32) -> ErrorCode {
+    fn set_idle_timeout(&self, timeout_ns: u64) -> ErrorCode {
         let mut req = io_channel::Msg::new();
         req.command = api_net::CMD_TCP_STREAM_SET_OPTION;
         req.handle = self.handle;
-        req.payload.args_64_mut()[0] = api_net::TCP_OPTION_TTL;
-        req.payload.args_32_mut()[2] = ttl;
+        req.payload.args_64_mut()[0] = api_net::TCP_OPTION_IDLE_TIMEOUT;
+        req.payload.args_64_mut()[1] = timeout_ns;
         self.channel.send_receive(req).status()
     }
 
-    fn ttl(&self) -> Result<u32, ErrorCode> {
+    fn set_rx_paused(&self, paused: bool) -> ErrorCode {
         let mut req = io_channel::Msg::new();
-        req.command = api_net::CMD_TCP_STREAM_GET_OPTION;
+        req.command = api_net::CMD_TCP_STREAM_SET_OPTION;
+        req.handle = self.handle;
+        req.payload.args_64_mut()[0] = api_net::TCP_OPTION_RX_PAUSED;
+        req.payload.args_64_mut()[1] = paused as u64;
+        self.channel.send_receive(req).status()
+    }
+
+    fn rx_paused(&self) -> Result<u8, ErrorCode> {
+        let mut req = io_channel::Msg::new();
+        req.command = api_net::CMD_TCP_STREAM_GET_OPTION;
+        req.handle = self.handle;
+        req.payload.args_64_mut()[0] = api_net::TCP_OPTION_RX_PAUSED;
+        let resp = self.channel.send_receive(req);
+
+        if resp.status() == moto_rt::E_OK {
+            Ok(resp.payload.args_64()[0] as u8)
+        } else {
+            Err(resp.status())
+        }
+    }
+
+    // Bytes left to read before the urgent mark, or u64::MAX.
+    fn urgent_mark(&self) -> Result<u64, ErrorCode> {
+        let mut req = io_channel::Msg::new();
+        req.command = api_net::CMD_TCP_STREAM_GET_OPTION;
+        req.handle = self.handle;
+        req.payload.args_64_mut()[0] = api_net::TCP_OPTION_URGENT_MARK;
+        let resp = self.channel.send_receive(req);
+        if resp.status() != moto_rt::E_OK {
+            return Err(resp.status());
+        }
+
+        let mark = resp.payload.args_64()[0];
+        if mark == u64::MAX {
+            return Ok(u64::MAX);
+        }
+
+        // Bytes received from sys-io but not yet read are still ahead.
+        let buffered = self
+            .rx_buf
+            .lock()
+            .as_ref()
+            .map(|rx_buf| rx_buf.available())
+            .unwrap_or(0);
+        let read = self.stats_rx_bytes.load(Ordering::Relaxed) - buffered as u64;
+        Ok(mark.checked_sub(read).unwrap_or(u64::MAX))
+    }
+
+    // Bytes that can be read without blocking: not yet read by the application,
+    // here or in sys-io.
+    fn rx_available(&self) -> Result<u64, ErrorCode> {
+        let mut req = io_channel::Msg::new();
+        req.command = api_net::CMD_TCP_STREAM_GET_OPTION;
+        req.handle = self.handle;
+        req.payload.args_64_mut()[0] = api_net::TCP_OPTION_RX_RECEIVED;
+        let resp = self.channel.send_receive(req);
+        if resp.status() != moto_rt::E_OK {
+            return Err(resp.status());
+        }
+
+        let received = resp.payload.args_64()[0];
+        let buffered = self
+            .rx_buf
+            .lock()
+            .as_ref()
+            .map(|rx_buf| rx_buf.available())
+            .unwrap_or(0);
+        let read = self.stats_rx_bytes.load(Ordering::Relaxed) - buffered as u64;
+        Ok(received.saturating_sub(read))
+    }
+
+    fn set_ttl(&self, ttl: u32) -> ErrorCode {
+        let mut req = io_channel::Msg::new();
+        req.command = api_net::CMD_TCP_STREAM_SET_OPTION;
+        req.handle = self.handle;
+        req.payload.args_64_mut()[0] = api_net::TCP_OPTION_TTL;
+        req.payload.args_32_mut()[2] = ttl;
+        self.channel.send_receive(req).status()
+    }
+
+    fn ttl(&self) -> Result<u32, ErrorCode> {
+        let mut req = io_channel::Msg::new();
+        req.command = api_net::CMD_TCP_STREAM_GET_OPTION;
         req.handle = self.handle;
         req.payload.args_64_mut()[0] = api_net::TCP_OPTION_TTL;
         let resp = self.channel.send_receive(req);
@@ -1259,6 +1863,20 @@ impl TcpStream {
         }
     }
 
+    fn peer_credentials(&self) -> Result<[u64; 2], ErrorCode> {
+        let mut req = io_channel::Msg::new();
+        req.command = api_net::CMD_TCP_STREAM_GET_OPTION;
+        req.handle = self.handle;
+        req.payload.args_64_mut()[0] = api_net::TCP_OPTION_PEER_CREDS;
+        let resp = self.channel.send_receive(req);
+
+        if resp.status() == moto_rt::E_OK {
+            Ok([resp.payload.args_64()[0], resp.payload.args_64()[1]])
+        } else {
+            Err(resp.status())
+        }
+    }
+
     fn take_error(&self) -> Result<Option<ErrorCode>, ErrorCode> {
         // We don't have this unixism.
         Ok(None)
@@ -1274,6 +1892,8 @@ pub struct TcpListener {
     channel: Arc<NetChannel>,
     handle: u64,
     nonblocking: AtomicBool,
+    fin_on_close: AtomicBool,
+    pollers: Pollers,
 }
 
 impl Drop for TcpListener {
@@ -1281,14 +1901,21 @@ impl Drop for TcpListener {
         let mut msg = io_channel::Msg::new();
         msg.command = api_net::CMD_TCP_LISTENER_DROP;
         msg.handle = self.handle;
+        if self.fin_on_close.load(Ordering::Relaxed) {
+            msg.flags = api_net::FLAG_TCP_LISTENER_DROP_FIN;
+        }
         self.channel.send_msg(msg);
         self.channel.tcp_listener_dropped(self.handle)
     }
 }
 
 impl TcpListener {
-    fn bind(socket_addr: &SocketAddr) -> Result<Arc<TcpListener>, ErrorCode> {
-        let req = api_net::bind_tcp_listener_request(socket_addr, None);
+    fn bind(
+        socket_addr: &SocketAddr,
+        exclusive_addr: bool,
+        reuse_port: bool,
+    ) -> Result<Arc<TcpListener>, ErrorCode> {
+        let req = api_net::bind_tcp_listener_request(socket_addr, None, exclusive_addr, reuse_port);
         let channel = NET.lock().reserve_channel();
         let resp = channel.send_receive(req);
         if resp.status() != moto_rt::E_OK {
@@ -1301,6 +1928,8 @@ impl TcpListener {
             channel: channel.clone(),
             handle: resp.handle,
             nonblocking: AtomicBool::new(false),
+            fin_on_close: AtomicBool::new(false),
+            pollers: Pollers::default(),
         });
         channel.tcp_listener_created(&inner);
 
@@ -1319,6 +1948,72 @@ impl TcpListener {
         Ok(self.socket_addr)
     }
 
+    fn set_idle_timeout(&self, timeout_ns: u64) -> ErrorCode {
+        let mut req = io_channel::Msg::new();
+        req.command = api_net::CMD_TCP_LISTENER_SET_OPTION;
+        req.handle = self.handle;
+        req.payload.args_64_mut()[0] = api_net::TCP_LISTENER_OPTION_IDLE_TIMEOUT;
+        req.payload.args_64_mut()[1] = timeout_ns;
+        self.channel.send_receive(req).status()
+    }
+
+    fn drain(&self) -> ErrorCode {
+        let mut req = io_channel::Msg::new();
+        req.command = api_net::CMD_TCP_LISTENER_SET_OPTION;
+        req.handle = self.handle;
+        req.payload.args_64_mut()[0] = api_net::TCP_LISTENER_OPTION_DRAIN;
+        self.channel.send_receive(req).status()
+    }
+
+    // Note: this is called from the IO thread, so must not sleep.
+    fn process_incoming_msg(&self, msg: io_channel::Msg) {
+        match msg.command {
+            api_net::EVT_TCP_LISTENER_READY => self.pollers.notify(),
+            _ => panic!(
+                "{}:{}: Unrecognized msg {} for listener 0x{:x}",
+                file!(),
+                line!(),
+                msg.command,
+                msg.handle
+            ),
+        }
+    }
+
+    // Readable if there are connections to accept (or accept() would fail
+    // right away).
+    fn poll_readiness(&self) -> u64 {
+        match self.pending_accepts() {
+            Ok(0) => 0,
+            _ => moto_rt::net::POLL_READABLE,
+        }
+    }
+
+    fn pending_accepts(&self) -> Result<u64, ErrorCode> {
+        let mut req = io_channel::Msg::new();
+        req.command = api_net::CMD_TCP_LISTENER_GET_OPTION;
+        req.handle = self.handle;
+        req.payload.args_64_mut()[0] = api_net::TCP_LISTENER_OPTION_PENDING_ACCEPTS;
+        let resp = self.channel.send_receive(req);
+        if resp.status() == moto_rt::E_OK {
+            Ok(resp.payload.args_64()[0])
+        } else {
+            Err(resp.status())
+        }
+    }
+
+    fn accepted(&self) -> Result<u64, ErrorCode> {
+        let mut req = io_channel::Msg::new();
+        req.command = api_net::CMD_TCP_LISTENER_GET_OPTION;
+        req.handle = self.handle;
+        req.payload.args_64_mut()[0] = api_net::TCP_LISTENER_OPTION_ACCEPTED;
+        let resp = self.channel.send_receive(req);
+        if resp.status() == moto_rt::E_OK {
+            Ok(resp.payload.args_64()[0])
+        } else {
+            Err(resp.status())
+        }
+    }
+
     fn accept(&self) -> Result<(Arc<TcpStream>, SocketAddr), ErrorCode> {
         // Because a listener can spawn thousands, millions of sockets
         // (think a long-running web server), we cannot use the listener's
@@ -1352,12 +2047,17 @@ impl TcpListener {
             rx_waiter: Mutex::new(None),
             tcp_state: AtomicU32::new(api_net::TcpState::ReadWrite.into()),
             rx_done: AtomicBool::new(false),
+            close_status: AtomicU16::new(moto_rt::E_OK),
             rx_timeout_ns: AtomicU64::new(u64::MAX),
             tx_timeout_ns: AtomicU64::new(u64::MAX),
+            linger_ns: AtomicU64::new(u64::MAX),
+            closed: AtomicBool::new(false),
+            fds: AtomicUsize::new(0),
             subchannel_idx,
             subchannel_mask,
             stats_rx_bytes: AtomicU64::new(0),
             stats_tx_bytes: AtomicU64::new(0),
+            pollers: Pollers::default(),
         });
 
         channel.tcp_stream_created(&inner);
@@ -1405,3 +2105,483 @@ impl TcpListener {
         Ok(())
     }
 }
+
+struct Datagram {
+    page: io_channel::IoPage,
+    len: usize,
+    from: SocketAddr,
+}
+
+pub struct UdpSocket {
+    channel: Arc<NetChannel>,
+    local_addr: SocketAddr,
+    handle: u64,
+
+    // Datagrams received, but not yet read.
+    recv_queue: Mutex<VecDeque<Datagram>>,
+    rx_waiter: Mutex<Option<SysHandle>>,
+
+    rx_timeout_ns: AtomicU64, // u64::MAX: no timeout.
+    dont_frag: AtomicBool,    // See moto_rt::net::SO_DONT_FRAG.
+
+    subchannel_idx: usize, // Never changes.
+    subchannel_mask: u64,  // Never changes.
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        // Free up server-allocated pages.
+        self.recv_queue.lock().clear();
+
+        let mut msg = io_channel::Msg::new();
+        msg.command = api_net::CMD_UDP_SOCKET_DROP;
+        msg.handle = self.handle;
+        self.channel.send_msg(msg);
+        self.channel
+            .udp_socket_dropped(self.handle, self.subchannel_idx);
+    }
+}
+
+impl UdpSocket {
+    fn bind(socket_addr: &SocketAddr) -> Result<Arc<UdpSocket>, ErrorCode> {
+        let channel = NET.lock().reserve_channel();
+        let subchannel_idx = channel.reserve_subchannel();
+        let subchannel_mask = api_net::io_subchannel_mask(subchannel_idx);
+
+        let resp = channel.send_receive(api_net::udp_socket_bind_request(
+            socket_addr,
+            subchannel_mask,
+        ));
+        if resp.status() != moto_rt::E_OK {
+            channel.release_subchannel(subchannel_idx);
+            NET.lock().release_channel(channel);
+            return Err(resp.status());
+        }
+
+        let inner = Arc::new(UdpSocket {
+            channel: channel.clone(),
+            local_addr: api_net::get_socket_addr(&resp.payload).unwrap(),
+            handle: resp.handle,
+            recv_queue: Mutex::new(VecDeque::new()),
+            rx_waiter: Mutex::new(None),
+            rx_timeout_ns: AtomicU64::new(u64::MAX),
+            dont_frag: AtomicBool::new(false),
+            subchannel_idx,
+            subchannel_mask,
+        });
+        channel.udp_socket_created(&inner);
+
+        #[cfg(debug_assertions)]
+        moto_log!(
+            "{}:{} new UdpSocket {:?} 0x{:x}",
+            file!(),
+            line!(),
+            inner.local_addr,
+            inner.handle
+        );
+
+        Ok(inner)
+    }
+
+    // Note: this is called from the IO thread, so must not sleep.
+    fn process_incoming_msg(&self, msg: io_channel::Msg) {
+        assert_eq!(msg.command, api_net::CMD_UDP_SOCKET_RX);
+        let (page_idx, len) = api_net::udp_datagram(&msg);
+        let Ok(page) = self.channel.conn.get_page(page_idx) else {
+            moto_log!("{}:{} bad RX page for 0x{:x}", file!(), line!(), msg.handle);
+            return;
+        };
+        let Ok(from) = api_net::get_udp_peer_addr(&msg.payload) else {
+            return; // Drop the page.
+        };
+
+        self.recv_queue
+            .lock()
+            .push_back(Datagram { page, len, from });
+    }
+
+    // Datagrams are never truncated: if @buf is too small, the datagram
+    // stays queued, and E_MSG_TOO_LARGE is returned.
+    fn poll_rx(&self, buf: &mut [u8], peek: bool) -> Result<(usize, SocketAddr), ErrorCode> {
+        let mut recv_queue = self.recv_queue.lock();
+        let Some(datagram) = recv_queue.front() else {
+            return Err(moto_rt::E_NOT_READY);
+        };
+        if datagram.len > buf.len() {
+            return Err(moto_rt::E_MSG_TOO_LARGE);
+        }
+
+        let res = (datagram.len, datagram.from);
+        buf[0..datagram.len].copy_from_slice(&datagram.page.bytes()[0..datagram.len]);
+        if !peek {
+            recv_queue.pop_front();
+        }
+        Ok(res)
+    }
+
+    fn recv_from(&self, buf: &mut [u8], peek: bool) -> Result<(usize, SocketAddr), ErrorCode> {
+        let rx_timeout_ns = self.rx_timeout_ns.load(Ordering::Relaxed);
+        let rx_timeout = if rx_timeout_ns == u64::MAX {
+            None
+        } else {
+            Some(Instant::now() + Duration::from_nanos(rx_timeout_ns))
+        };
+
+        loop {
+            match self.poll_rx(buf, peek) {
+                Err(moto_rt::E_NOT_READY) => {}
+                res => return res,
+            }
+            if let Some(timeout) = rx_timeout {
+                if Instant::now() >= timeout {
+                    return Err(moto_rt::E_TIMED_OUT);
+                }
+            }
+
+            // Store this thread's handle so that it is woken when a datagram arrives,
+            // and re-check for datagrams that arrived before that.
+            *self.rx_waiter.lock() =
+                Some(moto_sys::UserThreadControlBlock::get().self_handle.into());
+            match self.poll_rx(buf, peek) {
+                Err(moto_rt::E_NOT_READY) => {}
+                res => {
+                    *self.rx_waiter.lock() = None;
+                    return res;
+                }
+            }
+
+            self.channel.maybe_wake_io_thread();
+            let _ = moto_sys::SysCpu::wait_uninterruptible(
+                &mut [],
+                SysHandle::NONE,
+                SysHandle::NONE,
+                rx_timeout,
+            );
+        }
+    }
+
+    fn send_to(&self, buf: &[u8], addr: &SocketAddr) -> Result<usize, ErrorCode> {
+        if buf.len() > api_net::UDP_MAX_DATAGRAM_SIZE {
+            return Err(moto_rt::E_MSG_TOO_LARGE);
+        }
+
+        // sys-io frees TX pages as soon as it processes the datagrams.
+        let io_page = loop {
+            match self.channel.conn.alloc_page(self.subchannel_mask) {
+                Ok(page) => break page,
+                Err(_) => moto_sys::SysCpu::sched_yield(),
+            }
+        };
+        io_page.bytes_mut()[0..buf.len()].copy_from_slice(buf);
+
+        let mut msg = api_net::udp_socket_tx_msg(self.handle, io_page, buf.len(), addr);
+        if self.dont_frag.load(Ordering::Relaxed) {
+            msg.flags |= api_net::FLAG_UDP_TX_DONT_FRAG;
+        }
+        let resp = self.channel.send_receive(msg);
+        match resp.status() {
+            moto_rt::E_OK => Ok(buf.len()),
+            err => Err(err),
+        }
+    }
+}
+
+// Poll objects a source is registered with: (poll, fd the source is registered as).
+#[derive(Default)]
+struct Pollers(Mutex<Vec<(Weak<Poll>, RtFd)>>);
+
+impl Pollers {
+    fn add(&self, poll: &Arc<Poll>, fd: RtFd) {
+        self.0.lock().push((Arc::downgrade(poll), fd));
+    }
+
+    fn remove(&self, poll: &Poll, fd: RtFd) {
+        let this = poll as *const Poll;
+        self.0
+            .lock()
+            .retain(|(poll, poll_fd)| !(*poll_fd == fd && poll.as_ptr() == this));
+    }
+
+    // Note: this is called from the IO thread, so must not sleep.
+    fn notify(&self) {
+        self.0.lock().retain(|(poll, fd)| {
+            if let Some(poll) = poll.upgrade() {
+                poll.mark_ready(*fd);
+                true
+            } else {
+                false
+            }
+        });
+    }
+}
+
+#[derive(Clone)]
+enum PollSource {
+    Stream(Weak<TcpStream>),
+    Listener(Weak<TcpListener>),
+}
+
+impl PollSource {
+    fn from_fd(fd: &Fd) -> Result<Self, ErrorCode> {
+        match fd {
+            Fd::TcpStream(stream) => Ok(Self::Stream(Arc::downgrade(stream))),
+            Fd::TcpListener(listener) => Ok(Self::Listener(Arc::downgrade(listener))),
+            _ => Err(E_BAD_HANDLE),
+        }
+    }
+
+    fn same_as(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Stream(this), Self::Stream(other)) => this.ptr_eq(other),
+            (Self::Listener(this), Self::Listener(other)) => this.ptr_eq(other),
+            _ => false,
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        match self {
+            Self::Stream(stream) => stream.strong_count() > 0,
+            Self::Listener(listener) => listener.strong_count() > 0,
+        }
+    }
+
+    fn with_pollers<F: FnOnce(&Pollers)>(&self, f: F) {
+        match self {
+            Self::Stream(stream) => {
+                if let Some(stream) = stream.upgrade() {
+                    f(&stream.pollers)
+                }
+            }
+            Self::Listener(listener) => {
+                if let Some(listener) = listener.upgrade() {
+                    f(&listener.pollers)
+                }
+            }
+        }
+    }
+
+    // None if the source is gone.
+    fn readiness(&self) -> Option<u64> {
+        match self {
+            Self::Stream(stream) => stream.upgrade().map(|stream| stream.poll_readiness()),
+            Self::Listener(listener) => {
+                listener.upgrade().map(|listener| listener.poll_readiness())
+            }
+        }
+    }
+}
+
+struct PollRegistration {
+    source: PollSource,
+    token: u64,
+    interests: u64,
+}
+
+/// An epoll-like readiness multiplexer over TCP streams and listeners.
+///
+/// Sources notify the poll objects they are registered with from the IO
+/// thread when they process incoming messages (including the TX space and
+/// pending connection events sys-io sends for this); the notified sources go
+/// into the `ready` set, so wait() only has to look at sources that may
+/// actually be ready rather than at all registered ones. Any number of
+/// threads may wait() on the same poll object; all of them are woken.
+pub struct Poll {
+    registrations: Mutex<BTreeMap<RtFd, PollRegistration>>,
+
+    // Sources that may be ready: they have been notified, or reported
+    // as ready during the previous wait() (readiness is level-triggered).
+    ready: Mutex<BTreeSet<RtFd>>,
+
+    waiters: Mutex<Vec<SysHandle>>,
+}
+
+impl Poll {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            registrations: Mutex::new(BTreeMap::new()),
+            ready: Mutex::new(BTreeSet::new()),
+            waiters: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn validate_interests(interests: u64) -> Result<(), ErrorCode> {
+        const ALL: u64 = moto_rt::net::POLL_READABLE | moto_rt::net::POLL_WRITABLE;
+        if interests == 0 || (interests & !ALL) != 0 {
+            Err(moto_rt::E_INVALID_ARGUMENT)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn add(
+        self: &Arc<Self>,
+        fd: RtFd,
+        source: PollSource,
+        token: u64,
+        interests: u64,
+    ) -> Result<(), ErrorCode> {
+        Self::validate_interests(interests)?;
+
+        {
+            let mut registrations = self.registrations.lock();
+            if let Some(prev) = registrations.get(&fd) {
+                // The fd may have been closed and reused.
+                if prev.source.is_alive() {
+                    return Err(moto_rt::E_ALREADY_IN_USE);
+                }
+            }
+            registrations.insert(
+                fd,
+                PollRegistration {
+                    source: source.clone(),
+                    token,
+                    interests,
+                },
+            );
+        }
+
+        source.with_pollers(|pollers| pollers.add(self, fd));
+
+        // The source may already be ready.
+        self.mark_ready(fd);
+        Ok(())
+    }
+
+    fn modify(
+        &self,
+        fd: RtFd,
+        source: &PollSource,
+        token: u64,
+        interests: u64,
+    ) -> Result<(), ErrorCode> {
+        Self::validate_interests(interests)?;
+
+        {
+            let mut registrations = self.registrations.lock();
+            let Some(registration) = registrations.get_mut(&fd) else {
+                return Err(moto_rt::E_NOT_FOUND);
+            };
+            if !registration.source.same_as(source) {
+                return Err(moto_rt::E_NOT_FOUND);
+            }
+            registration.token = token;
+            registration.interests = interests;
+        }
+
+        self.mark_ready(fd);
+        Ok(())
+    }
+
+    fn delete(&self, fd: RtFd, source: &PollSource) -> Result<(), ErrorCode> {
+        {
+            let mut registrations = self.registrations.lock();
+            let Some(registration) = registrations.get(&fd) else {
+                return Err(moto_rt::E_NOT_FOUND);
+            };
+            if !registration.source.same_as(source) {
+                return Err(moto_rt::E_NOT_FOUND);
+            }
+            registrations.remove(&fd);
+        }
+
+        source.with_pollers(|pollers| pollers.remove(self, fd));
+        self.ready.lock().remove(&fd);
+        Ok(())
+    }
+
+    // Note: this is called from the IO thread, so must not sleep.
+    fn mark_ready(&self, fd: RtFd) {
+        self.ready.lock().insert(fd);
+        self.wake_waiters();
+    }
+
+    fn wake_waiters(&self) {
+        let waiters = core::mem::take(&mut *self.waiters.lock());
+        for waiter in waiters {
+            if waiter.as_u64() != moto_sys::UserThreadControlBlock::get().self_handle {
+                let _ = moto_sys::SysCpu::wake(waiter);
+            }
+        }
+    }
+
+    fn collect_ready(&self, events: &mut [moto_rt::net::PollEvent]) -> usize {
+        // Checking a listener takes a roundtrip to sys-io via the IO thread,
+        // which calls mark_ready(), so no locks are held while checking.
+        let candidates = core::mem::take(&mut *self.ready.lock());
+
+        let mut num_events = 0;
+        let mut keep = Vec::new();
+        for fd in candidates {
+            if num_events == events.len() {
+                keep.push(fd); // Not checked yet.
+                continue;
+            }
+
+            let Some((source, token, interests)) = self
+                .registrations
+                .lock()
+                .get(&fd)
+                .map(|reg| (reg.source.clone(), reg.token, reg.interests))
+            else {
+                continue;
+            };
+            let Some(readiness) = source.readiness() else {
+                continue;
+            };
+
+            let readiness = readiness & interests;
+            if readiness == 0 {
+                continue;
+            }
+
+            events[num_events] = moto_rt::net::PollEvent {
+                token,
+                events: readiness,
+            };
+            num_events += 1;
+            // Level-triggered: sources that were ready are re-checked on the next wait.
+            keep.push(fd);
+        }
+
+        // Other waiters may have found the ready set empty while it was
+        // being checked here.
+        if !keep.is_empty() {
+            self.ready.lock().extend(keep);
+            self.wake_waiters();
+        }
+        num_events
+    }
+
+    fn wait(&self, events: &mut [moto_rt::net::PollEvent], timeout: Option<Instant>) -> usize {
+        let self_handle: SysHandle = moto_sys::UserThreadControlBlock::get().self_handle.into();
+        let remove_waiter = || self.waiters.lock().retain(|waiter| *waiter != self_handle);
+
+        loop {
+            let num_events = self.collect_ready(events);
+            if num_events > 0 {
+                return num_events;
+            }
+
+            // Store this thread's handle so that it is woken when a source becomes ready.
+            self.waiters.lock().push(self_handle);
+
+            // Re-check: a source may have been marked ready after collect_ready() above.
+            if !self.ready.lock().is_empty() {
+                remove_waiter();
+                continue;
+            }
+
+            let res = moto_sys::SysCpu::wait_uninterruptible(
+                &mut [],
+                SysHandle::NONE,
+                SysHandle::NONE,
+                timeout,
+            );
+            remove_waiter();
+            if let Err(err) = res {
+                assert_eq!(err, moto_rt::E_TIMED_OUT);
+                return self.collect_ready(events);
+            }
+        }
+    }
+}
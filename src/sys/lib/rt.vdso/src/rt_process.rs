@@ -81,13 +81,19 @@ pub extern "C" fn kill(handle: u64) -> moto_rt::ErrorCode {
     }
 }
 pub extern "C" fn wait(handle: u64) -> moto_rt::ErrorCode {
-    match moto_sys::SysCpu::wait(
+    match moto_sys::SysCpu::wait_uninterruptible(
         &mut [handle.into()],
         moto_sys::SysHandle::NONE,
         moto_sys::SysHandle::NONE,
         None,
     ) {
-        Ok(()) => moto_rt::E_OK,
+        Ok(()) => {
+            // Let the relays of the child's output to fds finish writing.
+            if let Ok(Some(_)) = moto_sys::SysRay::process_status(handle.into()) {
+                crate::stdio::wait_fd_relays(handle);
+            }
+            moto_rt::E_OK
+        }
         Err(err) => err,
     }
 }
@@ -105,6 +111,72 @@ pub unsafe extern "C" fn status(handle: u64, status: *mut u64) -> moto_rt::Error
     }
 }
 
+fn tsc_to_nanos(tsc: u64) -> u64 {
+    let mut hi = 0_u64;
+    let mut lo = 0_u64;
+    crate::rt_time::ticks_to_nanos(tsc, &mut hi, &mut lo);
+    lo
+}
+
+pub unsafe extern "C" fn rusage(
+    handle: u64,
+    usage: *mut moto_rt::process::ResourceUsage,
+) -> moto_rt::ErrorCode {
+    let u = match moto_sys::SysRay::query_rusage(handle.into()) {
+        Ok(u) => u,
+        Err(err) => return err,
+    };
+    let io = match process_io(u.pid) {
+        Ok(io) => io,
+        Err(err) => return err,
+    };
+
+    *usage = moto_rt::process::ResourceUsage {
+        cpu_user_ns: tsc_to_nanos(u.cpu_uspace),
+        cpu_kernel_ns: tsc_to_nanos(u.cpu_kernel),
+        page_faults: u.page_faults,
+        peak_bytes_user: u.peak_bytes_user,
+        peak_bytes_kernel: u.peak_bytes_kernel,
+        total_threads: u.total_threads,
+        total_children: u.total_children,
+        context_switches: u.context_switches,
+        migrations: u.migrations,
+        sched_latency_total_ns: tsc_to_nanos(u.sched_latency.total),
+        sched_latency_max_ns: tsc_to_nanos(u.sched_latency.max),
+        fs_bytes_read: io.fs_bytes_read,
+        fs_bytes_written: io.fs_bytes_written,
+        net_bytes_rx: io.net_bytes_rx,
+        net_bytes_tx: io.net_bytes_tx,
+    };
+    moto_rt::E_OK
+}
+
+// The I/O of process @pid, from the sys-io stats service.
+fn process_io(pid: u64) -> Result<moto_sys_io::api_stats::ProcessIoStatsV1, ErrorCode> {
+    use moto_sys_io::api_stats::*;
+
+    let mut conn = moto_ipc::sync::ClientConnection::new(moto_ipc::sync::ChannelSize::Small)?;
+    conn.connect(URL_IO_STATS)?;
+
+    let req = conn.req::<GetProcessIoRequest>();
+    req.header.cmd = CMD_PROCESS_IO;
+    req.header.ver = 0;
+    req.header.flags = 0;
+    req.pid = pid;
+    conn.do_rpc(None)?;
+
+    let resp = conn.resp::<GetProcessIoResponse>();
+    if resp.header.result != moto_rt::E_OK {
+        return Err(resp.header.result);
+    }
+    Ok(resp.stats)
+}
+
+// Lets the kernel apply the panic action of the process; see SysRay::set_panic_action().
+pub extern "C" fn on_panic() {
+    moto_sys::SysRay::on_panic();
+}
+
 pub extern "C" fn exit(code: i32) -> ! {
     let code = unsafe { core::mem::transmute::<i32, u32>(code) } as u64;
     moto_sys::SysCpu::exit(code)
@@ -483,6 +555,29 @@ unsafe fn create_remote_args(
     Ok(remote)
 }
 
+// See SysRay::set_cmdline().
+fn encode_cmdline(args1: &[&[u8]], args2: &[&[u8]], env: &[(&[u8], &[u8])]) -> Vec<u8> {
+    let mut cmdline = Vec::new();
+    for arg in args1.iter().chain(args2.iter()) {
+        cmdline.extend_from_slice(arg);
+        cmdline.push(0);
+    }
+    cmdline.push(0);
+
+    for (k, v) in env {
+        if k.is_empty() {
+            continue; // Cleared keys (e.g. MOTURUS_CAPS) are not exposed.
+        }
+        cmdline.extend_from_slice(k);
+        cmdline.push(b'=');
+        cmdline.extend_from_slice(v);
+        cmdline.push(0);
+    }
+
+    cmdline.truncate(moto_sys::SysRay::MAX_CMDLINE_SIZE as usize);
+    cmdline
+}
+
 unsafe fn create_remote_env(
     address_space: moto_sys::SysHandle,
     env: Vec<(&[u8], &[u8])>,
@@ -586,6 +681,26 @@ fn run_elf(
         }
     }
 
+    // Find MOTURUS_CPU_LIMIT env var.
+    let mut cpu_limit = None;
+    for (k, v) in &mut env {
+        if *k == moto_sys::sys_ray::MOTURUS_CPU_LIMIT_ENV_KEY.as_bytes() {
+            *k = "".as_bytes(); // Clear the key: see env::create_remote_env().
+            let v = core::str::from_utf8(v).map_err(|_| moto_rt::E_INVALID_ARGUMENT)?;
+            cpu_limit = Some(parse_cpu_limit(v)?);
+        }
+    }
+
+    // Find MOTURUS_CPU_AFFINITY env var.
+    let mut cpu_affinity = None;
+    for (k, v) in &mut env {
+        if *k == moto_sys::sys_cpu::MOTURUS_CPU_AFFINITY_ENV_KEY.as_bytes() {
+            *k = "".as_bytes(); // Clear the key: see env::create_remote_env().
+            let v = core::str::from_utf8(v).map_err(|_| moto_rt::E_INVALID_ARGUMENT)?;
+            cpu_affinity = Some(parse_cpu_affinity(v)?);
+        }
+    }
+
     // Create the process from the address space.
     let proc_url = alloc::format!("process:entry_point={};capabilities={}", load_result, caps);
     let process = moto_sys::syscalls::RaiiHandle::from(moto_sys::SysObj::create(
@@ -594,6 +709,16 @@ fn run_elf(
         &proc_url,
     )?);
 
+    if let Some((limit, action)) = cpu_limit {
+        moto_sys::SysRay::set_cpu_limit(process.syshandle(), limit, action)?;
+    }
+
+    // Otherwise the child gets our affinity if we have the inherit flag set.
+    // Either way, this happens before its main thread is woken below.
+    if let Some((cpu, inherit)) = cpu_affinity {
+        moto_sys::SysCpu::set_process_affinity(process.syshandle(), cpu, inherit)?;
+    }
+
     // Set up stdio.
     let remote_process_data = create_remote_process_data(address_space.syshandle())?;
     crate::util::scopeguard::defer! {
@@ -612,6 +737,12 @@ fn run_elf(
         args1.push(arg.as_bytes());
     }
 
+    // Let the kernel know the cmdline, for ps-like tools. See SysRay::set_cmdline().
+    let cmdline = encode_cmdline(&args1, &args, &env);
+    if let Err(err) = moto_sys::SysRay::set_cmdline(process.syshandle(), cmdline.as_slice()) {
+        crate::moto_log!("failed to set cmdline: {:?}", err);
+    }
+
     unsafe {
         let pd = remote_process_data.as_mut().unwrap();
         pd.args = create_remote_args(address_space.syshandle(), &args1, &args, true)?;
@@ -638,6 +769,56 @@ fn run_elf(
     }
 }
 
+// Parses the value of MOTURUS_CPU_LIMIT into (limit TSC, action).
+fn parse_cpu_limit(val: &str) -> Result<(u64, u64), ErrorCode> {
+    let (millis, action) = match val.split_once(',') {
+        None => (val, moto_sys::SysRay::CPU_LIMIT_ACTION_KILL),
+        Some((millis, "kill")) => (millis, moto_sys::SysRay::CPU_LIMIT_ACTION_KILL),
+        Some((millis, "notify")) => (millis, moto_sys::SysRay::CPU_LIMIT_ACTION_NOTIFY),
+        Some(_) => {
+            crate::moto_log!("could not parse cpu limit {}", val);
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+    };
+
+    let millis: u64 = millis.trim().parse().map_err(|_| {
+        crate::moto_log!("could not parse cpu limit {}", val);
+        moto_rt::E_INVALID_ARGUMENT
+    })?;
+    if millis == 0 {
+        return Err(moto_rt::E_INVALID_ARGUMENT);
+    }
+
+    let limit = moto_rt::time::Instant::from_u64(0)
+        .checked_add_duration(&core::time::Duration::from_millis(millis))
+        .ok_or(moto_rt::E_INVALID_ARGUMENT)?
+        .as_u64();
+
+    Ok((limit, action))
+}
+
+// Parses the value of MOTURUS_CPU_AFFINITY into (cpu, inherit).
+fn parse_cpu_affinity(val: &str) -> Result<(Option<u32>, bool), ErrorCode> {
+    let (cpu, inherit) = match val.split_once(',') {
+        None => (val, false),
+        Some((cpu, "inherit")) => (cpu, true),
+        Some(_) => {
+            crate::moto_log!("could not parse cpu affinity {}", val);
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+    };
+
+    let cpu = match cpu.trim() {
+        "none" => None,
+        cpu => Some(cpu.parse().map_err(|_| {
+            crate::moto_log!("could not parse cpu affinity {}", val);
+            moto_rt::E_INVALID_ARGUMENT
+        })?),
+    };
+
+    Ok((cpu, inherit))
+}
+
 unsafe fn spawn_impl(
     args_rt: &moto_rt::process::SpawnArgsRt,
     result_rt: &mut moto_rt::process::SpawnResult,
@@ -54,7 +54,12 @@ pub extern "C" fn sleep(deadline: u64) {
     // return. Thus we have to track time and wait again.
     let deadline = moto_rt::time::Instant::from_u64(deadline);
     loop {
-        match moto_sys::SysCpu::wait(&mut [], SysHandle::NONE, SysHandle::NONE, Some(deadline)) {
+        match moto_sys::SysCpu::wait_uninterruptible(
+            &mut [],
+            SysHandle::NONE,
+            SysHandle::NONE,
+            Some(deadline),
+        ) {
             Ok(()) => continue,
             Err(moto_rt::E_TIMED_OUT) => {
                 debug_assert!(moto_rt::time::Instant::now() >= deadline);
@@ -90,7 +95,12 @@ pub extern "C" fn join(handle: u64) -> ErrorCode {
     let handle = SysHandle::from_u64(handle);
     loop {
         let mut handles = [handle];
-        match moto_sys::SysCpu::wait(&mut handles, SysHandle::NONE, SysHandle::NONE, None) {
+        match moto_sys::SysCpu::wait_uninterruptible(
+            &mut handles,
+            SysHandle::NONE,
+            SysHandle::NONE,
+            None,
+        ) {
             Ok(_) => {
                 if handles[0] == handle {
                     break;
@@ -1,5 +1,5 @@
 use crate::{rt_process::ProcessData, rt_process::StdioData, spin::Mutex};
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
 use moto_ipc::sync_pipe::Pipe;
 use moto_rt::{ErrorCode, RtFd, E_BAD_HANDLE, E_INVALID_ARGUMENT};
 use moto_sys::SysHandle;
@@ -189,8 +189,13 @@ pub fn set_relay(from: moto_rt::RtFd, to: *const u8) -> Result<SysHandle, ErrorC
             let mut had_error = false;
             loop {
                 let mut handles = wait_handles;
-                if moto_sys::SysCpu::wait(&mut handles, SysHandle::NONE, SysHandle::NONE, None)
-                    .is_err()
+                if moto_sys::SysCpu::wait_uninterruptible(
+                    &mut handles,
+                    SysHandle::NONE,
+                    SysHandle::NONE,
+                    None,
+                )
+                .is_err()
                 {
                     if had_error {
                         break;
@@ -284,6 +289,123 @@ pub fn set_relay(from: moto_rt::RtFd, to: *const u8) -> Result<SysHandle, ErrorC
     .map(|handle| SysHandle::from(handle))
 }
 
+// The threads relaying children's stdout/stderr to fds (see set_fd_relay()), by
+// child process handle: wait() joins them, so that once it returns, the child's
+// output is in the fds. Stdin relays are not here: they may block on their fd.
+static FD_RELAYS: Mutex<BTreeMap<u64, Vec<SysHandle>>> = Mutex::new(BTreeMap::new());
+
+// Called after @process has exited.
+pub fn wait_fd_relays(process: u64) {
+    let Some(threads) = FD_RELAYS.lock().remove(&process) else {
+        return;
+    };
+    for thread in threads {
+        crate::rt_thread::join(thread.as_u64());
+        moto_sys::SysObj::put(thread).unwrap();
+    }
+}
+
+// Called if spawning @process failed.
+fn forget_fd_relays(process: u64) {
+    if let Some(threads) = FD_RELAYS.lock().remove(&process) {
+        for thread in threads {
+            moto_sys::SysObj::put(thread).unwrap();
+        }
+    }
+}
+
+// Only files and pipes can be relayed to/from (see SpawnArgs).
+fn check_relay_fd(fd: RtFd) -> Result<(), ErrorCode> {
+    use crate::util::fd::{Fd, DESCRIPTORS};
+
+    match DESCRIPTORS.get(fd).as_deref() {
+        Some(Fd::File(_)) | Some(Fd::Pipe(_)) => Ok(()),
+        Some(_) => Err(E_INVALID_ARGUMENT),
+        None => Err(E_BAD_HANDLE),
+    }
+}
+
+// Relays the child's stdio pipe (@pipe is our end) to or from @fd, an open file
+// or pipe. The relay thread owns @fd: it closes it when done, i.e. on EOF for stdin,
+// or when the child is gone.
+fn set_fd_relay(
+    kind: RtFd,
+    fd: RtFd,
+    pipe: moto_ipc::sync_pipe::RawPipeData,
+) -> Result<SysHandle, ErrorCode> {
+    use moto_ipc::sync_pipe::{RawPipeData, Reader, Writer};
+
+    struct RelayArg {
+        stdin: bool,
+        fd: RtFd,
+        pipe: RawPipeData,
+    }
+    extern "C" fn relay_thread_fn(thread_arg: u64) {
+        let arg = unsafe { Box::from_raw(thread_arg as usize as *mut RelayArg) };
+        let RelayArg { stdin, fd, pipe } = *arg;
+        let mut buf = [0_u8; 256];
+
+        if stdin {
+            let mut dest = unsafe { Writer::new(pipe) };
+            loop {
+                let sz = crate::rt_fs::read(fd, buf.as_mut_ptr(), buf.len());
+                if sz <= 0 {
+                    break; // EOF or error.
+                }
+                if dest.write(&buf[0..(sz as usize)]).is_err() {
+                    break;
+                }
+            }
+            drop(dest); // The child sees EOF.
+        } else {
+            let mut src = unsafe { Reader::new(pipe) };
+            'outer: while let Ok(sz) = src.read(&mut buf) {
+                let mut written = 0;
+                while written < sz {
+                    let res = crate::rt_fs::write(fd, buf[written..].as_ptr(), sz - written);
+                    if res <= 0 {
+                        break 'outer;
+                    }
+                    written += res as usize;
+                }
+            }
+            drop(src);
+        }
+
+        crate::rt_fs::close(fd);
+        let _ = moto_sys::SysObj::put(SysHandle::SELF);
+        unreachable!()
+    } // relay_thread_fn
+
+    let local_copy = pipe.unsafe_copy();
+    let thread_arg = Box::into_raw(Box::new(RelayArg {
+        stdin: kind == moto_rt::FD_STDIN,
+        fd,
+        pipe,
+    })) as usize as u64;
+
+    #[cfg(debug_assertions)]
+    const RELAY_THREAD_STACK_SIZE: usize = 1024 * 16;
+    #[cfg(not(debug_assertions))]
+    const RELAY_THREAD_STACK_SIZE: usize = 1024 * 4;
+
+    moto_sys::SysCpu::spawn(
+        SysHandle::SELF,
+        RELAY_THREAD_STACK_SIZE as u64,
+        relay_thread_fn as usize as u64,
+        thread_arg,
+    )
+    .map_err(|err| {
+        unsafe {
+            drop(Box::from_raw(thread_arg as *mut RelayArg));
+            local_copy.release(SysHandle::SELF);
+        }
+
+        err
+    })
+    .map(|handle| SysHandle::from(handle))
+}
+
 pub fn init() {
     use crate::util::fd::{Fd, DESCRIPTORS};
     use alloc::sync::Arc;
@@ -309,13 +431,28 @@ pub fn create_child_stdio(
     remote_process_data: *mut ProcessData,
     args_rt: &moto_rt::process::SpawnArgsRt,
 ) -> Result<(RtFd, RtFd, RtFd), ErrorCode> {
+    // Reject bad fds before any of them is taken over by a relay.
+    for fd in [args_rt.stdin, args_rt.stdout, args_rt.stderr] {
+        if fd >= 0 {
+            check_relay_fd(fd)?;
+        }
+    }
+
     // If command has stdin/out/err, take those, otherwise use default.
-    let (stdin, stdin_theirs) =
-        create_stdio_pipes(remote_process, args_rt.stdin, moto_rt::FD_STDIN)?;
-    let (stdout, stdout_theirs) =
-        create_stdio_pipes(remote_process, args_rt.stdout, moto_rt::FD_STDOUT)?;
-    let (stderr, stderr_theirs) =
-        create_stdio_pipes(remote_process, args_rt.stderr, moto_rt::FD_STDERR)?;
+    let pipes = (|| -> Result<_, ErrorCode> {
+        Ok((
+            create_stdio_pipes(remote_process, args_rt.stdin, moto_rt::FD_STDIN)?,
+            create_stdio_pipes(remote_process, args_rt.stdout, moto_rt::FD_STDOUT)?,
+            create_stdio_pipes(remote_process, args_rt.stderr, moto_rt::FD_STDERR)?,
+        ))
+    })();
+    let ((stdin, stdin_theirs), (stdout, stdout_theirs), (stderr, stderr_theirs)) = match pipes {
+        Ok(pipes) => pipes,
+        Err(err) => {
+            forget_fd_relays(remote_process.as_u64());
+            return Err(err);
+        }
+    };
 
     unsafe {
         let pd = remote_process_data.as_mut().unwrap();
@@ -403,6 +540,37 @@ fn create_stdio_pipes(
                 ))
             }
         }
-        fd => panic!("fd: {fd}"),
+        fd if fd >= 0 => {
+            // The child's stdio is relayed to/from the fd (see SpawnArgs).
+            check_relay_fd(fd)?;
+
+            let (local_data, remote_data) =
+                moto_ipc::sync_pipe::make_pair(moto_sys::SysHandle::SELF, remote_process)?;
+            let thread = set_fd_relay(kind, fd, local_data).map_err(|err| {
+                unsafe {
+                    remote_data.unsafe_copy().release(remote_process);
+                }
+                err
+            })?;
+            if kind == moto_rt::FD_STDIN {
+                moto_sys::SysObj::put(thread).unwrap(); // Detached, as above.
+            } else {
+                FD_RELAYS
+                    .lock()
+                    .entry(remote_process.as_u64())
+                    .or_default()
+                    .push(thread);
+            }
+
+            Ok((
+                moto_rt::process::STDIO_NULL,
+                StdioData {
+                    pipe_addr: remote_data.buf_addr as u64,
+                    pipe_size: remote_data.buf_size as u64,
+                    handle: remote_data.ipc_handle,
+                },
+            ))
+        }
+        _ => Err(E_INVALID_ARGUMENT),
     }
 }
@@ -19,6 +19,8 @@ pub enum Fd {
     ReadDir(crate::rt_fs::ReadDir),
     TcpStream(Arc<crate::rt_net::TcpStream>),
     TcpListener(Arc<crate::rt_net::TcpListener>),
+    UdpSocket(Arc<crate::rt_net::UdpSocket>),
+    Poll(Arc<crate::rt_net::Poll>),
 }
 
 type Entry<T> = Mutex<Option<Arc<T>>>;
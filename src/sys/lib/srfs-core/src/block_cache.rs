@@ -61,8 +61,13 @@ impl BlockCache {
         block.block_no = block_no;
 
         self.block_reads += 1;
-        self.block_device
-            .read_block(block_no, block.block.as_bytes_mut())?;
+        if let Err(err) = self
+            .block_device
+            .read_block(block_no, block.block.as_bytes_mut())
+        {
+            block.block_no = u64::MAX; // Don't cache garbage.
+            return Err(err);
+        }
         self.push_top(CACHE_SIZE - 1);
         Ok(&self.blocks[0])
     }
@@ -82,8 +87,13 @@ impl BlockCache {
         block.block_no = block_no;
 
         self.block_reads += 1;
-        self.block_device
-            .read_block(block_no, block.block.as_bytes_mut())?;
+        if let Err(err) = self
+            .block_device
+            .read_block(block_no, block.block.as_bytes_mut())
+        {
+            block.block_no = u64::MAX; // Don't cache garbage.
+            return Err(err);
+        }
         self.push_top(CACHE_SIZE - 1);
         self.blocks[0].dirty = true;
         Ok(&mut self.blocks[0])
@@ -127,10 +137,17 @@ impl BlockCache {
                 self.push_top(idx);
                 debug_assert!(self.blocks[0].dirty);
                 self.block_writes += 1;
-                self.block_device
-                    .write_block(block_no, self.blocks[0].block.as_bytes())?;
+                let result = self
+                    .block_device
+                    .write_block(block_no, self.blocks[0].block.as_bytes());
+                if result.is_err() {
+                    // Drop the change, so that the block is re-read from the
+                    // device: the operation fails, but the cache stays
+                    // consistent with what is on disk.
+                    self.blocks[0].block_no = u64::MAX;
+                }
                 self.blocks[0].dirty = false;
-                return Ok(());
+                return result;
             }
         }
 
@@ -155,6 +172,34 @@ impl BlockCache {
         self.block_device.write_block(block_no, block.as_bytes())
     }
 
+    // Reads consecutive blocks straight into @buf. Cached blocks are clean
+    // between FS operations (see write()), so the device has their data too.
+    pub(crate) fn read_uncached_blocks(
+        &mut self,
+        block_no: u64,
+        buf: &mut [u8],
+    ) -> Result<(), FsError> {
+        self.block_reads += (buf.len() as u64) >> BLOCK_SIZE.ilog2();
+        self.block_device.read_blocks(block_no, buf)
+    }
+
+    // Writes consecutive blocks from @buf, dropping their cached copies.
+    pub(crate) fn write_uncached_blocks(
+        &mut self,
+        block_no: u64,
+        buf: &[u8],
+    ) -> Result<(), FsError> {
+        let end = block_no + ((buf.len() as u64) >> BLOCK_SIZE.ilog2());
+        for block in self.blocks.iter_mut() {
+            if block.block_no >= block_no && block.block_no < end {
+                debug_assert!(!block.dirty);
+                block.block_no = u64::MAX;
+            }
+        }
+        self.block_writes += end - block_no;
+        self.block_device.write_blocks(block_no, buf)
+    }
+
     fn push_top(&mut self, idx: usize) {
         debug_assert!(idx < CACHE_SIZE);
         let mut pos = idx;
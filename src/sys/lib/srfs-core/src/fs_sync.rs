@@ -965,7 +965,10 @@ impl SyncFileSystem {
         Ok(())
     }
 
-    /// Write buf to file at offset. At most 4096 bytes will be written.
+    /// Write buf to file at offset. At most 4096 bytes will be written, unless
+    /// the write overwrites whole blocks that are consecutive on the device,
+    /// and both offset and buf are aligned at BLOCK_SIZE: then all of them
+    /// are written in one device request.
     ///
     /// A write may succeed with the resulting usize less than buf.len; this usually
     /// happens when the write would otherwise cross a block boundary. Just do another
@@ -1014,12 +1017,15 @@ impl SyncFileSystem {
         }
 
         if new_size == prev_size {
-            return self.update_file(file_id, offset, buf);
+            return self.update_file(file_id, offset, &buf[0..((new_end - offset) as usize)]);
         } else {
             return self.append(file_id, offset, buf);
         }
     }
 
+    /// Read from file at offset into buf. As with write(), reads stop at the
+    /// next block boundary, unless whole blocks consecutive on the device are
+    /// read into an aligned buf.
     pub fn read(
         &mut self,
         file_id: EntryId,
@@ -1052,6 +1058,18 @@ impl SyncFileSystem {
         }
 
         let data_block_no = self.find_data_block(file_id, offset)?;
+        let whole_blocks = (end - offset) >> BLOCK_SIZE.ilog2();
+        if whole_blocks > 1 && is_block_aligned(offset, buf.as_ptr()) {
+            let blocks =
+                self.consecutive_data_blocks(file_id, offset, data_block_no, whole_blocks)?;
+            if blocks > 1 {
+                let len = (blocks << BLOCK_SIZE.ilog2()) as usize;
+                self.blockcache
+                    .read_uncached_blocks(data_block_no, &mut buf[0..len])?;
+                return Ok(len);
+            }
+        }
+
         let block_end = align_up(offset + 1, BLOCK_SIZE);
         let new_end = (offset + (buf.len() as u64)).min(block_end);
         let data_block = self.blockcache.read(data_block_no)?;
@@ -1066,6 +1084,166 @@ impl SyncFileSystem {
         return Ok((new_end - offset) as usize);
     }
 
+    /// The number of data blocks of the file; zero if its bytes are in its
+    /// metadata block (files of at most 3968 bytes).
+    pub fn get_file_data_blocks(&mut self, file_id: EntryId) -> Result<u64, FsError> {
+        self.error?;
+        if file_id.kind() != EntryKind::File {
+            return Err(FsError::InvalidArgument);
+        }
+        let meta_block = self.blockcache.read(file_id.block_no)?;
+        let meta = unsafe { meta_block.block().get::<EntryMetadata>() };
+        meta.validate_file(file_id)?;
+
+        if meta.size <= MAX_BYTES_IN_META_BLOCK {
+            Ok(0)
+        } else {
+            Ok(meta.size.div_ceil(BLOCK_SIZE))
+        }
+    }
+
+    /// The block map of the file: fills @buf with the block numbers of its
+    /// data blocks, starting with data block @first_block. Returns the number
+    /// of entries filled (less than buf.len() at the end of the file).
+    pub fn get_file_block_map(
+        &mut self,
+        file_id: EntryId,
+        first_block: u64,
+        buf: &mut [u64],
+    ) -> Result<usize, FsError> {
+        let num_blocks = self.get_file_data_blocks(file_id)?;
+        let mut filled = 0;
+        for block_idx in first_block..num_blocks {
+            if filled == buf.len() {
+                break;
+            }
+            buf[filled] = self.find_data_block(file_id, block_idx << BLOCK_SIZE.ilog2())?;
+            filled += 1;
+        }
+
+        Ok(filled)
+    }
+
+    /// The number of extents of the file, i.e. of runs of data blocks that
+    /// are consecutive on the device. Zero if the file has no data blocks.
+    pub fn get_file_extents(&mut self, file_id: EntryId) -> Result<u64, FsError> {
+        let num_blocks = self.get_file_data_blocks(file_id)?;
+        let mut extents = 0;
+        let mut prev_block_no = u64::MAX;
+        for block_idx in 0..num_blocks {
+            let block_no = self.find_data_block(file_id, block_idx << BLOCK_SIZE.ilog2())?;
+            if prev_block_no == u64::MAX || block_no != prev_block_no + 1 {
+                extents += 1;
+            }
+            prev_block_no = block_no;
+        }
+
+        Ok(extents)
+    }
+
+    /// Moves data block @block_idx of the file to the first block of the empty
+    /// area (see SuperblockHeader), and returns the new block number.
+    ///
+    /// The data is copied to the new block first, and then the link to it
+    /// is updated with a single block write, so the file is readable (and
+    /// consistent) at any point. The old block is then freed.
+    pub fn relocate_data_block(
+        &mut self,
+        file_id: EntryId,
+        block_idx: u64,
+    ) -> Result<u64, FsError> {
+        if block_idx >= self.get_file_data_blocks(file_id)? {
+            return Err(FsError::InvalidArgument);
+        }
+        {
+            let sbh = self.superblock.header();
+            if sbh.empty_area_start >= sbh.num_blocks {
+                return Err(FsError::FsFull);
+            }
+        }
+
+        let old_block_no = self.find_data_block(file_id, block_idx << BLOCK_SIZE.ilog2())?;
+        let (link_block_no, link_idx, in_meta) = self.find_data_block_link(file_id, block_idx)?;
+        let data = *self.blockcache.read(old_block_no)?.block();
+
+        self.start_txn(TXN_TYPE_RELOCATE, file_id)?;
+        let new_block_no = self.allocate_txn_block_from_empty_area(BlockType::Data)?;
+
+        let new_block = self.blockcache.get_block_uninit(new_block_no);
+        *new_block.block_mut() = data;
+        self.blockcache.write(new_block_no).map_err(|e| {
+            let _ = self.make_error();
+            e
+        })?;
+
+        // Switch the file to the new block.
+        let link_block = self.blockcache.read_mut(link_block_no);
+        if let Err(err) = link_block {
+            let _ = self.make_error();
+            return Err(err);
+        }
+        let link_block = link_block.unwrap();
+        if in_meta {
+            link_block
+                .block_mut()
+                .set_datablock_no_in_meta(link_idx, new_block_no);
+        } else {
+            link_block
+                .block_mut()
+                .set_datablock_no_in_link(link_idx, new_block_no);
+        }
+        self.blockcache.write(link_block_no).map_err(|e| {
+            let _ = self.make_error();
+            e
+        })?;
+
+        // Free the old block.
+        self.superblock.header_mut().txn_data_block = old_block_no;
+        self.free_txn_block(BlockType::Data)?;
+        self.commit_txn()?;
+
+        Ok(new_block_no)
+    }
+
+    /// Defragments the file, @max_blocks data blocks at a time, starting with
+    /// data block @first_block: moves them, in order, to the empty area (see
+    /// relocate_data_block()). Returns the next data block to move, which is
+    /// the number of data blocks of the file once it is done; call again with
+    /// that to continue, so that the file can be defragmented in the background,
+    /// while it is being read. Blocks freed here are reused first by later
+    /// allocations, so a defragmented file stays contiguous unless the empty
+    /// area is also allocated from between the calls.
+    ///
+    /// A file that is already contiguous is not moved. Fails with FsFull,
+    /// without moving anything, if the rest of the file does not fit into the
+    /// empty area.
+    pub fn defragment_file(
+        &mut self,
+        file_id: EntryId,
+        first_block: u64,
+        max_blocks: u64,
+    ) -> Result<u64, FsError> {
+        let num_blocks = self.get_file_data_blocks(file_id)?;
+        if first_block >= num_blocks {
+            return Ok(num_blocks);
+        }
+        if first_block == 0 && self.get_file_extents(file_id)? <= 1 {
+            return Ok(num_blocks);
+        }
+
+        let sbh = self.superblock.header();
+        if sbh.num_blocks - sbh.empty_area_start < num_blocks - first_block {
+            return Err(FsError::FsFull);
+        }
+
+        let end = num_blocks.min(first_block.saturating_add(max_blocks));
+        for block_idx in first_block..end {
+            self.relocate_data_block(file_id, block_idx)?;
+        }
+
+        Ok(end)
+    }
+
     fn append(&mut self, file_id: EntryId, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
         self.error?;
         // We may potentially need to allocate three new blocks:
@@ -1391,8 +1569,21 @@ impl SyncFileSystem {
         return Ok((new_size - prev_size) as usize);
     }
 
+    // @buf does not go past the end of the file.
     fn update_file(&mut self, file_id: EntryId, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
         let data_block_no = self.find_data_block(file_id, offset)?;
+        let whole_blocks = (buf.len() as u64) >> BLOCK_SIZE.ilog2();
+        if whole_blocks > 1 && is_block_aligned(offset, buf.as_ptr()) {
+            let blocks =
+                self.consecutive_data_blocks(file_id, offset, data_block_no, whole_blocks)?;
+            if blocks > 1 {
+                let len = (blocks << BLOCK_SIZE.ilog2()) as usize;
+                self.blockcache
+                    .write_uncached_blocks(data_block_no, &buf[0..len])?;
+                return Ok(len);
+            }
+        }
+
         let block_end = align_up(offset + 1, BLOCK_SIZE);
         let new_end = (offset + (buf.len() as u64)).min(block_end);
         let data_block = self.blockcache.read_mut(data_block_no)?;
@@ -1408,6 +1599,27 @@ impl SyncFileSystem {
         return Ok((new_end - offset) as usize);
     }
 
+    // The number of data blocks of the file, starting with @data_block_no
+    // at @offset, that are consecutive on the device; at most @max_blocks,
+    // which must not go past the end of the file.
+    fn consecutive_data_blocks(
+        &mut self,
+        file_id: EntryId,
+        offset: u64,
+        data_block_no: u64,
+        max_blocks: u64,
+    ) -> Result<u64, FsError> {
+        let mut blocks = 1;
+        while blocks < max_blocks {
+            let next = self.find_data_block(file_id, offset + (blocks << BLOCK_SIZE.ilog2()))?;
+            if next != data_block_no + blocks {
+                break;
+            }
+            blocks += 1;
+        }
+        Ok(blocks)
+    }
+
     fn find_data_block(&mut self, file_id: EntryId, offset: u64) -> Result<u64, FsError> {
         let meta_block = self.blockcache.get(file_id.block_no);
         let meta = unsafe { meta_block.block().get::<EntryMetadata>() };
@@ -1455,6 +1667,52 @@ impl SyncFileSystem {
         return Ok(link_block.block().get_datablock_no_in_link(data_block_idx));
     }
 
+    // Where the link to data block @block_idx of the file is: (the block
+    // number of the metadata or link block, the index of the link in it,
+    // whether it is the metadata block).
+    fn find_data_block_link(
+        &mut self,
+        file_id: EntryId,
+        block_idx: u64,
+    ) -> Result<(u64, u64, bool), FsError> {
+        let meta_block = self.blockcache.get(file_id.block_no);
+        let meta = unsafe { meta_block.block().get::<EntryMetadata>() };
+        let file_size = meta.size;
+        let offset = block_idx << BLOCK_SIZE.ilog2();
+        debug_assert!(file_size > MAX_BYTES_IN_META_BLOCK);
+        debug_assert!(offset < file_size);
+
+        if file_size <= MAX_BYTES_ONLY_DATA_BLOCKS {
+            // Files smaller than ~2M.
+            return Ok((file_id.block_no, block_idx, true));
+        }
+
+        let data_block_idx =
+            (offset & (BYTES_COVERED_BY_FIRST_LEVEL_BLOCKLIST - 1)) >> BLOCK_SIZE.ilog2();
+        if file_size <= MAX_BYTES_SINGLE_LEVEL_LIST_BLOCKS {
+            // Files smaller than ~1G.
+            let link_block_idx = offset >> BYTES_COVERED_BY_FIRST_LEVEL_BLOCKLIST.ilog2();
+            let meta_block = self.blockcache.get(file_id.block_no);
+            let link_block_no = meta_block.block().get_datablock_no_in_meta(link_block_idx);
+            return Ok((link_block_no, data_block_idx, false));
+        }
+
+        // Files larger than ~1G.
+        let list_of_links_block_idx = offset >> BYTES_COVERED_BY_SECOND_LEVEL_BLOCKLIST.ilog2();
+        let meta_block = self.blockcache.get(file_id.block_no);
+        let list_of_links_block_no = meta_block
+            .block()
+            .get_datablock_no_in_meta(list_of_links_block_idx);
+
+        let list_of_links_block = self.blockcache.read(list_of_links_block_no)?;
+        let link_block_idx = (offset & (BYTES_COVERED_BY_SECOND_LEVEL_BLOCKLIST - 1))
+            >> BYTES_COVERED_BY_FIRST_LEVEL_BLOCKLIST.ilog2();
+        let link_block_no = list_of_links_block
+            .block()
+            .get_datablock_no_in_link(link_block_idx);
+        Ok((link_block_no, data_block_idx, false))
+    }
+
     fn find_entry_by_id(
         &mut self,
         parent_id: EntryId,
@@ -1648,6 +1906,31 @@ impl SyncFileSystem {
         Ok(new_block_no)
     }
 
+    // Same as allocate_txn_block(), but never takes the block from the freelist.
+    fn allocate_txn_block_from_empty_area(
+        &mut self,
+        block_type: BlockType,
+    ) -> Result<u64, FsError> {
+        let fbh = self.superblock.header_mut();
+        assert_ne!(0, fbh.txn_blocks_owner);
+        if fbh.free_blocks == 0 || fbh.empty_area_start >= fbh.num_blocks {
+            return Err(FsError::FsFull);
+        }
+
+        let new_block_no = fbh.empty_area_start;
+        fbh.empty_area_start += 1;
+        fbh.free_blocks -= 1;
+        match block_type {
+            BlockType::Metadata => fbh.txn_meta_block = new_block_no,
+            BlockType::Data => fbh.txn_data_block = new_block_no,
+            BlockType::Links => fbh.txn_link_block = new_block_no,
+            BlockType::ListOfLinks => fbh.txn_list_of_links_block = new_block_no,
+        }
+        self.save_superblock()?;
+
+        Ok(new_block_no)
+    }
+
     fn free_txn_block(&mut self, block_type: BlockType) -> Result<(), FsError> {
         let sbh = self.superblock.header_mut();
         let block_no = match block_type {
@@ -1664,6 +1947,7 @@ impl SyncFileSystem {
             let block = self.blockcache.get_block_uninit(block_no);
             unsafe { *block.block_mut().get_mut::<u64>() = prev_head };
             self.blockcache.write(block_no)?;
+            sbh.freelist_head = block_no;
         }
 
         match block_type {
@@ -87,6 +87,12 @@ impl EntryId {
         }
     }
 
+    /// Unique for the lifetime of the filesystem, so it identifies the entry
+    /// (like an inode number) even after it has been deleted.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
     pub fn kind(&self) -> EntryKind {
         if (self.generation & 1) == 1 {
             EntryKind::Directory
@@ -355,6 +361,7 @@ pub(crate) const TXN_TYPE_ADD_BYTES: u32 = 2;
 pub(crate) const TXN_TYPE_REMOVE_NODE: u32 = 3;
 pub(crate) const TXN_TYPE_REMOVE_BYTES: u32 = 4;
 pub(crate) const TXN_TYPE_MOVE: u32 = 5;
+pub(crate) const TXN_TYPE_RELOCATE: u32 = 6;
 
 // The partition:
 // - the first block
@@ -597,3 +604,8 @@ pub(crate) fn align_up(what: u64, how: u64) -> u64 {
     debug_assert!(how.is_power_of_two());
     (what + how - 1) & !(how - 1)
 }
+
+// Whether I/O at @offset into @buf can go straight to the device.
+pub(crate) fn is_block_aligned(offset: u64, buf: *const u8) -> bool {
+    (offset & (BLOCK_SIZE - 1)) == 0 && ((buf as usize) & (BLOCK_SIZE as usize - 1)) == 0
+}
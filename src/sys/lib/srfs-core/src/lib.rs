@@ -64,6 +64,24 @@ pub trait SyncBlockDevice {
 
     /// Write a single block. Same alignment requirements as in read_block.
     fn write_block(&mut self, block_no: u64, buf: &[u8]) -> Result<(), FsError>;
+
+    /// Read consecutive blocks, starting at block_no, into buf; devices
+    /// that can should do this as a single request. buf must be aligned
+    /// to BLOCK_SIZE, and its length a multiple of BLOCK_SIZE.
+    fn read_blocks(&mut self, block_no: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        for (idx, block) in buf.chunks_mut(BLOCK_SIZE as usize).enumerate() {
+            self.read_block(block_no + (idx as u64), block)?;
+        }
+        Ok(())
+    }
+
+    /// Write consecutive blocks; see read_blocks().
+    fn write_blocks(&mut self, block_no: u64, buf: &[u8]) -> Result<(), FsError> {
+        for (idx, block) in buf.chunks(BLOCK_SIZE as usize).enumerate() {
+            self.write_block(block_no + (idx as u64), block)?;
+        }
+        Ok(())
+    }
 }
 
 /// Initializes the block device so that it has an SFFS with a single/empty root dir.
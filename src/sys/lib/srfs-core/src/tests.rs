@@ -416,3 +416,270 @@ fn large_file() {
     drop(fs);
     std::fs::remove_file(path.clone()).unwrap();
 }
+
+#[test]
+fn defragment() {
+    const NUM_BLOCKS: u64 = 256;
+    const FILE_BLOCKS: u64 = 8;
+    let path = std::env::temp_dir().join("fs_dev_defragment");
+    std::fs::remove_file(path.clone()).ok();
+
+    let mut bd = Box::new(FileBlockDevice::create(&path, NUM_BLOCKS).unwrap());
+    crate::fs_sync::format(bd.as_mut()).unwrap();
+    let mut fs = SyncFileSystem::open_fs(bd).unwrap();
+
+    let root = SyncFileSystem::root_dir_id();
+    let small = fs.add_file(root, "small").unwrap();
+    fs.write(small, 0, "tiny".as_bytes()).unwrap();
+    assert_eq!(0, fs.get_file_data_blocks(small).unwrap());
+    assert_eq!(0, fs.get_file_extents(small).unwrap());
+
+    // Interleave appends of two files, so that neither is contiguous.
+    let first = fs.add_file(root, "first").unwrap();
+    let second = fs.add_file(root, "second").unwrap();
+    let mut block = [0_u8; BLOCK_SIZE as usize];
+    for idx in 0..FILE_BLOCKS {
+        let offset = idx * BLOCK_SIZE;
+        block.fill(idx as u8);
+        assert_eq!(block.len(), fs.write(first, offset, &block).unwrap());
+        block.fill(0x80 | (idx as u8));
+        assert_eq!(block.len(), fs.write(second, offset, &block).unwrap());
+    }
+    assert_eq!(FILE_BLOCKS, fs.get_file_data_blocks(first).unwrap());
+    assert_eq!(FILE_BLOCKS, fs.get_file_extents(first).unwrap());
+    let empty_blocks = fs.empty_blocks();
+
+    // Defragment in steps; the file must stay readable in between.
+    let mut next = 0;
+    while next < FILE_BLOCKS {
+        next = fs.defragment_file(first, next, 3).unwrap();
+        for idx in 0..FILE_BLOCKS {
+            let mut buf = [0_u8; BLOCK_SIZE as usize];
+            assert_eq!(
+                buf.len(),
+                fs.read(first, idx * BLOCK_SIZE, &mut buf).unwrap()
+            );
+            assert!(buf.iter().all(|b| *b == idx as u8));
+        }
+    }
+    assert_eq!(FILE_BLOCKS, next);
+    assert_eq!(1, fs.get_file_extents(first).unwrap());
+    assert_eq!(empty_blocks, fs.empty_blocks());
+
+    let mut map = [0_u64; FILE_BLOCKS as usize];
+    assert_eq!(
+        map.len(),
+        fs.get_file_block_map(first, 0, &mut map).unwrap()
+    );
+    for idx in 1..map.len() {
+        assert_eq!(map[idx - 1] + 1, map[idx]);
+    }
+
+    // A contiguous file is not moved.
+    assert_eq!(FILE_BLOCKS, fs.defragment_file(first, 0, 100).unwrap());
+    let mut map_2 = [0_u64; FILE_BLOCKS as usize];
+    fs.get_file_block_map(first, 0, &mut map_2).unwrap();
+    assert_eq!(map, map_2);
+
+    // The other file is intact, and the freed blocks are reused.
+    for idx in 0..FILE_BLOCKS {
+        let mut buf = [0_u8; BLOCK_SIZE as usize];
+        fs.read(second, idx * BLOCK_SIZE, &mut buf).unwrap();
+        assert!(buf.iter().all(|b| *b == 0x80 | (idx as u8)));
+    }
+    let third = fs.add_file(root, "third").unwrap();
+    block.fill(0xff);
+    fs.write(third, 0, &block).unwrap();
+    let mut third_block = [0_u64; 1];
+    fs.get_file_block_map(third, 0, &mut third_block).unwrap();
+    assert!(third_block[0] < map[0]);
+
+    // Everything is still there after a remount.
+    drop(fs);
+    let bd = Box::new(FileBlockDevice::open(&path).unwrap());
+    let mut fs = SyncFileSystem::open_fs(bd).unwrap();
+    let first = fs.get_directory_entry_by_name(root, "first").unwrap().id;
+    assert_eq!(1, fs.get_file_extents(first).unwrap());
+    for idx in 0..FILE_BLOCKS {
+        let mut buf = [0_u8; BLOCK_SIZE as usize];
+        fs.read(first, idx * BLOCK_SIZE, &mut buf).unwrap();
+        assert!(buf.iter().all(|b| *b == idx as u8));
+    }
+
+    drop(fs);
+    std::fs::remove_file(path.clone()).unwrap();
+}
+
+// Counts device requests.
+struct CountingBlockDevice {
+    inner: FileBlockDevice,
+    requests: alloc::sync::Arc<core::sync::atomic::AtomicU64>,
+}
+
+impl crate::SyncBlockDevice for CountingBlockDevice {
+    fn num_blocks(&self) -> u64 {
+        self.inner.num_blocks()
+    }
+
+    fn read_block(&mut self, block_no: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        self.requests
+            .fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+        self.inner.read_block(block_no, buf)
+    }
+
+    fn write_block(&mut self, block_no: u64, buf: &[u8]) -> Result<(), FsError> {
+        self.requests
+            .fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+        self.inner.write_block(block_no, buf)
+    }
+
+    fn read_blocks(&mut self, block_no: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        self.requests
+            .fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+        self.inner.read_blocks(block_no, buf)
+    }
+
+    fn write_blocks(&mut self, block_no: u64, buf: &[u8]) -> Result<(), FsError> {
+        self.requests
+            .fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+        self.inner.write_blocks(block_no, buf)
+    }
+}
+
+#[test]
+fn multi_block_io() {
+    use core::sync::atomic::Ordering;
+
+    const NUM_BLOCKS: u64 = 64;
+    const FILE_BLOCKS: usize = 8;
+    let path = std::env::temp_dir().join("fs_dev_multi_block_io");
+    std::fs::remove_file(path.clone()).ok();
+
+    #[repr(C, align(4096))]
+    struct Blocks([u8; FILE_BLOCKS * BLOCK_SIZE as usize]);
+
+    let mut bd = Box::new(FileBlockDevice::create(&path, NUM_BLOCKS).unwrap());
+    crate::fs_sync::format(bd.as_mut()).unwrap();
+    drop(bd);
+    let requests = alloc::sync::Arc::new(core::sync::atomic::AtomicU64::new(0));
+    let bd = Box::new(CountingBlockDevice {
+        inner: FileBlockDevice::open(&path).unwrap(),
+        requests: requests.clone(),
+    });
+    let mut fs = SyncFileSystem::open_fs(bd).unwrap();
+
+    // Appends go one block at a time.
+    let root = SyncFileSystem::root_dir_id();
+    let file = fs.add_file(root, "file").unwrap();
+    let mut buf = Box::new(Blocks([0; FILE_BLOCKS * BLOCK_SIZE as usize]));
+    for (idx, byte) in buf.0.iter_mut().enumerate() {
+        *byte = (idx % 251) as u8;
+    }
+    let mut done = 0;
+    while done < buf.0.len() {
+        let written = fs.write(file, done as u64, &buf.0[done..]).unwrap();
+        assert!(written <= BLOCK_SIZE as usize);
+        done += written;
+    }
+    assert_eq!(1, fs.get_file_extents(file).unwrap());
+
+    // Cache a data block, to check that it is not stale after the overwrite.
+    let mut small = [0_u8; 16];
+    assert_eq!(16, fs.read(file, 2 * BLOCK_SIZE, &mut small).unwrap());
+
+    // An aligned overwrite of consecutive blocks is a single request.
+    for (idx, byte) in buf.0.iter_mut().enumerate() {
+        *byte = (idx % 241) as u8;
+    }
+    let before = requests.load(Ordering::Relaxed);
+    assert_eq!(buf.0.len(), fs.write(file, 0, &buf.0).unwrap());
+    assert_eq!(before + 1, requests.load(Ordering::Relaxed));
+
+    assert_eq!(16, fs.read(file, 2 * BLOCK_SIZE, &mut small).unwrap());
+    assert_eq!(
+        &small,
+        &buf.0[(2 * BLOCK_SIZE as usize)..(2 * BLOCK_SIZE as usize + 16)]
+    );
+
+    // So is an aligned read; the last block is partial, so it is not included.
+    let mut read_back = Box::new(Blocks([0; FILE_BLOCKS * BLOCK_SIZE as usize]));
+    fs.set_file_size(file, (FILE_BLOCKS as u64) * BLOCK_SIZE - 100)
+        .unwrap();
+    let before = requests.load(Ordering::Relaxed);
+    let read = fs.read(file, 0, &mut read_back.0).unwrap();
+    assert_eq!(read, (FILE_BLOCKS - 1) * BLOCK_SIZE as usize);
+    assert_eq!(before + 1, requests.load(Ordering::Relaxed));
+    assert_eq!(&read_back.0[0..read], &buf.0[0..read]);
+
+    // Unaligned reads still work, one block at a time.
+    let read = fs.read(file, 1, &mut read_back.0).unwrap();
+    assert_eq!(read, BLOCK_SIZE as usize - 1);
+    assert_eq!(&read_back.0[0..read], &buf.0[1..(read + 1)]);
+
+    drop(fs);
+    std::fs::remove_file(path.clone()).unwrap();
+}
+
+// Fails all requests while `failing` is set.
+struct FlakyBlockDevice {
+    inner: FileBlockDevice,
+    failing: alloc::sync::Arc<core::sync::atomic::AtomicBool>,
+}
+
+impl crate::SyncBlockDevice for FlakyBlockDevice {
+    fn num_blocks(&self) -> u64 {
+        self.inner.num_blocks()
+    }
+
+    fn read_block(&mut self, block_no: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        if self.failing.load(core::sync::atomic::Ordering::Relaxed) {
+            return Err(FsError::IoError);
+        }
+        self.inner.read_block(block_no, buf)
+    }
+
+    fn write_block(&mut self, block_no: u64, buf: &[u8]) -> Result<(), FsError> {
+        if self.failing.load(core::sync::atomic::Ordering::Relaxed) {
+            return Err(FsError::IoError);
+        }
+        self.inner.write_block(block_no, buf)
+    }
+}
+
+#[test]
+fn block_cache_io_errors() {
+    use crate::block_cache::BlockCache;
+    use crate::Block;
+    use crate::SyncBlockDevice;
+    use core::sync::atomic::Ordering;
+
+    const NUM_BLOCKS: u64 = 8;
+    let path = std::env::temp_dir().join("fs_dev_io_errors");
+    std::fs::remove_file(path.clone()).ok();
+
+    let mut inner = FileBlockDevice::create(&path, NUM_BLOCKS).unwrap();
+    let mut block = Box::new(Block::new_zeroed());
+    block.as_bytes_mut()[0] = 42;
+    inner.write_block(3, block.as_bytes()).unwrap();
+
+    let failing = alloc::sync::Arc::new(core::sync::atomic::AtomicBool::new(true));
+    let mut cache = BlockCache::new(Box::new(FlakyBlockDevice {
+        inner,
+        failing: failing.clone(),
+    }));
+
+    // A failed read is not cached.
+    assert_eq!(cache.read(3).err().unwrap(), FsError::IoError);
+    failing.store(false, Ordering::Relaxed);
+    assert_eq!(cache.read(3).unwrap().block().as_bytes()[0], 42);
+
+    // A failed write is dropped: the block is re-read from the device.
+    cache.read_mut(3).unwrap().block_mut().as_bytes_mut()[0] = 7;
+    failing.store(true, Ordering::Relaxed);
+    assert_eq!(cache.write(3).err().unwrap(), FsError::IoError);
+    failing.store(false, Ordering::Relaxed);
+    assert_eq!(cache.read(3).unwrap().block().as_bytes()[0], 42);
+
+    drop(cache);
+    std::fs::remove_file(path.clone()).unwrap();
+}
@@ -20,6 +20,11 @@ impl File {
         }
     }
 
+    /// A number that identifies the file, survives renames, and is never reused.
+    pub fn inode(&self) -> u64 {
+        self.id.generation()
+    }
+
     pub fn size(&mut self) -> Result<u64> {
         self.fs
             .borrow_mut()
@@ -68,6 +73,37 @@ impl File {
             .map_err(error::to_ioerror)
     }
 
+    /// The number of data blocks of the file; zero for small files, whose
+    /// bytes are in the metadata block.
+    pub fn data_blocks(&mut self) -> Result<u64> {
+        self.fs
+            .borrow_mut()
+            .fs_core()
+            .get_file_data_blocks(self.id)
+            .map_err(error::to_ioerror)
+    }
+
+    /// The number of extents of the file: runs of its data blocks that
+    /// are consecutive on the device.
+    pub fn extents(&mut self) -> Result<u64> {
+        self.fs
+            .borrow_mut()
+            .fs_core()
+            .get_file_extents(self.id)
+            .map_err(error::to_ioerror)
+    }
+
+    /// Moves up to `max_blocks` data blocks of the file, starting with
+    /// `first_block`, so that the file becomes contiguous; returns the next
+    /// block to move. See srfs_core::SyncFileSystem::defragment_file().
+    pub fn defragment(&mut self, first_block: u64, max_blocks: u64) -> Result<u64> {
+        self.fs
+            .borrow_mut()
+            .fs_core()
+            .defragment_file(self.id, first_block, max_blocks)
+            .map_err(error::to_ioerror)
+    }
+
     pub fn truncate(&mut self) -> Result<()> {
         todo!()
     }
@@ -15,8 +15,16 @@ pub use pci::le16;
 pub use pci::le32;
 pub use pci::le64;
 
+pub use virtio_blk::blk_stats;
 pub use virtio_blk::lsblk;
+pub use virtio_blk::set_queue_depth as set_blk_queue_depth;
+pub use virtio_blk::BlkStats;
 pub use virtio_device::init_virtio_devices;
+pub use virtio_device::log_virtio_device_info;
+pub use virtio_device::virtio_device_info;
+pub use virtio_device::VirtioDeviceInfo;
+pub use virtio_device::VirtioDeviceKind;
+pub use virtio_rng::get_entropy;
 
 pub(crate) use virtio_device::mapper;
 
@@ -59,8 +67,25 @@ pub const BLOCK_SIZE_LOG2: usize = 9;
 pub trait BlockDevice {
     // buf must be aligned at BLOCK_SIZE.
     fn read(&self, buf: &mut [u8], address: u64, number_of_blocks: usize) -> Result<(), ()>;
+    // The data is durable (the device cache is flushed) when write() returns.
     fn write(&self, buf: &[u8], address: u64, number_of_blocks: usize) -> Result<(), ()>;
+    // The write is completed by the device when write_no_flush() returns, so
+    // it is ordered before any subsequent write, but it may sit in the device's
+    // volatile cache until the next flush().
+    fn write_no_flush(&self, buf: &[u8], address: u64, number_of_blocks: usize) -> Result<(), ()>;
+    // Flush the device cache: all writes completed before the call are durable
+    // when flush() returns. Writes issued after flush() returns are not
+    // reordered before it.
+    fn flush(&self) -> Result<(), ()>;
     fn capacity(&self) -> u64; // In blocks.
+
+    // The device's logical block size, in bytes: a multiple of BLOCK_SIZE.
+    // Addresses (in bytes) and lengths of reads and writes must be multiples
+    // of it; capacity() is still in units of BLOCK_SIZE.
+    fn block_size(&self) -> u32;
+    // The largest request issued to the device, in bytes: larger reads and
+    // writes are split into requests of this size.
+    fn optimal_io_size(&self) -> u32;
 }
 
 pub type WaitHandle = u64;
@@ -1,5 +1,6 @@
 use core::sync::atomic::*;
 
+use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
@@ -27,27 +28,215 @@ use spin::Mutex;
  */
 // Virtio-Blk features used here.
 //const VIRTIO_BLK_F_SIZE_MAX : u64 = 1u64 << 1;
-//const VIRTIO_BLK_F_SEG_MAX  : u64 = 1u64 << 2;
+const VIRTIO_BLK_F_SEG_MAX: u64 = 1u64 << 2;
 const VIRTIO_BLK_F_RO: u64 = 1u64 << 5;
 // const VIRTIO_BLK_F_CONFIG_WCE: u64 = 1u64 << 11;
 const VIRTIO_BLK_F_FLUSH: u64 = 1u64 << 9;
-//const VIRTIO_BLK_F_BLK_SIZE : u64 = 1u64 << 6;
+const VIRTIO_BLK_F_BLK_SIZE: u64 = 1u64 << 6;
+const VIRTIO_BLK_F_TOPOLOGY: u64 = 1u64 << 10;
+
+// Offsets of fields in struct virtio_blk_config.
+const CFG_CAPACITY: u64 = 0;
+const CFG_SEG_MAX: u64 = 12;
+const CFG_BLK_SIZE: u64 = 20;
+const CFG_OPT_IO_SIZE: u64 = 28; // In blk_size units.
+
+// Data buffers are split into descriptors at page boundaries,
+// as they are not physically contiguous.
+const PAGE_SIZE: usize = 4096;
+
+// The largest request issued if the device does not report its
+// optimal I/O size, and the largest request issued at all.
+const DEFAULT_REQUEST_SIZE: usize = 64 << 10;
+const MAX_REQUEST_SIZE: usize = 1 << 20;
+
+// Each request uses at least three descriptors: the header, the data, and the status.
+const MIN_REQUEST_DESCRIPTORS: u16 = 3;
+
+// How many times a request that failed with VIRTIO_BLK_S_IOERR is retried
+// before the error is returned to the caller.
+const MAX_RETRIES: u32 = 3;
+
+/// Stats of a VirtIO block device.
+#[derive(Clone, Copy, Debug)]
+pub struct BlkStats {
+    pub queue_depth: u16,     // Requests issued at once; see set_queue_depth().
+    pub max_queue_depth: u16, // As many as the virtqueue fits.
+    pub block_size: u32,      // The logical block size reported by the device.
+    pub request_size: u32,    // The largest request issued, in bytes.
+    pub in_flight: u32,
+    pub peak_in_flight: u32, // Since the last set_queue_depth().
+    pub completed: u64,
+    pub retried: u64, // Requests that failed and were reissued.
+    pub failed: u64,  // Requests that failed after all retries.
+}
+
+#[derive(Default)]
+struct BlkCounters {
+    queue_depth: AtomicU16,
+    max_queue_depth: AtomicU16,
+    block_size: AtomicU32,
+    request_size: AtomicU32,
+    in_flight: AtomicU32,
+    peak_in_flight: AtomicU32,
+    completed: AtomicU64,
+    retried: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl BlkCounters {
+    fn start_request(&self) {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak_in_flight.fetch_max(in_flight, Ordering::Relaxed);
+    }
+
+    fn finish_request(&self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+        self.completed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// Why a request failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RequestError {
+    IoError,     // VIRTIO_BLK_S_IOERR: may be transient, so the request is retried.
+    Unsupported, // VIRTIO_BLK_S_UNSUPP, or a bad status: retrying will not help.
+}
+
+fn request_status(status: u8) -> Result<(), RequestError> {
+    const VIRTIO_BLK_S_OK: u8 = 0;
+    const VIRTIO_BLK_S_IOERR: u8 = 1;
+
+    match status {
+        VIRTIO_BLK_S_OK => Ok(()),
+        VIRTIO_BLK_S_IOERR => Err(RequestError::IoError),
+        _ => Err(RequestError::Unsupported),
+    }
+}
+
+// Kept outside of BLK so that stats can be read while a request is in progress.
+static BLK_COUNTERS: Mutex<Vec<Arc<BlkCounters>>> = Mutex::new(vec![]);
+
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+#[repr(C, packed)]
+#[derive(Clone, Copy, Default)]
+struct Header {
+    type_: u32,
+    _reserved: u32,
+    sector: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Read,
+    Write,
+    Flush,
+}
+
+impl Op {
+    fn request_type(self) -> u32 {
+        match self {
+            Op::Read => 0,  // VIRTIO_BLK_T_IN
+            Op::Write => 1, // VIRTIO_BLK_T_OUT
+            Op::Flush => 4, // VIRTIO_BLK_T_FLUSH
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Op::Read => "read",
+            Op::Write => "write",
+            Op::Flush => "flush",
+        }
+    }
+}
+
+// A part of a transfer issued as a single request.
+#[derive(Clone, Copy)]
+struct Request {
+    sector: u64,
+    addr: usize,
+    len: usize,
+    attempt: u32,
+}
+
+// What the device reads and writes, besides the data, while a request is
+// in flight; indexed by the request ID.
+#[derive(Clone, Copy, Default)]
+struct Slot {
+    header: Header,
+    // If we use a single byte for status, CHV corrupts the stack (writes more than one byte).
+    status: u64,
+    request: Option<Request>,
+}
 
 pub(super) struct Blk {
     dev: alloc::boxed::Box<VirtioDevice>,
     capacity: u64, // The number of sectors of BLOCK_SIZE.
     read_only: bool,
+    // The logical block size; requests are still addressed in sectors.
+    block_size: u32,
+    opt_io_size: u32,     // In bytes; zero if not reported by the device.
+    seg_max: u32,         // Data segments per request; zero if no limit.
+    request_sectors: u64, // The largest request issued, in sectors.
+    slots: Vec<Slot>,
+    counters: Arc<BlkCounters>,
 }
 
 impl Blk {
     fn self_init(&mut self) -> Result<(), ()> {
         self.dev.acknowledge_driver(); // Step 3
         self.negotiate_features()?; // Steps 4, 5, 6
+
         self.dev.init_virtqueues(1, 1)?; // Step 7
+        let queue_size = self.dev.virtqueues[0].queue_size;
+        self.slots = vec![Slot::default(); queue_size as usize];
+        let max_queue_depth = (queue_size / MIN_REQUEST_DESCRIPTORS).max(1);
+        self.counters
+            .max_queue_depth
+            .store(max_queue_depth, Ordering::Relaxed);
+        self.counters
+            .queue_depth
+            .store(max_queue_depth, Ordering::Relaxed);
+        self.request_sectors = self.request_size() >> BLOCK_SIZE_LOG2;
+        self.counters
+            .block_size
+            .store(self.block_size, Ordering::Relaxed);
+        self.counters.request_size.store(
+            (self.request_sectors << BLOCK_SIZE_LOG2) as u32,
+            Ordering::Relaxed,
+        );
+
         self.dev.driver_ok(); // Step 8
         Ok(())
     }
 
+    // The largest request to issue, in bytes: the device's optimal I/O size,
+    // as far as the virtqueue and segment limits allow. A request may span one
+    // more page than its size (buffers are only sector-aligned), and also uses
+    // two descriptors for the header and the status.
+    fn request_size(&self) -> u64 {
+        let size = match self.opt_io_size as usize {
+            0 => DEFAULT_REQUEST_SIZE,
+            size => size.clamp(BLOCK_SIZE, MAX_REQUEST_SIZE),
+        };
+
+        let mut max_segments = self.dev.virtqueues[0].queue_size as usize - 2;
+        if self.seg_max != 0 {
+            max_segments = max_segments.min(self.seg_max as usize);
+        }
+        let max_size = (max_segments.max(2) - 1) * PAGE_SIZE;
+
+        let size = size.min(max_size) as u64;
+        let block_size = (self.block_size as u64).max(BLOCK_SIZE as u64);
+        if size >= block_size {
+            size - (size % block_size)
+        } else {
+            size
+        }
+    }
+
     pub(super) fn init(dev: alloc::boxed::Box<VirtioDevice>) {
         if dev.device_cfg.is_none() {
             log::warn!("Skiping Virtio BLOCK device without device configuration.");
@@ -58,16 +247,28 @@ impl Blk {
             dev,
             capacity: 0,
             read_only: true,
+            block_size: BLOCK_SIZE as u32,
+            opt_io_size: 0,
+            seg_max: 0,
+            request_sectors: 1,
+            slots: vec![],
+            counters: Arc::new(BlkCounters::default()),
         };
 
         if blk.self_init().is_ok() {
             log::debug!(
-                "Initialized Virtio BLOCK device {:?}: capacity: 0x{:x} read only: {}.",
+                "Initialized Virtio BLOCK device {:?}: capacity: 0x{:x} read only: {} queue depth: {} block size: {} request size: {}.",
                 blk.dev.pci_device.id,
                 blk.capacity,
-                blk.read_only
+                blk.read_only,
+                blk.counters.queue_depth.load(Ordering::Relaxed),
+                blk.block_size,
+                blk.request_sectors << BLOCK_SIZE_LOG2
             );
-            (*BLK.lock()).push(blk);
+            // Keep BLK_COUNTERS and BLK indices in sync.
+            let mut blk_guard = BLK.lock();
+            BLK_COUNTERS.lock().push(blk.counters.clone());
+            blk_guard.push(blk);
         } else {
             moto_sys::SysRay::log("Failed to initialize Virtio BLK device.").ok();
             blk.dev.mark_failed();
@@ -89,7 +290,12 @@ impl Blk {
             return Err(());
         }
 
-        let features_acked = super::virtio_device::VIRTIO_F_VERSION_1 | VIRTIO_BLK_F_FLUSH;
+        // Optional features: used if available.
+        let features_optional = features_available
+            & (VIRTIO_BLK_F_SEG_MAX | VIRTIO_BLK_F_BLK_SIZE | VIRTIO_BLK_F_TOPOLOGY);
+
+        let features_acked =
+            super::virtio_device::VIRTIO_F_VERSION_1 | VIRTIO_BLK_F_FLUSH | features_optional;
         // | VIRTIO_BLK_F_RO;
         // (VIRTIO_F_VERSION_1 | VIRTIO_BLK_F_SIZE_MAX | VIRTIO_BLK_F_SEG_MAX | VIRTIO_BLK_F_RO | VIRTIO_BLK_F_BLK_SIZE);
         //  | VIRTIO_BLK_F_RO);
@@ -102,61 +308,35 @@ impl Blk {
         let cfg_bar: &PciBar = self.dev.pci_device.bars[device_cfg.bar as usize]
             .as_ref()
             .unwrap();
-        let capacity = cfg_bar.read_u64(device_cfg.offset as u64 + 0);
+        let cfg_offset = device_cfg.offset as u64;
+        let capacity = cfg_bar.read_u64(cfg_offset + CFG_CAPACITY);
         self.capacity = capacity;
 
-        Ok(())
-    }
-
-    #[inline(never)]
-    fn read(&mut self, sector: u64, buf: &mut [u8]) -> Result<(), ()> {
-        assert_eq!(BLOCK_SIZE, buf.len());
-
-        #[repr(C, packed)]
-        struct Header {
-            type_: u32,
-            _reserved: u32,
-            sector: u64,
+        if (features_acked & VIRTIO_BLK_F_SEG_MAX) != 0 {
+            self.seg_max = cfg_bar.read_u32(cfg_offset + CFG_SEG_MAX);
         }
-        let header = Header {
-            type_: 0, /* VIRTIO_BLK_T_IN */
-            _reserved: 0,
-            sector,
-        };
-
-        const VIRTIO_BLK_S_OK: u8 = 0;
-        const VIRTIO_BLK_S_IOERR: u8 = 1;
-        const VIRTIO_BLK_S_UNSUPP: u8 = 2;
-
-        // If we use a single byte for status, CHV corrupts the stack (writes more than one byte).
-        let mut status_64 = 0_u64;
-        let status_addr = &mut status_64 as *mut _ as usize;
-        unsafe {
-            let status = status_addr as *mut u8;
-            status.write_volatile(VIRTIO_BLK_S_UNSUPP);
+        if (features_acked & VIRTIO_BLK_F_BLK_SIZE) != 0 {
+            let block_size = cfg_bar.read_u32(cfg_offset + CFG_BLK_SIZE);
+            if block_size >= BLOCK_SIZE as u32 && block_size.is_power_of_two() {
+                self.block_size = block_size;
+            } else {
+                log::warn!(
+                    "Virtio BLK device {:?}: ignoring bad block size {}.",
+                    self.dev.pci_device.id,
+                    block_size
+                );
+            }
+        }
+        if (features_acked & VIRTIO_BLK_F_TOPOLOGY) != 0 {
+            let opt_io_blocks = cfg_bar.read_u32(cfg_offset + CFG_OPT_IO_SIZE);
+            self.opt_io_size = opt_io_blocks.saturating_mul(self.block_size);
         }
 
-        use super::virtio_queue::UserData;
-        let buffs: [UserData; 3] = [
-            UserData {
-                addr: &header as *const Header as usize as u64,
-                len: core::mem::size_of::<Header>() as u32,
-            },
-            UserData {
-                addr: buf.as_mut_ptr() as usize as u64,
-                len: BLOCK_SIZE as u32,
-            },
-            UserData {
-                addr: status_addr as u64,
-                len: 1,
-            },
-        ];
-
-        assert_eq!(self.dev.virtqueues.len(), 1);
-        let virtqueue = &mut self.dev.virtqueues[0];
-        virtqueue.add_buf(&buffs, 1, 2);
+        Ok(())
+    }
 
-        // Notify
+    fn notify(&self) {
+        let virtqueue = &self.dev.virtqueues[0];
         let notify_cap = self.dev.notify_cfg.unwrap();
         let cfg_bar: &PciBar = self.dev.pci_device.bars[notify_cap.bar as usize]
             .as_ref()
@@ -165,206 +345,247 @@ impl Blk {
             + (notify_cap.notify_off_multiplier as u64 * virtqueue.queue_notify_off as u64);
 
         cfg_bar.write_u16(notify_offset, virtqueue.queue_num);
+    }
 
-        let mut wait_failed = false;
+    // Waits until there is a completed request to pop.
+    fn wait(&self, wait_failed: &mut bool) {
+        let virtqueue = &self.dev.virtqueues[0];
         while !virtqueue.more_used_deprecated() {
             core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
             core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
-            if wait_failed {
+            if *wait_failed {
                 super::nop(); // Don't spam the kernel if something is wrong here.
             } else {
-                wait_failed = virtqueue.wait_deprecated().is_err();
-                if wait_failed {
+                *wait_failed = virtqueue.wait_deprecated().is_err();
+                if *wait_failed {
                     log::error!("virtqueue.wait() failed: switching to spinning.");
                 }
             }
         }
-        let consumed = virtqueue.consume_used_deprecated();
-        // Qemu indicates that 513 bytes were consumed, but CHV says 512.
-        assert!((consumed == 512) || (consumed == 513));
-
-        // todo!("add vring_get_isr");
-        core::sync::atomic::fence(core::sync::atomic::Ordering::Acquire);
-        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::Acquire);
-        let status = unsafe { (status_addr as *const u8).read_volatile() };
-        if status == VIRTIO_BLK_S_OK {
-            Ok(())
-        } else if status == VIRTIO_BLK_S_IOERR {
-            log::error!("VirtioBlk read error");
-            Err(())
-        } else {
-            panic!("status: {}", status)
-        }
     }
 
-    #[inline(never)]
-    fn write(&mut self, sector: u64, buf: &[u8]) -> Result<(), ()> {
-        assert_eq!(BLOCK_SIZE, buf.len());
-        // assert!(!self.read_only);
+    // Adds @request to the virtqueue, unless there are not enough free
+    // descriptors for it. The device is notified by the caller.
+    fn issue(&mut self, op: Op, request: Request) -> bool {
+        use super::virtio_queue::UserData;
 
-        #[repr(C, packed)]
-        struct Header {
-            type_: u32,
-            _reserved: u32,
-            sector: u64,
+        assert_eq!(self.dev.virtqueues.len(), 1);
+        let virtqueue = &mut self.dev.virtqueues[0];
+        let slot = &mut self.slots[virtqueue.next_request_id() as usize];
+
+        let mut sg = Vec::with_capacity(request.len / PAGE_SIZE + 3);
+        sg.push(UserData {
+            addr: &slot.header as *const Header as usize as u64,
+            len: core::mem::size_of::<Header>() as u32,
+        });
+        push_data_segments(&mut sg, request.addr, request.len);
+        let status_addr = &mut slot.status as *mut u64 as usize;
+        sg.push(UserData {
+            addr: status_addr as u64,
+            len: 1,
+        });
+        if sg.len() > virtqueue.free_descriptors() as usize {
+            return false;
         }
-        let header = Header {
-            type_: 1, /* VIRTIO_BLK_T_OUT */
+
+        slot.header = Header {
+            type_: op.request_type(),
             _reserved: 0,
-            sector,
+            sector: request.sector,
+        };
+        slot.request = Some(request);
+        unsafe { (status_addr as *mut u8).write_volatile(VIRTIO_BLK_S_UNSUPP) };
+
+        let data = sg.len() as u16 - 2;
+        let (outgoing, incoming) = match op {
+            Op::Read => (1, data + 1),
+            Op::Write => (data + 1, 1),
+            Op::Flush => (1, 1),
         };
 
-        const VIRTIO_BLK_S_OK: u8 = 0;
-        const VIRTIO_BLK_S_IOERR: u8 = 1;
-        const VIRTIO_BLK_S_UNSUPP: u8 = 2;
-
-        // If we use a single byte for status, CHV corrupts the stack (writes more than one byte).
-        let mut status_64 = 0_u64;
-        let status_addr = &mut status_64 as *mut _ as usize;
-        unsafe {
-            let status = status_addr as *mut u8;
-            status.write_volatile(VIRTIO_BLK_S_UNSUPP);
-        }
-
-        use super::virtio_queue::UserData;
-        let sg: [UserData; 3] = [
-            UserData {
-                addr: &header as *const Header as usize as u64,
-                len: core::mem::size_of::<Header>() as u32,
-            },
-            UserData {
-                addr: buf.as_ptr() as usize as u64,
-                len: BLOCK_SIZE as u32,
-            },
-            UserData {
-                addr: status_addr as u64,
-                len: 1,
-            },
-        ];
-
-        assert_eq!(self.dev.virtqueues.len(), 1);
-        let virtqueue = &mut self.dev.virtqueues[0];
         core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
         core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
-        virtqueue.add_buf(&sg, 2, 1);
-
-        // Notify
-        let notify_cap = self.dev.notify_cfg.unwrap();
-        let cfg_bar: &PciBar = self.dev.pci_device.bars[notify_cap.bar as usize]
-            .as_ref()
-            .unwrap();
-        let notify_offset = notify_cap.offset as u64
-            + (notify_cap.notify_off_multiplier as u64 * virtqueue.queue_notify_off as u64);
+        self.counters.start_request();
+        virtqueue.add_request(&sg, outgoing, incoming);
+        true
+    }
 
-        cfg_bar.write_u16(notify_offset, virtqueue.queue_num);
+    // Does @op on @len bytes at @addr, starting at @sector, in requests of up
+    // to request_sectors, keeping up to queue_depth of them in flight, and
+    // returns when all of them have completed. A request that fails with an
+    // I/O error is reissued, up to MAX_RETRIES times; if a request fails
+    // for good, the requests already in flight still complete, so a failed
+    // write may have written some of the data following the failed request.
+    fn transfer(&mut self, op: Op, sector: u64, addr: usize, len: usize) -> Result<(), ()> {
+        assert_eq!(0, len & (BLOCK_SIZE - 1));
+
+        let mut pending = VecDeque::new();
+        if op == Op::Flush {
+            pending.push_back(Request {
+                sector,
+                addr,
+                len: 0,
+                attempt: 0,
+            });
+        } else {
+            let request_bytes = (self.request_sectors as usize) << BLOCK_SIZE_LOG2;
+            let mut offset = 0;
+            while offset < len {
+                let request_len = request_bytes.min(len - offset);
+                pending.push_back(Request {
+                    sector: sector + (offset >> BLOCK_SIZE_LOG2) as u64,
+                    addr: addr + offset,
+                    len: request_len,
+                    attempt: 0,
+                });
+                offset += request_len;
+            }
+        }
 
+        let queue_depth = self.counters.queue_depth.load(Ordering::Relaxed) as u32;
+        let mut in_flight = 0_u32;
+        let mut result = Ok(());
         let mut wait_failed = false;
-        while !virtqueue.more_used_deprecated() {
-            core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
-            core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
-            if wait_failed {
-                super::nop(); // Don't spam the kernel if something is wrong here.
-            } else {
-                wait_failed = virtqueue.wait_deprecated().is_err();
-                if wait_failed {
-                    log::error!("virtqueue.wait() failed: switching to spinning.");
+
+        while !pending.is_empty() || in_flight > 0 {
+            let mut issued = false;
+            while in_flight < queue_depth {
+                let Some(request) = pending.front().copied() else {
+                    break;
+                };
+                if !self.issue(op, request) {
+                    break;
                 }
+                pending.pop_front();
+                in_flight += 1;
+                issued = true;
             }
-        }
-        virtqueue.reclaim_used_deprecated();
-
-        // todo!("add vring_get_isr");
-        core::sync::atomic::fence(core::sync::atomic::Ordering::Acquire);
-        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::Acquire);
-        let status = unsafe { (status_addr as *const u8).read_volatile() };
-        if status == VIRTIO_BLK_S_OK {
-            Ok(())
-        } else if status == VIRTIO_BLK_S_IOERR {
-            log::error!("VirtioBlk write error");
-            Err(())
-        } else {
-            panic!("status: {}", status)
-        }
-    }
+            if issued {
+                self.notify();
+            }
+            // request_size() makes sure that a request fits into an empty virtqueue.
+            assert_ne!(in_flight, 0);
+
+            self.wait(&mut wait_failed);
+            while let Some((req_id, consumed)) = self.dev.virtqueues[0].pop_used() {
+                in_flight -= 1;
+                self.counters.finish_request();
+
+                // todo!("add vring_get_isr");
+                core::sync::atomic::fence(core::sync::atomic::Ordering::Acquire);
+                core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::Acquire);
+                let slot = &mut self.slots[req_id as usize];
+                let request = slot.request.take().unwrap();
+                let status =
+                    unsafe { (core::ptr::addr_of!(slot.status) as *const u8).read_volatile() };
+
+                let err = match request_status(status) {
+                    Ok(()) => {
+                        // Qemu counts the status byte as consumed, but CHV does not.
+                        // (A failed request may consume less.)
+                        if op == Op::Read {
+                            let len = request.len as u32;
+                            assert!((consumed == len) || (consumed == len + 1));
+                        }
+                        continue;
+                    }
+                    Err(err) => err,
+                };
+
+                if result.is_err() {
+                    continue; // Already failed and logged.
+                }
 
-    #[inline(never)]
-    fn flush(&mut self) -> Result<(), ()> {
-        // assert!(!self.read_only);
+                if err == RequestError::IoError && request.attempt < MAX_RETRIES {
+                    self.counters.retried.fetch_add(1, Ordering::Relaxed);
+                    log::warn!(
+                        "VirtioBlk {:?}: {} of sector 0x{:x} failed: retrying ({}/{}).",
+                        self.dev.pci_device.id,
+                        op.name(),
+                        request.sector,
+                        request.attempt + 1,
+                        MAX_RETRIES
+                    );
+                    pending.push_front(Request {
+                        attempt: request.attempt + 1,
+                        ..request
+                    });
+                    continue;
+                }
 
-        #[repr(C, packed)]
-        struct Header {
-            type_: u32,
-            _reserved: u32,
-            sector: u64,
+                self.counters.failed.fetch_add(1, Ordering::Relaxed);
+                log::error!(
+                    "VirtioBlk {:?}: {} of sector 0x{:x} failed: {:?}.",
+                    self.dev.pci_device.id,
+                    op.name(),
+                    request.sector,
+                    err
+                );
+                pending.clear();
+                result = Err(());
+            }
         }
-        let header = Header {
-            type_: 4, /* VIRTIO_BLK_T_FLUSH */
-            _reserved: 0,
-            sector: 0,
-        };
-
-        const VIRTIO_BLK_S_OK: u8 = 0;
-        const VIRTIO_BLK_S_IOERR: u8 = 1;
-        const VIRTIO_BLK_S_UNSUPP: u8 = 2;
-
-        let mut status = AtomicU8::new(VIRTIO_BLK_S_UNSUPP);
-
-        use super::virtio_queue::UserData;
-        let sg: [UserData; 2] = [
-            UserData {
-                addr: &header as *const Header as usize as u64,
-                len: core::mem::size_of::<Header>() as u32,
-            },
-            UserData {
-                addr: &mut status as *mut _ as usize as u64,
-                len: 1,
-            },
-        ];
-
-        assert_eq!(self.dev.virtqueues.len(), 1);
-        let virtqueue = &mut self.dev.virtqueues[0];
-        virtqueue.add_buf(&sg, 1, 1);
 
-        // Notify
-        let notify_cap = self.dev.notify_cfg.unwrap();
-        let cfg_bar: &PciBar = self.dev.pci_device.bars[notify_cap.bar as usize]
-            .as_ref()
-            .unwrap();
-        let notify_offset = notify_cap.offset as u64
-            + (notify_cap.notify_off_multiplier as u64 * virtqueue.queue_notify_off as u64);
-
-        cfg_bar.write_u16(notify_offset, virtqueue.queue_num);
+        result
+    }
+}
 
-        let mut wait_failed = false;
-        while !virtqueue.more_used_deprecated() {
-            core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
-            core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
-            if wait_failed {
-                super::nop(); // Don't spam the kernel if something is wrong here.
-            } else {
-                wait_failed = virtqueue.wait_deprecated().is_err();
-                if wait_failed {
-                    log::error!("virtqueue.wait() failed: switching to spinning.");
-                }
-            }
-        }
-        virtqueue.reclaim_used_deprecated();
-
-        // todo!("add vring_get_isr");
-        core::sync::atomic::fence(core::sync::atomic::Ordering::Acquire);
-        if status.load(Ordering::Acquire) == VIRTIO_BLK_S_OK {
-            Ok(())
-        } else if status.load(Ordering::Acquire) == VIRTIO_BLK_S_IOERR {
-            log::error!("VirtioBlk write error");
-            Err(())
-        } else {
-            panic!("status: {}", status.load(Ordering::Relaxed))
-        }
+// Adds [addr, addr + len) to @sg, split at page boundaries.
+fn push_data_segments(sg: &mut Vec<super::virtio_queue::UserData>, addr: usize, len: usize) {
+    let mut addr = addr;
+    let end = addr + len;
+    while addr < end {
+        let next = ((addr & !(PAGE_SIZE - 1)) + PAGE_SIZE).min(end);
+        sg.push(super::virtio_queue::UserData {
+            addr: addr as u64,
+            len: (next - addr) as u32,
+        });
+        addr = next;
     }
 }
 
+// A device does one transfer at a time, under the lock, with up to
+// queue_depth requests of the transfer in flight.
 static BLK: Mutex<Vec<Blk>> = Mutex::new(vec![]);
 
+/// Sets how many requests VirtIO block device @blk_idx (in lsblk() order)
+/// keeps in flight at once; zero means as many as its virtqueue fits, which
+/// is the default and the upper bound. Resets peak_in_flight, and returns
+/// the queue depth set. Affects transfers that start afterwards.
+pub fn set_queue_depth(blk_idx: usize, depth: u16) -> Result<u16, ()> {
+    let all_counters = BLK_COUNTERS.lock();
+    let counters = all_counters.get(blk_idx).ok_or(())?;
+
+    let max_queue_depth = counters.max_queue_depth.load(Ordering::Relaxed);
+    let depth = if depth == 0 {
+        max_queue_depth
+    } else {
+        depth.min(max_queue_depth)
+    };
+    counters.queue_depth.store(depth, Ordering::Relaxed);
+    counters.peak_in_flight.store(0, Ordering::Relaxed);
+    Ok(depth)
+}
+
+/// Returns stats for each VirtIO block device, in lsblk() order.
+pub fn blk_stats() -> Vec<BlkStats> {
+    BLK_COUNTERS
+        .lock()
+        .iter()
+        .map(|counters| BlkStats {
+            queue_depth: counters.queue_depth.load(Ordering::Relaxed),
+            max_queue_depth: counters.max_queue_depth.load(Ordering::Relaxed),
+            block_size: counters.block_size.load(Ordering::Relaxed),
+            request_size: counters.request_size.load(Ordering::Relaxed),
+            in_flight: counters.in_flight.load(Ordering::Relaxed),
+            peak_in_flight: counters.peak_in_flight.load(Ordering::Relaxed),
+            completed: counters.completed.load(Ordering::Relaxed),
+            retried: counters.retried.load(Ordering::Relaxed),
+            failed: counters.failed.load(Ordering::Relaxed),
+        })
+        .collect()
+}
+
 pub fn lsblk() -> Vec<Arc<dyn super::BlockDevice>> {
     let mut result: Vec<Arc<dyn super::BlockDevice>> = alloc::vec![];
     let cnt = BLK.lock().len();
@@ -394,6 +615,17 @@ impl super::BlockDevice for VirtioDrive {
         let mut guard = BLK.lock();
         let blk = guard.get_mut(self.blk_idx as usize).unwrap();
 
+        // The device fails requests that are not in whole logical blocks.
+        if (address | buf.len() as u64) & (blk.block_size as u64 - 1) != 0 {
+            log::error!(
+                "Read not aligned at the block size: address: 0x{:x} len: 0x{:x} block size: {}",
+                address,
+                buf.len(),
+                blk.block_size
+            );
+            return Err(());
+        }
+
         let start_block = (address >> BLOCK_SIZE_LOG2) as u64;
         if start_block + (number_of_blocks as u64) > blk.capacity {
             log::error!(
@@ -405,12 +637,7 @@ impl super::BlockDevice for VirtioDrive {
             return Err(());
         }
 
-        let mut offset = 0;
-        for idx in 0..(number_of_blocks as u64) {
-            let curr_buf = &mut buf[offset..(offset + BLOCK_SIZE)];
-            blk.read(start_block + idx, curr_buf)?;
-            offset += BLOCK_SIZE;
-        }
+        blk.transfer(Op::Read, start_block, buf.as_mut_ptr() as usize, buf.len())?;
 
         core::sync::atomic::fence(Ordering::Acquire);
         core::sync::atomic::compiler_fence(Ordering::Acquire);
@@ -418,8 +645,12 @@ impl super::BlockDevice for VirtioDrive {
         Ok(())
     }
 
-    #[allow(unused)]
     fn write(&self, buf: &[u8], address: u64, number_of_blocks: usize) -> Result<(), ()> {
+        self.write_no_flush(buf, address, number_of_blocks)?;
+        self.flush()
+    }
+
+    fn write_no_flush(&self, buf: &[u8], address: u64, number_of_blocks: usize) -> Result<(), ()> {
         assert_eq!(0, address & (BLOCK_SIZE as u64 - 1));
         assert_eq!(buf.len(), number_of_blocks << BLOCK_SIZE_LOG2);
 
@@ -429,6 +660,17 @@ impl super::BlockDevice for VirtioDrive {
         let mut guard = BLK.lock();
         let blk = guard.get_mut(self.blk_idx as usize).unwrap();
 
+        // The device fails requests that are not in whole logical blocks.
+        if (address | buf.len() as u64) & (blk.block_size as u64 - 1) != 0 {
+            log::error!(
+                "Write not aligned at the block size: address: 0x{:x} len: 0x{:x} block size: {}",
+                address,
+                buf.len(),
+                blk.block_size
+            );
+            return Err(());
+        }
+
         let start_block = (address >> BLOCK_SIZE_LOG2) as u64;
         if start_block + (number_of_blocks as u64) > blk.capacity {
             log::error!(
@@ -440,18 +682,28 @@ impl super::BlockDevice for VirtioDrive {
             return Err(());
         }
 
-        let mut offset = 0;
-        for idx in 0..(number_of_blocks as u64) {
-            let curr_buf = &buf[offset..(offset + BLOCK_SIZE)];
-            blk.write(start_block + idx, curr_buf)?;
-            offset += BLOCK_SIZE;
-        }
+        blk.transfer(Op::Write, start_block, buf.as_ptr() as usize, buf.len())
+    }
 
-        blk.flush()?;
-        Ok(())
+    fn flush(&self) -> Result<(), ()> {
+        // Transfers return when all of their requests have completed, so all
+        // previously issued writes have completed by now.
+        let mut guard = BLK.lock();
+        let blk = guard.get_mut(self.blk_idx as usize).unwrap();
+        blk.transfer(Op::Flush, 0, 0, 0)
     }
 
     fn capacity(&self) -> u64 {
         BLK.lock().get(self.blk_idx as usize).unwrap().capacity
     }
+
+    fn block_size(&self) -> u32 {
+        BLK.lock().get(self.blk_idx as usize).unwrap().block_size
+    }
+
+    fn optimal_io_size(&self) -> u32 {
+        let guard = BLK.lock();
+        let blk = guard.get(self.blk_idx as usize).unwrap();
+        (blk.request_sectors << BLOCK_SIZE_LOG2) as u32
+    }
 }
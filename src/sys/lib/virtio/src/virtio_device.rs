@@ -12,7 +12,7 @@ use super::pci::PciDevice;
 use super::pci::PciDeviceID;
 use super::virtio_queue::Virtqueue;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum VirtioDeviceKind {
     UNKNOWN(u16),
     NET,
@@ -34,6 +34,18 @@ impl VirtioDeviceKind {
             x => VirtioDeviceKind::UNKNOWN(x),
         }
     }
+
+    /// The PCI device ID.
+    pub fn device_id(&self) -> u16 {
+        match self {
+            VirtioDeviceKind::NET => 0x1041,
+            VirtioDeviceKind::BLOCK => 0x1042,
+            VirtioDeviceKind::MEM => 0x1045,
+            VirtioDeviceKind::CONSOLE => 0x1043,
+            VirtioDeviceKind::RNG => 0x1044,
+            VirtioDeviceKind::UNKNOWN(x) => *x,
+        }
+    }
 }
 
 // From virtio 1.1 spec.
@@ -141,6 +153,59 @@ pub(super) struct VirtioDevice {
     // Each virtqueue is protected by a mutex so that the guest does not
     // access them concurrently.
     pub(super) virtqueues: alloc::vec::Vec<Virtqueue>,
+
+    // Diagnostics: see VirtioDeviceInfo.
+    features_offered: AtomicU64,
+    features_acked: AtomicU64,
+}
+
+/// What was negotiated with a VirtIO device during initialization.
+#[derive(Clone, Debug)]
+pub struct VirtioDeviceInfo {
+    pub kind: VirtioDeviceKind,
+    pub pci_bus: u8,
+    pub pci_slot: u8,
+    pub pci_func: u8,
+    pub features_offered: u64,
+    pub features_acked: u64,
+    pub queue_sizes: alloc::vec::Vec<u16>,
+    pub device_status: u8,
+}
+
+impl VirtioDeviceInfo {
+    pub fn failed(&self) -> bool {
+        self.device_status & FAILED_STATUS_BIT != 0
+    }
+
+    pub fn driver_ok(&self) -> bool {
+        self.device_status & DRIVER_OK_STATUS_BIT != 0
+    }
+}
+
+static DEVICE_INFO: spin::Mutex<alloc::vec::Vec<VirtioDeviceInfo>> =
+    spin::Mutex::new(alloc::vec::Vec::new());
+
+/// Returns negotiated features, queue sizes, and status of all initialized
+/// (or failed) VirtIO devices.
+pub fn virtio_device_info() -> alloc::vec::Vec<VirtioDeviceInfo> {
+    DEVICE_INFO.lock().clone()
+}
+
+/// Logs (at Info level) negotiated features, queue sizes, and status of all VirtIO devices.
+pub fn log_virtio_device_info() {
+    for info in &*DEVICE_INFO.lock() {
+        log::info!(
+            "VirtIO {:?} at {:02x}:{:02x}.{}: features offered: 0x{:x} acked: 0x{:x} queues: {:?} status: 0x{:x}",
+            info.kind,
+            info.pci_bus,
+            info.pci_slot,
+            info.pci_func,
+            info.features_offered,
+            info.features_acked,
+            info.queue_sizes,
+            info.device_status
+        );
+    }
 }
 
 impl VirtioDevice {
@@ -281,6 +346,8 @@ impl VirtioDevice {
             notify_cfg,
             msix: None,
             virtqueues: alloc::vec::Vec::new(),
+            features_offered: AtomicU64::new(0),
+            features_acked: AtomicU64::new(0),
         }))
     }
 
@@ -435,6 +502,38 @@ impl VirtioDevice {
         let mut status = cfg_bar.readb(status_offset);
         status |= FAILED_STATUS_BIT;
         cfg_bar.writeb(status_offset, status);
+        self.record_info();
+    }
+
+    fn record_info(&self) {
+        let cfg_bar: &PciBar = self.pci_device.bars[self.common_cfg.bar as usize]
+            .as_ref()
+            .unwrap();
+        let device_status = cfg_bar.readb(
+            self.common_cfg.offset as u64
+                + offset_of!(VirtioPciCommonCfgLayout, device_status) as u64,
+        );
+
+        let info = VirtioDeviceInfo {
+            kind: self.kind,
+            pci_bus: self.pci_device.id.bus,
+            pci_slot: self.pci_device.id.slot,
+            pci_func: self.pci_device.id.func,
+            features_offered: self.features_offered.load(Ordering::Relaxed),
+            features_acked: self.features_acked.load(Ordering::Relaxed),
+            queue_sizes: self.virtqueues.iter().map(|q| q.queue_size).collect(),
+            device_status,
+        };
+
+        // A device is recorded when it is initialized, and again if it fails later.
+        let mut devices = DEVICE_INFO.lock();
+        match devices.iter_mut().find(|other| {
+            (other.pci_bus, other.pci_slot, other.pci_func)
+                == (info.pci_bus, info.pci_slot, info.pci_func)
+        }) {
+            Some(other) => *other = info,
+            None => devices.push(info),
+        }
     }
 
     // Step 1
@@ -489,6 +588,7 @@ impl VirtioDevice {
         let features_hi = cfg_bar.read_u32(feature_offset);
 
         let features: u64 = ((features_hi as u64) << 32) | (features_lo as u64);
+        self.features_offered.store(features, Ordering::Relaxed);
         features
     }
 
@@ -504,6 +604,7 @@ impl VirtioDevice {
         cfg_bar.write_u32(feature_offset, (val & 0xff_ff_ff_ff) as u32);
         cfg_bar.write_u32(feature_select_offset, 1);
         cfg_bar.write_u32(feature_offset, (val >> 32) as u32);
+        self.features_acked.store(val, Ordering::Relaxed);
     }
 
     // Steps 5 and 6
@@ -719,6 +820,7 @@ impl VirtioDevice {
         let mut status = cfg_bar.readb(status_offset);
         status |= DRIVER_OK_STATUS_BIT;
         cfg_bar.writeb(status_offset, status);
+        self.record_info();
     }
 }
 
@@ -11,9 +11,11 @@ use super::virtio_device::VirtioDevice;
 const VIRTIO_NET_F_CSUM: u64 = 1_u64 << 0;
 const VIRTIO_NET_F_MTU: u64 = 1_u64 << 3;
 const VIRTIO_NET_F_MAC: u64 = 1_u64 << 5;
-#[allow(unused)]
 const VIRTIO_NET_F_STATUS: u64 = 1_u64 << 16;
 
+// VirtioNetConfig::status bits.
+const VIRTIO_NET_S_LINK_UP: u16 = 1;
+
 #[allow(unused)]
 #[repr(C, packed)]
 struct VirtioNetConfig {
@@ -85,6 +87,8 @@ pub struct NetDev {
     dev: alloc::boxed::Box<VirtioDevice>,
     mac: [u8; 6],
     mtu: Option<u16>,
+    // VIRTIO_NET_F_STATUS has been negotiated.
+    has_status: bool,
 
     rx_bufs: &'static mut [IoBuf; 256],
     tx_bufs: &'static mut [IoBuf; 128], // A single TX buf consumes two descriptors in virtqueue.
@@ -168,6 +172,7 @@ impl NetDev {
             dev,
             mac: [0; 6],
             mtu: None,
+            has_status: false,
             rx_bufs,
             tx_bufs,
             tx_buf_freelist,
@@ -226,20 +231,14 @@ impl NetDev {
             return Err(());
         }
 
-        /*
-        if (features_available & VIRTIO_NET_F_STATUS) == 0 {
-            log::warn!(
-                "Virtio NET device {:?}: VIRTIO_NET_F_STATUS feature not available; features: 0x{:x}.",
-                self.dev.pci_device.id,
-                features_available
-            );
-            return Err(());
+        // Without VIRTIO_NET_F_STATUS the link is assumed to be always up.
+        if (features_available & VIRTIO_NET_F_STATUS) != 0 {
+            features_acked |= VIRTIO_NET_F_STATUS;
         }
-        */
 
         features_acked |= super::virtio_device::VIRTIO_F_VERSION_1
             | VIRTIO_NET_F_MAC
-            | super::virtio_device::VIRTIO_F_RING_EVENT_IDX; // | VIRTIO_NET_F_STATUS;
+            | super::virtio_device::VIRTIO_F_RING_EVENT_IDX;
 
         if (features_available & VIRTIO_NET_F_CSUM) == VIRTIO_NET_F_CSUM {
             // Note: in VirtIO 1.1. spec, section 5.1.6.2, it says:
@@ -290,6 +289,8 @@ impl NetDev {
             self.mtu = Some(mtu);
         }
 
+        self.has_status = (features_acked & VIRTIO_NET_F_STATUS) != 0;
+
         Ok(())
     }
 
@@ -297,6 +298,22 @@ impl NetDev {
         self.mtu
     }
 
+    /// Whether the link is up, as reported by the device; always true if
+    /// the device does not report link status.
+    pub fn link_up(&self) -> bool {
+        if !self.has_status {
+            return true;
+        }
+
+        let device_cfg = self.dev.device_cfg.as_ref().unwrap();
+        let cfg_bar: &PciBar = self.dev.pci_device.bars[device_cfg.bar as usize]
+            .as_ref()
+            .unwrap();
+        let status =
+            cfg_bar.read_u16(device_cfg.offset as u64 + offset_of!(VirtioNetConfig, status) as u64);
+        (status & VIRTIO_NET_S_LINK_UP) != 0
+    }
+
     pub fn wait_handles(&self) -> alloc::vec::Vec<crate::WaitHandle> {
         let mut result = alloc::vec::Vec::new();
         for q in &self.dev.virtqueues {
@@ -106,6 +106,7 @@ pub(super) struct Virtqueue {
 
     free_head_idx: u16,
     last_used_idx: u16,
+    num_free: u16, // Free descriptors; only tracked by add_request()/pop_used().
 
     wait_handles: alloc::vec::Vec<crate::WaitHandle>,
 }
@@ -167,6 +168,7 @@ impl Virtqueue {
             used_ring,
             free_head_idx: 0,
             last_used_idx: 0,
+            num_free: queue_size,
             wait_handles: alloc::vec![],
         })
     }
@@ -271,6 +273,91 @@ impl Virtqueue {
         req_id
     }
 
+    // add_request() and pop_used() allow many requests in flight, completing
+    // in any order: descriptors of a request are returned to the free list
+    // only when the request completes. Don't mix them with add_buf() and
+    // the *_deprecated() functions on the same virtqueue.
+
+    pub fn free_descriptors(&self) -> u16 {
+        self.num_free
+    }
+
+    // The ID add_request() will return next.
+    pub fn next_request_id(&self) -> u16 {
+        self.free_head_idx
+    }
+
+    // Returns the request ID (its head descriptor), which pop_used() returns
+    // when the request completes. The caller must check free_descriptors() first.
+    pub fn add_request(&mut self, data: &[UserData], outgoing: u16, incoming: u16) -> u16 {
+        let elements = outgoing + incoming;
+        assert_ne!(elements, 0);
+        assert_eq!(elements, data.len() as u16);
+        assert!(elements <= self.num_free);
+
+        let req_id = self.free_head_idx;
+        let mut idx = req_id;
+        for el_idx in 0..elements {
+            let descriptor = self.get_descriptor(idx);
+            let el = &data[el_idx as usize];
+            descriptor.addr = super::mapper().virt_to_phys(el.addr).unwrap();
+            descriptor.len = el.len;
+
+            let mut flags: u16 = 0;
+            if el_idx < (elements - 1) {
+                flags |= VIRTQ_DESC_F_NEXT;
+            }
+            if el_idx >= outgoing {
+                flags |= VIRTQ_DESC_F_WRITE;
+            }
+            descriptor.flags = flags;
+
+            // The free list is linked via next, so a chain of free descriptors
+            // is already linked the way the device follows it.
+            idx = descriptor.next;
+        }
+
+        self.num_free -= elements;
+        self.free_head_idx = idx;
+        self.update_and_increment_available_idx(req_id);
+
+        req_id
+    }
+
+    // Returns the ID of a completed request, and the number of bytes the device wrote.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        core::sync::atomic::fence(core::sync::atomic::Ordering::Acquire);
+        if self.last_used_idx == unsafe { self.used_ring.idx.read_volatile() } {
+            return None;
+        }
+
+        let head = self.last_used_idx % self.queue_size;
+        let elem = &self.used_ring.ring[head as usize];
+        let req_id = elem.id as u16;
+        let consumed = elem.len;
+
+        // Return the chain to the head of the free list.
+        let mut idx = req_id;
+        let mut elements = 1;
+        loop {
+            let descriptor = self.get_descriptor(idx);
+            if (descriptor.flags & VIRTQ_DESC_F_NEXT) == 0 {
+                break;
+            }
+            idx = descriptor.next;
+            elements += 1;
+        }
+        let free_head_idx = self.free_head_idx;
+        self.get_descriptor(idx).next = free_head_idx;
+        self.free_head_idx = req_id;
+        self.num_free += elements;
+        assert!(self.num_free <= self.queue_size);
+
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+        core::sync::atomic::fence(core::sync::atomic::Ordering::Release);
+        Some((req_id, consumed))
+    }
+
     // Returns true if the host should be notified of the event.
     pub fn add_rx_buf(&mut self, phys_addr: u64, len: u32, descriptor_idx: u16) -> bool {
         let descriptor = self.get_descriptor(descriptor_idx);
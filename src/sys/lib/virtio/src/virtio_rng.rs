@@ -1,10 +1,25 @@
+use super::pci::PciBar;
 use super::virtio_device::VirtioDevice;
+use core::time::Duration;
+use moto_sys::{SysCpu, SysHandle};
 use spin::Mutex;
 
+const PAGE_SIZE: usize = 4096;
+
+// How many times in a row the device may return no bytes before
+// get_entropy() gives up.
+const MAX_EMPTY_REQUESTS: u32 = 16;
+
+// How long the device may keep a request before get_entropy() gives up on it.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
 static RNG: Mutex<Option<Rng>> = Mutex::new(None);
 
 pub(super) struct Rng {
     dev: alloc::boxed::Box<VirtioDevice>,
+    // The device writes here, not into the caller's buffer: if it does not
+    // return a request in time, it may still write into it later.
+    buf_addr: u64, // One page.
 }
 
 impl Rng {
@@ -26,7 +41,11 @@ impl Rng {
             dev.mark_failed();
             return;
         }
-        let mut rng = Rng { dev };
+        let Ok(buf_addr) = super::mapper().alloc_contiguous_pages(PAGE_SIZE as u64) else {
+            dev.mark_failed();
+            return;
+        };
+        let mut rng = Rng { dev, buf_addr };
 
         if rng.self_init().is_ok() {
             log::debug!("Initialized Virtio RNG device {:?}.", rng.dev.pci_device.id);
@@ -36,6 +55,100 @@ impl Rng {
         }
     }
 
+    // Fills @buf with bytes from the device, which may return fewer bytes
+    // than asked for per request.
+    fn fill(&mut self, buf: &mut [u8]) -> Result<(), ()> {
+        let mut done = 0;
+        let mut empty_requests = 0;
+        while done < buf.len() {
+            let len = PAGE_SIZE.min(buf.len() - done);
+
+            let written = self.request(len)?.min(len);
+            if written == 0 {
+                empty_requests += 1;
+                if empty_requests == MAX_EMPTY_REQUESTS {
+                    log::warn!(
+                        "Virtio RNG device {:?} returns no entropy.",
+                        self.dev.pci_device.id
+                    );
+                    return Err(());
+                }
+                continue;
+            }
+            empty_requests = 0;
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    self.buf_addr as usize as *const u8,
+                    buf[done..].as_mut_ptr(),
+                    written,
+                );
+            }
+            done += written;
+        }
+
+        Ok(())
+    }
+
+    // Returns the number of bytes the device wrote at self.buf_addr, or an
+    // error if the device did not return the request in time.
+    fn request(&mut self, len: usize) -> Result<usize, ()> {
+        use super::virtio_queue::UserData;
+        let sg = [UserData {
+            addr: self.buf_addr,
+            len: len as u32,
+        }];
+
+        assert_eq!(self.dev.virtqueues.len(), 1);
+        let virtqueue = &mut self.dev.virtqueues[0];
+        core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
+        virtqueue.add_buf(&sg, 0, 1);
+
+        // Notify
+        let notify_cap = self.dev.notify_cfg.unwrap();
+        let cfg_bar: &PciBar = self.dev.pci_device.bars[notify_cap.bar as usize]
+            .as_ref()
+            .unwrap();
+        let notify_offset = notify_cap.offset as u64
+            + (notify_cap.notify_off_multiplier as u64 * virtqueue.queue_notify_off as u64);
+
+        cfg_bar.write_u16(notify_offset, virtqueue.queue_num);
+
+        // Unlike virtio_blk.rs, don't wait forever: entropy is optional, and
+        // callers must not hang on a broken device.
+        let deadline = moto_rt::time::Instant::now() + REQUEST_TIMEOUT;
+        let mut handles: alloc::vec::Vec<SysHandle> = virtqueue
+            .wait_handles()
+            .iter()
+            .map(|handle| SysHandle::from_u64(*handle))
+            .collect();
+        while !virtqueue.more_used_deprecated() {
+            core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
+            if moto_rt::time::Instant::now() >= deadline {
+                log::warn!(
+                    "Virtio RNG device {:?} did not return a request in {:?}.",
+                    self.dev.pci_device.id,
+                    REQUEST_TIMEOUT
+                );
+                return Err(());
+            }
+            if handles.is_empty()
+                || SysCpu::wait(
+                    &mut handles,
+                    SysHandle::NONE,
+                    SysHandle::NONE,
+                    Some(deadline),
+                )
+                .is_err()
+            {
+                SysCpu::sched_yield();
+            }
+        }
+        let written = virtqueue.consume_used_deprecated() as usize;
+        core::sync::atomic::fence(core::sync::atomic::Ordering::Acquire);
+
+        Ok(written)
+    }
+
     // Step 4
     fn negotiate_features(&self) -> Result<(), ()> {
         let features_available = self.dev.get_available_features();
@@ -63,3 +176,23 @@ impl Rng {
         self.dev.confirm_features()
     }
 }
+
+/// Fills @buf with entropy from the VirtIO RNG device, looping until the
+/// device has provided all of it. Returns an error if there is no (working)
+/// RNG device; a device that does not return a request in time is not
+/// used again.
+pub fn get_entropy(buf: &mut [u8]) -> Result<(), ()> {
+    let mut guard = RNG.lock();
+    let Some(rng) = guard.as_mut() else {
+        return Err(());
+    };
+
+    let result = rng.fill(buf);
+    if result.is_err() {
+        // The device may still own the request, so its memory is leaked.
+        let rng = guard.take().unwrap();
+        rng.dev.mark_failed();
+        core::mem::forget(rng);
+    }
+    result
+}
@@ -50,6 +50,34 @@ fn process_config() -> Result<Config, String> {
     Ok(config)
 }
 
+fn spawn_log_server(path: &str) -> std::process::Child {
+    std::process::Command::new(path)
+        .env(
+            moto_sys::caps::MOTURUS_CAPS_ENV_KEY,
+            format!("0x{:x}", moto_sys::caps::CAP_LOG),
+        )
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect(format!("Error spawning {}", path).as_str())
+}
+
+// Restarts the log server whenever it exits, e.g. when it is killed by its
+// watchdog (see SysRay::WATCHDOG_ACTION_KILL).
+fn supervise_log_server(path: String, mut log_server: std::process::Child) -> ! {
+    // Don't spin if the log server keeps crashing on startup.
+    const RESTART_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+    loop {
+        let status = log_server.wait();
+        let _ =
+            SysRay::log(format!("sys-init: '{}' exited: {:?}; restarting.", path, status).as_str());
+        std::thread::sleep(RESTART_DELAY);
+        log_server = spawn_log_server(path.as_str());
+    }
+}
+
 fn main() {
     #[cfg(debug_assertions)]
     SysRay::log("sys-init started").ok();
@@ -69,16 +97,9 @@ fn main() {
     let config = config.unwrap();
 
     if let Some(log) = &config.log {
-        std::process::Command::new(log.as_str())
-            .env(
-                moto_sys::caps::MOTURUS_CAPS_ENV_KEY,
-                format!("0x{:x}", moto_sys::caps::CAP_LOG),
-            )
-            .stdin(std::process::Stdio::null())
-            .stdout(std::process::Stdio::null())
-            .stderr(std::process::Stdio::null())
-            .spawn()
-            .expect(format!("Error spawning {}", log).as_str());
+        let log_server = spawn_log_server(log.as_str());
+        let log_path = log.clone();
+        std::thread::spawn(move || supervise_log_server(log_path, log_server));
 
         // The logserver has just started. It needs time to start
         // listening, so we need to retry a few times.
@@ -0,0 +1,221 @@
+// Case-insensitive (case-preserving) lookup: an optional layer over the
+// mounted FileSystem, enabled by "case_insensitive = true" in CFG_PATH.
+//
+// Paths are resolved component by component to the names actually stored
+// on disk, comparing names case-insensitively; new entries keep the case
+// they were created with. Creating an entry that differs from an existing
+// one only by case fails with E_ALREADY_IN_USE, so lookups are never
+// ambiguous (unless the volume already had such entries when mounted: then
+// an exact match wins).
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::Deserialize;
+
+use moto_sys::ErrorCode;
+
+use super::filesystem::{DirectoryIter, File, FileSystem};
+
+const CFG_PATH: &str = "/sys/cfg/sys-fs.toml";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// True if lookups are case-insensitive, for the driver's state that is not
+/// on disk (e.g. snapshot views), which this layer can't resolve.
+pub(super) fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct FsConfig {
+    #[serde(default)]
+    case_insensitive: bool,
+}
+
+// Reads CFG_PATH from @fs directly (std::fs is not available yet).
+fn load_config(fs: *mut dyn FileSystem) -> FsConfig {
+    // Safe because the caller owns @fs, and our references do not escape.
+    let fs = || -> &'static mut dyn FileSystem { unsafe { &mut *fs } };
+
+    let Ok(attr) = fs().stat(CFG_PATH) else {
+        return FsConfig::default(); // No config: defaults.
+    };
+
+    let mut bytes = vec![0_u8; attr.size as usize];
+    let read = fs()
+        .open_file(CFG_PATH)
+        .and_then(|mut file| file.read_offset(0, bytes.as_mut_slice()));
+    if read != Ok(bytes.len()) {
+        log::error!("{}:{} error reading {}.", file!(), line!(), CFG_PATH);
+        return FsConfig::default();
+    }
+
+    let parsed = core::str::from_utf8(bytes.as_slice())
+        .map_err(|_| ())
+        .and_then(|s| toml::from_str::<FsConfig>(s).map_err(|_| ()));
+    match parsed {
+        Ok(config) => config,
+        Err(()) => {
+            log::error!("{}:{} error parsing {}.", file!(), line!(), CFG_PATH);
+            FsConfig::default()
+        }
+    }
+}
+
+/// Wraps @fs into a case-insensitive layer, if so configured.
+pub(super) fn maybe_wrap(mut fs: Box<dyn FileSystem>) -> Box<dyn FileSystem> {
+    if !load_config(fs.as_mut() as *mut dyn FileSystem).case_insensitive {
+        return fs;
+    }
+
+    log::info!("FS: case-insensitive lookups enabled.");
+    ENABLED.store(true, Ordering::Relaxed);
+    Box::new(CaseInsensitiveFs {
+        inner: Box::into_raw(fs),
+    })
+}
+
+struct CaseInsensitiveFs {
+    // Owned; never freed, as the FS lives forever (see filesystem::fs()).
+    inner: *mut dyn FileSystem,
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir == "/" {
+        alloc::format!("/{}", name)
+    } else {
+        alloc::format!("{}/{}", dir, name)
+    }
+}
+
+pub(super) fn fold(name: &str) -> String {
+    name.to_lowercase()
+}
+
+impl CaseInsensitiveFs {
+    fn inner(&self) -> &'static mut dyn FileSystem {
+        unsafe { &mut *self.inner }
+    }
+
+    // Finds the entry in @dir matching @name case-insensitively.
+    fn lookup(&self, dir: &str, name: &str) -> Option<String> {
+        let exact = join(dir, name);
+        if self.inner().stat(exact.as_str()).is_ok() {
+            return Some(exact);
+        }
+
+        let folded = fold(name);
+        self.inner()
+            .iter(dir)
+            .ok()?
+            .find(|entry| fold(entry.filename()) == folded)
+            .map(|entry| entry.path().to_owned())
+    }
+
+    // Returns @path as stored on disk. Components that do not exist (e.g. the
+    // last one, for a file being created) are kept as given.
+    fn resolve(&self, path: &str) -> Result<String, ErrorCode> {
+        if !path.starts_with('/') {
+            return Err(moto_rt::E_INVALID_FILENAME);
+        }
+
+        let mut resolved = "/".to_owned();
+        let mut components = path.split('/').filter(|c| !c.is_empty());
+        while let Some(name) = components.next() {
+            match self.lookup(resolved.as_str(), name) {
+                Some(found) => resolved = found,
+                None => {
+                    resolved = join(resolved.as_str(), name);
+                    for name in components {
+                        resolved = join(resolved.as_str(), name);
+                    }
+                    break;
+                }
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    // Resolves @path for an entry to be created: fails if an entry matching
+    // it case-insensitively already exists.
+    fn resolve_for_create(&self, path: &str) -> Result<String, ErrorCode> {
+        let resolved = self.resolve(path)?;
+        if self.inner().stat(resolved.as_str()).is_ok() {
+            // An entry that differs only by case (or not at all) exists.
+            return Err(moto_rt::E_ALREADY_IN_USE);
+        }
+        Ok(resolved)
+    }
+}
+
+impl FileSystem for CaseInsensitiveFs {
+    fn open_file(&'static mut self, path: &str) -> Result<Box<dyn File>, ErrorCode> {
+        let path = self.resolve(path)?;
+        self.inner().open_file(path.as_str())
+    }
+
+    fn create_file(&'static mut self, path: &str) -> Result<(), ErrorCode> {
+        let path = self.resolve_for_create(path)?;
+        self.inner().create_file(path.as_str())
+    }
+
+    fn iter(&'static mut self, path: &str) -> Result<Box<dyn DirectoryIter>, ErrorCode> {
+        let path = self.resolve(path)?;
+        self.inner().iter(path.as_str())
+    }
+
+    fn stat(&'static mut self, path: &str) -> Result<moto_rt::fs::FileAttr, ErrorCode> {
+        let path = self.resolve(path)?;
+        self.inner().stat(path.as_str())
+    }
+
+    fn mkdir(&'static mut self, path: &str) -> Result<(), ErrorCode> {
+        let path = self.resolve_for_create(path)?;
+        self.inner().mkdir(path.as_str())
+    }
+
+    fn unlink(&'static mut self, path: &str) -> Result<(), ErrorCode> {
+        let path = self.resolve(path)?;
+        self.inner().unlink(path.as_str())
+    }
+
+    fn rename(&'static mut self, old: &str, new: &str) -> Result<(), ErrorCode> {
+        let old = self.resolve(old)?;
+        let new_resolved = self.resolve(new)?;
+
+        if new_resolved != old {
+            return self.inner().rename(old.as_str(), new_resolved.as_str());
+        }
+
+        // Renaming an entry onto itself: only its case may change.
+        let Some((parent, _)) = new_resolved.rsplit_once('/') else {
+            return Err(moto_rt::E_INVALID_FILENAME);
+        };
+        let parent = if parent.is_empty() { "/" } else { parent };
+        let name = new.rsplit('/').find(|c| !c.is_empty()).unwrap_or("");
+        let new = join(parent, name);
+        if new == old {
+            return Ok(());
+        }
+        self.inner().rename(old.as_str(), new.as_str())
+    }
+
+    fn delete_dir(&'static mut self, path: &str) -> Result<(), ErrorCode> {
+        let path = self.resolve(path)?;
+        self.inner().delete_dir(path.as_str())
+    }
+
+    fn delete_dir_all(&'static mut self, path: &str) -> Result<(), ErrorCode> {
+        let path = self.resolve(path)?;
+        self.inner().delete_dir_all(path.as_str())
+    }
+
+    fn barrier(&'static mut self) -> Result<(), ErrorCode> {
+        self.inner().barrier()
+    }
+
+    fn canonical_path(&'static mut self, path: &str) -> Result<String, ErrorCode> {
+        self.resolve(path)
+    }
+}
@@ -0,0 +1,112 @@
+// Background defragmentation (see moto_rt::fs::defragment()).
+//
+// Files are queued by CMD_DEFRAGMENT, and moved BATCH_BLOCKS blocks at a
+// time between requests, so clients are not blocked behind a large file.
+// Each block is copied before the file is switched to it (see srfs-core's
+// relocate_data_block()), so the file stays readable and writable while
+// it is being moved. Like quotas, the queue lives in memory only.
+
+use std::collections::VecDeque;
+
+use moto_rt::time::Instant;
+use moto_sys::ErrorCode;
+
+use super::filesystem::{fs, File};
+use super::quota::is_within;
+
+// ~1ms of virtio-blk I/O.
+const BATCH_BLOCKS: u64 = 16;
+
+const MAX_QUEUED: usize = 64;
+
+struct Job {
+    path: String,
+    // Not the client's fd: the job outlives it.
+    file: Box<dyn File>,
+    blocks: u64,
+    next_block: u64,
+}
+
+#[derive(Default)]
+pub(super) struct Defragmenter {
+    queue: VecDeque<Job>,
+}
+
+impl Defragmenter {
+    /// Queues file @path; does nothing if it is contiguous, or queued already.
+    pub fn start(&mut self, path: &str) -> Result<(), ErrorCode> {
+        if self.pending_blocks(path) > 0 {
+            return Ok(());
+        }
+        if self.queue.len() >= MAX_QUEUED {
+            return Err(moto_rt::E_BUFFER_FULL);
+        }
+
+        let mut file = fs().open_file(path)?;
+        let (blocks, extents) = file.extents()?;
+        if extents <= 1 {
+            return Ok(());
+        }
+
+        log::debug!("fs: defragmenting '{}': {} extents", path, extents);
+        self.queue.push_back(Job {
+            path: path.to_owned(),
+            file,
+            blocks,
+            next_block: 0,
+        });
+        Ok(())
+    }
+
+    /// The number of blocks of file @path that are still to be moved.
+    pub fn pending_blocks(&self, path: &str) -> u64 {
+        self.queue
+            .iter()
+            .find(|job| job.path == path)
+            .map(|job| job.blocks - job.next_block)
+            .unwrap_or(0)
+    }
+
+    /// Called before @path, and everything below it, is removed.
+    pub fn remove_subtree(&mut self, path: &str) {
+        self.queue.retain(|job| !is_within(&job.path, path));
+    }
+
+    pub fn rename_subtree(&mut self, old: &str, new: &str) {
+        for job in self.queue.iter_mut() {
+            if is_within(&job.path, old) {
+                job.path = alloc::format!("{}{}", new, &job.path[old.len()..]);
+            }
+        }
+    }
+
+    /// When the next batch is due, if any.
+    pub fn deadline(&self) -> Option<Instant> {
+        if self.queue.is_empty() {
+            None
+        } else {
+            Some(Instant::now())
+        }
+    }
+
+    /// Moves the next batch of blocks.
+    pub fn maybe_defragment(&mut self) {
+        let Some(job) = self.queue.front_mut() else {
+            return;
+        };
+
+        match job.file.defragment(job.next_block, BATCH_BLOCKS) {
+            Ok(next_block) if next_block > job.next_block && next_block < job.blocks => {
+                job.next_block = next_block;
+                return;
+            }
+            Ok(_) => {} // Done, or the file has been truncated.
+            Err(err) => {
+                // Whatever has been moved stays moved.
+                log::warn!("fs: failed to defragment '{}': {:?}", job.path, err);
+            }
+        }
+
+        self.queue.pop_front();
+    }
+}
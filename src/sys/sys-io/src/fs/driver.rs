@@ -8,18 +8,61 @@ use moto_sys_io::api_fs::*;
 
 use super::filesystem::fs;
 
+// FS blocks (srfs::BLOCK_SIZE).
+const FS_BLOCK_SIZE: usize = 4096;
+
+#[repr(C, align(4096))]
+struct AlignedBlock([u8; FS_BLOCK_SIZE]);
+
+// An I/O buffer aligned at FS blocks: srfs reads and writes whole blocks in
+// aligned buffers straight from/to the device.
+struct AlignedBlocks(Vec<AlignedBlock>);
+
+impl AlignedBlocks {
+    fn new(len: usize) -> Self {
+        Self(
+            (0..len.div_ceil(FS_BLOCK_SIZE))
+                .map(|_| AlignedBlock([0; FS_BLOCK_SIZE]))
+                .collect(),
+        )
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            core::slice::from_raw_parts_mut(
+                self.0.as_mut_ptr() as *mut u8,
+                self.0.len() * FS_BLOCK_SIZE,
+            )
+        }
+    }
+}
+
 struct PerConnectionData {
+    pid: u64,  // The peer's, for super::open_files and I/O accounting.
+    caps: u64, // The peer's, as known to the kernel.
+    conn: u64,
     next_fd: u64,
     readdirs: std::collections::HashMap<u64, Box<dyn super::filesystem::DirectoryIter>>,
     files: std::collections::HashMap<u64, Box<dyn super::filesystem::File>>,
+    // Paths of open files, for quota accounting and defragmentation requests.
+    file_paths: std::collections::HashMap<u64, String>,
 }
 
 impl PerConnectionData {
-    fn new() -> Self {
+    fn new(conn: &LocalServerConnection) -> Self {
+        Self::with_peer(conn.handle())
+    }
+
+    fn with_peer(conn: SysHandle) -> Self {
+        let (pid, caps) = moto_sys::SysObj::get_peer_credentials(conn).unwrap_or((0, 0));
         PerConnectionData {
+            pid,
+            caps,
+            conn: conn.as_u64(),
             next_fd: 1,
             readdirs: std::collections::HashMap::new(),
             files: std::collections::HashMap::new(),
+            file_paths: std::collections::HashMap::new(),
         }
     }
 
@@ -38,10 +81,12 @@ impl PerConnectionData {
         self.readdirs.remove(&fd);
     }
 
-    fn add_file(&mut self, ptr: Box<dyn super::filesystem::File>) -> u64 {
+    fn add_file(&mut self, ptr: Box<dyn super::filesystem::File>, path: &str) -> u64 {
         let fd = self.next_fd;
         self.next_fd += 1;
         self.files.insert(fd, ptr);
+        self.file_paths.insert(fd, path.to_owned());
+        super::open_files::insert(self.pid, self.conn, fd, path);
         fd
     }
 
@@ -51,11 +96,171 @@ impl PerConnectionData {
 
     fn remove_file(&mut self, fd: u64) {
         self.files.remove(&fd);
+        self.file_paths.remove(&fd);
+        super::open_files::remove(self.pid, self.conn, fd);
+    }
+}
+
+impl Drop for PerConnectionData {
+    fn drop(&mut self) {
+        // The connection is gone, and its files are closed.
+        if !self.file_paths.is_empty() {
+            super::open_files::remove_connection(self.pid, self.conn);
+        }
+    }
+}
+
+// io_channel connections to FS_IO_URL (see moto_sys_io::io_executor).
+// Requests are handled in the driver thread, between sync ones.
+struct IoConnection {
+    conn: moto_ipc::io_channel::ServerConnection,
+    pcon: PerConnectionData,
+    // Completions that did not fit into the queue.
+    pending: std::collections::VecDeque<moto_ipc::io_channel::Msg>,
+}
+
+impl IoConnection {
+    // Returns false if the connection must be dropped.
+    fn process(&mut self) -> bool {
+        let mut completed = self.flush_pending();
+
+        loop {
+            let sqe = match self.conn.recv() {
+                Ok(sqe) => sqe,
+                Err(err) => {
+                    // E_NOT_CONNECTED: the client has shut down.
+                    assert!(err == moto_rt::E_NOT_READY || err == moto_rt::E_NOT_CONNECTED);
+                    break;
+                }
+            };
+            if sqe.status() != moto_rt::E_NOT_READY {
+                return false;
+            }
+
+            let cqe = Driver::on_io_sqe(&self.conn, &mut self.pcon, sqe);
+            if !self.pending.is_empty() || self.conn.send(cqe).is_err() {
+                self.pending.push_back(cqe);
+            }
+            completed = true;
+        }
+
+        !completed || moto_sys::SysCpu::wake(self.conn.wait_handle()).is_ok()
+    }
+
+    fn flush_pending(&mut self) -> bool {
+        let mut sent = false;
+        while let Some(cqe) = self.pending.front() {
+            if self.conn.send(*cqe).is_err() {
+                break;
+            }
+            self.pending.pop_front();
+            sent = true;
+        }
+        sent
+    }
+}
+
+#[derive(Default)]
+struct IoServer {
+    listeners: std::collections::HashMap<SysHandle, moto_ipc::io_channel::ServerConnection>,
+    conns: std::collections::HashMap<SysHandle, IoConnection>,
+}
+
+impl IoServer {
+    const MIN_LISTENERS: usize = 2;
+    // How often to retry completions the client has had no room for.
+    const PENDING_RETRY: core::time::Duration = core::time::Duration::from_millis(1);
+
+    fn wait_handles(&mut self) -> Vec<SysHandle> {
+        while self.listeners.len() < Self::MIN_LISTENERS {
+            match moto_ipc::io_channel::ServerConnection::create(FS_IO_URL) {
+                Ok(listener) => {
+                    self.listeners.insert(listener.wait_handle(), listener);
+                }
+                Err(err) => {
+                    log::error!("fs: failed to create an io_channel listener: {:?}", err);
+                    break;
+                }
+            }
+        }
+
+        self.listeners
+            .keys()
+            .chain(self.conns.keys())
+            .copied()
+            .collect()
+    }
+
+    fn deadline(&self) -> Option<moto_rt::time::Instant> {
+        if self
+            .conns
+            .values()
+            .any(|io_conn| !io_conn.pending.is_empty())
+        {
+            Some(moto_rt::time::Instant::now() + Self::PENDING_RETRY)
+        } else {
+            None
+        }
+    }
+
+    fn contains(&self, handle: SysHandle) -> bool {
+        self.listeners.contains_key(&handle) || self.conns.contains_key(&handle)
+    }
+
+    // The peer is gone; its files are closed.
+    fn drop_connection(&mut self, handle: SysHandle) {
+        self.listeners.remove(&handle);
+        self.conns.remove(&handle);
+    }
+
+    fn on_wakeup(&mut self, handle: SysHandle) {
+        if let Some(mut listener) = self.listeners.remove(&handle) {
+            if unsafe { listener.accept() }.is_err() {
+                return;
+            }
+            self.conns.insert(
+                handle,
+                IoConnection {
+                    pcon: PerConnectionData::with_peer(handle),
+                    conn: listener,
+                    pending: std::collections::VecDeque::new(),
+                },
+            );
+        }
+
+        let Some(io_conn) = self.conns.get_mut(&handle) else {
+            return;
+        };
+        if !io_conn.process() {
+            log::info!(
+                "fs: dropping io_channel connection 0x{:x}.",
+                handle.as_u64()
+            );
+            self.drop_connection(handle);
+        }
+    }
+
+    fn flush_pending(&mut self) {
+        let mut dead = Vec::new();
+        for (handle, io_conn) in self.conns.iter_mut() {
+            if io_conn.flush_pending() && moto_sys::SysCpu::wake(*handle).is_err() {
+                dead.push(*handle);
+            }
+        }
+        for handle in dead {
+            self.drop_connection(handle);
+        }
     }
 }
 
 struct Driver {
     ipc_server: LocalServer,
+    io: IoServer,
+    quotas: super::quota::DirQuotas,
+    versions: super::versions::FileVersions,
+    snapshots: super::snapshot::Snapshots,
+    writeback: super::writeback::Writeback,
+    defrag: super::defrag::Defragmenter,
 }
 
 static DRIVER_ADDRESS: AtomicUsize = AtomicUsize::new(0);
@@ -63,7 +268,15 @@ static DRIVER_ADDRESS: AtomicUsize = AtomicUsize::new(0);
 impl Driver {
     fn start() -> Result<(), ErrorCode> {
         let ipc_server = LocalServer::new(super::DRIVER_URL, ChannelSize::Small, 50, 20)?;
-        let driver = Box::leak(Box::new(Driver { ipc_server }));
+        let driver = Box::leak(Box::new(Driver {
+            ipc_server,
+            io: IoServer::default(),
+            quotas: super::quota::DirQuotas::load(),
+            versions: super::versions::FileVersions::default(),
+            snapshots: super::snapshot::Snapshots::new(),
+            writeback: super::writeback::Writeback::default(),
+            defrag: super::defrag::Defragmenter::default(),
+        }));
 
         let addr = driver as *mut _ as usize;
         let prev = DRIVER_ADDRESS.swap(addr, Ordering::Relaxed);
@@ -93,17 +306,38 @@ impl Driver {
 
         let self_ = Self::get();
         loop {
-            let wait_result = self_.ipc_server.wait(SysHandle::NONE, &[]);
+            self_.writeback.maybe_write_back();
+            self_.defrag.maybe_defragment();
+            self_.io.flush_pending();
+            let deadline = [
+                self_.writeback.deadline(),
+                self_.defrag.deadline(),
+                self_.io.deadline(),
+            ]
+            .into_iter()
+            .flatten()
+            .min();
+            let io_handles = self_.io.wait_handles();
+            let wait_result = self_
+                .ipc_server
+                .wait_timeout(SysHandle::NONE, &io_handles, deadline);
             let wakers = match wait_result {
                 Ok(wakers) => wakers,
                 Err(bad_wakers) => {
-                    assert!(bad_wakers.is_empty());
+                    // Only io_channel connections are left to the caller.
+                    for handle in bad_wakers {
+                        self_.io.drop_connection(handle);
+                    }
                     continue;
                 }
             };
 
             for idx in 0..wakers.len() {
                 let waker = &wakers[idx];
+                if self_.io.contains(*waker) {
+                    self_.io.on_wakeup(*waker);
+                    continue;
+                }
                 let conn = self_.ipc_server.get_connection(*waker);
                 if conn.is_none() {
                     continue;
@@ -129,6 +363,19 @@ impl Driver {
                         CMD_MKDIR => Self::on_mkdir(raw_channel),
                         CMD_UNLINK => Self::on_unlink(raw_channel),
                         CMD_RENAME => Self::on_rename(raw_channel),
+                        CMD_BARRIER => Self::on_barrier(raw_channel),
+                        CMD_SYNC_FILES => Self::on_sync_files(conn, raw_channel),
+                        CMD_SET_DIR_QUOTA | CMD_GET_DIR_QUOTA => {
+                            Self::on_dir_quota(conn, raw_channel)
+                        }
+                        CMD_FILE_VERSION => Self::on_file_version(conn, raw_channel),
+                        CMD_FILE_WRITE_IF_VERSION => {
+                            Self::on_file_write_if_version(conn, raw_channel)
+                        }
+                        CMD_STATFS => Self::on_statfs(conn, raw_channel),
+                        CMD_SNAPSHOT | CMD_RELEASE_SNAPSHOT => Self::on_snapshot(conn, raw_channel),
+                        CMD_WRITEBACK => Self::on_writeback(conn, raw_channel),
+                        CMD_DEFRAGMENT => Self::on_defragment(conn, raw_channel),
                         _ => Err(moto_rt::E_INVALID_ARGUMENT),
                     };
 
@@ -159,6 +406,20 @@ impl Driver {
         }
     }
 
+    // Credentials are taken from the kernel at connect time, not from the request.
+    fn peer_has_cap_sys(conn: &mut LocalServerConnection) -> bool {
+        let caps = match conn.extension_mut::<PerConnectionData>() {
+            Some(pcon) => pcon.caps,
+            None => {
+                let pcon = Box::new(PerConnectionData::new(conn));
+                let caps = pcon.caps;
+                conn.set_extension(pcon);
+                caps
+            }
+        };
+        (caps & moto_sys::caps::CAP_SYS) != 0
+    }
+
     unsafe fn on_mkdir(raw_channel: RawChannel) -> Result<(), ErrorCode> {
         let req = raw_channel.get::<MkdirRequest>();
         assert_eq!(req.header.cmd, CMD_MKDIR);
@@ -181,13 +442,302 @@ impl Driver {
             }
         };
 
+        // Snapshots are keyed by path: an alias that differs only by case must
+        // not bypass them (nor is_reserved()).
+        let fname = &Self::canonical_path(fname)?;
+
+        let snapshots = &mut Self::get().snapshots;
+        snapshots.check_writable(fname)?;
+        snapshots.before_create(fname, &mut Self::get().quotas);
+
+        let quotas = &mut Self::get().quotas;
+        quotas.check(fname, 0, 1, None)?;
         super::filesystem::fs().mkdir(fname)?;
+        quotas.charge(fname, 0, 1);
 
         let resp = raw_channel.get_mut::<CloseFdResponse>();
         resp.header.result = 0;
         Ok(())
     }
 
+    unsafe fn on_barrier(raw_channel: RawChannel) -> Result<(), ErrorCode> {
+        let req = raw_channel.get::<BarrierRequest>();
+        assert_eq!(req.header.cmd, CMD_BARRIER);
+
+        if (req.header.ver != 0) || (req.header.flags != 0) {
+            return Err(moto_rt::E_INTERNAL_ERROR);
+        }
+
+        fs().barrier()?;
+        Self::get().writeback.on_flushed();
+
+        let resp = raw_channel.get_mut::<BarrierResponse>();
+        resp.header.result = 0;
+        Ok(())
+    }
+
+    unsafe fn on_sync_files(
+        conn: &mut LocalServerConnection,
+        raw_channel: RawChannel,
+    ) -> Result<(), ErrorCode> {
+        let req = raw_channel.get::<SyncFilesRequest>();
+        assert_eq!(req.header.cmd, CMD_SYNC_FILES);
+
+        if (req.header.ver != 0) || (req.header.flags != 0) {
+            return Err(moto_rt::E_INTERNAL_ERROR);
+        }
+
+        let num_fds = req.num_fds as usize;
+        if num_fds > SyncFilesRequest::max_fds(&raw_channel) {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        let pcon = {
+            match conn.extension_mut::<PerConnectionData>() {
+                Some(pcon) => pcon,
+                None => return Err(moto_rt::E_INTERNAL_ERROR),
+            }
+        };
+
+        if req.parent_fd != 0 && pcon.get_readdir(req.parent_fd).is_none() {
+            return Err(moto_rt::E_BAD_HANDLE);
+        }
+
+        let fds_bytes = raw_channel.get_bytes(
+            req.fds.as_ptr() as *const u8,
+            num_fds * core::mem::size_of::<u64>(),
+        )?;
+        let fds = core::slice::from_raw_parts(fds_bytes.as_ptr() as *const u64, num_fds);
+
+        // Validate all fds before flushing, so that a bad fd fails the whole
+        // request without doing any I/O.
+        for fd in fds {
+            if pcon.get_file(*fd).is_none() {
+                return Err(moto_rt::E_BAD_HANDLE);
+            }
+        }
+
+        // A single barrier covers all files: FileSystem::barrier() flushes
+        // the underlying device, including directory metadata.
+        fs().barrier()?;
+        Self::get().writeback.on_flushed();
+
+        let resp = raw_channel.get_mut::<SyncFilesResponse>();
+        resp.header.result = 0;
+        Ok(())
+    }
+
+    unsafe fn on_dir_quota(
+        conn: &mut LocalServerConnection,
+        raw_channel: RawChannel,
+    ) -> Result<(), ErrorCode> {
+        let req = raw_channel.get::<DirQuotaRequest>();
+
+        if (req.header.ver != 0) || (req.header.flags != 0) {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        if req.header.cmd == CMD_SET_DIR_QUOTA && !Self::peer_has_cap_sys(conn) {
+            return Err(moto_rt::E_NOT_ALLOWED);
+        }
+
+        let fname_bytes = match raw_channel.get_bytes(req.fname.as_ptr(), req.fname_size as usize) {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                return Err(moto_rt::E_INVALID_FILENAME);
+            }
+        };
+
+        let fname = match core::str::from_utf8(fname_bytes) {
+            Ok(fname) => fname,
+            Err(_) => {
+                return Err(moto_rt::E_INVALID_FILENAME);
+            }
+        };
+
+        let fname = &Self::canonical_path(fname)?;
+        let quotas = &mut Self::get().quotas;
+        let quota = if req.header.cmd == CMD_SET_DIR_QUOTA {
+            quotas.set(fname, req.max_bytes, req.max_inodes)?;
+            // A removed quota is reported as unlimited.
+            quotas.get(fname).unwrap_or(moto_rt::fs::DirQuota {
+                max_bytes: moto_rt::fs::QUOTA_UNLIMITED,
+                max_inodes: moto_rt::fs::QUOTA_UNLIMITED,
+                used_bytes: 0,
+                used_inodes: 0,
+            })
+        } else {
+            quotas.get(fname)?
+        };
+
+        let resp = raw_channel.get_mut::<DirQuotaResponse>();
+        resp.header.result = 0;
+        resp.quota = quota;
+        Ok(())
+    }
+
+    unsafe fn on_statfs(
+        conn: &mut LocalServerConnection,
+        raw_channel: RawChannel,
+    ) -> Result<(), ErrorCode> {
+        let req = raw_channel.get::<StatFsRequest>();
+
+        if (req.header.ver != 0) || (req.header.flags & !StatFsRequest::F_SET_QUEUE_DEPTH) != 0 {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        if (req.header.flags & StatFsRequest::F_SET_QUEUE_DEPTH) != 0
+            && !Self::peer_has_cap_sys(conn)
+        {
+            return Err(moto_rt::E_NOT_ALLOWED);
+        }
+
+        let fname_bytes = match raw_channel.get_bytes(req.fname.as_ptr(), req.fname_size as usize) {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                return Err(moto_rt::E_INVALID_FILENAME);
+            }
+        };
+
+        let fname = match core::str::from_utf8(fname_bytes) {
+            Ok(fname) => fname,
+            Err(_) => {
+                return Err(moto_rt::E_INVALID_FILENAME);
+            }
+        };
+
+        // There is a single volume, but fname must be on it.
+        fs().stat(fname)?;
+
+        if (req.header.flags & StatFsRequest::F_SET_QUEUE_DEPTH) != 0 {
+            super::filesystem::set_queue_depth(req.queue_depth)?;
+        }
+
+        let resp = raw_channel.get_mut::<StatFsResponse>();
+        resp.header.result = 0;
+        resp.statfs = super::filesystem::statfs();
+        Ok(())
+    }
+
+    unsafe fn on_snapshot(
+        conn: &mut LocalServerConnection,
+        raw_channel: RawChannel,
+    ) -> Result<(), ErrorCode> {
+        let req = raw_channel.get::<SnapshotRequest>();
+
+        if (req.header.ver != 0) || (req.header.flags != 0) {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        // A snapshot pins the space of everything below it.
+        if !Self::peer_has_cap_sys(conn) {
+            return Err(moto_rt::E_NOT_ALLOWED);
+        }
+
+        let snapshots = &mut Self::get().snapshots;
+        let id = if req.header.cmd == CMD_SNAPSHOT {
+            let fname_bytes =
+                match raw_channel.get_bytes(req.fname.as_ptr(), req.fname_size as usize) {
+                    Ok(bytes) => bytes,
+                    Err(_) => {
+                        return Err(moto_rt::E_INVALID_FILENAME);
+                    }
+                };
+
+            let fname = match core::str::from_utf8(fname_bytes) {
+                Ok(fname) => fname,
+                Err(_) => {
+                    return Err(moto_rt::E_INVALID_FILENAME);
+                }
+            };
+
+            snapshots.create(&Self::canonical_path(fname)?)?
+        } else {
+            snapshots.release(req.id, &mut Self::get().quotas)?;
+            req.id
+        };
+
+        let resp = raw_channel.get_mut::<SnapshotResponse>();
+        resp.header.result = 0;
+        resp.id = id;
+        Ok(())
+    }
+
+    unsafe fn on_writeback(
+        conn: &mut LocalServerConnection,
+        raw_channel: RawChannel,
+    ) -> Result<(), ErrorCode> {
+        let req = raw_channel.get::<WritebackRequest>();
+
+        if (req.header.ver != 0)
+            || (req.header.flags & !(WritebackRequest::F_SET_POLICY | WritebackRequest::F_FORCE))
+                != 0
+        {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        if (req.header.flags & WritebackRequest::F_SET_POLICY) != 0 && !Self::peer_has_cap_sys(conn)
+        {
+            return Err(moto_rt::E_NOT_ALLOWED);
+        }
+
+        let writeback = &mut Self::get().writeback;
+        if (req.header.flags & WritebackRequest::F_SET_POLICY) != 0 {
+            writeback.set_policy(req.policy)?;
+        }
+        if (req.header.flags & WritebackRequest::F_FORCE) != 0 {
+            fs().barrier()?;
+            writeback.on_flushed();
+        }
+
+        let resp = raw_channel.get_mut::<WritebackResponse>();
+        resp.header.result = 0;
+        resp.stats = writeback.stats();
+        Ok(())
+    }
+
+    unsafe fn on_defragment(
+        conn: &mut LocalServerConnection,
+        raw_channel: RawChannel,
+    ) -> Result<(), ErrorCode> {
+        let req = raw_channel.get::<DefragmentRequest>();
+        assert_eq!(req.header.cmd, CMD_DEFRAGMENT);
+
+        if (req.header.ver != 0) || (req.header.flags & !DefragmentRequest::F_START) != 0 {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        let pcon = {
+            match conn.extension_mut::<PerConnectionData>() {
+                Some(pcon) => pcon,
+                None => return Err(moto_rt::E_INTERNAL_ERROR),
+            }
+        };
+
+        let Some(path) = pcon.file_paths.get(&req.fd).cloned() else {
+            return Err(moto_rt::E_BAD_HANDLE);
+        };
+        let Some(file) = pcon.get_file(req.fd) else {
+            return Err(moto_rt::E_BAD_HANDLE);
+        };
+        let (blocks, extents) = file.extents()?;
+
+        let defrag = &mut Self::get().defrag;
+        if (req.header.flags & DefragmentRequest::F_START) != 0 {
+            Self::get().snapshots.check_writable(&path)?;
+            defrag.start(&path)?;
+        }
+
+        let resp = raw_channel.get_mut::<DefragmentResponse>();
+        resp.header.result = 0;
+        resp.extents = moto_rt::fs::FileExtents {
+            blocks,
+            extents,
+            pending_blocks: defrag.pending_blocks(&path),
+        };
+        Ok(())
+    }
+
     unsafe fn on_unlink(raw_channel: RawChannel) -> Result<(), ErrorCode> {
         let req = raw_channel.get::<UnlinkRequest>();
         assert_eq!(req.header.cmd, CMD_UNLINK);
@@ -210,6 +760,22 @@ impl Driver {
             }
         };
 
+        let fname = &Self::canonical_path(fname)?;
+
+        let snapshots = &mut Self::get().snapshots;
+        snapshots.check_writable(fname)?;
+        snapshots.before_remove(fname, &mut Self::get().quotas);
+
+        let quotas = &mut Self::get().quotas;
+        let usage = if quotas.is_empty() {
+            None
+        } else {
+            Some(super::quota::entry_usage(fname)?)
+        };
+
+        if req.header.flags == F_UNLINK_FILE {
+            Self::get().versions.before_remove(fname);
+        }
         match req.header.flags {
             F_UNLINK_FILE => super::filesystem::fs().unlink(fname)?,
             F_UNLINK_DIR => super::filesystem::fs().delete_dir(fname)?,
@@ -217,11 +783,47 @@ impl Driver {
             _ => return Err(moto_rt::E_INVALID_ARGUMENT),
         }
 
+        if let Some((bytes, inodes)) = usage {
+            quotas.release(fname, bytes, inodes);
+            if req.header.flags != F_UNLINK_FILE {
+                quotas.remove_subtree(fname);
+            }
+        }
+        Self::get().defrag.remove_subtree(fname);
+
         let resp = raw_channel.get_mut::<UnlinkResponse>();
         resp.header.result = 0;
         Ok(())
     }
 
+    // @fname as the driver's state (quotas, snapshots, open files) is keyed by:
+    // see FileSystem::canonical_path(). Snapshot views are not on disk, so
+    // Snapshots resolves them.
+    fn canonical_path(fname: &str) -> Result<String, ErrorCode> {
+        match Self::get().snapshots.canonical_view_path(fname) {
+            Some(view) => Ok(view),
+            None => fs().canonical_path(fname),
+        }
+    }
+
+    // The path @new will have after renaming @old (canonical) onto it: its
+    // canonical path, unless only the case of @old changes.
+    fn canonical_rename_target(old: &str, new: &str) -> Result<String, ErrorCode> {
+        let canonical = Self::canonical_path(new)?;
+        if canonical != old {
+            return Ok(canonical);
+        }
+
+        let (Some(dir), Some(name)) = (super::quota::parent(old), new.rsplit('/').next()) else {
+            return Ok(canonical);
+        };
+        if dir == "/" {
+            Ok(alloc::format!("/{}", name))
+        } else {
+            Ok(alloc::format!("{}/{}", dir, name))
+        }
+    }
+
     unsafe fn on_rename(raw_channel: RawChannel) -> Result<(), ErrorCode> {
         let req = raw_channel.get::<RenameRequest>();
         assert_eq!(req.header.cmd, CMD_RENAME);
@@ -230,17 +832,44 @@ impl Driver {
             return Err(moto_rt::E_INVALID_ARGUMENT);
         }
 
-        let old = req.old(&raw_channel)?;
-        let new = req.new(&raw_channel)?;
-
-        log::debug!("driver: rename: {} -> {}", old, new);
+        Self::rename(req.old(&raw_channel)?, req.new(&raw_channel)?)?;
 
-        super::filesystem::fs().rename(old, new)?;
         let resp = raw_channel.get_mut::<RenameResponse>();
         resp.header.result = 0;
         Ok(())
     }
 
+    fn rename(old: &str, new: &str) -> Result<(), ErrorCode> {
+        let old = &Self::canonical_path(old)?;
+        let new = &Self::canonical_rename_target(old, new)?;
+
+        log::debug!("driver: rename: {} -> {}", old, new);
+
+        let snapshots = &mut Self::get().snapshots;
+        snapshots.check_writable(old)?;
+        snapshots.check_writable(new)?;
+        snapshots.before_remove(old, &mut Self::get().quotas);
+        snapshots.before_remove(new, &mut Self::get().quotas); // Replaced, or created.
+
+        let quotas = &mut Self::get().quotas;
+        if quotas.is_empty() {
+            super::filesystem::fs().rename(old, new)?;
+        } else {
+            // Quotas shared by old and new are not affected.
+            let (bytes, inodes) = super::quota::entry_usage(old)?;
+            quotas.check(new, bytes, inodes, Some(old))?;
+            super::filesystem::fs().rename(old, new)?;
+            quotas.release(old, bytes, inodes);
+            quotas.charge(new, bytes, inodes);
+            quotas.rename_subtree(old, new);
+        }
+
+        let defrag = &mut Self::get().defrag;
+        defrag.remove_subtree(new);
+        defrag.rename_subtree(old, new);
+        Ok(())
+    }
+
     unsafe fn on_readdir(
         conn: &mut LocalServerConnection,
         raw_channel: RawChannel,
@@ -266,12 +895,17 @@ impl Driver {
             }
         };
 
-        let iter = fs().iter(fname)?;
+        let fname = &Self::canonical_path(fname)?;
+        let iter = if super::snapshot::is_view(fname) {
+            Self::get().snapshots.iter(fname)?
+        } else {
+            fs().iter(fname)?
+        };
         let pcon = {
             match conn.extension_mut::<PerConnectionData>() {
                 Some(pcon) => pcon,
                 None => {
-                    let pcon = Box::new(PerConnectionData::new());
+                    let pcon = Box::new(PerConnectionData::new(conn));
                     conn.set_extension(pcon);
                     conn.extension_mut::<PerConnectionData>().unwrap()
                 }
@@ -377,15 +1011,51 @@ impl Driver {
             }
         };
 
-        let mut flags = req.header.flags;
+        let fname = &Self::canonical_path(fname)?;
+        let file = Self::open_file(fname, req.header.flags)?;
+        Self::add_open_file(conn, raw_channel, file, fname)
+    }
+
+    // @fname is canonical.
+    fn open_file(
+        fname: &str,
+        open_flags: u32,
+    ) -> Result<Box<dyn super::filesystem::File>, ErrorCode> {
+        let snapshots = &mut Self::get().snapshots;
+        if super::snapshot::is_view(fname) {
+            if open_flags != moto_rt::fs::O_READ {
+                return Err(moto_rt::E_NOT_ALLOWED);
+            }
+            return snapshots.open_file(fname);
+        }
+        snapshots.check_writable(fname)?;
+
+        let quotas = &mut Self::get().quotas;
+        let mut flags = open_flags;
         if (flags & moto_rt::fs::O_CREATE_NEW) != 0 {
+            snapshots.before_create(fname, quotas);
+            quotas.check(fname, 0, 1, None)?;
             fs().create_file(fname)?;
+            quotas.charge(fname, 0, 1);
             flags ^= moto_rt::fs::O_CREATE_NEW;
         }
 
         if flags == (moto_rt::fs::O_CREATE | moto_rt::fs::O_TRUNCATE | moto_rt::fs::O_WRITE) {
-            fs().unlink(fname).ok();
+            snapshots.before_remove(fname, quotas); // Unlinked and created again below.
+            Self::get().versions.before_remove(fname);
+            let old_size = if quotas.is_empty() {
+                None
+            } else {
+                fs().stat(fname).ok().map(|attr| attr.size)
+            };
+            if fs().unlink(fname).is_ok() {
+                if let Some(old_size) = old_size {
+                    quotas.release(fname, old_size, 1);
+                }
+            }
+            quotas.check(fname, 0, 1, None)?;
             fs().create_file(fname)?;
+            quotas.charge(fname, 0, 1);
             flags = moto_rt::fs::O_WRITE;
         }
 
@@ -394,22 +1064,26 @@ impl Driver {
             && flags != moto_rt::fs::O_APPEND
         {
             moto_sys::SysRay::log(
-                alloc::format!(
-                    "on_file_open: flags not supported: 0x{:x}",
-                    req.header.flags
-                )
-                .as_str(),
+                alloc::format!("on_file_open: flags not supported: 0x{:x}", open_flags).as_str(),
             )
             .ok();
             return Err(moto_rt::E_NOT_IMPLEMENTED);
         }
 
-        let mut file = fs().open_file(fname)?;
+        fs().open_file(fname)
+    }
+
+    unsafe fn add_open_file(
+        conn: &mut LocalServerConnection,
+        raw_channel: RawChannel,
+        mut file: Box<dyn super::filesystem::File>,
+        fname: &str,
+    ) -> Result<(), ErrorCode> {
         let pcon = {
             match conn.extension_mut::<PerConnectionData>() {
                 Some(pcon) => pcon,
                 None => {
-                    let pcon = Box::new(PerConnectionData::new());
+                    let pcon = Box::new(PerConnectionData::new(conn));
                     conn.set_extension(pcon);
                     conn.extension_mut::<PerConnectionData>().unwrap()
                 }
@@ -417,7 +1091,7 @@ impl Driver {
         };
 
         let file_sz = file.size()?;
-        let fd = pcon.add_file(file);
+        let fd = pcon.add_file(file, fname);
 
         let resp = raw_channel.get_mut::<FileOpenResponse>();
         resp.header.result = 0;
@@ -454,6 +1128,7 @@ impl Driver {
 
             let buf = raw_channel.get_bytes_mut(resp.data.as_mut_ptr(), buf_size)?;
             let bytes_read = file.read_offset(req.offset, buf)?;
+            crate::runtime::io_usage::on_fs_read(pcon.pid, bytes_read);
 
             resp.size = bytes_read as u32;
 
@@ -485,14 +1160,14 @@ impl Driver {
             }
         };
 
-        let p_file = pcon.get_file(req.fd);
-        if p_file.is_none() {
-            return Err(moto_rt::E_INTERNAL_ERROR);
-        }
+        let no_flush = match req.header.flags {
+            0 => false,
+            FileWriteRequest::F_NO_FLUSH => true,
+            _ => return Err(moto_rt::E_INVALID_ARGUMENT),
+        };
 
-        let file = p_file.unwrap();
         let buf = unsafe { core::slice::from_raw_parts(&req.data as *const u8, req.size as usize) };
-        let written = file.write_offset(req.offset, buf)?;
+        let (written, _) = Self::write_file(pcon, req.fd, req.offset, buf, no_flush)?;
 
         let resp = raw_channel.get_mut::<FileWriteResponse>();
         resp.header.result = 0;
@@ -501,6 +1176,359 @@ impl Driver {
         Ok(())
     }
 
+    unsafe fn on_file_version(
+        conn: &mut LocalServerConnection,
+        raw_channel: RawChannel,
+    ) -> Result<(), ErrorCode> {
+        let req = raw_channel.get::<FileVersionRequest>();
+        assert_eq!(req.header.cmd, CMD_FILE_VERSION);
+
+        if (req.header.ver != 0) || (req.header.flags != 0) {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        let pcon = {
+            match conn.extension_mut::<PerConnectionData>() {
+                Some(pcon) => pcon,
+                None => return Err(moto_rt::E_INTERNAL_ERROR),
+            }
+        };
+
+        let Some(file) = pcon.get_file(req.fd) else {
+            return Err(moto_rt::E_BAD_HANDLE);
+        };
+        let version = Self::get().versions.get(file.inode()?);
+
+        let resp = raw_channel.get_mut::<FileVersionResponse>();
+        resp.header.result = 0;
+        resp.version = version;
+        Ok(())
+    }
+
+    unsafe fn on_file_write_if_version(
+        conn: &mut LocalServerConnection,
+        raw_channel: RawChannel,
+    ) -> Result<(), ErrorCode> {
+        let req = raw_channel.get::<FileWriteIfVersionRequest>();
+        assert_eq!(req.header.cmd, CMD_FILE_WRITE_IF_VERSION);
+
+        if (req.header.ver != 0) || (req.header.flags != 0) || (req.size == 0) {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        if ((req.size as usize) + core::mem::size_of::<FileWriteIfVersionRequest>())
+            > raw_channel.size()
+        {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        let pcon = {
+            match conn.extension_mut::<PerConnectionData>() {
+                Some(pcon) => pcon,
+                None => return Err(moto_rt::E_INTERNAL_ERROR),
+            }
+        };
+
+        let current = match pcon.get_file(req.fd) {
+            Some(file) => Self::get().versions.get(file.inode()?),
+            None => return Err(moto_rt::E_BAD_HANDLE),
+        };
+
+        // The response overlaps the request, so it is written last.
+        if current != req.expected_version {
+            let resp = raw_channel.get_mut::<FileWriteIfVersionResponse>();
+            resp.version = current;
+            return Err(moto_rt::E_ALREADY_IN_USE);
+        }
+
+        let size = req.size as usize;
+        let buf = unsafe { core::slice::from_raw_parts(&req.data as *const u8, size) };
+        let (written, version) = Self::write_file(pcon, req.fd, req.offset, buf, false)?;
+        if written != size {
+            return Err(moto_rt::E_IO_ERROR); // The file has changed (and its version).
+        }
+
+        let resp = raw_channel.get_mut::<FileWriteIfVersionResponse>();
+        resp.header.result = 0;
+        resp.version = version;
+        Ok(())
+    }
+
+    // Writes @buf at @offset into file @fd, enforcing quotas, and bumps the file version.
+    // Returns (bytes written, the new version).
+    fn write_file(
+        pcon: &mut PerConnectionData,
+        fd: u64,
+        offset: u64,
+        buf: &[u8],
+        no_flush: bool,
+    ) -> Result<(usize, u64), ErrorCode> {
+        let quotas = &mut Self::get().quotas;
+        let quota_path = if quotas.is_empty() {
+            None
+        } else {
+            pcon.file_paths.get(&fd).cloned()
+        };
+
+        if let Some(path) = pcon.file_paths.get(&fd) {
+            Self::get()
+                .snapshots
+                .before_modify(path, &mut Self::get().quotas);
+        }
+
+        let p_file = pcon.get_file(fd);
+        if p_file.is_none() {
+            return Err(moto_rt::E_INTERNAL_ERROR);
+        }
+
+        let file = p_file.unwrap();
+
+        // Only the growth of the file is charged against quotas.
+        let old_size = match quota_path.as_ref() {
+            Some(path) => {
+                let old_size = file.size()?;
+                let new_size = offset + (buf.len() as u64);
+                quotas.check(path, new_size.saturating_sub(old_size), 0, None)?;
+                old_size
+            }
+            None => 0,
+        };
+
+        // Writes may be short (srfs stops at block boundaries, unless it
+        // writes whole blocks at once), so they are continued here, and
+        // flushed once at the end.
+        let mut written = 0;
+        while written < buf.len() {
+            match file.write_offset_no_flush(offset + (written as u64), &buf[written..]) {
+                Ok(0) => break,
+                Ok(sz) => written += sz,
+                Err(err) if written == 0 => return Err(err),
+                Err(_) => break,
+            }
+        }
+        crate::runtime::io_usage::on_fs_write(pcon.pid, written);
+        if !no_flush {
+            fs().barrier()?;
+        }
+        // A flushed write makes all writes before it durable, too.
+        let writeback = &mut Self::get().writeback;
+        if no_flush {
+            writeback.on_dirty(offset, written as u64);
+        } else {
+            writeback.on_flushed();
+        }
+
+        if let Some(path) = quota_path.as_ref() {
+            let new_size = offset + (written as u64);
+            quotas.charge(path, new_size.saturating_sub(old_size), 0);
+        }
+        // Files without inodes (e.g. on flatfs) are not versioned.
+        let version = match pcon.get_file(fd).unwrap().inode() {
+            Ok(inode) => Self::get().versions.bump(inode),
+            Err(_) => 0,
+        };
+
+        Ok((written, version))
+    }
+
+    fn on_io_sqe(
+        conn: &moto_ipc::io_channel::ServerConnection,
+        pcon: &mut PerConnectionData,
+        sqe: moto_ipc::io_channel::Msg,
+    ) -> moto_ipc::io_channel::Msg {
+        let mut cqe = sqe;
+        let result = match sqe.command {
+            CMD_IO_OPEN => Self::on_io_open(conn, pcon, &mut cqe),
+            CMD_IO_READ => Self::on_io_read(conn, pcon, &mut cqe),
+            CMD_IO_WRITE => Self::on_io_write(conn, pcon, &mut cqe),
+            CMD_IO_FSYNC => Self::on_io_fsync(pcon, &cqe),
+            CMD_IO_STAT => Self::on_io_stat(conn, &mut cqe),
+            CMD_IO_RENAME => Self::on_io_rename(conn, &mut cqe),
+            CMD_IO_CLOSE => {
+                if pcon.get_file(cqe.handle).is_some() {
+                    pcon.remove_file(cqe.handle);
+                    Ok(())
+                } else {
+                    Err(moto_rt::E_BAD_HANDLE)
+                }
+            }
+            _ => Err(moto_rt::E_INVALID_ARGUMENT),
+        };
+
+        cqe.status = match result {
+            Ok(()) => moto_rt::E_OK,
+            Err(err) => {
+                // Client pages have been freed, and server pages are attached only on success.
+                cqe.flags = 0;
+                err
+            }
+        };
+        cqe
+    }
+
+    fn on_io_open(
+        conn: &moto_ipc::io_channel::ServerConnection,
+        pcon: &mut PerConnectionData,
+        cqe: &mut moto_ipc::io_channel::Msg,
+    ) -> Result<(), ErrorCode> {
+        let page = conn.get_page(cqe.payload.shared_pages()[0])?;
+        let len = cqe.flags as usize;
+        if len == 0 || len > moto_rt::fs::MAX_PATH_LEN {
+            return Err(moto_rt::E_INVALID_FILENAME);
+        }
+        let fname =
+            core::str::from_utf8(&page.bytes()[0..len]).map_err(|_| moto_rt::E_INVALID_FILENAME)?;
+        let fname = &Self::canonical_path(fname)?;
+
+        let mut file = Self::open_file(fname, cqe.payload.args_32()[5])?;
+        let size = file.size()?;
+        cqe.handle = pcon.add_file(file, fname);
+        cqe.flags = 0;
+        cqe.payload.args_64_mut()[0] = size;
+        Ok(())
+    }
+
+    fn on_io_read(
+        conn: &moto_ipc::io_channel::ServerConnection,
+        pcon: &mut PerConnectionData,
+        cqe: &mut moto_ipc::io_channel::Msg,
+    ) -> Result<(), ErrorCode> {
+        use moto_ipc::io_channel::{NO_PAGE, PAGE_SIZE};
+
+        let len = cqe.flags as usize;
+        if len == 0 || len > IO_MAX_BYTES {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+        let offset = cqe.payload.args_64()[2];
+        let Some(file) = pcon.get_file(cqe.handle) else {
+            return Err(moto_rt::E_BAD_HANDLE);
+        };
+
+        // The pages are freed when dropped, unless attached to the CQE below.
+        let pages = conn.alloc_pages(IO_SUBCHANNEL_MASK, len.div_ceil(PAGE_SIZE))?;
+
+        // Read into a buffer aligned as the file is, so that whole blocks
+        // are read straight from the device, in one request.
+        let skew = (offset as usize) & (FS_BLOCK_SIZE - 1);
+        let mut blocks = AlignedBlocks::new(skew + len);
+        let buf = &mut blocks.bytes_mut()[skew..(skew + len)];
+        let mut done = 0;
+        while done < len {
+            match file.read_offset(offset + (done as u64), &mut buf[done..]) {
+                Ok(0) => break, // EOF.
+                Ok(read) => done += read,
+                Err(err) if done == 0 => return Err(err),
+                Err(_) => break,
+            }
+        }
+        crate::runtime::io_usage::on_fs_read(pcon.pid, done);
+        for (page, chunk) in pages.iter().zip(buf[0..done].chunks(PAGE_SIZE)) {
+            page.bytes_mut()[0..chunk.len()].copy_from_slice(chunk);
+        }
+
+        cqe.flags = 0;
+        cqe.payload.shared_pages_mut()[0..IO_MAX_PAGES].fill(NO_PAGE);
+        for (slot, page) in pages.into_iter().take(done.div_ceil(PAGE_SIZE)).enumerate() {
+            moto_ipc::io_channel::ServerConnection::attach_page(cqe, slot, page)?;
+        }
+        cqe.payload.args_64_mut()[2] = done as u64;
+        Ok(())
+    }
+
+    fn on_io_write(
+        conn: &moto_ipc::io_channel::ServerConnection,
+        pcon: &mut PerConnectionData,
+        cqe: &mut moto_ipc::io_channel::Msg,
+    ) -> Result<(), ErrorCode> {
+        use moto_ipc::io_channel::PAGE_SIZE;
+
+        // Take the client's pages first, so that they are freed whatever happens below.
+        let pages = conn.get_pages(cqe, IO_MAX_PAGES)?;
+        let len = cqe.flags as usize;
+        if len == 0 || len > pages.len() * PAGE_SIZE || len <= (pages.len() - 1) * PAGE_SIZE {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+        if pcon.get_file(cqe.handle).is_none() {
+            return Err(moto_rt::E_BAD_HANDLE);
+        }
+
+        // Gathered, aligned as in the file (see on_io_read()), so that the
+        // write is a single FS request, and whole blocks a single device request.
+        let offset = cqe.payload.args_64()[2];
+        let skew = (offset as usize) & (FS_BLOCK_SIZE - 1);
+        let mut blocks = AlignedBlocks::new(skew + len);
+        let buf = &mut blocks.bytes_mut()[skew..(skew + len)];
+        for (page, chunk) in pages.iter().zip(buf.chunks_mut(PAGE_SIZE)) {
+            chunk.copy_from_slice(&page.bytes()[0..chunk.len()]);
+        }
+        core::mem::drop(pages);
+
+        let (written, _) = Self::write_file(pcon, cqe.handle, offset, buf, false)?;
+        cqe.flags = 0;
+        cqe.payload.args_64_mut()[2] = written as u64;
+        Ok(())
+    }
+
+    fn on_io_fsync(
+        pcon: &mut PerConnectionData,
+        cqe: &moto_ipc::io_channel::Msg,
+    ) -> Result<(), ErrorCode> {
+        if pcon.get_file(cqe.handle).is_none() {
+            return Err(moto_rt::E_BAD_HANDLE);
+        }
+
+        // As in on_sync_files().
+        fs().barrier()?;
+        Self::get().writeback.on_flushed();
+        Ok(())
+    }
+
+    fn on_io_stat(
+        conn: &moto_ipc::io_channel::ServerConnection,
+        cqe: &mut moto_ipc::io_channel::Msg,
+    ) -> Result<(), ErrorCode> {
+        use moto_ipc::io_channel::NO_PAGE;
+
+        // As in on_io_open().
+        let page = conn.get_page(cqe.payload.shared_pages()[0])?;
+        let len = cqe.flags as usize;
+        if len == 0 || len > moto_rt::fs::MAX_PATH_LEN {
+            return Err(moto_rt::E_INVALID_FILENAME);
+        }
+        let fname =
+            core::str::from_utf8(&page.bytes()[0..len]).map_err(|_| moto_rt::E_INVALID_FILENAME)?;
+        let attr = Self::stat(fname)?;
+        core::mem::drop(page);
+
+        let page = conn.alloc_page(IO_SUBCHANNEL_MASK)?;
+        unsafe { (page.bytes_mut().as_mut_ptr() as *mut moto_rt::fs::FileAttr).write(attr) };
+        cqe.flags = 0;
+        cqe.payload.shared_pages_mut()[0..IO_MAX_PAGES].fill(NO_PAGE);
+        moto_ipc::io_channel::ServerConnection::attach_page(cqe, 0, page)?;
+        Ok(())
+    }
+
+    fn on_io_rename(
+        conn: &moto_ipc::io_channel::ServerConnection,
+        cqe: &mut moto_ipc::io_channel::Msg,
+    ) -> Result<(), ErrorCode> {
+        let page = conn.get_page(cqe.payload.shared_pages()[0])?;
+        let old_len = cqe.flags as usize;
+        let new_len = cqe.payload.args_32()[5] as usize;
+        let valid = 1..=moto_rt::fs::MAX_PATH_LEN;
+        if !valid.contains(&old_len) || !valid.contains(&new_len) {
+            return Err(moto_rt::E_INVALID_FILENAME);
+        }
+        let old = core::str::from_utf8(&page.bytes()[0..old_len])
+            .map_err(|_| moto_rt::E_INVALID_FILENAME)?;
+        let new = core::str::from_utf8(&page.bytes()[old_len..(old_len + new_len)])
+            .map_err(|_| moto_rt::E_INVALID_FILENAME)?;
+
+        Self::rename(old, new)?;
+        cqe.flags = 0;
+        Ok(())
+    }
+
     unsafe fn on_close_fd(
         conn: &mut LocalServerConnection,
         raw_channel: RawChannel,
@@ -566,7 +1594,7 @@ impl Driver {
             }
         };
 
-        let attr = fs().stat(fname)?;
+        let attr = Self::stat(fname)?;
 
         let resp = raw_channel.get_mut::<StatResponse>();
         resp.header.result = 0; // Ok.
@@ -575,8 +1603,22 @@ impl Driver {
 
         Ok(())
     }
+
+    fn stat(fname: &str) -> Result<moto_rt::fs::FileAttr, ErrorCode> {
+        let fname = &Self::canonical_path(fname)?;
+        if super::snapshot::is_view(fname) {
+            Self::get().snapshots.stat(fname)
+        } else {
+            fs().stat(fname)
+        }
+    }
 }
 
 pub fn start() -> Result<(), ErrorCode> {
     Driver::start()
 }
+
+// For files opened in snapshot views, which are read through the snapshot.
+pub(super) fn snapshots() -> &'static mut super::snapshot::Snapshots {
+    &mut Driver::get().snapshots
+}
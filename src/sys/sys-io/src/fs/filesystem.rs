@@ -6,7 +6,28 @@ use moto_sys::ErrorCode;
 pub trait File {
     fn size(&mut self) -> Result<u64, ErrorCode>;
     fn write_offset(&mut self, offset: u64, buf: &[u8]) -> Result<usize, ErrorCode>;
+    // Same as write_offset(), but the written data may not be durable until
+    // the next FileSystem::barrier() (or a write_offset()) completes.
+    fn write_offset_no_flush(&mut self, offset: u64, buf: &[u8]) -> Result<usize, ErrorCode>;
     fn read_offset(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, ErrorCode>;
+
+    // A number that identifies the file, survives renames, and is never reused.
+    fn inode(&mut self) -> Result<u64, ErrorCode> {
+        Err(moto_rt::E_NOT_IMPLEMENTED)
+    }
+
+    // The number of data blocks of the file, and of extents (runs of blocks
+    // consecutive on the device) they are in.
+    fn extents(&mut self) -> Result<(u64, u64), ErrorCode> {
+        Err(moto_rt::E_NOT_IMPLEMENTED)
+    }
+
+    // Moves up to @max_blocks data blocks, starting with @first_block, to make
+    // the file contiguous; the file stays readable. Returns the next block to
+    // move (the number of data blocks when done).
+    fn defragment(&mut self, _first_block: u64, _max_blocks: u64) -> Result<u64, ErrorCode> {
+        Err(moto_rt::E_NOT_IMPLEMENTED)
+    }
 }
 
 #[allow(unused)]
@@ -30,6 +51,15 @@ pub trait FileSystem {
     fn rename(&'static mut self, old: &str, new: &str) -> Result<(), ErrorCode>;
     fn delete_dir(&'static mut self, path: &str) -> Result<(), ErrorCode>;
     fn delete_dir_all(&'static mut self, path: &str) -> Result<(), ErrorCode>;
+    // All writes completed before barrier() are durable when it returns;
+    // writes issued after barrier() returns will not be reordered before it.
+    fn barrier(&'static mut self) -> Result<(), ErrorCode>;
+
+    // @path as stored on disk, so that the driver can key its state (quotas,
+    // snapshots, etc.) by path. Components that do not exist are kept as given.
+    fn canonical_path(&'static mut self, path: &str) -> Result<String, ErrorCode> {
+        Ok(path.to_owned())
+    }
 }
 
 // We can't have a pointer to dyn FileSystem, but we can have a pointer
@@ -45,6 +75,39 @@ pub fn fs() -> &'static mut Box<dyn FileSystem> {
     unsafe { &mut (*FS.load(std::sync::atomic::Ordering::Relaxed)).ptr }
 }
 
+// The volume (partition) FS is on, and the index of its drive in
+// moto_virtio::lsblk(); set in init().
+static VOLUME: std::sync::OnceLock<(moto_rt::fs::StatFs, usize)> = std::sync::OnceLock::new();
+
+pub fn statfs() -> moto_rt::fs::StatFs {
+    let (mut statfs, drive_idx) = *VOLUME.get().unwrap();
+    let stats = moto_virtio::blk_stats()[drive_idx];
+    statfs.queue_depth = stats.queue_depth as u32;
+    statfs.max_queue_depth = stats.max_queue_depth as u32;
+    statfs.peak_in_flight = stats.peak_in_flight;
+    statfs.completed_requests = stats.completed;
+    statfs
+}
+
+/// See moto_rt::fs::set_queue_depth().
+pub fn set_queue_depth(depth: u32) -> Result<(), ErrorCode> {
+    let (_, drive_idx) = *VOLUME.get().unwrap();
+    let depth = depth.min(u16::MAX as u32) as u16;
+    moto_virtio::set_blk_queue_depth(drive_idx, depth).map_err(|_| moto_rt::E_INTERNAL_ERROR)?;
+    Ok(())
+}
+
+fn set_volume(drive: &dyn moto_virtio::BlockDevice, drive_idx: usize, sectors: u64) {
+    let statfs = moto_rt::fs::StatFs {
+        block_size: drive.block_size(),
+        optimal_io_size: drive.optimal_io_size(),
+        total_bytes: sectors << moto_virtio::BLOCK_SIZE_LOG2,
+        ..Default::default()
+    };
+    log::debug!("FS volume: {:?}", statfs);
+    VOLUME.set((statfs, drive_idx)).unwrap();
+}
+
 pub fn init() {
     let mut drives = moto_virtio::lsblk();
     if drives.len() == 0 {
@@ -62,7 +125,7 @@ pub fn init() {
     unsafe { block.set_len(BLOCK_SIZE) }; // Safe because we just allocated with the same len.
 
     let mut fs: Option<Box<dyn FileSystem>> = None;
-    for drive in &mut drives {
+    for (drive_idx, drive) in drives.iter_mut().enumerate() {
         if let Ok(()) = drive.read(block.as_mut_slice(), 0, 1) {
             match super::mbr::Mbr::parse(block.as_slice()) {
                 Ok(mbr) => {
@@ -80,6 +143,7 @@ pub fn init() {
                                     pte.lba as u64,
                                     pte.sectors as u64,
                                 ));
+                                set_volume(drive.as_ref(), drive_idx, pte.sectors as u64);
                             }
                             super::mbr::PartitionType::SrFs => {
                                 if fs.is_some() {
@@ -92,6 +156,7 @@ pub fn init() {
                                     pte.lba as u64,
                                     pte.sectors as u64,
                                 ));
+                                set_volume(drive.as_ref(), drive_idx, pte.sectors as u64);
                             }
                             _ => continue,
                         }
@@ -113,7 +178,8 @@ pub fn init() {
         panic!("Couldn't find a data partition.");
     }
 
-    let holder = Box::leak(Box::new(FsHolder { ptr: fs.unwrap() }));
+    let fs = super::case_fold::maybe_wrap(fs.unwrap());
+    let holder = Box::leak(Box::new(FsHolder { ptr: fs }));
 
     assert!(FS
         .swap(holder, std::sync::atomic::Ordering::Relaxed)
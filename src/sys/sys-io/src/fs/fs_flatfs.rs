@@ -26,6 +26,10 @@ impl super::File for FileFlatFs {
         Err(moto_rt::E_NOT_ALLOWED)
     }
 
+    fn write_offset_no_flush(&mut self, _offset: u64, _buf: &[u8]) -> Result<usize, ErrorCode> {
+        Err(moto_rt::E_NOT_ALLOWED)
+    }
+
     fn read_offset(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, ErrorCode> {
         let offset = offset as usize;
         if offset == self.bytes.len() {
@@ -262,6 +266,10 @@ impl super::filesystem::FileSystem for FileSystemFlatFS {
         Err(moto_rt::E_NOT_ALLOWED)
     }
 
+    fn barrier(&'static mut self) -> Result<(), ErrorCode> {
+        Ok(()) // Read-only.
+    }
+
     fn rename(&'static mut self, _old: &str, _new: &str) -> Result<(), ErrorCode> {
         Err(moto_rt::E_NOT_ALLOWED)
     }
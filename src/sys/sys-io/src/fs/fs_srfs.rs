@@ -2,6 +2,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 
 use super::filesystem::FileSystem;
 use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
 use moto_sys::ErrorCode;
 
 const BLOCK_4K: usize = 4096;
@@ -9,10 +10,13 @@ const BLOCK_512: usize = 512;
 
 struct FileSystemSrFS {
     inner: srfs::FileSystem,
+    virtio_drive: Arc<dyn moto_virtio::BlockDevice>,
+    flush_writes: Arc<AtomicBool>, // Shared with DeviceAdapter.
 }
 
 struct File {
     inner: srfs::File,
+    flush_writes: Arc<AtomicBool>,
 }
 
 impl super::File for File {
@@ -24,9 +28,32 @@ impl super::File for File {
         self.inner.write_offset(offset, buf).map_err(to_error_code)
     }
 
+    fn write_offset_no_flush(&mut self, offset: u64, buf: &[u8]) -> Result<usize, ErrorCode> {
+        self.flush_writes.store(false, Ordering::Relaxed);
+        let result = self.inner.write_offset(offset, buf).map_err(to_error_code);
+        self.flush_writes.store(true, Ordering::Relaxed);
+        result
+    }
+
     fn read_offset(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, ErrorCode> {
         self.inner.read_offset(offset, buf).map_err(to_error_code)
     }
+
+    fn inode(&mut self) -> Result<u64, ErrorCode> {
+        Ok(self.inner.inode())
+    }
+
+    fn extents(&mut self) -> Result<(u64, u64), ErrorCode> {
+        let blocks = self.inner.data_blocks().map_err(to_error_code)?;
+        let extents = self.inner.extents().map_err(to_error_code)?;
+        Ok((blocks, extents))
+    }
+
+    fn defragment(&mut self, first_block: u64, max_blocks: u64) -> Result<u64, ErrorCode> {
+        self.inner
+            .defragment(first_block, max_blocks)
+            .map_err(to_error_code)
+    }
 }
 
 struct DirectoryEntrySrFs {
@@ -79,7 +106,10 @@ impl super::DirectoryIter for DirectoryIterSrFs {}
 impl super::filesystem::FileSystem for FileSystemSrFS {
     fn open_file(&'static mut self, path: &str) -> Result<Box<dyn super::File>, ErrorCode> {
         let inner = self.inner.open_file(path).map_err(to_error_code)?;
-        Ok(Box::new(File { inner }))
+        Ok(Box::new(File {
+            inner,
+            flush_writes: self.flush_writes.clone(),
+        }))
     }
 
     fn create_file(&'static mut self, path: &str) -> Result<(), ErrorCode> {
@@ -130,6 +160,10 @@ impl super::filesystem::FileSystem for FileSystemSrFS {
     fn rename(&'static mut self, old: &str, new: &str) -> Result<(), ErrorCode> {
         self.inner.rename(old, new).map_err(to_error_code)
     }
+
+    fn barrier(&'static mut self) -> Result<(), ErrorCode> {
+        self.virtio_drive.flush().map_err(|_| moto_rt::E_IO_ERROR)
+    }
 }
 
 pub(super) fn init(
@@ -139,20 +173,37 @@ pub(super) fn init(
 ) -> Box<dyn FileSystem> {
     assert_eq!(0, blocks & 3); // here blocks are in 512 bytes; we need in 4k.
 
+    // srfs blocks must be whole device blocks.
+    let block_size = virtio_drive.block_size() as u64;
+    assert!(
+        block_size <= BLOCK_4K as u64 && ((lba << BLOCK_512.ilog2()) & (block_size - 1)) == 0,
+        "srfs: unsupported device block size {} for partition at LBA {}",
+        block_size,
+        lba
+    );
+
+    let flush_writes = Arc::new(AtomicBool::new(true));
     let adapter = Box::new(DeviceAdapter {
-        virtio_drive,
+        virtio_drive: virtio_drive.clone(),
         blocks4k: blocks >> 2,
         lba_offset: lba << BLOCK_512.ilog2(),
+        flush_writes: flush_writes.clone(),
     });
 
     let inner = srfs::FileSystem::open_device(adapter).unwrap();
-    Box::new(FileSystemSrFS { inner })
+    Box::new(FileSystemSrFS {
+        inner,
+        virtio_drive,
+        flush_writes,
+    })
 }
 
 struct DeviceAdapter {
     virtio_drive: Arc<dyn moto_virtio::BlockDevice>,
     blocks4k: u64,
     lba_offset: u64,
+    // If false, block writes don't flush the device cache: see FileSystem::barrier().
+    flush_writes: Arc<AtomicBool>,
 }
 
 const VIRTIO_BLOCKS_IN_SRFS_BLOCKS: usize = BLOCK_4K / BLOCK_512; // 8
@@ -179,14 +230,46 @@ impl srfs::SyncBlockDevice for DeviceAdapter {
         debug_assert_eq!(0, (buf.as_ptr() as usize) & (BLOCK_4K - 1));
         debug_assert_eq!(BLOCK_4K, buf.len());
 
+        let address = self.lba_offset + (block_no << BLOCK_4K.ilog2());
+        if self.flush_writes.load(Ordering::Relaxed) {
+            self.virtio_drive
+                .write(buf, address, VIRTIO_BLOCKS_IN_SRFS_BLOCKS)
+        } else {
+            self.virtio_drive
+                .write_no_flush(buf, address, VIRTIO_BLOCKS_IN_SRFS_BLOCKS)
+        }
+        .map_err(|_| srfs::FsError::IoError)
+    }
+
+    // One device request (split by the driver into requests of
+    // optimal_io_size(), all in flight at once), not one per block.
+    fn read_blocks(&mut self, block_no: u64, buf: &mut [u8]) -> Result<(), srfs::FsError> {
+        debug_assert_eq!(0, (buf.as_ptr() as usize) & (BLOCK_4K - 1));
+        debug_assert_eq!(0, buf.len() & (BLOCK_4K - 1));
+
+        let sectors = buf.len() / BLOCK_512;
         self.virtio_drive
-            .write(
+            .read(
                 buf,
                 self.lba_offset + (block_no << BLOCK_4K.ilog2()),
-                VIRTIO_BLOCKS_IN_SRFS_BLOCKS,
+                sectors,
             )
             .map_err(|_| srfs::FsError::IoError)
     }
+
+    fn write_blocks(&mut self, block_no: u64, buf: &[u8]) -> Result<(), srfs::FsError> {
+        debug_assert_eq!(0, (buf.as_ptr() as usize) & (BLOCK_4K - 1));
+        debug_assert_eq!(0, buf.len() & (BLOCK_4K - 1));
+
+        let address = self.lba_offset + (block_no << BLOCK_4K.ilog2());
+        let sectors = buf.len() / BLOCK_512;
+        if self.flush_writes.load(Ordering::Relaxed) {
+            self.virtio_drive.write(buf, address, sectors)
+        } else {
+            self.virtio_drive.write_no_flush(buf, address, sectors)
+        }
+        .map_err(|_| srfs::FsError::IoError)
+    }
 }
 
 fn to_error_code(error: std::io::Error) -> ErrorCode {
@@ -194,6 +277,7 @@ fn to_error_code(error: std::io::Error) -> ErrorCode {
         std::io::ErrorKind::NotFound => moto_rt::E_NOT_FOUND,
         std::io::ErrorKind::PermissionDenied => moto_rt::E_NOT_ALLOWED,
         std::io::ErrorKind::AlreadyExists => moto_rt::E_ALREADY_IN_USE,
+        std::io::ErrorKind::BrokenPipe => moto_rt::E_IO_ERROR, // srfs::FsError::IoError.
         std::io::ErrorKind::WouldBlock => todo!(),
         std::io::ErrorKind::InvalidInput => todo!(),
         std::io::ErrorKind::InvalidData => moto_rt::E_UNKNOWN,
@@ -204,6 +288,7 @@ fn to_error_code(error: std::io::Error) -> ErrorCode {
         std::io::ErrorKind::UnexpectedEof => todo!(),
         std::io::ErrorKind::OutOfMemory => moto_rt::E_OUT_OF_MEMORY,
         std::io::ErrorKind::FileTooLarge => moto_rt::E_FILE_TOO_LARGE,
+        std::io::ErrorKind::FilesystemQuotaExceeded => moto_rt::E_QUOTA_EXCEEDED, // FsFull.
         // std::io::ErrorKind::Other => todo!(),
         _ => moto_rt::E_UNKNOWN,
     }
@@ -1,11 +1,19 @@
+mod case_fold;
+mod defrag;
 mod dispatcher;
 mod driver;
 mod filesystem;
 mod fs_flatfs;
 mod fs_srfs;
 mod mbr;
+mod open_files;
+mod quota;
+mod snapshot;
+mod versions;
+mod writeback;
 
 pub use filesystem::*;
+pub use open_files::open_files;
 const DRIVER_URL: &str = "moturus-fs-driver";
 
 pub static STARTED: core::sync::atomic::AtomicU32 = core::sync::atomic::AtomicU32::new(0);
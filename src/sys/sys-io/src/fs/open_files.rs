@@ -0,0 +1,41 @@
+// Open files of all processes, as seen by the FS driver: a copy of the
+// per-connection fd -> path mappings that other sys-io threads (e.g. the
+// stats service) can read without going through the driver.
+//
+// Paths are the ones files were opened with, even if renamed since.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+// (pid, connection handle, fd) -> path.
+static OPEN_FILES: Mutex<BTreeMap<(u64, u64, u64), String>> = Mutex::new(BTreeMap::new());
+
+pub(super) fn insert(pid: u64, conn: u64, fd: u64, path: &str) {
+    OPEN_FILES
+        .lock()
+        .unwrap()
+        .insert((pid, conn, fd), path.to_owned());
+}
+
+pub(super) fn remove(pid: u64, conn: u64, fd: u64) {
+    OPEN_FILES.lock().unwrap().remove(&(pid, conn, fd));
+}
+
+pub(super) fn remove_connection(pid: u64, conn: u64) {
+    OPEN_FILES
+        .lock()
+        .unwrap()
+        .retain(|(p, c, _), _| *p != pid || *c != conn);
+}
+
+/// (fd, path) of files opened by process @pid, ordered by fd.
+pub fn open_files(pid: u64) -> Vec<(u64, String)> {
+    let mut files: Vec<(u64, String)> = OPEN_FILES
+        .lock()
+        .unwrap()
+        .range((pid, 0, 0)..=(pid, u64::MAX, u64::MAX))
+        .map(|((_, _, fd), path)| (*fd, path.clone()))
+        .collect();
+    files.sort_by_key(|(fd, _)| *fd);
+    files
+}
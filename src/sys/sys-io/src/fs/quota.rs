@@ -0,0 +1,312 @@
+// Directory quotas: a directory can limit the total size of the files
+// (bytes) and the number of files and directories (inodes) below it.
+//
+// Usage is computed by walking the subtree once, when the quota is set,
+// and is then updated incrementally by the driver on every write, create,
+// unlink, and rename, so enforcement costs one hash lookup per ancestor
+// directory. Writes to a file are charged to the quotas above the path it
+// was opened with, even if it has been renamed since.
+//
+// The limits (not the usage) are saved in QUOTAS_DIR whenever they change,
+// and loaded when sys-io starts, walking each subtree again. QUOTAS_DIR is
+// reserved (see snapshot::is_reserved()), so clients can't change it.
+
+use std::collections::HashMap;
+
+use moto_rt::fs::DirQuota;
+use moto_sys::ErrorCode;
+
+use super::filesystem::fs;
+
+pub(super) const QUOTAS_DIR: &str = "/sys/quotas";
+// Saved to QUOTAS_NEW first, then renamed, so that there is always a
+// complete copy on the volume.
+const QUOTAS_FILE: &str = "/sys/quotas/quotas";
+const QUOTAS_NEW: &str = "/sys/quotas/quotas.new";
+
+fn read_file(path: &str) -> Result<String, ErrorCode> {
+    let mut file = fs().open_file(path)?;
+    let mut bytes = vec![0_u8; file.size()? as usize];
+    let mut done = 0;
+    while done < bytes.len() {
+        match file.read_offset(done as u64, &mut bytes[done..])? {
+            0 => break,
+            sz => done += sz,
+        }
+    }
+    bytes.truncate(done);
+    String::from_utf8(bytes).map_err(|_| moto_rt::E_BAD_DATA)
+}
+
+fn write_file(path: &str, bytes: &[u8]) -> Result<(), ErrorCode> {
+    if fs().stat(path).is_ok() {
+        fs().unlink(path)?;
+    }
+    fs().create_file(path)?;
+    let mut file = fs().open_file(path)?;
+    let mut done = 0;
+    while done < bytes.len() {
+        done += file.write_offset(done as u64, &bytes[done..])?;
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+pub(super) struct DirQuotas {
+    quotas: HashMap<String, DirQuota>,
+}
+
+pub(super) fn parent(path: &str) -> Option<&str> {
+    if path == "/" {
+        return None;
+    }
+
+    match path.rfind('/') {
+        Some(0) => Some("/"),
+        Some(idx) => Some(&path[..idx]),
+        None => None,
+    }
+}
+
+// Returns true if @path is @dir or is below it.
+pub(super) fn is_within(path: &str, dir: &str) -> bool {
+    if dir == "/" {
+        return true;
+    }
+    path == dir || (path.starts_with(dir) && path.as_bytes()[dir.len()] == b'/')
+}
+
+/// The (bytes, inodes) used below @path, not counting @path itself.
+pub(super) fn subtree_usage(path: &str) -> Result<(u64, u64), ErrorCode> {
+    let mut bytes = 0;
+    let mut inodes = 0;
+
+    let mut dirs = vec![path.to_owned()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs().iter(dir.as_str())? {
+            inodes += 1;
+            if entry.is_directory() {
+                dirs.push(entry.path().to_owned());
+            } else {
+                bytes += entry.size()?;
+            }
+        }
+    }
+
+    Ok((bytes, inodes))
+}
+
+/// The (bytes, inodes) charged for @path itself and, if it is a directory,
+/// everything below it.
+pub(super) fn entry_usage(path: &str) -> Result<(u64, u64), ErrorCode> {
+    let attr = fs().stat(path)?;
+    if attr.file_type == moto_rt::fs::FILETYPE_DIRECTORY {
+        let (bytes, inodes) = subtree_usage(path)?;
+        Ok((bytes, inodes + 1))
+    } else {
+        Ok((attr.size, 1))
+    }
+}
+
+impl DirQuotas {
+    /// The quotas saved by the previous instance of sys-io.
+    pub fn load() -> Self {
+        let mut quotas = Self::default();
+        let Ok(saved) = read_file(QUOTAS_FILE).or_else(|_| read_file(QUOTAS_NEW)) else {
+            return quotas;
+        };
+
+        // Each line is "<max_bytes> <max_inodes> <dir>".
+        for line in saved.lines() {
+            let mut words = line.splitn(3, ' ');
+            let (Some(max_bytes), Some(max_inodes), Some(dir)) =
+                (words.next(), words.next(), words.next())
+            else {
+                log::warn!("fs: bad quota entry '{}'", line);
+                continue;
+            };
+            let (Ok(max_bytes), Ok(max_inodes)) = (max_bytes.parse(), max_inodes.parse()) else {
+                log::warn!("fs: bad quota entry '{}'", line);
+                continue;
+            };
+
+            if let Err(err) = quotas.apply(dir, max_bytes, max_inodes) {
+                log::warn!("fs: dropping the quota on '{}': {:?}", dir, err);
+            }
+        }
+        quotas
+    }
+
+    // Quotas still work if they can't be saved (e.g. on a read-only volume),
+    // until sys-io restarts.
+    fn save(&self) {
+        let mut saved = String::new();
+        for (dir, quota) in &self.quotas {
+            saved.push_str(&alloc::format!(
+                "{} {} {}\n",
+                quota.max_bytes,
+                quota.max_inodes,
+                dir
+            ));
+        }
+
+        let result = (|| {
+            if fs().stat(QUOTAS_DIR).is_err() {
+                fs().mkdir(QUOTAS_DIR)?;
+            }
+            write_file(QUOTAS_NEW, saved.as_bytes())?;
+            if fs().stat(QUOTAS_FILE).is_ok() {
+                fs().unlink(QUOTAS_FILE)?;
+            }
+            fs().rename(QUOTAS_NEW, QUOTAS_FILE)
+        })();
+        if let Err(err) = result {
+            log::warn!("fs: failed to save quotas: {:?}", err);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.quotas.is_empty()
+    }
+
+    /// Fails with E_QUOTA_EXCEEDED if adding @bytes and @inodes at @path
+    /// would exceed the quota of one of its ancestors, skipping ancestors
+    /// of @skip_ancestors_of (used for renames within a quota).
+    pub fn check(
+        &self,
+        path: &str,
+        bytes: u64,
+        inodes: u64,
+        skip_ancestors_of: Option<&str>,
+    ) -> Result<(), ErrorCode> {
+        if self.quotas.is_empty() {
+            return Ok(());
+        }
+
+        let mut dir = parent(path);
+        while let Some(curr) = dir {
+            dir = parent(curr);
+
+            let Some(quota) = self.quotas.get(curr) else {
+                continue;
+            };
+            if let Some(other) = skip_ancestors_of {
+                if is_within(other, curr) {
+                    continue;
+                }
+            }
+
+            if quota.used_bytes.saturating_add(bytes) > quota.max_bytes
+                || quota.used_inodes.saturating_add(inodes) > quota.max_inodes
+            {
+                return Err(moto_rt::E_QUOTA_EXCEEDED);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds @bytes and @inodes to the usage of all quotas above @path.
+    pub fn charge(&mut self, path: &str, bytes: u64, inodes: u64) {
+        if self.quotas.is_empty() {
+            return;
+        }
+
+        let mut dir = parent(path);
+        while let Some(curr) = dir {
+            if let Some(quota) = self.quotas.get_mut(curr) {
+                quota.used_bytes += bytes;
+                quota.used_inodes += inodes;
+            }
+            dir = parent(curr);
+        }
+    }
+
+    /// Removes @bytes and @inodes from the usage of all quotas above @path.
+    pub fn release(&mut self, path: &str, bytes: u64, inodes: u64) {
+        if self.quotas.is_empty() {
+            return;
+        }
+
+        let mut dir = parent(path);
+        while let Some(curr) = dir {
+            if let Some(quota) = self.quotas.get_mut(curr) {
+                quota.used_bytes = quota.used_bytes.saturating_sub(bytes);
+                quota.used_inodes = quota.used_inodes.saturating_sub(inodes);
+            }
+            dir = parent(curr);
+        }
+    }
+
+    /// Drops quotas on @path and on directories below it (after @path is deleted).
+    pub fn remove_subtree(&mut self, path: &str) {
+        let count = self.quotas.len();
+        self.quotas.retain(|dir, _| !is_within(dir, path));
+        if self.quotas.len() != count {
+            self.save();
+        }
+    }
+
+    /// Moves quotas on @old and on directories below it to @new (after a rename).
+    pub fn rename_subtree(&mut self, old: &str, new: &str) {
+        let moved: Vec<String> = self
+            .quotas
+            .keys()
+            .filter(|dir| is_within(dir, old))
+            .cloned()
+            .collect();
+
+        if moved.is_empty() {
+            return;
+        }
+        for dir in moved {
+            let quota = self.quotas.remove(&dir).unwrap();
+            let renamed = alloc::format!("{}{}", new, &dir[old.len()..]);
+            self.quotas.insert(renamed, quota);
+        }
+        self.save();
+    }
+
+    /// Sets the quota on directory @dir; moto_rt::fs::QUOTA_UNLIMITED for both
+    /// limits removes it.
+    pub fn set(&mut self, dir: &str, max_bytes: u64, max_inodes: u64) -> Result<(), ErrorCode> {
+        self.apply(dir, max_bytes, max_inodes)?;
+        self.save();
+        Ok(())
+    }
+
+    fn apply(&mut self, dir: &str, max_bytes: u64, max_inodes: u64) -> Result<(), ErrorCode> {
+        use moto_rt::fs::QUOTA_UNLIMITED;
+
+        if fs().stat(dir)?.file_type != moto_rt::fs::FILETYPE_DIRECTORY {
+            return Err(moto_rt::E_NOT_A_DIRECTORY);
+        }
+
+        if max_bytes == QUOTA_UNLIMITED && max_inodes == QUOTA_UNLIMITED {
+            self.quotas.remove(dir);
+            return Ok(());
+        }
+
+        if let Some(quota) = self.quotas.get_mut(dir) {
+            quota.max_bytes = max_bytes;
+            quota.max_inodes = max_inodes;
+            return Ok(());
+        }
+
+        let (used_bytes, used_inodes) = subtree_usage(dir)?;
+        self.quotas.insert(
+            dir.to_owned(),
+            DirQuota {
+                max_bytes,
+                max_inodes,
+                used_bytes,
+                used_inodes,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn get(&self, dir: &str) -> Result<DirQuota, ErrorCode> {
+        self.quotas.get(dir).copied().ok_or(moto_rt::E_NOT_FOUND)
+    }
+}
@@ -0,0 +1,579 @@
+// Point-in-time snapshots of a directory tree (see moto_rt::fs::snapshot()).
+//
+// srfs has no shared (reference-counted) blocks, so a snapshot can't be
+// a block-level copy-on-write view; instead, the driver copies at file
+// granularity, before the first change. Taking a snapshot does no I/O;
+// the first write to, truncation, removal, or rename of an entry below
+// the snapshotted directory first preserves its state at snapshot time
+// (the contents of a file, the listing of a directory) in the snapshot's
+// store, on the same volume. Entries not preserved are read live.
+//
+// Space amplification: while a snapshot is held, every file changed or
+// removed below the snapshotted directory takes its full size again in the
+// store (once per snapshot, however many times it is written), so a snapshot
+// of a busy tree can grow up to the size of the tree; space of removed files
+// is not freed until the snapshot is released. So taking a snapshot requires
+// CAP_SYS, and each copy is charged to the quotas above the entry it preserves
+// (see DirQuotas), until the snapshot is released. The first write to a large
+// file waits for it to be copied.
+//
+// Entries are keyed by their canonical path (see FileSystem::canonical_path()),
+// as are the paths the driver passes in, so that a case-insensitive alias of
+// an entry is preserved like the entry itself.
+//
+// If an entry can't be preserved (e.g. the volume is full, or a copy would
+// exceed a quota), the snapshot is marked invalid, and live writes proceed.
+// Unlike quotas, snapshots live in memory only: they are lost, and their
+// stores deleted, when sys-io restarts.
+
+use std::collections::{BTreeMap, HashMap};
+
+use moto_rt::fs::{FileAttr, FILETYPE_DIRECTORY, FILETYPE_FILE, SNAPSHOT_DIR};
+use moto_sys::ErrorCode;
+
+use super::filesystem::{fs, DirectoryEntry, DirectoryIter, File};
+use super::quota::{is_within, parent, DirQuotas};
+
+// Where preserved entries are copied to: STORE_DIR/<snapshot id>/<n>.
+const STORE_DIR: &str = "/sys/snapshots";
+
+const MAX_SNAPSHOTS: usize = 16;
+
+const COPY_BUF_SIZE: usize = 64 * 1024;
+
+enum Preserved {
+    /// The contents of a file at snapshot time, copied to the store.
+    File(String),
+    /// The listing of a directory at snapshot time: (filename, is_directory).
+    Dir(Vec<(String, bool)>),
+}
+
+enum Resolved<'a> {
+    Live,
+    File(&'a str),
+    Dir(&'a [(String, bool)]),
+}
+
+struct Snapshot {
+    root: String,
+    store: String,
+    next_copy: u64,
+    broken: bool,
+    // Keyed by the (live) path the entry had at snapshot time.
+    preserved: HashMap<String, Preserved>,
+    // (path, bytes) of each copy, charged to the quotas above path.
+    charged: Vec<(String, u64)>,
+}
+
+pub(super) struct Snapshots {
+    snapshots: BTreeMap<u64, Snapshot>,
+    next_id: u64,
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir == "/" {
+        alloc::format!("/{}", name)
+    } else {
+        alloc::format!("{}/{}", dir, name)
+    }
+}
+
+fn dir_attr() -> FileAttr {
+    FileAttr {
+        version: 0,
+        perm: 0,
+        file_type: FILETYPE_DIRECTORY,
+        _reserved: [0; 7],
+        size: 0,
+        created: 0,
+        accessed: 0,
+        modified: 0,
+    }
+}
+
+/// Returns true if @path is in a snapshot view (below SNAPSHOT_DIR).
+pub(super) fn is_view(path: &str) -> bool {
+    is_within(path, SNAPSHOT_DIR)
+}
+
+/// Returns true if @path can't be changed by clients: snapshot views and
+/// stores, and saved quotas.
+pub(super) fn is_reserved(path: &str) -> bool {
+    is_view(path) || is_within(path, STORE_DIR) || is_within(path, super::quota::QUOTAS_DIR)
+}
+
+// The live listing of directory @path, without the store.
+fn list_dir(path: &str) -> Result<Vec<(String, bool)>, ErrorCode> {
+    let mut listing = Vec::new();
+    for entry in fs().iter(path)? {
+        if is_within(entry.path(), STORE_DIR) {
+            continue;
+        }
+        listing.push((entry.filename().to_owned(), entry.is_directory()));
+    }
+    Ok(listing)
+}
+
+impl Snapshot {
+    // What @path (below root) was at snapshot time.
+    fn resolve(&self, path: &str) -> Result<Resolved<'_>, ErrorCode> {
+        if is_within(path, STORE_DIR) {
+            return Err(moto_rt::E_NOT_FOUND);
+        }
+
+        let mut components = path[self.root.len()..]
+            .split('/')
+            .filter(|name| !name.is_empty());
+        let mut curr = self.root.clone();
+        loop {
+            let next = components.next();
+            match (self.preserved.get(&curr), next) {
+                (Some(Preserved::File(copy)), None) => return Ok(Resolved::File(copy)),
+                (Some(Preserved::File(_)), Some(_)) => return Err(moto_rt::E_NOT_FOUND),
+                (Some(Preserved::Dir(listing)), None) => return Ok(Resolved::Dir(listing)),
+                (Some(Preserved::Dir(listing)), Some(name)) => {
+                    if !listing.iter().any(|(filename, _)| filename == name) {
+                        return Err(moto_rt::E_NOT_FOUND);
+                    }
+                }
+                (None, None) => return Ok(Resolved::Live),
+                (None, Some(_)) => {}
+            }
+            curr = join(&curr, next.unwrap());
+        }
+    }
+
+    // Preserves @path, unless it has been preserved already or did not
+    // exist at snapshot time, and, if @recursive, everything below it.
+    fn preserve(
+        &mut self,
+        path: &str,
+        recursive: bool,
+        quotas: &mut DirQuotas,
+    ) -> Result<(), ErrorCode> {
+        let mut todo = vec![path.to_owned()];
+        while let Some(curr) = todo.pop() {
+            let children: Vec<String> = match self.resolve(&curr) {
+                Ok(Resolved::Live) => match fs().stat(&curr) {
+                    Ok(attr) => {
+                        if attr.file_type != FILETYPE_DIRECTORY {
+                            quotas.check(&curr, attr.size, 1, None)?;
+                            let copy = self.copy_file(&curr)?;
+                            quotas.charge(&curr, attr.size, 1);
+                            self.charged.push((curr.clone(), attr.size));
+                            self.preserved.insert(curr, Preserved::File(copy));
+                            continue;
+                        }
+
+                        let listing = list_dir(&curr)?;
+                        let children = listing.iter().map(|(name, _)| join(&curr, name)).collect();
+                        self.preserved.insert(curr, Preserved::Dir(listing));
+                        children
+                    }
+                    Err(_) => continue, // Created and removed after the snapshot.
+                },
+                Ok(Resolved::Dir(listing)) => {
+                    listing.iter().map(|(name, _)| join(&curr, name)).collect()
+                }
+                _ => continue,
+            };
+
+            if recursive {
+                todo.extend(children);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Copies file @path into the store; returns the path of the copy.
+    fn copy_file(&mut self, path: &str) -> Result<String, ErrorCode> {
+        let copy = join(&self.store, self.next_copy.to_string().as_str());
+        self.next_copy += 1;
+
+        fs().create_file(&copy)?;
+        let mut src = fs().open_file(path)?;
+        let mut dst = fs().open_file(&copy)?;
+
+        // Copies don't need to be durable: snapshots don't survive restarts.
+        let mut buf = vec![0_u8; COPY_BUF_SIZE];
+        let mut offset = 0;
+        loop {
+            let read = src.read_offset(offset, &mut buf)?;
+            if read == 0 {
+                break;
+            }
+
+            let mut written = 0;
+            while written < read {
+                written +=
+                    dst.write_offset_no_flush(offset + (written as u64), &buf[written..read])?;
+            }
+            offset += read as u64;
+        }
+
+        Ok(copy)
+    }
+}
+
+impl Snapshots {
+    pub fn new() -> Self {
+        // Stores left by the previous instance of sys-io.
+        if fs().stat(STORE_DIR).is_ok() {
+            let _ = fs().delete_dir_all(STORE_DIR);
+        }
+
+        Self {
+            snapshots: BTreeMap::new(),
+            next_id: 1,
+        }
+    }
+
+    /// Takes a snapshot of directory @dir; returns its ID.
+    pub fn create(&mut self, dir: &str) -> Result<u64, ErrorCode> {
+        if is_reserved(dir) {
+            return Err(moto_rt::E_NOT_ALLOWED);
+        }
+        if fs().stat(dir)?.file_type != FILETYPE_DIRECTORY {
+            return Err(moto_rt::E_NOT_A_DIRECTORY);
+        }
+        if self.snapshots.len() >= MAX_SNAPSHOTS {
+            return Err(moto_rt::E_QUOTA_EXCEEDED);
+        }
+
+        if fs().stat(STORE_DIR).is_err() {
+            fs().mkdir(STORE_DIR)?;
+        }
+        let id = self.next_id;
+        let store = join(STORE_DIR, id.to_string().as_str());
+        fs().mkdir(&store)?;
+        self.next_id += 1;
+
+        self.snapshots.insert(
+            id,
+            Snapshot {
+                root: dir.to_owned(),
+                store,
+                next_copy: 0,
+                broken: false,
+                preserved: HashMap::new(),
+                charged: Vec::new(),
+            },
+        );
+        log::debug!("fs: snapshot {} of '{}'", id, dir);
+        Ok(id)
+    }
+
+    pub fn release(&mut self, id: u64, quotas: &mut DirQuotas) -> Result<(), ErrorCode> {
+        let snapshot = self.snapshots.remove(&id).ok_or(moto_rt::E_NOT_FOUND)?;
+        for (path, bytes) in &snapshot.charged {
+            quotas.release(path, *bytes, 1);
+        }
+        if let Err(err) = fs().delete_dir_all(&snapshot.store) {
+            log::warn!(
+                "fs: failed to delete snapshot store '{}': {:?}",
+                snapshot.store,
+                err
+            );
+        }
+        Ok(())
+    }
+
+    /// Fails with E_NOT_ALLOWED if @path is in a snapshot view or store.
+    pub fn check_writable(&self, path: &str) -> Result<(), ErrorCode> {
+        if is_reserved(path) {
+            Err(moto_rt::E_NOT_ALLOWED)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn for_each_tracking(
+        &mut self,
+        path: &str,
+        quotas: &mut DirQuotas,
+        mut f: impl FnMut(&mut Snapshot, &mut DirQuotas) -> Result<(), ErrorCode>,
+    ) {
+        if self.snapshots.is_empty() || is_reserved(path) {
+            return;
+        }
+
+        for (id, snapshot) in self.snapshots.iter_mut() {
+            if snapshot.broken {
+                continue;
+            }
+            if let Err(err) = f(snapshot, quotas) {
+                log::error!(
+                    "fs: snapshot {} is now invalid: failed to preserve '{}': {:?}",
+                    id,
+                    path,
+                    err
+                );
+                snapshot.broken = true;
+            }
+        }
+    }
+
+    /// Called before the contents of file @path change.
+    pub fn before_modify(&mut self, path: &str, quotas: &mut DirQuotas) {
+        self.for_each_tracking(path, quotas, |snapshot, quotas| {
+            if is_within(path, &snapshot.root) {
+                snapshot.preserve(path, false, quotas)
+            } else {
+                Ok(())
+            }
+        });
+    }
+
+    /// Called before @path is created.
+    pub fn before_create(&mut self, path: &str, quotas: &mut DirQuotas) {
+        self.for_each_tracking(path, quotas, |snapshot, quotas| match parent(path) {
+            Some(dir) if is_within(dir, &snapshot.root) => snapshot.preserve(dir, false, quotas),
+            _ => Ok(()),
+        });
+    }
+
+    /// Called before @path, and everything below it, is removed or renamed
+    /// (also before a rename onto @path).
+    pub fn before_remove(&mut self, path: &str, quotas: &mut DirQuotas) {
+        self.for_each_tracking(path, quotas, |snapshot, quotas| {
+            if is_within(path, &snapshot.root) {
+                if let Some(dir) = parent(path) {
+                    if is_within(dir, &snapshot.root) {
+                        snapshot.preserve(dir, false, quotas)?;
+                    }
+                }
+                snapshot.preserve(path, true, quotas)
+            } else if is_within(&snapshot.root, path) {
+                let root = snapshot.root.clone();
+                snapshot.preserve(&root, true, quotas)
+            } else {
+                Ok(())
+            }
+        });
+    }
+
+    /// If @path is in a snapshot view, returns it with the names the view has,
+    /// when lookups are case-insensitive (see case_fold): views are not on disk,
+    /// so FileSystem::canonical_path() can't resolve them. Names not found
+    /// are kept as given. Returns None if @path is not in a view.
+    pub fn canonical_view_path(&self, path: &str) -> Option<String> {
+        if !super::case_fold::enabled() {
+            return is_view(path).then(|| path.to_owned());
+        }
+
+        // SNAPSHOT_DIR is ASCII, so a match ends at a char boundary.
+        let prefix_len = SNAPSHOT_DIR.len();
+        let bytes = path.as_bytes();
+        if bytes.len() < prefix_len
+            || !bytes[0..prefix_len].eq_ignore_ascii_case(SNAPSHOT_DIR.as_bytes())
+            || (bytes.len() > prefix_len && bytes[prefix_len] != b'/')
+        {
+            return None;
+        }
+
+        let mut components = path[prefix_len..].split('/').filter(|c| !c.is_empty());
+        let Some(id) = components.next() else {
+            return Some(SNAPSHOT_DIR.to_owned());
+        };
+        let mut view = join(SNAPSHOT_DIR, id);
+        // (the snapshot, the live path @view is at).
+        let mut at = self.parse_view(&view).ok();
+
+        for name in components {
+            let listing = match &at {
+                Some((snapshot, live)) => match snapshot.resolve(live) {
+                    Ok(Resolved::Live) => list_dir(live).unwrap_or_default(),
+                    Ok(Resolved::Dir(listing)) => listing.to_vec(),
+                    _ => Vec::new(),
+                },
+                None => Vec::new(),
+            };
+
+            let folded = super::case_fold::fold(name);
+            let found = listing
+                .iter()
+                .find(|(entry, _)| entry == name)
+                .or_else(|| {
+                    listing
+                        .iter()
+                        .find(|(entry, _)| super::case_fold::fold(entry) == folded)
+                })
+                .map(|(entry, _)| entry.as_str())
+                .unwrap_or(name);
+            view = join(&view, found);
+            at = at.map(|(snapshot, live)| (snapshot, join(&live, found)));
+        }
+
+        Some(view)
+    }
+
+    // SNAPSHOT_DIR/<id>/<path> => (the snapshot, root/path).
+    fn parse_view(&self, path: &str) -> Result<(&Snapshot, String), ErrorCode> {
+        let rest = path[SNAPSHOT_DIR.len()..].trim_start_matches('/');
+        let (id, rest) = rest.split_once('/').unwrap_or((rest, ""));
+        let id: u64 = id.parse().map_err(|_| moto_rt::E_NOT_FOUND)?;
+
+        let snapshot = self.snapshots.get(&id).ok_or(moto_rt::E_NOT_FOUND)?;
+        if snapshot.broken {
+            return Err(moto_rt::E_IO_ERROR);
+        }
+
+        if rest.is_empty() {
+            Ok((snapshot, snapshot.root.clone()))
+        } else {
+            Ok((snapshot, join(&snapshot.root, rest)))
+        }
+    }
+
+    /// Stats @path in a snapshot view (below SNAPSHOT_DIR).
+    pub fn stat(&self, path: &str) -> Result<FileAttr, ErrorCode> {
+        let (snapshot, live) = self.parse_view(path)?;
+        match snapshot.resolve(&live)? {
+            Resolved::Live => fs().stat(&live),
+            Resolved::File(copy) => fs().stat(copy),
+            Resolved::Dir(_) => Ok(dir_attr()),
+        }
+    }
+
+    /// Opens file @path in a snapshot view, read-only.
+    pub fn open_file(&self, path: &str) -> Result<Box<dyn File>, ErrorCode> {
+        if self.stat(path)?.file_type != FILETYPE_FILE {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        Ok(Box::new(SnapshotFile {
+            path: path.to_owned(),
+            live: None,
+            copy: None,
+        }))
+    }
+
+    /// Lists directory @path in a snapshot view.
+    pub fn iter(&self, path: &str) -> Result<Box<dyn DirectoryIter>, ErrorCode> {
+        let (snapshot, live) = self.parse_view(path)?;
+        let listing = match snapshot.resolve(&live)? {
+            Resolved::Live => {
+                if fs().stat(&live)?.file_type != FILETYPE_DIRECTORY {
+                    return Err(moto_rt::E_NOT_A_DIRECTORY);
+                }
+                list_dir(&live)?
+            }
+            Resolved::Dir(listing) => listing.to_vec(),
+            Resolved::File(_) => return Err(moto_rt::E_NOT_A_DIRECTORY),
+        };
+
+        // Sizes are taken now: a file changed later is preserved first.
+        let mut entries = Vec::with_capacity(listing.len());
+        for (filename, is_directory) in listing {
+            let entry_path = join(path, &filename);
+            let size = if is_directory {
+                0
+            } else {
+                self.stat(&entry_path)?.size
+            };
+            entries.push(SnapshotDirEntry {
+                filename,
+                path: entry_path,
+                is_directory,
+                size,
+            });
+        }
+
+        Ok(Box::new(SnapshotDirIter {
+            entries: entries.into_iter(),
+        }))
+    }
+}
+
+// A file in a snapshot view: reads the live file until it is preserved,
+// and the copy after that.
+struct SnapshotFile {
+    path: String, // In the view.
+    live: Option<Box<dyn File>>,
+    copy: Option<Box<dyn File>>,
+}
+
+impl SnapshotFile {
+    fn current(&mut self) -> Result<&mut Box<dyn File>, ErrorCode> {
+        let (snapshot, live) = super::driver::snapshots().parse_view(&self.path)?;
+        match snapshot.resolve(&live)? {
+            Resolved::Live => {
+                if self.live.is_none() {
+                    self.live = Some(fs().open_file(&live)?);
+                }
+                Ok(self.live.as_mut().unwrap())
+            }
+            Resolved::File(copy) => {
+                if self.copy.is_none() {
+                    self.copy = Some(fs().open_file(copy)?);
+                }
+                Ok(self.copy.as_mut().unwrap())
+            }
+            Resolved::Dir(_) => Err(moto_rt::E_NOT_FOUND),
+        }
+    }
+}
+
+impl File for SnapshotFile {
+    fn size(&mut self) -> Result<u64, ErrorCode> {
+        self.current()?.size()
+    }
+
+    fn write_offset(&mut self, _offset: u64, _buf: &[u8]) -> Result<usize, ErrorCode> {
+        Err(moto_rt::E_NOT_ALLOWED)
+    }
+
+    fn write_offset_no_flush(&mut self, _offset: u64, _buf: &[u8]) -> Result<usize, ErrorCode> {
+        Err(moto_rt::E_NOT_ALLOWED)
+    }
+
+    fn read_offset(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, ErrorCode> {
+        self.current()?.read_offset(offset, buf)
+    }
+}
+
+struct SnapshotDirEntry {
+    filename: String,
+    path: String,
+    is_directory: bool,
+    size: u64,
+}
+
+impl DirectoryEntry for SnapshotDirEntry {
+    fn is_directory(&self) -> bool {
+        self.is_directory
+    }
+
+    fn filename(&self) -> &str {
+        self.filename.as_str()
+    }
+
+    fn path(&self) -> &str {
+        self.path.as_str()
+    }
+
+    fn size(&self) -> Result<u64, ErrorCode> {
+        Ok(self.size)
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+struct SnapshotDirIter {
+    entries: std::vec::IntoIter<SnapshotDirEntry>,
+}
+
+impl Iterator for SnapshotDirIter {
+    type Item = Box<dyn DirectoryEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries
+            .next()
+            .map(|entry| Box::new(entry) as Box<dyn DirectoryEntry>)
+    }
+}
+
+impl DirectoryIter for SnapshotDirIter {}
@@ -0,0 +1,61 @@
+// File versions, for optimistic concurrency (see CMD_FILE_WRITE_IF_VERSION).
+//
+// Versions are keyed by inode (see File::inode()), so they follow a file
+// through renames, and whatever path (e.g. case) it was opened with. Every
+// write to a file sets its version to the next value of a counter shared by
+// all files, so a version is never reused. Versions live in memory only: on
+// start, the counter is set to the wall clock time in nanoseconds, which all
+// files not written to since then report, so versions seen before a restart
+// do not match any version after it.
+
+use std::collections::HashMap;
+
+use super::filesystem::fs;
+
+pub(super) struct FileVersions {
+    versions: HashMap<u64, u64>,
+    base_version: u64,
+    last_version: u64,
+}
+
+impl Default for FileVersions {
+    fn default() -> Self {
+        let base_version = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|since| since.as_nanos() as u64)
+            .unwrap_or(0)
+            .max(1);
+        Self {
+            versions: HashMap::new(),
+            base_version,
+            last_version: base_version,
+        }
+    }
+}
+
+impl FileVersions {
+    pub fn get(&self, inode: u64) -> u64 {
+        self.versions
+            .get(&inode)
+            .copied()
+            .unwrap_or(self.base_version)
+    }
+
+    /// Called after every write to file @inode; returns the new version.
+    pub fn bump(&mut self, inode: u64) -> u64 {
+        self.last_version += 1;
+        self.versions.insert(inode, self.last_version);
+        self.last_version
+    }
+
+    /// Called before file @path is deleted. Inodes are not reused, so this
+    /// only frees memory.
+    pub fn before_remove(&mut self, path: &str) {
+        if self.versions.is_empty() {
+            return;
+        }
+        if let Ok(inode) = fs().open_file(path).and_then(|mut file| file.inode()) {
+            self.versions.remove(&inode);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+// Writeback of data written with FileWriteRequest::F_NO_FLUSH (moto_rt::fs::O_WRITEBACK).
+//
+// sys-io does not cache file data: srfs writes blocks through to the device.
+// But writes that don't flush the device cache are not durable until the next
+// flush (a barrier), so they are "dirty" while they sit in the device's write
+// cache. The policy (see CMD_WRITEBACK) bounds how much dirty data there may be,
+// and for how long, before the driver issues a barrier itself.
+
+use moto_rt::fs::{WritebackPolicy, WritebackStats};
+use moto_rt::time::Instant;
+use moto_sys::ErrorCode;
+
+const PAGE_SIZE: u64 = 4096;
+
+// Longer is as good as never, and would overflow Instant.
+const MAX_DIRTY_AGE_MS: u64 = 24 * 3600 * 1000;
+
+// After a failed writeback.
+const RETRY_INTERVAL: core::time::Duration = core::time::Duration::from_secs(1);
+
+#[derive(Default)]
+pub(super) struct Writeback {
+    policy: WritebackPolicy,
+    dirty_bytes: u64,
+    dirty_pages: u64,
+    dirty_since: Option<Instant>, // The oldest dirty write.
+    retry_at: Option<Instant>,
+    stats_background_writebacks: u64,
+}
+
+impl Writeback {
+    pub fn set_policy(&mut self, policy: WritebackPolicy) -> Result<(), ErrorCode> {
+        if policy.max_dirty_age_ms > MAX_DIRTY_AGE_MS {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+        self.policy = policy;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> WritebackStats {
+        WritebackStats {
+            policy: self.policy,
+            dirty_bytes: self.dirty_bytes,
+            dirty_pages: self.dirty_pages,
+            background_writebacks: self.stats_background_writebacks,
+        }
+    }
+
+    /// Called after @len bytes are written at @offset without a flush.
+    pub fn on_dirty(&mut self, offset: u64, len: u64) {
+        if len == 0 {
+            return;
+        }
+
+        self.dirty_bytes += len;
+        self.dirty_pages += (offset + len).div_ceil(PAGE_SIZE) - offset / PAGE_SIZE;
+        if self.dirty_since.is_none() {
+            self.dirty_since = Some(Instant::now());
+        }
+    }
+
+    /// Called after the device cache is flushed, for whatever reason.
+    pub fn on_flushed(&mut self) {
+        self.dirty_bytes = 0;
+        self.dirty_pages = 0;
+        self.dirty_since = None;
+        self.retry_at = None;
+    }
+
+    /// When the policy requires a writeback, if there is dirty data.
+    pub fn deadline(&self) -> Option<Instant> {
+        let dirty_since = self.dirty_since?;
+        let deadline = if self.dirty_bytes >= self.policy.max_dirty_bytes {
+            dirty_since
+        } else {
+            dirty_since + core::time::Duration::from_millis(self.policy.max_dirty_age_ms)
+        };
+
+        match self.retry_at {
+            Some(retry_at) if retry_at > deadline => Some(retry_at),
+            _ => Some(deadline),
+        }
+    }
+
+    /// Flushes the device cache if the policy requires it.
+    pub fn maybe_write_back(&mut self) {
+        let Some(deadline) = self.deadline() else {
+            return;
+        };
+        if Instant::now() < deadline {
+            return;
+        }
+
+        match super::fs().barrier() {
+            Ok(()) => {
+                self.stats_background_writebacks += 1;
+                self.on_flushed();
+            }
+            Err(err) => {
+                // The data is still dirty.
+                log::error!("fs writeback failed: {:?}", err);
+                self.retry_at = Some(Instant::now() + RETRY_INTERVAL);
+            }
+        }
+    }
+}
@@ -3,12 +3,19 @@ use log::{Metadata, Record};
 
 struct MotoLogger;
 
+#[cfg(debug_assertions)]
+const DEFAULT_MAX_LEVEL: LevelFilter = LevelFilter::Debug;
+
+#[cfg(not(debug_assertions))]
+const DEFAULT_MAX_LEVEL: LevelFilter = LevelFilter::Info;
+
 impl log::Log for MotoLogger {
-    fn enabled(&self, _metadata: &Metadata) -> bool {
-        true
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
     }
 
     fn log(&self, record: &Record) {
+        // Checked before formatting, so filtered out records cost nothing.
         if self.enabled(record.metadata()) {
             crate::moto_log!(
                 "{} {}:{}: {}",
@@ -26,11 +33,11 @@ impl log::Log for MotoLogger {
 static LOGGER: MotoLogger = MotoLogger;
 
 pub fn init() -> Result<(), SetLoggerError> {
-    #[cfg(debug_assertions)]
-    let res = log::set_logger(&LOGGER).map(|()| log::set_max_level(LevelFilter::Debug));
-
-    #[cfg(not(debug_assertions))]
-    let res = log::set_logger(&LOGGER).map(|()| log::set_max_level(LevelFilter::Info));
+    log::set_logger(&LOGGER).map(|()| set_max_level(DEFAULT_MAX_LEVEL))
+}
 
-    res
+/// Changes which records are logged; can be called at any time, from any thread
+/// (see CMD_LOG_LEVEL in runtime/io_stats.rs).
+pub fn set_max_level(level: LevelFilter) {
+    log::set_max_level(level);
 }
@@ -40,6 +40,8 @@ pub extern "C" fn moturus_has_proc_data() -> u8 {
 pub extern "C" fn moturus_runtime_start() {
     let _ = logger::init();
     rt_vdso::load();
+    // The system is not usable without sys-io.
+    moto_sys::SysRay::set_panic_action(moto_sys::SysRay::PANIC_ACTION_REBOOT).unwrap();
     runtime::init();
     virtio::init();
     // We need to initialize FS before Rust runtime is initialized (Rust runtime != sys-io runtime).
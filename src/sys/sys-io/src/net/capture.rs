@@ -0,0 +1,224 @@
+// Packet capture (see api_net::capture_start_request()). While a device has
+// consumers, netdev.rs shows every frame to the device's PacketTap, which copies
+// it; NetSys then hands the copies to the consumers whose filters match, one
+// pcap record per server page. A consumer that does not return the pages fast
+// enough loses frames, which are counted.
+
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use moto_ipc::io_channel;
+use moto_sys_io::api_net::{self, CaptureFilter};
+use smoltcp::wire::*;
+
+use crate::runtime::PendingCompletion;
+
+// Frames are handed to consumers after each device poll; a poll that sees
+// more frames than this loses the rest.
+const MAX_TAPPED_FRAMES: usize = 256;
+
+pub(super) struct TappedFrame {
+    rx: bool,
+    timestamp: core::time::Duration, // Since the UNIX epoch.
+    orig_len: usize,
+    protocol: u8,              // Zero if not IP.
+    ports: Option<(u16, u16)>, // TCP or UDP (src, dst).
+    bytes: Vec<u8>,            // At most CAPTURE_MAX_SNAPLEN.
+}
+
+// The IP protocol and the TCP/UDP ports of the frame.
+// IPv4 fragments and IPv6 extension headers are not looked into.
+fn parse_frame(frame: &[u8]) -> (u8, Option<(u16, u16)>) {
+    let Ok(eth) = EthernetFrame::new_checked(frame) else {
+        return (0, None);
+    };
+    let (protocol, payload) = match eth.ethertype() {
+        EthernetProtocol::Ipv4 => {
+            let Ok(ip) = Ipv4Packet::new_checked(eth.payload()) else {
+                return (0, None);
+            };
+            if ip.more_frags() || ip.frag_offset() != 0 {
+                return (ip.next_header().into(), None);
+            }
+            (ip.next_header(), ip.payload())
+        }
+        EthernetProtocol::Ipv6 => {
+            let Ok(ip) = Ipv6Packet::new_checked(eth.payload()) else {
+                return (0, None);
+            };
+            (ip.next_header(), ip.payload())
+        }
+        _ => return (0, None),
+    };
+
+    let ports = match protocol {
+        IpProtocol::Tcp => TcpPacket::new_checked(payload)
+            .ok()
+            .map(|tcp| (tcp.src_port(), tcp.dst_port())),
+        IpProtocol::Udp => UdpPacket::new_checked(payload)
+            .ok()
+            .map(|udp| (udp.src_port(), udp.dst_port())),
+        _ => None,
+    };
+    (protocol.into(), ports)
+}
+
+#[derive(Default)]
+pub(super) struct PacketTap {
+    enabled: bool,
+    frames: VecDeque<TappedFrame>,
+    overflows: u64,
+}
+
+impl PacketTap {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.frames.clear();
+            self.overflows = 0;
+        }
+    }
+
+    pub fn on_rx(&mut self, frame: &[u8]) {
+        self.tap(true, frame);
+    }
+
+    pub fn on_tx(&mut self, frame: &[u8]) {
+        self.tap(false, frame);
+    }
+
+    fn tap(&mut self, rx: bool, frame: &[u8]) {
+        if !self.enabled || frame.is_empty() {
+            return;
+        }
+        if self.frames.len() >= MAX_TAPPED_FRAMES {
+            self.overflows += 1;
+            return;
+        }
+
+        let (protocol, ports) = parse_frame(frame);
+        let len = frame.len().min(api_net::CAPTURE_MAX_SNAPLEN as usize);
+        self.frames.push_back(TappedFrame {
+            rx,
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default(),
+            orig_len: frame.len(),
+            protocol,
+            ports,
+            bytes: frame[0..len].to_vec(),
+        });
+    }
+
+    // Returns the frames tapped so far, and how many were lost.
+    pub fn take(&mut self) -> (VecDeque<TappedFrame>, u64) {
+        (
+            core::mem::take(&mut self.frames),
+            core::mem::take(&mut self.overflows),
+        )
+    }
+}
+
+pub(super) struct Capture {
+    conn: Rc<io_channel::ServerConnection>,
+    filter: CaptureFilter,
+    subchannel_mask: u64,
+
+    stats_captured: u64,
+    stats_dropped: u64,
+}
+
+impl Capture {
+    pub fn new(
+        conn: Rc<io_channel::ServerConnection>,
+        filter: CaptureFilter,
+        subchannel_mask: u64,
+    ) -> Self {
+        Self {
+            conn,
+            filter,
+            subchannel_mask,
+            stats_captured: 0,
+            stats_dropped: 0,
+        }
+    }
+
+    pub fn captured(&self) -> u64 {
+        self.stats_captured
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.stats_dropped
+    }
+
+    pub fn on_device(&self, device_idx: usize) -> bool {
+        self.filter
+            .device_idx
+            .map_or(true, |idx| idx as usize == device_idx)
+    }
+
+    // Frames the device's tap lost may or may not have matched the filter;
+    // they are counted as dropped either way.
+    pub fn on_overflow(&mut self, frames: u64) {
+        self.stats_dropped += frames;
+    }
+
+    fn matches(&self, frame: &TappedFrame) -> bool {
+        let direction = if frame.rx {
+            api_net::CAPTURE_RX
+        } else {
+            api_net::CAPTURE_TX
+        };
+        if self.filter.directions & direction == 0 {
+            return false;
+        }
+        if self.filter.protocol != 0 && self.filter.protocol != frame.protocol {
+            return false;
+        }
+        if self.filter.port != 0 {
+            match frame.ports {
+                Some((src, dst)) => src == self.filter.port || dst == self.filter.port,
+                None => false,
+            }
+        } else {
+            true
+        }
+    }
+
+    // Returns the EVT_CAPTURE_PACKET message with the frame, if it matches the filter.
+    pub fn deliver(&mut self, frame: &TappedFrame) -> Option<PendingCompletion> {
+        if !self.matches(frame) {
+            return None;
+        }
+
+        let page = match self.conn.alloc_page(self.subchannel_mask) {
+            Ok(page) => page,
+            Err(_) => {
+                self.stats_dropped += 1;
+                return None;
+            }
+        };
+
+        let incl_len = frame.bytes.len().min(self.filter.snaplen as usize);
+        let bytes = page.bytes_mut();
+        bytes[0..4].copy_from_slice(&(frame.timestamp.as_secs() as u32).to_le_bytes());
+        bytes[4..8].copy_from_slice(&frame.timestamp.subsec_micros().to_le_bytes());
+        bytes[8..12].copy_from_slice(&(incl_len as u32).to_le_bytes());
+        bytes[12..16].copy_from_slice(&(frame.orig_len as u32).to_le_bytes());
+        let header_size = api_net::PCAP_RECORD_HEADER_SIZE;
+        bytes[header_size..(header_size + incl_len)].copy_from_slice(&frame.bytes[0..incl_len]);
+        self.stats_captured += 1;
+
+        let mut msg = io_channel::Msg::new();
+        msg.command = api_net::EVT_CAPTURE_PACKET;
+        io_channel::ServerConnection::attach_page(&mut msg, 0, page).unwrap(); // A server page.
+        msg.payload.args_64_mut()[1] = (header_size + incl_len) as u64;
+        msg.payload.args_64_mut()[2] = self.stats_dropped;
+        msg.status = moto_rt::E_OK;
+
+        Some(PendingCompletion {
+            msg,
+            endpoint_handle: self.conn.wait_handle(),
+        })
+    }
+}
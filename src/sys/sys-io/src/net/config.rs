@@ -83,6 +83,13 @@ pub(super) struct DeviceCfg {
     pub mac: MacAddress,
     pub cidrs: Vec<IpNetwork>,
     pub routes: Vec<IpRoute>,
+    // How long a partially received IPv4 datagram is kept (RFC 791).
+    #[serde(default = "default_reassembly_timeout_secs")]
+    pub reassembly_timeout_secs: u64,
+}
+
+fn default_reassembly_timeout_secs() -> u64 {
+    15
 }
 
 impl DeviceCfg {
@@ -92,8 +99,18 @@ impl DeviceCfg {
             mac: MacAddress::from_str(mac).unwrap(),
             cidrs: vec![],
             routes: vec![],
+            reassembly_timeout_secs: default_reassembly_timeout_secs(),
         }
     }
+
+    // Whether the device can reach @dst, either directly or via a gateway.
+    pub fn routes_to(&self, dst: &IpAddr) -> bool {
+        self.cidrs.iter().any(|cidr| cidr.contains(*dst))
+            || self
+                .routes
+                .iter()
+                .any(|route| route.ip_network.contains(*dst))
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -101,6 +118,11 @@ pub(super) struct NetConfig {
     #[allow(unused)]
     pub loopback: bool,
     pub devices: BTreeMap<String, DeviceCfg>,
+    // When a device's link goes down, move TCP connections whose local
+    // address was not chosen by the application to another device
+    // that routes to the peer.
+    #[serde(default)]
+    pub migrate_connections: bool,
 }
 
 pub(super) fn load() -> Result<NetConfig, ErrorCode> {
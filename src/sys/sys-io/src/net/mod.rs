@@ -1,11 +1,15 @@
 use moto_ipc::io_channel;
 
+mod capture;
 mod config;
 mod netdev;
 mod netsys;
 mod smoltcp_helpers;
 mod socket;
 mod tcp_listener;
+mod tcp_pause;
+mod tcp_urgent;
+mod udp_socket;
 
 pub fn init() -> Box<dyn crate::runtime::IoSubsystem> {
     let config = match config::load() {
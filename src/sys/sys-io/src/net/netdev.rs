@@ -8,7 +8,108 @@ use smoltcp::iface::{SocketHandle, SocketSet};
 use smoltcp::phy::Loopback;
 use smoltcp::phy::{RxToken, TxToken};
 
+use super::capture::PacketTap;
 use super::config::DeviceCfg;
+use super::tcp_pause::RxPauses;
+use super::tcp_urgent::UrgentTracker;
+
+// If the NIC has no TX buffers, up to this many outgoing packets are kept
+// in VirtioSmoltcpDevice::pending_tx; more are dropped.
+const MAX_PENDING_TX: usize = 256;
+
+// Cumulative device counters; see moto_sys_io::stats::NetDevStatsV1.
+#[derive(Default)]
+struct DevCounters {
+    rx_packets: u64,
+    rx_bytes: u64,
+    rx_errors: u64,
+    rx_drops_checksum: u64,
+    tx_packets: u64,
+    tx_bytes: u64,
+    tx_drops_no_buffer: u64,
+}
+
+impl DevCounters {
+    fn count_tx(&mut self, len: usize) {
+        self.tx_packets += 1;
+        self.tx_bytes += len as u64;
+    }
+
+    fn count_rx(&mut self, frame: &[u8]) {
+        self.rx_packets += 1;
+        self.rx_bytes += frame.len() as u64;
+    }
+
+    // smoltcp silently drops malformed frames and packets with bad checksums; we
+    // parse the headers (again) here to count them. Only IPv4 and IPv6 without
+    // extension headers are looked into.
+    fn check_rx(&mut self, frame: &[u8]) {
+        use smoltcp::wire::*;
+
+        let Ok(frame) = EthernetFrame::new_checked(frame) else {
+            self.rx_errors += 1;
+            return;
+        };
+
+        let (src_addr, dst_addr, protocol, payload) = match frame.ethertype() {
+            EthernetProtocol::Ipv4 => {
+                let Ok(packet) = Ipv4Packet::new_checked(frame.payload()) else {
+                    self.rx_errors += 1;
+                    return;
+                };
+                if !packet.verify_checksum() {
+                    self.rx_drops_checksum += 1;
+                    return;
+                }
+                // Fragments are checksummed once reassembled. Like other
+                // hosts, we ignore the DF bit in them: it is for routers.
+                if packet.more_frags() || packet.frag_offset() != 0 {
+                    return;
+                }
+                (
+                    IpAddress::Ipv4(packet.src_addr()),
+                    IpAddress::Ipv4(packet.dst_addr()),
+                    packet.next_header(),
+                    packet.payload(),
+                )
+            }
+            EthernetProtocol::Ipv6 => {
+                let Ok(packet) = Ipv6Packet::new_checked(frame.payload()) else {
+                    self.rx_errors += 1;
+                    return;
+                };
+                (
+                    IpAddress::Ipv6(packet.src_addr()),
+                    IpAddress::Ipv6(packet.dst_addr()),
+                    packet.next_header(),
+                    packet.payload(),
+                )
+            }
+            _ => return,
+        };
+
+        let checksum_ok = match protocol {
+            IpProtocol::Tcp => match TcpPacket::new_checked(payload) {
+                Ok(packet) => packet.verify_checksum(&src_addr, &dst_addr),
+                Err(_) => {
+                    self.rx_errors += 1;
+                    return;
+                }
+            },
+            IpProtocol::Udp => match UdpPacket::new_checked(payload) {
+                Ok(packet) => packet.verify_checksum(&src_addr, &dst_addr),
+                Err(_) => {
+                    self.rx_errors += 1;
+                    return;
+                }
+            },
+            _ => true,
+        };
+        if !checksum_ok {
+            self.rx_drops_checksum += 1;
+        }
+    }
+}
 
 struct VirtioRxToken {
     dev: *mut VirtioSmoltcpDevice,
@@ -28,6 +129,10 @@ impl RxToken for VirtioRxToken {
         if let Some(rx_packet) = self.dev().rx_packet.as_ref() {
             let buf = rx_packet.bytes_mut();
             // log::debug!("consuming {} RX bytes", buf.len());
+            self.dev().counters.count_rx(buf);
+            self.dev().tap.on_rx(buf);
+            self.dev().counters.check_rx(buf);
+            self.dev().urgent.on_rx(buf);
             let res = f(buf);
             self.dev().rx_packet = None;
 
@@ -72,6 +177,10 @@ impl TxToken for VirtioTxToken {
                 assert!(buf.len() >= len);
                 let packet = &mut buf[0..len];
                 let res = f(packet);
+                self.dev().urgent.on_tx(packet);
+                self.dev().rx_pauses.on_tx(packet);
+                self.dev().tap.on_tx(packet);
+                self.dev().counters.count_tx(len);
 
                 // #[cfg(debug_assertions)]
                 // log::debug!("enqueueing tx {} bytes into the NIC (zero pending)", len);
@@ -82,7 +191,15 @@ impl TxToken for VirtioTxToken {
 
         let mut buffer = vec![0u8; len];
         let result = f(&mut buffer);
-        self.dev().pending_tx.push_back(buffer);
+        self.dev().urgent.on_tx(&mut buffer);
+        self.dev().rx_pauses.on_tx(&mut buffer);
+        self.dev().tap.on_tx(&buffer);
+        if self.dev().pending_tx.len() < MAX_PENDING_TX {
+            self.dev().pending_tx.push_back(buffer);
+            self.dev().counters.count_tx(len);
+        } else {
+            self.dev().counters.tx_drops_no_buffer += 1;
+        }
 
         result
     }
@@ -93,6 +210,10 @@ struct VirtioSmoltcpDevice {
     pending_tx: VecDeque<Vec<u8>>,
     virtio_dev: moto_virtio::virtio_net::NetDev,
     rx_packet: Option<moto_virtio::virtio_net::RxPacket>,
+    counters: DevCounters,
+    urgent: UrgentTracker,
+    rx_pauses: RxPauses,
+    tap: PacketTap,
 }
 
 impl VirtioSmoltcpDevice {
@@ -105,6 +226,10 @@ impl VirtioSmoltcpDevice {
             pending_tx: VecDeque::new(),
             virtio_dev,
             rx_packet: None,
+            counters: DevCounters::default(),
+            urgent: UrgentTracker::default(),
+            rx_pauses: RxPauses::default(),
+            tap: PacketTap::default(),
         };
         self_.virtio_dev.start_receiving();
 
@@ -210,9 +335,124 @@ impl smoltcp::phy::Device for VirtioSmoltcpDevice {
     }
 }
 
+// smoltcp's Loopback device, with packets and bytes counted.
+struct LoopbackDevice {
+    inner: Loopback,
+    counters: DevCounters,
+    urgent: UrgentTracker,
+    rx_pauses: RxPauses,
+    tap: PacketTap,
+}
+
+struct LoopbackRxToken {
+    inner: <Loopback as smoltcp::phy::Device>::RxToken<'static>,
+    counters: *mut DevCounters,
+    urgent: *mut UrgentTracker,
+    tap: *mut PacketTap,
+}
+
+impl RxToken for LoopbackRxToken {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let counters = unsafe { self.counters.as_mut().unwrap() };
+        let urgent = unsafe { self.urgent.as_mut().unwrap() };
+        let tap = unsafe { self.tap.as_mut().unwrap() };
+        self.inner.consume(|buf| {
+            counters.count_rx(buf);
+            urgent.on_rx(buf);
+            tap.on_rx(buf);
+            f(buf)
+        })
+    }
+}
+
+struct LoopbackTxToken<'a> {
+    inner: <Loopback as smoltcp::phy::Device>::TxToken<'a>,
+    counters: *mut DevCounters,
+    urgent: *mut UrgentTracker,
+    rx_pauses: *mut RxPauses,
+    tap: *mut PacketTap,
+}
+
+impl<'a> TxToken for LoopbackTxToken<'a> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        unsafe { self.counters.as_mut().unwrap() }.count_tx(len);
+        let urgent = unsafe { self.urgent.as_mut().unwrap() };
+        let rx_pauses = unsafe { self.rx_pauses.as_mut().unwrap() };
+        let tap = unsafe { self.tap.as_mut().unwrap() };
+        self.inner.consume(len, |buf| {
+            let res = f(buf);
+            urgent.on_tx(buf);
+            rx_pauses.on_tx(buf);
+            tap.on_tx(buf);
+            res
+        })
+    }
+}
+
+impl smoltcp::phy::Device for LoopbackDevice {
+    type RxToken<'a> = LoopbackRxToken
+    where
+        Self: 'a;
+
+    type TxToken<'a> = LoopbackTxToken<'a>
+    where
+        Self: 'a;
+
+    fn receive(
+        &mut self,
+        timestamp: smoltcp::time::Instant,
+    ) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let counters = &mut self.counters as *mut DevCounters;
+        let urgent = &mut self.urgent as *mut UrgentTracker;
+        let rx_pauses = &mut self.rx_pauses as *mut RxPauses;
+        let tap = &mut self.tap as *mut PacketTap;
+        let (rx, tx) = self.inner.receive(timestamp)?;
+        Some((
+            LoopbackRxToken {
+                inner: rx,
+                counters,
+                urgent,
+                tap,
+            },
+            LoopbackTxToken {
+                inner: tx,
+                counters,
+                urgent,
+                rx_pauses,
+                tap,
+            },
+        ))
+    }
+
+    fn transmit(&mut self, timestamp: smoltcp::time::Instant) -> Option<Self::TxToken<'_>> {
+        let counters = &mut self.counters as *mut DevCounters;
+        let urgent = &mut self.urgent as *mut UrgentTracker;
+        let rx_pauses = &mut self.rx_pauses as *mut RxPauses;
+        let tap = &mut self.tap as *mut PacketTap;
+        let tx = self.inner.transmit(timestamp)?;
+        Some(LoopbackTxToken {
+            inner: tx,
+            counters,
+            urgent,
+            rx_pauses,
+            tap,
+        })
+    }
+
+    fn capabilities(&self) -> smoltcp::phy::DeviceCapabilities {
+        self.inner.capabilities()
+    }
+}
+
 enum SmoltcpDevice {
     VirtIo(VirtioSmoltcpDevice),
-    Loopback(smoltcp::phy::Loopback),
+    Loopback(LoopbackDevice),
 }
 
 impl SmoltcpDevice {
@@ -235,6 +475,11 @@ pub(super) struct NetDev {
     pub sockets: SocketSet<'static>,
 
     ports_in_use: std::collections::HashSet<u16>,
+    // UDP ports have their own namespace.
+    udp_ports_in_use: std::collections::HashSet<u16>,
+
+    // Addresses added by adopt_ip_addr() -> the number of sockets using them.
+    adopted_ip_addrs: std::collections::HashMap<IpAddr, usize>,
 }
 
 impl NetDev {
@@ -254,6 +499,134 @@ impl NetDev {
         self.name.as_str()
     }
 
+    pub fn stats(&self) -> moto_sys_io::stats::NetDevStatsV1 {
+        let counters = match &self.device {
+            SmoltcpDevice::VirtIo(dev) => &dev.counters,
+            SmoltcpDevice::Loopback(dev) => &dev.counters,
+        };
+
+        let mut stats = moto_sys_io::stats::NetDevStatsV1 {
+            rx_packets: counters.rx_packets,
+            rx_bytes: counters.rx_bytes,
+            rx_errors: counters.rx_errors,
+            rx_drops_checksum: counters.rx_drops_checksum,
+            tx_packets: counters.tx_packets,
+            tx_bytes: counters.tx_bytes,
+            tx_drops_no_buffer: counters.tx_drops_no_buffer,
+            ..Default::default()
+        };
+
+        let name = &self.name.as_bytes()[0..self.name.len().min(stats.name_bytes.len())];
+        stats.name_bytes[0..name.len()].copy_from_slice(name);
+        stats.name_len = name.len() as u8;
+
+        stats
+    }
+
+    pub fn link_up(&self) -> bool {
+        match &self.device {
+            SmoltcpDevice::VirtIo(dev) => dev.virtio_dev.link_up(),
+            SmoltcpDevice::Loopback(_) => true,
+        }
+    }
+
+    // Accept traffic to @addr, the local address of a connection moved here from
+    // another device. Fails if the interface has no room for more addresses.
+    // Returns true if the address has been added (or is shared with other moved
+    // connections), so release_ip_addr() must be called when the connection
+    // leaves the device; false if it is one of the device's own addresses.
+    pub fn adopt_ip_addr(&mut self, addr: IpAddr) -> Result<bool, ()> {
+        if let Some(refs) = self.adopted_ip_addrs.get_mut(&addr) {
+            *refs += 1;
+            return Ok(true);
+        }
+
+        let smol_addr = <smoltcp::wire::IpAddress as From<IpAddr>>::from(addr);
+        if self.iface.has_ip_addr(smol_addr) {
+            return Ok(false);
+        }
+
+        let prefix_len = match smol_addr {
+            smoltcp::wire::IpAddress::Ipv4(_) => 32,
+            smoltcp::wire::IpAddress::Ipv6(_) => 128,
+        };
+        let mut added = false;
+        self.iface.update_ip_addrs(|ip_addrs| {
+            added = ip_addrs
+                .push(smoltcp::wire::IpCidr::new(smol_addr, prefix_len))
+                .is_ok();
+        });
+        if !added {
+            return Err(());
+        }
+        self.adopted_ip_addrs.insert(addr, 1);
+        Ok(true)
+    }
+
+    // Removes @addr added by adopt_ip_addr() once no connection uses it.
+    pub fn release_ip_addr(&mut self, addr: IpAddr) {
+        let refs = self.adopted_ip_addrs.get_mut(&addr).unwrap();
+        *refs -= 1;
+        if *refs > 0 {
+            return;
+        }
+        self.adopted_ip_addrs.remove(&addr);
+
+        let smol_addr = <smoltcp::wire::IpAddress as From<IpAddr>>::from(addr);
+        self.iface.update_ip_addrs(|ip_addrs| {
+            ip_addrs.retain(|cidr| cidr.address() != smol_addr);
+        });
+    }
+
+    // TCP urgent data state of the connections on this device; see tcp_urgent.rs.
+    pub fn tcp_urgent(&mut self) -> &mut UrgentTracker {
+        match &mut self.device {
+            SmoltcpDevice::VirtIo(dev) => &mut dev.urgent,
+            SmoltcpDevice::Loopback(dev) => &mut dev.urgent,
+        }
+    }
+
+    // The largest IP packet the device sends without fragmenting it.
+    pub fn ip_mtu(&self) -> usize {
+        match &self.device {
+            SmoltcpDevice::VirtIo(dev) => smoltcp::phy::Device::capabilities(dev).ip_mtu(),
+            SmoltcpDevice::Loopback(dev) => smoltcp::phy::Device::capabilities(dev).ip_mtu(),
+        }
+    }
+
+    // Zero windows of paused connections on this device; see tcp_pause.rs.
+    pub fn tcp_rx_pauses(&mut self) -> &mut RxPauses {
+        match &mut self.device {
+            SmoltcpDevice::VirtIo(dev) => &mut dev.rx_pauses,
+            SmoltcpDevice::Loopback(dev) => &mut dev.rx_pauses,
+        }
+    }
+
+    // Sends a frame built by sys-io rather than smoltcp (see tcp_pause.rs).
+    pub fn send_frame(&mut self, frame: &[u8]) {
+        let copy = |buf: &mut [u8]| buf.copy_from_slice(frame);
+        match &mut self.device {
+            SmoltcpDevice::VirtIo(dev) => VirtioTxToken {
+                dev: dev as *mut VirtioSmoltcpDevice,
+            }
+            .consume(frame.len(), copy),
+            SmoltcpDevice::Loopback(dev) => {
+                let now = smoltcp::time::Instant::now();
+                if let Some(tx) = smoltcp::phy::Device::transmit(dev, now) {
+                    tx.consume(frame.len(), copy);
+                }
+            }
+        }
+    }
+
+    // Packet capture; see capture.rs.
+    pub fn tap(&mut self) -> &mut PacketTap {
+        match &mut self.device {
+            SmoltcpDevice::VirtIo(dev) => &mut dev.tap,
+            SmoltcpDevice::Loopback(dev) => &mut dev.tap,
+        }
+    }
+
     fn new(name: &str, dev_cfg: &super::config::DeviceCfg, mut device: SmoltcpDevice) -> Self {
         let mut config = smoltcp::iface::Config::new(device.ethernet_address().into());
         config.random_seed = std::time::SystemTime::now()
@@ -270,6 +643,17 @@ impl NetDev {
             }
         };
 
+        // Outgoing IPv4 datagrams larger than the MTU (e.g. replies to large
+        // pings) are fragmented by smoltcp, unless sent with DF (see
+        // api_net::FLAG_UDP_TX_DONT_FRAG); TCP segments always fit the MTU.
+        // Incoming fragments are reassembled in at
+        // most REASSEMBLY_BUFFER_COUNT (a Cargo feature) buffers at a time;
+        // incomplete ones are dropped after the timeout, so that lost (or
+        // malicious) fragments cannot hold the buffers forever.
+        iface.set_reassembly_timeout(smoltcp::time::Duration::from_secs(
+            dev_cfg.reassembly_timeout_secs,
+        ));
+
         iface.update_ip_addrs(|ip_addrs| {
             for cidr in &dev_cfg.cidrs {
                 log::debug!(
@@ -306,6 +690,8 @@ impl NetDev {
             iface,
             sockets: SocketSet::new(vec![]),
             ports_in_use: std::collections::HashSet::new(),
+            udp_ports_in_use: std::collections::HashSet::new(),
+            adopted_ip_addrs: std::collections::HashMap::new(),
         }
     }
 
@@ -336,10 +722,31 @@ impl NetDev {
         None
     }
 
+    // Reserve a specific local port (e.g. for a connection bound to a local address).
+    pub fn reserve_port(&mut self, port: u16) -> bool {
+        self.ports_in_use.insert(port)
+    }
+
     pub fn free_ephemeral_port(&mut self, port: u16) {
         self.ports_in_use.remove(&port);
     }
 
+    pub fn ephemeral_ports() -> core::ops::RangeInclusive<u16> {
+        Self::EPHEMERAL_PORT_MIN..=Self::EPHEMERAL_PORT_MAX
+    }
+
+    pub fn udp_port_in_use(&self, port: u16) -> bool {
+        self.udp_ports_in_use.contains(&port)
+    }
+
+    pub fn reserve_udp_port(&mut self, port: u16) -> bool {
+        self.udp_ports_in_use.insert(port)
+    }
+
+    pub fn free_udp_port(&mut self, port: u16) {
+        self.udp_ports_in_use.remove(&port);
+    }
+
     // Have to have this as a method here because it borrows self twice: for the socket and for the iface.
     pub fn connect_socket(
         &mut self,
@@ -386,7 +793,13 @@ pub(super) fn init(config: &super::config::NetConfig) -> Vec<NetDev> {
         loopback_cfg
             .cidrs
             .push(ipnetwork::IpNetwork::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8).unwrap());
-        let loopback_dev = Loopback::new(smoltcp::phy::Medium::Ethernet);
+        let loopback_dev = LoopbackDevice {
+            inner: Loopback::new(smoltcp::phy::Medium::Ethernet),
+            counters: DevCounters::default(),
+            urgent: UrgentTracker::default(),
+            rx_pauses: RxPauses::default(),
+            tap: PacketTap::default(),
+        };
         let dev = NetDev::new(
             "loopback",
             &loopback_cfg,
@@ -11,10 +11,12 @@ use moto_ipc::io_channel;
 use moto_sys::{ErrorCode, SysHandle};
 use moto_sys_io::api_net::{self, TcpState};
 
+use super::capture::Capture;
 use super::socket::MotoSocket;
 use super::socket::SocketId;
 use super::tcp_listener::TcpListener;
 use super::tcp_listener::TcpListenerId;
+use super::udp_socket::UdpSocket;
 use super::RxBuf;
 use super::{netdev::NetDev, TxBuf};
 
@@ -23,6 +25,8 @@ const DEFAULT_NUM_LISTENING_SOCKETS: usize = 4;
 const MAX_NUM_LISTENING_SOCKETS: usize = 32;
 // How many concurrent connections per listener (any SocketAddr) to allow.
 const _DEFAULT_MAX_CONNECTIONS_PER_LISTENER: usize = 16;
+// How often to check device links when connection migration is enabled.
+const LINK_CHECK_INTERVAL: core::time::Duration = core::time::Duration::from_secs(1);
 
 pub(super) struct NetSys {
     devices: Vec<NetDev>, // Never changes, as device_idx references inside here.
@@ -54,6 +58,35 @@ pub(super) struct NetSys {
     woken_sockets: Rc<RefCell<VecDeque<SocketId>>>,
     wakers: std::collections::HashMap<SocketId, std::task::Waker>,
 
+    // Sockets with an idle timeout, and when to check them next.
+    idle_tcp_sockets: HashSet<SocketId>,
+    next_idle_check: Option<moto_rt::time::Instant>,
+
+    // Closed sockets waiting for their queued bytes to be sent (SO_LINGER).
+    lingering_tcp_sockets: HashSet<SocketId>,
+
+    // Round-robin counter for listeners sharing an address (FLAG_TCP_LISTENER_REUSE_PORT).
+    next_reuse_port_pick: u64,
+
+    // Conn ID -> packet capture (CMD_CAPTURE_START).
+    captures: HashMap<SysHandle, Capture>,
+
+    // UDP sockets share SocketId space (and woken_sockets) with TCP sockets.
+    udp_sockets: HashMap<SocketId, UdpSocket>,
+    conn_udp_sockets: HashMap<SysHandle, HashSet<SocketId>>,
+    // UDP sockets with datagrams waiting for an IO page.
+    pending_udp_rx: VecDeque<SocketId>,
+
+    // Link state of each device, and when to check it next (only
+    // when config.migrate_connections is set).
+    links_up: Vec<bool>,
+    next_link_check: Option<moto_rt::time::Instant>,
+
+    // stats
+    stats_tcp_idle_reaped: u64,
+    stats_tcp_migrated: u64,
+    stats_connect_no_route: u64,
+
     // config: config::NetConfig,
     config: super::config::NetConfig,
 }
@@ -61,6 +94,7 @@ pub(super) struct NetSys {
 impl NetSys {
     pub fn new(config: super::config::NetConfig) -> Box<Self> {
         let devices = super::netdev::init(&config);
+        let links_up = devices.iter().map(|dev| dev.link_up()).collect();
         let mut self_ref = Box::new(Self {
             devices,
             wait_handles: HashMap::new(),
@@ -76,6 +110,19 @@ impl NetSys {
             conn_tcp_sockets: HashMap::new(),
             woken_sockets: Rc::new(std::cell::RefCell::new(VecDeque::new())),
             wakers: HashMap::new(),
+            idle_tcp_sockets: HashSet::new(),
+            next_idle_check: None,
+            lingering_tcp_sockets: HashSet::new(),
+            next_reuse_port_pick: 0,
+            captures: HashMap::new(),
+            udp_sockets: HashMap::new(),
+            conn_udp_sockets: HashMap::new(),
+            pending_udp_rx: VecDeque::new(),
+            links_up,
+            next_link_check: None,
+            stats_tcp_idle_reaped: 0,
+            stats_tcp_migrated: 0,
+            stats_connect_no_route: 0,
             config,
         });
 
@@ -113,12 +160,35 @@ impl NetSys {
             }
         };
 
-        // Verify that we are not listening on that address yet.
+        // Verify that we are not listening on that address yet, unless the address
+        // is shared by listeners of the same process (FLAG_TCP_LISTENER_REUSE_PORT).
+        let reuse_port = (sqe.flags & api_net::FLAG_TCP_LISTENER_REUSE_PORT) != 0;
+        let pid = match moto_sys::SysObj::get_pid(conn.wait_handle()) {
+            Ok(pid) => pid,
+            Err(err) => {
+                sqe.status = err;
+                return sqe;
+            }
+        };
         for (_, listener) in &self.tcp_listeners {
-            if *listener.socket_addr() == socket_addr {
+            if *listener.socket_addr() != socket_addr {
+                continue;
+            }
+            if !(reuse_port && listener.reuse_port()) {
                 sqe.status = moto_rt::E_ALREADY_IN_USE;
                 return sqe;
             }
+            match moto_sys::SysObj::get_pid(listener.conn_handle()) {
+                Ok(listener_pid) if listener_pid == pid => {}
+                Ok(_) => {
+                    sqe.status = moto_rt::E_ALREADY_IN_USE;
+                    return sqe;
+                }
+                Err(err) => {
+                    sqe.status = err;
+                    return sqe;
+                }
+            }
         }
 
         // TODO: what if we are listening on *:PORT, and are asked to listen on IP:PORT?
@@ -133,16 +203,24 @@ impl NetSys {
             match self.ip_addresses.get(&ip_addr) {
                 Some(idx) => Some(*idx),
                 None => {
-                    sqe.status = moto_rt::E_INVALID_ARGUMENT;
+                    sqe.status = moto_rt::E_ADDR_NOT_AVAILABLE;
                     return sqe;
                 }
             }
         };
 
-        let num_listeners = if sqe.flags == 0 {
-            DEFAULT_NUM_LISTENING_SOCKETS
-        } else {
-            sqe.flags as usize
+        // Lingering connections on the address from listeners that are gone don't
+        // block it, unless the caller asked for the address exclusively.
+        if (sqe.flags & api_net::FLAG_TCP_LISTENER_EXCLUSIVE_ADDR) != 0
+            && self.has_orphaned_connections_on(&socket_addr)
+        {
+            sqe.status = moto_rt::E_ALREADY_IN_USE;
+            return sqe;
+        }
+
+        let num_listeners = match sqe.flags & api_net::TCP_LISTENER_NUM_LISTENERS_MASK {
+            0 => DEFAULT_NUM_LISTENING_SOCKETS,
+            num => num as usize,
         };
         if num_listeners > MAX_NUM_LISTENING_SOCKETS {
             sqe.status = moto_rt::E_INVALID_ARGUMENT;
@@ -150,7 +228,7 @@ impl NetSys {
         }
 
         let listener_id: TcpListenerId = self.next_id().into();
-        let listener = TcpListener::new(conn.clone(), socket_addr);
+        let listener = TcpListener::new(conn.clone(), socket_addr, reuse_port);
         self.tcp_listeners.insert(listener_id, listener);
 
         let conn_listeners = match self.conn_tcp_listeners.get_mut(&conn.wait_handle()) {
@@ -184,7 +262,7 @@ impl NetSys {
                             local_addr,
                             num_listeners,
                         ) {
-                            self.drop_tcp_listener(listener_id);
+                            self.drop_tcp_listener(listener_id, false);
                             sqe.status = err.into();
                             return sqe;
                         }
@@ -195,7 +273,7 @@ impl NetSys {
                 if let Err(err) =
                     self.start_listening_on_device(listener_id, idx, socket_addr, num_listeners)
                 {
-                    self.drop_tcp_listener(listener_id);
+                    self.drop_tcp_listener(listener_id, false);
                     sqe.status = err.into();
                     return sqe;
                 }
@@ -207,6 +285,29 @@ impl NetSys {
         sqe
     }
 
+    // Whether there are connected sockets, previously accepted on socket_addr,
+    // whose listener has been dropped.
+    fn has_orphaned_connections_on(&self, socket_addr: &SocketAddr) -> bool {
+        for moto_socket in self.tcp_sockets.values() {
+            let Some(addr) = moto_socket.listening_on else {
+                continue;
+            };
+            if moto_socket.state == TcpState::Listening
+                || addr.port() != socket_addr.port()
+                || !(socket_addr.ip().is_unspecified() || addr.ip() == socket_addr.ip())
+            {
+                continue;
+            }
+
+            match moto_socket.listener_id {
+                Some(listener_id) if self.tcp_listeners.contains_key(&listener_id) => {}
+                _ => return true,
+            }
+        }
+
+        false
+    }
+
     fn start_listening_on_device(
         &mut self,
         listener_id: TcpListenerId,
@@ -227,10 +328,10 @@ impl NetSys {
             let mut moto_socket = self.new_socket_for_device(device_idx, conn)?;
             let socket_id = moto_socket.id;
             moto_socket.listener_id = Some(listener_id);
-            self.tcp_listeners
-                .get_mut(&listener_id)
-                .unwrap()
-                .add_listening_socket(socket_id);
+            let listener = self.tcp_listeners.get_mut(&listener_id).unwrap();
+            listener.add_listening_socket(socket_id);
+            // Accepted connections inherit this.
+            moto_socket.local_addr_pinned = !listener.socket_addr().ip().is_unspecified();
             moto_socket.state = TcpState::Listening;
             moto_socket.listening_on = Some(socket_addr);
             if let Some(conn_sockets) = self.conn_tcp_sockets.get_mut(&conn_handle) {
@@ -298,7 +399,11 @@ impl NetSys {
             listener_id: None,
             connect_req: None,
             ephemeral_port: None,
+            local_addr_pinned: false,
+            home_device_idx: None,
+            adopted_ip_addr: None,
             tx_queue: VecDeque::new(),
+            tx_space_wanted: false,
             rx_seq: 0,
             rx_ack: u64::MAX,
             state: TcpState::Closed,
@@ -306,27 +411,49 @@ impl NetSys {
             subchannel_mask: u64::MAX,
             listening_on: None,
             replacement_listener_created: false,
+            orphaned: false,
+            idle_timeout: None,
+            last_activity: moto_rt::time::Instant::now(),
+            rx_paused: false,
+            linger: None,
+            linger_close: None,
+            close_status: moto_rt::E_OK,
             stats_rx_bytes: 0,
             stats_tx_bytes: 0,
+            stats_migrations: 0,
         })
     }
 
-    fn drop_tcp_listener(&mut self, listener_id: TcpListenerId) {
+    // If close_pending_with_fin is true, connected but not yet accepted sockets
+    // are closed with a FIN rather than reset.
+    fn drop_tcp_listener(&mut self, listener_id: TcpListenerId, close_pending_with_fin: bool) {
         let mut listener = self.tcp_listeners.remove(&listener_id).unwrap();
+        self.complete_pending_accepts(&mut listener, moto_rt::E_BAD_HANDLE);
+
+        while let Some((socket_id, _)) = listener.pop_pending_socket() {
+            if self.tcp_sockets.contains_key(&socket_id) {
+                if close_pending_with_fin {
+                    self.close_orphaned_tcp_socket(socket_id);
+                } else {
+                    self.drop_tcp_socket(socket_id);
+                }
+            }
+        }
+
+        self.drop_listening_sockets(&mut listener);
+    }
+
+    fn complete_pending_accepts(&mut self, listener: &mut TcpListener, status: ErrorCode) {
         while let Some((mut req, conn)) = listener.get_pending_accept() {
-            req.status = moto_rt::E_BAD_HANDLE;
+            req.status = status;
             self.pending_completions.push_back(PendingCompletion {
                 msg: req,
                 endpoint_handle: conn.wait_handle(),
             });
         }
+    }
 
-        while let Some((socket_id, _)) = listener.pop_pending_socket() {
-            if self.tcp_sockets.contains_key(&socket_id) {
-                self.drop_tcp_socket(socket_id);
-            }
-        }
-
+    fn drop_listening_sockets(&mut self, listener: &mut TcpListener) {
         let ids = listener.take_listening_sockets();
         for id in ids {
             if self.tcp_sockets.contains_key(&id) {
@@ -335,6 +462,61 @@ impl NetSys {
         }
     }
 
+    // Gracefully closes a connected socket nobody has accepted: the socket
+    // is detached from its listener, and is dropped in on_tcp_socket_poll()
+    // when the close handshake completes (or times out).
+    fn close_orphaned_tcp_socket(&mut self, socket_id: SocketId) {
+        let moto_socket = self.tcp_sockets.get_mut(&socket_id).unwrap();
+        assert_eq!(moto_socket.state, TcpState::PendingAccept);
+        moto_socket.listener_id = None;
+        moto_socket.state = TcpState::Closed;
+        moto_socket.orphaned = true;
+
+        let device_idx = moto_socket.device_idx;
+        let smol_socket = self.devices[device_idx]
+            .sockets
+            .get_mut::<smoltcp::socket::tcp::Socket>(moto_socket.handle);
+
+        // Don't let a silent peer keep the socket around forever.
+        smol_socket.set_timeout(Some(smoltcp::time::Duration::from_millis(5_000)));
+        smol_socket.close();
+
+        // Poll the device to send the FIN.
+        while self.devices[device_idx].poll() {}
+    }
+
+    // Drops the urgent data and paused RX state (see tcp_urgent.rs and
+    // tcp_pause.rs) of connections @device_idx no longer has sockets for.
+    fn forget_tcp_state(&mut self, device_idx: usize) {
+        let device = &mut self.devices[device_idx];
+        if device.tcp_urgent().is_empty() && device.tcp_rx_pauses().is_empty() {
+            return;
+        }
+        let mut live: HashSet<_> = device
+            .sockets
+            .iter()
+            .filter_map(|(_, socket)| match socket {
+                smoltcp::socket::Socket::Tcp(socket) => {
+                    Some((socket.local_endpoint()?, socket.remote_endpoint()?))
+                }
+                _ => None,
+            })
+            .collect();
+        // Lingering sockets may have been reset (see tcp_urgent::UrgentTracker::peer_reset()).
+        for socket_id in &self.lingering_tcp_sockets {
+            let moto_socket = self.tcp_sockets.get(socket_id).unwrap();
+            if moto_socket.device_idx == device_idx {
+                live.extend(moto_socket.linger_close.as_ref().unwrap().2);
+            }
+        }
+        device
+            .tcp_urgent()
+            .retain(|endpoints| live.contains(endpoints));
+        device
+            .tcp_rx_pauses()
+            .retain(|endpoints| live.contains(endpoints));
+    }
+
     fn drop_tcp_socket(&mut self, socket_id: SocketId) {
         self.cancel_tcp_tx(socket_id);
 
@@ -357,7 +539,6 @@ impl NetSys {
         let smol_socket = self.devices[moto_socket.device_idx]
             .sockets
             .get_mut::<smoltcp::socket::tcp::Socket>(moto_socket.handle);
-
         smol_socket.abort();
 
         // Remove the waker so that any polls on the socket from below don't trigger
@@ -373,6 +554,8 @@ impl NetSys {
         // Now we can remove moto_socket.
         let mut moto_socket = self.tcp_sockets.remove(&socket_id).unwrap();
         assert!(self.socket_ids.remove(&socket_id));
+        self.idle_tcp_sockets.remove(&socket_id);
+        self.lingering_tcp_sockets.remove(&socket_id);
         while let Some(tx_buf) = moto_socket.tx_queue.pop_front() {
             core::mem::drop(tx_buf);
         }
@@ -410,10 +593,14 @@ impl NetSys {
             smoltcp::socket::Socket::Tcp(s) => s,
             _ => panic!(),
         };
+        self.forget_tcp_state(moto_socket.device_idx);
 
         if let Some(port) = moto_socket.ephemeral_port.take() {
             self.devices[moto_socket.device_idx].free_ephemeral_port(port);
         }
+        if let Some(addr) = moto_socket.adopted_ip_addr.take() {
+            self.devices[moto_socket.device_idx].release_ip_addr(addr);
+        }
 
         assert_eq!(smol_socket.state(), smoltcp::socket::tcp::State::Closed);
         self.put_unused_tcp_socket(smol_socket);
@@ -449,7 +636,15 @@ impl NetSys {
             }
         }
 
+        if listener.is_draining() && !listener.has_pending_sockets() {
+            // Like POSIX accept() on a socket that is not listening.
+            req.status = moto_rt::E_INVALID_ARGUMENT;
+            return Ok(Some(req));
+        }
+
+        let idle_timeout = listener.idle_timeout();
         if let Some((socket_id, socket_addr)) = listener.pop_pending_socket() {
+            listener.on_accepted();
             // TODO: the unwrap() below once triggered on remote drop.
             let moto_socket = self.tcp_sockets.get_mut(&socket_id).unwrap();
             assert_eq!(moto_socket.listener_id.unwrap(), listener_id);
@@ -485,6 +680,10 @@ impl NetSys {
                 u64::from(socket_id)
             );
 
+            if idle_timeout.is_some() {
+                self.set_tcp_idle_timeout(socket_id, idle_timeout);
+            }
+
             // Do rx after generating the accept completion, otherwise rx packets
             // may get delivered to the client for an unknown socket.
             self.do_tcp_rx(socket_id); // The socket can now Rx.
@@ -521,123 +720,630 @@ impl NetSys {
         if let Some(listeners) = self.conn_tcp_listeners.get_mut(&conn_handle) {
             assert!(listeners.remove(&listener_id));
         }
-        self.drop_tcp_listener(listener_id);
+        self.drop_tcp_listener(
+            listener_id,
+            msg.flags & api_net::FLAG_TCP_LISTENER_DROP_FIN != 0,
+        );
         Ok(())
     }
 
-    fn get_unused_tcp_socket(
+    fn tcp_listener_set_option(
         &mut self,
-    ) -> Result<smoltcp::socket::tcp::Socket<'static>, ErrorCode> {
-        if let Some(socket) = self.tcp_socket_cache.pop() {
-            Ok(socket)
-        } else {
-            const RX_BUF_SZ: usize =
-                io_channel::PAGE_SIZE * (api_net::TCP_RX_MAX_INFLIGHT as usize);
-            let rx_buffer = smoltcp::socket::tcp::SocketBuffer::new(vec![0; RX_BUF_SZ]);
-            let tx_buffer = smoltcp::socket::tcp::SocketBuffer::new(vec![0; 16384 * 2]);
+        conn: &Rc<io_channel::ServerConnection>,
+        mut msg: io_channel::Msg,
+    ) -> Result<Option<io_channel::Msg>, ()> {
+        let listener_id: TcpListenerId = msg.handle.into();
+        let mut listener = match self.tcp_listeners.remove(&listener_id) {
+            Some(val) => val,
+            None => {
+                return Err(());
+            }
+        };
+        if listener.conn_handle() != conn.wait_handle() {
+            self.tcp_listeners.insert(listener_id, listener);
+            return Err(());
+        }
 
-            log::debug!("{}:{} new TCP socket", file!(), line!());
-            Ok(smoltcp::socket::tcp::Socket::new(rx_buffer, tx_buffer))
+        match msg.payload.args_64()[0] {
+            api_net::TCP_LISTENER_OPTION_DRAIN => {
+                // Stop accepting new connections, but keep the already
+                // connected ones so that the application can accept them.
+                if !listener.is_draining() {
+                    listener.set_draining();
+                    self.drop_listening_sockets(&mut listener);
+
+                    // Pending accepts imply there are no pending sockets,
+                    // and there will be no new ones.
+                    self.complete_pending_accepts(&mut listener, moto_rt::E_INVALID_ARGUMENT);
+                }
+                msg.status = moto_rt::E_OK;
+            }
+            api_net::TCP_LISTENER_OPTION_IDLE_TIMEOUT => {
+                listener
+                    .set_idle_timeout(api_net::idle_timeout_from_nanos(msg.payload.args_64()[1]));
+                msg.status = moto_rt::E_OK;
+            }
+            _ => msg.status = moto_rt::E_INVALID_ARGUMENT,
         }
-    }
 
-    fn put_unused_tcp_socket(&mut self, socket: smoltcp::socket::tcp::Socket<'static>) {
-        debug_assert_eq!(socket.state(), smoltcp::socket::tcp::State::Closed);
-        // TODO: limit the size of the cache (i.e. drop socket if the cache is too large).
-        self.tcp_socket_cache.push(socket);
+        self.tcp_listeners.insert(listener_id, listener);
+        Ok(Some(msg))
     }
 
-    fn tcp_stream_connect(
+    fn tcp_listener_get_option(
         &mut self,
         conn: &Rc<io_channel::ServerConnection>,
-        mut sqe: io_channel::Msg,
-    ) -> Option<io_channel::Msg> {
-        let remote_addr = match api_net::get_socket_addr(&sqe.payload) {
-            Ok(addr) => addr,
-            Err(err) => {
-                sqe.status = err.into();
-                return Some(sqe);
+        mut msg: io_channel::Msg,
+    ) -> Result<Option<io_channel::Msg>, ()> {
+        let listener_id: TcpListenerId = msg.handle.into();
+        let listener = match self.tcp_listeners.get(&listener_id) {
+            Some(val) => val,
+            None => {
+                return Err(());
             }
         };
+        if listener.conn_handle() != conn.wait_handle() {
+            return Err(());
+        }
 
-        let timeout = api_net::tcp_stream_connect_timeout(&sqe);
-        if let Some(timo) = timeout {
-            if timo <= moto_rt::time::Instant::now() {
-                sqe.status = moto_rt::E_TIMED_OUT;
-                return Some(sqe);
+        match msg.payload.args_64()[0] {
+            api_net::TCP_LISTENER_OPTION_PENDING_ACCEPTS => {
+                msg.payload.args_64_mut()[0] = listener.num_pending_sockets() as u64;
+                msg.status = moto_rt::E_OK;
+            }
+            api_net::TCP_LISTENER_OPTION_ACCEPTED => {
+                msg.payload.args_64_mut()[0] = listener.stats_accepted();
+                msg.status = moto_rt::E_OK;
             }
+            _ => msg.status = moto_rt::E_INVALID_ARGUMENT,
+        }
+
+        Ok(Some(msg))
+    }
+
+    fn capture_start(
+        &mut self,
+        conn: &Rc<io_channel::ServerConnection>,
+        mut msg: io_channel::Msg,
+    ) -> io_channel::Msg {
+        let allowed = match conn.peer_credentials() {
+            Ok((_, caps)) => (caps & moto_sys::caps::CAP_NET_CAPTURE) != 0,
+            Err(_) => false,
         };
+        if !allowed {
+            msg.status = moto_rt::E_NOT_ALLOWED;
+            return msg;
+        }
 
-        #[cfg(debug_assertions)]
-        log::debug!(
-            "sys-io: 0x{:x}: tcp connect to {:?}",
-            conn.wait_handle().as_u64(),
-            remote_addr
+        let filter = api_net::capture_filter(&msg);
+        let subchannel_mask = msg.payload.args_64()[0];
+        if subchannel_mask == 0
+            || filter.directions == 0
+            || (filter.directions & !(api_net::CAPTURE_RX | api_net::CAPTURE_TX)) != 0
+            || filter.snaplen == 0
+            || filter.snaplen > api_net::CAPTURE_MAX_SNAPLEN
+            || filter
+                .device_idx
+                .is_some_and(|idx| idx as usize >= self.devices.len())
+        {
+            msg.status = moto_rt::E_INVALID_ARGUMENT;
+            return msg;
+        }
+        if self.captures.contains_key(&conn.wait_handle()) {
+            msg.status = moto_rt::E_ALREADY_IN_USE;
+            return msg;
+        }
+
+        self.captures.insert(
+            conn.wait_handle(),
+            Capture::new(conn.clone(), filter, subchannel_mask),
         );
+        self.update_packet_taps();
 
-        let (device_idx, local_ip_addr) = if let Some(pair) = self.find_route(&remote_addr.ip()) {
-            pair
-        } else {
-            #[cfg(debug_assertions)]
-            log::debug!(
-                "sys-io: 0x{:x}: tcp connect to {:?}: route not found",
-                conn.wait_handle().as_u64(),
-                remote_addr
-            );
+        msg.status = moto_rt::E_OK;
+        msg
+    }
 
-            sqe.status = moto_rt::E_NOT_FOUND;
-            return Some(sqe);
+    fn capture_stop(
+        &mut self,
+        conn: &Rc<io_channel::ServerConnection>,
+        mut msg: io_channel::Msg,
+    ) -> io_channel::Msg {
+        let Some(capture) = self.captures.remove(&conn.wait_handle()) else {
+            msg.status = moto_rt::E_NOT_FOUND;
+            return msg;
         };
+        self.update_packet_taps();
 
-        let local_port =
-            match self.devices[device_idx].get_ephemeral_port(&local_ip_addr, &remote_addr) {
-                Some(port) => port,
-                None => {
-                    log::info!("get_ephemeral_port({:?}) failed", local_ip_addr);
-                    sqe.status = moto_rt::E_OUT_OF_MEMORY;
-                    return Some(sqe);
+        msg.payload.args_64_mut()[0] = capture.captured();
+        msg.payload.args_64_mut()[1] = capture.dropped();
+        msg.status = moto_rt::E_OK;
+        msg
+    }
+
+    // Devices copy frames only while someone captures them.
+    fn update_packet_taps(&mut self) {
+        for (device_idx, dev) in self.devices.iter_mut().enumerate() {
+            let enabled = self
+                .captures
+                .values()
+                .any(|capture| capture.on_device(device_idx));
+            dev.tap().set_enabled(enabled);
+        }
+    }
+
+    fn deliver_captured_packets(&mut self) {
+        if self.captures.is_empty() {
+            return;
+        }
+
+        for (device_idx, dev) in self.devices.iter_mut().enumerate() {
+            let (frames, overflows) = dev.tap().take();
+            if frames.is_empty() && overflows == 0 {
+                continue;
+            }
+
+            for capture in self.captures.values_mut() {
+                if !capture.on_device(device_idx) {
+                    continue;
                 }
-            };
+                capture.on_overflow(overflows);
+                for frame in &frames {
+                    if let Some(pc) = capture.deliver(frame) {
+                        self.pending_completions.push_back(pc);
+                    }
+                }
+            }
+        }
+    }
 
-        let mut moto_socket = match self.new_socket_for_device(device_idx, conn.clone()) {
-            Ok(s) => s,
+    fn udp_socket_bind(
+        &mut self,
+        conn: &Rc<io_channel::ServerConnection>,
+        mut sqe: io_channel::Msg,
+    ) -> io_channel::Msg {
+        let socket_addr = match api_net::get_socket_addr(&sqe.payload) {
+            Ok(addr) => addr,
             Err(err) => {
-                sqe.status = err.into();
-                return Some(sqe);
+                sqe.status = err;
+                return sqe;
             }
         };
-        moto_socket.subchannel_mask = sqe.payload.args_64()[0];
+        let subchannel_mask = sqe.handle;
+        if subchannel_mask == 0 {
+            sqe.status = moto_rt::E_INVALID_ARGUMENT;
+            return sqe;
+        }
 
-        let conn_handle = conn.wait_handle();
-        if let Some(conn_sockets) = self.conn_tcp_sockets.get_mut(&conn_handle) {
-            conn_sockets.insert(moto_socket.id);
+        let ip_addr = socket_addr.ip();
+        let device_idxs: Vec<usize> = if ip_addr.is_unspecified() {
+            (0..self.devices.len()).collect()
         } else {
-            let mut conn_sockets = HashSet::new();
-            conn_sockets.insert(moto_socket.id);
-            self.conn_tcp_sockets.insert(conn_handle, conn_sockets);
+            match self.ip_addresses.get(&ip_addr) {
+                Some(idx) => vec![*idx],
+                None => {
+                    sqe.status = moto_rt::E_ADDR_NOT_AVAILABLE;
+                    return sqe;
+                }
+            }
+        };
+        if device_idxs.is_empty() {
+            sqe.status = moto_rt::E_NOT_FOUND;
+            return sqe;
         }
 
-        let local_addr = SocketAddr::new(local_ip_addr, local_port);
+        // The port must be free on all the devices.
+        let port_free = |port: u16| {
+            !device_idxs
+                .iter()
+                .any(|idx| self.devices[*idx].udp_port_in_use(port))
+        };
+        let port = if socket_addr.port() != 0 {
+            if !port_free(socket_addr.port()) {
+                sqe.status = moto_rt::E_ALREADY_IN_USE;
+                return sqe;
+            }
+            socket_addr.port()
+        } else {
+            // TODO: do better than a linear search.
+            match NetDev::ephemeral_ports().find(|port| port_free(*port)) {
+                Some(port) => port,
+                None => {
+                    sqe.status = moto_rt::E_OUT_OF_MEMORY;
+                    return sqe;
+                }
+            }
+        };
 
-        moto_socket.connect_req = Some(sqe);
-        // Note: we don't generate the state change event because it is implied.
-        moto_socket.state = TcpState::Connecting;
-        moto_socket.ephemeral_port = Some(local_port);
+        let socket_id: SocketId = self.next_id().into();
+        let socket_waker = super::socket::SocketWaker::new(socket_id, self.woken_sockets.clone());
+        let waker = unsafe { std::task::Waker::from_raw(socket_waker.into_raw_waker()) };
 
-        let smol_handle = moto_socket.handle;
-        self.socket_ids.insert(moto_socket.id);
-        self.tcp_sockets.insert(moto_socket.id, moto_socket);
+        let listen_addr = if ip_addr.is_unspecified() {
+            None
+        } else {
+            Some(ip_addr.into())
+        };
+        let mut handles = Vec::with_capacity(device_idxs.len());
+        for device_idx in device_idxs {
+            let mut smol_socket = UdpSocket::new_smoltcp_socket();
+            smol_socket
+                .bind(smoltcp::wire::IpListenEndpoint {
+                    addr: listen_addr,
+                    port,
+                })
+                .unwrap();
+            smol_socket.register_recv_waker(&waker);
 
-        let smol_socket = self.devices[device_idx]
-            .sockets
-            .get_mut::<smoltcp::socket::tcp::Socket>(smol_handle);
+            let device = &mut self.devices[device_idx];
+            assert!(device.reserve_udp_port(port));
+            handles.push((device_idx, device.sockets.add(smol_socket)));
+        }
+        self.wakers.insert(socket_id, waker);
 
-        if let Some(timeout) = timeout {
-            let now = moto_rt::time::Instant::now();
-            if timeout <= now {
-                // We check this upon receiving sqe; the thread got preempted or something.
-                // Just use an arbitrary small timeout.
-                smol_socket.set_timeout(Some(smoltcp::time::Duration::from_micros(10)));
+        let local_addr = SocketAddr::new(ip_addr, port);
+        self.udp_sockets.insert(
+            socket_id,
+            UdpSocket {
+                id: socket_id,
+                conn: conn.clone(),
+                pid: moto_sys::SysObj::get_pid(conn.wait_handle()).unwrap_or(0),
+                subchannel_mask,
+                local_addr,
+                handles,
+                stats_rx_dropped: 0,
+            },
+        );
+        self.conn_udp_sockets
+            .entry(conn.wait_handle())
+            .or_default()
+            .insert(socket_id);
+
+        #[cfg(debug_assertions)]
+        log::debug!(
+            "sys-io: 0x{:x}: new UDP socket 0x{:x} on {:?}",
+            conn.wait_handle().as_u64(),
+            u64::from(socket_id),
+            local_addr
+        );
+
+        sqe.handle = socket_id.into();
+        api_net::put_socket_addr(&mut sqe.payload, &local_addr);
+        sqe.status = moto_rt::E_OK;
+        sqe
+    }
+
+    fn udp_socket_from_msg(
+        &self,
+        conn_handle: SysHandle,
+        sqe: &io_channel::Msg,
+    ) -> Result<SocketId, ErrorCode> {
+        let socket_id: SocketId = sqe.handle.into();
+
+        // Validate that the socket belongs to the connection.
+        match self.conn_udp_sockets.get(&conn_handle) {
+            Some(socks) if socks.contains(&socket_id) => Ok(socket_id),
+            _ => Err(moto_rt::E_INVALID_ARGUMENT),
+        }
+    }
+
+    fn udp_socket_tx(
+        &mut self,
+        conn: &Rc<io_channel::ServerConnection>,
+        mut sqe: io_channel::Msg,
+    ) -> io_channel::Msg {
+        sqe.status = match self.do_udp_tx(conn, &sqe) {
+            Ok(()) => moto_rt::E_OK,
+            Err(err) => err,
+        };
+        sqe
+    }
+
+    fn do_udp_tx(
+        &mut self,
+        conn: &Rc<io_channel::ServerConnection>,
+        sqe: &io_channel::Msg,
+    ) -> Result<(), ErrorCode> {
+        // Get the page first, so that it is freed on errors.
+        let (page_idx, sz) = api_net::udp_datagram(sqe);
+        let page = conn.get_page(page_idx)?;
+        let socket_id = self.udp_socket_from_msg(conn.wait_handle(), sqe)?;
+        if sz > api_net::UDP_MAX_DATAGRAM_SIZE {
+            return Err(moto_rt::E_MSG_TOO_LARGE);
+        }
+        let dest = api_net::get_udp_peer_addr(&sqe.payload)?;
+        if dest.port() == 0 || dest.ip().is_unspecified() {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        }
+
+        let Some((device_idx, _)) = self.find_route(&dest.ip()) else {
+            return Err(moto_rt::E_NOT_FOUND);
+        };
+        let udp_socket = self.udp_sockets.get(&socket_id).unwrap();
+        // A socket bound to a specific IP sends only from its device.
+        let Some(handle) = udp_socket.handle_on(device_idx) else {
+            return Err(moto_rt::E_ADDR_NOT_AVAILABLE);
+        };
+        if sqe.flags & api_net::FLAG_UDP_TX_DONT_FRAG != 0 {
+            // smoltcp sets DF in IPv4 datagrams it does not fragment.
+            let ip_header_len = match dest {
+                SocketAddr::V4(_) => smoltcp::wire::IPV4_HEADER_LEN,
+                SocketAddr::V6(_) => smoltcp::wire::IPV6_HEADER_LEN,
+            };
+            let udp_header_len = smoltcp::wire::UDP_HEADER_LEN;
+            if ip_header_len + udp_header_len + sz > self.devices[device_idx].ip_mtu() {
+                return Err(moto_rt::E_MSG_TOO_LARGE);
+            }
+        }
+
+        let pid = udp_socket.pid;
+        let smol_socket = self.devices[device_idx]
+            .sockets
+            .get_mut::<smoltcp::socket::udp::Socket>(handle);
+        match smol_socket.send_slice(&page.bytes()[0..sz], (dest.ip(), dest.port())) {
+            Ok(()) => {
+                crate::runtime::io_usage::on_net_tx(pid, sz);
+                Ok(())
+            }
+            Err(smoltcp::socket::udp::SendError::BufferFull) => Err(moto_rt::E_BUFFER_FULL),
+            Err(smoltcp::socket::udp::SendError::Unaddressable) => Err(moto_rt::E_INVALID_ARGUMENT),
+        }
+    }
+
+    fn udp_socket_drop(
+        &mut self,
+        conn: &Rc<io_channel::ServerConnection>,
+        msg: io_channel::Msg,
+    ) -> Result<(), ()> {
+        let socket_id = self
+            .udp_socket_from_msg(conn.wait_handle(), &msg)
+            .map_err(|_| ())?;
+        self.conn_udp_sockets
+            .get_mut(&conn.wait_handle())
+            .unwrap()
+            .remove(&socket_id);
+        self.drop_udp_socket(socket_id);
+        Ok(())
+    }
+
+    fn drop_udp_socket(&mut self, socket_id: SocketId) {
+        let udp_socket = self.udp_sockets.remove(&socket_id).unwrap();
+        self.wakers.remove(&socket_id);
+        for (device_idx, handle) in &udp_socket.handles {
+            let device = &mut self.devices[*device_idx];
+            device.sockets.remove(*handle);
+            device.free_udp_port(udp_socket.local_addr.port());
+        }
+
+        log::debug!(
+            "{}:{} dropped UDP socket 0x{:x} ({} oversized datagrams dropped)",
+            file!(),
+            line!(),
+            u64::from(socket_id),
+            udp_socket.stats_rx_dropped
+        );
+    }
+
+    // Delivers received datagrams, one per IO page.
+    fn do_udp_rx(&mut self, socket_id: SocketId) {
+        let Some(udp_socket) = self.udp_sockets.get_mut(&socket_id) else {
+            return; // Dropped while on self.pending_udp_rx.
+        };
+        let waker = self.wakers.get(&socket_id).unwrap();
+
+        for (device_idx, handle) in &udp_socket.handles {
+            let smol_socket = self.devices[*device_idx]
+                .sockets
+                .get_mut::<smoltcp::socket::udp::Socket>(*handle);
+            // Registered wakers fire only once, so we need to re-register them every time.
+            smol_socket.register_recv_waker(waker);
+
+            while let Ok((bytes, _)) = smol_socket.peek() {
+                if bytes.len() > api_net::UDP_MAX_DATAGRAM_SIZE {
+                    let _ = smol_socket.recv();
+                    udp_socket.stats_rx_dropped += 1;
+                    continue;
+                }
+
+                let page = match udp_socket.conn.alloc_page(udp_socket.subchannel_mask) {
+                    Ok(page) => page,
+                    Err(err) => {
+                        assert_eq!(err, moto_rt::E_NOT_READY);
+                        if !self.pending_udp_rx.contains(&socket_id) {
+                            self.pending_udp_rx.push_back(socket_id);
+                        }
+                        return;
+                    }
+                };
+
+                let (bytes, meta) = smol_socket.recv().unwrap();
+                page.bytes_mut()[0..bytes.len()].copy_from_slice(bytes);
+                crate::runtime::io_usage::on_net_rx(udp_socket.pid, bytes.len());
+                let src = super::smoltcp_helpers::socket_addr_from_endpoint(meta.endpoint);
+                let mut msg = api_net::udp_socket_rx_msg(socket_id.into(), page, bytes.len(), &src);
+                msg.status = moto_rt::E_OK;
+
+                self.pending_completions.push_back(PendingCompletion {
+                    msg,
+                    endpoint_handle: udp_socket.conn.wait_handle(),
+                });
+            }
+        }
+    }
+
+    fn get_unused_tcp_socket(
+        &mut self,
+    ) -> Result<smoltcp::socket::tcp::Socket<'static>, ErrorCode> {
+        if let Some(socket) = self.tcp_socket_cache.pop() {
+            Ok(socket)
+        } else {
+            const RX_BUF_SZ: usize =
+                io_channel::PAGE_SIZE * (api_net::TCP_RX_MAX_INFLIGHT as usize);
+            let rx_buffer = smoltcp::socket::tcp::SocketBuffer::new(vec![0; RX_BUF_SZ]);
+            let tx_buffer = smoltcp::socket::tcp::SocketBuffer::new(vec![0; 16384 * 2]);
+
+            log::debug!("{}:{} new TCP socket", file!(), line!());
+            Ok(smoltcp::socket::tcp::Socket::new(rx_buffer, tx_buffer))
+        }
+    }
+
+    fn put_unused_tcp_socket(&mut self, socket: smoltcp::socket::tcp::Socket<'static>) {
+        debug_assert_eq!(socket.state(), smoltcp::socket::tcp::State::Closed);
+        // TODO: limit the size of the cache (i.e. drop socket if the cache is too large).
+        self.tcp_socket_cache.push(socket);
+    }
+
+    // Whether a listener accepts connections on @ip:@port, or on *:@port.
+    fn listener_holds_port(&self, ip: &IpAddr, port: u16) -> bool {
+        self.tcp_listeners.values().any(|listener| {
+            let addr = listener.socket_addr();
+            addr.port() == port && (addr.ip().is_unspecified() || addr.ip() == *ip)
+        })
+    }
+
+    // NOTE: no TCP Fast Open: smoltcp (0.11) neither sends data in SYN nor
+    // handles the TFO cookie option, so connects always do the full handshake.
+    fn tcp_stream_connect(
+        &mut self,
+        conn: &Rc<io_channel::ServerConnection>,
+        mut sqe: io_channel::Msg,
+    ) -> Option<io_channel::Msg> {
+        // The local address to bind to, if requested. The page is taken first,
+        // so that it is freed whatever happens below.
+        let bind_addr = match api_net::tcp_stream_connect_local_addr_page(&sqe) {
+            Some(page_idx) => {
+                let page = match conn.get_page(page_idx) {
+                    Ok(page) => page,
+                    Err(err) => {
+                        sqe.status = err;
+                        return Some(sqe);
+                    }
+                };
+                match api_net::get_socket_addr_from_page(&page) {
+                    Ok(addr) => Some(addr),
+                    Err(err) => {
+                        sqe.status = err;
+                        return Some(sqe);
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let remote_addr = match api_net::get_socket_addr(&sqe.payload) {
+            Ok(addr) => addr,
+            Err(err) => {
+                sqe.status = err.into();
+                return Some(sqe);
+            }
+        };
+
+        let timeout = api_net::tcp_stream_connect_timeout(&sqe);
+        if let Some(timo) = timeout {
+            if timo <= moto_rt::time::Instant::now() {
+                sqe.status = moto_rt::E_TIMED_OUT;
+                return Some(sqe);
+            }
+        };
+
+        #[cfg(debug_assertions)]
+        log::debug!(
+            "sys-io: 0x{:x}: tcp connect to {:?} from {:?}",
+            conn.wait_handle().as_u64(),
+            remote_addr,
+            bind_addr
+        );
+
+        let route = match bind_addr {
+            Some(addr) if !addr.ip().is_unspecified() => {
+                // Validate that the address belongs to a configured interface.
+                match self.ip_addresses.get(&addr.ip()) {
+                    Some(device_idx) => Some((*device_idx, addr.ip())),
+                    None => {
+                        sqe.status = moto_rt::E_ADDR_NOT_AVAILABLE;
+                        return Some(sqe);
+                    }
+                }
+            }
+            _ => self.find_route(&remote_addr.ip()),
+        };
+
+        let (device_idx, local_ip_addr) = if let Some(pair) = route {
+            pair
+        } else {
+            #[cfg(debug_assertions)]
+            log::debug!(
+                "sys-io: 0x{:x}: tcp connect to {:?}: route not found",
+                conn.wait_handle().as_u64(),
+                remote_addr
+            );
+
+            self.stats_connect_no_route += 1;
+            sqe.status = moto_rt::E_NOT_FOUND;
+            return Some(sqe);
+        };
+
+        let local_port = match bind_addr {
+            Some(addr) if addr.port() != 0 => {
+                // Listening sockets don't hold their ports in reserve_port().
+                if self.listener_holds_port(&local_ip_addr, addr.port())
+                    || !self.devices[device_idx].reserve_port(addr.port())
+                {
+                    sqe.status = moto_rt::E_ALREADY_IN_USE;
+                    return Some(sqe);
+                }
+                addr.port()
+            }
+            _ => match self.devices[device_idx].get_ephemeral_port(&local_ip_addr, &remote_addr) {
+                Some(port) => port,
+                None => {
+                    log::info!("get_ephemeral_port({:?}) failed", local_ip_addr);
+                    sqe.status = moto_rt::E_OUT_OF_MEMORY;
+                    return Some(sqe);
+                }
+            },
+        };
+
+        let mut moto_socket = match self.new_socket_for_device(device_idx, conn.clone()) {
+            Ok(s) => s,
+            Err(err) => {
+                sqe.status = err.into();
+                return Some(sqe);
+            }
+        };
+        moto_socket.subchannel_mask = sqe.payload.args_64()[0];
+
+        let conn_handle = conn.wait_handle();
+        if let Some(conn_sockets) = self.conn_tcp_sockets.get_mut(&conn_handle) {
+            conn_sockets.insert(moto_socket.id);
+        } else {
+            let mut conn_sockets = HashSet::new();
+            conn_sockets.insert(moto_socket.id);
+            self.conn_tcp_sockets.insert(conn_handle, conn_sockets);
+        }
+
+        let local_addr = SocketAddr::new(local_ip_addr, local_port);
+
+        moto_socket.connect_req = Some(sqe);
+        // Note: we don't generate the state change event because it is implied.
+        moto_socket.state = TcpState::Connecting;
+        moto_socket.ephemeral_port = Some(local_port);
+        moto_socket.local_addr_pinned = bind_addr.is_some_and(|addr| !addr.ip().is_unspecified());
+
+        let smol_handle = moto_socket.handle;
+        self.socket_ids.insert(moto_socket.id);
+        self.tcp_sockets.insert(moto_socket.id, moto_socket);
+
+        let smol_socket = self.devices[device_idx]
+            .sockets
+            .get_mut::<smoltcp::socket::tcp::Socket>(smol_handle);
+
+        if let Some(timeout) = timeout {
+            let now = moto_rt::time::Instant::now();
+            if timeout <= now {
+                // We check this upon receiving sqe; the thread got preempted or something.
+                // Just use an arbitrary small timeout.
+                smol_socket.set_timeout(Some(smoltcp::time::Duration::from_micros(10)));
             } else {
                 smol_socket.set_timeout(Some(smoltcp::time::Duration::from_micros(
                     timeout.duration_since(now).as_micros() as u64,
@@ -676,33 +1382,40 @@ impl NetSys {
         Ok(socket_id)
     }
 
-    // Note: does not return anything because TX is one-way, nobody is listening for TX responses.
-    fn tcp_stream_write(&mut self, conn: &Rc<io_channel::ServerConnection>, msg: io_channel::Msg) {
-        // Note: we need to get the page so that it is freed.
-        let page_idx = msg.payload.shared_pages()[0];
-        let page = if let Ok(page) = conn.get_page(page_idx) {
-            page
-        } else {
-            return;
+    // Note: TX is one-way, nobody is listening for TX responses, except for
+    // urgent writes (see CMD_TCP_STREAM_TX in process_sqe()).
+    fn tcp_stream_write(
+        &mut self,
+        conn: &Rc<io_channel::ServerConnection>,
+        msg: io_channel::Msg,
+    ) -> Result<(), ErrorCode> {
+        // Note: we need to get the pages so that they are freed.
+        let pages = match conn.get_pages(&msg, api_net::TCP_STREAM_TX_MAX_PAGES) {
+            Ok(pages) if !pages.is_empty() => pages,
+            _ => return Err(moto_rt::E_INVALID_ARGUMENT),
         };
         let socket_id = if let Ok(s) = self.tcp_socket_from_msg(conn.wait_handle(), &msg) {
             s
         } else {
-            return;
+            return Err(moto_rt::E_BAD_HANDLE);
         };
 
         // Validate that the socket belongs to the connection.
         if let Some(socks) = self.conn_tcp_sockets.get(&conn.wait_handle()) {
             if !socks.contains(&socket_id) {
-                return;
+                return Err(moto_rt::E_BAD_HANDLE);
             }
         } else {
-            return;
+            return Err(moto_rt::E_BAD_HANDLE);
         }
 
+        // All but the last page are full, and none is empty: each becomes a TxBuf.
         let sz = msg.payload.args_64()[1] as usize;
-        if sz > io_channel::PAGE_SIZE {
-            return;
+        if sz == 0
+            || sz > pages.len() * io_channel::PAGE_SIZE
+            || sz <= (pages.len() - 1) * io_channel::PAGE_SIZE
+        {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
         }
 
         let moto_socket = self.tcp_sockets.get_mut(&socket_id).unwrap();
@@ -716,17 +1429,43 @@ impl NetSys {
                 //     line!(),
                 //     u64::from(socket_id)
                 // );
-                return;
+                return Err(moto_rt::E_NOT_CONNECTED);
             }
             TcpState::_Max => panic!(),
         }
 
+        if msg.flags & api_net::FLAG_TCP_STREAM_TX_URGENT != 0 && sz > 0 {
+            // The byte is not sent (as a regular one) unless it can be marked urgent.
+            let device = &mut self.devices[moto_socket.device_idx];
+            let smol_socket = device
+                .sockets
+                .get::<smoltcp::socket::tcp::Socket>(moto_socket.handle);
+            let endpoints = (smol_socket.local_endpoint(), smol_socket.remote_endpoint());
+            let (Some(local), Some(remote)) = endpoints else {
+                return Err(moto_rt::E_NOT_CONNECTED);
+            };
+            let offset = (moto_socket.stats_tx_bytes + sz as u64 - 1) as u32;
+            device.tcp_urgent().set_tx_urgent((local, remote), offset)?;
+        }
         moto_socket.stats_tx_bytes += sz as u64;
-        moto_socket.tx_queue.push_back(TxBuf {
-            page,
-            len: sz,
-            consumed: 0,
-        });
+        crate::runtime::io_usage::on_net_tx(moto_socket.pid, sz);
+        if moto_socket.idle_timeout.is_some() {
+            moto_socket.last_activity = moto_rt::time::Instant::now();
+        }
+        if msg.flags & api_net::FLAG_TCP_STREAM_TX_NOTIFY != 0 {
+            moto_socket.tx_space_wanted = true;
+        }
+        // The pages are sent in slot order, as one write.
+        let mut remaining = sz;
+        for page in pages {
+            let len = remaining.min(io_channel::PAGE_SIZE);
+            remaining -= len;
+            moto_socket.tx_queue.push_back(TxBuf {
+                page,
+                len,
+                consumed: 0,
+            });
+        }
 
         // log::debug!(
         //     "socket 0x{:x} total TX bytes: {}",
@@ -734,6 +1473,7 @@ impl NetSys {
         //     moto_socket.stats_tx_bytes
         // );
         self.do_tcp_tx(socket_id);
+        Ok(())
     }
 
     fn tcp_stream_rx_ack(
@@ -814,6 +1554,72 @@ impl NetSys {
             return sqe;
         }
 
+        if options == api_net::TCP_OPTION_IDLE_TIMEOUT {
+            let timeout = api_net::idle_timeout_from_nanos(sqe.payload.args_64()[1]);
+            self.set_tcp_idle_timeout(socket_id, timeout);
+            sqe.status = moto_rt::E_OK;
+            return sqe;
+        }
+
+        if options == api_net::TCP_OPTION_RX_PAUSED {
+            let paused = match sqe.payload.args_64()[1] {
+                1 => true,
+                0 => false,
+                _ => {
+                    sqe.status = moto_rt::E_INVALID_ARGUMENT;
+                    return sqe;
+                }
+            };
+
+            if paused == moto_socket.rx_paused {
+                sqe.status = moto_rt::E_OK;
+                return sqe;
+            }
+
+            let device = &mut self.devices[moto_socket.device_idx];
+            let smol_socket = device
+                .sockets
+                .get::<smoltcp::socket::tcp::Socket>(moto_socket.handle);
+            // Not connected: nothing to advertise a window to.
+            let endpoints = match (smol_socket.local_endpoint(), smol_socket.remote_endpoint()) {
+                (Some(local), Some(remote)) => Some((local, remote)),
+                _ => None,
+            };
+
+            if paused {
+                if let Some(endpoints) = endpoints {
+                    if let Err(err) = device.tcp_rx_pauses().pause(endpoints) {
+                        sqe.status = err;
+                        return sqe;
+                    }
+                }
+                moto_socket.rx_paused = true;
+            } else {
+                moto_socket.rx_paused = false;
+                if let Some(update) = endpoints.and_then(|ep| device.tcp_rx_pauses().resume(ep)) {
+                    device.send_frame(&update);
+                }
+                // Idle time does not accrue while paused.
+                let idle_timeout = moto_socket.idle_timeout;
+                if idle_timeout.is_some() {
+                    self.set_tcp_idle_timeout(socket_id, idle_timeout);
+                }
+                // Deliver whatever has been buffered while paused.
+                self.do_tcp_rx(socket_id);
+            }
+            sqe.status = moto_rt::E_OK;
+            return sqe;
+        }
+
+        if options == api_net::TCP_OPTION_LINGER {
+            moto_socket.linger = match sqe.payload.args_64()[1] {
+                u64::MAX => None,
+                nanos => Some(core::time::Duration::from_nanos(nanos)),
+            };
+            sqe.status = moto_rt::E_OK;
+            return sqe;
+        }
+
         if options == api_net::TCP_OPTION_TTL {
             let ttl = sqe.payload.args_32()[2];
             if ttl == 0 || ttl > 255 {
@@ -940,13 +1746,101 @@ impl NetSys {
                 sqe.payload.args_32_mut()[0] = ttl;
                 sqe.status = moto_rt::E_OK;
             }
+            api_net::TCP_OPTION_RX_PAUSED => {
+                sqe.payload.args_64_mut()[0] = if moto_socket.rx_paused { 1 } else { 0 };
+                sqe.status = moto_rt::E_OK;
+            }
+            api_net::TCP_OPTION_URGENT_MARK => {
+                let device = &mut self.devices[moto_socket.device_idx];
+                let smol_socket = device
+                    .sockets
+                    .get::<smoltcp::socket::tcp::Socket>(moto_socket.handle);
+                let mark = match (smol_socket.local_endpoint(), smol_socket.remote_endpoint()) {
+                    (Some(local), Some(remote)) => device.tcp_urgent().rx_mark((local, remote)),
+                    _ => None,
+                };
+                // The mark is modulo 2^32, and is within 2^31 bytes of what
+                // has been delivered to the application.
+                sqe.payload.args_64_mut()[0] = match mark {
+                    Some(mark) => {
+                        let delivered = moto_socket.stats_rx_bytes;
+                        let delta = mark.wrapping_sub(delivered as u32) as i32;
+                        delivered.wrapping_add_signed(delta as i64)
+                    }
+                    None => u64::MAX,
+                };
+                sqe.status = moto_rt::E_OK;
+            }
+            api_net::TCP_OPTION_RX_RECEIVED => {
+                let smol_socket = self.devices[moto_socket.device_idx]
+                    .sockets
+                    .get::<smoltcp::socket::tcp::Socket>(moto_socket.handle);
+                // While paused, the bytes in smol_socket can't be read.
+                let buffered = if moto_socket.rx_paused {
+                    0
+                } else {
+                    smol_socket.recv_queue() as u64
+                };
+                sqe.payload.args_64_mut()[0] = moto_socket.stats_rx_bytes + buffered;
+                sqe.status = moto_rt::E_OK;
+            }
+            api_net::TCP_OPTION_PEER_CREDS => {
+                let smol_socket = self.devices[moto_socket.device_idx]
+                    .sockets
+                    .get::<smoltcp::socket::tcp::Socket>(moto_socket.handle);
+                let local = smol_socket.local_endpoint();
+                let remote = smol_socket.remote_endpoint();
+                match self.tcp_loopback_peer_creds(socket_id, local, remote) {
+                    Ok((pid, caps)) => {
+                        sqe.payload.args_64_mut()[0] = pid;
+                        sqe.payload.args_64_mut()[1] = caps;
+                        sqe.status = moto_rt::E_OK;
+                    }
+                    Err(err) => sqe.status = err,
+                }
+            }
             _ => {
                 log::debug!("Invalid option 0x{}", options);
                 sqe.status = moto_rt::E_INVALID_ARGUMENT;
             }
         }
 
-        sqe
+        sqe
+    }
+
+    // Finds the socket at the other end of a loopback connection and returns
+    // the (PID, capabilities) of its owner, as reported by the kernel for the
+    // owner's io_channel connection.
+    fn tcp_loopback_peer_creds(
+        &self,
+        socket_id: SocketId,
+        local: Option<smoltcp::wire::IpEndpoint>,
+        remote: Option<smoltcp::wire::IpEndpoint>,
+    ) -> Result<(u64, u64), ErrorCode> {
+        let (Some(local), Some(remote)) = (local, remote) else {
+            return Err(moto_rt::E_INVALID_ARGUMENT);
+        };
+        if !remote.addr.is_loopback() {
+            return Err(moto_rt::E_NOT_IMPLEMENTED);
+        }
+
+        for (peer_id, peer) in &self.tcp_sockets {
+            if *peer_id == socket_id || peer.orphaned {
+                continue;
+            }
+
+            let smol_socket = self.devices[peer.device_idx]
+                .sockets
+                .get::<smoltcp::socket::tcp::Socket>(peer.handle);
+            if smol_socket.local_endpoint() == Some(remote)
+                && smol_socket.remote_endpoint() == Some(local)
+            {
+                return moto_sys::SysObj::get_peer_credentials(peer.conn.wait_handle());
+            }
+        }
+
+        // The peer is gone.
+        Err(moto_rt::E_NOT_FOUND)
     }
 
     fn tcp_stream_close(
@@ -966,15 +1860,141 @@ impl NetSys {
             line!(),
             u64::from(socket_id)
         );
-        // While there can still be outgoing writes that need completion,
-        // we drop everything here: let the user-side worry about
-        // not dropping connections before writes are complete.
-        // TODO: implement SO_LINGER (Rust: TcpStream::set_linger()).
+        if let Some(timeout) = self.tcp_sockets.get(&socket_id).unwrap().linger {
+            if !timeout.is_zero() {
+                // Completed in on_lingering_tcp_socket_poll() or expire_lingering_tcp_sockets().
+                self.linger_tcp_socket(socket_id, sqe, timeout);
+                return None;
+            }
+        }
+
+        // Without SO_LINGER, or with a zero linger timeout, the bytes not yet
+        // sent are dropped, and the connection is reset.
         self.drop_tcp_socket(socket_id);
         sqe.status = moto_rt::E_OK;
         Some(sqe)
     }
 
+    fn linger_tcp_socket(
+        &mut self,
+        socket_id: SocketId,
+        sqe: io_channel::Msg,
+        timeout: core::time::Duration,
+    ) {
+        let moto_socket = self.tcp_sockets.get_mut(&socket_id).unwrap();
+        let smol_socket = self.devices[moto_socket.device_idx]
+            .sockets
+            .get::<smoltcp::socket::tcp::Socket>(moto_socket.handle);
+        let endpoints = smol_socket
+            .local_endpoint()
+            .zip(smol_socket.remote_endpoint());
+        // Linger timeouts are set by the application: don't overflow.
+        let deadline = moto_rt::time::Instant::now()
+            .checked_add_duration(&timeout)
+            .unwrap_or(moto_rt::time::Instant::infinite_future());
+        moto_socket.linger_close = Some((sqe, deadline, endpoints));
+        // The application is gone: don't read anything more from the socket.
+        moto_socket.rx_paused = true;
+        self.idle_tcp_sockets.remove(&socket_id);
+        moto_socket.idle_timeout = None;
+        self.lingering_tcp_sockets.insert(socket_id);
+
+        self.on_lingering_tcp_socket_poll(socket_id);
+    }
+
+    fn on_lingering_tcp_socket_poll(&mut self, socket_id: SocketId) {
+        self.do_tcp_tx(socket_id);
+
+        let moto_socket = self.tcp_sockets.get_mut(&socket_id).unwrap();
+        let device_idx = moto_socket.device_idx;
+        let smol_socket = self.devices[device_idx]
+            .sockets
+            .get_mut::<smoltcp::socket::tcp::Socket>(moto_socket.handle);
+
+        if moto_socket.tx_queue.is_empty() {
+            // The FIN goes out after the bytes already in the socket (no-op if sent).
+            smol_socket.close();
+        }
+
+        if moto_socket.tx_queue.is_empty() && smol_socket.send_queue() == 0 {
+            // Everything has been acked.
+            self.finish_tcp_linger(socket_id, moto_rt::E_OK);
+        } else if smol_socket.state() == smoltcp::socket::tcp::State::Closed {
+            // Reset by the peer, or timed out: queued bytes did not make it.
+            let endpoints = moto_socket.linger_close.as_ref().unwrap().2;
+            let peer_reset = endpoints.is_some_and(|endpoints| {
+                self.devices[device_idx].tcp_urgent().peer_reset(&endpoints)
+            });
+            let status = if peer_reset {
+                moto_rt::E_CONNECTION_RESET
+            } else {
+                moto_rt::E_TIMED_OUT
+            };
+            self.finish_tcp_linger(socket_id, status);
+        } else {
+            // Kick the TX.
+            while self.devices[device_idx].poll() {}
+        }
+    }
+
+    fn finish_tcp_linger(&mut self, socket_id: SocketId, status: ErrorCode) {
+        self.lingering_tcp_sockets.remove(&socket_id);
+        let moto_socket = self.tcp_sockets.get_mut(&socket_id).unwrap();
+        let (mut sqe, _, _) = moto_socket.linger_close.take().unwrap();
+        sqe.status = status;
+        self.pending_completions.push_back(PendingCompletion {
+            msg: sqe,
+            endpoint_handle: moto_socket.conn.wait_handle(),
+        });
+
+        if status != moto_rt::E_OK {
+            self.drop_tcp_socket(socket_id); // Send a RST.
+            return;
+        }
+
+        // Let the close handshake complete in the background, as with
+        // orphaned sockets (see close_orphaned_tcp_socket()).
+        moto_socket.state = TcpState::Closed;
+        moto_socket.orphaned = true;
+        let smol_socket = self.devices[moto_socket.device_idx]
+            .sockets
+            .get_mut::<smoltcp::socket::tcp::Socket>(moto_socket.handle);
+        smol_socket.set_timeout(Some(smoltcp::time::Duration::from_millis(5_000)));
+        match smol_socket.state() {
+            smoltcp::socket::tcp::State::Closed | smoltcp::socket::tcp::State::TimeWait => {
+                self.drop_tcp_socket(socket_id);
+            }
+            _ => {}
+        }
+    }
+
+    fn expire_lingering_tcp_sockets(&mut self) {
+        if self.lingering_tcp_sockets.is_empty() {
+            return;
+        }
+
+        let now = moto_rt::time::Instant::now();
+        let expired: Vec<SocketId> = self
+            .lingering_tcp_sockets
+            .iter()
+            .filter(|socket_id| {
+                let moto_socket = self.tcp_sockets.get(socket_id).unwrap();
+                moto_socket.linger_close.as_ref().unwrap().1 <= now
+            })
+            .copied()
+            .collect();
+
+        for socket_id in expired {
+            log::debug!(
+                "{}:{} linger timeout for socket 0x{:x}",
+                file!(),
+                line!(),
+                u64::from(socket_id)
+            );
+            self.finish_tcp_linger(socket_id, moto_rt::E_TIMED_OUT);
+        }
+    }
+
     fn next_id(&mut self) -> u64 {
         let res = self.next_id;
         self.next_id += 1;
@@ -1023,6 +2043,8 @@ impl NetSys {
 
     // Returns 'true' if the socket can do tx/rx.
     fn on_tcp_listener_connected(&mut self, socket_id: SocketId) -> bool {
+        self.balance_reuse_port_connection(socket_id);
+
         let moto_socket = self.tcp_sockets.get_mut(&socket_id).unwrap();
         let device_idx = moto_socket.device_idx;
         let smol_socket = self.devices[device_idx]
@@ -1042,8 +2064,10 @@ impl NetSys {
 
         let listener_id = *moto_socket.listener_id.as_ref().unwrap();
         let listener = self.tcp_listeners.get_mut(&listener_id).unwrap();
+        let idle_timeout = listener.idle_timeout();
         let may_do_io = if let Some((mut msg, conn)) = listener.get_pending_accept() {
             assert!(listener.remove_listening_socket(socket_id));
+            listener.on_accepted();
             moto_socket.state = TcpState::ReadWrite;
             moto_socket.listener_id = None;
             let endpoint_handle = conn.wait_handle();
@@ -1069,6 +2093,16 @@ impl NetSys {
             // Note: we don't generate the state change event because accept() is handled explicitly.
             moto_socket.state = TcpState::PendingAccept;
             listener.add_pending_socket(moto_socket.id, remote_addr);
+
+            // For pollers (see moto_rt::net::poll_add()).
+            let mut msg = io_channel::Msg::new();
+            msg.command = api_net::EVT_TCP_LISTENER_READY;
+            msg.handle = listener_id.into();
+            msg.status = moto_rt::E_OK;
+            self.pending_completions.push_back(PendingCompletion {
+                msg,
+                endpoint_handle: listener.conn_handle(),
+            });
             false
         };
 
@@ -1082,9 +2116,73 @@ impl NetSys {
             may_do_io
         );
 
+        if may_do_io && idle_timeout.is_some() {
+            self.set_tcp_idle_timeout(socket_id, idle_timeout);
+        }
+
         may_do_io
     }
 
+    // Listeners sharing an address (FLAG_TCP_LISTENER_REUSE_PORT) each have their
+    // own listening sockets, but smoltcp hands an incoming connection to whichever
+    // matching socket it finds first; so a new connection is moved to the next
+    // listener sharing the address, round-robin.
+    fn balance_reuse_port_connection(&mut self, socket_id: SocketId) {
+        let moto_socket = self.tcp_sockets.get(&socket_id).unwrap();
+        let listener_id = *moto_socket.listener_id.as_ref().unwrap();
+        let listener = self.tcp_listeners.get(&listener_id).unwrap();
+        if !listener.reuse_port() {
+            return;
+        }
+
+        let socket_addr = *listener.socket_addr();
+        let mut group: Vec<TcpListenerId> = self
+            .tcp_listeners
+            .iter()
+            .filter(|(_, other)| {
+                other.reuse_port() && !other.is_draining() && *other.socket_addr() == socket_addr
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        if group.len() < 2 {
+            return;
+        }
+        group.sort_by_key(|id| u64::from(*id));
+        let target_id = group[(self.next_reuse_port_pick % (group.len() as u64)) as usize];
+        self.next_reuse_port_pick += 1;
+        if target_id == listener_id {
+            return;
+        }
+
+        assert!(self
+            .tcp_listeners
+            .get_mut(&listener_id)
+            .unwrap()
+            .remove_listening_socket(socket_id));
+        let target = self.tcp_listeners.get_mut(&target_id).unwrap();
+        target.add_listening_socket(socket_id);
+        let conn = target.conn().clone();
+
+        // Until accepted, the socket belongs to the connection of its listener.
+        let moto_socket = self.tcp_sockets.get_mut(&socket_id).unwrap();
+        moto_socket.listener_id = Some(target_id);
+        if let Some(conn_sockets) = self
+            .conn_tcp_sockets
+            .get_mut(&moto_socket.conn.wait_handle())
+        {
+            conn_sockets.remove(&socket_id);
+        }
+        let conn_handle = conn.wait_handle();
+        moto_socket.conn = conn;
+        if let Some(conn_sockets) = self.conn_tcp_sockets.get_mut(&conn_handle) {
+            conn_sockets.insert(socket_id);
+        } else {
+            let mut conn_sockets = HashSet::new();
+            conn_sockets.insert(socket_id);
+            self.conn_tcp_sockets.insert(conn_handle, conn_sockets);
+        }
+    }
+
     fn spawn_replacement_listener(&mut self, socket_id: SocketId) {
         let moto_socket = self.tcp_sockets.get_mut(&socket_id).unwrap();
         assert_eq!(moto_socket.state, TcpState::Listening);
@@ -1175,6 +2273,21 @@ impl NetSys {
         smol_socket.register_recv_waker(&waker);
         smol_socket.register_send_waker(&waker);
 
+        if moto_socket.linger_close.is_some() {
+            self.on_lingering_tcp_socket_poll(socket_id);
+            return;
+        }
+
+        if moto_socket.orphaned {
+            match smol_socket.state() {
+                smoltcp::socket::tcp::State::Closed | smoltcp::socket::tcp::State::TimeWait => {
+                    self.drop_tcp_socket(socket_id);
+                }
+                _ => {}
+            }
+            return;
+        }
+
         let may_send = smol_socket.may_send();
         let can_recv = smol_socket.can_recv() || !moto_socket.rx_closed_notified;
         let can_send = smol_socket.can_send();
@@ -1402,7 +2515,11 @@ impl NetSys {
             } else {
                 break;
             };
-            self.on_tcp_socket_poll(socket_id);
+            if self.udp_sockets.contains_key(&socket_id) {
+                self.do_udp_rx(socket_id);
+            } else {
+                self.on_tcp_socket_poll(socket_id);
+            }
         }
         assert!(self.woken_sockets.borrow().is_empty());
     }
@@ -1413,7 +2530,7 @@ impl NetSys {
         msg.command = api_net::EVT_TCP_STREAM_STATE_CHANGED;
         msg.handle = moto_socket.id.into();
         msg.payload.args_32_mut()[0] = moto_socket.state.into();
-        msg.status = moto_rt::E_OK;
+        msg.status = moto_socket.close_status;
 
         self.pending_completions.push_back(PendingCompletion {
             msg,
@@ -1438,115 +2555,402 @@ impl NetSys {
             .sockets
             .get_mut::<smoltcp::socket::tcp::Socket>(moto_socket.handle);
 
-        if moto_socket.rx_ack == u64::MAX
-            || (moto_socket.rx_seq > (moto_socket.rx_ack + api_net::TCP_RX_MAX_INFLIGHT))
-        {
-            #[cfg(debug_assertions)]
-            if moto_socket.rx_ack != u64::MAX {
+        if moto_socket.rx_ack == u64::MAX
+            || (moto_socket.rx_seq > (moto_socket.rx_ack + api_net::TCP_RX_MAX_INFLIGHT))
+        {
+            #[cfg(debug_assertions)]
+            if moto_socket.rx_ack != u64::MAX {
+                log::debug!(
+                    "{}:{} TCP RX: stuck seq: {} ack: {} state: {:?}",
+                    file!(),
+                    line!(),
+                    moto_socket.rx_seq,
+                    moto_socket.rx_ack,
+                    moto_socket.state
+                );
+            }
+            return;
+        }
+
+        if moto_socket.rx_paused {
+            // The bytes stay in smol_socket: its receive window shrinks as it fills up.
+            return;
+        }
+
+        // We are attempting to deliver any incoming bytes even if the peer has closed the connection.
+        while smol_socket.recv_queue() > 0 {
+            let page = match moto_socket.conn.alloc_page(moto_socket.subchannel_mask) {
+                Ok(page) => page,
+                Err(err) => {
+                    assert_eq!(err, moto_rt::E_NOT_READY);
+                    self.pending_tcp_rx.push_back(socket_id);
+                    #[cfg(debug_assertions)]
+                    log::debug!(
+                        "{}:{} do_tcp_rx: alloc error for socket 0x{:x}",
+                        file!(),
+                        line!(),
+                        u64::from(socket_id)
+                    );
+                    return;
+                }
+            };
+            let mut rx_buf = RxBuf::new(page);
+            let mut receive_closure = |bytes: &mut [u8]| {
+                let len = bytes.len().min(io_channel::PAGE_SIZE - rx_buf.consumed);
+                unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        bytes.as_ptr(),
+                        rx_buf.bytes_mut().as_mut_ptr(),
+                        len,
+                    );
+                }
+
+                rx_buf.consume(len);
+                (len, ())
+            };
+
+            smol_socket.recv(&mut receive_closure).unwrap();
+
+            // #[cfg(debug_assertions)]
+            // log::debug!(
+            //     "{}:{} TcpRx event: seq {} {} bytes",
+            //     file!(),
+            //     line!(),
+            //     moto_socket.rx_seq,
+            //     rx_buf.consumed
+            // );
+
+            moto_socket.rx_seq += 1;
+            moto_socket.stats_rx_bytes += rx_buf.consumed as u64;
+            crate::runtime::io_usage::on_net_rx(moto_socket.pid, rx_buf.consumed);
+            if moto_socket.idle_timeout.is_some() {
+                moto_socket.last_activity = moto_rt::time::Instant::now();
+            }
+            self.pending_completions.push_back(Self::rx_buf_to_pc(
+                socket_id,
+                moto_socket.conn.wait_handle(),
+                rx_buf,
+                moto_socket.rx_seq,
+            ));
+
+            if moto_socket.rx_seq > (moto_socket.rx_ack + api_net::TCP_RX_MAX_INFLIGHT) {
+                break;
+            }
+        }
+
+        // log::debug!(
+        //     "socket 0x{:x} total RX bytes: {}",
+        //     u64::from(socket_id),
+        //     moto_socket.stats_rx_bytes
+        // );
+
+        // While the process knows about the socket state, it does not know that there are
+        // no cached RX packets. The code below delivers a zero-sized RX packet to that end.
+        if smol_socket.recv_queue() == 0
+            && (moto_socket.state == TcpState::WriteOnly || moto_socket.state == TcpState::Closed)
+        {
+            Self::notify_rx_closed(moto_socket, &mut self.pending_completions);
+        }
+    }
+
+    fn notify_rx_closed(
+        moto_socket: &mut MotoSocket,
+        pending_completions: &mut VecDeque<PendingCompletion>,
+    ) {
+        if moto_socket.rx_closed_notified {
+            return;
+        }
+
+        moto_socket.rx_seq += 1;
+
+        let mut msg = io_channel::Msg::new();
+        msg.command = api_net::CMD_TCP_STREAM_RX;
+        msg.handle = moto_socket.id.into();
+        msg.payload.shared_pages_mut()[0] = u16::MAX;
+        msg.payload.args_64_mut()[1] = 0;
+        msg.payload.args_64_mut()[2] = moto_socket.rx_seq;
+        msg.status = moto_socket.close_status;
+
+        pending_completions.push_back(PendingCompletion {
+            msg,
+            endpoint_handle: moto_socket.conn.wait_handle(),
+        });
+        moto_socket.rx_closed_notified = true;
+        log::debug!(
+            "{}:{} RX Closed for socket 0x{:x}",
+            file!(),
+            line!(),
+            u64::from(moto_socket.id)
+        );
+    }
+
+    fn set_tcp_idle_timeout(&mut self, socket_id: SocketId, timeout: Option<core::time::Duration>) {
+        let moto_socket = self.tcp_sockets.get_mut(&socket_id).unwrap();
+        moto_socket.idle_timeout = timeout;
+        moto_socket.last_activity = moto_rt::time::Instant::now();
+
+        if let Some(timeout) = timeout {
+            self.idle_tcp_sockets.insert(socket_id);
+            let deadline = moto_socket.last_activity + timeout;
+            if self.next_idle_check.map_or(true, |next| deadline < next) {
+                self.next_idle_check = Some(deadline);
+            }
+        } else {
+            self.idle_tcp_sockets.remove(&socket_id);
+        }
+    }
+
+    fn reap_idle_tcp_sockets(&mut self) {
+        let now = moto_rt::time::Instant::now();
+        match self.next_idle_check {
+            Some(next) if next <= now => {}
+            _ => return,
+        }
+
+        self.next_idle_check = None;
+        let mut idle_sockets = Vec::new();
+        for socket_id in &self.idle_tcp_sockets {
+            let moto_socket = self.tcp_sockets.get(socket_id).unwrap();
+            if moto_socket.rx_paused {
+                continue; // Rescheduled on resume.
+            }
+            let deadline = moto_socket.last_activity + moto_socket.idle_timeout.unwrap();
+            if deadline <= now {
+                idle_sockets.push(*socket_id);
+            } else if self.next_idle_check.map_or(true, |next| deadline < next) {
+                self.next_idle_check = Some(deadline);
+            }
+        }
+
+        for socket_id in idle_sockets {
+            self.reap_idle_tcp_socket(socket_id);
+        }
+    }
+
+    // Unlike keepalive, which closes connections to unresponsive peers, this
+    // closes connections that are not used, even if the peer is alive.
+    fn reap_idle_tcp_socket(&mut self, socket_id: SocketId) {
+        self.idle_tcp_sockets.remove(&socket_id);
+        let moto_socket = self.tcp_sockets.get_mut(&socket_id).unwrap();
+        moto_socket.idle_timeout = None;
+        match moto_socket.state {
+            TcpState::ReadWrite | TcpState::ReadOnly | TcpState::WriteOnly => {}
+            _ => return, // Already closed: the application just hasn't dropped it yet.
+        }
+
+        log::debug!(
+            "{}:{} reaping idle socket 0x{:x}",
+            file!(),
+            line!(),
+            u64::from(socket_id)
+        );
+
+        let device_idx = moto_socket.device_idx;
+        self.devices[device_idx]
+            .sockets
+            .get_mut::<smoltcp::socket::tcp::Socket>(moto_socket.handle)
+            .abort();
+        moto_socket.state = TcpState::Closed;
+        moto_socket.close_status = moto_rt::E_TIMED_OUT;
+        self.stats_tcp_idle_reaped += 1;
+
+        self.cancel_tcp_tx(socket_id);
+        self.on_socket_state_changed(socket_id);
+        let moto_socket = self.tcp_sockets.get_mut(&socket_id).unwrap();
+        Self::notify_rx_closed(moto_socket, &mut self.pending_completions);
+
+        // Send the RST.
+        while self.devices[device_idx].poll() {}
+    }
+
+    // Connection migration (see NetConfig::migrate_connections): when a device's
+    // link goes down, its connections not pinned to a local address move to
+    // another device that routes to the peer. A connection keeps its local
+    // address, which the new device adopts, so it survives if the network
+    // delivers packets for that address via the new device. Once the original
+    // device's link returns, its connections move back, and the new device
+    // drops the adopted address (also dropped when its last connection closes).
+    fn check_links(&mut self) {
+        if !self.config.migrate_connections || self.devices.len() < 2 {
+            return;
+        }
+
+        let now = moto_rt::time::Instant::now();
+        if self.next_link_check.is_some_and(|next| next > now) {
+            return;
+        }
+        self.next_link_check = Some(now + LINK_CHECK_INTERVAL);
+
+        for device_idx in 0..self.devices.len() {
+            let link_up = self.devices[device_idx].link_up();
+            if link_up == self.links_up[device_idx] {
+                continue;
+            }
+            self.links_up[device_idx] = link_up;
+            log::info!(
+                "sys-io: net device {} link is {}.",
+                self.devices[device_idx].name(),
+                if link_up { "up" } else { "down" }
+            );
+
+            if link_up {
+                self.return_tcp_sockets_to(device_idx);
+            } else {
+                self.migrate_tcp_sockets_from(device_idx);
+            }
+        }
+    }
+
+    fn migrate_tcp_sockets_from(&mut self, device_idx: usize) {
+        let socket_ids: Vec<SocketId> = self
+            .tcp_sockets
+            .values()
+            .filter(|moto_socket| {
+                moto_socket.device_idx == device_idx
+                    && !moto_socket.local_addr_pinned
+                    && !matches!(moto_socket.state, TcpState::Closed | TcpState::Listening)
+            })
+            .map(|moto_socket| moto_socket.id)
+            .collect();
+
+        for socket_id in socket_ids {
+            if !self.migrate_tcp_socket(socket_id) {
                 log::debug!(
-                    "{}:{} TCP RX: stuck seq: {} ack: {} state: {:?}",
+                    "{}:{} TCP socket 0x{:x} stays on device #{}",
                     file!(),
                     line!(),
-                    moto_socket.rx_seq,
-                    moto_socket.rx_ack,
-                    moto_socket.state
+                    u64::from(socket_id),
+                    device_idx
                 );
             }
-            return;
         }
+    }
 
-        // We are attempting to deliver any incoming bytes even if the peer has closed the connection.
-        while smol_socket.recv_queue() > 0 {
-            let page = match moto_socket.conn.alloc_page(moto_socket.subchannel_mask) {
-                Ok(page) => page,
-                Err(err) => {
-                    assert_eq!(err, moto_rt::E_NOT_READY);
-                    self.pending_tcp_rx.push_back(socket_id);
-                    #[cfg(debug_assertions)]
-                    log::debug!(
-                        "{}:{} do_tcp_rx: alloc error for socket 0x{:x}",
-                        file!(),
-                        line!(),
-                        u64::from(socket_id)
-                    );
-                    return;
-                }
-            };
-            let mut rx_buf = RxBuf::new(page);
-            let mut receive_closure = |bytes: &mut [u8]| {
-                let len = bytes.len().min(io_channel::PAGE_SIZE - rx_buf.consumed);
-                unsafe {
-                    core::ptr::copy_nonoverlapping(
-                        bytes.as_ptr(),
-                        rx_buf.bytes_mut().as_mut_ptr(),
-                        len,
-                    );
-                }
+    // Moves the sockets that migrated away from the device back to it, so that
+    // the addresses adopted for them by other devices are released.
+    fn return_tcp_sockets_to(&mut self, device_idx: usize) {
+        let socket_ids: Vec<SocketId> = self
+            .tcp_sockets
+            .values()
+            .filter(|moto_socket| moto_socket.home_device_idx == Some(device_idx))
+            .map(|moto_socket| moto_socket.id)
+            .collect();
+
+        for socket_id in socket_ids {
+            if !self.move_tcp_socket(socket_id, device_idx) {
+                log::debug!(
+                    "{}:{} TCP socket 0x{:x} can't return to device #{}",
+                    file!(),
+                    line!(),
+                    u64::from(socket_id),
+                    device_idx
+                );
+            }
+        }
+    }
 
-                rx_buf.consume(len);
-                (len, ())
-            };
+    // Moves the socket to another device with a route to the peer, if there is one.
+    fn migrate_tcp_socket(&mut self, socket_id: SocketId) -> bool {
+        let moto_socket = self.tcp_sockets.get(&socket_id).unwrap();
+        let old_idx = moto_socket.device_idx;
 
-            smol_socket.recv(&mut receive_closure).unwrap();
+        let smol_socket = self.devices[old_idx]
+            .sockets
+            .get::<smoltcp::socket::tcp::Socket>(moto_socket.handle);
+        let Some(remote) = smol_socket.remote_endpoint() else {
+            return false;
+        };
+        let remote_addr = super::smoltcp_helpers::socket_addr_from_endpoint(remote);
+
+        let Some(new_idx) = (0..self.devices.len()).find(|idx| {
+            *idx != old_idx
+                && self.links_up[*idx]
+                && self.devices[*idx].dev_cfg().routes_to(&remote_addr.ip())
+        }) else {
+            return false;
+        };
 
-            // #[cfg(debug_assertions)]
-            // log::debug!(
-            //     "{}:{} TcpRx event: seq {} {} bytes",
-            //     file!(),
-            //     line!(),
-            //     moto_socket.rx_seq,
-            //     rx_buf.consumed
-            // );
+        self.move_tcp_socket(socket_id, new_idx)
+    }
 
-            moto_socket.rx_seq += 1;
-            moto_socket.stats_rx_bytes += rx_buf.consumed as u64;
-            self.pending_completions.push_back(Self::rx_buf_to_pc(
-                socket_id,
-                moto_socket.conn.wait_handle(),
-                rx_buf,
-                moto_socket.rx_seq,
-            ));
+    fn move_tcp_socket(&mut self, socket_id: SocketId, new_idx: usize) -> bool {
+        let moto_socket = self.tcp_sockets.get(&socket_id).unwrap();
+        let old_idx = moto_socket.device_idx;
+        let handle = moto_socket.handle;
+        let ephemeral_port = moto_socket.ephemeral_port;
 
-            if moto_socket.rx_seq > (moto_socket.rx_ack + api_net::TCP_RX_MAX_INFLIGHT) {
-                break;
+        let smol_socket = self.devices[old_idx]
+            .sockets
+            .get::<smoltcp::socket::tcp::Socket>(handle);
+        let (Some(local), Some(remote)) =
+            (smol_socket.local_endpoint(), smol_socket.remote_endpoint())
+        else {
+            return false;
+        };
+        let local_addr = super::smoltcp_helpers::socket_addr_from_endpoint(local);
+        let remote_addr = super::smoltcp_helpers::socket_addr_from_endpoint(remote);
+
+        if let Some(port) = ephemeral_port {
+            if !self.devices[new_idx].reserve_port(port) {
+                return false;
             }
         }
+        let Ok(adopted) = self.devices[new_idx].adopt_ip_addr(local_addr.ip()) else {
+            if let Some(port) = ephemeral_port {
+                self.devices[new_idx].free_ephemeral_port(port);
+            }
+            return false;
+        };
 
-        // log::debug!(
-        //     "socket 0x{:x} total RX bytes: {}",
-        //     u64::from(socket_id),
-        //     moto_socket.stats_rx_bytes
-        // );
-
-        // While the process knows about the socket state, it does not know that there are
-        // no cached RX packets. The code below delivers a zero-sized RX packet to that end.
-        if smol_socket.recv_queue() == 0
-            && (moto_socket.state == TcpState::WriteOnly || moto_socket.state == TcpState::Closed)
+        let smol_socket = match self.devices[old_idx].sockets.remove(handle) {
+            smoltcp::socket::Socket::Tcp(s) => s,
+            _ => panic!(),
+        };
+        let new_handle = self.devices[new_idx].sockets.add(smol_socket);
+        if let Some(urgent) = self.devices[old_idx].tcp_urgent().take((local, remote)) {
+            self.devices[new_idx]
+                .tcp_urgent()
+                .adopt((local, remote), urgent);
+        }
+        if self.devices[old_idx]
+            .tcp_rx_pauses()
+            .take((local, remote))
+            .is_some()
         {
-            if !moto_socket.rx_closed_notified {
-                moto_socket.rx_seq += 1;
-
-                let mut msg = io_channel::Msg::new();
-                msg.command = api_net::CMD_TCP_STREAM_RX;
-                msg.handle = moto_socket.id.into();
-                msg.payload.shared_pages_mut()[0] = u16::MAX;
-                msg.payload.args_64_mut()[1] = 0;
-                msg.payload.args_64_mut()[2] = moto_socket.rx_seq;
-                msg.status = moto_rt::E_OK;
+            // The window update saved on the old device has its MAC addresses.
+            self.devices[new_idx]
+                .tcp_rx_pauses()
+                .adopt((local, remote), None);
+        }
+        if let Some(port) = ephemeral_port {
+            self.devices[old_idx].free_ephemeral_port(port);
+        }
 
-                self.pending_completions.push_back(PendingCompletion {
-                    msg,
-                    endpoint_handle: moto_socket.conn.wait_handle(),
-                });
-                moto_socket.rx_closed_notified = true;
-                log::debug!(
-                    "{}:{} RX Closed for socket 0x{:x}",
-                    file!(),
-                    line!(),
-                    u64::from(socket_id)
-                );
-            }
+        let moto_socket = self.tcp_sockets.get_mut(&socket_id).unwrap();
+        if let Some(addr) = moto_socket.adopted_ip_addr.take() {
+            self.devices[old_idx].release_ip_addr(addr);
+        }
+        if adopted {
+            moto_socket.adopted_ip_addr = Some(local_addr.ip());
         }
+        moto_socket.home_device_idx = match moto_socket.home_device_idx {
+            Some(home) if home == new_idx => None,
+            Some(home) => Some(home),
+            None => Some(old_idx),
+        };
+        moto_socket.handle = new_handle;
+        moto_socket.device_idx = new_idx;
+        moto_socket.stats_migrations += 1;
+        self.stats_tcp_migrated += 1;
+
+        log::info!(
+            "sys-io: TCP connection {:?} -> {:?} moved from {} to {}.",
+            local_addr,
+            remote_addr,
+            self.devices[old_idx].name(),
+            self.devices[new_idx].name()
+        );
+        true
     }
 
     fn do_tcp_tx(&mut self, socket_id: SocketId) {
@@ -1555,6 +2959,7 @@ impl NetSys {
             .sockets
             .get_mut::<smoltcp::socket::tcp::Socket>(moto_socket.handle);
 
+        let mut freed_pages = false;
         while smol_socket.can_send() {
             let mut tx_buf = if let Some(x) = moto_socket.tx_queue.pop_front() {
                 x
@@ -1568,6 +2973,7 @@ impl NetSys {
                     if tx_buf.is_consumed() {
                         // Client writes are completed in tcp_stream_write,
                         // actual socket writes happen later/asynchronously.
+                        freed_pages = true;
                         continue;
                     } else {
                         // moto_socket.
@@ -1578,6 +2984,19 @@ impl NetSys {
                 Err(_err) => todo!(),
             }
         }
+
+        // Sent after the pages are freed (dropped above).
+        if freed_pages && moto_socket.tx_space_wanted {
+            moto_socket.tx_space_wanted = !moto_socket.tx_queue.is_empty();
+            let mut msg = io_channel::Msg::new();
+            msg.command = api_net::EVT_TCP_STREAM_TX_SPACE;
+            msg.handle = moto_socket.id.into();
+            msg.status = moto_rt::E_OK;
+            self.pending_completions.push_back(PendingCompletion {
+                msg,
+                endpoint_handle: moto_socket.conn.wait_handle(),
+            });
+        }
     }
 
     fn cancel_tcp_tx(&mut self, socket_id: SocketId) {
@@ -1594,6 +3013,98 @@ impl NetSys {
         // TODO: Linux keeps sending buffered packets after socket close. Should we do the same?
         moto_socket.tx_queue.clear();
     }
+
+    fn get_net_stats(&mut self, msg: &crate::runtime::internal_queue::Msg) {
+        let payload = msg
+            .payload
+            .clone()
+            .downcast::<crate::runtime::io_stats::GetNetStatsPayload>()
+            .unwrap();
+
+        let mut stats = moto_sys_io::stats::NetStatsV1::default();
+        stats.tcp_idle_reaped = self.stats_tcp_idle_reaped;
+        stats.tcp_migrated = self.stats_tcp_migrated;
+        payload.results.store(stats);
+    }
+
+    fn get_net_dev_stats(&mut self, msg: &crate::runtime::internal_queue::Msg) {
+        let payload = msg
+            .payload
+            .clone()
+            .downcast::<crate::runtime::io_stats::GetNetDevStatsPayload>()
+            .unwrap();
+
+        let results = self
+            .devices
+            .iter()
+            .take(moto_sys_io::stats::MAX_NET_DEV_STATS)
+            .map(|dev| dev.stats())
+            .collect();
+        payload.results.store(results);
+        payload.connect_no_route.store(self.stats_connect_no_route);
+    }
+
+    fn get_tcp_stats(&mut self, msg: &crate::runtime::internal_queue::Msg) {
+        let num_results = moto_sys_io::stats::MAX_TCP_SOCKET_STATS.min(self.socket_ids.len());
+
+        let payload = msg
+            .payload
+            .clone()
+            .downcast::<crate::runtime::io_stats::GetTcpStatsPayload>()
+            .unwrap();
+
+        let start_id = SocketId::from(payload.start_id);
+        let mut results = Vec::new();
+
+        for &socket_id in self.socket_ids.range(start_id..) {
+            let moto_socket = self.tcp_sockets.get(&socket_id).unwrap();
+            if payload
+                .owner_pid
+                .is_some_and(|owner_pid| owner_pid != moto_socket.pid)
+            {
+                continue;
+            }
+            let device_idx = moto_socket.device_idx;
+            let smol_socket = self.devices[device_idx]
+                .sockets
+                .get_mut::<smoltcp::socket::tcp::Socket>(moto_socket.handle);
+
+            let mut stats = moto_sys_io::stats::TcpSocketStatsV1::default();
+            stats.id = moto_socket.id.into();
+            stats.device_id = device_idx as u64;
+            stats.pid = moto_socket.pid;
+
+            let local_addr = if let Some(e) = smol_socket.local_endpoint() {
+                Some(super::smoltcp_helpers::socket_addr_from_endpoint(e))
+            } else {
+                moto_socket.listening_on.clone()
+            };
+            let remote_addr = smol_socket
+                .remote_endpoint()
+                .map(|e| super::smoltcp_helpers::socket_addr_from_endpoint(e));
+
+            if let Some(addr) = local_addr {
+                stats.local_port = addr.port();
+                stats.local_addr = super::smoltcp_helpers::addr_to_octets(addr.ip());
+            }
+
+            if let Some(addr) = remote_addr {
+                stats.remote_port = addr.port();
+                stats.remote_addr = super::smoltcp_helpers::addr_to_octets(addr.ip());
+            }
+
+            stats.tcp_state = moto_socket.state.try_into().unwrap();
+            stats.smoltcp_state = smol_socket.state();
+            stats.migrations = moto_socket.stats_migrations;
+
+            results.push(stats);
+            if results.len() == num_results {
+                break;
+            }
+        }
+
+        payload.results.swap(results);
+    }
 }
 
 impl IoSubsystem for NetSys {
@@ -1625,15 +3136,32 @@ impl IoSubsystem for NetSys {
             api_net::CMD_TCP_LISTENER_BIND => Ok(Some(self.tcp_listener_bind(conn, msg))),
             api_net::CMD_TCP_LISTENER_ACCEPT => self.tcp_listener_accept(conn, msg),
             api_net::CMD_TCP_LISTENER_DROP => self.tcp_listener_drop(conn, msg).map(|_| None),
+            api_net::CMD_TCP_LISTENER_SET_OPTION => self.tcp_listener_set_option(conn, msg),
+            api_net::CMD_TCP_LISTENER_GET_OPTION => self.tcp_listener_get_option(conn, msg),
             api_net::CMD_TCP_STREAM_CONNECT => Ok(self.tcp_stream_connect(conn, msg)),
             api_net::CMD_TCP_STREAM_TX => {
-                self.tcp_stream_write(conn, msg);
-                Ok(None)
+                let res = self.tcp_stream_write(conn, msg);
+                // Urgent writes wait for the result (see tcp_urgent.rs).
+                if msg.flags & api_net::FLAG_TCP_STREAM_TX_URGENT != 0 {
+                    let mut resp = msg;
+                    resp.status = match res {
+                        Ok(()) => moto_rt::E_OK,
+                        Err(err) => err,
+                    };
+                    Ok(Some(resp))
+                } else {
+                    Ok(None)
+                }
             }
             api_net::CMD_TCP_STREAM_RX_ACK => self.tcp_stream_rx_ack(conn, msg).map(|_| None),
             api_net::CMD_TCP_STREAM_SET_OPTION => Ok(Some(self.tcp_stream_set_option(conn, msg))),
             api_net::CMD_TCP_STREAM_GET_OPTION => Ok(Some(self.tcp_stream_get_option(conn, msg))),
             api_net::CMD_TCP_STREAM_CLOSE => Ok(self.tcp_stream_close(conn, msg)),
+            api_net::CMD_CAPTURE_START => Ok(Some(self.capture_start(conn, msg))),
+            api_net::CMD_CAPTURE_STOP => Ok(Some(self.capture_stop(conn, msg))),
+            api_net::CMD_UDP_SOCKET_BIND => Ok(Some(self.udp_socket_bind(conn, msg))),
+            api_net::CMD_UDP_SOCKET_TX => Ok(Some(self.udp_socket_tx(conn, msg))),
+            api_net::CMD_UDP_SOCKET_DROP => self.udp_socket_drop(conn, msg).map(|_| None),
             _ => {
                 #[cfg(debug_assertions)]
                 log::debug!(
@@ -1658,7 +3186,17 @@ impl IoSubsystem for NetSys {
 
         if let Some(listeners) = self.conn_tcp_listeners.remove(&conn) {
             for listener_id in listeners {
-                self.drop_tcp_listener(listener_id);
+                self.drop_tcp_listener(listener_id, false);
+            }
+        }
+
+        if self.captures.remove(&conn).is_some() {
+            self.update_packet_taps();
+        }
+
+        if let Some(udp_sockets) = self.conn_udp_sockets.remove(&conn) {
+            for socket_id in udp_sockets {
+                self.drop_udp_socket(socket_id);
             }
         }
 
@@ -1666,11 +3204,19 @@ impl IoSubsystem for NetSys {
     }
 
     fn poll(&mut self) -> Option<PendingCompletion> {
+        self.reap_idle_tcp_sockets();
+        self.expire_lingering_tcp_sockets();
+        self.check_links();
+
         let mut pending_tcp_rx: VecDeque<SocketId> = VecDeque::new();
         core::mem::swap(&mut pending_tcp_rx, &mut self.pending_tcp_rx);
         while let Some(socket_id) = pending_tcp_rx.pop_front() {
             self.do_tcp_rx(socket_id); // May insert socket_id back into self.pending_tcp_rx.
         }
+        let pending_udp_rx = core::mem::take(&mut self.pending_udp_rx);
+        for socket_id in pending_udp_rx {
+            self.do_udp_rx(socket_id); // May insert socket_id back into self.pending_udp_rx.
+        }
 
         // client writes (tcp_stream_write) wake sockets; make sure we
         // process them before polling devices.
@@ -1685,6 +3231,7 @@ impl IoSubsystem for NetSys {
             for dev in &mut self.devices {
                 polled |= dev.poll();
             }
+            self.deliver_captured_packets();
 
             // Sometimes, e.g. on listener bind, sockets will get polled/woken
             // outside of dev.poll(), so we cannot rely on only !polled.
@@ -1722,61 +3269,34 @@ impl IoSubsystem for NetSys {
             }
         }
 
-        timeout
-    }
-
-    fn get_stats(&mut self, msg: &crate::runtime::internal_queue::Msg) {
-        let num_results = moto_sys_io::stats::MAX_TCP_SOCKET_STATS.min(self.socket_ids.len());
-
-        let payload = msg
-            .payload
-            .clone()
-            .downcast::<crate::runtime::io_stats::GetTcpStatsPayload>()
-            .unwrap();
-
-        let start_id = SocketId::from(payload.start_id);
-        let mut results = Vec::new();
-
-        for &socket_id in self.socket_ids.range(start_id..) {
-            let moto_socket = self.tcp_sockets.get(&socket_id).unwrap();
-            let device_idx = moto_socket.device_idx;
-            let smol_socket = self.devices[device_idx]
-                .sockets
-                .get_mut::<smoltcp::socket::tcp::Socket>(moto_socket.handle);
-
-            let mut stats = moto_sys_io::stats::TcpSocketStatsV1::default();
-            stats.id = moto_socket.id.into();
-            stats.device_id = device_idx as u64;
-            stats.pid = moto_socket.pid;
-
-            let local_addr = if let Some(e) = smol_socket.local_endpoint() {
-                Some(super::smoltcp_helpers::socket_addr_from_endpoint(e))
-            } else {
-                moto_socket.listening_on.clone()
-            };
-            let remote_addr = smol_socket
-                .remote_endpoint()
-                .map(|e| super::smoltcp_helpers::socket_addr_from_endpoint(e));
-
-            if let Some(addr) = local_addr {
-                stats.local_port = addr.port();
-                stats.local_addr = super::smoltcp_helpers::addr_to_octets(addr.ip());
+        for next in [self.next_idle_check, self.next_link_check]
+            .into_iter()
+            .flatten()
+        {
+            let timo = next.duration_since(moto_rt::time::Instant::now());
+            if timeout.map_or(true, |prev| timo < prev) {
+                timeout = Some(timo);
             }
+        }
 
-            if let Some(addr) = remote_addr {
-                stats.remote_port = addr.port();
-                stats.remote_addr = super::smoltcp_helpers::addr_to_octets(addr.ip());
+        for socket_id in &self.lingering_tcp_sockets {
+            let moto_socket = self.tcp_sockets.get(socket_id).unwrap();
+            let deadline = moto_socket.linger_close.as_ref().unwrap().1;
+            let timo = deadline.duration_since(moto_rt::time::Instant::now());
+            if timeout.map_or(true, |prev| timo < prev) {
+                timeout = Some(timo);
             }
+        }
 
-            stats.tcp_state = moto_socket.state.try_into().unwrap();
-            stats.smoltcp_state = smol_socket.state();
+        timeout
+    }
 
-            results.push(stats);
-            if results.len() == num_results {
-                break;
-            }
+    fn get_stats(&mut self, msg: &crate::runtime::internal_queue::Msg) {
+        match msg.cmd {
+            moto_sys_io::stats::CMD_TCP_STATS => self.get_tcp_stats(msg),
+            moto_sys_io::stats::CMD_NET_STATS => self.get_net_stats(msg),
+            moto_sys_io::stats::CMD_NET_DEV_STATS => self.get_net_dev_stats(msg),
+            _ => panic!(),
         }
-
-        payload.results.swap(results);
     }
 }
@@ -35,7 +35,19 @@ pub(super) struct MotoSocket {
 
     pub ephemeral_port: Option<u16>,
 
+    // The application bound the socket (or its listener) to a specific
+    // local address, so the socket never moves to another device.
+    pub local_addr_pinned: bool,
+
+    // The device the socket was on before it first moved, to move it back
+    // when that device's link returns.
+    pub home_device_idx: Option<usize>,
+    // The local address this device adopted for the socket (see NetDev::adopt_ip_addr()).
+    pub adopted_ip_addr: Option<std::net::IpAddr>,
+
     pub tx_queue: VecDeque<super::TxBuf>,
+    // See api_net::FLAG_TCP_STREAM_TX_NOTIFY.
+    pub tx_space_wanted: bool,
 
     pub rx_seq: u64,
     pub rx_ack: u64,
@@ -55,9 +67,39 @@ pub(super) struct MotoSocket {
     // we create a replacement listening socket, and set this flag to true.
     pub replacement_listener_created: bool,
 
+    // A pending (connected but not accepted) socket whose listener was dropped
+    // with FLAG_TCP_LISTENER_DROP_FIN: it is being closed with a FIN and has
+    // no owner; it is dropped once the close handshake completes.
+    pub orphaned: bool,
+
+    // If set, the socket is closed (reaped) after this long without activity
+    // (bytes sent or delivered to the application), even if keepalive
+    // probes still succeed.
+    pub idle_timeout: Option<core::time::Duration>,
+    pub last_activity: moto_rt::time::Instant,
+
+    // See api_net::TCP_OPTION_RX_PAUSED.
+    pub rx_paused: bool,
+
+    // See api_net::TCP_OPTION_LINGER; None: off.
+    pub linger: Option<core::time::Duration>,
+    // The CMD_TCP_STREAM_CLOSE request held while the socket lingers, when
+    // to give up on the bytes still queued, and the connection's endpoints
+    // (smoltcp forgets them on reset; see tcp_urgent::UrgentTracker::peer_reset()).
+    pub linger_close: Option<(
+        io_channel::Msg,
+        moto_rt::time::Instant,
+        Option<super::tcp_urgent::Endpoints>,
+    )>,
+
+    // Reported to the application when the socket is closed by sys-io
+    // (e.g. E_TIMED_OUT when reaped); E_OK for normal closures.
+    pub close_status: moto_rt::ErrorCode,
+
     // stats
     pub stats_rx_bytes: u64, // Bytes sent to the application.
     pub stats_tx_bytes: u64, // Bytes received from the application.
+    pub stats_migrations: u64,
 }
 
 impl Drop for MotoSocket {
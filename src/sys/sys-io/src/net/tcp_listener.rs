@@ -38,6 +38,19 @@ pub struct TcpListener {
 
     // Pure listening sockets. We need to track them to drop when the listener is dropped.
     listening_sockets: HashSet<SocketId>,
+
+    // Set by TCP_LISTENER_OPTION_DRAIN: no new connections are accepted,
+    // and there are no listening sockets.
+    draining: bool,
+
+    // Applied to accepted connections. See TCP_LISTENER_OPTION_IDLE_TIMEOUT.
+    idle_timeout: Option<core::time::Duration>,
+
+    // See FLAG_TCP_LISTENER_REUSE_PORT.
+    reuse_port: bool,
+
+    // See TCP_LISTENER_OPTION_ACCEPTED.
+    stats_accepted: u64,
 }
 
 impl Drop for TcpListener {
@@ -51,13 +64,21 @@ impl Drop for TcpListener {
 }
 
 impl TcpListener {
-    pub fn new(conn: std::rc::Rc<io_channel::ServerConnection>, socket_addr: SocketAddr) -> Self {
+    pub fn new(
+        conn: std::rc::Rc<io_channel::ServerConnection>,
+        socket_addr: SocketAddr,
+        reuse_port: bool,
+    ) -> Self {
         Self {
             conn,
             socket_addr,
             pending_accepts: VecDeque::new(),
             pending_sockets: VecDeque::new(),
             listening_sockets: HashSet::new(),
+            draining: false,
+            idle_timeout: None,
+            reuse_port,
+            stats_accepted: 0,
         }
     }
 
@@ -73,6 +94,42 @@ impl TcpListener {
         &self.socket_addr
     }
 
+    pub fn reuse_port(&self) -> bool {
+        self.reuse_port
+    }
+
+    pub fn stats_accepted(&self) -> u64 {
+        self.stats_accepted
+    }
+
+    pub fn on_accepted(&mut self) {
+        self.stats_accepted += 1;
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining
+    }
+
+    pub fn set_draining(&mut self) {
+        self.draining = true;
+    }
+
+    pub fn idle_timeout(&self) -> Option<core::time::Duration> {
+        self.idle_timeout
+    }
+
+    pub fn set_idle_timeout(&mut self, timeout: Option<core::time::Duration>) {
+        self.idle_timeout = timeout;
+    }
+
+    pub fn has_pending_sockets(&self) -> bool {
+        !self.pending_sockets.is_empty()
+    }
+
+    pub fn num_pending_sockets(&self) -> usize {
+        self.pending_sockets.len()
+    }
+
     pub fn add_pending_socket(&mut self, id: SocketId, addr: SocketAddr) {
         assert!(self.listening_sockets.remove(&id));
         self.pending_sockets.push_back((id, addr));
@@ -0,0 +1,127 @@
+// Zero receive windows for paused streams (TCP_OPTION_RX_PAUSED), which
+// smoltcp does not support: netdev.rs shows every outgoing frame to
+// RxPauses below smoltcp, which clamps the window advertised in segments
+// of paused connections to zero.
+//
+// smoltcp does not know its window was clamped, so on resume it sends a
+// window update only if its own window has grown since. resume() returns
+// the last segment sent while paused, as a pure ACK with the window smoltcp
+// meant to advertise, so that the peer does not wait for its persist timer.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use moto_sys::ErrorCode;
+use smoltcp::wire::*;
+
+use super::tcp_urgent::{tcp_segment, Endpoints};
+
+// Same as tcp_urgent.rs.
+const MAX_STREAMS: usize = 4096;
+
+#[derive(Default)]
+pub(super) struct RxPauses {
+    // The window update to send on resume, if any.
+    streams: HashMap<Endpoints, Option<Vec<u8>>>,
+}
+
+// A copy of the headers of @frame (@range is its TCP segment) acking the
+// same bytes, with no payload or FIN, and @window advertised.
+fn window_update(frame: &[u8], range: Range<usize>, window: u16) -> Option<Vec<u8>> {
+    let segment = TcpPacket::new_checked(&frame[range.clone()]).ok()?;
+    let header_len = segment.header_len() as usize;
+    let payload_len = segment.payload().len();
+    let mut update = frame[0..range.start + header_len].to_vec();
+
+    let (src, dst) = match EthernetFrame::new_unchecked(&update[..]).ethertype() {
+        EthernetProtocol::Ipv4 => {
+            let mut ip = Ipv4Packet::new_unchecked(&mut update[ETHERNET_HEADER_LEN..]);
+            ip.set_total_len(ip.header_len() as u16 + header_len as u16);
+            ip.fill_checksum();
+            (
+                IpAddress::Ipv4(ip.src_addr()),
+                IpAddress::Ipv4(ip.dst_addr()),
+            )
+        }
+        EthernetProtocol::Ipv6 => {
+            let mut ip = Ipv6Packet::new_unchecked(&mut update[ETHERNET_HEADER_LEN..]);
+            ip.set_payload_len(header_len as u16);
+            (
+                IpAddress::Ipv6(ip.src_addr()),
+                IpAddress::Ipv6(ip.dst_addr()),
+            )
+        }
+        _ => return None,
+    };
+
+    // Past the payload (and FIN): where the peer expects the next segment.
+    let mut ack = TcpPacket::new_unchecked(&mut update[range.start..]);
+    ack.set_seq_number(ack.seq_number() + payload_len + ack.fin() as usize);
+    ack.set_fin(false);
+    ack.set_psh(false);
+    ack.set_window_len(window);
+    ack.fill_checksum(&src, &dst);
+    Some(update)
+}
+
+impl RxPauses {
+    /// Starts advertising a zero window for the connection.
+    pub fn pause(&mut self, endpoints: Endpoints) -> Result<(), ErrorCode> {
+        if !self.streams.contains_key(&endpoints) {
+            if self.streams.len() >= MAX_STREAMS {
+                return Err(moto_rt::E_OUT_OF_MEMORY);
+            }
+            self.streams.insert(endpoints, None);
+        }
+        Ok(())
+    }
+
+    /// Stops clamping the window; returns the window update to send, if any.
+    pub fn resume(&mut self, endpoints: Endpoints) -> Option<Vec<u8>> {
+        self.streams.remove(&endpoints).flatten()
+    }
+
+    /// Called for every frame sent by the device, after smoltcp has filled it.
+    pub fn on_tx(&mut self, frame: &mut [u8]) {
+        if self.streams.is_empty() {
+            return;
+        }
+        let Some((src, dst, range)) = tcp_segment(frame) else {
+            return;
+        };
+        let mut segment = TcpPacket::new_unchecked(&mut frame[range.clone()]);
+        let endpoints = (
+            IpEndpoint::new(src, segment.src_port()),
+            IpEndpoint::new(dst, segment.dst_port()),
+        );
+        let Some(update) = self.streams.get_mut(&endpoints) else {
+            return;
+        };
+        if segment.rst() || segment.syn() {
+            return;
+        }
+
+        let window = segment.window_len();
+        segment.set_window_len(0);
+        segment.fill_checksum(&src, &dst);
+        *update = window_update(frame, range, window);
+    }
+
+    /// See UrgentTracker::retain().
+    pub fn retain<F: Fn(&Endpoints) -> bool>(&mut self, is_live: F) {
+        self.streams.retain(|endpoints, _| is_live(endpoints));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    /// Removes a paused connection moving to another device; see adopt().
+    pub fn take(&mut self, endpoints: Endpoints) -> Option<Option<Vec<u8>>> {
+        self.streams.remove(&endpoints)
+    }
+
+    pub fn adopt(&mut self, endpoints: Endpoints, update: Option<Vec<u8>>) {
+        self.streams.insert(endpoints, update);
+    }
+}
@@ -0,0 +1,254 @@
+// TCP urgent data (RFC 6093), which smoltcp does not support: netdev.rs
+// shows every frame to UrgentTracker below smoltcp, which sets the urgent
+// pointer on outgoing segments and records it from incoming ones.
+//
+// Urgent data is always inline (as with SO_OOBINLINE): the urgent byte stays
+// in the byte stream, and only its position, the urgent mark, is tracked.
+// Only the most recent mark is kept in each direction. Marks are offsets in
+// the stream (the first byte after the SYN is at offset 0), modulo 2^32.
+//
+// Incoming frames have not been looked at by smoltcp yet, so nothing here
+// trusts them more than smoltcp would: segments with bad checksums are
+// ignored, connections are only tracked once smoltcp sends a SYN (or SYN-ACK)
+// for them, the peer's ISN is taken from a SYN-ACK only if it acks our SYN,
+// and acks beyond what has been sent are ignored. Incoming RSTs and FINs are
+// not acted upon: a connection is forgotten when its socket is dropped (see
+// retain()), or when smoltcp sends an RST for it. Incoming RSTs are only
+// recorded, to tell a reset by the peer from a timeout (see peer_reset()). Outgoing frames are
+// only rewritten if their checksum is valid, and their checksum is refilled.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use moto_sys::ErrorCode;
+use smoltcp::wire::*;
+
+// Connections beyond this many are not tracked (urgent marks get lost).
+const MAX_STREAMS: usize = 4096;
+
+// (local, remote).
+pub(super) type Endpoints = (IpEndpoint, IpEndpoint);
+
+#[derive(Default)]
+pub(super) struct UrgentState {
+    local_isn: u32,
+    // The sequence number following the last byte sent (SYN and FIN included).
+    tx_next: u32,
+    remote_isn: Option<u32>,
+    // The sequence number following the urgent byte being sent, until acked.
+    tx_urgent_ptr: Option<u32>,
+    // The offset of the last urgent byte received.
+    rx_mark: Option<u32>,
+    // An RST has been received (which smoltcp may or may not have accepted).
+    rx_reset: bool,
+}
+
+#[derive(Default)]
+pub(super) struct UrgentTracker {
+    streams: HashMap<Endpoints, UrgentState>,
+}
+
+fn seq_before(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+// Finds the TCP segment in an Ethernet frame: (src, dst, segment bytes).
+// IPv4 fragments and IPv6 extension headers are not looked into, nor are
+// segments with bad checksums.
+pub(super) fn tcp_segment(frame: &[u8]) -> Option<(IpAddress, IpAddress, Range<usize>)> {
+    let eth = EthernetFrame::new_checked(frame).ok()?;
+    let (src, dst, range) = match eth.ethertype() {
+        EthernetProtocol::Ipv4 => {
+            let ip = Ipv4Packet::new_checked(eth.payload()).ok()?;
+            if ip.next_header() != IpProtocol::Tcp || ip.more_frags() || ip.frag_offset() != 0 {
+                return None;
+            }
+            if !ip.verify_checksum() {
+                return None;
+            }
+            let header_len = ip.header_len() as usize;
+            let start = ETHERNET_HEADER_LEN + header_len;
+            let end = ETHERNET_HEADER_LEN + ip.total_len() as usize;
+            (
+                IpAddress::Ipv4(ip.src_addr()),
+                IpAddress::Ipv4(ip.dst_addr()),
+                start..end,
+            )
+        }
+        EthernetProtocol::Ipv6 => {
+            let ip = Ipv6Packet::new_checked(eth.payload()).ok()?;
+            if ip.next_header() != IpProtocol::Tcp {
+                return None;
+            }
+            let start = ETHERNET_HEADER_LEN + IPV6_HEADER_LEN;
+            (
+                IpAddress::Ipv6(ip.src_addr()),
+                IpAddress::Ipv6(ip.dst_addr()),
+                start..start + ip.payload_len() as usize,
+            )
+        }
+        _ => return None,
+    };
+
+    let segment = TcpPacket::new_checked(&frame[range.clone()]).ok()?;
+    if !segment.verify_checksum(&src, &dst) {
+        return None;
+    }
+    Some((src, dst, range))
+}
+
+// The sequence space a segment takes.
+fn segment_len<T: AsRef<[u8]>>(segment: &TcpPacket<T>) -> u32 {
+    segment.payload().len() as u32 + segment.syn() as u32 + segment.fin() as u32
+}
+
+impl UrgentTracker {
+    /// Called for every frame received by the device, before smoltcp sees it.
+    pub fn on_rx(&mut self, frame: &[u8]) {
+        let Some((src, dst, range)) = tcp_segment(frame) else {
+            return;
+        };
+        let segment = TcpPacket::new_unchecked(&frame[range]);
+        let endpoints = (
+            IpEndpoint::new(dst, segment.dst_port()),
+            IpEndpoint::new(src, segment.src_port()),
+        );
+        // Connections smoltcp hasn't sent anything for are not tracked.
+        let Some(state) = self.streams.get_mut(&endpoints) else {
+            return;
+        };
+        if segment.rst() {
+            state.rx_reset = true;
+            return;
+        }
+
+        let seq = segment.seq_number().0 as u32;
+        let ack = segment.ack_number().0 as u32;
+        if segment.syn() {
+            // Only a SYN-ACK for our SYN: a SYN is answered by smoltcp (with
+            // a SYN-ACK, which records the peer's ISN), or dropped.
+            if segment.ack() && state.remote_isn.is_none() && ack == state.local_isn.wrapping_add(1)
+            {
+                state.remote_isn = Some(seq);
+            }
+            return;
+        }
+        if segment.ack() && !seq_before(state.tx_next, ack) {
+            if let Some(ptr) = state.tx_urgent_ptr {
+                if !seq_before(ack, ptr) {
+                    state.tx_urgent_ptr = None; // The urgent byte got through.
+                }
+            }
+        }
+        if segment.urg() && segment.urgent_at() > 0 {
+            if let Some(isn) = state.remote_isn {
+                let ptr = seq.wrapping_add(segment.urgent_at() as u32);
+                state.rx_mark = Some(ptr.wrapping_sub(isn).wrapping_sub(2));
+            }
+        }
+    }
+
+    /// Whether the peer has sent an RST for the connection. smoltcp closes a
+    /// socket the same way when the peer resets it, and when it gives up on
+    /// it (then sending an RST itself, which forgets the connection here).
+    pub fn peer_reset(&self, endpoints: &Endpoints) -> bool {
+        self.streams
+            .get(endpoints)
+            .is_some_and(|state| state.rx_reset)
+    }
+
+    /// Called for every frame sent by the device, after smoltcp has filled it.
+    pub fn on_tx(&mut self, frame: &mut [u8]) {
+        let Some((src, dst, range)) = tcp_segment(frame) else {
+            return;
+        };
+        let mut segment = TcpPacket::new_unchecked(&mut frame[range]);
+        let endpoints = (
+            IpEndpoint::new(src, segment.src_port()),
+            IpEndpoint::new(dst, segment.dst_port()),
+        );
+
+        if segment.rst() {
+            self.streams.remove(&endpoints);
+            return;
+        }
+
+        let seq = segment.seq_number().0 as u32;
+        let end = seq.wrapping_add(segment_len(&segment));
+        if segment.syn() {
+            // A new connection (a SYN retransmit changes nothing).
+            let remote_isn = if segment.ack() {
+                Some((segment.ack_number().0 as u32).wrapping_sub(1))
+            } else {
+                None
+            };
+            if self.streams.get(&endpoints).map(|state| state.local_isn) != Some(seq) {
+                if self.streams.len() >= MAX_STREAMS && !self.streams.contains_key(&endpoints) {
+                    return;
+                }
+                self.streams.insert(
+                    endpoints,
+                    UrgentState {
+                        local_isn: seq,
+                        tx_next: end,
+                        remote_isn,
+                        ..Default::default()
+                    },
+                );
+            }
+            return;
+        }
+
+        let Some(state) = self.streams.get_mut(&endpoints) else {
+            return;
+        };
+        if seq_before(state.tx_next, end) {
+            state.tx_next = end;
+        }
+        if let Some(ptr) = state.tx_urgent_ptr {
+            // Same as Linux: the pointer is relative to the segment, and
+            // is clamped if the urgent byte is 64K or more away.
+            if seq_before(seq, ptr) {
+                let urgent_at = ptr.wrapping_sub(seq).min(u16::MAX as u32) as u16;
+                segment.set_urg(true);
+                segment.set_urgent_at(urgent_at);
+                segment.fill_checksum(&src, &dst);
+            }
+        }
+    }
+
+    /// Marks the byte at @offset in the outgoing stream as urgent. Fails with
+    /// E_NOT_IMPLEMENTED if the connection is not tracked.
+    pub fn set_tx_urgent(&mut self, endpoints: Endpoints, offset: u32) -> Result<(), ErrorCode> {
+        let Some(state) = self.streams.get_mut(&endpoints) else {
+            return Err(moto_rt::E_NOT_IMPLEMENTED);
+        };
+        state.tx_urgent_ptr = Some(state.local_isn.wrapping_add(offset).wrapping_add(2));
+        Ok(())
+    }
+
+    /// The offset of the last urgent byte received, if any.
+    pub fn rx_mark(&self, endpoints: Endpoints) -> Option<u32> {
+        self.streams.get(&endpoints).and_then(|state| state.rx_mark)
+    }
+
+    /// Forgets the connections @is_live returns false for; called when
+    /// sockets are dropped (smoltcp no longer knows the endpoints of a
+    /// reset connection, so they can't be looked up one by one).
+    pub fn retain<F: Fn(&Endpoints) -> bool>(&mut self, is_live: F) {
+        self.streams.retain(|endpoints, _| is_live(endpoints));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+    /// Removes the state of a connection moving to another device; see take().
+    pub fn take(&mut self, endpoints: Endpoints) -> Option<UrgentState> {
+        self.streams.remove(&endpoints)
+    }
+
+    /// Tracks a connection that moved here from another device.
+    pub fn adopt(&mut self, endpoints: Endpoints, state: UrgentState) {
+        self.streams.insert(endpoints, state);
+    }
+}
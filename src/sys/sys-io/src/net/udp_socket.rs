@@ -0,0 +1,57 @@
+// UDP sockets (see api_net::udp_socket_bind_request()). Sockets are not
+// connected: each datagram carries its peer address. A socket bound to an
+// unspecified IP has a smoltcp socket on every device, and datagrams are
+// sent from the device that routes to the destination.
+
+use std::net::SocketAddr;
+use std::rc::Rc;
+
+use moto_ipc::io_channel;
+use moto_sys_io::api_net;
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::udp;
+
+use super::socket::SocketId;
+
+// How many datagrams smoltcp buffers per socket (and direction). The payload
+// buffers are larger than what fits in a message, so that oversized incoming
+// datagrams are seen (and dropped) rather than lost in smoltcp.
+const BUFFERED_DATAGRAMS: usize = 16;
+const BUFFER_SIZE: usize = 65536;
+
+pub(super) struct UdpSocket {
+    pub id: SocketId,
+    pub conn: Rc<io_channel::ServerConnection>,
+    pub pid: u64, // Owner's process ID.
+    // See moto_ipc::io_channel::ServerConnection::alloc_page().
+    pub subchannel_mask: u64,
+    // The port is never zero.
+    pub local_addr: SocketAddr,
+    // (device_idx, handle): one entry, or one per device if the IP is unspecified.
+    pub handles: Vec<(usize, SocketHandle)>,
+
+    // stats
+    pub stats_rx_dropped: u64, // Incoming datagrams too large for a message.
+}
+
+impl UdpSocket {
+    pub fn new_smoltcp_socket() -> udp::Socket<'static> {
+        let rx_buffer = udp::PacketBuffer::new(
+            vec![udp::PacketMetadata::EMPTY; BUFFERED_DATAGRAMS],
+            vec![0; BUFFER_SIZE],
+        );
+        let tx_buffer = udp::PacketBuffer::new(
+            vec![udp::PacketMetadata::EMPTY; BUFFERED_DATAGRAMS],
+            vec![0; api_net::UDP_MAX_DATAGRAM_SIZE * BUFFERED_DATAGRAMS],
+        );
+
+        udp::Socket::new(rx_buffer, tx_buffer)
+    }
+
+    pub fn handle_on(&self, device_idx: usize) -> Option<SocketHandle> {
+        self.handles
+            .iter()
+            .find(|(idx, _)| *idx == device_idx)
+            .map(|(_, handle)| *handle)
+    }
+}
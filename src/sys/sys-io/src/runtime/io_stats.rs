@@ -53,6 +53,13 @@ fn process_ipc(service: &mut moto_ipc::sync::LocalServer, waker: moto_sys::SysHa
     let cmd = conn.req::<RequestHeader>().cmd;
     match cmd {
         CMD_TCP_STATS => get_tcp_stats(conn),
+        CMD_NET_STATS => get_net_stats(conn),
+        CMD_NET_DEV_STATS => get_net_dev_stats(conn),
+        CMD_OPEN_FILES => get_open_files(conn),
+        CMD_PROCESS_IO => get_process_io(conn),
+        CMD_LOG_LEVEL => log_level(conn),
+        CMD_VIRTIO_RNG => get_virtio_rng_bytes(conn),
+        CMD_VIRTIO_DEVICES => get_virtio_devices(conn),
         _ => {
             conn.disconnect();
         }
@@ -61,15 +68,28 @@ fn process_ipc(service: &mut moto_ipc::sync::LocalServer, waker: moto_sys::SysHa
 
 pub struct GetTcpStatsPayload {
     pub start_id: u64,
+    pub owner_pid: Option<u64>, // If set, only sockets of this process are listed.
     pub results: crossbeam::atomic::AtomicCell<Vec<TcpSocketStatsV1>>,
 }
 
 fn get_tcp_stats(conn: &mut LocalServerConnection) {
+    // Connections are private: without CAP_SYS, a process sees only its own.
+    let owner_pid = match moto_sys::SysObj::get_peer_credentials(conn.handle()) {
+        Ok((_, caps)) if (caps & moto_sys::caps::CAP_SYS) != 0 => None,
+        Ok((peer_pid, _)) => Some(peer_pid),
+        Err(_) => {
+            conn.resp::<GetTcpSocketStatsResponse<1>>().header.result = moto_rt::E_NOT_ALLOWED;
+            let _ = conn.finish_rpc();
+            return;
+        }
+    };
+
     let req = conn.req::<GetTcpSocketStatsRequest>();
     let start_id = req.start_id;
 
     let payload = Arc::new(GetTcpStatsPayload {
         start_id,
+        owner_pid,
         results: crossbeam::atomic::AtomicCell::new(Vec::new()),
     });
 
@@ -89,3 +109,185 @@ fn get_tcp_stats(conn: &mut LocalServerConnection) {
     resp.header.result = moto_rt::E_OK;
     let _ = conn.finish_rpc();
 }
+
+pub struct GetNetStatsPayload {
+    pub results: crossbeam::atomic::AtomicCell<NetStatsV1>,
+}
+
+fn get_net_stats(conn: &mut LocalServerConnection) {
+    let payload = Arc::new(GetNetStatsPayload {
+        results: crossbeam::atomic::AtomicCell::new(NetStatsV1::default()),
+    });
+
+    super::internal_queue::call(CMD_NET_STATS, payload.clone());
+
+    let resp = conn.resp::<GetNetStatsResponse>();
+    resp.stats = payload.results.load();
+    resp.header.result = moto_rt::E_OK;
+    let _ = conn.finish_rpc();
+}
+
+pub struct GetNetDevStatsPayload {
+    pub results: crossbeam::atomic::AtomicCell<Vec<NetDevStatsV1>>,
+    pub connect_no_route: crossbeam::atomic::AtomicCell<u64>,
+}
+
+fn get_net_dev_stats(conn: &mut LocalServerConnection) {
+    let payload = Arc::new(GetNetDevStatsPayload {
+        results: crossbeam::atomic::AtomicCell::new(Vec::new()),
+        connect_no_route: crossbeam::atomic::AtomicCell::new(0),
+    });
+
+    super::internal_queue::call(CMD_NET_DEV_STATS, payload.clone());
+
+    let resp = conn.resp::<GetNetDevStatsResponse>();
+
+    let results = payload.results.swap(Vec::new());
+    assert!(results.len() <= MAX_NET_DEV_STATS);
+    resp.num_devices = results.len() as u64;
+    for idx in 0..results.len() {
+        resp.devices[idx] = results[idx];
+    }
+    resp.connect_no_route = payload.connect_no_route.load();
+
+    resp.header.result = moto_rt::E_OK;
+    let _ = conn.finish_rpc();
+}
+
+fn get_open_files(conn: &mut LocalServerConnection) {
+    let allowed = match moto_sys::SysObj::get_peer_credentials(conn.handle()) {
+        Ok((_, caps)) => (caps & moto_sys::caps::CAP_SYS) != 0,
+        Err(_) => false,
+    };
+    if !allowed {
+        conn.resp::<GetOpenFilesResponse>().header.result = moto_rt::E_NOT_ALLOWED;
+        let _ = conn.finish_rpc();
+        return;
+    }
+
+    let req = conn.req::<GetOpenFilesRequest>();
+    let pid = req.pid;
+    let start = req.start;
+
+    let raw_channel = conn.raw_channel();
+    let resp = conn.resp::<GetOpenFilesResponse>();
+    let mut num_results = 0;
+    let mut offset = 0;
+    for (fd, path) in crate::fs::open_files(pid).iter().skip(start as usize) {
+        match unsafe { resp.put_file(&raw_channel, offset, *fd, path.as_str()) } {
+            Some(next) => offset = next,
+            None => break, // The rest will be fetched by subsequent calls.
+        }
+        num_results += 1;
+    }
+
+    resp.num_results = num_results;
+    resp.header.result = moto_rt::E_OK;
+    let _ = conn.finish_rpc();
+}
+
+fn get_process_io(conn: &mut LocalServerConnection) {
+    let pid = conn.req::<GetProcessIoRequest>().pid;
+    let usage = super::io_usage::get(pid);
+
+    // Zeroes tell nothing, so only actual counters are checked.
+    let allowed = match (usage, moto_sys::SysObj::get_peer_credentials(conn.handle())) {
+        (None, _) => true,
+        (Some((_, parent_pid)), Ok((peer_pid, caps))) => {
+            peer_pid == pid || peer_pid == parent_pid || (caps & moto_sys::caps::CAP_SYS) != 0
+        }
+        (Some(_), Err(_)) => false,
+    };
+    if !allowed {
+        conn.resp::<GetProcessIoResponse>().header.result = moto_rt::E_NOT_ALLOWED;
+        let _ = conn.finish_rpc();
+        return;
+    }
+
+    let resp = conn.resp::<GetProcessIoResponse>();
+    resp.stats = usage.map(|(stats, _)| stats).unwrap_or_default();
+    resp.header.result = moto_rt::E_OK;
+    let _ = conn.finish_rpc();
+}
+
+fn log_level(conn: &mut LocalServerConnection) {
+    use log::LevelFilter;
+
+    let req = conn.req::<LogLevelRequest>();
+    let (flags, level) = (req.header.flags, req.level);
+    let prev = log::max_level() as u64;
+
+    let result = match flags {
+        0 => moto_rt::E_OK,
+        F_LOG_LEVEL_SET => {
+            let allowed = match moto_sys::SysObj::get_peer_credentials(conn.handle()) {
+                Ok((_, caps)) => (caps & moto_sys::caps::CAP_SYS) != 0,
+                Err(_) => false,
+            };
+            let level = match level {
+                0 => Some(LevelFilter::Off),
+                1 => Some(LevelFilter::Error),
+                2 => Some(LevelFilter::Warn),
+                3 => Some(LevelFilter::Info),
+                4 => Some(LevelFilter::Debug),
+                5 => Some(LevelFilter::Trace),
+                _ => None,
+            };
+            match (allowed, level) {
+                (false, _) => moto_rt::E_NOT_ALLOWED,
+                (true, None) => moto_rt::E_INVALID_ARGUMENT,
+                (true, Some(level)) => {
+                    crate::logger::set_max_level(level);
+                    moto_rt::E_OK
+                }
+            }
+        }
+        _ => moto_rt::E_INVALID_ARGUMENT,
+    };
+
+    let resp = conn.resp::<LogLevelResponse>();
+    resp.level = prev;
+    resp.header.result = result;
+    let _ = conn.finish_rpc();
+}
+
+fn get_virtio_rng_bytes(conn: &mut LocalServerConnection) {
+    let len = conn.req::<GetVirtioRngRequest>().len as usize;
+
+    let resp = conn.resp::<GetVirtioRngResponse>();
+    resp.header.result = if len > MAX_VIRTIO_RNG_BYTES {
+        moto_rt::E_INVALID_ARGUMENT
+    } else if moto_virtio::get_entropy(&mut resp.bytes[0..len]).is_err() {
+        moto_rt::E_NOT_FOUND
+    } else {
+        moto_rt::E_OK
+    };
+    let _ = conn.finish_rpc();
+}
+
+fn get_virtio_devices(conn: &mut LocalServerConnection) {
+    let devices = moto_virtio::virtio_device_info();
+
+    let resp = conn.resp::<GetVirtioDevicesResponse>();
+    let num_devices = devices.len().min(MAX_VIRTIO_DEVICES);
+    for (dst, src) in resp.devices.iter_mut().zip(&devices[0..num_devices]) {
+        let mut queue_sizes = [0_u16; VirtioDeviceStatsV1::MAX_QUEUES];
+        for (dst, src) in queue_sizes.iter_mut().zip(&src.queue_sizes) {
+            *dst = *src;
+        }
+        *dst = VirtioDeviceStatsV1 {
+            pci_device_id: src.kind.device_id(),
+            pci_bus: src.pci_bus,
+            pci_slot: src.pci_slot,
+            pci_func: src.pci_func,
+            device_status: src.device_status,
+            num_queues: src.queue_sizes.len() as u16,
+            queue_sizes,
+            features_offered: src.features_offered,
+            features_acked: src.features_acked,
+        };
+    }
+    resp.num_devices = num_devices as u64;
+    resp.header.result = moto_rt::E_OK;
+    let _ = conn.finish_rpc();
+}
@@ -136,8 +136,10 @@ impl IoRuntime {
         loop {
             let msg = match conn.recv() {
                 Ok(sqe) => sqe,
+                // E_NOT_CONNECTED: the client has shut down; its requests in
+                // flight are still completed until it drops the connection.
                 Err(err) => {
-                    assert_eq!(err, moto_rt::E_NOT_READY);
+                    assert!(err == moto_rt::E_NOT_READY || err == moto_rt::E_NOT_CONNECTED);
                     break;
                 }
             };
@@ -331,7 +333,9 @@ impl IoRuntime {
         };
 
         match msg.cmd {
-            moto_sys_io::stats::CMD_TCP_STATS => self.net.get_stats(&msg),
+            moto_sys_io::stats::CMD_TCP_STATS
+            | moto_sys_io::stats::CMD_NET_STATS
+            | moto_sys_io::stats::CMD_NET_DEV_STATS => self.net.get_stats(&msg),
             _ => panic!(),
         }
         msg.mark_done();
@@ -0,0 +1,107 @@
+// Bytes read and written by each process through sys-io (see
+// moto_sys_io::api_stats::ProcessIoStatsV1): the kernel does not see I/O,
+// so the FS and net threads count it here, and the stats service reports it.
+//
+// Counters are updated when requests are served, so they are final by the
+// time a process has exited. They are kept after that, so that they can be
+// read after wait(): when there are too many, the least recently updated
+// are dropped. The parent is recorded while the process is alive, as the
+// stats service lets parents query their children (see io_stats.rs).
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use moto_sys_io::api_stats::ProcessIoStatsV1;
+
+const MAX_PROCESSES: usize = 1024;
+
+struct Entry {
+    stats: ProcessIoStatsV1,
+    parent_pid: u64,
+    updated: u64, // See IoUsage::clock.
+}
+
+struct IoUsage {
+    processes: BTreeMap<u64, Entry>,
+    clock: u64, // Bumped on every update.
+}
+
+static IO_USAGE: Mutex<IoUsage> = Mutex::new(IoUsage {
+    processes: BTreeMap::new(),
+    clock: 0,
+});
+
+fn parent_pid(pid: u64) -> u64 {
+    let mut stats = [moto_sys::stats::ProcessStatsV1::default()];
+    match moto_sys::stats::ProcessStatsV1::list(pid, &mut stats) {
+        Ok(1) if stats[0].pid == pid => stats[0].parent_pid,
+        _ => 0,
+    }
+}
+
+fn update<F: FnOnce(&mut ProcessIoStatsV1)>(pid: u64, func: F) {
+    if pid == 0 {
+        return; // Unknown peer.
+    }
+
+    // Not under the lock. If the FS and net threads both add @pid, the first wins.
+    let known = IO_USAGE.lock().unwrap().processes.contains_key(&pid);
+    let parent = if known { 0 } else { parent_pid(pid) };
+
+    let mut usage = IO_USAGE.lock().unwrap();
+    usage.clock += 1;
+    let clock = usage.clock;
+
+    if !usage.processes.contains_key(&pid) && usage.processes.len() >= MAX_PROCESSES {
+        let oldest = usage
+            .processes
+            .iter()
+            .min_by_key(|(_, entry)| entry.updated)
+            .map(|(pid, _)| *pid)
+            .unwrap();
+        usage.processes.remove(&oldest);
+    }
+
+    let entry = usage.processes.entry(pid).or_insert(Entry {
+        stats: ProcessIoStatsV1::default(),
+        parent_pid: parent,
+        updated: 0,
+    });
+    entry.updated = clock;
+    func(&mut entry.stats);
+}
+
+pub fn on_fs_read(pid: u64, bytes: usize) {
+    if bytes > 0 {
+        update(pid, |stats| stats.fs_bytes_read += bytes as u64);
+    }
+}
+
+pub fn on_fs_write(pid: u64, bytes: usize) {
+    if bytes > 0 {
+        update(pid, |stats| stats.fs_bytes_written += bytes as u64);
+    }
+}
+
+pub fn on_net_rx(pid: u64, bytes: usize) {
+    if bytes > 0 {
+        update(pid, |stats| stats.net_bytes_rx += bytes as u64);
+    }
+}
+
+pub fn on_net_tx(pid: u64, bytes: usize) {
+    if bytes > 0 {
+        update(pid, |stats| stats.net_bytes_tx += bytes as u64);
+    }
+}
+
+/// (the I/O of @pid, its parent's PID), or None if @pid has done no I/O
+/// (or its counters have been dropped).
+pub fn get(pid: u64) -> Option<(ProcessIoStatsV1, u64)> {
+    IO_USAGE
+        .lock()
+        .unwrap()
+        .processes
+        .get(&pid)
+        .map(|entry| (entry.stats, entry.parent_pid))
+}
@@ -5,6 +5,7 @@ use moto_sys::SysHandle;
 
 pub mod internal_queue;
 pub mod io_stats;
+pub mod io_usage;
 mod io_thread;
 
 pub struct PendingCompletion {
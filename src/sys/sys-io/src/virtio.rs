@@ -82,4 +82,27 @@ impl moto_virtio::KernelAdapter for Mapper {
 
 pub fn init() {
     moto_virtio::init_virtio_devices(&MAPPER);
+    #[cfg(debug_assertions)]
+    moto_virtio::log_virtio_device_info();
+    feed_entropy();
+}
+
+// Mixes bytes from the VirtIO RNG device, if there is one, into the kernel
+// entropy pool behind SysMem::get_random(). The kernel has its own sources,
+// so a missing device is not an error.
+fn feed_entropy() {
+    let mut bytes = [0_u8; SysMem::MAX_RANDOM_BYTES];
+    if moto_virtio::get_entropy(&mut bytes).is_err() {
+        log::info!("No VirtIO RNG device: not feeding the kernel entropy pool.");
+        return;
+    }
+    if bytes.iter().all(|byte| *byte == 0) {
+        log::warn!("VirtIO RNG device returned all zeroes: ignored.");
+        return;
+    }
+
+    if let Err(err) = SysMem::add_entropy(&bytes) {
+        log::error!("SysMem::add_entropy() failed: {:?}", err);
+    }
+    bytes.fill(0);
 }
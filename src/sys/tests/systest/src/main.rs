@@ -142,6 +142,244 @@ fn test_cpus() {
     println!("test_cpus PASS");
 }
 
+fn test_cpu_idle() {
+    use moto_sys::SysCpu;
+
+    let num_cpus = moto_sys::num_cpus() as usize;
+    assert_eq!(
+        SysCpu::query_idle(&mut []).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+
+    let mut before = vec![0_u64; num_cpus];
+    SysCpu::query_idle(&mut before).unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    let mut after = vec![0_u64; num_cpus];
+    SysCpu::query_idle(&mut after).unwrap();
+
+    // Idle time only grows, and some CPU must have been idle while we slept.
+    assert!(before.iter().zip(after.iter()).all(|(b, a)| a >= b));
+    assert!(before.iter().zip(after.iter()).any(|(b, a)| a > b));
+
+    println!("test_cpu_idle() PASS");
+}
+
+// Idle time is counted once, and only while the CPU has nothing to run.
+fn test_cpu_idle_accounting() {
+    use moto_sys::SysCpu;
+
+    let num_cpus = moto_sys::num_cpus();
+    if num_cpus < 2 {
+        println!("test_cpu_idle_accounting() SKIPPED: need at least 2 CPUs");
+        return;
+    }
+    let cpu = num_cpus - 1; // Not CPU 0, which only the IO_MANAGER can affine to.
+
+    let query = move || {
+        let mut idle = vec![0_u64; num_cpus as usize];
+        SysCpu::query_idle(&mut idle).unwrap();
+        (moto_rt::time::Instant::now().as_u64(), idle)
+    };
+
+    // While sleeping, no CPU is idle for longer than the time that has passed.
+    let (start, before) = query();
+    std::thread::sleep(Duration::from_millis(100));
+    let (end, after) = query();
+    for (b, a) in before.iter().zip(after.iter()) {
+        assert!(a - b <= end - start);
+    }
+
+    // A CPU kept busy is (almost) never idle.
+    std::thread::spawn(move || {
+        SysCpu::affine_to_cpu(Some(cpu)).unwrap();
+        let (start, before) = query();
+        let spin_start = std::time::Instant::now();
+        while spin_start.elapsed() < Duration::from_millis(100) {
+            core::hint::spin_loop();
+        }
+        let (end, after) = query();
+        let idle = after[cpu as usize] - before[cpu as usize];
+        assert!(idle < (end - start) / 10, "idle {idle} of {}", end - start);
+    })
+    .join()
+    .unwrap();
+
+    println!("test_cpu_idle_accounting() PASS");
+}
+
+fn test_cpu_usage_detailed() {
+    use moto_sys::stats::CpuUsageDetail;
+
+    let num_cpus = moto_sys::num_cpus() as usize;
+    assert_eq!(
+        moto_sys::stats::get_cpu_usage_detailed(&mut []).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+
+    let start = std::time::Instant::now();
+    while start.elapsed() < Duration::from_millis(20) {
+        core::hint::spin_loop();
+    }
+
+    // Entries past num_cpus are left untouched.
+    let sentinel = CpuUsageDetail {
+        uspace: -1.0,
+        kernel: -1.0,
+        idle: -1.0,
+    };
+    let mut usage = vec![sentinel; num_cpus + 1];
+    moto_sys::stats::get_cpu_usage_detailed(&mut usage).unwrap();
+    assert_eq!(usage[num_cpus].uspace, -1.0);
+
+    for entry in &usage[0..num_cpus] {
+        assert!(entry.uspace >= 0.0 && entry.kernel >= 0.0 && entry.idle >= 0.0);
+        assert!((entry.uspace + entry.kernel + entry.idle - 1.0).abs() < 0.01);
+    }
+
+    println!("test_cpu_usage_detailed() PASS");
+}
+
+fn test_thread_cpu_usage() {
+    use moto_sys::stats::ThreadCpuUsage;
+    use moto_sys::SysRay;
+
+    let pid = moto_sys::current_pid();
+    let mut before = vec![ThreadCpuUsage::default(); 64];
+    let (filled, _) = SysRay::query_threads(pid, &mut before).unwrap();
+    assert!(filled >= 2); // Exited threads, and at least this thread.
+    assert_eq!(before[0].tid, 0);
+
+    // A busy thread shows up under its own TID, then in the exited bucket.
+    let busy = std::thread::spawn(|| {
+        let start = std::time::Instant::now();
+        while start.elapsed() < Duration::from_millis(20) {
+            core::hint::spin_loop();
+        }
+    });
+    busy.join().unwrap();
+
+    // The joiner is woken before the exited thread is folded into the bucket.
+    let mut after = vec![ThreadCpuUsage::default(); 64];
+    let (filled, available) = loop {
+        let (filled, available) = SysRay::query_threads(pid, &mut after).unwrap();
+        if after[0].cpu_uspace > before[0].cpu_uspace {
+            break (filled, available);
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    };
+    assert!(filled <= available);
+    assert!(after[1..filled].windows(2).all(|w| w[0].tid < w[1].tid));
+
+    // The threads add up to at most the process total.
+    let threads_total: u64 = after[0..filled]
+        .iter()
+        .map(|t| t.cpu_uspace + t.cpu_kernel)
+        .sum();
+    let mut percpu = vec![0_u64; moto_sys::num_cpus() as usize];
+    let num_cpus = SysRay::query_percpu_usage(pid, &mut percpu).unwrap();
+    if filled == available {
+        assert!(threads_total <= percpu[0..num_cpus].iter().sum());
+    }
+
+    assert_eq!(
+        SysRay::query_threads(pid, &mut []).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+
+    println!("test_thread_cpu_usage() PASS");
+}
+
+fn test_thread_cpu_time() {
+    use moto_rt::time::Instant;
+    use moto_sys::SysCpu;
+
+    std::thread::spawn(|| {
+        // Spinning is counted in uspace.
+        let (uspace_start, kernel_start) = SysCpu::thread_cpu_time().unwrap();
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(30) {
+            core::hint::spin_loop();
+        }
+        let spun = Instant::now().as_u64() - start.as_u64();
+        let (uspace_spun, kernel_spun) = SysCpu::thread_cpu_time().unwrap();
+        assert!(uspace_spun - uspace_start >= spun / 2);
+        assert!(kernel_spun >= kernel_start);
+
+        // Sleeping is not.
+        let start = Instant::now();
+        std::thread::sleep(Duration::from_millis(30));
+        let slept = Instant::now().as_u64() - start.as_u64();
+        let (uspace_slept, kernel_slept) = SysCpu::thread_cpu_time().unwrap();
+        assert!(uspace_slept >= uspace_spun && kernel_slept >= kernel_spun);
+        assert!((uspace_slept - uspace_spun) + (kernel_slept - kernel_spun) < slept / 2);
+    })
+    .join()
+    .unwrap();
+
+    // Another thread's spinning is not counted for this one.
+    let (uspace_before, _) = SysCpu::thread_cpu_time().unwrap();
+    let start = Instant::now();
+    std::thread::spawn(|| {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(30) {
+            core::hint::spin_loop();
+        }
+    })
+    .join()
+    .unwrap();
+    let elapsed = Instant::now().as_u64() - start.as_u64();
+    let (uspace_after, _) = SysCpu::thread_cpu_time().unwrap();
+    assert!(uspace_after - uspace_before < elapsed / 2);
+
+    println!("test_thread_cpu_time() PASS");
+}
+
+fn test_percpu_migrations() {
+    use moto_sys::stats::CpuMigrations;
+    use moto_sys::{SysCpu, SysRay};
+
+    let num_cpus = moto_sys::num_cpus() as usize;
+    if num_cpus < 3 {
+        println!("test_percpu_migrations() SKIPPED: needs 3+ CPUs");
+        return;
+    }
+
+    let pid = moto_sys::current_pid();
+    let query = || {
+        let mut migrations = vec![CpuMigrations::default(); num_cpus];
+        assert_eq!(
+            SysRay::query_percpu_migrations(pid, &mut migrations).unwrap(),
+            num_cpus
+        );
+        migrations
+    };
+    assert_eq!(
+        SysRay::query_percpu_migrations(pid, &mut vec![CpuMigrations::default(); num_cpus - 1])
+            .unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+
+    // A thread moved from CPU 1 to CPU 2 (CPU 0 is reserved) migrates out
+    // of CPU 1 and into CPU 2.
+    let (before, after) = std::thread::spawn(move || {
+        SysCpu::affine_to_cpu(Some(1)).unwrap();
+        std::thread::yield_now();
+        let before = query();
+        SysCpu::affine_to_cpu(Some(2)).unwrap();
+        std::thread::yield_now();
+        let after = query();
+        SysCpu::affine_to_cpu(None).unwrap();
+        (before, after)
+    })
+    .join()
+    .unwrap();
+
+    assert!(after[1].migrations_out > before[1].migrations_out);
+    assert!(after[2].migrations_in > before[2].migrations_in);
+
+    println!("test_percpu_migrations() PASS");
+}
+
 fn test_ipc() {
     use moto_ipc::sync::*;
 
@@ -196,270 +434,3613 @@ fn test_ipc() {
     );
 }
 
-fn test_pipes() {
-    use moto_sys::syscalls::*;
-    std::thread::sleep(std::time::Duration::from_millis(1000));
-
-    let (d1, d2) = moto_ipc::sync_pipe::make_pair(SysHandle::SELF, SysHandle::SELF).unwrap();
+fn test_submit_sqe_blocking() {
+    use moto_ipc::io_channel::*;
+    use moto_sys::SysCpu;
 
-    let mut reader = unsafe { moto_ipc::sync_pipe::Reader::new(d1) };
-    let mut writer = unsafe { moto_ipc::sync_pipe::Writer::new(d2) };
+    // More than fits into the queue, so that some submits have to wait.
+    const NOOPS: u64 = QUEUE_SIZE * 4;
 
-    let reader_thread = std::thread::spawn(move || {
-        let mut step = 1_usize;
-        loop {
-            let mut buf: Vec<u8> = vec![];
-            buf.resize(step % 8176 + 17, 0);
+    let conn = ClientConnection::connect("sys-io").unwrap();
+    for step in 0..NOOPS {
+        let mut sqe = Msg::new();
+        sqe.command = CMD_NOOP_OK;
+        sqe.id = step;
+        let policy = if step % 2 == 0 {
+            RetryPolicy::Wait
+        } else {
+            RetryPolicy::SpinThenWait(100)
+        };
+        let timeout = moto_rt::time::Instant::now() + Duration::from_secs(5);
+        conn.submit_sqe_blocking(sqe, Some(timeout), policy)
+            .unwrap();
+    }
 
-            let read = reader.read(buf.as_mut_slice()).unwrap();
-            assert!(read > 0);
-            if buf[read - 1] == 0 {
-                break;
+    let mut ids = 0;
+    let mut completions = 0;
+    while completions < NOOPS {
+        match conn.recv() {
+            Ok(cqe) => {
+                assert_eq!(cqe.status(), moto_rt::E_OK);
+                ids += cqe.id;
+                completions += 1;
             }
+            Err(err) => {
+                assert_eq!(err, moto_rt::E_NOT_READY);
+                SysCpu::wait(
+                    &mut [conn.server_handle()],
+                    SysHandle::NONE,
+                    conn.server_handle(),
+                    None,
+                )
+                .unwrap();
+            }
+        }
+    }
+    assert_eq!(ids, NOOPS * (NOOPS - 1) / 2);
 
-            step += 1;
+    println!("test_submit_sqe_blocking() PASS");
+}
+
+fn test_recv_batch() {
+    use moto_ipc::io_channel::*;
+    use moto_sys::SysCpu;
+
+    const NOOPS: u64 = QUEUE_SIZE / 2;
+
+    let conn = ClientConnection::connect("sys-io").unwrap();
+    let mut cqes = [Msg::new(); 8];
+    assert_eq!(conn.recv_batch(&mut cqes).unwrap(), 0);
+    assert_eq!(conn.recv_batch(&mut []).unwrap(), 0);
+
+    let sqes: Vec<Msg> = (0..NOOPS)
+        .map(|step| {
+            let mut sqe = Msg::new();
+            sqe.command = CMD_NOOP_OK;
+            sqe.id = step;
+            sqe
+        })
+        .collect();
+    assert_eq!(conn.send_batch(&sqes).unwrap(), NOOPS as usize);
+
+    let mut ids = 0;
+    let mut completions = 0;
+    while completions < NOOPS {
+        let received = conn.recv_batch(&mut cqes).unwrap();
+        if received == 0 {
+            SysCpu::wait(
+                &mut [conn.server_handle()],
+                SysHandle::NONE,
+                SysHandle::NONE,
+                None,
+            )
+            .unwrap();
+            continue;
         }
 
-        reader.total_read()
-    });
+        assert!(received <= cqes.len());
+        for cqe in &cqes[0..received] {
+            assert_eq!(cqe.status(), moto_rt::E_OK);
+            ids += cqe.id;
+        }
+        completions += received as u64;
+    }
+    assert_eq!(ids, NOOPS * (NOOPS - 1) / 2);
+    assert_eq!(conn.recv_batch(&mut cqes).unwrap(), 0);
 
-    let writer_thread = std::thread::spawn(move || {
-        for step in 1_usize..8000_usize {
-            let mut buf = vec![];
+    println!("test_recv_batch() PASS");
+}
 
-            for _idx in 0..step {
-                buf.push(7_u8);
+fn test_io_channel_send_batch() {
+    use moto_ipc::io_channel::*;
+    use moto_sys::SysCpu;
+
+    const URL: &str = "systest_io_channel_send_batch";
+    const BATCH: u64 = 8;
+
+    let server_started = Arc::new(AtomicBool::new(false));
+    let server_watcher = server_started.clone();
+    let server_thread = std::thread::spawn(move || {
+        let mut server = ServerConnection::create(URL).unwrap();
+        assert_eq!(
+            server.send_batch(&[Msg::new()]).unwrap_err(),
+            moto_rt::E_INVALID_ARGUMENT
+        );
+        server_started.store(true, Ordering::Release);
+        SysCpu::wait(
+            &mut [server.wait_handle()],
+            SysHandle::NONE,
+            SysHandle::NONE,
+            None,
+        )
+        .unwrap();
+        unsafe { server.accept().unwrap() };
+
+        let recv_all = |count: u64| {
+            let mut sqes = Vec::new();
+            while (sqes.len() as u64) < count {
+                match server.recv() {
+                    Ok(sqe) => sqes.push(sqe),
+                    Err(err) => {
+                        assert_eq!(err, moto_rt::E_NOT_READY);
+                        SysCpu::wait(
+                            &mut [server.wait_handle()],
+                            SysHandle::NONE,
+                            SysHandle::NONE,
+                            None,
+                        )
+                        .unwrap();
+                    }
+                }
             }
-            assert_eq!(writer.write(buf.as_slice()).unwrap(), step);
+            sqes
+        };
+
+        // Complete in reverse: the client must see CQEs in slice order.
+        let mut cqes = recv_all(BATCH);
+        cqes.reverse();
+        for cqe in &mut cqes {
+            cqe.status = moto_rt::E_OK;
         }
+        assert_eq!(server.send_batch(&[]).unwrap(), 0);
+        assert_eq!(server.send_batch(&cqes).unwrap(), BATCH as usize);
 
-        assert_eq!(1, writer.write(&[0_u8; 1]).unwrap());
-        writer.total_written()
+        // The client does not drain its queue until woken, so the batch
+        // stops at the first CQE that does not fit.
+        let mut cqe = recv_all(1)[0];
+        cqe.status = moto_rt::E_OK;
+        let cqes = vec![cqe; QUEUE_SIZE as usize + 1];
+        assert_eq!(server.send_batch(&cqes).unwrap(), QUEUE_SIZE as usize);
     });
 
-    let read = reader_thread.join().unwrap();
-    let written = writer_thread.join().unwrap();
+    while !server_watcher.load(Ordering::Acquire) {
+        std::hint::spin_loop();
+    }
 
-    assert_eq!(read, written);
+    let conn = ClientConnection::connect(URL).unwrap();
+    let sqes: Vec<Msg> = (0..BATCH)
+        .map(|id| {
+            let mut sqe = Msg::new();
+            sqe.command = CMD_NOOP_OK;
+            sqe.id = id;
+            sqe
+        })
+        .collect();
+    assert_eq!(conn.send_batch(&sqes).unwrap(), BATCH as usize);
 
-    println!("test_pipes PASS");
-}
+    // The server enqueues the whole batch before its single wakeup, so the
+    // first non-empty receive after a wakeup gets all of it.
+    let mut cqes = [Msg::new(); BATCH as usize * 2];
+    let received = loop {
+        SysCpu::wait(
+            &mut [conn.server_handle()],
+            SysHandle::NONE,
+            SysHandle::NONE,
+            None,
+        )
+        .unwrap();
+        let received = conn.recv_batch(&mut cqes).unwrap();
+        if received > 0 {
+            break received;
+        }
+    };
+    assert_eq!(received, BATCH as usize);
+    for (idx, cqe) in cqes[0..received].iter().enumerate() {
+        assert_eq!(cqe.id, BATCH - 1 - idx as u64);
+        assert_eq!(cqe.status(), moto_rt::E_OK);
+    }
 
-fn test_thread() {
-    use std::sync::atomic::AtomicU64;
-    let atomic = AtomicU64::new(0);
-    let atomic_ref = unsafe { (&atomic as *const AtomicU64).as_ref::<'static>().unwrap() };
+    let mut sqe = Msg::new();
+    sqe.command = CMD_NOOP_OK;
+    sqe.id = BATCH;
+    assert_eq!(conn.send_batch(&[sqe]).unwrap(), 1);
 
-    std::thread::spawn(|| {
-        atomic_ref.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-    })
-    .join()
-    .unwrap();
+    let mut completions = 0;
+    while completions < QUEUE_SIZE {
+        match conn.recv() {
+            Ok(cqe) => {
+                assert_eq!(cqe.id, BATCH);
+                completions += 1;
+            }
+            Err(err) => {
+                assert_eq!(err, moto_rt::E_NOT_READY);
+                SysCpu::wait(
+                    &mut [conn.server_handle()],
+                    SysHandle::NONE,
+                    SysHandle::NONE,
+                    None,
+                )
+                .unwrap();
+            }
+        }
+    }
+    server_thread.join().unwrap();
+    assert!(conn.recv().is_err());
 
-    assert_eq!(1, atomic.load(std::sync::atomic::Ordering::Relaxed));
-    println!("test_thread PASS");
+    println!("test_io_channel_send_batch() PASS");
 }
 
-fn test_lazy_memory_map() {
-    use moto_sys::*;
+fn test_channel_capacity() {
+    use moto_ipc::io_channel::*;
 
-    let addr = SysMem::map(
-        SysHandle::SELF,
-        SysMem::F_READABLE | SysMem::F_WRITABLE | SysMem::F_LAZY,
-        u64::MAX,
-        u64::MAX,
-        sys_mem::PAGE_SIZE_SMALL,
-        1,
-    )
-    .unwrap();
-    // let prev_log_level = moto_sys::syscalls::SysCtl::set_log_level(4).unwrap();
-    let buf = unsafe {
-        core::slice::from_raw_parts_mut(addr as usize as *mut u8, sys_mem::PAGE_SIZE_SMALL as usize)
-    };
-    for idx in 0..buf.len() {
-        assert_eq!(0, buf[idx]);
-    }
-    std::thread::sleep(std::time::Duration::from_millis(100));
-    for idx in 0..buf.len() {
-        buf[idx] = (idx % (u8::MAX as usize)) as u8;
-        assert_eq!(buf[idx], (idx % (u8::MAX as usize)) as u8);
+    let conn = ClientConnection::connect("sys-io").unwrap();
+    assert_eq!(conn.free_pages(u64::MAX), CHANNEL_PAGE_COUNT);
+    let pages = conn.alloc_pages(u64::MAX, 5).unwrap();
+    assert_eq!(conn.free_pages(u64::MAX), CHANNEL_PAGE_COUNT - 5);
+    assert_eq!(conn.free_pages(0b111), 0);
+    let rest = conn
+        .alloc_pages(u64::MAX, conn.free_pages(u64::MAX))
+        .unwrap();
+    assert_eq!(conn.alloc_page(u64::MAX).unwrap_err(), moto_rt::E_NOT_READY);
+    core::mem::drop(pages);
+    core::mem::drop(rest);
+    assert_eq!(conn.free_pages(u64::MAX), CHANNEL_PAGE_COUNT);
+
+    // The server is not woken below, but may still drain the queue: it can
+    // only take more messages, never fewer, than reported.
+    let space = conn.send_queue_space();
+    assert_eq!(space, QUEUE_SIZE as usize);
+    let mut sent = 0;
+    loop {
+        let mut sqe = Msg::new();
+        sqe.command = CMD_NOOP_OK;
+        if conn.send(sqe).is_err() || sent == QUEUE_SIZE as usize * 2 {
+            break;
+        }
+        sent += 1;
     }
+    assert!(sent >= space);
 
-    // moto_sys::syscalls::SysCtl::set_log_level(prev_log_level).unwrap();
-    SysMem::free(addr).unwrap();
-    println!("test_lazy_memory_map: done");
+    // From two threads: the other one freeing pages of this subchannel, allocating
+    // in another one, and letting the server drain the queue, only adds capacity.
+    const MINE: u64 = 0x0000_0000_ffff_ffff;
+    const THEIRS: u64 = !MINE;
+    let (page_tx, page_rx) = std::sync::mpsc::channel::<Vec<IoPage>>();
+    let stop = std::sync::atomic::AtomicBool::new(false);
+    let (conn, stop) = (&conn, &stop);
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            while !stop.load(Ordering::Acquire) {
+                while let Ok(pages) = page_rx.try_recv() {
+                    core::mem::drop(pages);
+                }
+                if let Ok(pages) = conn.alloc_pages(THEIRS, 7) {
+                    core::mem::drop(pages);
+                }
+                let _ = moto_sys::SysCpu::wake(conn.server_handle());
+                while conn.recv().is_ok() {}
+            }
+        });
+
+        for _ in 0..1000 {
+            let free = conn.free_pages(MINE);
+            if free > 0 {
+                page_tx.send(conn.alloc_pages(MINE, free).unwrap()).unwrap();
+            }
+            for _ in 0..conn.send_queue_space() {
+                let mut sqe = Msg::new();
+                sqe.command = CMD_NOOP_OK;
+                conn.send(sqe).unwrap();
+            }
+        }
+        stop.store(true, Ordering::Release);
+    });
+
+    println!("test_channel_capacity() PASS");
 }
 
-fn stress_test_threads() {
-    // Basically, do some cpu-bound stuff and
-    // verify that preemption does not mess up registers.
-    println!("stress_test_threads starting...");
-    const THREADS: usize = 8;
+// The client sends SQEs and shuts down; the server completes them and
+// shuts down once it sees the client's shutdown, or, if @concurrent,
+// right after its first completion, without waiting for the client.
+fn do_test_io_channel_shutdown(url: &'static str, server_first: bool) {
+    use moto_ipc::io_channel::*;
+    use moto_sys::SysCpu;
 
-    let shutdown = AtomicBool::new(false);
-    let shutdown_ref = unsafe {
-        (&shutdown as *const AtomicBool)
-            .as_ref::<'static>()
-            .unwrap()
-    };
+    const SQES: u64 = QUEUE_SIZE / 2;
 
-    let mut handles = Vec::with_capacity(THREADS);
+    let server_started = Arc::new(AtomicBool::new(false));
+    let server_watcher = server_started.clone();
+    let server_thread = std::thread::spawn(move || {
+        let mut server = ServerConnection::create(url).unwrap();
+        server_started.store(true, Ordering::Release);
+        SysCpu::wait(
+            &mut [server.wait_handle()],
+            SysHandle::NONE,
+            SysHandle::NONE,
+            None,
+        )
+        .unwrap();
+        unsafe { server.accept().unwrap() };
 
-    for _ in 0..THREADS {
-        handles.push(std::thread::spawn(|| {
-            let mut counter: u64 = 0;
-            while !shutdown_ref.load(std::sync::atomic::Ordering::Relaxed) {
-                #[cfg(feature = "test_sse")]
-                {
-                    let af64: f64 = f64::from((counter + 1) as u32);
-                    let bf64: f64 = f64::from((counter + 2) as u32);
-                    let mut res_f64: f64;
+        let mut received = 0;
+        let mut completed = 0;
+        loop {
+            match server.recv() {
+                Ok(mut sqe) => {
+                    received += 1;
+                    sqe.status = moto_rt::E_OK;
+                    // SQEs received after shutdown() are completed as well.
+                    loop {
+                        match server.send_batch(&[sqe]) {
+                            Ok(1) => break,
+                            Ok(_) => SysCpu::wait(
+                                &mut [server.wait_handle()],
+                                SysHandle::NONE,
+                                SysHandle::NONE,
+                                None,
+                            )
+                            .unwrap(),
+                            Err(err) => panic!("send_batch: {err}"),
+                        }
+                    }
+                    completed += 1;
+                    if server_first && completed == 1 {
+                        server.shutdown().unwrap();
+                        assert_eq!(server.shutdown().unwrap_err(), moto_rt::E_NOT_CONNECTED);
+                    }
+                }
+                Err(moto_rt::E_NOT_READY) => {
+                    SysCpu::wait(
+                        &mut [server.wait_handle()],
+                        SysHandle::NONE,
+                        SysHandle::NONE,
+                        None,
+                    )
+                    .unwrap();
                 }
+                Err(err) => {
+                    // SQEs are shut down, and all that were sent are here.
+                    assert_eq!(err, moto_rt::E_NOT_CONNECTED);
+                    break;
+                }
+            }
+        }
+        server.shutdown_completions().unwrap();
+        assert_eq!(
+            server.shutdown_completions().unwrap_err(),
+            moto_rt::E_NOT_CONNECTED
+        );
+        assert_eq!(server.recv().unwrap_err(), moto_rt::E_NOT_CONNECTED);
+        assert_eq!(
+            server.send(Msg::new()).unwrap_err(),
+            moto_rt::E_NOT_CONNECTED
+        );
 
-                let mut result: u64;
-                unsafe {
-                    std::arch::asm!(
-                                "
-                        mov rcx, rax
-                        mov rdx, rax
-                        mov rsi, rax
-                        mov rdi, rax
-                        mov r8, rax
-                        mov r9, rax
-                        mov r10, rax
-                        mov r11, rax
-                        mov r12, rax
-                        mov r13, rax
-                        mov r14, rax
-                        mov r15, rax
+        // Keep the connection until the client is done with it.
+        let _ = SysCpu::wait(
+            &mut [server.wait_handle()],
+            SysHandle::NONE,
+            SysHandle::NONE,
+            Some(moto_rt::time::Instant::now() + Duration::from_secs(5)),
+        );
+        (received, completed)
+    });
 
-                        add r15, rax
-                        add r15, rcx
-                        add r15, rdx
-                        add r15, rsi
-                        add r15, rdi
-                        add r15, r8
-                        add r15, r9
-                        add r15, r10
-                        add r15, r11
-                        add r15, r12
-                        add r15, r13
-                        add r15, r14
-                        ",
-                        in("rax") counter,
-                        out("rcx") _,
-                        out("rdx") _,
-                        out("rsi") _,
-                        out("rdi") _,
-                        out("r8") _,
-                        out("r9") _,
-                        out("r10") _,
-                        out("r11") _,
-                        out("r12") _,
-                        out("r13") _,
-                        out("r14") _,
-                        out("r15") result,
-                        options(nomem, nostack)
-                    );
+    while !server_watcher.load(Ordering::Acquire) {
+        std::hint::spin_loop();
+    }
+    let conn = ClientConnection::connect(url).unwrap();
+    let mut sent = 0;
+    loop {
+        if !server_first && sent == SQES {
+            conn.shutdown().unwrap();
+            break;
+        }
 
-                    #[cfg(feature = "test_sse")]
-                    {
-                        std::arch::asm!(
-                            "addsd {0}, {1}",
-                            inlateout(xmm_reg) af64 => res_f64,
-                            in(xmm_reg) bf64,
-                        );
-                    }
-                }
-                #[cfg(feature = "test_sse")]
-                {
-                    res_f64 *= 13.3;
-                    assert!((res_f64 as i64) < (((counter + 1) * 14) as i64));
+        let mut sqe = Msg::new();
+        sqe.command = CMD_NOOP_OK;
+        sqe.id = sent;
+        match conn.submit_sqe_blocking(sqe, None, RetryPolicy::Wait) {
+            Ok(()) => sent += 1,
+            Err(err) => {
+                // The server has stopped accepting SQEs.
+                assert!(server_first);
+                assert_eq!(err, moto_rt::E_NOT_CONNECTED);
+                break;
+            }
+        }
+    }
+    assert_eq!(conn.shutdown().unwrap_err(), moto_rt::E_NOT_CONNECTED);
+    assert_eq!(conn.send(Msg::new()).unwrap_err(), moto_rt::E_NOT_CONNECTED);
+    assert_eq!(
+        conn.submit_sqe_blocking(Msg::new(), None, RetryPolicy::Wait)
+            .unwrap_err(),
+        moto_rt::E_NOT_CONNECTED
+    );
+
+    // CQEs come in order, for every SQE sent, until the server shuts down
+    // its completions.
+    let mut cqes = 0;
+    loop {
+        match conn.recv() {
+            Ok(cqe) => {
+                assert_eq!(cqe.id, cqes);
+                cqes += 1;
+            }
+            Err(moto_rt::E_NOT_READY) => {
+                // The server may be waiting for CQE queue space.
+                SysCpu::wake(conn.server_handle()).unwrap();
+                SysCpu::wait(
+                    &mut [conn.server_handle()],
+                    SysHandle::NONE,
+                    SysHandle::NONE,
+                    None,
+                )
+                .unwrap();
+            }
+            Err(err) => {
+                assert_eq!(err, moto_rt::E_NOT_CONNECTED);
+                break;
+            }
+        }
+    }
+    SysCpu::wake(conn.server_handle()).unwrap();
+
+    let (received, completed) = server_thread.join().unwrap();
+    assert!(sent >= 1);
+    assert_eq!(received, sent);
+    assert_eq!(completed, sent);
+    assert_eq!(cqes, sent);
+}
+
+fn test_io_channel_shutdown() {
+    do_test_io_channel_shutdown("systest_shutdown", false);
+    do_test_io_channel_shutdown("systest_shutdown_server_first", true);
+
+    println!("test_io_channel_shutdown() PASS");
+}
+
+fn test_shared_page_count() {
+    use moto_ipc::io_channel::*;
+
+    let mut msg = Msg::new();
+    msg.payload.shared_pages_mut()[0..4].fill(NO_PAGE);
+    assert_eq!(msg.payload.shared_page_count(4).unwrap(), 0);
+
+    msg.payload.shared_pages_mut()[0] = 7;
+    msg.payload.shared_pages_mut()[1] = 3;
+    assert_eq!(msg.payload.shared_page_count(4).unwrap(), 2);
+
+    // A page after an unused slot.
+    msg.payload.shared_pages_mut()[1] = NO_PAGE;
+    msg.payload.shared_pages_mut()[2] = 3;
+    assert_eq!(
+        msg.payload.shared_page_count(4).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+
+    msg.payload.shared_pages_mut()[1] = 5;
+    msg.payload.shared_pages_mut()[3] = 1;
+    assert_eq!(msg.payload.shared_page_count(4).unwrap(), 4);
+
+    println!("test_shared_page_count() PASS");
+}
+
+fn test_attach_page() {
+    use moto_ipc::io_channel::*;
+    use moto_sys::SysCpu;
+
+    const URL: &str = "systest_attach_page";
+
+    let server_started = Arc::new(AtomicBool::new(false));
+    let server_watcher = server_started.clone();
+    let server_thread = std::thread::spawn(move || {
+        let mut server = ServerConnection::create(URL).unwrap();
+        server_started.store(true, Ordering::Release);
+        SysCpu::wait(
+            &mut [server.wait_handle()],
+            SysHandle::NONE,
+            SysHandle::NONE,
+            None,
+        )
+        .unwrap();
+        unsafe { server.accept().unwrap() };
+
+        let mut sqe = loop {
+            match server.recv() {
+                Ok(sqe) => break sqe,
+                Err(err) => {
+                    assert_eq!(err, moto_rt::E_NOT_READY);
+                    SysCpu::wait(
+                        &mut [server.wait_handle()],
+                        SysHandle::NONE,
+                        SysHandle::NONE,
+                        None,
+                    )
+                    .unwrap();
                 }
+            }
+        };
 
-                assert_eq!(result, counter * 13);
+        // The client's page cannot be attached; it is freed instead.
+        let client_page = server.get_page(sqe.payload.shared_pages()[0]).unwrap();
+        assert_eq!(
+            ServerConnection::attach_page(&mut sqe, 0, client_page).unwrap_err(),
+            moto_rt::E_INVALID_ARGUMENT
+        );
 
-                counter += 1;
-                if counter > 1_000_000_000 {
-                    counter = 0;
+        let in_use = server.pool_usage().in_use;
+        let page = server.alloc_page(u64::MAX).unwrap();
+        assert_eq!(
+            ServerConnection::attach_page(&mut sqe, 12, page).unwrap_err(),
+            moto_rt::E_INVALID_ARGUMENT
+        );
+        assert_eq!(server.pool_usage().in_use, in_use);
+
+        let page = server.alloc_page(u64::MAX).unwrap();
+        page.bytes_mut()[0..5].copy_from_slice(b"hello");
+        sqe.payload.shared_pages_mut().fill(NO_PAGE);
+        ServerConnection::attach_page(&mut sqe, 0, page).unwrap();
+        sqe.status = moto_rt::E_OK;
+        server.send(sqe).unwrap();
+
+        // Keep the connection until the client is done with it.
+        let _ = SysCpu::wait(
+            &mut [server.wait_handle()],
+            SysHandle::NONE,
+            SysHandle::NONE,
+            Some(moto_rt::time::Instant::now() + Duration::from_secs(5)),
+        );
+    });
+
+    while !server_watcher.load(Ordering::Acquire) {
+        std::hint::spin_loop();
+    }
+    let conn = ClientConnection::connect(URL).unwrap();
+    let mut sqe = Msg::new();
+    sqe.command = CMD_NOOP_OK;
+    sqe.payload.shared_pages_mut()[0] = IoPage::into_u16(conn.alloc_page(u64::MAX).unwrap());
+    conn.submit_sqe_blocking(sqe, None, RetryPolicy::Wait)
+        .unwrap();
+
+    let cqe = loop {
+        match conn.recv() {
+            Ok(cqe) => break cqe,
+            Err(err) => {
+                assert_eq!(err, moto_rt::E_NOT_READY);
+                SysCpu::wait(
+                    &mut [conn.server_handle()],
+                    SysHandle::NONE,
+                    SysHandle::NONE,
+                    None,
+                )
+                .unwrap();
+            }
+        }
+    };
+    assert_eq!(cqe.status(), moto_rt::E_OK);
+    // The rejected client page was freed by the server.
+    assert_eq!(conn.pool_usage().in_use, 0);
+    let page = conn.take_server_page(&cqe, 0).unwrap();
+    assert_eq!(&page.bytes()[0..5], b"hello");
+    core::mem::drop(page);
+
+    SysCpu::wake(conn.server_handle()).unwrap();
+    server_thread.join().unwrap();
+
+    println!("test_attach_page() PASS");
+}
+
+// Serves one client of @url on a new thread, completing each SQE with what
+// @handler returns, until the client sets @done and wakes the server.
+fn spawn_io_channel_server<F>(
+    url: &'static str,
+    done: Arc<AtomicBool>,
+    mut handler: F,
+) -> std::thread::JoinHandle<()>
+where
+    F: FnMut(
+            &mut moto_ipc::io_channel::ServerConnection,
+            moto_ipc::io_channel::Msg,
+        ) -> moto_ipc::io_channel::Msg
+        + Send
+        + 'static,
+{
+    use moto_ipc::io_channel::*;
+    use moto_sys::SysCpu;
+
+    let server_started = Arc::new(AtomicBool::new(false));
+    let server_watcher = server_started.clone();
+    let server_thread = std::thread::spawn(move || {
+        let mut server = ServerConnection::create(url).unwrap();
+        server_started.store(true, Ordering::Release);
+        SysCpu::wait(
+            &mut [server.wait_handle()],
+            SysHandle::NONE,
+            SysHandle::NONE,
+            None,
+        )
+        .unwrap();
+        unsafe { server.accept().unwrap() };
+
+        loop {
+            match server.recv() {
+                Ok(sqe) => {
+                    let cqe = handler(&mut server, sqe);
+                    assert_eq!(server.send_batch(&[cqe]).unwrap(), 1);
+                }
+                Err(err) => {
+                    assert_eq!(err, moto_rt::E_NOT_READY);
+                    if done.load(Ordering::Acquire) {
+                        break;
+                    }
+                    let _ = SysCpu::wait(
+                        &mut [server.wait_handle()],
+                        SysHandle::NONE,
+                        SysHandle::NONE,
+                        Some(moto_rt::time::Instant::now() + Duration::from_millis(100)),
+                    );
                 }
             }
-        }));
+        }
+    });
+
+    while !server_watcher.load(Ordering::Acquire) {
+        std::hint::spin_loop();
     }
+    server_thread
+}
 
-    std::thread::sleep(std::time::Duration::from_secs(2));
-    shutdown.store(true, Ordering::Release);
+// Submits @sqe and waits for its completion.
+fn io_channel_call(
+    conn: &moto_ipc::io_channel::ClientConnection,
+    sqe: moto_ipc::io_channel::Msg,
+) -> moto_ipc::io_channel::Msg {
+    use moto_ipc::io_channel::*;
+    use moto_sys::SysCpu;
 
-    for _ in 0..THREADS {
-        let handle = handles.pop().unwrap();
-        handle.join().unwrap();
+    conn.submit_sqe_blocking(sqe, None, RetryPolicy::Wait)
+        .unwrap();
+    loop {
+        match conn.recv() {
+            Ok(cqe) => return cqe,
+            Err(err) => {
+                assert_eq!(err, moto_rt::E_NOT_READY);
+                SysCpu::wait(
+                    &mut [conn.server_handle()],
+                    SysHandle::NONE,
+                    SysHandle::NONE,
+                    None,
+                )
+                .unwrap();
+            }
+        }
+    }
+}
+
+fn test_io_channel_checksums() {
+    use moto_ipc::io_channel::*;
+
+    const URL: &str = "systest_io_channel_checksums";
+
+    let done = Arc::new(AtomicBool::new(false));
+    let server_thread = spawn_io_channel_server(URL, done.clone(), |server, mut sqe| {
+        sqe.status = match server.get_page(sqe.payload.shared_pages()[0]) {
+            Ok(page) if &page.bytes()[0..5] == b"hello" => moto_rt::E_OK,
+            Ok(_) => moto_rt::E_INTERNAL_ERROR,
+            Err(err) => err,
+        };
+        sqe
+    });
+
+    let conn = ClientConnection::connect(URL).unwrap();
+    assert!(!conn.checksums_enabled());
+    conn.enable_checksums();
+    assert!(conn.checksums_enabled());
+
+    let send_page = |corrupt: bool| {
+        let page = conn.alloc_page(u64::MAX).unwrap();
+        page.bytes_mut()[0..5].copy_from_slice(b"hello");
+        let bytes = page.bytes_mut().as_mut_ptr();
+        let mut sqe = Msg::new();
+        sqe.command = CMD_NOOP_OK;
+        sqe.payload.shared_pages_mut()[0] = IoPage::into_u16(page);
+        if corrupt {
+            // After into_u16() has computed the checksum.
+            unsafe { *bytes = b'j' };
+        }
+        io_channel_call(&conn, sqe).status()
+    };
+    assert_eq!(send_page(false), moto_rt::E_OK);
+    assert_eq!(send_page(true), moto_rt::E_BAD_DATA);
+    // The server freed both pages, the corrupted one in get_page().
+    assert_eq!(conn.pool_usage().in_use, 0);
+
+    done.store(true, Ordering::Release);
+    moto_sys::SysCpu::wake(conn.server_handle()).unwrap();
+    server_thread.join().unwrap();
+
+    println!("test_io_channel_checksums() PASS");
+}
+
+fn test_io_channel_inline_data() {
+    use moto_ipc::io_channel::*;
+
+    const URL: &str = "systest_io_channel_inline_data";
+
+    let mut msg = Msg::new();
+    let max = [7_u8; Payload::INLINE_DATA_MAX];
+    msg.payload.set_inline_data(&max).unwrap();
+    assert_eq!(msg.payload.inline_data().unwrap(), &max);
+    assert_eq!(
+        msg.payload
+            .set_inline_data(&[0_u8; Payload::INLINE_DATA_MAX + 1])
+            .unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    assert_eq!(msg.payload.inline_data().unwrap(), &max);
+    msg.payload.set_inline_data(&[]).unwrap();
+    assert!(msg.payload.inline_data().unwrap().is_empty());
+    msg.payload.args_8_mut()[0] = (Payload::INLINE_DATA_MAX + 1) as u8;
+    assert_eq!(
+        msg.payload.inline_data().unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+
+    // The data gets to the peer without any pages; the server sends it back reversed.
+    let done = Arc::new(AtomicBool::new(false));
+    let server_thread = spawn_io_channel_server(URL, done.clone(), |_, mut sqe| {
+        let mut data = sqe.payload.inline_data().unwrap().to_vec();
+        data.reverse();
+        sqe.payload.set_inline_data(&data).unwrap();
+        sqe.status = moto_rt::E_OK;
+        sqe
+    });
+
+    let conn = ClientConnection::connect(URL).unwrap();
+    let mut sqe = Msg::new();
+    sqe.command = CMD_NOOP_OK;
+    sqe.payload.set_inline_data(b"hello").unwrap();
+    let cqe = io_channel_call(&conn, sqe);
+    assert_eq!(cqe.status(), moto_rt::E_OK);
+    assert_eq!(cqe.payload.inline_data().unwrap(), b"olleh");
+    assert_eq!(conn.pool_usage().high_water, 0);
+
+    done.store(true, Ordering::Release);
+    moto_sys::SysCpu::wake(conn.server_handle()).unwrap();
+    server_thread.join().unwrap();
+
+    println!("test_io_channel_inline_data() PASS");
+}
+
+fn test_io_channel_alignment() {
+    use moto_ipc::io_channel::*;
+
+    for bad in [0, 3, PAGE_ALIGNMENT * 2] {
+        assert_eq!(
+            ClientConnection::connect_aligned("sys-io", bad)
+                .err()
+                .unwrap(),
+            moto_rt::E_INVALID_ARGUMENT
+        );
+        assert_eq!(
+            ServerConnection::create_aligned("systest_io_channel_alignment", bad)
+                .err()
+                .unwrap(),
+            moto_rt::E_INVALID_ARGUMENT
+        );
+    }
+
+    for alignment in [512, PAGE_ALIGNMENT] {
+        let conn = ClientConnection::connect_aligned("sys-io", alignment).unwrap();
+        let pages = conn.alloc_pages(u64::MAX, 8).unwrap();
+        for page in &pages {
+            assert_eq!(page.bytes().as_ptr() as usize % alignment, 0);
+            assert_eq!(page.bytes().len(), PAGE_SIZE);
+        }
+    }
+
+    println!("test_io_channel_alignment() PASS");
+}
+
+fn test_io_channel_latency() {
+    use moto_ipc::io_channel::*;
+
+    const URL: &str = "systest_io_channel_latency";
+    const CMD_SLOW: u16 = CMD_RESERVED_MAX + 1;
+    const CMD_REPORT: u16 = CMD_RESERVED_MAX + 2;
+    const SLOW_REQUESTS: u64 = 4;
+
+    // Round trips, as seen by the client.
+    let mut conn = ClientConnection::connect("sys-io").unwrap();
+    assert!(conn.latency_histogram().is_none());
+    conn.enable_latency_histogram();
+    for id in 0..16 {
+        let mut sqe = Msg::new();
+        sqe.command = CMD_NOOP_OK;
+        sqe.id = id;
+        assert_eq!(io_channel_call(&conn, sqe).status(), moto_rt::E_OK);
+    }
+    let histogram = conn.latency_histogram().unwrap();
+    assert_eq!(histogram.count(), 16);
+    assert_eq!(histogram.untracked, 0);
+    assert!(histogram.percentile(50.0).unwrap() <= histogram.percentile(100.0).unwrap());
+    conn.reset_latency_histogram();
+    assert_eq!(conn.latency_histogram().unwrap().count(), 0);
+    assert!(conn.latency_histogram().unwrap().percentile(50.0).is_none());
+    core::mem::drop(conn);
+
+    // Service times, as seen by the server, which enables recording on the
+    // first request (which is thus not recorded).
+    let done = Arc::new(AtomicBool::new(false));
+    let server_thread = spawn_io_channel_server(URL, done.clone(), |server, mut sqe| {
+        match server.latency_histogram() {
+            None => server.enable_latency_histogram(),
+            Some(histogram) if sqe.command == CMD_REPORT => {
+                sqe.payload.args_64_mut()[0] = histogram.count();
+                sqe.payload.args_64_mut()[1] = histogram.percentile(0.0).unwrap().as_nanos() as u64;
+            }
+            Some(_) => std::thread::sleep(Duration::from_millis(2)),
+        }
+        sqe.status = moto_rt::E_OK;
+        sqe
+    });
+
+    let conn = ClientConnection::connect(URL).unwrap();
+    for id in 0..(SLOW_REQUESTS + 2) {
+        let mut sqe = Msg::new();
+        sqe.command = if id == SLOW_REQUESTS + 1 {
+            CMD_REPORT
+        } else {
+            CMD_SLOW
+        };
+        sqe.id = id;
+        let cqe = io_channel_call(&conn, sqe);
+        assert_eq!(cqe.status(), moto_rt::E_OK);
+        if sqe.command == CMD_REPORT {
+            // The report itself is not complete yet.
+            assert_eq!(cqe.payload.args_64()[0], SLOW_REQUESTS);
+            // Even the fastest request took longer than the sleep.
+            assert!(cqe.payload.args_64()[1] >= 2_000_000);
+        }
+    }
+
+    done.store(true, Ordering::Release);
+    moto_sys::SysCpu::wake(conn.server_handle()).unwrap();
+    server_thread.join().unwrap();
+
+    println!("test_io_channel_latency() PASS");
+}
+
+fn test_io_channel_peer_credentials() {
+    use moto_ipc::io_channel::*;
+
+    const URL: &str = "systest_io_channel_peer_credentials";
+
+    let me = (
+        moto_sys::current_pid(),
+        moto_sys::ProcessStaticPage::get().capabilities,
+    );
+
+    let done = Arc::new(AtomicBool::new(false));
+    let server_thread = spawn_io_channel_server(URL, done.clone(), |server, mut sqe| {
+        let (pid, caps) = server.peer_credentials().unwrap();
+        sqe.payload.args_64_mut()[0] = pid;
+        sqe.payload.args_64_mut()[1] = caps;
+        sqe.status = moto_rt::E_OK;
+        sqe
+    });
+
+    // Both ends are in this process.
+    let conn = ClientConnection::connect(URL).unwrap();
+    assert_eq!(conn.peer_credentials().unwrap(), me);
+    let mut sqe = Msg::new();
+    sqe.command = CMD_NOOP_OK;
+    let cqe = io_channel_call(&conn, sqe);
+    assert_eq!((cqe.payload.args_64()[0], cqe.payload.args_64()[1]), me);
+
+    done.store(true, Ordering::Release);
+    moto_sys::SysCpu::wake(conn.server_handle()).unwrap();
+    server_thread.join().unwrap();
+
+    // sys-io is not.
+    let conn = ClientConnection::connect("sys-io").unwrap();
+    let (pid, _) = conn.peer_credentials().unwrap();
+    assert_ne!(pid, me.0);
+    let mut stats = [moto_sys::stats::ProcessStatsV1::default()];
+    assert_eq!(
+        moto_sys::stats::ProcessStatsV1::list(pid, &mut stats).unwrap(),
+        1
+    );
+    assert_eq!(stats[0].pid, pid);
+
+    println!("test_io_channel_peer_credentials() PASS");
+}
+
+fn test_percpu_usage() {
+    use moto_sys::{SysCpu, SysRay};
+
+    let num_cpus = moto_sys::num_cpus() as usize;
+    let pid = moto_sys::current_pid();
+    assert_eq!(
+        SysRay::query_percpu_usage(pid, &mut vec![0; num_cpus - 1]).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    assert_eq!(
+        SysRay::query_percpu_usage(u64::MAX, &mut vec![0; num_cpus]).unwrap_err(),
+        moto_rt::E_NOT_FOUND
+    );
+    if num_cpus < 2 {
+        println!("test_percpu_usage() SKIPPED: need at least 2 CPUs");
+        return;
+    }
+
+    // Time spent spinning on a CPU is counted on that CPU.
+    let cpu = num_cpus - 1; // Not CPU 0, which only the IO_MANAGER can affine to.
+    let (before, after, spun) = std::thread::spawn(move || {
+        SysCpu::affine_to_cpu(Some(cpu as u32)).unwrap();
+        std::thread::yield_now();
+        let mut before = vec![0_u64; num_cpus];
+        assert_eq!(
+            SysRay::query_percpu_usage(pid, &mut before).unwrap(),
+            num_cpus
+        );
+        let start = moto_rt::time::Instant::now();
+        while start.elapsed() < Duration::from_millis(50) {
+            core::hint::spin_loop();
+        }
+        let spun = moto_rt::time::Instant::now().as_u64() - start.as_u64();
+        let mut after = vec![0_u64; num_cpus];
+        SysRay::query_percpu_usage(pid, &mut after).unwrap();
+        SysCpu::affine_to_cpu(None).unwrap();
+        (before, after, spun)
+    })
+    .join()
+    .unwrap();
+
+    assert!(before.iter().zip(after.iter()).all(|(b, a)| a >= b));
+    assert!(after[cpu] - before[cpu] >= spun / 2);
+
+    println!("test_percpu_usage() PASS");
+}
+
+fn test_cpu_sampling() {
+    use moto_sys::stats::CpuSample;
+    use moto_sys::{SysCpu, SysRay};
+
+    let mut samples = vec![CpuSample::default(); 256];
+    if moto_sys::ProcessStaticPage::get().capabilities & moto_sys::caps::CAP_SYS == 0 {
+        assert_eq!(
+            SysRay::set_cpu_sampling(true).unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+        assert_eq!(
+            SysRay::cpu_samples(0, 0, &mut samples).unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+        println!("test_cpu_sampling() SKIPPED: needs CAP_SYS");
+        return;
+    }
+    let num_cpus = moto_sys::num_cpus();
+    if num_cpus < 2 {
+        println!("test_cpu_sampling() SKIPPED: need at least 2 CPUs");
+        return;
+    }
+
+    assert_eq!(
+        SysRay::cpu_samples(num_cpus, 0, &mut samples).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    assert_eq!(
+        SysRay::cpu_samples(0, 0, &mut []).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+
+    // Reads all the samples of the last CPU after @from.
+    let cpu = num_cpus - 1;
+    let read_from = |mut from: u64| {
+        let mut all = vec![];
+        let mut samples = vec![CpuSample::default(); 256];
+        loop {
+            let (count, next) = SysRay::cpu_samples(cpu, from, &mut samples).unwrap();
+            if count == 0 {
+                return (all, from);
+            }
+            all.extend_from_slice(&samples[0..count]);
+            from = next;
+        }
+    };
+
+    // A thread spinning on the CPU shows up in its samples.
+    SysRay::set_cpu_sampling(true).unwrap();
+    let (_, start) = read_from(0);
+    let tid = std::thread::spawn(move || {
+        SysCpu::affine_to_cpu(Some(cpu)).unwrap();
+        std::thread::yield_now();
+        let start = std::time::Instant::now();
+        while start.elapsed() < Duration::from_millis(100) {
+            core::hint::spin_loop();
+        }
+        SysCpu::affine_to_cpu(None).unwrap();
+        moto_sys::UserThreadControlBlock::this_thread_tid()
+    })
+    .join()
+    .unwrap();
+    SysRay::set_cpu_sampling(false).unwrap();
+
+    let (sampled, end) = read_from(start);
+    assert!(sampled
+        .iter()
+        .any(|sample| sample.pid == moto_sys::current_pid() && sample.tid == tid));
+
+    // Nothing is recorded while sampling is stopped.
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(SysRay::cpu_samples(cpu, end, &mut samples).unwrap().0, 0);
+
+    println!("test_cpu_sampling() PASS");
+}
+
+fn test_lock_stats() {
+    use moto_sys::stats::LockSiteStats;
+    use moto_sys::SysRay;
+
+    let mut sites = vec![LockSiteStats::default(); 1024];
+    if moto_sys::ProcessStaticPage::get().capabilities & moto_sys::caps::CAP_SYS == 0 {
+        assert_eq!(
+            SysRay::set_lock_stats(true).unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+        assert_eq!(
+            SysRay::lock_stats(&mut sites).unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+        assert_eq!(
+            SysRay::reset_lock_stats().unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+        println!("test_lock_stats() SKIPPED: needs CAP_SYS");
+        return;
+    }
+
+    assert_eq!(
+        SysRay::lock_stats(&mut []).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    let total = |sites: &[LockSiteStats]| -> u64 { sites.iter().map(|s| s.acquisitions).sum() };
+
+    // Nothing is counted while disabled.
+    SysRay::reset_lock_stats().unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    let (count, _) = SysRay::lock_stats(&mut sites).unwrap();
+    assert_eq!(total(&sites[0..count]), 0);
+
+    // Syscalls from several threads take kernel locks.
+    SysRay::set_lock_stats(true).unwrap();
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            std::thread::spawn(|| {
+                for _ in 0..1000 {
+                    let _ = moto_sys::stats::MemoryStats::get().unwrap();
+                    std::thread::yield_now();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    SysRay::set_lock_stats(false).unwrap();
+
+    let (count, _) = SysRay::lock_stats(&mut sites).unwrap();
+    let recorded = &sites[0..count];
+    assert!(total(recorded) > 0);
+    assert!(recorded.windows(2).all(|w| w[0].spins >= w[1].spins));
+    for site in recorded {
+        assert_ne!(site.line, 0);
+        assert!(site.contended <= site.acquisitions);
+        assert!(site.spins >= site.contended);
+    }
+
+    // Stopped: the counters stay put.
+    let before = total(recorded);
+    std::thread::sleep(Duration::from_millis(10));
+    let (count, _) = SysRay::lock_stats(&mut sites).unwrap();
+    assert_eq!(total(&sites[0..count]), before);
+
+    SysRay::reset_lock_stats().unwrap();
+    let (count, dropped) = SysRay::lock_stats(&mut sites).unwrap();
+    assert_eq!(total(&sites[0..count]), 0);
+    assert_eq!(dropped, 0);
+
+    println!("test_lock_stats() PASS");
+}
+
+fn test_thread_interrupt() {
+    use moto_sys::SysCpu;
+    use std::sync::atomic::AtomicU64;
+
+    assert_eq!(
+        SysCpu::interrupt(SysHandle::NONE).unwrap_err(),
+        moto_rt::E_BAD_HANDLE
+    );
+
+    // Nothing pending yet.
+    assert!(!SysCpu::take_interrupt().unwrap());
+
+    let thread_handle = Arc::new(AtomicU64::new(0));
+    let handle_writer = thread_handle.clone();
+    let waiter = std::thread::spawn(move || {
+        handle_writer.store(
+            moto_sys::UserThreadControlBlock::this_thread_handle().as_u64(),
+            Ordering::Release,
+        );
+
+        // Interrupted long before the timeout.
+        let start = std::time::Instant::now();
+        let timeout = moto_rt::time::Instant::now() + Duration::from_secs(5);
+        let first = SysCpu::wait(&mut [], SysHandle::NONE, SysHandle::NONE, Some(timeout));
+        let elapsed = start.elapsed();
+
+        // Sticky: the next wait also returns right away.
+        let second = SysCpu::wait(&mut [], SysHandle::NONE, SysHandle::NONE, Some(timeout));
+
+        let taken = SysCpu::take_interrupt().unwrap();
+        let taken_again = SysCpu::take_interrupt().unwrap();
+
+        // Blocks normally again.
+        let timeout = moto_rt::time::Instant::now() + Duration::from_millis(10);
+        let third = SysCpu::wait(&mut [], SysHandle::NONE, SysHandle::NONE, Some(timeout));
+
+        (first, elapsed, second, taken, taken_again, third)
+    });
+
+    while thread_handle.load(Ordering::Acquire) == 0 {
+        std::thread::yield_now();
+    }
+    std::thread::sleep(Duration::from_millis(50));
+    SysCpu::interrupt(SysHandle::from_u64(thread_handle.load(Ordering::Acquire))).unwrap();
+
+    let (first, elapsed, second, taken, taken_again, third) = waiter.join().unwrap();
+    assert_eq!(first.unwrap_err(), moto_rt::E_INTERRUPTED);
+    assert!(elapsed < Duration::from_secs(4));
+    assert_eq!(second.unwrap_err(), moto_rt::E_INTERRUPTED);
+    assert!(taken);
+    assert!(!taken_again);
+    assert_eq!(third.unwrap_err(), moto_rt::E_TIMED_OUT);
+
+    println!("test_thread_interrupt() PASS");
+}
+
+// std's blocking calls (sleep, Mutex::lock, join) ignore interrupts,
+// which stay pending for when the thread waits for them.
+fn test_thread_interrupt_std() {
+    use moto_sys::SysCpu;
+    use std::sync::atomic::AtomicU64;
+    use std::sync::Mutex;
+
+    let mutex = Arc::new(Mutex::new(0_u32));
+    let thread_handle = Arc::new(AtomicU64::new(0));
+    let handle_writer = thread_handle.clone();
+    let mutex_user = mutex.clone();
+
+    let guard = mutex.lock().unwrap();
+    let waiter = std::thread::spawn(move || {
+        handle_writer.store(
+            moto_sys::UserThreadControlBlock::this_thread_handle().as_u64(),
+            Ordering::Release,
+        );
+
+        // Interrupted while sleeping.
+        let start = std::time::Instant::now();
+        std::thread::sleep(Duration::from_millis(200));
+        let slept = start.elapsed();
+
+        // Interrupted while waiting for the mutex.
+        *mutex_user.lock().unwrap() += 1;
+
+        (slept, SysCpu::take_interrupt().unwrap())
+    });
+
+    while thread_handle.load(Ordering::Acquire) == 0 {
+        std::thread::yield_now();
+    }
+    let waiter_handle = SysHandle::from_u64(thread_handle.load(Ordering::Acquire));
+
+    std::thread::sleep(Duration::from_millis(50));
+    SysCpu::interrupt(waiter_handle).unwrap();
+
+    // By now the waiter is done sleeping and is blocked on the mutex.
+    std::thread::sleep(Duration::from_millis(300));
+    SysCpu::interrupt(waiter_handle).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    drop(guard);
+
+    // Interrupted while joining.
+    SysCpu::interrupt(moto_sys::UserThreadControlBlock::this_thread_handle()).unwrap();
+    let (slept, waiter_interrupted) = waiter.join().unwrap();
+    assert!(SysCpu::take_interrupt().unwrap());
+
+    assert!(slept >= Duration::from_millis(200));
+    assert!(waiter_interrupted);
+    assert_eq!(*mutex.lock().unwrap(), 1);
+
+    println!("test_thread_interrupt_std() PASS");
+}
+
+fn test_wait_ex() {
+    use moto_ipc::io_channel::*;
+    use moto_sys::{SysCpu, WakeCause};
+
+    const URL: &str = "systest_wait_ex";
+
+    let soon = || Some(moto_rt::time::Instant::now() + Duration::from_millis(10));
+    let later = || Some(moto_rt::time::Instant::now() + Duration::from_secs(5));
+
+    assert_eq!(
+        SysCpu::wait_ex(&mut [], SysHandle::NONE, SysHandle::NONE, soon()).unwrap(),
+        WakeCause::TimedOut(0)
+    );
+    assert_eq!(
+        SysCpu::wait_ex(
+            &mut [SysHandle::from_u64(0xbad_0bad)],
+            SysHandle::NONE,
+            SysHandle::NONE,
+            soon()
+        )
+        .unwrap_err(),
+        moto_rt::E_BAD_HANDLE
+    );
+
+    // The server's reply wakes the client via the server handle.
+    let done = Arc::new(AtomicBool::new(false));
+    let server_thread = spawn_io_channel_server(URL, done.clone(), |_, mut sqe| {
+        sqe.status = moto_rt::E_OK;
+        sqe
+    });
+    let conn = ClientConnection::connect(URL).unwrap();
+    let mut sqe = Msg::new();
+    sqe.command = CMD_NOOP_OK;
+    conn.submit_sqe_blocking(sqe, None, RetryPolicy::Wait)
+        .unwrap();
+    loop {
+        match conn.recv() {
+            Ok(cqe) => {
+                assert_eq!(cqe.status(), moto_rt::E_OK);
+                break;
+            }
+            Err(err) => assert_eq!(err, moto_rt::E_NOT_READY),
+        }
+        let mut handles = [conn.server_handle()];
+        assert_eq!(
+            SysCpu::wait_ex(&mut handles, SysHandle::NONE, SysHandle::NONE, later()).unwrap(),
+            WakeCause::Handles(1)
+        );
+        assert_eq!(handles[0], conn.server_handle());
+    }
+    done.store(true, Ordering::Release);
+    SysCpu::wake(conn.server_handle()).unwrap();
+    server_thread.join().unwrap();
+
+    // SysCpu::wake() of the thread itself: no handles.
+    let thread_handle = Arc::new(std::sync::atomic::AtomicU64::new(0));
+    let handle_writer = thread_handle.clone();
+    let waiter = std::thread::spawn(move || {
+        handle_writer.store(
+            moto_sys::UserThreadControlBlock::this_thread_handle().as_u64(),
+            Ordering::Release,
+        );
+        let woken = SysCpu::wait_ex(&mut [], SysHandle::NONE, SysHandle::NONE, later());
+
+        // And an interrupt, posted by the thread to itself.
+        SysCpu::interrupt(moto_sys::UserThreadControlBlock::this_thread_handle()).unwrap();
+        let interrupted = SysCpu::wait_ex(&mut [], SysHandle::NONE, SysHandle::NONE, later());
+        assert!(SysCpu::take_interrupt().unwrap());
+
+        (woken, interrupted)
+    });
+    while thread_handle.load(Ordering::Acquire) == 0 {
+        std::thread::yield_now();
+    }
+    std::thread::sleep(Duration::from_millis(20));
+    SysCpu::wake(SysHandle::from_u64(thread_handle.load(Ordering::Acquire))).unwrap();
+    let (woken, interrupted) = waiter.join().unwrap();
+    assert_eq!(woken.unwrap(), WakeCause::Handles(0));
+    assert_eq!(interrupted.unwrap(), WakeCause::Interrupted(0));
+
+    println!("test_wait_ex() PASS");
+}
+
+fn test_channel_pool_growth() {
+    use moto_ipc::io_channel::*;
+
+    let conn = ClientConnection::connect("sys-io").unwrap();
+    assert_eq!(conn.pool_limits(), PoolLimits::FIXED);
+    for bad in [
+        PoolLimits {
+            initial: 4,
+            max: 0,
+            shrink_when_idle: false,
+        },
+        PoolLimits {
+            initial: 8,
+            max: 4,
+            shrink_when_idle: false,
+        },
+        PoolLimits {
+            initial: 4,
+            max: CHANNEL_PAGE_COUNT + 1,
+            shrink_when_idle: false,
+        },
+    ] {
+        assert_eq!(
+            conn.set_pool_limits(bad).unwrap_err(),
+            moto_rt::E_INVALID_ARGUMENT
+        );
+    }
+
+    let limits = PoolLimits {
+        initial: 4,
+        max: 16,
+        shrink_when_idle: true,
+    };
+    conn.set_pool_limits(limits).unwrap();
+    assert_eq!(conn.pool_limits(), limits);
+    let usage = conn.pool_usage();
+    assert_eq!(usage.capacity, 4);
+    assert_eq!(usage.max_capacity, 16);
+    assert_eq!(conn.free_pages(u64::MAX), 16);
+    assert_eq!(
+        conn.alloc_pages(u64::MAX, 17).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    // Pages above the max are never handed out.
+    assert_eq!(
+        conn.alloc_page(!0xffff).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+
+    // The pool grows (doubling) only once the pages it has are in use.
+    let mut pages = conn.alloc_pages(u64::MAX, 4).unwrap();
+    assert_eq!(conn.pool_usage().capacity, 4);
+    pages.push(conn.alloc_page(u64::MAX).unwrap());
+    assert_eq!(conn.pool_usage().capacity, 8);
+    pages.extend(conn.alloc_pages(u64::MAX, 11).unwrap());
+    let usage = conn.pool_usage();
+    assert_eq!(usage.capacity, 16);
+    assert_eq!(usage.in_use, 16);
+    assert_eq!(conn.alloc_page(u64::MAX).unwrap_err(), moto_rt::E_NOT_READY);
+
+    // And shrinks back once idle.
+    core::mem::drop(pages);
+    let usage = conn.pool_usage();
+    assert_eq!(usage.in_use, 0);
+    assert_eq!(usage.capacity, 4);
+    assert_eq!(usage.high_water, 16);
+
+    // Without shrink_when_idle, the pool keeps its capacity.
+    conn.set_pool_limits(PoolLimits {
+        shrink_when_idle: false,
+        ..limits
+    })
+    .unwrap();
+    let pages = conn.alloc_pages(u64::MAX, 6).unwrap();
+    core::mem::drop(pages);
+    assert_eq!(conn.pool_usage().capacity, 8);
+
+    conn.set_pool_limits(PoolLimits::FIXED).unwrap();
+    assert_eq!(conn.pool_usage().capacity, CHANNEL_PAGE_COUNT);
+
+    println!("test_channel_pool_growth() PASS");
+}
+
+fn test_pipes() {
+    use moto_sys::syscalls::*;
+    std::thread::sleep(std::time::Duration::from_millis(1000));
+
+    let (d1, d2) = moto_ipc::sync_pipe::make_pair(SysHandle::SELF, SysHandle::SELF).unwrap();
+
+    let mut reader = unsafe { moto_ipc::sync_pipe::Reader::new(d1) };
+    let mut writer = unsafe { moto_ipc::sync_pipe::Writer::new(d2) };
+
+    let reader_thread = std::thread::spawn(move || {
+        let mut step = 1_usize;
+        loop {
+            let mut buf: Vec<u8> = vec![];
+            buf.resize(step % 8176 + 17, 0);
+
+            let read = reader.read(buf.as_mut_slice()).unwrap();
+            assert!(read > 0);
+            if buf[read - 1] == 0 {
+                break;
+            }
+
+            step += 1;
+        }
+
+        reader.total_read()
+    });
+
+    let writer_thread = std::thread::spawn(move || {
+        for step in 1_usize..8000_usize {
+            let mut buf = vec![];
+
+            for _idx in 0..step {
+                buf.push(7_u8);
+            }
+            assert_eq!(writer.write(buf.as_slice()).unwrap(), step);
+        }
+
+        assert_eq!(1, writer.write(&[0_u8; 1]).unwrap());
+        writer.total_written()
+    });
+
+    let read = reader_thread.join().unwrap();
+    let written = writer_thread.join().unwrap();
+
+    assert_eq!(read, written);
+
+    println!("test_pipes PASS");
+}
+
+// sys-io's log level can be changed at runtime, with CAP_SYS.
+fn test_sys_io_log_level() {
+    use moto_sys::SysRay;
+    use moto_sys_io::stats::IoStatsService;
+
+    let mut stats_service = IoStatsService::connect().unwrap();
+    let level = stats_service.log_level().unwrap();
+    assert!(level <= SysRay::KLOG_LEVEL_TRACE);
+
+    if moto_sys::ProcessStaticPage::get().capabilities & moto_sys::caps::CAP_SYS == 0 {
+        assert_eq!(
+            stats_service
+                .set_log_level(SysRay::KLOG_LEVEL_TRACE)
+                .unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+        assert_eq!(stats_service.log_level().unwrap(), level);
+        println!("test_sys_io_log_level() SKIPPED: needs CAP_SYS");
+        return;
+    }
+
+    assert_eq!(
+        stats_service
+            .set_log_level(SysRay::KLOG_LEVEL_TRACE + 1)
+            .unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    assert_eq!(
+        stats_service
+            .set_log_level(SysRay::KLOG_LEVEL_WARN)
+            .unwrap(),
+        level
+    );
+    assert_eq!(stats_service.log_level().unwrap(), SysRay::KLOG_LEVEL_WARN);
+    assert_eq!(
+        stats_service.set_log_level(level).unwrap(),
+        SysRay::KLOG_LEVEL_WARN
+    );
+    assert_eq!(stats_service.log_level().unwrap(), level);
+
+    println!("test_sys_io_log_level() PASS");
+}
+
+fn test_rusage() {
+    use moto_rt::fs;
+
+    const LEN: usize = 100_000;
+
+    let (child_stdin, commands) = fs::pipe(0).unwrap();
+    let args = moto_rt::process::SpawnArgs {
+        program: std::env::args().next().unwrap(),
+        args: vec!["subcommand".to_owned()],
+        env: vec![
+            ("some_key".to_owned(), "some_val".to_owned()),
+            ("none_key".to_owned(), "".to_owned()),
+        ],
+        cwd: None,
+        stdin: child_stdin,
+        stdout: moto_rt::process::STDIO_NULL,
+        stderr: moto_rt::process::STDIO_NULL,
+    };
+    let (handle, _, _, _) = moto_rt::process::spawn(args).unwrap();
+
+    let commands_str = format!("io /sys/tmp/systest_rusage 127.0.0.1:3345 {LEN}\nexit 0\n");
+    assert_eq!(
+        fs::write(commands, commands_str.as_bytes()).unwrap(),
+        commands_str.len()
+    );
+    fs::close(commands).unwrap();
+
+    let (status, usage) = moto_rt::process::wait_rusage(handle).unwrap();
+    assert_eq!(status, 0);
+
+    // The child both sent and received the bytes over TCP.
+    assert!(usage.fs_bytes_written >= LEN as u64);
+    assert!(usage.fs_bytes_read >= LEN as u64);
+    assert!(usage.net_bytes_tx >= LEN as u64);
+    assert!(usage.net_bytes_rx >= LEN as u64);
+
+    assert!(usage.cpu_total() > Duration::ZERO);
+    assert!(usage.total_threads >= 1);
+    assert!(usage.context_switches >= usage.total_threads);
+    assert!(usage.migrations < usage.context_switches);
+    assert!(usage.sched_latency_max_ns <= usage.sched_latency_total_ns);
+
+    // Final: the process has exited.
+    let again = moto_rt::process::rusage(handle).unwrap();
+    assert_eq!(again.context_switches, usage.context_switches);
+    assert_eq!(again.fs_bytes_written, usage.fs_bytes_written);
+    assert_eq!(again.net_bytes_rx, usage.net_bytes_rx);
+
+    moto_sys::SysObj::put(SysHandle::from_u64(handle)).unwrap();
+
+    println!("test_rusage() PASS");
+}
+
+fn test_anon_pipe() {
+    use moto_rt::fs;
+
+    let mut buf = [0_u8; 16];
+
+    let (reader, writer) = fs::pipe(fs::PIPE_NONBLOCK).unwrap();
+    assert_eq!(
+        fs::read(reader, &mut buf).unwrap_err(),
+        moto_rt::E_NOT_READY
+    );
+    assert_eq!(fs::write(writer, b"abc").unwrap(), 3);
+    assert_eq!(fs::read(reader, &mut buf).unwrap(), 3);
+    assert_eq!(&buf[0..3], b"abc");
+    fs::close(writer).unwrap();
+    assert_eq!(fs::read(reader, &mut buf).unwrap(), 0); // EOF.
+    fs::close(reader).unwrap();
+
+    let (reader, writer) = fs::pipe(0).unwrap();
+    fs::close(reader).unwrap();
+    assert_eq!(
+        fs::write(writer, b"abc").unwrap_err(),
+        moto_rt::E_BROKEN_PIPE
+    );
+    fs::close(writer).unwrap();
+
+    // A child between two pipes: spawn() takes over the child's ends.
+    let (child_stdin, commands) = fs::pipe(0).unwrap();
+    let (output, child_stdout) = fs::pipe(0).unwrap();
+    let args = moto_rt::process::SpawnArgs {
+        program: std::env::args().next().unwrap(),
+        args: vec!["subcommand".to_owned()],
+        env: vec![
+            ("some_key".to_owned(), "some_val".to_owned()),
+            ("none_key".to_owned(), "".to_owned()),
+        ],
+        cwd: None,
+        stdin: child_stdin,
+        stdout: child_stdout,
+        stderr: moto_rt::process::STDIO_NULL,
+    };
+    let (handle, _, _, _) = moto_rt::process::spawn(args).unwrap();
+
+    let commands_str = "echo through a pipe\nexit 0\n";
+    assert_eq!(
+        fs::write(commands, commands_str.as_bytes()).unwrap(),
+        commands_str.len()
+    );
+    fs::close(commands).unwrap();
+
+    let mut received = vec![];
+    loop {
+        let sz = fs::read(output, &mut buf).unwrap();
+        if sz == 0 {
+            break; // The child has exited.
+        }
+        received.extend_from_slice(&buf[0..sz]);
+    }
+    fs::close(output).unwrap();
+    assert_eq!(received.as_slice(), b"through a pipe\n");
+
+    assert_eq!(0, moto_rt::process::wait(handle).unwrap());
+    moto_sys::SysObj::put(SysHandle::from_u64(handle)).unwrap();
+
+    println!("test_anon_pipe() PASS");
+}
+
+fn test_thread() {
+    use std::sync::atomic::AtomicU64;
+    let atomic = AtomicU64::new(0);
+    let atomic_ref = unsafe { (&atomic as *const AtomicU64).as_ref::<'static>().unwrap() };
+
+    std::thread::spawn(|| {
+        atomic_ref.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    })
+    .join()
+    .unwrap();
+
+    assert_eq!(1, atomic.load(std::sync::atomic::Ordering::Relaxed));
+    println!("test_thread PASS");
+}
+
+fn test_lazy_memory_map() {
+    use moto_sys::*;
+
+    let addr = SysMem::map(
+        SysHandle::SELF,
+        SysMem::F_READABLE | SysMem::F_WRITABLE | SysMem::F_LAZY,
+        u64::MAX,
+        u64::MAX,
+        sys_mem::PAGE_SIZE_SMALL,
+        1,
+    )
+    .unwrap();
+    // let prev_log_level = moto_sys::syscalls::SysCtl::set_log_level(4).unwrap();
+    let buf = unsafe {
+        core::slice::from_raw_parts_mut(addr as usize as *mut u8, sys_mem::PAGE_SIZE_SMALL as usize)
+    };
+    for idx in 0..buf.len() {
+        assert_eq!(0, buf[idx]);
+    }
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    for idx in 0..buf.len() {
+        buf[idx] = (idx % (u8::MAX as usize)) as u8;
+        assert_eq!(buf[idx], (idx % (u8::MAX as usize)) as u8);
+    }
+
+    // moto_sys::syscalls::SysCtl::set_log_level(prev_log_level).unwrap();
+    SysMem::free(addr).unwrap();
+    println!("test_lazy_memory_map: done");
+}
+
+fn test_reserved_memory_map() {
+    use moto_sys::*;
+
+    const NUM_PAGES: u64 = 1024;
+    let faults_before = SysRay::query_page_faults(current_pid()).unwrap();
+
+    let addr = SysMem::map(
+        SysHandle::SELF,
+        SysMem::F_READABLE | SysMem::F_WRITABLE | SysMem::F_RESERVE,
+        u64::MAX,
+        u64::MAX,
+        sys_mem::PAGE_SIZE_SMALL,
+        NUM_PAGES,
+    )
+    .unwrap();
+
+    // Touch the first and the last page only.
+    for page in [0, NUM_PAGES - 1] {
+        let ptr = (addr + page * sys_mem::PAGE_SIZE_SMALL) as usize as *mut u64;
+        unsafe {
+            assert_eq!(0, ptr.read_volatile());
+            ptr.write_volatile(page + 1);
+            assert_eq!(page + 1, ptr.read_volatile());
+        }
+    }
+
+    // Other threads may fault in their stack pages, so this is not exact.
+    let faults_after = SysRay::query_page_faults(current_pid()).unwrap();
+    assert!(faults_after - faults_before >= 2);
+    assert!(faults_after - faults_before < NUM_PAGES);
+
+    SysMem::free(addr).unwrap();
+    println!("test_reserved_memory_map: done");
+}
+
+fn test_mem_advise() {
+    use moto_sys::*;
+
+    const NUM_PAGES: u64 = 256;
+    let pages_user = || {
+        let mut stats = [stats::ProcessStatsV1::default()];
+        assert_eq!(
+            stats::ProcessStatsV1::list(current_pid(), &mut stats).unwrap(),
+            1
+        );
+        stats[0].pages_user
+    };
+
+    let addr = SysMem::map(
+        SysHandle::SELF,
+        SysMem::F_READABLE | SysMem::F_WRITABLE | SysMem::F_LAZY,
+        u64::MAX,
+        u64::MAX,
+        sys_mem::PAGE_SIZE_SMALL,
+        NUM_PAGES,
+    )
+    .unwrap();
+
+    assert_eq!(
+        SysMem::advise(addr + 1, 1, SysMem::F_ADVISE_WILLNEED).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    assert_eq!(
+        SysMem::advise(addr, 0, SysMem::F_ADVISE_WILLNEED).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    assert_eq!(
+        SysMem::advise(addr, 1, SysMem::F_ADVISE_DONTNEED + 1).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    assert_eq!(
+        SysMem::advise(addr, NUM_PAGES + 1, SysMem::F_ADVISE_WILLNEED).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+
+    // Prefaulted pages are counted right away, and accessing them does not fault.
+    let before = pages_user();
+    assert_eq!(
+        SysMem::advise(addr, NUM_PAGES, SysMem::F_ADVISE_WILLNEED).unwrap(),
+        NUM_PAGES
+    );
+    assert!(pages_user() >= before + NUM_PAGES / 2);
+    assert_eq!(
+        SysMem::advise(addr, NUM_PAGES, SysMem::F_ADVISE_WILLNEED).unwrap(),
+        0
+    );
+
+    // Other threads may fault in their stack pages, so this is not exact.
+    let faults_before = SysRay::query_page_faults(current_pid()).unwrap();
+    for page in 0..NUM_PAGES {
+        let ptr = (addr + page * sys_mem::PAGE_SIZE_SMALL) as usize as *mut u64;
+        unsafe {
+            assert_eq!(0, ptr.read_volatile());
+            ptr.write_volatile(page + 1);
+        }
+    }
+    let faults_after = SysRay::query_page_faults(current_pid()).unwrap();
+    assert!(faults_after - faults_before < NUM_PAGES / 2);
+
+    // Released pages are uncounted, and read back as zeroes.
+    let before = pages_user();
+    assert_eq!(
+        SysMem::advise(addr, NUM_PAGES, SysMem::F_ADVISE_DONTNEED).unwrap(),
+        NUM_PAGES
+    );
+    assert!(pages_user() + NUM_PAGES / 2 <= before);
+    assert_eq!(
+        SysMem::advise(addr, NUM_PAGES, SysMem::F_ADVISE_DONTNEED).unwrap(),
+        0
+    );
+    for page in 0..NUM_PAGES {
+        let ptr = (addr + page * sys_mem::PAGE_SIZE_SMALL) as usize as *const u64;
+        assert_eq!(0, unsafe { ptr.read_volatile() });
+    }
+    SysMem::free(addr).unwrap();
+
+    // Only lazy (and reserved) mappings can be advised.
+    let addr = SysMem::alloc(sys_mem::PAGE_SIZE_SMALL, 1).unwrap();
+    assert_eq!(
+        SysMem::advise(addr, 1, SysMem::F_ADVISE_WILLNEED).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    assert_eq!(
+        SysMem::advise(addr, 1, SysMem::F_ADVISE_DONTNEED).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    SysMem::free(addr).unwrap();
+
+    println!("test_mem_advise() PASS");
+}
+
+fn test_stats_reset() {
+    use moto_sys::*;
+
+    const NUM_PAGES: u64 = 64;
+    let pid = current_pid();
+
+    if ProcessStaticPage::get().capabilities & caps::CAP_SYS == 0 {
+        assert_eq!(
+            SysRay::reset_stats(pid).unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+        println!("test_stats_reset() SKIPPED: needs CAP_SYS");
+        return;
+    }
+
+    let addr = SysMem::map(
+        SysHandle::SELF,
+        SysMem::F_READABLE | SysMem::F_WRITABLE | SysMem::F_LAZY,
+        u64::MAX,
+        u64::MAX,
+        sys_mem::PAGE_SIZE_SMALL,
+        NUM_PAGES,
+    )
+    .unwrap();
+    for page in 0..NUM_PAGES {
+        let ptr = (addr + page * sys_mem::PAGE_SIZE_SMALL) as usize as *mut u64;
+        unsafe { ptr.write_volatile(page) };
+    }
+    SysMem::free(addr).unwrap();
+    let faults_before = SysRay::query_page_faults(pid).unwrap();
+    assert!(faults_before >= NUM_PAGES);
+
+    // Page faults are reset with other counters. Other threads may fault
+    // in their stack pages concurrently, so this is not exact.
+    SysRay::reset_stats(pid).unwrap();
+    assert!(SysRay::query_page_faults(pid).unwrap() < NUM_PAGES);
+
+    println!("test_stats_reset() PASS");
+}
+
+fn test_sched_latency() {
+    use moto_sys::stats::SchedLatencyStats;
+
+    // Every wakeup from a sleep makes the thread runnable.
+    for _ in 0..10 {
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+
+    let stats = SchedLatencyStats::get(moto_sys::current_pid()).unwrap();
+    assert!(stats.count >= 10);
+    assert!(stats.max >= stats.avg());
+    println!("test_sched_latency: done");
+}
+
+fn test_timer_accuracy() {
+    const SLEEP: Duration = Duration::from_micros(200);
+    const ITERS: u32 = 100;
+
+    let mut total = Duration::ZERO;
+    let mut max = Duration::ZERO;
+    for _ in 0..ITERS {
+        let start = std::time::Instant::now();
+        std::thread::sleep(SLEEP);
+        let late = start.elapsed().saturating_sub(SLEEP);
+        total += late;
+        max = max.max(late);
+    }
+
+    // Wakeups must not be rounded up to the next scheduler tick.
+    let avg = total / ITERS;
+    assert!(avg < moto_sys::SysCpu::time_slice().unwrap() / 4);
+
+    let resolution = moto_sys::SysCpu::timer_resolution().unwrap();
+    println!(
+        "test_timer_accuracy: {:?} sleeps wake up {:?} late on average ({:?} max); timer resolution: {:?}",
+        SLEEP, avg, max, resolution
+    );
+}
+
+// Changing the time slice needs CAP_SYS, and does not change the CPU sampling rate.
+fn test_time_slice() {
+    use moto_sys::{SysCpu, SysRay};
+
+    let slice = SysCpu::time_slice().unwrap();
+    assert!(slice >= SysCpu::MIN_TIME_SLICE && slice <= SysCpu::MAX_TIME_SLICE);
+
+    if moto_sys::ProcessStaticPage::get().capabilities & moto_sys::caps::CAP_SYS == 0 {
+        assert_eq!(
+            SysCpu::set_time_slice(SysCpu::MIN_TIME_SLICE).unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+        assert_eq!(SysCpu::time_slice().unwrap(), slice);
+        println!("test_time_slice() SKIPPED: needs CAP_SYS");
+        return;
+    }
+    if moto_sys::num_cpus() < 2 {
+        println!("test_time_slice() SKIPPED: needs 2+ CPUs");
+        return;
+    }
+
+    assert_eq!(
+        SysCpu::set_time_slice(SysCpu::MIN_TIME_SLICE / 2).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    assert_eq!(
+        SysCpu::set_time_slice(SysCpu::MAX_TIME_SLICE * 2).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    assert_eq!(SysCpu::time_slice().unwrap(), slice);
+
+    // The number of samples CPU 1 takes while a thread spins there.
+    const SPIN: Duration = Duration::from_millis(500);
+    let count_samples = || {
+        std::thread::spawn(|| {
+            SysCpu::affine_to_cpu(Some(1)).unwrap();
+            std::thread::yield_now();
+            let mut buf = [moto_sys::stats::CpuSample::default(); 256];
+            let (_, from) = SysRay::cpu_samples(1, u64::MAX, &mut buf).unwrap();
+            let start = std::time::Instant::now();
+            while start.elapsed() < SPIN {
+                core::hint::spin_loop();
+            }
+            let (_, to) = SysRay::cpu_samples(1, u64::MAX, &mut buf).unwrap();
+            SysCpu::affine_to_cpu(None).unwrap();
+            to - from
+        })
+        .join()
+        .unwrap()
+    };
+
+    SysRay::set_cpu_sampling(true).unwrap();
+    let expected = (SPIN.as_micros() / SysRay::CPU_SAMPLE_PERIOD.as_micros()) as u64;
+    for new_slice in [SysCpu::MIN_TIME_SLICE, SysCpu::MAX_TIME_SLICE] {
+        SysCpu::set_time_slice(new_slice).unwrap();
+        assert_eq!(SysCpu::time_slice().unwrap(), new_slice);
+        let samples = count_samples();
+        assert!(
+            samples >= expected / 2 && samples <= expected * 2,
+            "{samples} samples with a {new_slice:?} time slice"
+        );
+    }
+    SysRay::set_cpu_sampling(false).unwrap();
+    SysCpu::set_time_slice(slice).unwrap();
+
+    println!("test_time_slice() PASS");
+}
+
+fn stress_test_threads() {
+    // Basically, do some cpu-bound stuff and
+    // verify that preemption does not mess up registers.
+    println!("stress_test_threads starting...");
+    const THREADS: usize = 8;
+
+    let shutdown = AtomicBool::new(false);
+    let shutdown_ref = unsafe {
+        (&shutdown as *const AtomicBool)
+            .as_ref::<'static>()
+            .unwrap()
+    };
+
+    let mut handles = Vec::with_capacity(THREADS);
+
+    for _ in 0..THREADS {
+        handles.push(std::thread::spawn(|| {
+            let mut counter: u64 = 0;
+            while !shutdown_ref.load(std::sync::atomic::Ordering::Relaxed) {
+                #[cfg(feature = "test_sse")]
+                {
+                    let af64: f64 = f64::from((counter + 1) as u32);
+                    let bf64: f64 = f64::from((counter + 2) as u32);
+                    let mut res_f64: f64;
+                }
+
+                let mut result: u64;
+                unsafe {
+                    std::arch::asm!(
+                                "
+                        mov rcx, rax
+                        mov rdx, rax
+                        mov rsi, rax
+                        mov rdi, rax
+                        mov r8, rax
+                        mov r9, rax
+                        mov r10, rax
+                        mov r11, rax
+                        mov r12, rax
+                        mov r13, rax
+                        mov r14, rax
+                        mov r15, rax
+
+                        add r15, rax
+                        add r15, rcx
+                        add r15, rdx
+                        add r15, rsi
+                        add r15, rdi
+                        add r15, r8
+                        add r15, r9
+                        add r15, r10
+                        add r15, r11
+                        add r15, r12
+                        add r15, r13
+                        add r15, r14
+                        ",
+                        in("rax") counter,
+                        out("rcx") _,
+                        out("rdx") _,
+                        out("rsi") _,
+                        out("rdi") _,
+                        out("r8") _,
+                        out("r9") _,
+                        out("r10") _,
+                        out("r11") _,
+                        out("r12") _,
+                        out("r13") _,
+                        out("r14") _,
+                        out("r15") result,
+                        options(nomem, nostack)
+                    );
+
+                    #[cfg(feature = "test_sse")]
+                    {
+                        std::arch::asm!(
+                            "addsd {0}, {1}",
+                            inlateout(xmm_reg) af64 => res_f64,
+                            in(xmm_reg) bf64,
+                        );
+                    }
+                }
+                #[cfg(feature = "test_sse")]
+                {
+                    res_f64 *= 13.3;
+                    assert!((res_f64 as i64) < (((counter + 1) * 14) as i64));
+                }
+
+                assert_eq!(result, counter * 13);
+
+                counter += 1;
+                if counter > 1_000_000_000 {
+                    counter = 0;
+                }
+            }
+        }));
+    }
+
+    std::thread::sleep(std::time::Duration::from_secs(2));
+    shutdown.store(true, Ordering::Release);
+
+    for _ in 0..THREADS {
+        let handle = handles.pop().unwrap();
+        handle.join().unwrap();
+    }
+    println!("stress_test_threads PASS");
+}
+
+fn test_file_write() {
+    const WRITTEN: &str = "Lorem Ipsum";
+
+    let mut path = std::env::temp_dir();
+    path.push("temp_file");
+
+    if path.exists() {
+        std::fs::remove_file(path.clone()).unwrap();
+    }
+
+    // Write.
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path.clone())
+        .expect(format!("Failed to create {:?}", path).as_str());
+
+    file.write(WRITTEN.as_bytes()).unwrap();
+    std::mem::drop(file); // Close it.
+
+    // Read.
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .open(path.clone())
+        .unwrap();
+    let mut read_back = String::new();
+    file.read_to_string(&mut read_back).unwrap();
+
+    assert_eq!(read_back.as_str(), WRITTEN);
+    core::mem::drop(file);
+
+    std::fs::remove_file(path.clone()).unwrap();
+    println!("test_file_write() PASS");
+}
+
+fn test_fs_block_io() {
+    use moto_sys_io::api_fs::IO_MAX_BYTES;
+    use moto_sys_io::io_executor::{block_on, File};
+
+    let mut path = std::env::temp_dir();
+    path.push("block_io");
+    let path = path.to_str().unwrap().to_owned();
+
+    // 64K, written in one go: likely consecutive blocks on the device.
+    let payload: Vec<u8> = (0..(64 * 1024)).map(|idx| (idx % 251) as u8).collect();
+    std::fs::write(&path, &payload).unwrap();
+
+    let statfs = moto_rt::fs::statfs(&path).unwrap();
+    assert!(statfs.block_size >= 512 && statfs.block_size.is_power_of_two());
+    assert!(statfs.block_size <= 4096); // See fs_srfs::init().
+    assert!(statfs.optimal_io_size >= statfs.block_size);
+    assert!(statfs.total_bytes > 0);
+    let max_requests = IO_MAX_BYTES.div_ceil(statfs.optimal_io_size as usize) as u64;
+
+    let rt_fd = moto_rt::fs::open(&path, moto_rt::fs::O_READ).unwrap();
+    let contiguous = moto_rt::fs::file_extents(rt_fd).unwrap().extents == 1;
+    moto_rt::fs::close(rt_fd).unwrap();
+
+    let mut expected = payload.clone();
+    block_on(async {
+        let file = File::open(&path, moto_rt::fs::O_READ | moto_rt::fs::O_WRITE)
+            .await
+            .unwrap();
+
+        // Reads and writes of whole blocks are coalesced into as few device
+        // requests as optimal_io_size allows, not issued one block at a time.
+        let mut buf = vec![0_u8; IO_MAX_BYTES];
+        let before = moto_rt::fs::statfs(&path).unwrap().completed_requests;
+        assert_eq!(file.read_at(4096, &mut buf).await.unwrap(), buf.len());
+        let after = moto_rt::fs::statfs(&path).unwrap().completed_requests;
+        assert!(buf[..] == payload[4096..(4096 + IO_MAX_BYTES)]);
+        if contiguous {
+            assert!(after - before <= max_requests);
+        }
+
+        buf.iter_mut().for_each(|byte| *byte = !*byte);
+        let before = moto_rt::fs::statfs(&path).unwrap().completed_requests;
+        assert_eq!(file.write_at(8192, &buf).await.unwrap(), buf.len());
+        let after = moto_rt::fs::statfs(&path).unwrap().completed_requests;
+        expected[8192..(8192 + IO_MAX_BYTES)].copy_from_slice(&buf);
+        if contiguous {
+            assert!(after - before <= max_requests + 1); // + the cache flush.
+        }
+
+        // Unaligned requests cross block boundaries, too.
+        let patch: Vec<u8> = (0..10_000).map(|idx| (idx % 7) as u8).collect();
+        assert_eq!(file.write_at(3000, &patch).await.unwrap(), patch.len());
+        expected[3000..(3000 + patch.len())].copy_from_slice(&patch);
+        let mut buf = vec![0_u8; IO_MAX_BYTES];
+        assert_eq!(file.read_at(100, &mut buf).await.unwrap(), buf.len());
+        assert!(buf[..] == expected[100..(100 + IO_MAX_BYTES)]);
+        file.close().await.unwrap();
+    });
+
+    assert!(std::fs::read(&path).unwrap() == expected);
+    std::fs::remove_file(&path).unwrap();
+    println!("test_fs_block_io() PASS");
+}
+
+fn test_fs_queue_depth() {
+    let mut path = std::env::temp_dir();
+    path.push("queue_depth_test");
+    let path_str = path.to_str().unwrap();
+
+    // 1M, more than a single request.
+    let data: Vec<u8> = (0..(1_usize << 20)).map(|idx| (idx % 251) as u8).collect();
+
+    let write_and_read = || {
+        std::fs::write(path.clone(), &data).unwrap();
+        std::fs::File::open(path.clone())
+            .unwrap()
+            .sync_all()
+            .unwrap();
+        assert_eq!(std::fs::read(path.clone()).unwrap(), data);
+    };
+
+    std::fs::write(path.clone(), b"").unwrap();
+
+    let before = moto_rt::fs::statfs(path_str).unwrap();
+    assert!(before.max_queue_depth >= 1);
+    assert!(before.queue_depth >= 1 && before.queue_depth <= before.max_queue_depth);
+    write_and_read();
+    let after = moto_rt::fs::statfs(path_str).unwrap();
+    assert!(after.completed_requests > before.completed_requests);
+    assert!(after.peak_in_flight >= 1 && after.peak_in_flight <= after.queue_depth);
+
+    if moto_sys::ProcessStaticPage::get().capabilities & moto_sys::caps::CAP_SYS == 0 {
+        assert_eq!(
+            moto_rt::fs::set_queue_depth(path_str, 1).unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+        assert_eq!(
+            moto_rt::fs::statfs(path_str).unwrap().queue_depth,
+            after.queue_depth
+        );
+        std::fs::remove_file(path).unwrap();
+        println!("test_fs_queue_depth() SKIPPED: no CAP_SYS");
+        return;
+    }
+
+    // With a queue depth of one, requests are issued one at a time.
+    moto_rt::fs::set_queue_depth(path_str, 1).unwrap();
+    let statfs = moto_rt::fs::statfs(path_str).unwrap();
+    assert_eq!(statfs.queue_depth, 1);
+    assert_eq!(statfs.peak_in_flight, 0);
+    write_and_read();
+    assert_eq!(moto_rt::fs::statfs(path_str).unwrap().peak_in_flight, 1);
+
+    // Depths are bounded by the device.
+    moto_rt::fs::set_queue_depth(path_str, u32::MAX).unwrap();
+    let statfs = moto_rt::fs::statfs(path_str).unwrap();
+    assert_eq!(statfs.queue_depth, statfs.max_queue_depth);
+    write_and_read();
+    assert!(moto_rt::fs::statfs(path_str).unwrap().peak_in_flight <= statfs.max_queue_depth);
+
+    // Zero restores the default.
+    moto_rt::fs::set_queue_depth(path_str, 0).unwrap();
+    assert_eq!(
+        moto_rt::fs::statfs(path_str).unwrap().queue_depth,
+        statfs.max_queue_depth
+    );
+
+    std::fs::remove_file(path).unwrap();
+    println!("test_fs_queue_depth() PASS");
+}
+
+fn test_fs_dir_quota() {
+    let mut dir = std::env::temp_dir();
+    dir.push("quota_test");
+    if dir.exists() {
+        std::fs::remove_dir_all(dir.clone()).unwrap();
+    }
+    std::fs::create_dir(dir.clone()).unwrap();
+    let path = dir.to_str().unwrap();
+
+    // Setting (or removing) a quota requires CAP_SYS.
+    assert_eq!(
+        moto_rt::fs::set_dir_quota(path, 4096, 16).unwrap_err(),
+        moto_rt::E_NOT_ALLOWED
+    );
+    assert_eq!(
+        moto_rt::fs::set_dir_quota(
+            path,
+            moto_rt::fs::QUOTA_UNLIMITED,
+            moto_rt::fs::QUOTA_UNLIMITED
+        )
+        .unwrap_err(),
+        moto_rt::E_NOT_ALLOWED
+    );
+    assert_eq!(
+        moto_rt::fs::dir_quota(path).unwrap_err(),
+        moto_rt::E_NOT_FOUND
+    );
+
+    // Nor can saved quotas be changed behind the driver's back.
+    assert!(std::fs::write("/sys/quotas/quotas", "0 0 /").is_err());
+    assert!(std::fs::create_dir_all("/sys/quotas/dir").is_err());
+
+    std::fs::remove_dir_all(dir).unwrap();
+    println!("test_fs_dir_quota() PASS");
+}
+
+fn test_fs_snapshot() {
+    let mut dir = std::env::temp_dir();
+    dir.push("snapshot_test");
+    if dir.exists() {
+        std::fs::remove_dir_all(dir.clone()).unwrap();
+    }
+    std::fs::create_dir(dir.clone()).unwrap();
+
+    let file = dir.join("file");
+    std::fs::write(file.clone(), "before").unwrap();
+
+    // Taking and releasing snapshots requires CAP_SYS.
+    if moto_sys::ProcessStaticPage::get().capabilities & moto_sys::caps::CAP_SYS == 0 {
+        assert_eq!(
+            moto_rt::fs::snapshot(dir.to_str().unwrap()).unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+        assert_eq!(
+            moto_rt::fs::release_snapshot(1).unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+        println!("test_fs_snapshot() SKIPPED: needs CAP_SYS");
+        return;
+    }
+
+    // Copies are charged to quotas.
+    moto_rt::fs::set_dir_quota(
+        dir.to_str().unwrap(),
+        moto_rt::fs::QUOTA_UNLIMITED,
+        moto_rt::fs::QUOTA_UNLIMITED,
+    )
+    .unwrap();
+    moto_rt::fs::set_dir_quota(dir.to_str().unwrap(), 1 << 20, 1024).unwrap();
+    let usage = moto_rt::fs::dir_quota(dir.to_str().unwrap()).unwrap();
+
+    let id = moto_rt::fs::snapshot(dir.to_str().unwrap()).unwrap();
+    let view = std::path::PathBuf::from(format!("{}/{}", moto_rt::fs::SNAPSHOT_DIR, id));
+
+    // Live writes continue, but the snapshot does not see them.
+    std::fs::write(file.clone(), "after").unwrap();
+    let charged = moto_rt::fs::dir_quota(dir.to_str().unwrap()).unwrap();
+    // The copy of "before" is charged, on top of the new "after".
+    assert_eq!(charged.used_inodes, usage.used_inodes + 1);
+    assert_eq!(charged.used_bytes, usage.used_bytes + "after".len() as u64);
+    std::fs::write(dir.join("new_file"), "new").unwrap();
+    assert_eq!(std::fs::read_to_string(file.clone()).unwrap(), "after");
+    assert_eq!(
+        std::fs::read_to_string(view.join("file")).unwrap(),
+        "before"
+    );
+    assert!(!view.join("new_file").exists());
+
+    std::fs::remove_file(file.clone()).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(view.join("file")).unwrap(),
+        "before"
+    );
+    let names: Vec<String> = std::fs::read_dir(view.clone())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(names, vec!["file".to_owned()]);
+
+    // Snapshots are read-only.
+    assert!(std::fs::write(view.join("file"), "no").is_err());
+
+    moto_rt::fs::release_snapshot(id).unwrap();
+    assert!(!view.exists());
+    // Only "new_file" is left.
+    let released = moto_rt::fs::dir_quota(dir.to_str().unwrap()).unwrap();
+    assert_eq!(released.used_inodes, 1);
+    assert_eq!(released.used_bytes, "new".len() as u64);
+    moto_rt::fs::set_dir_quota(
+        dir.to_str().unwrap(),
+        moto_rt::fs::QUOTA_UNLIMITED,
+        moto_rt::fs::QUOTA_UNLIMITED,
+    )
+    .unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+
+    println!("test_fs_snapshot() PASS");
+}
+
+fn test_fs_case_insensitive() {
+    let mut dir = std::env::temp_dir();
+    dir.push("CaseTest");
+    if dir.exists() {
+        std::fs::remove_dir_all(dir.clone()).unwrap();
+    }
+    std::fs::create_dir(dir.clone()).unwrap();
+    std::fs::write(dir.join("MixedCase"), "data").unwrap();
+    let alias = std::env::temp_dir().join("casetest");
+
+    if !alias.join("mixedcase").exists() {
+        // Names that differ only by case are different entries.
+        std::fs::create_dir(alias.clone()).unwrap();
+        std::fs::write(dir.join("mixedcase"), "other").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("MixedCase")).unwrap(),
+            "data"
+        );
+        std::fs::remove_dir_all(alias).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+        println!("test_fs_case_insensitive() SKIPPED: the volume is case-sensitive");
+        return;
+    }
+
+    // Lookups ignore case; names keep the case they were created with,
+    // and no second entry that differs only by case can be created.
+    assert_eq!(
+        std::fs::read_to_string(alias.join("MIXEDCASE")).unwrap(),
+        "data"
+    );
+    assert!(std::fs::File::create_new(alias.join("mixedCASE")).is_err());
+    let names: Vec<String> = std::fs::read_dir(alias.clone())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(names, vec!["MixedCase".to_owned()]);
+
+    if moto_sys::ProcessStaticPage::get().capabilities & moto_sys::caps::CAP_SYS == 0 {
+        assert_eq!(
+            moto_rt::fs::set_dir_quota(alias.to_str().unwrap(), 4096, 16).unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+        assert_eq!(
+            moto_rt::fs::dir_quota(alias.to_str().unwrap()).unwrap_err(),
+            moto_rt::E_NOT_FOUND
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+        println!("test_fs_case_insensitive() SKIPPED: needs CAP_SYS");
+        return;
+    }
+
+    // A quota set via an alias is the directory's quota, and is charged
+    // for files created via another alias.
+    moto_rt::fs::set_dir_quota(alias.to_str().unwrap(), moto_rt::fs::QUOTA_UNLIMITED, 2).unwrap();
+    let quota = moto_rt::fs::dir_quota(dir.to_str().unwrap()).unwrap();
+    assert_eq!(quota.max_inodes, 2);
+    assert_eq!(quota.used_inodes, 1);
+    std::fs::write(std::env::temp_dir().join("CASETEST/second"), "").unwrap();
+    assert!(std::fs::write(alias.join("third"), "").is_err());
+    assert_eq!(
+        moto_rt::fs::dir_quota(alias.to_str().unwrap())
+            .unwrap()
+            .used_inodes,
+        2
+    );
+    moto_rt::fs::set_dir_quota(
+        dir.to_str().unwrap(),
+        moto_rt::fs::QUOTA_UNLIMITED,
+        moto_rt::fs::QUOTA_UNLIMITED,
+    )
+    .unwrap();
+
+    // A snapshot taken via an alias preserves the entry written via another,
+    // and its view is case-insensitive, too.
+    let id = moto_rt::fs::snapshot(alias.to_str().unwrap()).unwrap();
+    std::fs::write(dir.join("mixedcase"), "changed").unwrap();
+    let view = std::path::PathBuf::from(format!("{}/{}", moto_rt::fs::SNAPSHOT_DIR, id));
+    let view_alias = std::path::PathBuf::from(format!(
+        "{}/{}",
+        moto_rt::fs::SNAPSHOT_DIR.to_uppercase(),
+        id
+    ));
+    assert_eq!(
+        std::fs::read_to_string(view.join("MixedCase")).unwrap(),
+        "data"
+    );
+    assert_eq!(
+        std::fs::read_to_string(view_alias.join("MIXEDCASE")).unwrap(),
+        "data"
+    );
+    assert_eq!(
+        std::fs::metadata(view.join("mixedcase")).unwrap().len(),
+        "data".len() as u64
+    );
+    assert!(std::fs::write(view_alias.join("MixedCase"), "no").is_err());
+    moto_rt::fs::release_snapshot(id).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(alias.join("MIXEDCASE")).unwrap(),
+        "changed"
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+    println!("test_fs_case_insensitive() PASS");
+}
+
+fn test_fs_vectored_io() {
+    use moto_sys_io::io_executor::{block_on, File};
+
+    let mut path = std::env::temp_dir();
+    path.push("vectored_io");
+    let path = path.to_str().unwrap().to_owned();
+    std::fs::write(&path, "Lorem Ipsum").unwrap();
+
+    // Over the FS channel, as one request.
+    let rt_fd = moto_rt::fs::open(&path, moto_rt::fs::O_READ | moto_rt::fs::O_WRITE).unwrap();
+    assert_eq!(
+        5,
+        moto_rt::fs::pwritev(rt_fd, &[&b"Dol"[..], &b"or"[..]], 6).unwrap()
+    );
+    let mut head = [0_u8; 5];
+    let mut sep = [0_u8; 1];
+    let mut tail = [0_u8; 5];
+    assert_eq!(
+        11,
+        moto_rt::fs::preadv(rt_fd, &mut [&mut head[..], &mut sep[..], &mut tail[..]], 0).unwrap()
+    );
+    assert_eq!(&head, b"Lorem");
+    assert_eq!(&sep, b" ");
+    assert_eq!(&tail, b"Dolor");
+    // The file position is not used or changed.
+    assert_eq!(
+        0,
+        moto_rt::fs::seek(rt_fd, 0, moto_rt::fs::SEEK_CUR).unwrap()
+    );
+    moto_rt::fs::close(rt_fd).unwrap();
+
+    // Over io_channel, scatter/gather: a header and a body that straddle
+    // page and request boundaries.
+    let header: Vec<u8> = (0..100).map(|idx| idx as u8).collect();
+    let body: Vec<u8> = (0..(40 * 1024)).map(|idx| (idx % 251) as u8).collect();
+    block_on(async {
+        let file = File::open(&path, moto_rt::fs::O_READ | moto_rt::fs::O_WRITE)
+            .await
+            .unwrap();
+        assert_eq!(
+            file.write_vectored_at(11, &[&header[..], &body[..]])
+                .await
+                .unwrap(),
+            header.len() + body.len()
+        );
+
+        let mut header_back = vec![0_u8; header.len()];
+        let mut body_back = vec![0_u8; body.len()];
+        assert_eq!(
+            file.read_vectored_at(11, &mut [&mut header_back[..], &mut body_back[..]])
+                .await
+                .unwrap(),
+            header.len() + body.len()
+        );
+        assert_eq!(header_back, header);
+        assert!(body_back == body);
+
+        // Short at EOF: later segments are left alone.
+        let mut first = [0_u8; 8];
+        let mut second = [0xff_u8; 8];
+        let eof = 11 + (header.len() + body.len()) as u64;
+        assert_eq!(
+            file.read_vectored_at(eof - 4, &mut [&mut first[..], &mut second[..]])
+                .await
+                .unwrap(),
+            4
+        );
+        assert_eq!(&first[0..4], &body[(body.len() - 4)..]);
+        assert_eq!(second, [0xff_u8; 8]);
+        file.close().await.unwrap();
+    });
+
+    let contents = std::fs::read(&path).unwrap();
+    assert_eq!(&contents[0..11], b"Lorem Dolor");
+    assert_eq!(&contents[11..111], &header[..]);
+    assert!(contents[111..] == body[..]);
+
+    std::fs::remove_file(&path).unwrap();
+    println!("test_fs_vectored_io() PASS");
+}
+
+fn test_fs_writeback() {
+    use moto_rt::fs::WritebackPolicy;
+
+    let old_policy = moto_rt::fs::writeback_stats().unwrap().policy;
+    // Make sure the background writeback does not kick in mid-test.
+    let policy = WritebackPolicy {
+        max_dirty_bytes: 1 << 30,
+        max_dirty_age_ms: 3600 * 1000,
+    };
+
+    let mut path = std::env::temp_dir();
+    path.push("writeback_test");
+    std::fs::write(path.clone(), "").unwrap();
+
+    // Setting the policy requires CAP_SYS; without it, the dirty counters
+    // may change under the test, so only check that the data gets written back.
+    if moto_sys::ProcessStaticPage::get().capabilities & moto_sys::caps::CAP_SYS == 0 {
+        assert_eq!(
+            moto_rt::fs::set_writeback_policy(&policy).unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+        assert_eq!(moto_rt::fs::writeback_stats().unwrap().policy, old_policy);
+
+        let rt_fd = moto_rt::fs::open(
+            path.to_str().unwrap(),
+            moto_rt::fs::O_WRITE | moto_rt::fs::O_WRITEBACK,
+        )
+        .unwrap();
+        assert_eq!(
+            10,
+            moto_rt::fs::pwritev(rt_fd, &[&b"Lorem"[..], &b"Ipsum"[..]], 0).unwrap()
+        );
+        moto_rt::fs::writeback().unwrap();
+        moto_rt::fs::close(rt_fd).unwrap();
+        assert_eq!(std::fs::read(path.clone()).unwrap(), b"LoremIpsum");
+
+        std::fs::remove_file(path).unwrap();
+        println!("test_fs_writeback() SKIPPED: needs CAP_SYS");
+        return;
+    }
+
+    moto_rt::fs::set_writeback_policy(&policy).unwrap();
+    assert_eq!(moto_rt::fs::writeback_stats().unwrap().policy, policy);
+
+    let rt_fd = moto_rt::fs::open(
+        path.to_str().unwrap(),
+        moto_rt::fs::O_WRITE | moto_rt::fs::O_WRITEBACK,
+    )
+    .unwrap();
+    assert_eq!(
+        10,
+        moto_rt::fs::pwritev(rt_fd, &[&b"Lorem"[..], &b"Ipsum"[..]], 0).unwrap()
+    );
+    let stats = moto_rt::fs::writeback_stats().unwrap();
+    assert!(stats.dirty_bytes >= 10);
+    assert!(stats.dirty_pages >= 1);
+
+    moto_rt::fs::writeback().unwrap();
+    let stats = moto_rt::fs::writeback_stats().unwrap();
+    assert_eq!(stats.dirty_bytes, 0);
+    assert_eq!(stats.dirty_pages, 0);
+
+    // fsync on an O_WRITEBACK fd also writes back.
+    moto_rt::fs::pwritev(rt_fd, &[&b"Dolor"[..]], 10).unwrap();
+    assert!(moto_rt::fs::writeback_stats().unwrap().dirty_bytes > 0);
+    moto_rt::fs::fsync(rt_fd).unwrap();
+    assert_eq!(moto_rt::fs::writeback_stats().unwrap().dirty_bytes, 0);
+
+    // So does fsync on another (not O_WRITEBACK) fd of the same file.
+    let block = [0xa5_u8; 4096];
+    assert_eq!(
+        8192,
+        moto_rt::fs::pwritev(rt_fd, &[&block[..], &block[..]], 15).unwrap()
+    );
+    assert!(moto_rt::fs::writeback_stats().unwrap().dirty_bytes >= 8192);
+    let sync_fd = moto_rt::fs::open(path.to_str().unwrap(), moto_rt::fs::O_READ).unwrap();
+    moto_rt::fs::fsync(sync_fd).unwrap();
+    assert_eq!(moto_rt::fs::writeback_stats().unwrap().dirty_bytes, 0);
+
+    let mut buf = [0_u8; 8192];
+    assert_eq!(
+        8192,
+        moto_rt::fs::preadv(sync_fd, &mut [&mut buf[..]], 15).unwrap()
+    );
+    assert!(buf.iter().all(|b| *b == 0xa5));
+    moto_rt::fs::close(sync_fd).unwrap();
+    moto_rt::fs::close(rt_fd).unwrap();
+
+    let contents = std::fs::read(path.clone()).unwrap();
+    assert_eq!(contents.len(), 15 + 8192);
+    assert_eq!(&contents[0..15], b"LoremIpsumDolor");
+
+    assert_eq!(
+        moto_rt::fs::set_writeback_policy(&WritebackPolicy {
+            max_dirty_bytes: 0,
+            max_dirty_age_ms: u64::MAX,
+        }),
+        Err(moto_rt::E_INVALID_ARGUMENT)
+    );
+
+    moto_rt::fs::set_writeback_policy(&old_policy).unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    println!("test_fs_writeback() PASS");
+}
+
+fn test_fs_barrier() {
+    let mut path = std::env::temp_dir();
+    path.push("barrier_test");
+    std::fs::write(path.clone(), "").unwrap();
+
+    // An ordered log: each record is durable before the next one is written.
+    let rt_fd = moto_rt::fs::open(
+        path.to_str().unwrap(),
+        moto_rt::fs::O_WRITE | moto_rt::fs::O_WRITEBACK,
+    )
+    .unwrap();
+    for (idx, record) in [&b"Lorem"[..], &b"Ipsum"[..], &b"Dolor"[..]]
+        .iter()
+        .enumerate()
+    {
+        assert_eq!(
+            5,
+            moto_rt::fs::pwritev(rt_fd, &[record], (idx * 5) as u64).unwrap()
+        );
+        moto_rt::fs::barrier().unwrap();
+        assert_eq!(moto_rt::fs::writeback_stats().unwrap().dirty_bytes, 0);
+    }
+    moto_rt::fs::close(rt_fd).unwrap();
+    assert_eq!(std::fs::read(path.clone()).unwrap(), b"LoremIpsumDolor");
+
+    std::fs::remove_file(path).unwrap();
+    println!("test_fs_barrier() PASS");
+}
+
+fn test_fs_file_versions() {
+    let dir = std::env::temp_dir();
+    let path = dir.join("versions_test");
+    let renamed = dir.join("versions_test_renamed");
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&renamed);
+    std::fs::write(&path, "Lorem").unwrap();
+
+    let fd_a = moto_rt::fs::open(path.to_str().unwrap(), moto_rt::fs::O_WRITE).unwrap();
+    let fd_b = moto_rt::fs::open(path.to_str().unwrap(), moto_rt::fs::O_WRITE).unwrap();
+    let v0 = moto_rt::fs::file_version(fd_a).unwrap();
+    assert_eq!(moto_rt::fs::file_version(fd_b).unwrap(), v0);
+
+    // The first writer wins; the second sees a conflict, and writes nothing.
+    let v1 = moto_rt::fs::write_if_version(fd_a, b"Ipsum", v0).unwrap();
+    assert_ne!(v1, v0);
+    assert_eq!(moto_rt::fs::file_version(fd_b).unwrap(), v1);
+    assert_eq!(
+        moto_rt::fs::write_if_version(fd_b, b"Dolor", v0).unwrap_err(),
+        moto_rt::E_ALREADY_IN_USE
+    );
+    assert_eq!(std::fs::read(&path).unwrap(), b"Ipsum");
+
+    // The version belongs to the file, not to its path.
+    std::fs::rename(&path, &renamed).unwrap();
+    assert_eq!(moto_rt::fs::file_version(fd_a).unwrap(), v1);
+    let fd_c = moto_rt::fs::open(renamed.to_str().unwrap(), moto_rt::fs::O_READ).unwrap();
+    assert_eq!(moto_rt::fs::file_version(fd_c).unwrap(), v1);
+
+    // Plain writes bump it, too.
+    assert_eq!(5, moto_rt::fs::pwritev(fd_b, &[&b"Dolor"[..]], 5).unwrap());
+    let v2 = moto_rt::fs::file_version(fd_c).unwrap();
+    assert_ne!(v2, v1);
+    assert_eq!(
+        moto_rt::fs::write_if_version(fd_a, b"Sit", v1).unwrap_err(),
+        moto_rt::E_ALREADY_IN_USE
+    );
+    // At fd_a's position, after its first write.
+    assert!(moto_rt::fs::write_if_version(fd_a, b"Sit", v2).unwrap() > v2);
+    assert_eq!(std::fs::read(&renamed).unwrap(), b"IpsumSitor");
+
+    moto_rt::fs::close(fd_c).unwrap();
+    moto_rt::fs::close(fd_b).unwrap();
+    moto_rt::fs::close(fd_a).unwrap();
+    std::fs::remove_file(&renamed).unwrap();
+
+    println!("test_fs_file_versions() PASS");
+}
+
+fn test_fs_async_io() {
+    use moto_sys_io::io_executor::{block_on, File};
+
+    let mut path = std::env::temp_dir();
+    path.push("async_io");
+    let path = path.to_str().unwrap().to_owned();
+    let _ = std::fs::remove_file(&path);
+
+    block_on(async {
+        let file = File::open(
+            &path,
+            moto_rt::fs::O_CREATE | moto_rt::fs::O_TRUNCATE | moto_rt::fs::O_WRITE,
+        )
+        .await
+        .unwrap();
+        assert_eq!(file.size(), 0);
+
+        // Larger than one request: several requests in flight.
+        let payload: Vec<u8> = (0..(40 * 1024)).map(|idx| (idx % 251) as u8).collect();
+        assert_eq!(file.write_at(512, &payload).await.unwrap(), payload.len());
+        let mut read_back = vec![0_u8; payload.len()];
+        assert_eq!(
+            file.read_at(512, &mut read_back).await.unwrap(),
+            payload.len()
+        );
+        assert_eq!(read_back, payload);
+
+        // Stops at EOF.
+        let mut tail = vec![0_u8; 64 * 1024];
+        assert_eq!(
+            file.read_at(512 + (payload.len() as u64) - 100, &mut tail)
+                .await
+                .unwrap(),
+            100
+        );
+        assert_eq!(&tail[0..100], &payload[(payload.len() - 100)..]);
+        file.sync().await.unwrap();
+
+        // More requests in flight than the channel's page pool can carry.
+        let big: Vec<u8> = (0..(320 * 1024)).map(|idx| (idx % 253) as u8).collect();
+        assert_eq!(file.write_at(64 * 1024, &big).await.unwrap(), big.len());
+        let mut big_back = vec![0_u8; big.len()];
+        assert_eq!(
+            file.read_at(64 * 1024, &mut big_back).await.unwrap(),
+            big.len()
+        );
+        assert!(big_back == big);
+        file.close().await.unwrap();
+
+        // Failed requests don't leak pages: the pool is still usable after
+        // many more of them than it has pages.
+        for _ in 0..(2 * moto_ipc::io_channel::CHANNEL_PAGE_COUNT) {
+            assert_eq!(
+                File::open("/sys/tmp/no-such-dir/no-such-file", moto_rt::fs::O_READ)
+                    .await
+                    .err(),
+                Some(moto_rt::E_NOT_FOUND)
+            );
+        }
+        let file = File::open(&path, moto_rt::fs::O_READ).await.unwrap();
+        assert_eq!(file.size(), 64 * 1024 + big.len() as u64);
+        let mut read_back = vec![0_u8; payload.len()];
+        assert_eq!(
+            file.read_at(512, &mut read_back).await.unwrap(),
+            payload.len()
+        );
+        assert_eq!(read_back, payload);
+
+        // A cancelled read: its pages are freed when it completes.
+        {
+            let mut buf = vec![0_u8; payload.len()];
+            let mut read = Box::pin(file.read_at(512, &mut buf));
+            let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+            let _ = std::future::Future::poll(read.as_mut(), &mut cx);
+        }
+        for _ in 0..moto_ipc::io_channel::CHANNEL_PAGE_COUNT {
+            assert_eq!(
+                file.read_at(512, &mut read_back).await.unwrap(),
+                payload.len()
+            );
+        }
+    });
+
+    std::fs::remove_file(&path).unwrap();
+    println!("test_fs_async_io() PASS");
+}
+
+fn test_fs_async_metadata() {
+    use moto_sys_io::io_executor::{block_on, rename, stat, File};
+
+    let mut path = std::env::temp_dir();
+    path.push("async_meta");
+    let path = path.to_str().unwrap().to_owned();
+    let renamed = format!("{path}.renamed");
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&renamed);
+
+    block_on(async {
+        let file = File::open(&path, moto_rt::fs::O_CREATE | moto_rt::fs::O_WRITE)
+            .await
+            .unwrap();
+        assert_eq!(file.write_at(0, &[7_u8; 5000]).await.unwrap(), 5000);
+
+        // The metadata is current, unlike size().
+        assert_eq!(file.size(), 0);
+        let attr = file.metadata().await.unwrap();
+        assert_eq!(attr.size, 5000);
+        assert_eq!(attr.file_type, moto_rt::fs::FILETYPE_FILE);
+        assert!(attr == stat(&path).await.unwrap());
+        assert!(attr == moto_rt::fs::stat(&path).unwrap());
+        file.close().await.unwrap();
+
+        let dir = stat(std::env::temp_dir().to_str().unwrap()).await.unwrap();
+        assert_eq!(dir.file_type, moto_rt::fs::FILETYPE_DIRECTORY);
+
+        rename(&path, &renamed).await.unwrap();
+        assert_eq!(stat(&path).await.err(), Some(moto_rt::E_NOT_FOUND));
+        assert_eq!(stat(&renamed).await.unwrap().size, 5000);
+        assert_eq!(
+            rename(&path, &renamed).await.err(),
+            Some(moto_rt::E_NOT_FOUND)
+        );
+
+        // Failed requests don't leak pages (see test_fs_async_io()).
+        for _ in 0..(2 * moto_ipc::io_channel::CHANNEL_PAGE_COUNT) {
+            assert_eq!(
+                stat("/sys/tmp/no-such-dir/no-such-file").await.err(),
+                Some(moto_rt::E_NOT_FOUND)
+            );
+        }
+        assert_eq!(stat(&renamed).await.unwrap().size, 5000);
+    });
+
+    std::fs::remove_file(&renamed).unwrap();
+    println!("test_fs_async_metadata() PASS");
+}
+
+fn test_fs_sync_files() {
+    use moto_rt::fs::{O_CREATE, O_WRITE, O_WRITEBACK};
+
+    let dir = std::env::temp_dir().join("sync_files_test");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir(&dir).unwrap();
+    let paths = [dir.join("a"), dir.join("b")];
+
+    // Several files and their directory, made durable together.
+    let rt_fds: Vec<_> = paths
+        .iter()
+        .map(|path| {
+            moto_rt::fs::open(path.to_str().unwrap(), O_WRITE | O_CREATE | O_WRITEBACK).unwrap()
+        })
+        .collect();
+    for (rt_fd, record) in rt_fds.iter().zip([&b"Lorem"[..], &b"Ipsum"[..]]) {
+        assert_eq!(5, moto_rt::fs::pwritev(*rt_fd, &[record], 0).unwrap());
+    }
+    let dir_fd = moto_rt::fs::opendir(dir.to_str().unwrap()).unwrap();
+    moto_rt::fs::sync_files(&rt_fds, Some(dir_fd)).unwrap();
+    assert_eq!(moto_rt::fs::writeback_stats().unwrap().dirty_bytes, 0);
+
+    // Without the directory, and with no files at all.
+    moto_rt::fs::sync_files(&rt_fds, None).unwrap();
+    moto_rt::fs::sync_files(&[], Some(dir_fd)).unwrap();
+
+    // Files must be files, and the directory a directory.
+    assert_eq!(
+        moto_rt::fs::sync_files(&[rt_fds[0], dir_fd], None).unwrap_err(),
+        moto_rt::E_BAD_HANDLE
+    );
+    assert_eq!(
+        moto_rt::fs::sync_files(&rt_fds, Some(rt_fds[0])).unwrap_err(),
+        moto_rt::E_BAD_HANDLE
+    );
+
+    moto_rt::fs::closedir(dir_fd).unwrap();
+    for rt_fd in &rt_fds {
+        moto_rt::fs::close(*rt_fd).unwrap();
+    }
+    assert_eq!(
+        moto_rt::fs::sync_files(&rt_fds, None).unwrap_err(),
+        moto_rt::E_BAD_HANDLE
+    );
+
+    assert_eq!(std::fs::read(&paths[0]).unwrap(), b"Lorem");
+    assert_eq!(std::fs::read(&paths[1]).unwrap(), b"Ipsum");
+    std::fs::remove_dir_all(&dir).unwrap();
+    println!("test_fs_sync_files() PASS");
+}
+
+fn test_fs_async_cancel() {
+    use moto_ipc::io_channel::{Msg, CHANNEL_PAGE_COUNT, NO_PAGE};
+    use moto_sys_io::api_fs::{CMD_IO_READ, CMD_IO_WRITE, IO_MAX_BYTES, IO_MAX_PAGES};
+    use moto_sys_io::io_executor::{self, block_on, File};
+
+    let mut path = std::env::temp_dir();
+    path.push("async_cancel");
+    let path = path.to_str().unwrap().to_owned();
+    let _ = std::fs::remove_file(&path);
+
+    let read_sqe = |fd: u64| {
+        let mut sqe = Msg::new();
+        sqe.command = CMD_IO_READ;
+        sqe.handle = fd;
+        sqe.flags = IO_MAX_BYTES as u32;
+        sqe.payload.args_64_mut()[2] = 0;
+        sqe
+    };
+
+    block_on(async {
+        let file = File::open(&path, moto_rt::fs::O_CREATE | moto_rt::fs::O_WRITE)
+            .await
+            .unwrap();
+        let payload: Vec<u8> = (0..IO_MAX_BYTES).map(|idx| (idx % 249) as u8).collect();
+        assert_eq!(file.write_at(0, &payload).await.unwrap(), payload.len());
+
+        // Reads that time out before their CQEs arrive: each holds IO_MAX_PAGES
+        // server pages until its CQE is reaped, so if they were leaked, the
+        // pool would run out long before the loop ends.
+        let mut timed_out = 0;
+        for _ in 0..(2 * CHANNEL_PAGE_COUNT) {
+            match io_executor::submit_with_timeout(
+                read_sqe(file.fd()),
+                vec![],
+                IO_MAX_PAGES,
+                Duration::ZERO,
+            )
+            .await
+            {
+                Err(moto_rt::E_TIMED_OUT) => timed_out += 1,
+                Ok((cqe, pages)) => {
+                    assert_eq!(cqe.status(), moto_rt::E_OK);
+                    assert_eq!(pages.len(), IO_MAX_PAGES);
+                }
+                Err(err) => panic!("unexpected error {err}"),
+            }
+        }
+        assert!(timed_out > 0);
+
+        // Writes that time out: their client pages belong to the server, which
+        // frees them.
+        for _ in 0..(2 * CHANNEL_PAGE_COUNT) {
+            let page = io_executor::alloc_pages(1).await.unwrap().pop().unwrap();
+            page.bytes_mut()[0..16].copy_from_slice(&payload[0..16]);
+            let mut sqe = Msg::new();
+            sqe.command = CMD_IO_WRITE;
+            sqe.handle = file.fd();
+            sqe.flags = 16;
+            sqe.payload.shared_pages_mut()[0..IO_MAX_PAGES].fill(NO_PAGE);
+            sqe.payload.args_64_mut()[2] = 0;
+            let result = io_executor::submit_with_timeout(sqe, vec![page], 0, Duration::ZERO).await;
+            assert!(matches!(result, Ok(_) | Err(moto_rt::E_TIMED_OUT)));
+        }
+
+        // Reads cancelled from another thread, after they have been sent.
+        for _ in 0..CHANNEL_PAGE_COUNT {
+            let (read, token) =
+                io_executor::submit_cancellable(read_sqe(file.fd()), vec![], IO_MAX_PAGES);
+            let mut read = Box::pin(read);
+            let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+            if let std::task::Poll::Ready(result) =
+                std::future::Future::poll(read.as_mut(), &mut cx)
+            {
+                assert_eq!(result.unwrap().0.status(), moto_rt::E_OK);
+                continue;
+            }
+            let canceller = std::thread::spawn(move || token.cancel());
+            match read.await {
+                Err(moto_rt::E_INTERRUPTED) => {}
+                Ok((cqe, _)) => assert_eq!(cqe.status(), moto_rt::E_OK), // Raced with cancel().
+                Err(err) => panic!("unexpected error {err}"),
+            }
+            canceller.join().unwrap();
+        }
+
+        // A cancelled request that has completed is not affected.
+        let (read, token) =
+            io_executor::submit_cancellable(read_sqe(file.fd()), vec![], IO_MAX_PAGES);
+        let (cqe, _) = read.await.unwrap();
+        token.cancel();
+        assert_eq!(cqe.status(), moto_rt::E_OK);
+
+        // All pages have been reclaimed: a read that needs the whole pool's
+        // worth of pages completes, in time.
+        let mut buf = vec![0_u8; CHANNEL_PAGE_COUNT * moto_ipc::io_channel::PAGE_SIZE];
+        for chunk in buf.chunks_mut(IO_MAX_BYTES) {
+            let (cqe, pages) = io_executor::submit_with_timeout(
+                read_sqe(file.fd()),
+                vec![],
+                IO_MAX_PAGES,
+                Duration::from_secs(5),
+            )
+            .await
+            .unwrap();
+            assert_eq!(cqe.status(), moto_rt::E_OK);
+            assert_eq!(cqe.payload.args_64()[2] as usize, IO_MAX_BYTES);
+            for (dst, page) in chunk
+                .chunks_mut(moto_ipc::io_channel::PAGE_SIZE)
+                .zip(pages.iter())
+            {
+                dst.copy_from_slice(page.bytes());
+            }
+            assert!(chunk == payload.as_slice());
+        }
+        file.close().await.unwrap();
+    });
+
+    std::fs::remove_file(&path).unwrap();
+    println!("test_fs_async_cancel() PASS");
+}
+
+fn test_fs_scatter_gather() {
+    use moto_ipc::io_channel::{IoPage, Msg, CHANNEL_PAGE_COUNT, NO_PAGE, PAGE_SIZE};
+    use moto_sys_io::api_fs::{CMD_IO_WRITE, IO_MAX_PAGES};
+    use moto_sys_io::io_executor::{self, block_on, File};
+
+    // The FS block size.
+    const BLOCK_SIZE: usize = 4096;
+
+    let mut path = std::env::temp_dir();
+    path.push("scatter_gather");
+    let path = path.to_str().unwrap().to_owned();
+    let _ = std::fs::remove_file(&path);
+
+    // A 40-block write from four 10-block buffers, read back into four others.
+    let bufs: Vec<Vec<u8>> = (0..4)
+        .map(|buf_idx| {
+            (0..(10 * BLOCK_SIZE))
+                .map(|idx| ((idx + buf_idx * 61) % 247) as u8)
+                .collect()
+        })
+        .collect();
+    block_on(async {
+        let file = File::open(
+            &path,
+            moto_rt::fs::O_CREATE | moto_rt::fs::O_READ | moto_rt::fs::O_WRITE,
+        )
+        .await
+        .unwrap();
+        let segments: Vec<&[u8]> = bufs.iter().map(|buf| &buf[..]).collect();
+        assert_eq!(
+            file.write_vectored_at(0, &segments).await.unwrap(),
+            40 * BLOCK_SIZE
+        );
+
+        let mut bufs_back = vec![vec![0_u8; 10 * BLOCK_SIZE]; 4];
+        let mut segments: Vec<&mut [u8]> = bufs_back.iter_mut().map(|buf| &mut buf[..]).collect();
+        assert_eq!(
+            file.read_vectored_at(0, &mut segments).await.unwrap(),
+            40 * BLOCK_SIZE
+        );
+        assert!(bufs_back == bufs);
+
+        // A page after an unused slot fails the request, and the server
+        // frees the pages: the pool is still usable after many such requests.
+        for _ in 0..CHANNEL_PAGE_COUNT {
+            let mut pages = io_executor::alloc_pages(2).await.unwrap().into_iter();
+            let mut sqe = Msg::new();
+            sqe.command = CMD_IO_WRITE;
+            sqe.handle = file.fd();
+            sqe.flags = (2 * PAGE_SIZE) as u32;
+            sqe.payload.shared_pages_mut()[0..IO_MAX_PAGES].fill(NO_PAGE);
+            sqe.payload.shared_pages_mut()[0] = IoPage::into_u16(pages.next().unwrap());
+            sqe.payload.shared_pages_mut()[2] = IoPage::into_u16(pages.next().unwrap());
+            sqe.payload.args_64_mut()[2] = 0;
+
+            let (cqe, _) = io_executor::submit(sqe, vec![], 0).await.unwrap();
+            assert_eq!(cqe.status(), moto_rt::E_INVALID_ARGUMENT);
+        }
+        file.close().await.unwrap();
+    });
+
+    // Nothing was written by the failed requests.
+    assert!(std::fs::read(&path).unwrap() == bufs.concat());
+
+    std::fs::remove_file(&path).unwrap();
+    println!("test_fs_scatter_gather() PASS");
+}
+
+fn test_fs_defragment() {
+    const BLOCKS: usize = 64;
+    const BLOCK_SIZE: usize = 4096;
+
+    let dir = std::env::temp_dir();
+    let path = dir.join("defrag_test");
+    let other = dir.join("defrag_test_other");
+    let contents = |tag: u8| -> Vec<u8> {
+        (0..(BLOCKS * BLOCK_SIZE))
+            .map(|idx| tag ^ ((idx / BLOCK_SIZE) as u8))
+            .collect()
+    };
+
+    // Interleave appends to two files, so that neither is contiguous.
+    {
+        let mut file = std::fs::File::create(path.clone()).unwrap();
+        let mut other_file = std::fs::File::create(other.clone()).unwrap();
+        let (bytes, other_bytes) = (contents(0), contents(0x80));
+        for idx in 0..BLOCKS {
+            let range = (idx * BLOCK_SIZE)..((idx + 1) * BLOCK_SIZE);
+            file.write_all(&bytes[range.clone()]).unwrap();
+            other_file.write_all(&other_bytes[range]).unwrap();
+        }
+    }
+
+    let rt_fd = moto_rt::fs::open(path.to_str().unwrap(), moto_rt::fs::O_READ).unwrap();
+    let before = moto_rt::fs::file_extents(rt_fd).unwrap();
+    assert_eq!(before.blocks, BLOCKS as u64);
+    assert!(before.extents > 1);
+    assert_eq!(before.pending_blocks, 0);
+
+    let started = moto_rt::fs::defragment(rt_fd).unwrap();
+    assert_eq!(started.extents, before.extents);
+    assert_eq!(started.pending_blocks, BLOCKS as u64);
+
+    // The file is moved in the background, and is readable meanwhile.
+    let expected = contents(0);
+    let deadline = std::time::Instant::now() + Duration::from_secs(10);
+    loop {
+        let mut buf = vec![0_u8; expected.len()];
+        moto_rt::fs::seek(rt_fd, 0, moto_rt::fs::SEEK_SET).unwrap();
+        let mut done = 0;
+        while done < buf.len() {
+            let sz = moto_rt::fs::read(rt_fd, &mut buf[done..]).unwrap();
+            assert!(sz > 0);
+            done += sz;
+        }
+        assert!(buf == expected);
+
+        let extents = moto_rt::fs::file_extents(rt_fd).unwrap();
+        if extents.pending_blocks == 0 {
+            assert_eq!(extents.blocks, BLOCKS as u64);
+            assert_eq!(extents.extents, 1);
+            break;
+        }
+        assert!(std::time::Instant::now() < deadline);
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    // A contiguous file is not moved again.
+    let again = moto_rt::fs::defragment(rt_fd).unwrap();
+    assert_eq!(again.extents, 1);
+    assert_eq!(again.pending_blocks, 0);
+    moto_rt::fs::close(rt_fd).unwrap();
+
+    assert!(std::fs::read(other.clone()).unwrap() == contents(0x80));
+    std::fs::remove_file(path).unwrap();
+    std::fs::remove_file(other).unwrap();
+
+    println!("test_fs_defragment() PASS");
+}
+
+#[allow(unused)]
+fn test_stdio() {
+    fn func(num: i32) {
+        for i in 0..5 {
+            println!("test_stdio {}: {}", num, i);
+        }
+    }
+
+    func(0);
+
+    let t1 = std::thread::spawn(|| func(1));
+    let t2 = std::thread::spawn(|| func(2));
+    let t3 = std::thread::spawn(|| func(3));
+
+    t1.join().unwrap();
+    t2.join().unwrap();
+    t3.join().unwrap();
+
+    println!("test_stdio() PASS");
+}
+
+fn test_stdio_redirect() {
+    use moto_rt::process::*;
+
+    const INPUT: &str = "/sys/tmp/systest_stdio_redirect.in";
+    const OUTPUT: &str = "/sys/tmp/systest_stdio_redirect.out";
+
+    // Runs the subcommand with stdin from INPUT and stdout to OUTPUT,
+    // and returns the contents of OUTPUT afterwards.
+    fn run(commands: &str, append: bool) -> String {
+        std::fs::write(INPUT, commands).unwrap();
+        let args = SpawnArgs {
+            program: std::env::args().next().unwrap(),
+            args: vec!["subcommand".to_owned()],
+            env: vec![
+                ("some_key".to_owned(), "some_val".to_owned()),
+                ("none_key".to_owned(), "".to_owned()),
+            ],
+            cwd: None,
+            stdin: moto_rt::fs::open(INPUT, moto_rt::fs::O_READ).unwrap(),
+            stdout: open_stdio_file(OUTPUT, append).unwrap(),
+            stderr: STDIO_NULL,
+        };
+        let (handle, _, _, _) = spawn(args).unwrap();
+        // Returns after the relay has written all of the child's output.
+        assert_eq!(0, wait(handle).unwrap());
+        moto_sys::SysObj::put(SysHandle::from_u64(handle)).unwrap();
+        std::fs::read_to_string(OUTPUT).unwrap()
+    }
+
+    assert_eq!(run("echo first\nexit 0\n", false), "first\n");
+    assert_eq!(run("echo second\nexit 0\n", true), "first\nsecond\n");
+    assert_eq!(run("echo third\nexit 0\n", false), "third\n");
+
+    // Only files and pipes can be relayed to; the fd is not taken over.
+    let dir = moto_rt::fs::opendir("/sys/tmp").unwrap();
+    let args = SpawnArgs {
+        program: std::env::args().next().unwrap(),
+        args: vec!["subcommand".to_owned()],
+        env: vec![],
+        cwd: None,
+        stdin: STDIO_NULL,
+        stdout: dir,
+        stderr: STDIO_NULL,
+    };
+    assert_eq!(spawn(args).unwrap_err(), moto_rt::E_INVALID_ARGUMENT);
+    moto_rt::fs::readdir(dir).unwrap();
+    moto_rt::fs::closedir(dir).unwrap();
+
+    std::fs::remove_file(INPUT).unwrap();
+    std::fs::remove_file(OUTPUT).unwrap();
+    println!("test_stdio_redirect() PASS");
+}
+
+fn test_oom() {
+    let mut child = subcommand::spawn();
+    child.oom();
+    let status = child.wait().unwrap();
+    assert!(!status.success());
+
+    println!("test_oom() PASS");
+}
+
+fn test_process_stats_v2() {
+    use moto_sys::stats::ProcessStatsV2;
+
+    let pid = moto_sys::current_pid();
+    let mut stats = [ProcessStatsV2::default()];
+    assert_eq!(ProcessStatsV2::list(pid, &mut stats).unwrap(), 1);
+    let stats = &stats[0];
+    assert_eq!(stats.v1.pid, pid);
+    assert_eq!(stats.per_cpu().len(), moto_sys::num_cpus() as usize);
+    assert_eq!(stats.truncated, 0);
+
+    let per_cpu_total: u64 = stats
+        .per_cpu()
+        .iter()
+        .map(|entry| entry.uspace + entry.kernel)
+        .sum();
+    assert!(per_cpu_total > 0);
+    assert!(stats.per_cpu().iter().any(|entry| entry.uspace > 0));
+
+    // Peaks are read after the current values, so they can't be lower.
+    assert!(stats.peak_pages_user >= stats.v1.pages_user);
+    assert!(stats.peak_pages_kernel >= stats.v1.pages_kernel);
+
+    println!("test_process_stats_v2() PASS");
+}
+
+fn test_committed_pages() {
+    use moto_sys::stats::ProcessStatsV2;
+    use moto_sys::*;
+
+    // Other threads may allocate or free memory, so this is not exact.
+    const NUM_PAGES: u64 = 1024;
+    let get = || {
+        let mut stats = [ProcessStatsV2::default()];
+        assert_eq!(ProcessStatsV2::list(current_pid(), &mut stats).unwrap(), 1);
+        assert!(stats[0].pages_user_committed >= stats[0].v1.pages_user);
+        (stats[0].v1.pages_user, stats[0].pages_user_committed)
+    };
+
+    // A lazy mapping is committed in full right away, but becomes resident
+    // only as its pages are touched.
+    let (resident_before, committed_before) = get();
+    let addr = SysMem::map(
+        SysHandle::SELF,
+        SysMem::F_READABLE | SysMem::F_WRITABLE | SysMem::F_LAZY,
+        u64::MAX,
+        u64::MAX,
+        sys_mem::PAGE_SIZE_SMALL,
+        NUM_PAGES,
+    )
+    .unwrap();
+    let (resident_mapped, committed_mapped) = get();
+    assert!(committed_mapped >= committed_before + NUM_PAGES / 2);
+    assert!(resident_mapped < resident_before + NUM_PAGES / 4);
+
+    for page in 0..(NUM_PAGES / 2) {
+        let ptr = (addr + page * sys_mem::PAGE_SIZE_SMALL) as usize as *mut u64;
+        unsafe { ptr.write_volatile(page) };
+    }
+    let (resident_touched, committed_touched) = get();
+    assert!(resident_touched >= resident_mapped + NUM_PAGES / 4);
+    assert!(resident_touched < resident_mapped + NUM_PAGES * 3 / 4);
+    assert!(committed_touched < committed_mapped + NUM_PAGES / 4);
+
+    SysMem::free(addr).unwrap();
+    let (resident_freed, committed_freed) = get();
+    assert!(committed_freed + NUM_PAGES / 2 <= committed_touched);
+    assert!(resident_freed + NUM_PAGES / 4 <= resident_touched);
+
+    println!("test_committed_pages() PASS");
+}
+
+fn test_list_descendants() {
+    use moto_sys::stats::{ProcessStatsV2, PID_SYSTEM};
+
+    let pid = moto_sys::current_pid();
+    let mut children = [subcommand::spawn(), subcommand::spawn()];
+    let child_pids = [children[0].pid(), children[1].pid()];
+    let mut buf: Vec<ProcessStatsV2> = (0..64).map(|_| ProcessStatsV2::default()).collect();
+
+    // Depth zero: the start process only.
+    assert_eq!(
+        ProcessStatsV2::list_descendants(pid, 0, 0, &mut buf).unwrap(),
+        1
+    );
+    assert_eq!(buf[0].v1.pid, pid);
+
+    // Depth one: the process, then its children, in PID order.
+    let count = ProcessStatsV2::list_descendants(pid, 1, 0, &mut buf).unwrap();
+    assert_eq!(buf[0].v1.pid, pid);
+    let listed: Vec<u64> = buf[1..count].iter().map(|stats| stats.v1.pid).collect();
+    assert!(buf[1..count].iter().all(|stats| stats.v1.parent_pid == pid));
+    assert!(listed.windows(2).all(|pids| pids[0] < pids[1]));
+    assert!(child_pids.iter().all(|child| listed.contains(child)));
+
+    // The whole tree, pre-order: each process comes after its parent.
+    let count = ProcessStatsV2::list_descendants(PID_SYSTEM, u32::MAX, 0, &mut buf).unwrap();
+    let all: Vec<&ProcessStatsV2> = buf[..count].iter().collect();
+    let position = |pid: u64| all.iter().position(|stats| stats.v1.pid == pid);
+    assert_eq!(all[0].v1.pid, PID_SYSTEM);
+    if count < buf.len() {
+        let own = position(pid).unwrap();
+        assert!(child_pids
+            .iter()
+            .all(|child| position(*child).unwrap() > own));
+    }
+
+    // Filtered by capabilities: the tree is still walked, but only
+    // processes that have all of them are listed.
+    let caps = moto_sys::ProcessStaticPage::get().capabilities;
+    let count = ProcessStatsV2::list_descendants(pid, 1, u64::MAX, &mut buf).unwrap();
+    assert_eq!(count, 0);
+    if caps != 0 {
+        let count = ProcessStatsV2::list_descendants(PID_SYSTEM, u32::MAX, caps, &mut buf).unwrap();
+        assert!(buf[..count].iter().any(|stats| stats.v1.pid == pid));
+    }
+
+    for child in &mut children {
+        child.do_exit(0);
+        assert!(child.wait().unwrap().success());
+    }
+    println!("test_list_descendants() PASS");
+}
+
+// Done in a child, as without CAP_SYS the raised priority can't be reverted,
+// and systest would remain the preferred OOM victim.
+fn test_oom_priority() {
+    use moto_sys::SysRay;
+
+    let mut child = subcommand::spawn();
+    let pid = child.pid();
+    let priority = SysRay::oom_priority(pid).unwrap();
+    assert_ne!(priority, SysRay::OOM_PRIORITY_MAX);
+    if moto_sys::ProcessStaticPage::get().capabilities & moto_sys::caps::CAP_SYS == 0 {
+        // Only the process itself can raise its priority.
+        assert_eq!(
+            SysRay::set_oom_priority(pid, SysRay::OOM_PRIORITY_MAX).unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+    }
+
+    child.raise_oom_priority();
+    let start = std::time::Instant::now();
+    while SysRay::oom_priority(pid).unwrap() != SysRay::OOM_PRIORITY_MAX {
+        assert!(child.try_wait().unwrap().is_none()); // The child's checks failed.
+        assert!(start.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    child.do_exit(0);
+    assert!(child.wait().unwrap().success());
+
+    println!("test_oom_priority() PASS");
+}
+
+fn test_log_rate_limit() {
+    use moto_sys::SysRay;
+
+    assert_eq!(
+        SysRay::log_rate_limit().unwrap(),
+        (SysRay::LOG_RATE_DEFAULT, SysRay::LOG_BURST_DEFAULT)
+    );
+    assert_eq!(SysRay::log_messages_dropped().unwrap(), 0);
+
+    let caps = moto_sys::ProcessStaticPage::get().capabilities;
+    if caps & moto_sys::caps::CAP_SYS == 0 {
+        // Changing the limit requires CAP_SYS.
+        assert_eq!(
+            SysRay::set_log_rate_limit(0, 0).unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+        println!("test_log_rate_limit() SKIPPED: needs CAP_SYS");
+        return;
+    }
+    if caps & moto_sys::caps::CAP_LOG == 0 {
+        println!("test_log_rate_limit() SKIPPED: needs CAP_LOG");
+        return;
+    }
+
+    assert_eq!(
+        SysRay::set_log_rate_limit(1, 0).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+
+    // One message a second: all but the first are dropped, without an error.
+    SysRay::set_log_rate_limit(1, 1).unwrap();
+    for idx in 0..5 {
+        SysRay::log(format!("test_log_rate_limit: message {idx}").as_str()).unwrap();
+    }
+    assert!(SysRay::log_messages_dropped().unwrap() >= 3);
+
+    // The drops are reported once the rate recovers, without another message.
+    let start = std::time::Instant::now();
+    while SysRay::log_messages_dropped().unwrap() != 0 {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(10));
     }
-    println!("stress_test_threads PASS");
+
+    SysRay::set_log_rate_limit(SysRay::LOG_RATE_DEFAULT, SysRay::LOG_BURST_DEFAULT).unwrap();
+    println!("test_log_rate_limit() PASS");
 }
 
-fn test_file_write() {
-    const WRITTEN: &str = "Lorem Ipsum";
+fn test_panic_action() {
+    use moto_sys::SysRay;
 
-    let mut path = std::env::temp_dir();
-    path.push("temp_file");
+    // Only CAP_SYS processes can take the system down with them.
+    for action in [SysRay::PANIC_ACTION_HALT, SysRay::PANIC_ACTION_REBOOT] {
+        assert_eq!(
+            SysRay::set_panic_action(action).unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+    }
+    assert_eq!(
+        SysRay::set_panic_action(SysRay::PANIC_ACTION_REBOOT + 1).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    SysRay::set_panic_action(SysRay::PANIC_ACTION_EXIT).unwrap();
 
-    if path.exists() {
-        std::fs::remove_file(path.clone()).unwrap();
+    // A process that panics just exits.
+    let mut child = subcommand::spawn();
+    child.panic();
+    assert!(!child.wait().unwrap().success());
+    let mut child = subcommand::spawn();
+    child.do_exit(0);
+    assert!(child.wait().unwrap().success());
+
+    println!("test_panic_action() PASS");
+}
+
+fn test_klog() {
+    use moto_sys::SysRay;
+
+    let mut buf = vec![0_u8; 4096];
+    if moto_sys::ProcessStaticPage::get().capabilities & moto_sys::caps::CAP_LOG == 0 {
+        assert_eq!(
+            SysRay::klog_read(0, &mut buf).unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+        println!("test_klog() SKIPPED: needs CAP_LOG");
+        return;
     }
 
-    // Write.
-    let mut file = std::fs::OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(path.clone())
-        .expect(format!("Failed to create {:?}", path).as_str());
+    assert_eq!(
+        SysRay::klog_read(0, &mut []).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    assert_eq!(
+        SysRay::klog_set_level("kernel", SysRay::KLOG_LEVEL_TRACE + 1).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    assert_eq!(
+        SysRay::klog_set_level("", SysRay::KLOG_LEVEL_INFO).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
 
-    file.write(WRITTEN.as_bytes()).unwrap();
-    std::mem::drop(file); // Close it.
+    // Reads all the lines logged after @from.
+    let read_from = |mut from: u64| {
+        let mut lines = String::new();
+        let mut buf = vec![0_u8; 4096];
+        loop {
+            let (len, next) = SysRay::klog_read(from, &mut buf).unwrap();
+            if len == 0 {
+                return (lines, from);
+            }
+            assert_eq!(next, from + len as u64);
+            // Whole lines only.
+            assert_eq!(buf[len - 1], b'\n');
+            lines.push_str(core::str::from_utf8(&buf[0..len]).unwrap());
+            from = next;
+        }
+    };
+    let (_, end) = read_from(0);
 
-    // Read.
-    let mut file = std::fs::OpenOptions::new()
-        .read(true)
-        .open(path.clone())
-        .unwrap();
-    let mut read_back = String::new();
-    file.read_to_string(&mut read_back).unwrap();
+    // A line too long for the buffer is split.
+    let marker = format!(
+        "test_klog marker {}",
+        moto_rt::time::Instant::now().as_u64()
+    );
+    SysRay::log(marker.as_str()).unwrap();
+    let mut short = [0_u8; 8];
+    assert_eq!(SysRay::klog_read(end, &mut short).unwrap(), (8, end + 8));
+    let (lines, end) = read_from(end);
+    assert!(lines.contains(marker.as_str()));
 
-    assert_eq!(read_back.as_str(), WRITTEN);
-    core::mem::drop(file);
-    std::fs::remove_file(path.clone()).unwrap();
+    // Setting a level is logged by kernel::uspace::sys_ray, at Info,
+    // unless sys_ray itself is at Error.
+    const SYS_RAY: &str = "kernel::uspace::sys_ray";
+    const DUMMY: &str = "kernel::systest_klog";
+    assert_eq!(
+        SysRay::klog_set_level(SYS_RAY, SysRay::KLOG_LEVEL_ERROR).unwrap(),
+        SysRay::KLOG_LEVEL_OFF
+    );
+    SysRay::klog_set_level(DUMMY, SysRay::KLOG_LEVEL_INFO).unwrap();
+    assert_eq!(
+        SysRay::klog_set_level(SYS_RAY, SysRay::KLOG_LEVEL_OFF).unwrap(),
+        SysRay::KLOG_LEVEL_ERROR
+    );
+    assert_eq!(
+        SysRay::klog_set_level(DUMMY, SysRay::KLOG_LEVEL_OFF).unwrap(),
+        SysRay::KLOG_LEVEL_INFO
+    );
+    let (lines, _) = read_from(end);
+    assert!(!lines.contains(format!("'{DUMMY}' to Info").as_str()));
+    assert!(lines.contains(format!("'{SYS_RAY}' to Off").as_str()));
+    assert!(lines.contains(format!("'{DUMMY}' to Off").as_str()));
 
-    println!("test_file_write() PASS");
+    println!("test_klog() PASS");
 }
 
-#[allow(unused)]
-fn test_stdio() {
-    fn func(num: i32) {
-        for i in 0..5 {
-            println!("test_stdio {}: {}", num, i);
-        }
+fn test_deadlock_detection() {
+    use moto_sys::SysRay;
+
+    assert!(!SysRay::deadlock_detection().unwrap());
+    // Enabling it requires CAP_SYS.
+    assert_eq!(
+        SysRay::set_deadlock_detection(true).unwrap_err(),
+        moto_rt::E_NOT_ALLOWED
+    );
+
+    // Two processes, each waiting only for the other, with detection enabled
+    // for themselves. Whichever blocks last sees the cycle, and wakes the other.
+    let mut server = subcommand::spawn();
+    server.deadlock("systest_deadlock", true);
+    let mut client = subcommand::spawn();
+    client.deadlock("systest_deadlock", false);
+    let server_code = server.wait().unwrap().code().unwrap();
+    let client_code = client.wait().unwrap().code().unwrap();
+    for code in [server_code, client_code] {
+        assert!(code == 0 || code == subcommand::DEADLOCK_DETECTED);
     }
+    assert!(
+        server_code == subcommand::DEADLOCK_DETECTED
+            || client_code == subcommand::DEADLOCK_DETECTED
+    );
 
-    func(0);
+    println!("test_deadlock_detection() PASS");
+}
 
-    let t1 = std::thread::spawn(|| func(1));
-    let t2 = std::thread::spawn(|| func(2));
-    let t3 = std::thread::spawn(|| func(3));
+fn test_watchdog() {
+    use moto_sys::SysRay;
 
-    t1.join().unwrap();
-    t2.join().unwrap();
-    t3.join().unwrap();
+    // The watchdog is for system services only.
+    if moto_sys::ProcessStaticPage::get().capabilities & moto_sys::caps::CAP_SYS == 0 {
+        assert_eq!(
+            SysRay::watchdog_register(Duration::from_secs(1), SysRay::WATCHDOG_ACTION_LOG)
+                .unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+        assert_eq!(SysRay::watchdog_pet().unwrap_err(), moto_rt::E_NOT_ALLOWED);
+        assert_eq!(
+            SysRay::watchdog_unregister().unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+        println!("test_watchdog() SKIPPED: needs CAP_SYS");
+        return;
+    }
 
-    println!("test_stdio() PASS");
-}
+    assert_eq!(SysRay::watchdog_pet().unwrap_err(), moto_rt::E_NOT_FOUND);
+    for (timeout, action) in [
+        (Duration::ZERO, SysRay::WATCHDOG_ACTION_LOG),
+        (Duration::MAX, SysRay::WATCHDOG_ACTION_LOG),
+        (
+            Duration::from_secs(SysRay::MAX_WATCHDOG_TIMEOUT_SECS + 1),
+            SysRay::WATCHDOG_ACTION_LOG,
+        ),
+        (Duration::from_secs(1), 100),
+    ] {
+        assert_eq!(
+            SysRay::watchdog_register(timeout, action).unwrap_err(),
+            moto_rt::E_INVALID_ARGUMENT
+        );
+    }
 
-fn test_oom() {
-    let mut child = subcommand::spawn();
-    child.oom();
-    let status = child.wait().unwrap();
-    assert!(!status.success());
+    // A missed deadline with WATCHDOG_ACTION_LOG only logs, and the next pet re-arms it.
+    SysRay::watchdog_register(Duration::from_millis(10), SysRay::WATCHDOG_ACTION_LOG).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    SysRay::watchdog_pet().unwrap();
+    SysRay::watchdog_register(
+        Duration::from_secs(SysRay::MAX_WATCHDOG_TIMEOUT_SECS),
+        SysRay::WATCHDOG_ACTION_LOG,
+    )
+    .unwrap();
+    SysRay::watchdog_pet().unwrap();
+    SysRay::watchdog_unregister().unwrap();
+    assert_eq!(
+        SysRay::watchdog_unregister().unwrap_err(),
+        moto_rt::E_NOT_FOUND
+    );
 
-    println!("test_oom() PASS");
+    println!("test_watchdog() PASS");
 }
 
 fn test_caps() {
@@ -480,6 +4061,528 @@ fn test_caps() {
     println!("test_caps() PASS");
 }
 
+fn test_hostname() {
+    use moto_sys::SysRay;
+
+    let get = || {
+        let mut buf = [0_u8; SysRay::MAX_HOSTNAME_LEN];
+        let len = SysRay::get_hostname(&mut buf).unwrap();
+        assert!(len > 0 && len <= SysRay::MAX_HOSTNAME_LEN);
+        let hostname = core::str::from_utf8(&buf[0..len]).unwrap().to_owned();
+        assert!(!hostname.chars().any(|c| c.is_control()));
+        hostname
+    };
+
+    // A short buffer gets a prefix, and the full length is still returned.
+    let hostname = get();
+    let mut short = [0_u8; 1];
+    assert_eq!(SysRay::get_hostname(&mut short).unwrap(), hostname.len());
+    assert_eq!(short[0], hostname.as_bytes()[0]);
+    assert_eq!(SysRay::get_hostname(&mut []).unwrap(), hostname.len());
+
+    if moto_sys::ProcessStaticPage::get().capabilities & moto_sys::caps::CAP_SYS == 0 {
+        assert_eq!(
+            SysRay::set_hostname("systest").unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+        assert_eq!(get(), hostname);
+        println!("test_hostname() SKIPPED: needs CAP_SYS");
+        return;
+    }
+
+    let too_long = "h".repeat(SysRay::MAX_HOSTNAME_LEN + 1);
+    for bad in ["", "sys\ntest", too_long.as_str()] {
+        assert_eq!(
+            SysRay::set_hostname(bad).unwrap_err(),
+            moto_rt::E_INVALID_ARGUMENT
+        );
+        assert_eq!(get(), hostname);
+    }
+
+    let longest = "h".repeat(SysRay::MAX_HOSTNAME_LEN);
+    for name in ["systest-\u{00e9}", longest.as_str()] {
+        SysRay::set_hostname(name).unwrap();
+        assert_eq!(get(), name);
+    }
+    SysRay::set_hostname(hostname.as_str()).unwrap();
+    assert_eq!(get(), hostname);
+
+    println!("test_hostname() PASS");
+}
+
+fn test_cpu_limit() {
+    use moto_sys::SysRay;
+
+    assert_eq!(
+        SysRay::set_cpu_limit(SysHandle::SELF, 1000, 0).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    assert_eq!(
+        SysRay::set_cpu_limit(SysHandle::SELF, 0, SysRay::CPU_LIMIT_ACTION_KILL).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    assert!(std::process::Command::new(std::env::args().next().unwrap())
+        .arg("subcommand")
+        .env(moto_sys::sys_ray::MOTURUS_CPU_LIMIT_ENV_KEY, "50,sometimes")
+        .spawn()
+        .is_err());
+
+    // No limit by default.
+    let (limit, _) = SysRay::get_cpu_limit(moto_sys::current_pid()).unwrap();
+    assert_eq!(limit, 0);
+
+    // A child that uses up its 50ms is killed long before it is done spinning.
+    let mut child =
+        subcommand::spawn_with_env(&[(moto_sys::sys_ray::MOTURUS_CPU_LIMIT_ENV_KEY, "50")]);
+    let (limit, remaining) = SysRay::get_cpu_limit(child.pid()).unwrap();
+    assert!(limit > 0);
+    assert!(remaining <= limit);
+
+    let start = std::time::Instant::now();
+    child.spin(Duration::from_secs(10));
+    assert!(!child.wait().unwrap().success());
+    assert!(start.elapsed() < Duration::from_secs(10));
+
+    // A child that is notified instead keeps running: its std calls (here reading
+    // stdin) are not interrupted, and the interrupt stays pending.
+    let mut child =
+        subcommand::spawn_with_env(&[(moto_sys::sys_ray::MOTURUS_CPU_LIMIT_ENV_KEY, "50,notify")]);
+    child.spin(Duration::from_millis(200));
+    child.check_interrupted();
+    assert!(child.wait().unwrap().success());
+
+    println!("test_cpu_limit() PASS");
+}
+
+fn test_cmdline() {
+    use moto_sys::SysRay;
+
+    // Our own.
+    let pid = moto_sys::current_pid();
+    let len = SysRay::get_cmdline(pid, &mut []).unwrap();
+    assert!(len > 0);
+    let mut cmdline = vec![0_u8; len];
+    assert_eq!(len, SysRay::get_cmdline(pid, &mut cmdline).unwrap());
+    let (args, _) = subcommand::decode_cmdline(&cmdline);
+    assert_eq!(args, std::env::args().collect::<Vec<_>>());
+
+    // A short buffer gets a prefix.
+    let mut prefix = [0_u8; 4];
+    assert_eq!(len, SysRay::get_cmdline(pid, &mut prefix).unwrap());
+    assert_eq!(prefix[..], cmdline[0..4]);
+
+    // The child sees its own args and env, but without the capabilities.
+    let caps = format!(
+        "0x{:x}",
+        moto_sys::caps::CAP_SPAWN | moto_sys::caps::CAP_LOG
+    );
+    let mut child =
+        subcommand::spawn_with_env(&[(moto_sys::caps::MOTURUS_CAPS_ENV_KEY, caps.as_str())]);
+
+    // Others' cmdlines require CAP_SYS.
+    assert_eq!(
+        SysRay::get_cmdline(child.pid(), &mut cmdline).unwrap_err(),
+        moto_rt::E_NOT_ALLOWED
+    );
+
+    child.check_cmdline();
+    assert!(child.wait().unwrap().success());
+
+    println!("test_cmdline() PASS");
+}
+
+fn test_syscall_filter() {
+    use moto_sys::syscalls::SyscallFilter;
+
+    // Filters can't be loosened, so the child installs them.
+    assert_eq!(
+        moto_sys::SysObj::syscall_filter().unwrap(),
+        SyscallFilter::allow_all()
+    );
+    let mut child = subcommand::spawn();
+    child.syscall_filter();
+    assert!(child.wait().unwrap().success());
+
+    // The parent is not affected.
+    assert_eq!(
+        moto_sys::SysObj::syscall_filter().unwrap(),
+        SyscallFilter::allow_all()
+    );
+    moto_sys::SysRay::get_hostname(&mut []).unwrap();
+
+    println!("test_syscall_filter() PASS");
+}
+
+fn test_process_affinity() {
+    use moto_sys::SysCpu;
+
+    if moto_sys::num_cpus() < 3 {
+        println!("test_process_affinity() SKIPPED: need at least 3 CPUs");
+        return;
+    }
+
+    let (old_cpu, old_inherit) = SysCpu::process_affinity(SysHandle::SELF).unwrap();
+
+    // Set at spawn time.
+    let mut child = subcommand::spawn_with_env(&[(
+        moto_sys::sys_cpu::MOTURUS_CPU_AFFINITY_ENV_KEY,
+        "2,inherit",
+    )]);
+    child.check_affinity(Some(2), true);
+    assert!(child.wait().unwrap().success());
+
+    // Inherited from the parent.
+    SysCpu::set_process_affinity(SysHandle::SELF, Some(1), true).unwrap();
+    let mut child = subcommand::spawn();
+    child.check_affinity(Some(1), true);
+    assert!(child.wait().unwrap().success());
+
+    // Not inherited.
+    SysCpu::set_process_affinity(SysHandle::SELF, Some(1), false).unwrap();
+    let mut child = subcommand::spawn();
+    child.check_affinity(None, false);
+    assert!(child.wait().unwrap().success());
+
+    // Only IO_MANAGER can be affined to CPU 0.
+    assert_eq!(
+        SysCpu::set_process_affinity(SysHandle::SELF, Some(0), false).unwrap_err(),
+        moto_rt::E_NOT_ALLOWED
+    );
+    assert_eq!(
+        SysCpu::set_process_affinity(SysHandle::SELF, Some(moto_sys::num_cpus()), false)
+            .unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+
+    SysCpu::set_process_affinity(SysHandle::SELF, old_cpu, old_inherit).unwrap();
+
+    println!("test_process_affinity() PASS");
+}
+
+fn test_mem_fragmentation() {
+    use moto_sys::stats::MemFragmentationStats;
+    use moto_sys::sys_mem::PAGE_SIZE_SMALL;
+    use moto_sys::SysMem;
+
+    let get = || {
+        let stats = MemFragmentationStats::get().unwrap();
+
+        // Each run is counted in its order, and runs never span segments.
+        let mut min_pages = 0;
+        let mut max_pages = 0;
+        for (order, runs) in stats.free_runs.iter().enumerate() {
+            min_pages += runs << order;
+            max_pages += runs * ((1 << (order + 1)) - 1);
+        }
+        assert!(min_pages <= stats.free_pages && stats.free_pages <= max_pages);
+        assert!(stats.largest_free_run <= 64);
+        if let Some(order) = stats.free_runs.iter().rposition(|runs| *runs > 0) {
+            assert!((1 << order) <= stats.largest_free_run);
+            assert!(stats.largest_free_run < (1 << (order + 1)));
+        } else {
+            assert_eq!(stats.largest_free_run, 0);
+        }
+        stats
+    };
+
+    // 16M worth of small pages come out of, then go back to, the free runs.
+    const NUM_PAGES: u64 = 4096;
+    let before = get();
+    let addr = SysMem::alloc(PAGE_SIZE_SMALL, NUM_PAGES).unwrap();
+    let allocated = get();
+    assert!(allocated.free_pages + NUM_PAGES / 2 <= before.free_pages);
+    SysMem::free(addr).unwrap();
+    let freed = get();
+    assert!(allocated.free_pages + NUM_PAGES / 2 <= freed.free_pages);
+
+    println!("test_mem_fragmentation() PASS");
+}
+
+fn test_frame_caches() {
+    use moto_sys::stats::FrameCacheStats;
+    use moto_sys::sys_mem::PAGE_SIZE_SMALL;
+    use moto_sys::{SysCpu, SysMem};
+
+    let get = || {
+        let stats = FrameCacheStats::get().unwrap();
+        assert_eq!(stats.len(), moto_sys::num_cpus() as usize);
+        for cache in &stats {
+            assert!(cache.cached_pages <= 64);
+            assert!((0.0..=1.0).contains(&cache.hit_rate()));
+        }
+        stats
+    };
+
+    // Allocations on a CPU are counted in its cache; pages freed there are
+    // cached, so allocating them again hits.
+    const NUM_PAGES: u64 = 256;
+    let cpu = moto_sys::num_cpus() - 1;
+    let (before, allocated, reallocated) = std::thread::spawn(move || {
+        SysCpu::affine_to_cpu(Some(cpu)).unwrap();
+        std::thread::yield_now();
+
+        let before = get()[cpu as usize];
+        let addr = SysMem::alloc(PAGE_SIZE_SMALL, NUM_PAGES).unwrap();
+        let allocated = get()[cpu as usize];
+        SysMem::free(addr).unwrap();
+        let addr = SysMem::alloc(PAGE_SIZE_SMALL, 8).unwrap();
+        let reallocated = get()[cpu as usize];
+        SysMem::free(addr).unwrap();
+
+        SysCpu::affine_to_cpu(None).unwrap();
+        (before, allocated, reallocated)
+    })
+    .join()
+    .unwrap();
+
+    assert!(allocated.hits + allocated.misses >= before.hits + before.misses + NUM_PAGES);
+    assert!(allocated.misses > before.misses);
+    assert!(reallocated.hits >= allocated.hits + 8);
+
+    println!("test_frame_caches() PASS");
+}
+
+fn test_memory_pressure() {
+    use moto_sys::{SysCpu, SysMem};
+
+    // Disabled by default.
+    let (under_pressure, high, low) = SysMem::memory_pressure().unwrap();
+    assert!(!under_pressure);
+    assert_eq!(high, SysMem::PRESSURE_DISABLED);
+    assert_eq!(low, 0);
+
+    // Nothing changes while disabled, so the handle is not woken.
+    let handle = SysMem::memory_pressure_handle().unwrap();
+    let wait = |timeout: Duration| {
+        SysCpu::wait(
+            &mut [handle],
+            SysHandle::NONE,
+            SysHandle::NONE,
+            Some(moto_rt::time::Instant::now() + timeout),
+        )
+    };
+    let buf = vec![0_u8; 1 << 20];
+    assert_eq!(
+        wait(Duration::from_millis(10)).unwrap_err(),
+        moto_rt::E_TIMED_OUT
+    );
+    core::mem::drop(buf);
+
+    if moto_sys::ProcessStaticPage::get().capabilities & moto_sys::caps::CAP_SYS == 0 {
+        // Setting the watermarks requires CAP_SYS.
+        assert_eq!(
+            SysMem::set_memory_pressure_thresholds(1 << 30, 1 << 29).unwrap_err(),
+            moto_rt::E_NOT_ALLOWED
+        );
+        moto_sys::SysObj::put(handle).unwrap();
+        println!("test_memory_pressure() SKIPPED: needs CAP_SYS");
+        return;
+    }
+
+    // No hysteresis is not allowed.
+    assert_eq!(
+        SysMem::set_memory_pressure_thresholds(1 << 30, 1 << 30).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    assert_eq!(
+        SysMem::set_memory_pressure_thresholds(1 << 29, 1 << 30).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    assert_eq!(
+        SysMem::memory_pressure().unwrap(),
+        (false, SysMem::PRESSURE_DISABLED, 0)
+    );
+
+    // Cross the high watermark up, then the low one down.
+    const MB: u64 = 1 << 20;
+    let used = moto_sys::stats::MemoryStats::get().unwrap().used();
+    let (high, low) = (used + 32 * MB, used + 16 * MB);
+    SysMem::set_memory_pressure_thresholds(high, low).unwrap();
+    assert_eq!(SysMem::memory_pressure().unwrap(), (false, high, low));
+
+    let addr = SysMem::alloc(
+        moto_sys::sys_mem::PAGE_SIZE_SMALL,
+        48 * MB / moto_sys::sys_mem::PAGE_SIZE_SMALL,
+    )
+    .unwrap();
+    wait(Duration::from_secs(5)).unwrap();
+    assert!(SysMem::memory_pressure().unwrap().0);
+
+    SysMem::free(addr).unwrap();
+    wait(Duration::from_secs(5)).unwrap();
+    assert!(!SysMem::memory_pressure().unwrap().0);
+
+    // Raising the watermarks above the old high leaves them consistent.
+    SysMem::set_memory_pressure_thresholds(high + 64 * MB, high + 32 * MB).unwrap();
+    assert_eq!(
+        SysMem::memory_pressure().unwrap(),
+        (false, high + 64 * MB, high + 32 * MB)
+    );
+
+    SysMem::set_memory_pressure_thresholds(SysMem::PRESSURE_DISABLED, 0).unwrap();
+    moto_sys::SysObj::put(handle).unwrap();
+    println!("test_memory_pressure() PASS");
+}
+
+fn test_numa_nodes() {
+    use moto_sys::stats::NumaNodeStats;
+    use moto_sys::SysMem;
+
+    let nodes = NumaNodeStats::get().unwrap();
+    assert!(!nodes.is_empty() && nodes.len() <= NumaNodeStats::MAX_NODES);
+
+    // Each CPU is in exactly one node.
+    let num_cpus = moto_sys::num_cpus();
+    let mut cpus = 0_u64;
+    for node in &nodes {
+        assert_eq!(0, cpus & node.cpus);
+        cpus |= node.cpus;
+        assert!(node.free_pages <= node.total_pages);
+    }
+    assert_eq!(cpus, (1_u64 << num_cpus) - 1);
+    let total_pages: u64 = nodes.iter().map(|node| node.total_pages).sum();
+    let mem_stats = moto_sys::stats::MemoryStats::get().unwrap();
+    assert!(total_pages << moto_sys::sys_mem::PAGE_SIZE_SMALL_LOG2 <= mem_stats.available);
+
+    assert_eq!(None, SysMem::numa_node().unwrap());
+    assert_eq!(
+        SysMem::set_numa_node(Some(nodes.len() as u32)).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+
+    // Prefer the last node, and touch some memory: it comes from that node
+    // (or, if there are no free pages there, from others, counted as fallbacks).
+    let node = nodes.len() - 1;
+    SysMem::set_numa_node(Some(node as u32)).unwrap();
+    assert_eq!(Some(node as u32), SysMem::numa_node().unwrap());
+
+    const NUM_PAGES: u64 = 256;
+    let before = NumaNodeStats::get().unwrap()[node];
+    let addr = SysMem::map(
+        moto_sys::SysHandle::SELF,
+        SysMem::F_READABLE | SysMem::F_WRITABLE | SysMem::F_LAZY,
+        u64::MAX,
+        u64::MAX,
+        moto_sys::sys_mem::PAGE_SIZE_SMALL,
+        NUM_PAGES,
+    )
+    .unwrap();
+    for page in 0..NUM_PAGES {
+        let ptr = (addr + page * moto_sys::sys_mem::PAGE_SIZE_SMALL) as usize as *mut u64;
+        unsafe { ptr.write_volatile(page) };
+    }
+    let after = NumaNodeStats::get().unwrap()[node];
+    SysMem::free(addr).unwrap();
+
+    // Other threads allocate and free pages concurrently, so this is not exact.
+    assert!(
+        after.free_pages + NUM_PAGES / 2 < before.free_pages
+            || after.remote_fallbacks > before.remote_fallbacks,
+        "NUMA node {node}: free pages before: {} after: {}",
+        before.free_pages,
+        after.free_pages
+    );
+
+    SysMem::set_numa_node(None).unwrap();
+    assert_eq!(None, SysMem::numa_node().unwrap());
+
+    println!("test_numa_nodes() PASS");
+}
+
+fn test_random() {
+    let mut a = [0_u8; 1000];
+    let mut b = [0_u8; 1000];
+    moto_sys::SysMem::get_random(&mut a).unwrap();
+    moto_sys::SysMem::get_random(&mut b).unwrap();
+    assert!(a.iter().any(|byte| *byte != 0));
+    assert_ne!(a, b);
+
+    // The most a single syscall gets.
+    let mut a = [0_u8; moto_sys::SysMem::MAX_RANDOM_BYTES];
+    let mut b = [0_u8; moto_sys::SysMem::MAX_RANDOM_BYTES];
+    moto_sys::SysMem::get_random(&mut a).unwrap();
+    moto_sys::SysMem::get_random(&mut b).unwrap();
+    assert!(a.iter().any(|byte| *byte != 0));
+    assert_ne!(a, b);
+
+    // Only sys-io feeds the entropy pool.
+    assert_eq!(
+        moto_sys::SysMem::add_entropy(&a).unwrap_err(),
+        moto_rt::E_NOT_ALLOWED
+    );
+
+    test_virtio_rng();
+    println!("test_random() PASS");
+}
+
+fn test_virtio_rng() {
+    use moto_sys_io::stats::{IoStatsService, MAX_VIRTIO_RNG_BYTES};
+
+    let mut stats = IoStatsService::connect().unwrap();
+    let mut a = [0_u8; MAX_VIRTIO_RNG_BYTES];
+    let mut b = [0_u8; MAX_VIRTIO_RNG_BYTES];
+    match stats.get_virtio_rng_bytes(&mut a) {
+        Ok(()) => {}
+        Err(moto_rt::E_NOT_FOUND) => {
+            println!("test_virtio_rng() SKIPPED: no VirtIO RNG device");
+            return;
+        }
+        Err(err) => panic!("get_virtio_rng_bytes() failed: {err}"),
+    }
+    stats.get_virtio_rng_bytes(&mut b).unwrap();
+    assert!(a.iter().any(|byte| *byte != 0));
+    assert_ne!(a, b);
+
+    // Fewer bytes than the device may return in one request.
+    let mut c = [0_u8; 3];
+    stats.get_virtio_rng_bytes(&mut c).unwrap();
+
+    let mut too_many = [0_u8; MAX_VIRTIO_RNG_BYTES + 1];
+    assert_eq!(
+        stats.get_virtio_rng_bytes(&mut too_many).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+
+    println!("test_virtio_rng() PASS");
+}
+
+fn test_virtio_devices() {
+    use moto_sys_io::stats::{IoStatsService, VirtioDeviceStatsV1};
+
+    const PCI_DEVICE_ID_BLOCK: u16 = 0x1042;
+    const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+    let devices = IoStatsService::connect()
+        .unwrap()
+        .get_virtio_devices()
+        .unwrap();
+
+    // The FS is on a VirtIO block device.
+    assert!(devices
+        .iter()
+        .any(|dev| dev.pci_device_id == PCI_DEVICE_ID_BLOCK && dev.driver_ok() && !dev.failed()));
+
+    for (idx, dev) in devices.iter().enumerate() {
+        // Each device is listed once, even if it has failed after initialization.
+        assert!(devices[(idx + 1)..].iter().all(|other| {
+            (other.pci_bus, other.pci_slot, other.pci_func)
+                != (dev.pci_bus, dev.pci_slot, dev.pci_func)
+        }));
+
+        // The driver acks only what the device offers.
+        assert_eq!(0, dev.features_acked & !dev.features_offered);
+
+        if dev.driver_ok() && !dev.failed() {
+            assert_ne!(0, dev.features_acked & VIRTIO_F_VERSION_1);
+            assert!(dev.num_queues > 0);
+            let listed = (dev.num_queues as usize).min(VirtioDeviceStatsV1::MAX_QUEUES);
+            assert!(dev.queue_sizes[0..listed].iter().all(|sz| *sz > 0));
+        }
+    }
+
+    println!("test_virtio_devices() PASS");
+}
+
 fn test_thread_names() {
     let handle = std::thread::current();
     assert_eq!(handle.name(), Some("main"));
@@ -560,10 +4663,45 @@ fn main() {
 
     test_thread_names();
     test_cpus();
+    test_cpu_idle();
+    test_cpu_idle_accounting();
+    test_thread_cpu_usage();
+    test_thread_cpu_time();
+    test_percpu_migrations();
+    test_percpu_usage();
+    test_cpu_sampling();
+    test_lock_stats();
+    test_thread_interrupt();
+    test_thread_interrupt_std();
+    test_wait_ex();
+    test_cpu_usage_detailed();
     tls::test_tls();
     test_caps();
+    test_hostname();
+    test_cpu_limit();
+    test_stdio_redirect();
+    test_cmdline();
+    test_syscall_filter();
+    test_process_affinity();
+    test_random();
+    test_virtio_devices();
+    test_mem_fragmentation();
+    test_frame_caches();
+    test_memory_pressure();
+    test_numa_nodes();
     spawn_wait_kill::test_pid_kill();
+    spawn_wait_kill::test_pid_kill_completion();
+    spawn_wait_kill::test_process_snapshot();
     test_oom();
+    test_oom_priority();
+    test_process_stats_v2();
+    test_committed_pages();
+    test_list_descendants();
+    test_log_rate_limit();
+    test_klog();
+    test_panic_action();
+    test_deadlock_detection();
+    test_watchdog();
     std::thread::sleep(Duration::new(1, 10_000_000));
     test_rt_mutex();
     test_futex();
@@ -578,13 +4716,50 @@ fn main() {
     // tcp::test_wget();
     // test_stdio();
     test_file_write();
+    test_fs_vectored_io();
+    test_fs_block_io();
+    test_fs_queue_depth();
+    test_fs_dir_quota();
+    test_fs_async_io();
+    test_fs_async_metadata();
+    test_fs_async_cancel();
+    test_fs_scatter_gather();
+    test_fs_snapshot();
+    test_fs_case_insensitive();
+    test_fs_writeback();
+    test_fs_barrier();
+    test_fs_sync_files();
+    test_fs_file_versions();
+    test_fs_defragment();
 
     test_lazy_memory_map();
+    test_reserved_memory_map();
+    test_mem_advise();
+    test_stats_reset();
+    test_sched_latency();
+    test_timer_accuracy();
+    test_time_slice();
     test_syscall();
     stress_test_threads();
     test_thread();
     test_ipc();
+    test_submit_sqe_blocking();
+    test_recv_batch();
+    test_io_channel_send_batch();
+    test_channel_capacity();
+    test_shared_page_count();
+    test_attach_page();
+    test_io_channel_checksums();
+    test_io_channel_inline_data();
+    test_io_channel_alignment();
+    test_io_channel_latency();
+    test_io_channel_peer_credentials();
+    test_io_channel_shutdown();
+    test_channel_pool_growth();
     test_pipes();
+    test_anon_pipe();
+    test_rusage();
+    test_sys_io_log_level();
 
     println!("PASS");
 
@@ -68,3 +68,106 @@ pub fn test_pid_kill() {
 
     println!("test_pid_kill test PASS");
 }
+
+pub fn test_pid_kill_completion() {
+    use moto_sys::{SysCpu, SysHandle, SysObj};
+    use std::time::Duration;
+
+    let mut child = subcommand::spawn();
+    let pid = child.pid();
+    let completion = SysCpu::kill_pid_with_completion(pid).unwrap();
+    assert_eq!(-1, child.wait().unwrap().code().unwrap());
+
+    // Our handle to the child keeps it from being reclaimed.
+    let timeout = moto_rt::time::Instant::now() + Duration::from_millis(50);
+    assert_eq!(
+        SysCpu::wait(
+            &mut [completion],
+            SysHandle::NONE,
+            SysHandle::NONE,
+            Some(timeout)
+        )
+        .unwrap_err(),
+        moto_rt::E_TIMED_OUT
+    );
+
+    core::mem::drop(child);
+    let timeout = moto_rt::time::Instant::now() + Duration::from_secs(5);
+    SysCpu::wait(
+        &mut [completion],
+        SysHandle::NONE,
+        SysHandle::NONE,
+        Some(timeout),
+    )
+    .unwrap();
+    SysObj::put(completion).unwrap();
+
+    // The stats entry is gone.
+    let mut stats = [ProcessStatsV1::default()];
+    match ProcessStatsV1::list(pid, &mut stats) {
+        Ok(0) => {}
+        Ok(1) => assert_ne!(stats[0].pid, pid),
+        res => panic!("unexpected {res:?}"),
+    }
+
+    // A PID that is gone, and sys-io (usually PID 2).
+    assert_eq!(
+        SysCpu::kill_pid_with_completion(pid).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    assert!(SysCpu::kill_pid_with_completion(2).is_err());
+
+    println!("test_pid_kill_completion() PASS");
+}
+
+pub fn test_process_snapshot() {
+    let me = moto_sys::current_pid();
+    let mut children = [subcommand::spawn(), subcommand::spawn()];
+
+    assert_eq!(
+        ProcessStatsV1::snapshot(me, &mut []).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    let mut buf = vec![ProcessStatsV1::default(); 64];
+    assert_eq!(
+        ProcessStatsV1::snapshot(u64::MAX, &mut buf).unwrap_err(),
+        moto_rt::E_NOT_FOUND
+    );
+
+    // This process first, then its descendants, each after its parent.
+    let (copied, total) = ProcessStatsV1::snapshot(me, &mut buf).unwrap();
+    assert_eq!(copied, total);
+    let snapshot = &buf[0..copied];
+    assert_eq!(snapshot[0].pid, me);
+    assert!(snapshot.windows(2).all(|w| w[0].pid < w[1].pid));
+    for (idx, entry) in snapshot.iter().enumerate().skip(1) {
+        assert!(snapshot[0..idx]
+            .iter()
+            .any(|ancestor| ancestor.pid == entry.parent_pid));
+    }
+    for child in &children {
+        assert!(snapshot
+            .iter()
+            .any(|entry| entry.pid == child.pid() && entry.parent_pid == me));
+    }
+
+    // Truncated.
+    let (copied, truncated_total) = ProcessStatsV1::snapshot(me, &mut buf[0..1]).unwrap();
+    assert_eq!(copied, 1);
+    assert_eq!(buf[0].pid, me);
+    assert!(truncated_total >= 3);
+
+    // Everything, including sys-io and us.
+    let (copied, total) = ProcessStatsV1::snapshot(moto_sys::stats::PID_SYSTEM, &mut buf).unwrap();
+    assert!(total >= copied);
+    assert_eq!(buf[0].pid, moto_sys::stats::PID_SYSTEM);
+    if copied == total {
+        assert!(buf[0..copied].iter().any(|entry| entry.pid == me));
+    }
+
+    for child in &mut children {
+        child.do_exit(0);
+        assert_eq!(0, child.wait().unwrap().code().unwrap());
+    }
+    println!("test_process_snapshot() PASS");
+}
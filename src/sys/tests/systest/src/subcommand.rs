@@ -10,10 +10,15 @@ pub struct Subcommand {
 }
 
 pub fn spawn() -> Subcommand {
+    spawn_with_env(&[])
+}
+
+pub fn spawn_with_env(env: &[(&str, &str)]) -> Subcommand {
     let mut inst = std::process::Command::new(std::env::args().next().unwrap())
         .arg("subcommand")
         .env("some_key", "some_val")
         .env("none_key", "")
+        .envs(env.iter().copied())
         .stdin(std::process::Stdio::piped())
         .stdout(std::process::Stdio::piped())
         .stderr(std::process::Stdio::piped())
@@ -59,6 +64,82 @@ impl Subcommand {
         self.inst.kill().unwrap()
     }
 
+    pub fn pid(&self) -> u64 {
+        self.inst.id() as u64
+    }
+
+    // The subcommand exits with zero if its affinity is (cpu, inherit).
+    pub fn check_affinity(&mut self, cpu: Option<u32>, inherit: bool) {
+        use std::io::Write;
+        let cpu = match cpu {
+            Some(cpu) => cpu.to_string(),
+            None => "none".to_owned(),
+        };
+        self.stdin
+            .write(format!("check_affinity {} {}\n", cpu, inherit).as_bytes())
+            .unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    // The subcommand exits with zero if the kernel has its args and env
+    // (see spawn_with_env()) as its cmdline.
+    pub fn check_cmdline(&mut self) {
+        use std::io::Write;
+        self.stdin.write(b"check_cmdline\n").unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    // The subcommand checks that it can't ask for the system to be rebooted
+    // when it panics, then panics.
+    pub fn panic(&mut self) {
+        use std::io::Write;
+        self.stdin.write(b"panic\n").unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    // The subcommand checks that a syscall filter blocks what it denies, can
+    // only be tightened, and is inherited by children; exits with zero if so.
+    pub fn syscall_filter(&mut self) {
+        use std::io::Write;
+        self.stdin.write(b"syscall_filter\n").unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    // The subcommand exits with zero if its main thread has a pending interrupt.
+    pub fn check_interrupted(&mut self) {
+        use std::io::Write;
+        self.stdin.write(b"check_interrupted\n").unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    // The subcommand exits with zero if it can't get the hostname.
+    pub fn check_hostname_denied(&mut self) {
+        use std::io::Write;
+        self.stdin.write(b"check_hostname_denied\n").unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    // Connects to (or serves) @url, and waits only for the peer, which does
+    // the same; exits with DEADLOCK_DETECTED if the wait fails with E_DEADLOCK,
+    // and with zero if woken by the peer after it detected the deadlock.
+    pub fn deadlock(&mut self, url: &str, server: bool) {
+        use std::io::Write;
+        self.stdin
+            .write(format!("deadlock {} {}\n", url, server).as_bytes())
+            .unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    // The subcommand raises its own OOM priority to OOM_PRIORITY_MAX and checks
+    // that it can't lower it back (panics if it can).
+    pub fn raise_oom_priority(&mut self) {
+        use std::io::Write;
+        self.stdin
+            .write(format!("raise_oom_priority\n").as_bytes())
+            .unwrap();
+        self.stdin.flush().unwrap();
+    }
+
     pub fn start_xor_service(&mut self) {
         use std::io::Write;
         self.stdin
@@ -112,11 +193,221 @@ fn do_command(cmd: String) {
             let code = words[1].parse::<i32>().unwrap();
             std::process::exit(code)
         }
+        "check_affinity" => {
+            assert_eq!(3, words.len());
+            let cpu = match words[1] {
+                "none" => None,
+                cpu => Some(cpu.parse::<u32>().unwrap()),
+            };
+            let inherit = words[2].parse::<bool>().unwrap();
+            let affinity = moto_sys::SysCpu::process_affinity(moto_sys::SysHandle::SELF).unwrap();
+            std::process::exit(if affinity == (cpu, inherit) { 0 } else { 1 })
+        }
+        "check_cmdline" => {
+            assert_eq!(1, words.len());
+            std::process::exit(if cmdline_ok() { 0 } else { 1 })
+        }
+        "panic" => {
+            use moto_sys::SysRay;
+            assert_eq!(1, words.len());
+            assert_eq!(
+                SysRay::set_panic_action(SysRay::PANIC_ACTION_REBOOT).unwrap_err(),
+                moto_rt::E_NOT_ALLOWED
+            );
+            SysRay::set_panic_action(SysRay::PANIC_ACTION_EXIT).unwrap();
+            panic!("subcommand panic")
+        }
+        "syscall_filter" => {
+            assert_eq!(1, words.len());
+            syscall_filter()
+        }
+        "check_hostname_denied" => {
+            assert_eq!(1, words.len());
+            let denied = moto_sys::SysRay::get_hostname(&mut []) == Err(moto_rt::E_NOT_ALLOWED);
+            std::process::exit(if denied { 0 } else { 1 })
+        }
+        "check_interrupted" => {
+            assert_eq!(1, words.len());
+            let interrupted = moto_sys::SysCpu::take_interrupt().unwrap();
+            std::process::exit(if interrupted { 0 } else { 1 })
+        }
+        "echo" => println!("{}", words[1..].join(" ")),
+        "io" => {
+            assert_eq!(4, words.len());
+            let len = words[3].parse::<usize>().unwrap();
+            do_io(words[1], words[2], len)
+        }
         "xor_service" => crate::xor_server::start(),
+        "raise_oom_priority" => {
+            assert_eq!(1, words.len());
+            raise_oom_priority()
+        }
+        "deadlock" => {
+            assert_eq!(3, words.len());
+            let server = words[2].parse::<bool>().unwrap();
+            deadlock(words[1], server)
+        }
         _ => panic!("unknown command: {:?}", words),
     }
 }
 
+/// Splits a cmdline (see moto_sys::SysRay::set_cmdline()) into (args, env).
+pub fn decode_cmdline(cmdline: &[u8]) -> (Vec<String>, Vec<String>) {
+    let mut items = cmdline
+        .strip_suffix(&[0])
+        .unwrap_or(cmdline)
+        .split(|b| *b == 0)
+        .map(|item| String::from_utf8_lossy(item).into_owned());
+    let args = items.by_ref().take_while(|item| !item.is_empty()).collect();
+    (args, items.collect())
+}
+
+fn cmdline_ok() -> bool {
+    let pid = moto_sys::current_pid();
+    let len = moto_sys::SysRay::get_cmdline(pid, &mut []).unwrap();
+    let mut cmdline = vec![0_u8; len];
+    assert_eq!(
+        len,
+        moto_sys::SysRay::get_cmdline(pid, &mut cmdline).unwrap()
+    );
+
+    let (args, env) = decode_cmdline(&cmdline);
+    args == std::env::args().collect::<Vec<_>>()
+        && env.iter().any(|kv| kv == "some_key=some_val")
+        && env.iter().any(|kv| kv == "none_key=")
+        // Capabilities passed in the env are not exposed.
+        && !env.iter().any(|kv| kv.starts_with(moto_sys::caps::MOTURUS_CAPS_ENV_KEY))
+}
+
+// Writes and reads back @len bytes, to file @path and over TCP via @addr.
+fn do_io(path: &str, addr: &str, len: usize) {
+    use std::io::{Read, Write};
+
+    let data = vec![0xA5_u8; len];
+    std::fs::write(path, &data).unwrap();
+    assert_eq!(std::fs::read(path).unwrap(), data);
+    std::fs::remove_file(path).unwrap();
+
+    let listener = std::net::TcpListener::bind(addr).unwrap();
+    let mut client = std::net::TcpStream::connect(addr).unwrap();
+    let (mut server, _) = listener.accept().unwrap();
+    client.write_all(&data).unwrap();
+    let mut received = vec![0_u8; len];
+    server.read_exact(&mut received).unwrap();
+    assert_eq!(received, data);
+}
+
+fn raise_oom_priority() {
+    use moto_sys::SysRay;
+
+    let pid = moto_sys::current_pid();
+    let priority = SysRay::oom_priority(pid).unwrap();
+
+    // Raising is allowed, lowering isn't without CAP_SYS.
+    SysRay::set_oom_priority(pid, SysRay::OOM_PRIORITY_MAX).unwrap();
+    assert_eq!(SysRay::oom_priority(pid).unwrap(), SysRay::OOM_PRIORITY_MAX);
+    assert_eq!(
+        SysRay::set_oom_priority(pid, priority).unwrap_err(),
+        moto_rt::E_NOT_ALLOWED
+    );
+    assert_eq!(
+        SysRay::set_oom_priority(pid, SysRay::OOM_PRIORITY_MAX + 1).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+}
+
+fn syscall_filter() -> ! {
+    use moto_sys::syscalls::{SyscallFilter, SYS_RAY};
+    use moto_sys::{SysCpu, SysObj, SysRay};
+
+    assert_eq!(
+        SysObj::syscall_filter().unwrap(),
+        SyscallFilter::allow_all()
+    );
+    SysRay::get_hostname(&mut []).unwrap();
+
+    let mut filter = SyscallFilter::allow_all();
+    filter.deny(SYS_RAY, SysRay::OP_HOSTNAME);
+    SysObj::set_syscall_filter(&filter).unwrap();
+    assert_eq!(SysObj::syscall_filter().unwrap(), filter);
+    assert_eq!(
+        SysRay::get_hostname(&mut []).unwrap_err(),
+        moto_rt::E_NOT_ALLOWED
+    );
+    // Other operations of the same syscall are still allowed.
+    SysRay::oom_priority(moto_sys::current_pid()).unwrap();
+
+    // Setting a looser filter does not loosen it.
+    SysObj::set_syscall_filter(&SyscallFilter::allow_all()).unwrap();
+    assert_eq!(SysObj::syscall_filter().unwrap(), filter);
+    assert_eq!(
+        SysRay::get_hostname(&mut []).unwrap_err(),
+        moto_rt::E_NOT_ALLOWED
+    );
+
+    let mut child = spawn();
+    child.check_hostname_denied();
+    assert!(child.wait().unwrap().success());
+
+    // Exiting and reading the filter are never blocked.
+    SysObj::set_syscall_filter(&SyscallFilter::deny_all()).unwrap();
+    if SysObj::syscall_filter() != Ok(SyscallFilter::deny_all())
+        || SysRay::oom_priority(moto_sys::current_pid()) != Err(moto_rt::E_NOT_ALLOWED)
+    {
+        SysCpu::exit(1)
+    }
+    SysCpu::exit(0)
+}
+
+pub const DEADLOCK_DETECTED: i32 = 3;
+
+fn deadlock(url: &str, server: bool) -> ! {
+    use moto_ipc::io_channel::{ClientConnection, ServerConnection};
+    use moto_sys::{SysCpu, SysHandle, SysRay};
+
+    SysRay::set_process_deadlock_detection(true).unwrap();
+
+    // Keep the connection until we exit.
+    let (_server, _client, handle) = if server {
+        let mut server = ServerConnection::create(url).unwrap();
+        // Not a deadlock: the peer is not connected yet.
+        SysCpu::wait(
+            &mut [server.wait_handle()],
+            SysHandle::NONE,
+            SysHandle::NONE,
+            None,
+        )
+        .unwrap();
+        unsafe { server.accept().unwrap() };
+        let handle = server.wait_handle();
+
+        // Not a deadlock either, even if the peer is blocked: a pending
+        // wake ends the wait right away.
+        SysCpu::wake(moto_sys::UserThreadControlBlock::get().self_handle.into()).unwrap();
+        SysCpu::wait(&mut [handle], SysHandle::NONE, SysHandle::NONE, None).unwrap();
+
+        (Some(server), None, handle)
+    } else {
+        let client = loop {
+            match ClientConnection::connect(url) {
+                Ok(client) => break client,
+                Err(_) => std::thread::sleep(Duration::from_millis(10)),
+            }
+        };
+        let handle = client.server_handle();
+        (None, Some(client), handle)
+    };
+
+    match SysCpu::wait(&mut [handle], SysHandle::NONE, SysHandle::NONE, None) {
+        Err(moto_rt::E_DEADLOCK) => {
+            SysCpu::wake(handle).unwrap();
+            std::process::exit(DEADLOCK_DETECTED)
+        }
+        Ok(()) => std::process::exit(0),
+        Err(err) => panic!("unexpected wait result: {err}"),
+    }
+}
+
 fn trigger_oom() -> ! {
     use moto_sys::SysMem;
 
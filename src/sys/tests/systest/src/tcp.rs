@@ -181,6 +181,966 @@ fn test_read_timeout() {
     // server.join();
 }
 
+fn test_reuse_port() {
+    let addr: std::net::SocketAddr = "127.0.0.1:3335".parse().unwrap();
+    let proto = moto_rt::net::PROTO_TCP | moto_rt::net::BIND_REUSEPORT;
+    let listeners = [
+        moto_rt::net::bind(proto, &addr.into()).unwrap(),
+        moto_rt::net::bind(proto, &addr.into()).unwrap(),
+    ];
+    // Listeners that did not opt in can't share the address.
+    assert!(std::net::TcpListener::bind(addr).is_err());
+
+    const CONNECTIONS: u64 = 8;
+    let clients: Vec<_> = (0..CONNECTIONS)
+        .map(|_| std::net::TcpStream::connect(addr).unwrap())
+        .collect();
+
+    let pending = |idx: usize| moto_rt::net::rx_available(listeners[idx]).unwrap();
+    let start = std::time::Instant::now();
+    while pending(0) + pending(1) < CONNECTIONS {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    // Round-robin.
+    for listener in listeners {
+        assert_eq!(
+            moto_rt::net::rx_available(listener).unwrap(),
+            CONNECTIONS / 2
+        );
+        for _ in 0..(CONNECTIONS / 2) {
+            let (stream, _) = moto_rt::net::accept(listener).unwrap();
+            moto_rt::fs::close(stream).unwrap();
+        }
+        assert_eq!(
+            moto_rt::net::listener_accepted(listener).unwrap(),
+            CONNECTIONS / 2
+        );
+        moto_rt::fs::close(listener).unwrap();
+    }
+    core::mem::drop(clients);
+
+    println!("test_reuse_port() PASS");
+}
+
+fn test_reuse_addr() {
+    let addr: std::net::SocketAddr = "127.0.0.1:3339".parse().unwrap();
+    let listener = std::net::TcpListener::bind(addr).unwrap();
+    let client = std::net::TcpStream::connect(addr).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    core::mem::drop(listener);
+
+    // The accepted connection outlives its listener, but doesn't keep
+    // a new listener from binding (the old one may take a moment to go away).
+    let start = std::time::Instant::now();
+    let listener = loop {
+        match std::net::TcpListener::bind(addr) {
+            Ok(listener) => break listener,
+            Err(_) => {
+                assert!(start.elapsed() < Duration::from_secs(5));
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+    };
+    // Still one active listener per address.
+    assert!(std::net::TcpListener::bind(addr).is_err());
+
+    // The new listener accepts new connections.
+    let client2 = std::net::TcpStream::connect(addr).unwrap();
+    let (server2, _) = listener.accept().unwrap();
+
+    // With BIND_EXCLUSIVEADDR, the lingering connection blocks the address. The
+    // wildcard address doesn't clash with the listener above, only with the connection.
+    let any: std::net::SocketAddr = "0.0.0.0:3339".parse().unwrap();
+    let proto = moto_rt::net::PROTO_TCP | moto_rt::net::BIND_EXCLUSIVEADDR;
+    assert_eq!(
+        moto_rt::net::bind(proto, &any.into()).err(),
+        Some(moto_rt::E_ALREADY_IN_USE)
+    );
+    let any_listener = moto_rt::net::bind(moto_rt::net::PROTO_TCP, &any.into()).unwrap();
+    moto_rt::fs::close(any_listener).unwrap();
+
+    core::mem::drop(server2);
+    core::mem::drop(client2);
+    core::mem::drop(listener);
+    core::mem::drop(server);
+    core::mem::drop(client);
+    println!("test_reuse_addr() PASS");
+}
+
+fn test_udp() {
+    let loopback: std::net::SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let socket_a = moto_rt::net::bind(moto_rt::net::PROTO_UDP, &loopback.into()).unwrap();
+    let socket_b = moto_rt::net::bind(moto_rt::net::PROTO_UDP, &loopback.into()).unwrap();
+    let to_addr = |addr: moto_rt::netc::sockaddr| -> std::net::SocketAddr { addr.into() };
+    let addr_a = to_addr(moto_rt::net::socket_addr(socket_a).unwrap());
+    let addr_b = to_addr(moto_rt::net::socket_addr(socket_b).unwrap());
+    assert_ne!(addr_a.port(), 0);
+    assert_ne!(addr_a.port(), addr_b.port());
+
+    // The port is taken.
+    assert_eq!(
+        moto_rt::net::bind(moto_rt::net::PROTO_UDP, &addr_a.into()).err(),
+        Some(moto_rt::E_ALREADY_IN_USE)
+    );
+
+    assert_eq!(
+        moto_rt::net::udp_send_to(socket_a, b"ping", &addr_b.into()).unwrap(),
+        4
+    );
+    let mut buf = [0_u8; 16];
+    // Peeking leaves the datagram queued; a short buffer fails without truncating.
+    let (sz, from) = moto_rt::net::udp_peek_from(socket_b, &mut buf).unwrap();
+    assert_eq!(&buf[0..sz], b"ping");
+    assert_eq!(to_addr(from), addr_a);
+    assert_eq!(
+        moto_rt::net::udp_recv_from(socket_b, &mut buf[0..2]).err(),
+        Some(moto_rt::E_MSG_TOO_LARGE)
+    );
+    let (sz, from) = moto_rt::net::udp_recv_from(socket_b, &mut buf).unwrap();
+    assert_eq!(&buf[0..sz], b"ping");
+    assert_eq!(to_addr(from), addr_a);
+
+    moto_rt::net::udp_send_to(socket_b, b"pong", &from).unwrap();
+    let (sz, from) = moto_rt::net::udp_recv_from(socket_a, &mut buf).unwrap();
+    assert_eq!(&buf[0..sz], b"pong");
+    assert_eq!(to_addr(from), addr_b);
+
+    // Datagrams larger than a page are rejected.
+    let large = vec![0_u8; 4097];
+    assert_eq!(
+        moto_rt::net::udp_send_to(socket_a, &large, &addr_b.into()).err(),
+        Some(moto_rt::E_MSG_TOO_LARGE)
+    );
+
+    moto_rt::net::set_read_timeout(socket_a, Some(Duration::from_millis(10))).unwrap();
+    assert_eq!(
+        moto_rt::net::udp_recv_from(socket_a, &mut buf).err(),
+        Some(moto_rt::E_TIMED_OUT)
+    );
+
+    moto_rt::fs::close(socket_a).unwrap();
+    moto_rt::fs::close(socket_b).unwrap();
+    println!("test_udp() PASS");
+}
+
+fn test_udp_dont_frag() {
+    let any: std::net::SocketAddr = "0.0.0.0:0".parse().unwrap();
+    let socket = moto_rt::net::bind(moto_rt::net::PROTO_UDP, &any.into()).unwrap();
+    assert!(!moto_rt::net::dont_fragment(socket).unwrap());
+
+    // Over loopback (64K MTU), everything fits.
+    let loopback: std::net::SocketAddr = "127.0.0.1:3343".parse().unwrap();
+    let large = vec![0_u8; 3000];
+    moto_rt::net::set_dont_fragment(socket, true).unwrap();
+    assert!(moto_rt::net::dont_fragment(socket).unwrap());
+    moto_rt::net::udp_send_to(socket, &large, &loopback.into()).unwrap();
+
+    // The default gateway of the full image's net0 (1500 MTU).
+    let gateway: std::net::SocketAddr = "192.168.4.1:3343".parse().unwrap();
+    match moto_rt::net::udp_send_to(socket, &large[0..1000], &gateway.into()) {
+        Ok(_) => {}
+        Err(err) => {
+            assert_eq!(err, moto_rt::E_NOT_FOUND); // No route.
+            moto_rt::fs::close(socket).unwrap();
+            println!("test_udp_dont_frag() SKIPPED: no net0");
+            return;
+        }
+    }
+    assert_eq!(
+        moto_rt::net::udp_send_to(socket, &large, &gateway.into()).err(),
+        Some(moto_rt::E_MSG_TOO_LARGE)
+    );
+    moto_rt::net::set_dont_fragment(socket, false).unwrap();
+    assert_eq!(
+        moto_rt::net::udp_send_to(socket, &large, &gateway.into()).unwrap(),
+        large.len()
+    );
+
+    moto_rt::fs::close(socket).unwrap();
+    println!("test_udp_dont_frag() PASS");
+}
+
+fn test_connect_from() {
+    let to_addr = |addr: moto_rt::netc::sockaddr| -> std::net::SocketAddr { addr.into() };
+    let listener = std::net::TcpListener::bind("127.0.0.1:3337").unwrap();
+    let remote: std::net::SocketAddr = "127.0.0.1:3337".parse().unwrap();
+    let local: std::net::SocketAddr = "127.0.0.1:3338".parse().unwrap();
+    let timeout = Duration::from_secs(5);
+
+    let stream = moto_rt::net::tcp_connect_from(&local.into(), &remote.into(), timeout).unwrap();
+    assert_eq!(to_addr(moto_rt::net::socket_addr(stream).unwrap()), local);
+    let (server, peer) = listener.accept().unwrap();
+    assert_eq!(peer, local);
+
+    // The port is taken, by the connection...
+    assert_eq!(
+        moto_rt::net::tcp_connect_from(&local.into(), &remote.into(), timeout).err(),
+        Some(moto_rt::E_ALREADY_IN_USE)
+    );
+    // ... or by a listener.
+    assert_eq!(
+        moto_rt::net::tcp_connect_from(&remote.into(), &remote.into(), timeout).err(),
+        Some(moto_rt::E_ALREADY_IN_USE)
+    );
+
+    // Not an address of ours.
+    let foreign: std::net::SocketAddr = "10.255.255.1:0".parse().unwrap();
+    assert_eq!(
+        moto_rt::net::tcp_connect_from(&foreign.into(), &remote.into(), timeout).err(),
+        Some(moto_rt::E_ADDR_NOT_AVAILABLE)
+    );
+
+    // An ephemeral port on the given address.
+    let any_port: std::net::SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let other = moto_rt::net::tcp_connect_from(&any_port.into(), &remote.into(), timeout).unwrap();
+    let other_addr = to_addr(moto_rt::net::socket_addr(other).unwrap());
+    assert_eq!(other_addr.ip(), any_port.ip());
+    assert_ne!(other_addr.port(), 0);
+    let (other_server, _) = listener.accept().unwrap();
+
+    moto_rt::fs::close(other).unwrap();
+    moto_rt::fs::close(stream).unwrap();
+    core::mem::drop(other_server);
+    core::mem::drop(server);
+    println!("test_connect_from() PASS");
+}
+
+fn test_poll() {
+    use moto_rt::net::{POLL_READABLE, POLL_WRITABLE};
+
+    let addr: std::net::SocketAddr = "127.0.0.1:3340".parse().unwrap();
+    let listener = moto_rt::net::bind(moto_rt::net::PROTO_TCP, &addr.into()).unwrap();
+    let poll = moto_rt::net::poll_new().unwrap();
+    moto_rt::net::poll_add(poll, listener, 1, POLL_READABLE).unwrap();
+    let mut events = [moto_rt::net::PollEvent::default(); 4];
+    let short = Some(Duration::from_millis(10));
+    assert_eq!(
+        moto_rt::net::poll_wait(poll, &mut events, short).unwrap(),
+        0
+    );
+
+    // All waiters are woken.
+    let waiters: Vec<_> = (0..2)
+        .map(|_| {
+            std::thread::spawn(move || {
+                let mut events = [moto_rt::net::PollEvent::default(); 4];
+                let timeout = Some(Duration::from_secs(5));
+                assert_eq!(
+                    moto_rt::net::poll_wait(poll, &mut events, timeout).unwrap(),
+                    1
+                );
+                assert_eq!(events[0].token, 1);
+                assert_eq!(events[0].events, POLL_READABLE);
+            })
+        })
+        .collect();
+    std::thread::sleep(Duration::from_millis(10));
+
+    // The listener becomes readable once there's a connection to accept.
+    let timeout = Duration::from_secs(5);
+    let client = moto_rt::net::tcp_connect(&addr.into(), timeout).unwrap();
+    for waiter in waiters {
+        waiter.join().unwrap();
+    }
+    let (server, _) = moto_rt::net::accept(listener).unwrap();
+    assert_eq!(
+        moto_rt::net::poll_wait(poll, &mut events, short).unwrap(),
+        0
+    );
+    moto_rt::net::poll_delete(poll, listener).unwrap();
+
+    moto_rt::net::poll_add(poll, client, 2, POLL_READABLE | POLL_WRITABLE).unwrap();
+    assert_eq!(
+        moto_rt::net::poll_wait(poll, &mut events, short).unwrap(),
+        1
+    );
+    assert_eq!(events[0].events, POLL_WRITABLE);
+    moto_rt::fs::write(server, b"ping").unwrap();
+    let start = std::time::Instant::now();
+    while events[0].events != POLL_READABLE | POLL_WRITABLE {
+        assert!(start.elapsed() < timeout);
+        moto_rt::net::poll_wait(poll, &mut events, Some(timeout)).unwrap();
+    }
+    let mut buf = [0_u8; 4];
+    assert_eq!(moto_rt::fs::read(client, &mut buf).unwrap(), 4);
+
+    // Fill the stream while the peer isn't reading: it stops being writable
+    // once there are no IO pages left, ...
+    moto_rt::net::poll_modify(poll, client, 2, POLL_WRITABLE).unwrap();
+    const TX_BYTES: usize = 16 << 20;
+    let writer = std::thread::spawn(move || {
+        let buf = vec![0_u8; 1 << 16];
+        let mut written = 0;
+        while written < TX_BYTES {
+            written += moto_rt::fs::write(client, &buf).unwrap();
+        }
+    });
+    let start = std::time::Instant::now();
+    while moto_rt::net::poll_wait(poll, &mut events, short).unwrap() > 0 {
+        assert!(start.elapsed() < timeout);
+    }
+
+    // ... and becomes writable again as the peer reads.
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(10));
+        let mut buf = vec![0_u8; 1 << 16];
+        let mut read = 0;
+        while read < TX_BYTES {
+            read += moto_rt::fs::read(server, &mut buf).unwrap();
+        }
+        moto_rt::fs::close(server).unwrap();
+    });
+    assert_eq!(
+        moto_rt::net::poll_wait(poll, &mut events, Some(timeout)).unwrap(),
+        1
+    );
+    assert_eq!(events[0].events, POLL_WRITABLE);
+    writer.join().unwrap();
+
+    moto_rt::fs::close(poll).unwrap();
+    moto_rt::fs::close(client).unwrap();
+    moto_rt::fs::close(listener).unwrap();
+    println!("test_poll() PASS");
+}
+
+fn test_urgent() {
+    let addr: std::net::SocketAddr = "127.0.0.1:3341".parse().unwrap();
+    let listener = moto_rt::net::bind(moto_rt::net::PROTO_TCP, &addr.into()).unwrap();
+    let client = moto_rt::net::tcp_connect(&addr.into(), Duration::from_secs(5)).unwrap();
+    let (server, _) = moto_rt::net::accept(listener).unwrap();
+    moto_rt::fs::close(listener).unwrap();
+
+    let wait_for_bytes = |fd, bytes| {
+        let start = std::time::Instant::now();
+        while moto_rt::net::rx_available(fd).unwrap() < bytes {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    };
+    let read_exact = |fd, buf: &mut [u8]| {
+        let mut done = 0;
+        while done < buf.len() {
+            done += moto_rt::fs::read(fd, &mut buf[done..]).unwrap();
+        }
+    };
+
+    assert_eq!(moto_rt::net::urgent_mark(server).unwrap(), None);
+    moto_rt::fs::write(client, b"ab").unwrap();
+    moto_rt::net::send_urgent(client, b'!').unwrap();
+    moto_rt::fs::write(client, b"cd").unwrap();
+    wait_for_bytes(server, 5);
+
+    // The urgent byte is inline; the mark counts down as bytes are read.
+    assert_eq!(moto_rt::net::urgent_mark(server).unwrap(), Some(2));
+    let mut buf = [0_u8; 5];
+    read_exact(server, &mut buf[0..2]);
+    assert_eq!(moto_rt::net::urgent_mark(server).unwrap(), Some(0));
+    read_exact(server, &mut buf[2..]);
+    assert_eq!(&buf, b"ab!cd");
+    assert_eq!(moto_rt::net::urgent_mark(server).unwrap(), None);
+
+    // The other way.
+    moto_rt::net::send_urgent(server, b'x').unwrap();
+    wait_for_bytes(client, 1);
+    assert_eq!(moto_rt::net::urgent_mark(client).unwrap(), Some(0));
+    read_exact(client, &mut buf[0..1]);
+    assert_eq!(buf[0], b'x');
+
+    // Errors are reported, rather than the byte being sent as a regular one.
+    moto_rt::net::shutdown(client, moto_rt::net::SHUTDOWN_WRITE).unwrap();
+    assert!(moto_rt::net::send_urgent(client, b'!').is_err());
+
+    moto_rt::fs::close(client).unwrap();
+    moto_rt::fs::close(server).unwrap();
+    println!("test_urgent() PASS");
+}
+
+fn test_rx_available() {
+    let addr: std::net::SocketAddr = "127.0.0.1:3351".parse().unwrap();
+    let listener = moto_rt::net::bind(moto_rt::net::PROTO_TCP, &addr.into()).unwrap();
+    assert_eq!(moto_rt::net::rx_available(listener).unwrap(), 0);
+
+    let wait_for = |fd, count| {
+        let start = std::time::Instant::now();
+        while moto_rt::net::rx_available(fd).unwrap() < count {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(moto_rt::net::rx_available(fd).unwrap(), count);
+    };
+    let connect = || moto_rt::net::tcp_connect(&addr.into(), Duration::from_secs(5)).unwrap();
+
+    // A stream counts the bytes it can read, whether or not they have reached
+    // the process yet; bytes read are no longer counted.
+    let client = connect();
+    wait_for(listener, 1);
+    let (server, _) = moto_rt::net::accept(listener).unwrap();
+    assert_eq!(moto_rt::net::rx_available(listener).unwrap(), 0);
+    assert_eq!(moto_rt::net::rx_available(server).unwrap(), 0);
+
+    moto_rt::fs::write(client, &[3_u8; 100]).unwrap();
+    wait_for(server, 100);
+    let mut buf = [0_u8; 100];
+    let mut read = 0;
+    while read < 40 {
+        read += moto_rt::fs::read(server, &mut buf[read..40]).unwrap();
+    }
+    assert_eq!(moto_rt::net::rx_available(server).unwrap(), 60);
+    while read < 100 {
+        read += moto_rt::fs::read(server, &mut buf[read..]).unwrap();
+    }
+    assert!(buf.iter().all(|b| *b == 3));
+    assert_eq!(moto_rt::net::rx_available(server).unwrap(), 0);
+    assert_eq!(moto_rt::net::rx_available(client).unwrap(), 0);
+
+    // A listener counts the connections it can accept.
+    let clients = [connect(), connect()];
+    wait_for(listener, 2);
+    for pending in [1, 0] {
+        let (stream, _) = moto_rt::net::accept(listener).unwrap();
+        assert_eq!(moto_rt::net::rx_available(listener).unwrap(), pending);
+        moto_rt::fs::close(stream).unwrap();
+    }
+
+    for fd in clients {
+        moto_rt::fs::close(fd).unwrap();
+    }
+    moto_rt::fs::close(client).unwrap();
+    moto_rt::fs::close(server).unwrap();
+    moto_rt::fs::close(listener).unwrap();
+    println!("test_rx_available() PASS");
+}
+
+fn test_rx_pause() {
+    let addr: std::net::SocketAddr = "127.0.0.1:3342".parse().unwrap();
+    let listener = moto_rt::net::bind(moto_rt::net::PROTO_TCP, &addr.into()).unwrap();
+    let client = moto_rt::net::tcp_connect(&addr.into(), Duration::from_secs(5)).unwrap();
+    let (server, _) = moto_rt::net::accept(listener).unwrap();
+    moto_rt::fs::close(listener).unwrap();
+
+    let idle_timeout = Duration::from_millis(50);
+    moto_rt::net::set_idle_timeout(server, Some(idle_timeout)).unwrap();
+    moto_rt::net::pause_recv(server).unwrap();
+    assert!(moto_rt::net::recv_paused(server).unwrap());
+
+    // The peer stops sending, so its writes stall...
+    const TX_BYTES: usize = 1 << 20;
+    let written = Arc::new(AtomicUsize::new(0));
+    let written_by_writer = written.clone();
+    let writer = std::thread::spawn(move || {
+        let buf = vec![7_u8; 1 << 14];
+        while written_by_writer.load(Ordering::Relaxed) < TX_BYTES {
+            let sz = moto_rt::fs::write(client, &buf).unwrap();
+            written_by_writer.fetch_add(sz, Ordering::Relaxed);
+        }
+    });
+    std::thread::sleep(idle_timeout * 4);
+    let stalled_at = written.load(Ordering::Relaxed);
+    assert!(stalled_at < TX_BYTES);
+    std::thread::sleep(idle_timeout * 2);
+    assert_eq!(written.load(Ordering::Relaxed), stalled_at);
+    assert_eq!(moto_rt::net::rx_available(server).unwrap(), 0);
+
+    // ... and the paused stream outlives its idle timeout.
+    moto_rt::net::resume_recv(server).unwrap();
+    assert!(!moto_rt::net::recv_paused(server).unwrap());
+    let mut buf = vec![0_u8; 1 << 14];
+    let mut read = 0;
+    while read < TX_BYTES {
+        let sz = moto_rt::fs::read(server, &mut buf).unwrap();
+        assert_ne!(sz, 0);
+        assert!(buf[0..sz].iter().all(|b| *b == 7));
+        read += sz;
+    }
+    writer.join().unwrap();
+    moto_rt::net::set_idle_timeout(server, None).unwrap();
+
+    moto_rt::fs::close(client).unwrap();
+    moto_rt::fs::close(server).unwrap();
+    println!("test_rx_pause() PASS");
+}
+
+fn test_idle_timeout() {
+    use moto_sys_io::stats::IoStatsService;
+
+    let addr: std::net::SocketAddr = "127.0.0.1:3349".parse().unwrap();
+    let listener = moto_rt::net::bind(moto_rt::net::PROTO_TCP, &addr.into()).unwrap();
+    let idle_timeout = Duration::from_millis(100);
+    assert_eq!(
+        moto_rt::net::set_idle_timeout(listener, Some(Duration::ZERO)).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+
+    let mut stats_service = IoStatsService::connect().unwrap();
+    let reaped = stats_service.get_net_stats().unwrap().tcp_idle_reaped;
+
+    // Set on the stream: activity keeps it alive.
+    let client = moto_rt::net::tcp_connect(&addr.into(), Duration::from_secs(5)).unwrap();
+    let (server, _) = moto_rt::net::accept(listener).unwrap();
+    moto_rt::net::set_idle_timeout(server, Some(idle_timeout)).unwrap();
+    let mut buf = [0_u8; 4];
+    for _ in 0..6 {
+        std::thread::sleep(idle_timeout / 2);
+        assert_eq!(moto_rt::fs::write(client, b"ping").unwrap(), 4);
+        assert_eq!(moto_rt::fs::read(server, &mut buf).unwrap(), 4);
+    }
+
+    // Then it is reaped, and the application sees E_TIMED_OUT.
+    let reaped_on_read = |server| {
+        moto_rt::net::set_read_timeout(server, Some(Duration::from_secs(5))).unwrap();
+        let start = std::time::Instant::now();
+        let mut buf = [0_u8; 4];
+        assert_eq!(
+            moto_rt::fs::read(server, &mut buf).unwrap_err(),
+            moto_rt::E_TIMED_OUT
+        );
+        // Well before the read timeout.
+        assert!(start.elapsed() < Duration::from_secs(2));
+        moto_rt::fs::close(server).unwrap();
+    };
+    reaped_on_read(server);
+    moto_rt::fs::close(client).unwrap();
+
+    // Set on the listener: applies to connections accepted afterwards.
+    moto_rt::net::set_idle_timeout(listener, Some(idle_timeout)).unwrap();
+    let client = moto_rt::net::tcp_connect(&addr.into(), Duration::from_secs(5)).unwrap();
+    let (server, _) = moto_rt::net::accept(listener).unwrap();
+    reaped_on_read(server);
+    moto_rt::fs::close(client).unwrap();
+    moto_rt::fs::close(listener).unwrap();
+
+    assert!(stats_service.get_net_stats().unwrap().tcp_idle_reaped >= reaped + 2);
+
+    println!("test_idle_timeout() PASS");
+}
+
+fn test_net_dev_stats() {
+    use moto_sys_io::stats::IoStatsService;
+
+    let mut stats_service = IoStatsService::connect().unwrap();
+    let loopback = |stats_service: &mut IoStatsService| {
+        let stats = stats_service.get_net_dev_stats().unwrap();
+        let total = stats.total();
+        let dev = *stats
+            .devices
+            .iter()
+            .find(|dev| dev.name() == "loopback")
+            .unwrap();
+        assert!(total.rx_bytes >= dev.rx_bytes && total.tx_bytes >= dev.tx_bytes);
+        (dev, stats.connect_no_route)
+    };
+    let (before, no_route_before) = loopback(&mut stats_service);
+
+    // Traffic over the loopback device is counted both ways.
+    const BYTES: usize = 64 * 1024;
+    let addr: std::net::SocketAddr = "127.0.0.1:3350".parse().unwrap();
+    let listener = moto_rt::net::bind(moto_rt::net::PROTO_TCP, &addr.into()).unwrap();
+    let client = moto_rt::net::tcp_connect(&addr.into(), Duration::from_secs(5)).unwrap();
+    let (server, _) = moto_rt::net::accept(listener).unwrap();
+    moto_rt::fs::close(listener).unwrap();
+    let writer = std::thread::spawn(move || {
+        let buf = vec![3_u8; BYTES];
+        let mut written = 0;
+        while written < BYTES {
+            written += moto_rt::fs::write(client, &buf[written..]).unwrap();
+        }
+        client
+    });
+    let mut buf = vec![0_u8; BYTES];
+    let mut read = 0;
+    while read < BYTES {
+        read += moto_rt::fs::read(server, &mut buf[read..]).unwrap();
+    }
+    let client = writer.join().unwrap();
+    moto_rt::fs::close(client).unwrap();
+    moto_rt::fs::close(server).unwrap();
+
+    let (after, _) = loopback(&mut stats_service);
+    assert!(after.tx_bytes >= before.tx_bytes + BYTES as u64);
+    assert!(after.rx_bytes >= before.rx_bytes + BYTES as u64);
+    assert!(after.tx_packets > before.tx_packets);
+    assert!(after.rx_packets > before.rx_packets);
+
+    // Connects nothing routes to are counted.
+    let unroutable: std::net::SocketAddr = "[2001:db8::1]:80".parse().unwrap();
+    if moto_rt::net::tcp_connect(&unroutable.into(), Duration::from_secs(1)).err()
+        == Some(moto_rt::E_NOT_FOUND)
+    {
+        let (_, no_route_after) = loopback(&mut stats_service);
+        assert!(no_route_after > no_route_before);
+    }
+
+    println!("test_net_dev_stats() PASS");
+}
+
+fn test_packet_capture() {
+    use moto_sys_io::api_net::{self, CaptureFilter};
+    use moto_sys_io::capture::PacketCapture;
+
+    const PORT: u16 = 3352;
+    const SNAPLEN: u32 = 96;
+
+    let header = api_net::pcap_file_header(SNAPLEN);
+    assert_eq!(&header[0..4], &0xa1b2_c3d4_u32.to_le_bytes());
+    assert_eq!(&header[16..20], &SNAPLEN.to_le_bytes());
+    assert_eq!(
+        &header[20..24],
+        &api_net::PCAP_LINKTYPE_ETHERNET.to_le_bytes()
+    );
+
+    if moto_sys::ProcessStaticPage::get().capabilities & moto_sys::caps::CAP_NET_CAPTURE == 0 {
+        assert_eq!(
+            PacketCapture::start(&CaptureFilter::default()).err(),
+            Some(moto_rt::E_NOT_ALLOWED)
+        );
+        println!("test_packet_capture() SKIPPED: needs CAP_NET_CAPTURE");
+        return;
+    }
+
+    let mut stats_service = moto_sys_io::stats::IoStatsService::connect().unwrap();
+    let devices = stats_service.get_net_dev_stats().unwrap().devices;
+    let loopback = devices
+        .iter()
+        .position(|dev| dev.name() == "loopback")
+        .unwrap() as u16;
+
+    for bad in [
+        CaptureFilter {
+            directions: 0,
+            ..Default::default()
+        },
+        CaptureFilter {
+            directions: api_net::CAPTURE_TX << 1,
+            ..Default::default()
+        },
+        CaptureFilter {
+            snaplen: 0,
+            ..Default::default()
+        },
+        CaptureFilter {
+            snaplen: api_net::CAPTURE_MAX_SNAPLEN + 1,
+            ..Default::default()
+        },
+        CaptureFilter {
+            device_idx: Some(devices.len() as u16),
+            ..Default::default()
+        },
+    ] {
+        assert_eq!(
+            PacketCapture::start(&bad).err(),
+            Some(moto_rt::E_INVALID_ARGUMENT)
+        );
+    }
+
+    let filter = CaptureFilter {
+        device_idx: Some(loopback),
+        protocol: 6, // TCP.
+        port: PORT,
+        snaplen: SNAPLEN,
+        ..Default::default()
+    };
+    let mut capture = PacketCapture::start(&filter).unwrap();
+    let other = PacketCapture::start(&CaptureFilter {
+        port: PORT + 1,
+        ..filter
+    })
+    .unwrap();
+
+    const BYTES: usize = 1000;
+    let addr: std::net::SocketAddr = format!("127.0.0.1:{PORT}").parse().unwrap();
+    let listener = moto_rt::net::bind(moto_rt::net::PROTO_TCP, &addr.into()).unwrap();
+    let client = moto_rt::net::tcp_connect(&addr.into(), Duration::from_secs(5)).unwrap();
+    let (server, _) = moto_rt::net::accept(listener).unwrap();
+    moto_rt::fs::close(listener).unwrap();
+    moto_rt::fs::write(client, &[3_u8; BYTES]).unwrap();
+    let mut buf = [0_u8; BYTES];
+    let mut read = 0;
+    while read < BYTES {
+        read += moto_rt::fs::read(server, &mut buf[read..]).unwrap();
+    }
+    moto_rt::fs::close(client).unwrap();
+    moto_rt::fs::close(server).unwrap();
+
+    // Each record is a pcap record header and an IPv4 TCP frame to or from
+    // PORT, truncated to SNAPLEN.
+    let u32_at = |record: &[u8], offset: usize| {
+        u32::from_le_bytes(record[offset..(offset + 4)].try_into().unwrap())
+    };
+    let mut records = 0;
+    let mut truncated = false;
+    while let Some(record) = capture
+        .next_record(Some(
+            moto_rt::time::Instant::now() + Duration::from_millis(200),
+        ))
+        .unwrap()
+    {
+        let incl_len = u32_at(&record, 8);
+        let orig_len = u32_at(&record, 12);
+        assert_eq!(
+            record.len(),
+            api_net::PCAP_RECORD_HEADER_SIZE + incl_len as usize
+        );
+        assert!(incl_len <= SNAPLEN && incl_len <= orig_len);
+        truncated |= orig_len as usize > BYTES;
+
+        let frame = &record[api_net::PCAP_RECORD_HEADER_SIZE..];
+        assert_eq!(&frame[12..14], &[0x08, 0x00]); // IPv4.
+        assert_eq!(frame[14 + 9], 6);
+        let tcp = 14 + ((frame[14] & 0xf) as usize) * 4;
+        let src_port = u16::from_be_bytes([frame[tcp], frame[tcp + 1]]);
+        let dst_port = u16::from_be_bytes([frame[tcp + 2], frame[tcp + 3]]);
+        assert!(src_port == PORT || dst_port == PORT);
+        records += 1;
+    }
+    // At least the handshake, the data and the FINs.
+    assert!(records >= 4);
+    assert!(truncated);
+
+    let (captured, dropped) = capture.stop().unwrap();
+    assert!(captured >= records);
+    assert_eq!(dropped, 0);
+    assert_eq!(other.stop().unwrap(), (0, 0));
+
+    println!("test_packet_capture() PASS");
+}
+
+fn test_linger() {
+    let addr: std::net::SocketAddr = "127.0.0.1:3344".parse().unwrap();
+    let listener = moto_rt::net::bind(moto_rt::net::PROTO_TCP, &addr.into()).unwrap();
+    let connect = || {
+        let client = moto_rt::net::tcp_connect(&addr.into(), Duration::from_secs(5)).unwrap();
+        let (server, _) = moto_rt::net::accept(listener).unwrap();
+        (client, server)
+    };
+    let buf = vec![7_u8; 1 << 14];
+
+    // The queued bytes get through: close succeeds. The timeout is long
+    // enough to overflow a deadline computed naively.
+    let (client, server) = connect();
+    assert_eq!(moto_rt::net::linger(client).unwrap(), None);
+    moto_rt::net::set_linger(client, Some(Duration::MAX)).unwrap();
+    assert!(moto_rt::net::linger(client).unwrap().is_some());
+    assert_eq!(moto_rt::fs::write(client, &buf).unwrap(), buf.len());
+    let reader = std::thread::spawn(move || {
+        let mut read_buf = vec![0_u8; 1 << 14];
+        let mut read = 0;
+        loop {
+            match moto_rt::fs::read(server, &mut read_buf) {
+                Ok(0) => break,
+                Ok(sz) => read += sz,
+                Err(err) => panic!("read failed: {err}"),
+            }
+        }
+        moto_rt::fs::close(server).unwrap();
+        read
+    });
+    moto_rt::fs::close(client).unwrap();
+    assert_eq!(reader.join().unwrap(), buf.len());
+
+    // A duplicated fd keeps the stream open; the last close reports.
+    let (client, server) = connect();
+    moto_rt::net::pause_recv(server).unwrap();
+    moto_rt::net::set_linger(client, Some(Duration::from_millis(100))).unwrap();
+    assert_eq!(moto_rt::fs::write(client, &buf).unwrap(), buf.len());
+    let dup = moto_rt::fs::duplicate(client).unwrap();
+    moto_rt::fs::close(client).unwrap();
+
+    // The peer does not read: the linger timeout expires.
+    let started = std::time::Instant::now();
+    assert_eq!(moto_rt::fs::close(dup).err(), Some(moto_rt::E_TIMED_OUT));
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    moto_rt::fs::close(server).unwrap();
+
+    // The peer resets the connection before the timeout.
+    let (client, server) = connect();
+    moto_rt::net::pause_recv(server).unwrap();
+    moto_rt::net::set_linger(client, Some(Duration::from_secs(10))).unwrap();
+    assert_eq!(moto_rt::fs::write(client, &buf).unwrap(), buf.len());
+    let resetter = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        moto_rt::net::set_linger(server, Some(Duration::ZERO)).unwrap();
+        moto_rt::fs::close(server).unwrap(); // Sends an RST.
+    });
+    let started = std::time::Instant::now();
+    assert_eq!(
+        moto_rt::fs::close(client).err(),
+        Some(moto_rt::E_CONNECTION_RESET)
+    );
+    assert!(started.elapsed() < Duration::from_secs(10));
+    resetter.join().unwrap();
+
+    // With a zero timeout, close resets the connection at once, even with
+    // bytes queued that the peer does not read.
+    let (client, server) = connect();
+    moto_rt::net::pause_recv(server).unwrap();
+    moto_rt::net::set_linger(client, Some(Duration::ZERO)).unwrap();
+    assert_eq!(moto_rt::fs::write(client, &buf).unwrap(), buf.len());
+    let started = std::time::Instant::now();
+    moto_rt::fs::close(client).unwrap();
+    assert!(started.elapsed() < Duration::from_millis(100));
+    moto_rt::fs::close(server).unwrap();
+
+    moto_rt::fs::close(listener).unwrap();
+    println!("test_linger() PASS");
+}
+
+// Connections migrate only off devices whose link goes down (with
+// migrate_connections set in sys-net.toml), which can't be done from here;
+// check that connections not yet moved report so.
+fn test_migration_stats() {
+    use moto_sys_io::stats::IoStatsService;
+
+    let mut stats_service = IoStatsService::connect().unwrap();
+    let migrated = stats_service.get_net_stats().unwrap().tcp_migrated;
+
+    // Neither end is pinned to a local address, so both could migrate.
+    let listener = std::net::TcpListener::bind("0.0.0.0:3336").unwrap();
+    let client = std::net::TcpStream::connect("127.0.0.1:3336").unwrap();
+    let (server, _) = listener.accept().unwrap();
+
+    // Listing sockets does not need CAP_SYS, unlike get_open_handles().
+    let sockets: Vec<_> = stats_service
+        .get_tcp_socket_stats(0)
+        .unwrap()
+        .iter()
+        .copied()
+        // The two ends of the connection, not the listening sockets.
+        .filter(|stats| match (stats.local_addr(), stats.remote_addr()) {
+            (Some(local), Some(remote)) => local.port() == 3336 || remote.port() == 3336,
+            _ => false,
+        })
+        .collect();
+    assert_eq!(sockets.len(), 2);
+    assert!(sockets.iter().all(|stats| stats.migrations == 0));
+    assert_eq!(
+        stats_service.get_net_stats().unwrap().tcp_migrated,
+        migrated
+    );
+
+    core::mem::drop(server);
+    core::mem::drop(client);
+    println!("test_migration_stats() PASS");
+}
+
+// Without CAP_SYS, only the caller's own sockets are listed.
+fn test_tcp_stats_access() {
+    use moto_sys_io::stats::IoStatsService;
+
+    let mut stats_service = IoStatsService::connect().unwrap();
+    let listener = std::net::TcpListener::bind("127.0.0.1:3337").unwrap();
+    let client = std::net::TcpStream::connect("127.0.0.1:3337").unwrap();
+    let (server, _) = listener.accept().unwrap();
+
+    let mut sockets = Vec::new();
+    let mut start_id = 0;
+    loop {
+        let stats = stats_service.get_tcp_socket_stats(start_id).unwrap();
+        let Some(last) = stats.last() else {
+            break;
+        };
+        start_id = last.id + 1;
+        sockets.extend_from_slice(stats);
+    }
+
+    let ours = sockets
+        .iter()
+        .filter(|stats| stats.pid == moto_sys::current_pid())
+        .count();
+    assert!(ours >= 3); // The listener and both ends of the connection.
+    if moto_sys::ProcessStaticPage::get().capabilities & moto_sys::caps::CAP_SYS == 0 {
+        assert_eq!(ours, sockets.len());
+    }
+
+    core::mem::drop(server);
+    core::mem::drop(client);
+    core::mem::drop(listener);
+    println!("test_tcp_stats_access() PASS");
+}
+
+fn test_peer_credentials() {
+    let addr: std::net::SocketAddr = "127.0.0.1:3345".parse().unwrap();
+    let listener = moto_rt::net::bind(moto_rt::net::PROTO_TCP, &addr.into()).unwrap();
+    let client = moto_rt::net::tcp_connect(&addr.into(), Duration::from_secs(5)).unwrap();
+    let (server, _) = moto_rt::net::accept(listener).unwrap();
+    moto_rt::fs::close(listener).unwrap();
+
+    // Both ends of the loopback connection are in this process.
+    let me = (
+        moto_sys::current_pid(),
+        moto_sys::ProcessStaticPage::get().capabilities,
+    );
+    assert_eq!(moto_rt::net::peer_credentials(client).unwrap(), me);
+    assert_eq!(moto_rt::net::peer_credentials(server).unwrap(), me);
+
+    moto_rt::fs::close(client).unwrap();
+    moto_rt::fs::close(server).unwrap();
+    println!("test_peer_credentials() PASS");
+}
+
+fn test_listener_drain() {
+    let timeout = Duration::from_secs(5);
+
+    // A blocked accept() fails once there's nothing left to drain.
+    let addr: std::net::SocketAddr = "127.0.0.1:3346".parse().unwrap();
+    let listener = moto_rt::net::bind(moto_rt::net::PROTO_TCP, &addr.into()).unwrap();
+    let acceptor = std::thread::spawn(move || moto_rt::net::accept(listener).err());
+    std::thread::sleep(Duration::from_millis(50));
+    moto_rt::net::drain_listener(listener).unwrap();
+    assert_eq!(acceptor.join().unwrap(), Some(moto_rt::E_INVALID_ARGUMENT));
+    moto_rt::fs::close(listener).unwrap();
+
+    // Queued connections can still be accepted, new ones are refused.
+    let addr: std::net::SocketAddr = "127.0.0.1:3347".parse().unwrap();
+    let listener = moto_rt::net::bind(moto_rt::net::PROTO_TCP, &addr.into()).unwrap();
+    let clients: Vec<_> = (0..2)
+        .map(|_| moto_rt::net::tcp_connect(&addr.into(), timeout).unwrap())
+        .collect();
+    let start = std::time::Instant::now();
+    while moto_rt::net::rx_available(listener).unwrap() < 2 {
+        assert!(start.elapsed() < timeout);
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    moto_rt::net::drain_listener(listener).unwrap();
+    moto_rt::net::drain_listener(listener).unwrap(); // Idempotent.
+    assert!(moto_rt::net::tcp_connect(&addr.into(), Duration::from_secs(1)).is_err());
+
+    for client in clients {
+        let (server, _) = moto_rt::net::accept(listener).unwrap();
+        moto_rt::fs::write(server, b"bye").unwrap();
+        let mut buf = [0_u8; 3];
+        assert_eq!(moto_rt::fs::read(client, &mut buf).unwrap(), 3);
+        moto_rt::fs::close(server).unwrap();
+        moto_rt::fs::close(client).unwrap();
+    }
+    assert_eq!(
+        moto_rt::net::accept(listener).unwrap_err(),
+        moto_rt::E_INVALID_ARGUMENT
+    );
+    moto_rt::fs::close(listener).unwrap();
+
+    // With fin_on_close, a connection nobody accepted gets an orderly close.
+    let addr: std::net::SocketAddr = "127.0.0.1:3348".parse().unwrap();
+    let listener = moto_rt::net::bind(moto_rt::net::PROTO_TCP, &addr.into()).unwrap();
+    moto_rt::net::set_listener_fin_on_close(listener, true).unwrap();
+    let client = moto_rt::net::tcp_connect(&addr.into(), timeout).unwrap();
+    let start = std::time::Instant::now();
+    while moto_rt::net::rx_available(listener).unwrap() < 1 {
+        assert!(start.elapsed() < timeout);
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    moto_rt::fs::close(listener).unwrap();
+
+    moto_rt::net::set_read_timeout(client, Some(timeout)).unwrap();
+    let mut buf = [0_u8; 4];
+    assert_eq!(moto_rt::fs::read(client, &mut buf), Ok(0));
+    moto_rt::fs::close(client).unwrap();
+
+    println!("test_listener_drain() PASS");
+}
+
 pub fn test_tcp_loopback() {
     assert!(std::net::TcpStream::connect("localhost:3333").is_err());
     let start = Arc::new(AtomicBool::new(false));
@@ -222,6 +1182,57 @@ pub fn test_tcp_loopback() {
     test_read_timeout();
     // TODO: how can we test write timeout?
 
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    test_reuse_port();
+
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    test_reuse_addr();
+
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    test_udp();
+
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    test_udp_dont_frag();
+
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    test_linger();
+
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    test_migration_stats();
+
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    test_tcp_stats_access();
+
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    test_connect_from();
+
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    test_poll();
+
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    test_urgent();
+
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    test_rx_pause();
+
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    test_rx_available();
+
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    test_peer_credentials();
+
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    test_listener_drain();
+
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    test_idle_timeout();
+
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    test_net_dev_stats();
+
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    test_packet_capture();
+
     // Wrap the output in sleeps to avoid debug console output mangling.
     std::thread::sleep(std::time::Duration::from_millis(10));
     println!("test_tcp() PASS");
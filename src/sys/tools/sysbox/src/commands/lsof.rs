@@ -0,0 +1,52 @@
+use moto_sys_io::stats::OpenHandle;
+
+fn print_usage_and_exit(exit_code: i32) -> ! {
+    eprintln!("usage:\n\tlsof $PID\n");
+    std::process::exit(exit_code);
+}
+
+pub fn do_command(args: &[String]) {
+    assert_eq!(args[0], "lsof");
+
+    if args.len() != 2 {
+        print_usage_and_exit(1);
+    }
+
+    let arg_str = args[1].as_str();
+    if arg_str == "--help" {
+        print_usage_and_exit(0);
+    }
+
+    let pid = match arg_str.parse::<u64>() {
+        Ok(pid) => pid,
+        Err(_) => print_usage_and_exit(1),
+    };
+
+    let mut svc = moto_sys_io::stats::IoStatsService::connect().unwrap();
+    let handles = match svc.get_open_handles(pid) {
+        Ok(handles) => handles,
+        Err(err) => {
+            eprintln!("lsof failed: {:?}", err);
+            std::process::exit(1);
+        }
+    };
+
+    for handle in &handles {
+        match handle {
+            OpenHandle::File(file) => println!("file {:>5} {}", file.fd, file.path),
+            OpenHandle::TcpSocket(stat) => {
+                let addr_str = |addr: Option<std::net::SocketAddr>| match addr {
+                    Some(addr) => addr.to_string(),
+                    None => "*".to_owned(),
+                };
+                println!(
+                    "tcp  {:>5} {} -> {} {:?}",
+                    stat.id,
+                    addr_str(stat.local_addr()),
+                    addr_str(stat.remote_addr()),
+                    stat.tcp_state
+                )
+            }
+        }
+    }
+}
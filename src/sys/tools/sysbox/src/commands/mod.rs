@@ -5,6 +5,7 @@ pub mod free;
 pub mod kill;
 pub mod loop_cmd; // Can't be just 'loop', as it is a keyword.
 pub mod ls;
+pub mod lsof;
 pub mod mkdir;
 pub mod mv;
 pub mod ps;
@@ -13,6 +14,7 @@ pub mod rm;
 pub mod rmdir;
 pub mod sleep;
 pub mod ss;
+pub mod tcpdump;
 pub mod time;
 pub mod top;
 pub mod uptime;
@@ -0,0 +1,79 @@
+use std::io::Write;
+
+use moto_sys_io::api_net::{self, CaptureFilter};
+
+fn print_usage_and_exit(exit_code: i32) -> ! {
+    eprintln!(
+        "usage:\n\ttcpdump [-i $DEV_IDX] [-p tcp|udp|icmp|$PROTO] [--port $PORT] [--rx|--tx] [-c $COUNT] [-s $SNAPLEN] -w $FILE\n"
+    );
+    eprintln!("Writes captured frames to $FILE in pcap format. Requires CAP_NET_CAPTURE.");
+    std::process::exit(exit_code);
+}
+
+fn parse_or_exit<T: std::str::FromStr>(arg: Option<&String>) -> T {
+    match arg.map(|arg| arg.parse::<T>()) {
+        Some(Ok(val)) => val,
+        _ => print_usage_and_exit(1),
+    }
+}
+
+pub fn do_command(args: &[String]) {
+    assert_eq!(args[0], "tcpdump");
+
+    let mut filter = CaptureFilter::default();
+    let mut count = None;
+    let mut path = None;
+
+    let mut args = args[1..].iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" => print_usage_and_exit(0),
+            "-i" => filter.device_idx = Some(parse_or_exit(args.next())),
+            "-p" => {
+                let proto = args.next();
+                filter.protocol = match proto.map(|arg| arg.as_str()) {
+                    Some("tcp") => 6,
+                    Some("udp") => 17,
+                    Some("icmp") => 1,
+                    _ => parse_or_exit(proto),
+                }
+            }
+            "--port" => filter.port = parse_or_exit(args.next()),
+            "--rx" => filter.directions = api_net::CAPTURE_RX,
+            "--tx" => filter.directions = api_net::CAPTURE_TX,
+            "-c" => count = Some(parse_or_exit::<u64>(args.next())),
+            "-s" => filter.snaplen = parse_or_exit(args.next()),
+            "-w" => path = Some(parse_or_exit::<String>(args.next())),
+            _ => print_usage_and_exit(1),
+        }
+    }
+
+    let Some(path) = path else {
+        print_usage_and_exit(1);
+    };
+
+    let mut capture = match moto_sys_io::capture::PacketCapture::start(&filter) {
+        Ok(capture) => capture,
+        Err(err) => {
+            eprintln!("tcpdump failed: {:?}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut file = std::fs::File::create(path.as_str()).unwrap();
+    file.write_all(&capture.file_header()).unwrap();
+
+    crate::spawn_generic_input_listener();
+
+    let mut captured = 0;
+    while count.map_or(true, |count| captured < count) {
+        let record = capture.next_record(None).unwrap().unwrap();
+        file.write_all(&record).unwrap();
+        captured += 1;
+    }
+
+    file.flush().unwrap();
+    let dropped = capture.dropped();
+    let _ = capture.stop();
+    eprintln!("{} packets captured, {} dropped", captured, dropped);
+}
@@ -12,6 +12,7 @@ fn print_usage_and_exit(exit_code: i32) -> ! {
     println!("\tsysbox kill");
     println!("\tsysbox loop");
     println!("\tsysbox ls");
+    println!("\tsysbox lsof");
     println!("\tsysbox mkdir");
     println!("\tsysbox mv");
     println!("\tsysbox ps");
@@ -20,6 +21,7 @@ fn print_usage_and_exit(exit_code: i32) -> ! {
     println!("\tsysbox rmdir");
     println!("\tsysbox sleep");
     println!("\tsysbox ss");
+    println!("\tsysbox tcpdump");
     println!("\tsysbox time");
     println!("\tsysbox top");
     println!("\tsysbox uptime");
@@ -63,6 +65,7 @@ fn main() {
         "kill" => commands::kill::do_command(&args[1..]),
         "loop" => commands::loop_cmd::do_command(&args[1..]),
         "ls" => commands::ls::do_command(&args[1..]),
+        "lsof" => commands::lsof::do_command(&args[1..]),
         "mkdir" => commands::mkdir::do_command(&args[1..]),
         "mv" => commands::mv::do_command(&args[1..]),
         "ps" => commands::ps::do_command(&args[1..]),
@@ -71,6 +74,7 @@ fn main() {
         "rmdir" => commands::rmdir::do_command(&args[1..]),
         "sleep" => commands::sleep::do_command(&args[1..]),
         "ss" => commands::ss::do_command(&args[1..]),
+        "tcpdump" => commands::tcpdump::do_command(&args[1..]),
         "time" => commands::time::do_command(&args[1..]),
         "top" => commands::top::do_command(&args[1..]),
         "uptime" => commands::uptime::do_command(&args[1..]),